@@ -0,0 +1,35 @@
+#ifndef ARCHAEO_H
+#define ARCHAEO_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Extracts the flattened metrics of a file, or every C/C++ file under a directory,
+ * as a JSON array of rows. Returns NULL on failure, a panic included, see
+ * archaeo_last_error.
+ * The result must be released with archaeo_string_free.
+ */
+char *archaeo_extract_json(const char *path);
+
+/*
+ * As archaeo_extract_json, configured by a JSON object with any of the fields
+ * "extensions", "extended", "query" and "plugins". NULL options use the defaults.
+ */
+char *archaeo_extract_json_with_options(const char *path, const char *options);
+
+/*
+ * Describes the last failure on the calling thread, or NULL if there was none.
+ * Valid until the next call on the same thread, must not be freed.
+ */
+const char *archaeo_last_error(void);
+
+/* Releases a string returned by archaeo. NULL is ignored. */
+void archaeo_string_free(char *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,199 @@
+//! C ABI over the archaeo extraction core. See `include/archaeo.h` for the interface.
+//!
+//! Every string handed out is owned by the caller and must be released with
+//! `archaeo_string_free`. On failure NULL is returned and `archaeo_last_error` describes
+//! what went wrong on the calling thread. Panics are caught and reported as failures too,
+//! since unwinding into the host is undefined behavior.
+
+use archaeo::extract::{extract_path, ExtractOptions};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Borrows a NUL terminated UTF-8 string from C
+///
+/// # Safety
+/// `value` must be NULL or point at a valid NUL terminated string
+unsafe fn borrow_str<'a>(value: *const c_char, what: &str) -> Result<&'a str, String> {
+    if value.is_null() {
+        return Err(format!("{} is NULL", what));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| format!("{} is not valid UTF-8", what))
+}
+
+fn extract_json(path: &str, options: &ExtractOptions) -> Result<String, String> {
+    let rows = extract_path(Path::new(path), options).map_err(|err| err.to_string())?;
+    serde_json::to_string(&rows).map_err(|err| err.to_string())
+}
+
+fn into_c_string(result: Result<String, String>) -> *mut c_char {
+    match result.and_then(|json| CString::new(json).map_err(|err| err.to_string())) {
+        Ok(json) => json.into_raw(),
+        Err(err) => {
+            set_last_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Runs `body`, turning a panic into a failure so it never unwinds across the C ABI
+fn guarded(body: impl FnOnce() -> Result<String, String>) -> *mut c_char {
+    let result = panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".to_string());
+        Err(format!("archaeo panicked: {}", message))
+    });
+    into_c_string(result)
+}
+
+/// Extracts the flattened metrics of a file, or every C/C++ file under a directory, as a
+/// JSON array of rows
+///
+/// # Safety
+/// `path` must be NULL or point at a valid NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn archaeo_extract_json(path: *const c_char) -> *mut c_char {
+    guarded(|| {
+        borrow_str(path, "path").and_then(|path| extract_json(path, &ExtractOptions::default()))
+    })
+}
+
+/// As `archaeo_extract_json`, configured by a JSON object with any of the fields
+/// `extensions`, `extended`, `query` and `plugins`. NULL options use the defaults
+///
+/// # Safety
+/// `path` and `options` must be NULL or point at valid NUL terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn archaeo_extract_json_with_options(
+    path: *const c_char,
+    options: *const c_char,
+) -> *mut c_char {
+    guarded(|| {
+        borrow_str(path, "path").and_then(|path| {
+            let options = if options.is_null() {
+                ExtractOptions::default()
+            } else {
+                serde_json::from_str(borrow_str(options, "options")?)
+                    .map_err(|err| format!("Invalid options: {}", err))?
+            };
+            extract_json(path, &options)
+        })
+    })
+}
+
+/// Describes the last failure on the calling thread, or NULL if there was none. The
+/// string stays valid until the next call on the same thread and must not be freed
+#[no_mangle]
+pub extern "C" fn archaeo_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Releases a string returned by archaeo
+///
+/// # Safety
+/// `value` must be NULL or a string returned by archaeo that has not been freed yet
+#[no_mangle]
+pub unsafe extern "C" fn archaeo_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        let error = archaeo_last_error();
+        assert!(!error.is_null());
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn panics_are_reported_as_failures() {
+        assert!(guarded(|| panic!("a {} panic", "formatted")).is_null());
+        assert_eq!(last_error(), "archaeo panicked: a formatted panic");
+        assert!(guarded(|| std::panic::panic_any(1)).is_null());
+        assert_eq!(last_error(), "archaeo panicked: unknown cause");
+
+        let json = guarded(|| Ok("[]".to_string()));
+        assert_eq!(unsafe { CStr::from_ptr(json) }.to_str(), Ok("[]"));
+        unsafe { archaeo_string_free(json) };
+    }
+
+    #[test]
+    fn rows_are_handed_out_as_json() {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/fixtures/c");
+        let path = CString::new(fixtures).unwrap();
+        let options = CString::new("{\"extensions\": [\"c\"], \"extended\": true}").unwrap();
+        let json = unsafe { archaeo_extract_json_with_options(path.as_ptr(), options.as_ptr()) };
+        assert!(!json.is_null());
+        let rows: serde_json::Value =
+            serde_json::from_str(unsafe { CStr::from_ptr(json) }.to_str().unwrap()).unwrap();
+        unsafe { archaeo_string_free(json) };
+
+        let rows = rows.as_array().unwrap();
+        let mut names: Vec<&str> = rows
+            .iter()
+            .map(|row| row["name"].as_str().unwrap())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "main",
+                "stack_init",
+                "stack_pop",
+                "stack_push",
+                "sum_positive"
+            ]
+        );
+        assert!(rows.iter().all(|row| row.get("nargs_total").is_some()));
+
+        // NULL options and the plain call take the defaults
+        let plain = unsafe { archaeo_extract_json(path.as_ptr()) };
+        let defaults = unsafe { archaeo_extract_json_with_options(path.as_ptr(), ptr::null()) };
+        assert_eq!(unsafe { CStr::from_ptr(plain) }, unsafe {
+            CStr::from_ptr(defaults)
+        });
+        unsafe {
+            archaeo_string_free(plain);
+            archaeo_string_free(defaults);
+            archaeo_string_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn failures_set_the_last_error() {
+        assert!(unsafe { archaeo_extract_json(ptr::null()) }.is_null());
+        assert_eq!(last_error(), "path is NULL");
+
+        let path = CString::new("/nonexistent/archaeo").unwrap();
+        let options = CString::new("{\"extended\": 1}").unwrap();
+        let json = unsafe { archaeo_extract_json_with_options(path.as_ptr(), options.as_ptr()) };
+        assert!(json.is_null());
+        assert!(last_error().starts_with("Invalid options"));
+    }
+}
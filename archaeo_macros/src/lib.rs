@@ -1,48 +1,286 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, Data, DeriveInput, Fields};
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, Lit, UnOp};
 
-#[proc_macro_derive(ReplaceInfNan)]
+/// Derives `ReplaceInfNan` for a struct, applying the given `NonFinite` policy to every
+/// `f64` and `f32` field, including those wrapped in `Option` or `Vec`. A field marked
+/// `#[replace_with(zero)]`, `(null)`, `(raw)` or `(-1)` falls back to that policy rather
+/// than zero when none is given.
+///
+/// `#[replace_inf_nan(skip)]` leaves a field alone, and `#[replace_inf_nan(nested)]` applies
+/// the policy within a field whose type derives `ReplaceInfNan` itself. A derive can't tell
+/// which types those are, so any other field is a compile error until it is marked one or
+/// the other
+#[proc_macro_derive(ReplaceInfNan, attributes(replace_with, replace_inf_nan))]
 pub fn replace_inf_nan_derive(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
-    impl_replace_inf_nan(&ast).into()
+    impl_replace_inf_nan(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
 }
 
-fn is_f64_type(ty: &syn::Type) -> bool {
+/// Whether `ty` is `f64` or `f32`, or an `Option` or `Vec` of them, all of which implement
+/// `Finite`
+fn is_float_type(ty: &syn::Type) -> bool {
+    if let syn::Type::Group(group) = ty {
+        return is_float_type(&group.elem);
+    }
     if let syn::Type::Path(type_path) = ty {
         if let Some(segment) = type_path.path.segments.last() {
-            return segment.ident == "f64";
+            if segment.ident == "Option" || segment.ident == "Vec" {
+                if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+                    return matches!(
+                        args.args.first(),
+                        Some(syn::GenericArgument::Type(inner)) if is_float_type(inner)
+                    );
+                }
+            }
+            return segment.ident == "f64" || segment.ident == "f32";
         }
     }
     false
 }
 
-fn impl_replace_inf_nan(ast: &DeriveInput) -> proc_macro2::TokenStream {
+/// How a field is handled, from its `#[replace_inf_nan(..)]` attribute
+enum Handling {
+    Default,
+    Skip,
+    Nested,
+}
+
+fn handling(field: &syn::Field) -> syn::Result<Handling> {
+    let Some(attr) = field
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("replace_inf_nan"))
+    else {
+        return Ok(Handling::Default);
+    };
+    let ident: syn::Ident = attr.parse_args()?;
+    match ident.to_string().as_str() {
+        "skip" => Ok(Handling::Skip),
+        "nested" => Ok(Handling::Nested),
+        _ => Err(syn::Error::new_spanned(
+            ident,
+            "expected replace_inf_nan(skip) or replace_inf_nan(nested)",
+        )),
+    }
+}
+
+/// The `NonFinite` policy a `#[replace_with(..)]` attribute names
+fn policy(attr: &syn::Attribute) -> syn::Result<proc_macro2::TokenStream> {
+    let expr: Expr = attr.parse_args()?;
+    let sentinel = |lit: &Lit, negative: bool| -> Option<proc_macro2::TokenStream> {
+        let value = match lit {
+            Lit::Int(int) => int.base10_parse::<f64>().ok()?,
+            Lit::Float(float) => float.base10_parse::<f64>().ok()?,
+            _ => return None,
+        };
+        let value = if negative { -value } else { value };
+        Some(quote! { NonFinite::Sentinel(#value) })
+    };
+    let tokens = match &expr {
+        Expr::Path(path) if path.path.is_ident("zero") => Some(quote! { NonFinite::Zero }),
+        Expr::Path(path) if path.path.is_ident("null") => Some(quote! { NonFinite::Null }),
+        Expr::Path(path) if path.path.is_ident("raw") => Some(quote! { NonFinite::Raw }),
+        Expr::Lit(lit) => sentinel(&lit.lit, false),
+        Expr::Unary(unary) if matches!(unary.op, UnOp::Neg(_)) => match &*unary.expr {
+            Expr::Lit(lit) => sentinel(&lit.lit, true),
+            _ => None,
+        },
+        _ => None,
+    };
+    tokens.ok_or_else(|| {
+        syn::Error::new_spanned(
+            &expr,
+            "expected replace_with(zero), replace_with(null), replace_with(raw) or a number",
+        )
+    })
+}
+
+fn impl_replace_inf_nan(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
     let fields = match &ast.data {
         Data::Struct(data) => &data.fields,
         _ => panic!("ReplaceInfNan can only be derived for structs"),
     };
 
-    let replace_fields = match fields {
-        Fields::Named(fields) => fields.named.iter().filter_map(|field| {
-            if is_f64_type(&field.ty) {
+    let mut replace_fields = Vec::new();
+    match fields {
+        Fields::Named(fields) => {
+            for field in &fields.named {
                 let ident = &field.ident;
-                Some(quote! {
-                    self.#ident.replace_inf_nan();
-                })
-            } else {
-                None
+                let replace_with = field
+                    .attrs
+                    .iter()
+                    .find(|attr| attr.path().is_ident("replace_with"));
+                match handling(field)? {
+                    Handling::Skip => continue,
+                    Handling::Nested => {
+                        if let Some(attr) = replace_with {
+                            return Err(syn::Error::new_spanned(
+                                attr,
+                                "replace_with can't apply to a replace_inf_nan(nested) field, \
+                                 mark the fields of its type instead",
+                            ));
+                        }
+                        replace_fields.push(quote! {
+                            kept.extend(self.#ident.replace_inf_nan(policy));
+                        });
+                        continue;
+                    }
+                    Handling::Default if !is_float_type(&field.ty) => {
+                        return Err(syn::Error::new_spanned(
+                            field,
+                            "ReplaceInfNan only replaces f64 and f32 fields, mark this one \
+                             replace_inf_nan(skip), or replace_inf_nan(nested) if its type \
+                             derives ReplaceInfNan",
+                        ));
+                    }
+                    Handling::Default => {}
+                }
+                let column = ident.as_ref().map(|ident| ident.to_string());
+                let fallback = match replace_with {
+                    Some(attr) => policy(attr)?,
+                    None => quote! { NonFinite::Zero },
+                };
+                replace_fields.push(quote! {
+                    if let Some(value) = self.#ident.replace_non_finite(policy.unwrap_or(#fallback)) {
+                        kept.push((#column, value));
+                    }
+                });
             }
-        }),
+        }
         _ => panic!("ReplaceInfNan only supports named fields"),
     };
 
-    quote! {
+    Ok(quote! {
         impl ReplaceInfNan for #name {
-            fn replace_inf_nan(&mut self) {
+            fn replace_inf_nan(&mut self, policy: Option<NonFinite>) -> Vec<(&'static str, f64)> {
+                let mut kept = Vec::new();
                 #(#replace_fields)*
+                kept
+            }
+        }
+    })
+}
+
+/// Derives `MetricSchema` for a struct, describing the name, type, nullability and metric
+/// family of every column it serializes to. The family is the name up to its first `_`
+/// unless the field is marked `#[metric(family = "...")]`, or `#[metric(identifier)]` for
+/// columns identifying a row rather than measuring it. Flattened and skipped fields, whose
+/// columns vary, are left out
+#[proc_macro_derive(MetricSchema, attributes(metric))]
+pub fn metric_schema_derive(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    impl_metric_schema(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// The `ColumnKind` and nullability of a field type, looking through `Option`
+fn column_kind(ty: &syn::Type) -> syn::Result<(proc_macro2::TokenStream, bool)> {
+    // Types a `macro_rules!` passes on as `$ty:ty` arrive wrapped in an invisible group
+    if let syn::Type::Group(group) = ty {
+        return column_kind(&group.elem);
+    }
+    let unsupported = || syn::Error::new_spanned(ty, "MetricSchema can't describe this type");
+    let syn::Type::Path(type_path) = ty else {
+        return Err(unsupported());
+    };
+    let Some(segment) = type_path.path.segments.last() else {
+        return Err(unsupported());
+    };
+    if segment.ident == "Option" {
+        if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+            if let Some(syn::GenericArgument::Type(inner)) = args.args.first() {
+                let (kind, _) = column_kind(inner)?;
+                return Ok((kind, true));
             }
         }
+        return Err(unsupported());
+    }
+    let kind = match segment.ident.to_string().as_str() {
+        "f64" | "f32" => quote! { ColumnKind::Float },
+        "usize" | "u64" | "u32" | "i64" | "i32" => quote! { ColumnKind::Integer },
+        "String" => quote! { ColumnKind::Text },
+        "bool" => quote! { ColumnKind::Boolean },
+        _ => return Err(unsupported()),
+    };
+    Ok((kind, false))
+}
+
+/// Whether a `#[serde(..)]` attribute flattens or skips its field
+fn varies(attr: &syn::Attribute) -> bool {
+    if !attr.path().is_ident("serde") {
+        return false;
     }
+    let mut varies = false;
+    let _ = attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("flatten") || meta.path.is_ident("skip") {
+            varies = true;
+        }
+        if meta.input.peek(syn::Token![=]) {
+            meta.value()?.parse::<Expr>()?;
+        }
+        Ok(())
+    });
+    varies
+}
+
+fn impl_metric_schema(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let Data::Struct(data) = &ast.data else {
+        panic!("MetricSchema can only be derived for structs");
+    };
+    let Fields::Named(fields) = &data.fields else {
+        panic!("MetricSchema only supports named fields");
+    };
+
+    let mut columns = Vec::new();
+    for field in &fields.named {
+        if field.attrs.iter().any(varies) {
+            continue;
+        }
+        let column = field
+            .ident
+            .as_ref()
+            .map(|ident| ident.to_string())
+            .unwrap_or_default();
+        let mut family = Some(column.split('_').next().unwrap_or(&column).to_string());
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("metric")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("identifier") {
+                    family = None;
+                    Ok(())
+                } else if meta.path.is_ident("family") {
+                    family = Some(meta.value()?.parse::<syn::LitStr>()?.value());
+                    Ok(())
+                } else {
+                    Err(meta.error("expected metric(identifier) or metric(family = \"...\")"))
+                }
+            })?;
+        }
+        let family = match family {
+            Some(family) => quote! { Some(#family.to_string()) },
+            None => quote! { None },
+        };
+        let (kind, nullable) = column_kind(&field.ty)?;
+        columns.push(quote! {
+            ColumnInfo {
+                name: #column.to_string(),
+                kind: #kind,
+                nullable: #nullable,
+                family: #family,
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl MetricSchema for #name {
+            fn columns() -> Vec<ColumnInfo> {
+                vec![#(#columns),*]
+            }
+        }
+    })
 }
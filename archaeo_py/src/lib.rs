@@ -0,0 +1,98 @@
+use archaeo::extract::{self, ExtractOptions, MetricsRow};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Extracts the flattened metrics of every space in a single file
+#[pyfunction]
+#[pyo3(signature = (path, extended = false, query = None, plugins = None))]
+fn extract_file<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    extended: bool,
+    query: Option<PathBuf>,
+    plugins: Option<Vec<String>>,
+) -> PyResult<Bound<'py, PyList>> {
+    let options = ExtractOptions {
+        extended,
+        query,
+        plugins: plugins.unwrap_or_default(),
+        ..ExtractOptions::default()
+    };
+    let rows = py
+        .detach(|| extract::extract_file(&path, &options))
+        .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+    rows_to_py(py, &rows)
+}
+
+/// Extracts the flattened metrics of every matching file under a directory
+#[pyfunction]
+#[pyo3(signature = (path, extensions = None, extended = false, query = None, plugins = None))]
+fn extract_dir<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    extensions: Option<Vec<String>>,
+    extended: bool,
+    query: Option<PathBuf>,
+    plugins: Option<Vec<String>>,
+) -> PyResult<Bound<'py, PyList>> {
+    let defaults = ExtractOptions::default();
+    let options = ExtractOptions {
+        extensions: extensions.unwrap_or(defaults.extensions.clone()),
+        extended,
+        query,
+        plugins: plugins.unwrap_or_default(),
+        ..defaults
+    };
+    let rows = py
+        .detach(|| extract::extract_dir(&path, &options))
+        .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+    rows_to_py(py, &rows)
+}
+
+fn rows_to_py<'py>(py: Python<'py>, rows: &[MetricsRow]) -> PyResult<Bound<'py, PyList>> {
+    let list = PyList::empty(py);
+    for row in rows {
+        let dict = PyDict::new(py);
+        for (key, value) in row {
+            dict.set_item(key, value_to_py(py, value)?)?;
+        }
+        list.append(dict)?;
+    }
+    Ok(list)
+}
+
+fn value_to_py<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match value {
+        Value::Null => py.None().into_bound(py),
+        Value::Bool(b) => b.into_pyobject(py)?.to_owned().into_any(),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into_pyobject(py)?.into_any(),
+            None => n.as_f64().unwrap_or_default().into_pyobject(py)?.into_any(),
+        },
+        Value::String(s) => s.into_pyobject(py)?.into_any(),
+        Value::Array(values) => {
+            let list = PyList::empty(py);
+            for value in values {
+                list.append(value_to_py(py, value)?)?;
+            }
+            list.into_any()
+        }
+        Value::Object(object) => {
+            let dict = PyDict::new(py);
+            for (key, value) in object {
+                dict.set_item(key, value_to_py(py, value)?)?;
+            }
+            dict.into_any()
+        }
+    })
+}
+
+#[pymodule]
+fn archaeo_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(extract_file, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_dir, m)?)?;
+    Ok(())
+}
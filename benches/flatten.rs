@@ -0,0 +1,66 @@
+//! Benchmarks of computing, flattening and serializing the metrics of a file. Run with
+//! `cargo bench`
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+use std::io::sink;
+use std::path::Path;
+
+use archaeo::callgraph::Coupling;
+use archaeo::commands::source::{
+    flatten_spaces, flatten_spaces_extended, read_spaces, FileContext, FlattenedMetrics,
+};
+use archaeo::extract::to_rows;
+use archaeo::language::SourceReader;
+use archaeo::output::{write_csv, RowStream};
+
+const SOURCE: &str = "test-data/project_1/dummy.cpp";
+
+fn benchmarks(c: &mut Criterion) {
+    let path = Path::new(SOURCE);
+    let reader = SourceReader::default();
+    let coupling = Coupling::default();
+    let (_, source, space, _) = read_spaces(path, &reader)
+        .expect("test data is readable")
+        .expect("test data has functions");
+    let context = FileContext::new(SOURCE.to_string(), &source, &coupling);
+
+    c.bench_function("read_spaces", |b| {
+        b.iter(|| read_spaces(black_box(path), &reader))
+    });
+    c.bench_function("flatten_spaces", |b| {
+        b.iter(|| {
+            let mut flattened = Vec::new();
+            flatten_spaces(black_box(&space.spaces), &context, &mut flattened);
+            flattened
+        })
+    });
+    c.bench_function("flatten_spaces_extended", |b| {
+        b.iter(|| {
+            let mut flattened = Vec::new();
+            flatten_spaces_extended(black_box(&space.spaces), &context, &mut flattened);
+            flattened
+        })
+    });
+
+    let mut flattened: Vec<FlattenedMetrics> = Vec::new();
+    flatten_spaces(&space.spaces, &context, &mut flattened);
+    c.bench_function("to_rows", |b| b.iter(|| to_rows(black_box(&flattened))));
+
+    let rows = to_rows(&flattened).expect("rows serialize");
+    c.bench_function("write_csv", |b| {
+        b.iter(|| write_csv(sink(), black_box(&rows)))
+    });
+    c.bench_function("write_json", |b| {
+        b.iter(|| {
+            let mut stream = RowStream::json(sink());
+            for row in black_box(&rows) {
+                stream.write(row)?;
+            }
+            stream.finish()
+        })
+    });
+}
+
+criterion_group!(benches, benchmarks);
+criterion_main!(benches);
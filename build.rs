@@ -0,0 +1,25 @@
+//! Embeds the commit `archaeo` was built from, reported by `--version-info` and recorded in
+//! the manifest of every run
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=ARCHAEO_GIT_HASH={}", hash);
+
+    // Outside a checkout, as when built from crates.io, there's nothing to watch
+    for watched in [".git/HEAD", ".git/refs"] {
+        if Path::new(watched).exists() {
+            println!("cargo:rerun-if-changed={}", watched);
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,56 @@
+//! Names for the closures, lambdas and anonymous functions rust-code-analysis leaves
+//! unnamed, which would otherwise all share `<anonymous>` and can't be joined on
+
+use rust_code_analysis::{FuncSpace, SpaceKind};
+
+use crate::functions::FunctionNode;
+
+/// What becomes of an anonymous space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnonymousNaming {
+    /// Named `<parent>::lambda@line:col` after where it starts
+    Synthetic,
+    /// Written with a null name
+    Null,
+    /// Left out of the output, the spaces nested in it are still visited
+    Drop,
+}
+
+impl AnonymousNaming {
+    /// Parses an `--anonymous`, already checked by clap
+    pub fn parse(naming: &str) -> Self {
+        match naming {
+            "null" => Self::Null,
+            "drop" => Self::Drop,
+            _ => Self::Synthetic,
+        }
+    }
+}
+
+/// Whether `space` is a function rust-code-analysis found no name for
+pub fn is_anonymous(space: &FuncSpace) -> bool {
+    space.kind == SpaceKind::Function
+        && space
+            .name
+            .as_deref()
+            .is_none_or(|name| name.is_empty() || name == "<anonymous>")
+}
+
+/// The synthetic name of an anonymous `space` nested in the space named `parent`, the
+/// `nth` function on its lines. Without its `node` in the syntax tree, the column gives way
+/// to that place among the functions on the line, as `lambda@line#n`
+pub fn synthetic_name(
+    parent: Option<&str>,
+    space: &FuncSpace,
+    node: Option<&FunctionNode>,
+    nth: usize,
+) -> String {
+    let position = match node {
+        Some(node) => format!("{}:{}", space.start_line, node.column),
+        None => format!("{}#{}", space.start_line, nth + 1),
+    };
+    match parent {
+        Some(parent) => format!("{}::lambda@{}", parent, position),
+        None => format!("lambda@{}", position),
+    }
+}
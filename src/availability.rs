@@ -0,0 +1,76 @@
+//! Which metrics rust-code-analysis defines for every language. The ones it leaves out
+//! come out as 0.0, indistinguishable from a real zero, unless `--mask-unsupported` nulls
+//! them and records what is defined in a `metrics_supported` bitmask
+
+use rust_code_analysis::LANG;
+
+/// A family of metric columns, numbered by its bit in `metrics_supported`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// `fn_args`, `closure_args` and `nargs_*`
+    NArgs = 0,
+    /// `nexits*`
+    Exits = 1,
+    /// `cognitive*`
+    Cognitive = 2,
+    /// `cyclomatic*`
+    Cyclomatic = 3,
+    /// `halstead_*`
+    Halstead = 4,
+    /// `loc_*`
+    Loc = 5,
+    /// `nom_*`
+    Nom = 6,
+    /// `mi_*`, derived from the Halstead volume, cyclomatic complexity and lines of code
+    Mi = 7,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 8] = [
+        Metric::NArgs,
+        Metric::Exits,
+        Metric::Cognitive,
+        Metric::Cyclomatic,
+        Metric::Halstead,
+        Metric::Loc,
+        Metric::Nom,
+        Metric::Mi,
+    ];
+
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// The `metrics_supported` bitmask of `language`
+pub fn supported(language: &LANG) -> u32 {
+    let computed: &[Metric] = match language {
+        LANG::Cpp
+        | LANG::Rust
+        | LANG::Python
+        | LANG::Mozjs
+        | LANG::Javascript
+        | LANG::Typescript
+        | LANG::Tsx => &Metric::ALL,
+        // Only the argument and function counts, which work off the grammar alone
+        LANG::Java => &[Metric::NArgs, Metric::Nom],
+        LANG::Preproc | LANG::Ccomment => &[],
+    };
+    computed.iter().fold(0, |mask, metric| mask | metric.bit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_hold_a_bit_per_defined_family() {
+        assert_eq!(supported(&LANG::Rust), 0xff);
+        assert_eq!(
+            supported(&LANG::Java),
+            Metric::NArgs.bit() | Metric::Nom.bit()
+        );
+        assert_eq!(supported(&LANG::Java), 65);
+        assert_eq!(supported(&LANG::Preproc), 0);
+    }
+}
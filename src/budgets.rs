@@ -0,0 +1,223 @@
+//! Per-path metric limits from a `budgets.toml`, checked like `--fail-on` rules. Every file
+//! gets the limits of the first budget whose glob matches it, or the default ones:
+//!
+//! ```toml
+//! default = ["cyclomatic<=10"]
+//!
+//! [[budget]]
+//! name = "parser"
+//! path = "src/parser/**"
+//! limits = ["cyclomatic<=25", "cognitive<=30"]
+//! ```
+//!
+//! Globs are relative to the directory of the budgets file
+
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::CliError;
+use crate::gate::{parse_limit, Rule};
+
+/// Name of the budget of the files no other budget matches
+pub const DEFAULT: &str = "default";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BudgetsFile {
+    #[serde(default)]
+    default: Vec<String>,
+    #[serde(default)]
+    budget: Vec<BudgetEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BudgetEntry {
+    name: Option<String>,
+    path: String,
+    limits: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Budget {
+    name: String,
+    glob: GlobMatcher,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+pub struct Budgets {
+    root: PathBuf,
+    default: Vec<Rule>,
+    budgets: Vec<Budget>,
+}
+
+impl Budgets {
+    pub fn from_file(path: &Path) -> Result<Self, CliError> {
+        let invalid =
+            |message: String| CliError::InvalidArgument(format!("{}: {}", path.display(), message));
+        let file: BudgetsFile =
+            toml::from_str(&fs::read_to_string(path)?).map_err(|err| invalid(err.to_string()))?;
+
+        let limits = |limits: &[String], name: &str| -> Result<Vec<Rule>, CliError> {
+            limits
+                .iter()
+                .map(|limit| parse_limit(limit, name).map_err(&invalid))
+                .collect()
+        };
+        let budgets = file
+            .budget
+            .iter()
+            .map(|entry| {
+                let name = entry.name.clone().unwrap_or_else(|| entry.path.clone());
+                let glob = GlobBuilder::new(&entry.path)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|err| invalid(format!("invalid path {}: {}", entry.path, err)))?
+                    .compile_matcher();
+                Ok(Budget {
+                    rules: limits(&entry.limits, &name)?,
+                    name,
+                    glob,
+                })
+            })
+            .collect::<Result<_, CliError>>()?;
+
+        let root = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        Ok(Self {
+            root: root.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            default: limits(&file.default, DEFAULT)?,
+            budgets,
+        })
+    }
+
+    /// The name and rules of the budget of `file`
+    pub fn budget_of(&self, file: &Path) -> (&str, &[Rule]) {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let relative = file.strip_prefix(&self.root).unwrap_or(&file);
+        match self
+            .budgets
+            .iter()
+            .find(|budget| budget.glob.is_match(relative))
+        {
+            Some(budget) => (&budget.name, &budget.rules),
+            None => (DEFAULT, &self.default),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn budgets(text: &str) -> (TempDir, Result<Budgets, CliError>) {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("budgets.toml");
+        fs::write(&path, text).unwrap();
+        let budgets = Budgets::from_file(&path);
+        (directory, budgets)
+    }
+
+    /// The budget of every one of `files` under the directory of the budgets file, with the
+    /// rules it fails functions by
+    fn budgets_of(text: &str, files: &[&str]) -> Vec<(String, Vec<String>)> {
+        let (directory, budgets) = budgets(text);
+        let budgets = budgets.unwrap();
+        let root = directory.path().canonicalize().unwrap();
+        files
+            .iter()
+            .map(|file| {
+                let (name, rules) = budgets.budget_of(&root.join(file));
+                let rules = rules.iter().map(Rule::to_string).collect();
+                (name.to_string(), rules)
+            })
+            .collect()
+    }
+
+    fn budget(name: &str, rules: &[&str]) -> (String, Vec<String>) {
+        (
+            name.to_string(),
+            rules.iter().map(|rule| rule.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn files_get_the_first_budget_matching_them() {
+        let text = r#"
+default = ["cyclomatic<=10"]
+
+[[budget]]
+name = "parser"
+path = "src/parser/**"
+limits = ["cyclomatic<=25", "cognitive<30"]
+
+[[budget]]
+path = "src/**"
+limits = ["cyclomatic<=15"]
+"#;
+        assert_eq!(
+            budgets_of(
+                text,
+                &[
+                    "src/parser/lexer/a.c",
+                    "src/b.c",
+                    "tests/c.c",
+                    "src/parser.c"
+                ]
+            ),
+            [
+                budget(
+                    "parser",
+                    &[
+                        "the parser budget of cyclomatic<=25",
+                        "the parser budget of cognitive<30"
+                    ]
+                ),
+                budget("src/**", &["the src/** budget of cyclomatic<=15"]),
+                budget("default", &["the default budget of cyclomatic<=10"]),
+                budget("src/**", &["the src/** budget of cyclomatic<=15"]),
+            ]
+        );
+        // Without default limits, files no budget matches are free of any
+        assert_eq!(
+            budgets_of(
+                "[[budget]]\npath = \"*.c\"\nlimits = []\n",
+                &["a.c", "src/a.c"]
+            ),
+            [budget("*.c", &[]), budget("default", &[])]
+        );
+    }
+
+    #[test]
+    fn limits_turn_into_the_rules_breaking_them() {
+        let rule = |limit: &str| parse_limit(limit, "parser").unwrap();
+        let breaks = |limit: &str, cyclomatic: f64| {
+            let row = serde_json::json!({ "cyclomatic": cyclomatic });
+            rule(limit).breaks(row.as_object().unwrap())
+        };
+        assert!(!breaks("cyclomatic<=25", 25.0));
+        assert!(breaks("cyclomatic<=25", 26.0));
+        assert!(breaks("cyclomatic<25", 25.0));
+        assert!(!breaks("cyclomatic>=2", 2.0));
+        assert!(breaks("cyclomatic>=2", 1.0));
+        assert!(breaks("cyclomatic>2", 2.0));
+        // Shown as the limit they enforce, not the rule breaking it
+        assert_eq!(
+            rule("cyclomatic>2").to_string(),
+            "the parser budget of cyclomatic>2"
+        );
+        assert_eq!(rule("cyclomatic>2").budget.as_deref(), Some("parser"));
+    }
+
+    #[test]
+    fn broken_budgets_files_are_refused() {
+        let error = |text: &str| budgets(text).1.unwrap_err().to_string();
+        assert!(error("default = [\"cyclomatic=10\"]\n").contains("expected e.g. cyclomatic>15"));
+        assert!(error("[[budget]]\npath = \"[\"\nlimits = []\n").contains("invalid path ["));
+        assert!(error("[[budget]]\npath = \"a\"\nlimit = []\n").contains("unknown field"));
+        assert!(error("defaults = []\n").contains("unknown field"));
+    }
+}
@@ -0,0 +1,231 @@
+//! Defect labels from the history: the commits whose message tells they fix something,
+//! and how many of them touched every file and function, for `bugfix_count` and
+//! `file_bugfix_count`. With the SZZ algorithm, the lines every fix removed are blamed on
+//! the commits introducing them, counted in `bug_inducing_changes`
+
+use rayon::prelude::*;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::errors::CliError;
+use crate::git;
+
+/// Pattern of the messages of fixing commits when `--bugfixes` is given without one
+pub const DEFAULT_PATTERN: &str = r"(?i)\b(fix(e[sd])?|bugs?|cve-\d+)\b";
+
+/// Lines of a file a commit added or changed, as the lines they are at in the revision
+/// labelled
+#[derive(Debug)]
+struct Change {
+    commit: String,
+    start: usize,
+    end: usize,
+}
+
+/// The changes of some commits, by the path of the file they're in
+type Changes = HashMap<String, Vec<Change>>;
+
+/// The fixing commits of the history of a revision
+#[derive(Debug)]
+pub struct Bugfixes {
+    /// Root of the repository
+    root: PathBuf,
+    /// How many fixing commits changed every file, by its path relative to the root
+    files: HashMap<String, usize>,
+    /// The lines every fixing commit changed
+    fixes: Changes,
+    /// The lines changed by the commits introducing lines a fix later removed, in the
+    /// files they were removed from, when SZZ is run
+    inducing: Option<Changes>,
+}
+
+/// The fixes touching a range of lines
+#[derive(Debug, Default)]
+pub struct LineFixes {
+    /// How many fixing commits changed the lines
+    pub fixes: usize,
+    /// How many commits changing the lines introduced lines later fixed, when SZZ is run
+    pub inducing: Option<usize>,
+}
+
+impl Bugfixes {
+    /// The commits of the history of `rev` in the repository at `root` with a message
+    /// matching `pattern`, and with `szz` the commits inducing them. Every one of them is
+    /// diffed once, and the lines it changed followed to `rev` through the diff between
+    /// them
+    pub fn find(root: &Path, rev: &str, pattern: &str, szz: bool) -> Result<Self, CliError> {
+        let pattern = Regex::new(pattern).map_err(|err| {
+            CliError::InvalidArgument(format!("invalid --bugfixes pattern: {}", err))
+        })?;
+        let mut commits = HashSet::new();
+        let mut files: HashMap<String, usize> = HashMap::new();
+        let history = git::messages(root, rev)?;
+        for commit in history
+            .iter()
+            .filter(|commit| pattern.is_match(&commit.message))
+        {
+            for file in &commit.changed {
+                *files.entry(file.clone()).or_default() += 1;
+            }
+            commits.insert(commit.hash.clone());
+        }
+        info!(
+            "{} of the {} commits of {} fix something",
+            commits.len(),
+            history.len(),
+            rev
+        );
+
+        let fixes = changes(root, rev, commits.iter().map(|commit| (commit, None)));
+        let inducing = szz.then(|| {
+            let inducing = inducing(root, &commits);
+            info!("{} commits introduced lines fixed later", inducing.len());
+            let commits = inducing.iter().map(|(commit, files)| (commit, Some(files)));
+            changes(root, rev, commits)
+        });
+        Ok(Self {
+            root: root.to_path_buf(),
+            files,
+            fixes,
+            inducing,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The fixes of `file`, a path relative to the root of the repository
+    pub fn file(&self, file: String) -> FileBugfixes<'_> {
+        FileBugfixes {
+            bugfixes: self,
+            file,
+        }
+    }
+}
+
+/// The fixing commits touching a file
+#[derive(Debug)]
+pub struct FileBugfixes<'a> {
+    bugfixes: &'a Bugfixes,
+    file: String,
+}
+
+impl FileBugfixes<'_> {
+    /// How many fixing commits changed the file
+    pub fn count(&self) -> usize {
+        self.bugfixes.files.get(&self.file).copied().unwrap_or(0)
+    }
+
+    /// The fixes of lines `start` to `end` of the file
+    pub fn between(&self, start: usize, end: usize) -> LineFixes {
+        let touching = |changes: &Changes| {
+            let commits: HashSet<&str> = changes
+                .get(&self.file)
+                .into_iter()
+                .flatten()
+                .filter(|change| change.start <= end && start <= change.end)
+                .map(|change| change.commit.as_str())
+                .collect();
+            commits.len()
+        };
+        LineFixes {
+            fixes: touching(&self.bugfixes.fixes),
+            inducing: self.bugfixes.inducing.as_ref().map(touching),
+        }
+    }
+}
+
+/// The lines each of `commits` added or changed, followed to `rev`. When given, only those
+/// in the files paired with a commit
+fn changes<'a>(
+    root: &Path,
+    rev: &str,
+    commits: impl Iterator<Item = (&'a String, Option<&'a HashSet<String>>)>,
+) -> Changes {
+    let commits: Vec<_> = commits.collect();
+    let changed: Vec<(String, Change)> = commits
+        .par_iter()
+        .flat_map_iter(|&(commit, only)| {
+            let diff = git::commit_diff(root, commit)
+                .and_then(|files| Ok((files, git::line_maps(root, commit, rev)?)));
+            let (files, line_maps) = diff.unwrap_or_else(|err| {
+                warn!(commit = %commit, "Failed to diff: {}", err);
+                Default::default()
+            });
+            let mut changed = Vec::new();
+            for file in files {
+                let Some(path) = file.new_path else {
+                    continue;
+                };
+                if only.is_some_and(|only| !only.contains(&path)) {
+                    continue;
+                }
+                let line_map = line_maps.get(&path);
+                let at = |line: usize| line_map.map_or(line, |line_map| line_map.map(line));
+                for &(_, _, new_start, new_count) in &file.hunks {
+                    // A hunk only removing lines touches the line they were removed after
+                    let (first, last) = if new_count == 0 {
+                        (new_start.max(1), new_start.max(1))
+                    } else {
+                        (new_start, new_start + new_count - 1)
+                    };
+                    let change = Change {
+                        commit: commit.clone(),
+                        start: at(first),
+                        end: at(last),
+                    };
+                    changed.push((path.clone(), change));
+                }
+            }
+            changed
+        })
+        .collect();
+
+    let mut changes = Changes::new();
+    for (path, change) in changed {
+        changes.entry(path).or_default().push(change);
+    }
+    changes
+}
+
+/// The commits introducing the lines the fixing `commits` removed or changed, blamed on
+/// the commit before each fix, with the paths of the files the lines were in
+fn inducing(root: &Path, commits: &HashSet<String>) -> HashMap<String, HashSet<String>> {
+    let blamed: Vec<(String, String)> = commits
+        .par_iter()
+        .flat_map_iter(|fix| {
+            let files = git::commit_diff(root, fix).unwrap_or_else(|err| {
+                warn!(commit = %fix, "Failed to diff: {}", err);
+                Vec::new()
+            });
+            let parent = format!("{}^", fix);
+            files
+                .into_iter()
+                .filter_map(|file| {
+                    let removed: Vec<(usize, usize)> = file
+                        .hunks
+                        .iter()
+                        .filter(|hunk| hunk.1 > 0)
+                        .map(|hunk| (hunk.0, hunk.1))
+                        .collect();
+                    Some((file.old_path?, removed)).filter(|(_, removed)| !removed.is_empty())
+                })
+                .flat_map(|(file, removed)| {
+                    git::blame_lines(root, &parent, &file, &removed).unwrap_or_else(|err| {
+                        warn!(commit = %fix, file = %file, "Failed to blame: {}", err);
+                        Vec::new()
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut inducing: HashMap<String, HashSet<String>> = HashMap::new();
+    for (commit, file) in blamed {
+        inducing.entry(commit).or_default().insert(file);
+    }
+    inducing
+}
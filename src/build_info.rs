@@ -0,0 +1,109 @@
+//! What an `archaeo` binary was built from. Metric values can shift between versions of
+//! rust-code-analysis and the tree-sitter grammars, so these are reported by
+//! `--version-info` and recorded in the manifest of every run
+
+use rust_code_analysis::LANG;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::manifest::RUST_CODE_ANALYSIS_VERSION;
+
+/// Short hash of the commit the binary was built from, `unknown` outside a git checkout
+pub const GIT_HASH: &str = env!("ARCHAEO_GIT_HASH");
+
+/// The grammars rust-code-analysis parses with, pinned alongside it in Cargo.toml
+pub const GRAMMARS: [(&str, &str); 11] = [
+    ("tree-sitter", "0.19.3"),
+    ("tree-sitter-ccomment", "0.19.0"),
+    ("tree-sitter-cpp", "0.19.0"),
+    ("tree-sitter-java", "0.19.0"),
+    ("tree-sitter-javascript", "0.19.0"),
+    ("tree-sitter-mozcpp", "0.19.5"),
+    ("tree-sitter-mozjs", "0.19.0"),
+    ("tree-sitter-preproc", "0.19.0"),
+    ("tree-sitter-python", "0.19.0"),
+    ("tree-sitter-rust", "0.19.0"),
+    ("tree-sitter-typescript", "0.19.0"),
+];
+
+/// The grammar rust-code-analysis parses `language` with and its version, such as
+/// `tree-sitter-mozcpp@0.19.5`
+pub fn grammar_version(language: &LANG) -> String {
+    let grammar = match language {
+        LANG::Mozjs => "tree-sitter-mozjs",
+        LANG::Javascript => "tree-sitter-javascript",
+        LANG::Java => "tree-sitter-java",
+        LANG::Rust => "tree-sitter-rust",
+        LANG::Cpp => "tree-sitter-mozcpp",
+        LANG::Python => "tree-sitter-python",
+        LANG::Tsx | LANG::Typescript => "tree-sitter-typescript",
+        LANG::Ccomment => "tree-sitter-ccomment",
+        LANG::Preproc => "tree-sitter-preproc",
+    };
+    let version = GRAMMARS
+        .iter()
+        .find(|(name, _)| *name == grammar)
+        .map_or("unknown", |(_, version)| version);
+    format!("{}@{}", grammar, version)
+}
+
+/// Every optional feature, with whether it was compiled in
+const FEATURES: [(&str, bool); 10] = [
+    ("wasm", cfg!(feature = "wasm")),
+    ("arrow", cfg!(feature = "arrow")),
+    ("flight", cfg!(feature = "flight")),
+    ("parquet", cfg!(feature = "parquet")),
+    ("object-store", cfg!(feature = "object-store")),
+    ("postgres", cfg!(feature = "postgres")),
+    ("telemetry", cfg!(feature = "telemetry")),
+    ("tui", cfg!(feature = "tui")),
+    ("sqlite", cfg!(feature = "sqlite")),
+    ("webhook", cfg!(feature = "webhook")),
+];
+
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub archaeo_version: &'static str,
+    pub git_hash: &'static str,
+    pub rust_code_analysis_version: &'static str,
+    pub grammar_versions: BTreeMap<&'static str, &'static str>,
+    /// The optional features compiled in
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            archaeo_version: env!("CARGO_PKG_VERSION"),
+            git_hash: GIT_HASH,
+            rust_code_analysis_version: RUST_CODE_ANALYSIS_VERSION,
+            grammar_versions: GRAMMARS.into_iter().collect(),
+            features: FEATURES
+                .into_iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| feature)
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "archaeo {} ({})", self.archaeo_version, self.git_hash)?;
+        writeln!(
+            f,
+            "{:<24}{}",
+            "rust-code-analysis", self.rust_code_analysis_version
+        )?;
+        for (grammar, version) in &self.grammar_versions {
+            writeln!(f, "{:<24}{}", grammar, version)?;
+        }
+        let features = if self.features.is_empty() {
+            "none".to_string()
+        } else {
+            self.features.join(", ")
+        };
+        write!(f, "{:<24}{}", "features", features)
+    }
+}
@@ -0,0 +1,269 @@
+use rayon::prelude::*;
+use rust_code_analysis::LANG;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+use tree_sitter::Node;
+
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::generated::Skipped;
+use crate::language::SourceReader;
+use crate::syntax::{collapse_whitespace, node_text, parse, symbol_kind, symbol_name};
+
+/// The calls made from within a single function definition
+#[derive(Debug)]
+pub struct FunctionCalls {
+    pub name: String,
+    pub start_line: usize,
+    /// Callee names in the order they appear, including repeats
+    pub callees: Vec<String>,
+}
+
+/// Walks the syntax tree rooted at `root` and records, for every function definition,
+/// the names of the functions it calls. Calls in nested definitions are attributed to
+/// the innermost one.
+pub fn extract_calls(lang: &LANG, root: Node, source: &[u8]) -> Vec<FunctionCalls> {
+    let mut functions: Vec<FunctionCalls> = Vec::new();
+    let mut stack: Vec<(Node, Option<usize>)> = vec![(root, None)];
+
+    while let Some((node, current)) = stack.pop() {
+        let mut child_current = current;
+
+        if symbol_kind(lang, &node) == Some("function") {
+            functions.push(FunctionCalls {
+                name: symbol_name(lang, &node, source).unwrap_or("no_name_found".to_string()),
+                start_line: node.start_position().row + 1,
+                callees: Vec::new(),
+            });
+            child_current = Some(functions.len() - 1);
+        } else if let Some(idx) = current {
+            if let Some(callee) = callee_name(lang, &node, source) {
+                functions[idx].callees.push(callee);
+            }
+        }
+
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.named_children(&mut cursor).collect();
+        for child in children.into_iter().rev() {
+            stack.push((child, child_current));
+        }
+    }
+
+    functions
+}
+
+/// Returns the name of the called function if `node` is a call site. Calls through
+/// anything other than a (possibly qualified or member) name, such as function
+/// pointers, are ignored.
+fn callee_name(lang: &LANG, node: &Node, source: &[u8]) -> Option<String> {
+    let function = match (lang, node.kind()) {
+        (LANG::Java, "method_invocation") => node.child_by_field_name("name")?,
+        (LANG::Python, "call") | (_, "call_expression") => node.child_by_field_name("function")?,
+        _ => return None,
+    };
+
+    let name = match function.kind() {
+        "identifier" | "qualified_identifier" | "scoped_identifier" => function,
+        "field_expression" => function.child_by_field_name("field")?,
+        "member_expression" => function.child_by_field_name("property")?,
+        "attribute" => function.child_by_field_name("attribute")?,
+        "template_function" | "generic_function" => function
+            .child_by_field_name("name")
+            .or_else(|| function.child_by_field_name("function"))?,
+        _ => return None,
+    };
+
+    Some(collapse_whitespace(&node_text(&name, source)))
+}
+
+/// The unqualified part of a function name, used to match call sites against
+/// definitions (`Foo::bar` and `obj.bar` both become `bar`)
+pub fn simple_name(name: &str) -> &str {
+    name.rsplit([':', '.']).next().unwrap_or(name)
+}
+
+/// A caller -> callee edge. `callee_file` is only populated when the callee could be
+/// matched against a definition by name, preferring definitions in the caller's file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CallEdge {
+    pub caller: String,
+    pub caller_file: String,
+    pub caller_start_line: usize,
+    pub callee: String,
+    pub callee_file: Option<String>,
+    pub calls: usize,
+}
+
+/// The calls of every file, keyed and sorted by the file's path
+pub type CorpusCalls = Vec<(String, Vec<FunctionCalls>)>;
+
+/// Extracts the calls of every file
+pub fn corpus_calls(filepaths: &[PathBuf], reader: &SourceReader) -> CorpusCalls {
+    let mut per_file: Vec<(String, Vec<FunctionCalls>)> = filepaths
+        .par_iter()
+        .filter_map(|fp| {
+            file_calls(fp, reader).map(|calls| (fp.to_string_lossy().to_string(), calls))
+        })
+        .collect();
+    per_file.sort_by(|a, b| a.0.cmp(&b.0));
+    per_file
+}
+
+/// Discovers the files under `path` and extracts their calls like `corpus_calls`, reading
+/// every file as soon as the walk finds it. Also returns the files in the order they were
+/// walked and those `--skip-generated` left out
+pub fn discover_calls(
+    discovery: &DiscoveryArgs,
+    path: &Path,
+    reader: &SourceReader,
+) -> Result<(Vec<PathBuf>, Vec<Skipped>, CorpusCalls), CliError> {
+    let (found, skipped) = discovery.discover_visiting(path, |fp| file_calls(fp, reader))?;
+    let mut filepaths = Vec::with_capacity(found.len());
+    let mut per_file = Vec::new();
+    for (fp, calls) in found {
+        if let Some(calls) = calls {
+            per_file.push((fp.to_string_lossy().to_string(), calls));
+        }
+        filepaths.push(fp);
+    }
+    per_file.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok((filepaths, skipped, per_file))
+}
+
+/// Like `corpus_calls` for a single file whose contents are held in memory
+pub fn source_calls(
+    path: &Path,
+    bytes: &[u8],
+    reader: &SourceReader,
+) -> Vec<(String, Vec<FunctionCalls>)> {
+    let read = reader
+        .decode_bytes(path, bytes.to_vec())
+        .map(|(language, source, _)| (language, source));
+    calls_of(path, read)
+        .map(|calls| vec![(path.to_string_lossy().to_string(), calls)])
+        .unwrap_or_default()
+}
+
+fn file_calls(path: &Path, reader: &SourceReader) -> Option<Vec<FunctionCalls>> {
+    calls_of(path, reader.read(path))
+}
+
+fn calls_of(path: &Path, read: Result<(LANG, Vec<u8>), CliError>) -> Option<Vec<FunctionCalls>> {
+    let (language, source) = match read {
+        Ok(read) => read,
+        Err(CliError::FailedGuessLang(_)) => {
+            warn!(file = %path.display(), "Failed to guess programming lang");
+            return None;
+        }
+        Err(_) => {
+            warn!(file = %path.display(), "Failed to read");
+            return None;
+        }
+    };
+
+    let tree = match parse(&language, &source) {
+        Some(tree) => tree,
+        None => {
+            warn!(file = %path.display(), "Failed to parse");
+            return None;
+        }
+    };
+
+    let calls = extract_calls(&language, tree.root_node(), &source);
+    debug!("Found {} functions in {}", calls.len(), path.display());
+
+    Some(calls)
+}
+
+/// Collapses repeated call sites into counted edges and resolves each callee against
+/// the definitions seen across the whole corpus
+pub fn build_edges(per_file: &[(String, Vec<FunctionCalls>)]) -> Vec<CallEdge> {
+    let mut definitions: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for (file, functions) in per_file {
+        for function in functions {
+            definitions
+                .entry(simple_name(&function.name))
+                .or_default()
+                .insert(file.as_str());
+        }
+    }
+
+    let mut edges = Vec::new();
+    for (file, functions) in per_file {
+        for function in functions {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for callee in &function.callees {
+                *counts.entry(callee.as_str()).or_insert(0) += 1;
+            }
+
+            for (callee, calls) in counts {
+                let callee_file = definitions.get(simple_name(callee)).and_then(|files| {
+                    if files.contains(file.as_str()) {
+                        Some(file.clone())
+                    } else if files.len() == 1 {
+                        files.iter().next().map(|f| f.to_string())
+                    } else {
+                        None
+                    }
+                });
+
+                edges.push(CallEdge {
+                    caller: function.name.clone(),
+                    caller_file: file.clone(),
+                    caller_start_line: function.start_line,
+                    callee: callee.to_string(),
+                    callee_file,
+                    calls,
+                });
+            }
+        }
+    }
+
+    edges
+}
+
+/// Per-function coupling derived from the corpus call graph
+#[derive(Debug, Default)]
+pub struct Coupling {
+    /// Distinct callees, keyed by (file, start line) of the caller
+    fan_out: HashMap<(String, usize), usize>,
+    /// Distinct callers resolved onto a definition, keyed by (file, simple name)
+    fan_in: HashMap<(String, String), usize>,
+}
+
+impl Coupling {
+    pub fn from_edges(edges: &[CallEdge]) -> Self {
+        let mut coupling = Coupling::default();
+
+        for edge in edges {
+            *coupling
+                .fan_out
+                .entry((edge.caller_file.clone(), edge.caller_start_line))
+                .or_insert(0) += 1;
+
+            if let Some(callee_file) = &edge.callee_file {
+                *coupling
+                    .fan_in
+                    .entry((callee_file.clone(), simple_name(&edge.callee).to_string()))
+                    .or_insert(0) += 1;
+            }
+        }
+
+        coupling
+    }
+
+    pub fn fan_out(&self, file: &str, start_line: usize) -> f64 {
+        self.fan_out
+            .get(&(file.to_string(), start_line))
+            .map_or(0.0, |n| *n as f64)
+    }
+
+    /// Best-effort, as callers are matched to definitions by name only
+    pub fn fan_in(&self, file: &str, name: &str) -> f64 {
+        self.fan_in
+            .get(&(file.to_string(), simple_name(name).to_string()))
+            .map_or(0.0, |n| *n as f64)
+    }
+}
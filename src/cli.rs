@@ -1,26 +1,171 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
 use clap::Subcommand;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use color_eyre::Result;
+use std::ffi::OsString;
 
+use crate::build_info::BuildInfo;
 use crate::commands;
 use crate::errors::CliError;
+use crate::logging::LoggingArgs;
+use crate::telemetry::TelemetryArgs;
 
 #[derive(Parser)]
-#[command(author, version, about)]
+#[command(author, version, about, arg_required_else_help = true)]
 pub struct Cli {
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+
+    /// Print the versions of rust-code-analysis and the tree-sitter grammars, the commit
+    /// and the features `archaeo` was built with
+    #[arg(long)]
+    version_info: bool,
+
+    #[command(flatten)]
+    pub logging: LoggingArgs,
+
+    #[command(flatten)]
+    pub telemetry: TelemetryArgs,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Source(commands::source::SourceCommand),
+    Source(Box<commands::source::SourceCommand>),
+    Stats(commands::stats::StatsCommand),
+    Symbols(commands::symbols::SymbolsCommand),
+    Callgraph(commands::callgraph::CallgraphCommand),
+    Deps(commands::deps::DepsCommand),
+    Ghidra(commands::ghidra::GhidraCommand),
+    Pair(commands::pair::PairCommand),
+    Patch(commands::patch::PatchCommand),
+    Trend(commands::trend::TrendCommand),
+    Survival(commands::survival::SurvivalCommand),
+    Hotspots(commands::hotspots::HotspotsCommand),
+    Risk(commands::risk::RiskCommand),
+    Scorecard(commands::scorecard::ScorecardCommand),
+    Validate(commands::validate::ValidateCommand),
+    Merge(commands::merge::MergeCommand),
+    Serve(commands::serve::ServeCommand),
+    Init(commands::init::InitCommand),
+    Visualize(commands::visualize::VisualizeCommand),
+    Lsp(commands::lsp::LspCommand),
+    Completions(commands::completions::CompletionsCommand),
+    Man(commands::man::ManCommand),
+    #[cfg(feature = "parquet")]
+    HfExport(commands::hf_export::HfExportCommand),
+    #[cfg(feature = "arrow")]
+    Arrow(commands::arrow::ArrowCommand),
+    #[cfg(feature = "tui")]
+    Tui(commands::tui::TuiCommand),
+    #[cfg(feature = "sqlite")]
+    Record(commands::record::RecordCommand),
+    #[cfg(feature = "sqlite")]
+    Query(commands::query::QueryCommand),
 }
 
 impl Cli {
+    /// Parses the command line, filling in the options of `source` it leaves out from the
+    /// project configuration
+    pub fn parse_configured() -> Result<Self, CliError> {
+        Self::parse_configured_from(std::env::args_os())
+    }
+
+    /// Like `parse_configured`, for the arguments `args`. Bad arguments, `--help` and
+    /// `--version` fail with `CliError::Usage` for the caller to print and exit on
+    pub fn parse_configured_from<I, T>(args: I) -> Result<Self, CliError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Cli::command().try_get_matches_from(args)?;
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        match (cli.version_info, &cli.command) {
+            (true, Some(_)) => {
+                return Err(Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--version-info can't be combined with a subcommand",
+                    )
+                    .into())
+            }
+            (false, None) => {
+                return Err(Cli::command()
+                    .error(
+                        ErrorKind::MissingSubcommand,
+                        "a subcommand is required unless --version-info is passed",
+                    )
+                    .into())
+            }
+            _ => {}
+        }
+        if let (Some(Commands::Source(cmd)), Some(("source", source))) =
+            (&mut cli.command, matches.subcommand())
+        {
+            cmd.configure(|id| source.value_source(id) == Some(ValueSource::CommandLine))?;
+        }
+        Ok(cli)
+    }
+
     pub fn execute(self) -> Result<(), CliError> {
-        match self.command {
-            Commands::Source(cmd) => cmd.execute(),
+        let Some(command) = self.command else {
+            // Only `--version-info` runs without a subcommand
+            println!("{}", BuildInfo::current());
+            return Ok(());
+        };
+        match command {
+            Commands::Source(cmd) => (*cmd).execute(),
+            Commands::Stats(cmd) => cmd.execute(),
+            Commands::Symbols(cmd) => cmd.execute(),
+            Commands::Callgraph(cmd) => cmd.execute(),
+            Commands::Deps(cmd) => cmd.execute(),
+            Commands::Ghidra(cmd) => cmd.execute(),
+            Commands::Pair(cmd) => cmd.execute(),
+            Commands::Patch(cmd) => cmd.execute(),
+            Commands::Trend(cmd) => cmd.execute(),
+            Commands::Survival(cmd) => cmd.execute(),
+            Commands::Hotspots(cmd) => cmd.execute(),
+            Commands::Risk(cmd) => cmd.execute(),
+            Commands::Scorecard(cmd) => cmd.execute(),
+            Commands::Validate(cmd) => cmd.execute(),
+            Commands::Merge(cmd) => cmd.execute(),
+            Commands::Serve(cmd) => cmd.execute(),
+            Commands::Init(cmd) => cmd.execute(),
+            Commands::Visualize(cmd) => cmd.execute(),
+            Commands::Lsp(cmd) => cmd.execute(),
+            Commands::Completions(cmd) => cmd.execute(),
+            Commands::Man(cmd) => cmd.execute(),
+            #[cfg(feature = "parquet")]
+            Commands::HfExport(cmd) => cmd.execute(),
+            #[cfg(feature = "arrow")]
+            Commands::Arrow(cmd) => cmd.execute(),
+            #[cfg(feature = "tui")]
+            Commands::Tui(cmd) => cmd.execute(),
+            #[cfg(feature = "sqlite")]
+            Commands::Record(cmd) => cmd.execute(),
+            #[cfg(feature = "sqlite")]
+            Commands::Query(cmd) => cmd.execute(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::exit_code;
+
+    #[test]
+    fn usage_errors_are_returned_rather_than_exiting() {
+        let code = |args: &[&str]| match Cli::parse_configured_from(args) {
+            Err(err @ CliError::Usage(_)) => err.exit_code(),
+            _ => panic!("{:?} parsed", args),
+        };
+        assert_eq!(code(&["archaeo", "source", "--fmt", "xml"]), exit_code::USAGE);
+        assert_eq!(code(&["archaeo", "--version-info", "stats"]), exit_code::USAGE);
+        // Printing the help isn't a failure
+        assert_eq!(code(&["archaeo", "--help"]), exit_code::SUCCESS);
+
+        let cli = Cli::parse_configured_from(["archaeo", "--version-info"]).unwrap();
+        assert!(cli.version_info && cli.command.is_none());
+    }
+}
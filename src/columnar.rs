@@ -0,0 +1,267 @@
+//! Conversion of metric rows into Arrow record batches for the columnar outputs
+
+use arrow_array::builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Float32Array, Float64Array, Int32Array, Int64Array,
+    LargeStringArray, RecordBatch, StringArray, UInt64Array,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+#[cfg(feature = "parquet")]
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+#[cfg(feature = "parquet")]
+use parquet::arrow::ArrowWriter;
+#[cfg(feature = "parquet")]
+use parquet::basic::Compression;
+#[cfg(feature = "parquet")]
+use parquet::file::properties::WriterProperties;
+use serde_json::{json, Value};
+#[cfg(feature = "parquet")]
+use std::fs::File;
+use std::io::Write;
+#[cfg(feature = "parquet")]
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::errors::CliError;
+use crate::extract::MetricsRow;
+
+/// Infers a schema covering every column of `rows`, in the order columns are first seen.
+/// Whole numbers stay integers, mixed numbers become floats and anything else is text
+pub fn infer_schema(rows: &[MetricsRow]) -> SchemaRef {
+    let mut columns: Vec<(String, Option<DataType>)> = Vec::new();
+    for row in rows {
+        for (key, value) in row {
+            let index = match columns.iter().position(|(name, _)| name == key) {
+                Some(index) => index,
+                None => {
+                    columns.push((key.clone(), None));
+                    columns.len() - 1
+                }
+            };
+            let current = &mut columns[index].1;
+            *current = match (current.take(), value_type(value)) {
+                (current, None) => current,
+                (None, seen) => seen,
+                (Some(a), Some(b)) if a == b => Some(a),
+                (Some(DataType::Int64), Some(DataType::Float64))
+                | (Some(DataType::Float64), Some(DataType::Int64)) => Some(DataType::Float64),
+                _ => Some(DataType::Utf8),
+            };
+        }
+    }
+
+    Arc::new(Schema::new(
+        columns
+            .into_iter()
+            .map(|(name, ty)| Field::new(name, ty.unwrap_or(DataType::Utf8), true))
+            .collect::<Vec<Field>>(),
+    ))
+}
+
+fn value_type(value: &Value) -> Option<DataType> {
+    match value {
+        Value::Null => None,
+        Value::Bool(_) => Some(DataType::Boolean),
+        Value::Number(n) if n.is_i64() || n.is_u64() => Some(DataType::Int64),
+        Value::Number(_) => Some(DataType::Float64),
+        _ => Some(DataType::Utf8),
+    }
+}
+
+/// Builds a record batch of `rows` conforming to `schema`. Missing values are null
+pub fn record_batch(schema: &SchemaRef, rows: &[MetricsRow]) -> Result<RecordBatch, CliError> {
+    let columns: Vec<ArrayRef> = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let values = rows.iter().map(|row| row.get(field.name()));
+            let array: ArrayRef = match field.data_type() {
+                DataType::Boolean => {
+                    let mut builder = BooleanBuilder::new();
+                    values.for_each(|v| builder.append_option(v.and_then(Value::as_bool)));
+                    Arc::new(builder.finish())
+                }
+                DataType::Int64 => {
+                    let mut builder = Int64Builder::new();
+                    values.for_each(|v| builder.append_option(v.and_then(Value::as_i64)));
+                    Arc::new(builder.finish())
+                }
+                DataType::Float64 => {
+                    let mut builder = Float64Builder::new();
+                    values.for_each(|v| builder.append_option(v.and_then(Value::as_f64)));
+                    Arc::new(builder.finish())
+                }
+                _ => {
+                    let mut builder = StringBuilder::new();
+                    values.for_each(|v| match v {
+                        None | Some(Value::Null) => builder.append_null(),
+                        Some(Value::String(s)) => builder.append_value(s),
+                        Some(other) => builder.append_value(other.to_string()),
+                    });
+                    Arc::new(builder.finish())
+                }
+            };
+            array
+        })
+        .collect();
+
+    RecordBatch::try_new(schema.clone(), columns)
+        .map_err(|err| CliError::ExportError(err.to_string()))
+}
+
+/// Splits `rows` into record batches of at most `batch_size` rows
+pub fn record_batches(
+    schema: &SchemaRef,
+    rows: &[MetricsRow],
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>, CliError> {
+    rows.chunks(batch_size.max(1))
+        .map(|chunk| record_batch(schema, chunk))
+        .collect()
+}
+
+/// Writes `batches` as an Arrow IPC stream
+pub fn write_ipc_stream<W: Write>(
+    writer: W,
+    schema: &SchemaRef,
+    batches: &[RecordBatch],
+) -> Result<(), CliError> {
+    let export = |err: arrow_schema::ArrowError| CliError::ExportError(err.to_string());
+    let mut writer = StreamWriter::try_new(writer, schema).map_err(export)?;
+    for batch in batches {
+        writer.write(batch).map_err(export)?;
+    }
+    writer.finish().map_err(export)
+}
+
+/// Writes `rows` to a Snappy compressed parquet file with `schema`
+#[cfg(feature = "parquet")]
+pub fn write_parquet(path: &Path, schema: &SchemaRef, rows: &[MetricsRow]) -> Result<(), CliError> {
+    let export = |err: parquet::errors::ParquetError| CliError::ExportError(err.to_string());
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(properties))
+        .map_err(export)?;
+    writer.write(&record_batch(schema, rows)?).map_err(export)?;
+    writer.close().map_err(export)?;
+    Ok(())
+}
+
+/// Reads every row of a parquet file written by `write_parquet`
+#[cfg(feature = "parquet")]
+pub fn read_parquet(path: &Path) -> Result<Vec<MetricsRow>, CliError> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+        .and_then(|builder| builder.build())
+        .map_err(|err| CliError::FailedProcessing(format!("{}: {}", path.display(), err)))?;
+
+    let mut rows = Vec::new();
+    for batch in reader {
+        let batch = batch
+            .map_err(|err| CliError::FailedProcessing(format!("{}: {}", path.display(), err)))?;
+        rows.extend(batch_rows(&batch)?);
+    }
+    Ok(rows)
+}
+
+/// Converts a record batch back into rows
+pub fn batch_rows(batch: &RecordBatch) -> Result<Vec<MetricsRow>, CliError> {
+    let schema = batch.schema();
+    let mut rows = vec![MetricsRow::new(); batch.num_rows()];
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        for (index, row) in rows.iter_mut().enumerate() {
+            row.insert(field.name().clone(), value_at(column, index)?);
+        }
+    }
+    Ok(rows)
+}
+
+/// The value at `index` of an array of one of the types `infer_schema` produces, or of a
+/// common numeric or string type
+fn value_at(array: &ArrayRef, index: usize) -> Result<Value, CliError> {
+    if array.is_null(index) {
+        return Ok(Value::Null);
+    }
+    let any = array.as_any();
+    let value = match array.data_type() {
+        DataType::Boolean => json!(any.downcast_ref::<BooleanArray>().map(|a| a.value(index))),
+        DataType::Int32 => json!(any.downcast_ref::<Int32Array>().map(|a| a.value(index))),
+        DataType::Int64 => json!(any.downcast_ref::<Int64Array>().map(|a| a.value(index))),
+        DataType::UInt64 => json!(any.downcast_ref::<UInt64Array>().map(|a| a.value(index))),
+        DataType::Float32 => json!(any.downcast_ref::<Float32Array>().map(|a| a.value(index))),
+        DataType::Float64 => json!(any.downcast_ref::<Float64Array>().map(|a| a.value(index))),
+        DataType::Utf8 => json!(any.downcast_ref::<StringArray>().map(|a| a.value(index))),
+        DataType::LargeUtf8 => {
+            json!(any
+                .downcast_ref::<LargeStringArray>()
+                .map(|a| a.value(index)))
+        }
+        other => {
+            return Err(CliError::FailedProcessing(format!(
+                "unsupported column type {}",
+                other
+            )))
+        }
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_ipc::reader::StreamReader;
+
+    fn rows(values: Value) -> Vec<MetricsRow> {
+        serde_json::from_value(values).unwrap()
+    }
+
+    #[test]
+    fn schemas_widen_to_hold_every_value() {
+        let schema = infer_schema(&rows(json!([
+            { "name": "f", "nargs": 1, "cyclomatic": 1, "exported": true, "note": null },
+            { "name": "g", "nargs": 2, "cyclomatic": 1.5, "exported": 3 },
+        ])));
+        let types: Vec<(&str, &DataType)> = schema
+            .fields()
+            .iter()
+            .map(|field| (field.name().as_str(), field.data_type()))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("name", &DataType::Utf8),
+                ("nargs", &DataType::Int64),
+                ("cyclomatic", &DataType::Float64),
+                ("exported", &DataType::Utf8),
+                ("note", &DataType::Utf8),
+            ]
+        );
+    }
+
+    #[test]
+    fn ipc_streams_hand_back_the_rows_in_batches() {
+        let rows = rows(json!([
+            { "name": "f", "nargs": 1, "cyclomatic": 1.0 },
+            { "name": "g", "nargs": 2 },
+            { "name": "h", "nargs": 0, "cyclomatic": 2.5 },
+        ]));
+        let schema = infer_schema(&rows);
+        let batches = record_batches(&schema, &rows, 2).unwrap();
+        assert_eq!(
+            batches.iter().map(RecordBatch::num_rows).collect::<Vec<_>>(),
+            [2, 1]
+        );
+
+        let mut stream = Vec::new();
+        write_ipc_stream(&mut stream, &schema, &batches).unwrap();
+        let read: Vec<MetricsRow> = StreamReader::try_new(stream.as_slice(), None)
+            .unwrap()
+            .flat_map(|batch| batch_rows(&batch.unwrap()).unwrap())
+            .collect();
+        // Missing values come back as nulls
+        let mut expected = rows.clone();
+        expected[1].insert("cyclomatic".to_string(), Value::Null);
+        assert_eq!(read, expected);
+    }
+}
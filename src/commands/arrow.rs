@@ -0,0 +1,83 @@
+use clap::Args;
+use color_eyre::Result;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::columnar::{infer_schema, record_batches, write_ipc_stream};
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::extract::{extract_path, ExtractOptions};
+use crate::flight;
+use crate::language::LanguageArgs;
+
+#[derive(Args)]
+pub struct ArrowCommand {
+    /// Path to the source code to be processed
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// File to write the Arrow IPC stream to. Written to stdout when not provided or `-`
+    #[arg(short, long, conflicts_with = "serve")]
+    output_path: Option<PathBuf>,
+
+    /// Serve the rows as the `metrics` Arrow Flight on this address, such as
+    /// 127.0.0.1:50051, instead of writing a stream. Needs the `flight` feature
+    #[arg(long)]
+    serve: Option<String>,
+
+    /// Maximum number of rows per record batch
+    #[arg(long, default_value = "65536")]
+    batch_size: usize,
+
+    /// Include the source text of every function in a `source` column
+    #[arg(long, default_value = "false")]
+    with_source: bool,
+
+    /// Export the extended metric columns
+    #[arg(long, default_value = "false")]
+    extended: bool,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+impl ArrowCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if self.batch_size == 0 {
+            return Err(CliError::InvalidArgument(
+                "--batch-size must be at least 1".to_string(),
+            ));
+        }
+
+        let options = ExtractOptions {
+            extended: self.extended,
+            with_source: self.with_source,
+            ..ExtractOptions::new(&self.discovery, &self.language)
+        };
+        let rows = extract_path(&self.path, &options)?;
+        let schema = infer_schema(&rows);
+        let batches = record_batches(&schema, &rows, self.batch_size)?;
+
+        if let Some(address) = &self.serve {
+            return flight::serve(address, schema, batches);
+        }
+
+        match &self.output_path {
+            Some(path) if path.as_os_str() != "-" => {
+                write_ipc_stream(BufWriter::new(File::create(path)?), &schema, &batches)?;
+                info!("Wrote {} rows to {}", rows.len(), path.display());
+            }
+            _ => {
+                let mut stdout = io::stdout().lock();
+                write_ipc_stream(&mut stdout, &schema, &batches)?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
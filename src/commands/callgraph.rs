@@ -0,0 +1,156 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::callgraph::{build_edges, corpus_calls, simple_name, CallEdge, Coupling, FunctionCalls};
+use crate::commands::source::{flatten_spaces, read_spaces, FileContext, FlattenedMetrics};
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::graph_export::{metric_attributes, Attributes, Graph};
+use crate::language::{LanguageArgs, SourceReader};
+use tracing::{info, warn};
+
+#[derive(Args)]
+pub struct CallgraphCommand {
+    /// Path to the source code to extract the call graph from
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// Optional file to save the call graph to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the call graph. csv and json write the edge list
+    /// Options: csv, json, dot, graphml, node-link
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["csv", "json", "dot", "graphml", "node-link"]))]
+    fmt: String,
+
+    /// Attach the flattened metric columns of each function as node attributes
+    /// when writing a graph format
+    #[arg(long, default_value = "false")]
+    with_metrics: bool,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+impl CallgraphCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let filepaths = self.discovery.discover(&self.path)?;
+        let reader = self.language.reader()?;
+        info!("Extracting calls from {} files...", filepaths.len());
+
+        let calls = corpus_calls(&filepaths, &reader);
+        let edges = build_edges(&calls);
+        info!("Found {} call edges", edges.len());
+
+        let writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match self.fmt.as_str() {
+            "csv" => {
+                let mut writer = csv::Writer::from_writer(writer);
+                for entry in &edges {
+                    writer.serialize(entry)?
+                }
+                writer.flush()?;
+            }
+            "json" => {
+                let mut writer = writer;
+                serde_json::to_writer_pretty(&mut writer, &edges)?;
+                writeln!(writer)?;
+            }
+            fmt => {
+                let metrics = if self.with_metrics {
+                    function_metrics(&filepaths, &reader, &Coupling::from_edges(&edges))
+                } else {
+                    HashMap::new()
+                };
+                let mut writer = writer;
+                build_graph(&calls, &edges, &metrics).write(fmt, &mut writer)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds the call graph with a node for every defined function, optionally carrying
+/// its metrics, plus a node for every unresolved callee
+fn build_graph(
+    calls: &[(String, Vec<FunctionCalls>)],
+    edges: &[CallEdge],
+    metrics: &HashMap<(String, usize), Attributes>,
+) -> Graph {
+    let mut graph = Graph::new("callgraph");
+
+    for (file, functions) in calls {
+        for function in functions {
+            let node = graph.node(&node_id(&function.name, Some(file)), &function.name);
+            node.attributes
+                .insert("source_file".to_string(), json!(file));
+            if let Some(attributes) = metrics.get(&(file.clone(), function.start_line)) {
+                node.attributes.extend(attributes.clone());
+            }
+        }
+    }
+
+    for edge in edges {
+        let caller = node_id(&edge.caller, Some(&edge.caller_file));
+        let callee = node_id(&edge.callee, edge.callee_file.as_deref());
+        graph.node(&callee, &edge.callee);
+        graph.edge(
+            &caller,
+            &callee,
+            Attributes::from([("calls".to_string(), json!(edge.calls))]),
+        );
+    }
+
+    graph
+}
+
+/// Flattened metrics of every function in the corpus, keyed by (file, start line)
+fn function_metrics(
+    filepaths: &[PathBuf],
+    reader: &SourceReader,
+    coupling: &Coupling,
+) -> HashMap<(String, usize), Attributes> {
+    filepaths
+        .par_iter()
+        .flat_map(|fp| {
+            let file = fp.to_string_lossy().to_string();
+            let mut flattened: Vec<FlattenedMetrics> = Vec::new();
+            match read_spaces(fp, reader) {
+                Ok(Some((_, source, space, _))) => flatten_spaces(
+                    &space.spaces,
+                    &FileContext::new(file.clone(), &source, coupling),
+                    &mut flattened,
+                ),
+                _ => warn!(file = %fp.display(), "Failed to compute metrics"),
+            }
+            flattened
+                .into_iter()
+                .map(|row| ((file.clone(), row.start_line), metric_attributes(&row)))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Functions resolved to a file are keyed by file and name, unresolved callees (library
+/// functions, ambiguous names) by name alone
+fn node_id(name: &str, file: Option<&str>) -> String {
+    match file {
+        Some(file) => format!("{}:{}", file, simple_name(name)),
+        None => name.to_string(),
+    }
+}
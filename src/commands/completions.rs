@@ -0,0 +1,44 @@
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+use color_eyre::Result;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::cli::Cli;
+use crate::errors::CliError;
+
+#[derive(Args)]
+pub struct CompletionsCommand {
+    /// Shell to complete the arguments of `archaeo` in
+    #[arg(value_parser = clap::builder::PossibleValuesParser::new(["bash", "zsh", "fish"]))]
+    shell: String,
+
+    /// File to write the script to, instead of stdout
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+}
+
+impl CompletionsCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let shell = match self.shell.as_str() {
+            "zsh" => Shell::Zsh,
+            "fish" => Shell::Fish,
+            _ => Shell::Bash,
+        };
+        // Rendered up front, as clap_complete panics on write errors such as a closed pipe
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut Cli::command(), "archaeo", &mut script);
+        match &self.output_path {
+            Some(path) => fs::write(path, script),
+            None => io::stdout().write_all(&script),
+        }
+        .map_err(CliError::FileCreationError)?;
+
+        if let Some(path) = &self.output_path {
+            info!("Wrote {} completions to {}", self.shell, path.display());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,665 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use rust_code_analysis::LANG;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tree_sitter::Node;
+
+use crate::callgraph::Coupling;
+use crate::commands::source::{read_spaces, FileContext, FlattenedMetrics, SpaceIdentity};
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::graph_export::{metric_attributes, Attributes, Graph};
+use crate::language::{LanguageArgs, SourceReader};
+use crate::syntax::{collapse_whitespace, node_text, parse};
+use tracing::{debug, info, warn};
+
+#[derive(Args)]
+pub struct DepsCommand {
+    /// Path to the source code to extract the dependency graph from
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// Optional file to save the dependency graph to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the dependency graph. csv writes the edge list
+    /// Options: csv, dot, graphml, node-link
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["csv", "dot", "graphml", "node-link"]))]
+    fmt: String,
+
+    /// Attach the file level metric columns of each scanned file as node attributes
+    /// when writing a graph format
+    #[arg(long, default_value = "false")]
+    with_metrics: bool,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+/// A single `#include` / `use` / `import` found in a file
+#[derive(Debug)]
+pub struct Import {
+    pub target: String,
+    pub kind: &'static str,
+    pub line: usize,
+}
+
+/// A file -> dependency edge. `target_file` is only populated when the import could be
+/// resolved to one of the scanned files
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DepEdge {
+    pub source_file: String,
+    pub line: usize,
+    pub kind: String,
+    pub target: String,
+    pub target_file: Option<String>,
+    pub in_cycle: bool,
+}
+
+impl DepsCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let filepaths = self.discovery.discover(&self.path)?;
+        let reader = self.language.reader()?;
+        info!("Extracting dependencies from {} files...", filepaths.len());
+
+        let mut per_file: Vec<(String, LANG, Vec<Import>)> = filepaths
+            .par_iter()
+            .filter_map(|fp| {
+                file_imports(fp, &reader).map(|(lang, imports)| (normalise(fp), lang, imports))
+            })
+            .collect();
+        per_file.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut edges = resolve_edges(&per_file);
+        let cycles = mark_cycles(&mut edges);
+        for cycle in &cycles {
+            warn!("Dependency cycle detected: {}", cycle.join(" -> "));
+        }
+        info!(
+            "Found {} dependency edges and {} cycles",
+            edges.len(),
+            cycles.len()
+        );
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match self.fmt.as_str() {
+            "csv" => {
+                let mut writer = csv::Writer::from_writer(writer);
+                for entry in &edges {
+                    writer.serialize(entry)?
+                }
+                writer.flush()?;
+            }
+            fmt => {
+                let metrics = if self.with_metrics {
+                    file_metrics(&filepaths, &reader)
+                } else {
+                    HashMap::new()
+                };
+                build_graph(&edges, &metrics).write(fmt, &mut writer)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Paths are compared with forward slashes so suffix matching works across platforms
+fn normalise(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn file_imports(path: &Path, reader: &SourceReader) -> Option<(LANG, Vec<Import>)> {
+    let (language, source) = match reader.read(path) {
+        Ok(read) => read,
+        Err(CliError::FailedGuessLang(_)) => {
+            warn!(file = %path.display(), "Failed to guess programming lang");
+            return None;
+        }
+        Err(_) => {
+            warn!(file = %path.display(), "Failed to read");
+            return None;
+        }
+    };
+
+    let tree = match parse(&language, &source) {
+        Some(tree) => tree,
+        None => {
+            warn!(file = %path.display(), "Failed to parse");
+            return None;
+        }
+    };
+
+    let imports = extract_imports(&language, tree.root_node(), &source);
+    debug!("Found {} imports in {}", imports.len(), path.display());
+
+    Some((language, imports))
+}
+
+/// Collects every `#include` / `use` / `import` statement in the syntax tree
+pub fn extract_imports(lang: &LANG, root: Node, source: &[u8]) -> Vec<Import> {
+    let mut imports = Vec::new();
+    let mut stack = vec![root];
+
+    while let Some(node) = stack.pop() {
+        let line = node.start_position().row + 1;
+        let found: Vec<(String, &'static str)> = match (lang, node.kind()) {
+            (LANG::Cpp, "preproc_include") => node
+                .child_by_field_name("path")
+                .map(|path| vec![(strip_delimiters(&node_text(&path, source)), "include")])
+                .unwrap_or_default(),
+            (LANG::Rust, "use_declaration") => node
+                .child_by_field_name("argument")
+                .map(|arg| vec![(collapse_whitespace(&node_text(&arg, source)), "use")])
+                .unwrap_or_default(),
+            (LANG::Rust, "mod_item") if node.child_by_field_name("body").is_none() => node
+                .child_by_field_name("name")
+                .map(|name| vec![(node_text(&name, source), "mod")])
+                .unwrap_or_default(),
+            (LANG::Python, "import_statement") => {
+                let mut cursor = node.walk();
+                node.children_by_field_name("name", &mut cursor)
+                    .map(|name| {
+                        let name = name.child_by_field_name("name").unwrap_or(name);
+                        (node_text(&name, source), "import")
+                    })
+                    .collect()
+            }
+            (LANG::Python, "import_from_statement") => node
+                .child_by_field_name("module_name")
+                .map(|name| vec![(node_text(&name, source), "import")])
+                .unwrap_or_default(),
+            (LANG::Java, "import_declaration") => node
+                .named_child(0)
+                .map(|name| vec![(node_text(&name, source), "import")])
+                .unwrap_or_default(),
+            (LANG::Mozjs | LANG::Javascript | LANG::Typescript | LANG::Tsx, "import_statement") => {
+                node.child_by_field_name("source")
+                    .map(|src| vec![(strip_delimiters(&node_text(&src, source)), "import")])
+                    .unwrap_or_default()
+            }
+            _ => Vec::new(),
+        };
+
+        if found.is_empty() {
+            let mut cursor = node.walk();
+            let children: Vec<Node> = node.named_children(&mut cursor).collect();
+            stack.extend(children.into_iter().rev());
+        } else {
+            imports.extend(
+                found
+                    .into_iter()
+                    .map(|(target, kind)| Import { target, kind, line }),
+            );
+        }
+    }
+
+    imports
+}
+
+fn strip_delimiters(text: &str) -> String {
+    text.trim_matches(|c| c == '"' || c == '\'' || c == '<' || c == '>')
+        .to_string()
+}
+
+/// Candidate paths, relative to the scanned tree, an import may refer to
+fn candidates(lang: &LANG, import: &Import) -> Vec<String> {
+    let target = import.target.as_str();
+    match lang {
+        LANG::Cpp => vec![target.to_string()],
+        LANG::Python => {
+            let base = target.trim_start_matches('.').replace('.', "/");
+            vec![format!("{}.py", base), format!("{}/__init__.py", base)]
+        }
+        LANG::Java => vec![format!("{}.java", target.replace('.', "/"))],
+        LANG::Rust => {
+            let base = target
+                .trim_start_matches("crate::")
+                .trim_start_matches("self::")
+                .trim_start_matches("super::")
+                .split("::")
+                .filter(|segment| !segment.starts_with('{') && *segment != "*")
+                .collect::<Vec<&str>>()
+                .join("/");
+            // `use a::b::C` most likely lives in a/b.rs, `mod a;` in a.rs or a/mod.rs
+            let parent = base.rsplit_once('/').map(|(parent, _)| parent.to_string());
+            let mut paths = vec![format!("{}.rs", base), format!("{}/mod.rs", base)];
+            if let Some(parent) = parent {
+                paths.push(format!("{}.rs", parent));
+                paths.push(format!("{}/mod.rs", parent));
+            }
+            paths
+        }
+        _ => {
+            let base = target.trim_start_matches("./").to_string();
+            ["", ".js", ".jsx", ".ts", ".tsx", "/index.js", "/index.ts"]
+                .iter()
+                .map(|ext| format!("{}{}", base, ext))
+                .collect()
+        }
+    }
+}
+
+/// Resolves each import against the scanned files: first relative to the importing
+/// file, then by a unique path suffix anywhere in the tree
+fn resolve_edges(per_file: &[(String, LANG, Vec<Import>)]) -> Vec<DepEdge> {
+    let known: BTreeSet<&str> = per_file.iter().map(|(file, _, _)| file.as_str()).collect();
+
+    let mut edges = Vec::new();
+    for (file, lang, imports) in per_file {
+        let dir = file.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+        for import in imports {
+            let mut target_file = None;
+            for candidate in candidates(lang, import) {
+                let relative = join_relative(dir, &candidate);
+                if known.contains(relative.as_str()) {
+                    target_file = Some(relative);
+                    break;
+                }
+
+                let suffix = format!("/{}", candidate);
+                let mut matches = known
+                    .iter()
+                    .filter(|k| k.ends_with(&suffix) || **k == candidate);
+                if let (Some(found), None) = (matches.next(), matches.next()) {
+                    target_file = Some(found.to_string());
+                    break;
+                }
+            }
+
+            edges.push(DepEdge {
+                source_file: file.clone(),
+                line: import.line,
+                kind: import.kind.to_string(),
+                target: import.target.clone(),
+                target_file,
+                in_cycle: false,
+            });
+        }
+    }
+
+    edges
+}
+
+/// Joins `candidate` onto `dir`, folding away any `.` and `..` segments
+fn join_relative(dir: &str, candidate: &str) -> String {
+    let mut segments: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in candidate.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let joined = segments.join("/");
+    if dir.starts_with('/') {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+/// Finds the strongly connected components of the resolved file graph (Tarjan's
+/// algorithm), flags every edge inside one, and returns the files of each cycle
+fn mark_cycles(edges: &mut [DepEdge]) -> Vec<Vec<String>> {
+    let mut ids: BTreeMap<String, usize> = BTreeMap::new();
+    for edge in edges.iter() {
+        let next = ids.len();
+        ids.entry(edge.source_file.clone()).or_insert(next);
+        if let Some(target) = &edge.target_file {
+            let next = ids.len();
+            ids.entry(target.clone()).or_insert(next);
+        }
+    }
+    let names: Vec<String> = {
+        let mut names = vec![String::new(); ids.len()];
+        for (name, id) in &ids {
+            names[*id] = name.to_string();
+        }
+        names
+    };
+
+    let mut adjacency: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); ids.len()];
+    for edge in edges.iter() {
+        if let Some(target) = &edge.target_file {
+            adjacency[ids[edge.source_file.as_str()]].insert(ids[target.as_str()]);
+        }
+    }
+
+    let components = tarjan(&adjacency);
+    let mut component_of: HashMap<usize, usize> = HashMap::new();
+    let mut cycles = Vec::new();
+    for (index, component) in components.iter().enumerate() {
+        let is_cycle = component.len() > 1 || adjacency[component[0]].contains(&component[0]);
+        if is_cycle {
+            for node in component {
+                component_of.insert(*node, index);
+            }
+            let mut files: Vec<String> = component.iter().map(|n| names[*n].clone()).collect();
+            files.sort();
+            cycles.push(files);
+        }
+    }
+
+    for edge in edges.iter_mut() {
+        if let Some(target) = &edge.target_file {
+            let source = component_of.get(&ids[edge.source_file.as_str()]);
+            let target = component_of.get(&ids[target.as_str()]);
+            edge.in_cycle = source.is_some() && source == target;
+        }
+    }
+
+    cycles
+}
+
+/// Iterative Tarjan SCC so deep include chains can't overflow the stack
+fn tarjan(adjacency: &[BTreeSet<usize>]) -> Vec<Vec<usize>> {
+    let count = adjacency.len();
+    let mut index = vec![usize::MAX; count];
+    let mut lowlink = vec![0; count];
+    let mut on_stack = vec![false; count];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut next_index = 0;
+
+    for root in 0..count {
+        if index[root] != usize::MAX {
+            continue;
+        }
+
+        let mut work: Vec<(usize, Vec<usize>)> = Vec::new();
+        index[root] = next_index;
+        lowlink[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        work.push((root, adjacency[root].iter().rev().copied().collect()));
+
+        while let Some((node, pending)) = work.last_mut() {
+            let node = *node;
+            if let Some(next) = pending.pop() {
+                if index[next] == usize::MAX {
+                    index[next] = next_index;
+                    lowlink[next] = next_index;
+                    next_index += 1;
+                    stack.push(next);
+                    on_stack[next] = true;
+                    work.push((next, adjacency[next].iter().rev().copied().collect()));
+                } else if on_stack[next] {
+                    lowlink[node] = lowlink[node].min(index[next]);
+                }
+                continue;
+            }
+
+            work.pop();
+            if let Some((parent, _)) = work.last() {
+                lowlink[*parent] = lowlink[*parent].min(lowlink[node]);
+            }
+
+            if lowlink[node] == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+
+    components
+}
+
+/// Resolved files are identified by path, unresolved dependencies by the import itself
+fn node_id(edge: &DepEdge) -> &str {
+    edge.target_file.as_deref().unwrap_or(&edge.target)
+}
+
+/// Builds the file graph, optionally attaching the file level metrics of each scanned file
+fn build_graph(edges: &[DepEdge], metrics: &HashMap<String, Attributes>) -> Graph {
+    let mut graph = Graph::new("deps");
+
+    for edge in edges {
+        for (id, resolved) in [
+            (edge.source_file.as_str(), true),
+            (node_id(edge), edge.target_file.is_some()),
+        ] {
+            let node = graph.node(id, id);
+            node.attributes
+                .insert("resolved".to_string(), json!(resolved));
+            if let Some(attributes) = metrics.get(id) {
+                node.attributes.extend(attributes.clone());
+            }
+        }
+
+        let mut attributes = Attributes::from([
+            ("kind".to_string(), json!(edge.kind)),
+            ("in_cycle".to_string(), json!(edge.in_cycle)),
+        ]);
+        if edge.in_cycle {
+            attributes.insert("color".to_string(), json!("red"));
+        }
+        graph.edge(&edge.source_file, node_id(edge), attributes);
+    }
+
+    graph
+}
+
+/// File level (unit space) metrics of every scanned file, keyed by normalised path
+fn file_metrics(filepaths: &[PathBuf], reader: &SourceReader) -> HashMap<String, Attributes> {
+    filepaths
+        .par_iter()
+        .filter_map(|fp| match read_spaces(fp, reader) {
+            Ok(Some((_, source, space, _))) => {
+                let coupling = Coupling::default();
+                let context =
+                    FileContext::new(fp.to_string_lossy().to_string(), &source, &coupling);
+                let row = FlattenedMetrics::from_space(&space, SpaceIdentity::of(&space), &context);
+                Some((normalise(fp), metric_attributes(&row)))
+            }
+            _ => {
+                warn!(file = %fp.display(), "Failed to compute metrics");
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(target: &str) -> Import {
+        Import {
+            target: target.to_string(),
+            kind: "include",
+            line: 1,
+        }
+    }
+
+    /// The files scanned, every one of them with the includes it holds
+    fn includes(files: &[(&str, &[&str])]) -> Vec<(String, LANG, Vec<Import>)> {
+        files
+            .iter()
+            .map(|(file, targets)| {
+                let imports = targets.iter().map(|target| import(target)).collect();
+                (file.to_string(), LANG::Cpp, imports)
+            })
+            .collect()
+    }
+
+    /// An edge as `(source_file, target_file, in_cycle)`
+    type Edge = (String, Option<String>, bool);
+
+    /// The edges of `files`, with the cycles
+    fn graph(files: &[(&str, &[&str])]) -> (Vec<Edge>, Vec<Vec<String>>) {
+        let mut edges = resolve_edges(&includes(files));
+        let cycles = mark_cycles(&mut edges);
+        let edges = edges
+            .into_iter()
+            .map(|edge| (edge.source_file, edge.target_file, edge.in_cycle))
+            .collect();
+        (edges, cycles)
+    }
+
+    fn edge(source: &str, target: Option<&str>, in_cycle: bool) -> Edge {
+        (source.to_string(), target.map(String::from), in_cycle)
+    }
+
+    #[test]
+    fn a_file_including_itself_is_a_cycle() {
+        let (edges, cycles) = graph(&[("src/a.h", &["a.h", "stdio.h"])]);
+        assert_eq!(
+            edges,
+            [
+                edge("src/a.h", Some("src/a.h"), true),
+                edge("src/a.h", None, false)
+            ]
+        );
+        assert_eq!(cycles, [["src/a.h"]]);
+    }
+
+    #[test]
+    fn cycles_through_three_files_flag_only_their_edges() {
+        let (edges, cycles) = graph(&[
+            ("a.h", &["b.h"]),
+            ("b.h", &["c.h"]),
+            ("c.h", &["a.h", "d.h"]),
+            ("d.h", &[]),
+            ("main.c", &["a.h"]),
+        ]);
+        assert_eq!(
+            edges,
+            [
+                edge("a.h", Some("b.h"), true),
+                edge("b.h", Some("c.h"), true),
+                edge("c.h", Some("a.h"), true),
+                edge("c.h", Some("d.h"), false),
+                edge("main.c", Some("a.h"), false),
+            ]
+        );
+        assert_eq!(cycles, [["a.h", "b.h", "c.h"]]);
+    }
+
+    #[test]
+    fn deep_chains_dont_overflow_the_stack() {
+        let count = 100_000;
+        let adjacency: Vec<BTreeSet<usize>> = (0..count)
+            .map(|node| BTreeSet::from([(node + 1) % count]))
+            .collect();
+        let components = tarjan(&adjacency);
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0].len(), count);
+    }
+
+    #[test]
+    fn relative_includes_fold_their_dots() {
+        assert_eq!(join_relative("src/net", "../util/log.h"), "src/util/log.h");
+        assert_eq!(join_relative("src/net", "./tcp.h"), "src/net/tcp.h");
+        assert_eq!(join_relative("/abs/src", "../x.h"), "/abs/x.h");
+        assert_eq!(join_relative("", "../../x.h"), "x.h");
+
+        let (edges, _) = graph(&[
+            ("src/net/tcp.c", &["../util/log.h"]),
+            ("src/util/log.h", &[]),
+            ("vendor/util/log.h", &[]),
+        ]);
+        assert_eq!(
+            edges,
+            [edge("src/net/tcp.c", Some("src/util/log.h"), false)]
+        );
+    }
+
+    #[test]
+    fn ambiguous_suffixes_stay_unresolved() {
+        let files: &[(&str, &[&str])] = &[
+            ("app/main.c", &["config.h", "util.h"]),
+            ("lib/a/config.h", &[]),
+            ("lib/b/config.h", &[]),
+            ("lib/util.h", &[]),
+        ];
+        let (edges, _) = graph(files);
+        assert_eq!(
+            edges,
+            [
+                edge("app/main.c", None, false),
+                edge("app/main.c", Some("lib/util.h"), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn python_and_rust_imports_name_their_candidate_files() {
+        assert_eq!(
+            candidates(&LANG::Python, &import("pkg.sub")),
+            ["pkg/sub.py", "pkg/sub/__init__.py"]
+        );
+        assert_eq!(
+            candidates(&LANG::Python, &import(".utils")),
+            ["utils.py", "utils/__init__.py"]
+        );
+        assert_eq!(
+            candidates(&LANG::Rust, &import("crate::a::b::C")),
+            ["a/b/C.rs", "a/b/C/mod.rs", "a/b.rs", "a/b/mod.rs"]
+        );
+        assert_eq!(
+            candidates(&LANG::Rust, &import("self::a::{b, c}")),
+            ["a.rs", "a/mod.rs"]
+        );
+        assert_eq!(candidates(&LANG::Rust, &import("a")), ["a.rs", "a/mod.rs"]);
+        assert_eq!(
+            candidates(&LANG::Java, &import("org.example.App")),
+            ["org/example/App.java"]
+        );
+
+        let per_file = vec![
+            (
+                "pkg/main.py".to_string(),
+                LANG::Python,
+                vec![import(".utils"), import("pkg.sub")],
+            ),
+            ("pkg/utils.py".to_string(), LANG::Python, Vec::new()),
+            ("pkg/sub/__init__.py".to_string(), LANG::Python, Vec::new()),
+            (
+                "src/lib.rs".to_string(),
+                LANG::Rust,
+                vec![import("crate::a::b::C"), import("std::io")],
+            ),
+            ("src/a/b.rs".to_string(), LANG::Rust, Vec::new()),
+        ];
+        let targets: Vec<Option<String>> = resolve_edges(&per_file)
+            .into_iter()
+            .map(|edge| edge.target_file)
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                Some("pkg/utils.py".to_string()),
+                Some("pkg/sub/__init__.py".to_string()),
+                Some("src/a/b.rs".to_string()),
+                None,
+            ]
+        );
+    }
+}
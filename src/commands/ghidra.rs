@@ -0,0 +1,117 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::decompiled::{address, binary_name};
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::extract::{extract_path, ExtractOptions, MetricsRow};
+use crate::language::LanguageArgs;
+use crate::output::write_csv;
+
+#[derive(Args)]
+pub struct GhidraCommand {
+    /// Path to the directory headless Ghidra exported decompiled C to. Either one file per
+    /// binary (`<binary>.c`) or per function, grouped in a directory per binary
+    /// (`<binary>/<function>@<address>.c`, `<binary>/FUN_00401000.c`, ...)
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// Optional file to save the metrics to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the metrics
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    /// Export the extended metric columns
+    #[arg(long, default_value = "false")]
+    extended: bool,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+impl GhidraCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let options = ExtractOptions {
+            extended: self.extended,
+            decompiled: true,
+            ..ExtractOptions::new(&self.discovery, &LanguageArgs::default())
+        };
+        let rows: Vec<MetricsRow> = extract_path(&self.path, &options)?
+            .into_iter()
+            .map(|row| self.locate(row))
+            .collect();
+        info!("Extracted {} functions", rows.len());
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &rows)?,
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &rows)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the binary and address of a row from where its file sits in the export,
+    /// placing both right after the function name
+    fn locate(&self, row: MetricsRow) -> MetricsRow {
+        let source_file = row
+            .get("source_file")
+            .and_then(Value::as_str)
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        let name = row.get("name").and_then(Value::as_str).unwrap_or_default();
+
+        // Per-function exports are grouped into a directory per binary
+        let binary = match source_file
+            .strip_prefix(&self.path)
+            .map(|relative| relative.components().count())
+        {
+            Ok(depth) if depth > 1 => source_file
+                .parent()
+                .and_then(Path::file_name)
+                .map(|dir| dir.to_string_lossy().to_string())
+                .unwrap_or_else(|| binary_name(&source_file)),
+            _ => binary_name(&source_file),
+        };
+        // Ghidra's default names carry the address, otherwise per-function exports carry
+        // it in the file name
+        let address = address(name).or_else(|| {
+            source_file
+                .file_stem()
+                .and_then(|stem| address(&stem.to_string_lossy()))
+        });
+
+        let mut located = MetricsRow::new();
+        for (key, value) in row {
+            if key == "binary" {
+                continue;
+            }
+            let is_name = key == "name";
+            located.insert(key, value);
+            if is_name {
+                located.insert("binary".to_string(), json!(binary));
+                located.insert("address".to_string(), json!(address));
+            }
+        }
+        located
+    }
+}
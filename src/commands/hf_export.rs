@@ -0,0 +1,169 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::columnar::{infer_schema, write_parquet};
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::extract::{extract_path, ExtractOptions, MetricsRow};
+use crate::language::LanguageArgs;
+use arrow_schema::DataType;
+
+#[derive(Args)]
+pub struct HfExportCommand {
+    /// Path to the source code to be processed
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// Directory to write the dataset to
+    #[arg(short, long, required = true)]
+    output_path: PathBuf,
+
+    /// Name recorded in dataset_infos.json. Defaults to the output directory name
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Include the source text of every function in a `source` column
+    #[arg(long, default_value = "false")]
+    with_source: bool,
+
+    /// Export the extended metric columns
+    #[arg(long, default_value = "false")]
+    extended: bool,
+
+    /// Maximum number of rows per parquet shard
+    #[arg(long, default_value = "100000")]
+    shard_size: usize,
+
+    /// Train, validation and test ratios to split rows by, each split getting its own
+    /// shards. Everything goes into train when not provided
+    #[arg(long, value_delimiter = ',')]
+    split: Vec<f64>,
+
+    /// Whether whole files or individual functions are assigned to a split
+    /// Options: file, function
+    #[arg(long, default_value = "file", value_parser = clap::builder::PossibleValuesParser::new(["file", "function"]))]
+    split_by: String,
+
+    /// CSV of labels to merge into the rows they match
+    #[arg(long)]
+    labels: Option<PathBuf>,
+
+    /// Columns the labels are matched to rows on
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "source_file,name",
+        requires = "labels"
+    )]
+    join_on: Vec<String>,
+
+    /// Treat every file as decompiler pseudo-C, recording its binary in a `binary` column
+    #[arg(long, default_value = "false")]
+    decompiled: bool,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+impl HfExportCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if self.shard_size == 0 {
+            return Err(CliError::InvalidArgument(
+                "--shard-size must be at least 1".to_string(),
+            ));
+        }
+
+        let options = ExtractOptions {
+            extended: self.extended,
+            split: self.split.clone(),
+            split_by: self.split_by.clone(),
+            labels: self.labels.clone(),
+            join_on: self.join_on.clone(),
+            with_source: self.with_source,
+            decompiled: self.decompiled,
+            ..ExtractOptions::new(&self.discovery, &self.language)
+        };
+        let rows = extract_path(&self.path, &options)?;
+        info!("Exporting {} rows...", rows.len());
+
+        // The split is encoded in the shard names, as the Hub expects
+        let mut splits: BTreeMap<String, Vec<MetricsRow>> = BTreeMap::new();
+        for mut row in rows {
+            let split = match row.remove("split") {
+                Some(Value::String(split)) => split,
+                _ => "train".to_string(),
+            };
+            splits.entry(split).or_default().push(row);
+        }
+
+        let all_rows: Vec<MetricsRow> = splits.values().flatten().cloned().collect();
+        let schema = infer_schema(&all_rows);
+
+        let data_dir = self.output_path.join("data");
+        fs::create_dir_all(&data_dir)?;
+
+        let mut split_infos = Map::new();
+        for (split, rows) in &splits {
+            let shards = rows.len().div_ceil(self.shard_size).max(1);
+            let mut num_bytes = 0;
+            for (index, chunk) in rows.chunks(self.shard_size).enumerate() {
+                let path =
+                    data_dir.join(format!("{}-{:05}-of-{:05}.parquet", split, index, shards));
+                write_parquet(&path, &schema, chunk)?;
+                num_bytes += fs::metadata(&path)?.len();
+                info!("Wrote {}", path.display());
+            }
+            split_infos.insert(
+                split.clone(),
+                json!({ "name": split, "num_examples": rows.len(), "num_bytes": num_bytes }),
+            );
+        }
+
+        let features: Map<String, Value> = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let dtype = match field.data_type() {
+                    DataType::Boolean => "bool",
+                    DataType::Int64 => "int64",
+                    DataType::Float64 => "float64",
+                    _ => "string",
+                };
+                (
+                    field.name().clone(),
+                    json!({ "dtype": dtype, "_type": "Value" }),
+                )
+            })
+            .collect();
+
+        let name = self.name.clone().unwrap_or_else(|| {
+            self.output_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "archaeo".to_string())
+        });
+        let infos = json!({
+            "default": {
+                "description": format!("Source code metrics extracted by archaeo {}", env!("CARGO_PKG_VERSION")),
+                "dataset_name": name,
+                "config_name": "default",
+                "features": features,
+                "splits": split_infos,
+            }
+        });
+        serde_json::to_writer_pretty(
+            File::create(self.output_path.join("dataset_infos.json"))?,
+            &infos,
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::discovery::DiscoveryArgs;
+use crate::errors::{check_input, CliError};
+use crate::extract::{extract_dir, ExtractOptions, MetricsRow};
+use crate::git;
+use crate::language::LanguageArgs;
+use crate::output::write_csv;
+use crate::treemap::{self, Tile};
+
+#[derive(Args)]
+pub struct HotspotsCommand {
+    /// Path to the git repository to rank the files of. Its working tree is analysed
+    #[arg(long, default_value = ".")]
+    repo: PathBuf,
+
+    /// Only count churn from commits after this date, e.g. "1 year ago" or 2024-01-01
+    #[arg(long)]
+    since: Option<String>,
+
+    /// Metric column summed over the functions of a file to measure its complexity
+    #[arg(long, default_value = "cyclomatic")]
+    metric: String,
+
+    /// What churn counts
+    /// Options: commits, lines
+    #[arg(long, default_value = "commits", value_parser = clap::builder::PossibleValuesParser::new(["commits", "lines"]))]
+    churn: String,
+
+    /// Exponent weighting complexity in the score. 0 ignores it
+    #[arg(long, default_value = "1.0")]
+    complexity_weight: f64,
+
+    /// Exponent weighting churn in the score. 0 ignores it
+    #[arg(long, default_value = "1.0")]
+    churn_weight: f64,
+
+    /// Only output the highest scoring files
+    #[arg(long)]
+    top: Option<usize>,
+
+    /// Optional file to save the ranked files to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the ranked files
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    /// Also write an HTML treemap of the ranked files, sized by lines of code and coloured
+    /// by score
+    #[arg(long)]
+    treemap: Option<PathBuf>,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+/// The complexity, size and churn of a file
+#[derive(Debug, Default)]
+struct Hotspot {
+    functions: u64,
+    complexity: f64,
+    sloc: f64,
+    commits: u64,
+    lines: u64,
+    score: f64,
+}
+
+impl HotspotsCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if self.complexity_weight < 0.0 || self.churn_weight < 0.0 {
+            return Err(CliError::InvalidArgument(
+                "weights must not be negative".to_string(),
+            ));
+        }
+        check_input(&self.repo)?;
+        let root = git::toplevel(&self.repo)?;
+        let churn = git::churn(&root, self.since.as_deref())?;
+
+        let options = ExtractOptions::new(&self.discovery, &self.language);
+        let rows = extract_dir(&root, &options)?;
+        if rows
+            .first()
+            .is_some_and(|row| !row.get(&self.metric).is_some_and(Value::is_number))
+        {
+            return Err(CliError::InvalidArgument(format!(
+                "{} is not a numeric metric column",
+                self.metric
+            )));
+        }
+
+        let mut hotspots: BTreeMap<String, Hotspot> = BTreeMap::new();
+        for row in &rows {
+            let file = row
+                .get("source_file")
+                .and_then(Value::as_str)
+                .map(|file| git::relative(Path::new(file), &root))
+                .unwrap_or_default();
+            let hotspot = hotspots.entry(file).or_default();
+            hotspot.functions += 1;
+            hotspot.complexity += row.get(&self.metric).and_then(Value::as_f64).unwrap_or(0.0);
+            hotspot.sloc += row.get("loc_sloc").and_then(Value::as_f64).unwrap_or(0.0);
+        }
+        for (file, hotspot) in hotspots.iter_mut() {
+            let churn = churn.get(file).copied().unwrap_or_default();
+            hotspot.commits = churn.commits;
+            hotspot.lines = churn.lines;
+        }
+
+        let mut ranked = self.score(hotspots);
+        ranked.sort_by(|(a, x), (b, y)| y.score.total_cmp(&x.score).then_with(|| a.cmp(b)));
+        if let Some(top) = self.top {
+            ranked.truncate(top);
+        }
+        info!("Ranked {} files", ranked.len());
+
+        let records: Vec<MetricsRow> = ranked
+            .iter()
+            .enumerate()
+            .map(|(rank, (file, hotspot))| {
+                let mut record = MetricsRow::new();
+                record.insert("rank".to_string(), json!(rank + 1));
+                record.insert("source_file".to_string(), json!(file));
+                record.insert("functions".to_string(), json!(hotspot.functions));
+                record.insert(self.metric.clone(), json!(hotspot.complexity));
+                record.insert("loc_sloc".to_string(), json!(hotspot.sloc));
+                record.insert("commits".to_string(), json!(hotspot.commits));
+                record.insert("lines_changed".to_string(), json!(hotspot.lines));
+                record.insert("score".to_string(), json!(hotspot.score));
+                record
+            })
+            .collect();
+
+        if let Some(path) = &self.treemap {
+            let tiles: Vec<Tile> = ranked
+                .iter()
+                .map(|(file, hotspot)| Tile {
+                    label: file.clone(),
+                    size: hotspot.sloc,
+                    heat: hotspot.score,
+                    tooltip: format!(
+                        "{}\n{}: {}\ncommits: {}\nlines changed: {}\nscore: {:.3}",
+                        file,
+                        self.metric,
+                        hotspot.complexity,
+                        hotspot.commits,
+                        hotspot.lines,
+                        hotspot.score
+                    ),
+                })
+                .collect();
+            let mut writer = File::create(path)?;
+            treemap::write_html(&mut writer, "Hotspots", &tiles)?;
+        }
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &records)?,
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &records)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scores every file as `(complexity / max)^complexity_weight * (churn / max)^churn_weight`,
+    /// so the most complex, most changed file scores 1
+    fn score(&self, hotspots: BTreeMap<String, Hotspot>) -> Vec<(String, Hotspot)> {
+        let churn = |hotspot: &Hotspot| match self.churn.as_str() {
+            "lines" => hotspot.lines as f64,
+            _ => hotspot.commits as f64,
+        };
+        let max_complexity = hotspots.values().map(|h| h.complexity).fold(0.0, f64::max);
+        let max_churn = hotspots.values().map(churn).fold(0.0, f64::max);
+        let ratio = |value: f64, max: f64| if max > 0.0 { value / max } else { 0.0 };
+
+        hotspots
+            .into_iter()
+            .map(|(file, mut hotspot)| {
+                hotspot.score = ratio(hotspot.complexity, max_complexity)
+                    .powf(self.complexity_weight)
+                    * ratio(churn(&hotspot), max_churn).powf(self.churn_weight);
+                (file, hotspot)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,262 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use rust_code_analysis::get_from_ext;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use tracing::info;
+use walkdir::WalkDir;
+
+use crate::commands::stats::StatsCommand;
+use crate::config::CONFIG;
+use crate::errors::CliError;
+use crate::extract::{extract_dir, ExtractOptions};
+use crate::language::LanguageArgs;
+use crate::paths::slashed;
+
+/// Directories of vendored, installed or built code suggested as excludes
+const EXCLUDED_DIRS: [&str; 12] = [
+    "vendor",
+    "vendored",
+    "third_party",
+    "third-party",
+    "thirdparty",
+    "external",
+    "node_modules",
+    "build",
+    "target",
+    "dist",
+    "out",
+    "deps",
+];
+
+/// Metrics the suggested `fail_on` rules gate on
+const GATED: [&str; 2] = ["cyclomatic", "cognitive"];
+
+#[derive(Args)]
+pub struct InitCommand {
+    /// Repository to inspect
+    #[arg(short, long, default_value = ".")]
+    path: PathBuf,
+
+    /// File to write the configuration to
+    #[arg(short, long, default_value = CONFIG)]
+    output_path: PathBuf,
+
+    /// Overwrite the configuration if it already exists
+    #[arg(long, default_value = "false")]
+    force: bool,
+
+    /// Percentile of the current functions the suggested --fail-on rules gate at, so the
+    /// rest of them already pass
+    #[arg(long, default_value = "95")]
+    percentile: f64,
+}
+
+/// What `init` found in the repository
+#[derive(Default)]
+struct Inventory {
+    /// Source files per extension
+    extensions: BTreeMap<String, usize>,
+    /// Source files per language
+    languages: BTreeMap<String, usize>,
+    /// Vendored or build directories, relative to the repository
+    excluded: Vec<String>,
+    files: Vec<PathBuf>,
+}
+
+impl InitCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if self.output_path.exists() && !self.force {
+            return Err(CliError::InvalidArgument(format!(
+                "{} already exists, pass --force to overwrite it",
+                self.output_path.display()
+            )));
+        }
+        if !(0.0..=100.0).contains(&self.percentile) {
+            return Err(CliError::InvalidArgument(
+                "--percentile must be between 0 and 100".to_string(),
+            ));
+        }
+        if !self.path.is_dir() {
+            return Err(CliError::InvalidInputPath(format!(
+                "{} is not a directory",
+                self.path.display()
+            )));
+        }
+
+        let inventory = self.inventory();
+        let mut extensions: Vec<(&String, &usize)> = inventory.extensions.iter().collect();
+        extensions.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let extensions: Vec<String> = extensions.into_iter().map(|(ext, _)| ext.clone()).collect();
+        info!(
+            "Found {} source files, {} vendored or build directories",
+            inventory.files.len(),
+            inventory.excluded.len()
+        );
+
+        let reader = LanguageArgs::default().reader()?;
+        let sloc: f64 = inventory
+            .files
+            .par_iter()
+            .filter_map(|fp| StatsCommand::file_stats(fp, &reader))
+            .map(|stats| stats.sloc)
+            .sum();
+
+        let rows = if extensions.is_empty() {
+            Vec::new()
+        } else {
+            extract_dir(
+                &self.path,
+                &ExtractOptions {
+                    extensions: extensions.clone(),
+                    exclude: inventory.excluded.clone(),
+                    skip_generated: true,
+                    ..ExtractOptions::default()
+                },
+            )?
+        };
+        let rules: Vec<String> = GATED
+            .iter()
+            .filter_map(|metric| {
+                let mut values: Vec<f64> = rows
+                    .iter()
+                    .filter_map(|row| row.get(*metric).and_then(|value| value.as_f64()))
+                    .collect();
+                percentile(&mut values, self.percentile)
+                    .map(|limit| format!("{}>{}", metric, limit.ceil()))
+            })
+            .collect();
+
+        let mut text = String::new();
+        let _ = writeln!(
+            text,
+            "# archaeo configuration, read by `archaeo source` from the working directory or --config."
+        );
+        let _ = writeln!(text, "# Options given on the command line take precedence.");
+        let _ = writeln!(text);
+        let languages: Vec<String> = inventory
+            .languages
+            .iter()
+            .map(|(language, files)| format!("{} {}", files, language))
+            .collect();
+        let _ = writeln!(
+            text,
+            "# Found {} files ({}), {} source lines",
+            inventory.files.len(),
+            if languages.is_empty() {
+                "no supported languages".to_string()
+            } else {
+                languages.join(", ")
+            },
+            sloc
+        );
+        let _ = writeln!(text, "extensions = {}", array(&extensions));
+        let _ = writeln!(text);
+        let _ = writeln!(text, "# Vendored, installed and build directories");
+        let _ = writeln!(text, "exclude = {}", array(&inventory.excluded));
+        let _ = writeln!(text);
+        let _ = writeln!(text, "# Binary, generated, minified and amalgamated files");
+        let _ = writeln!(text, "skip_generated = true");
+        let _ = writeln!(text);
+        let _ = writeln!(
+            text,
+            "# The {}th percentile of the {} current functions, so the worst {}% of them fail",
+            self.percentile,
+            rows.len(),
+            100.0 - self.percentile
+        );
+        let _ = writeln!(text, "fail_on = {}", array(&rules));
+
+        fs::write(&self.output_path, text)?;
+        info!("Configuration written to {}", self.output_path.display());
+        Ok(())
+    }
+
+    /// Walks the repository for source files of the supported languages, without
+    /// descending into hidden, vendored or build directories
+    fn inventory(&self) -> Inventory {
+        let mut inventory = Inventory::default();
+        let mut walker = WalkDir::new(&self.path).into_iter();
+        while let Some(entry) = walker.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let relative = entry
+                .path()
+                .strip_prefix(&self.path)
+                .unwrap_or(entry.path());
+            if relative.as_os_str().is_empty() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy();
+            if entry.file_type().is_dir() {
+                if name.starts_with('.') {
+                    walker.skip_current_dir();
+                } else if EXCLUDED_DIRS.contains(&name.as_ref()) {
+                    inventory.excluded.push(format!("{}/**", slashed(relative)));
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+
+            let Some(ext) = entry.path().extension() else {
+                continue;
+            };
+            let ext = ext.to_string_lossy().to_lowercase();
+            if let Some(language) = get_from_ext(&ext) {
+                *inventory.extensions.entry(ext).or_default() += 1;
+                *inventory
+                    .languages
+                    .entry(language.get_name().to_string())
+                    .or_default() += 1;
+                inventory.files.push(entry.into_path());
+            }
+        }
+        inventory
+    }
+}
+
+/// The nearest-rank percentile of `values`
+pub fn percentile(values: &mut [f64], percentile: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let rank = (percentile / 100.0 * values.len() as f64).ceil() as usize;
+    Some(values[rank.clamp(1, values.len()) - 1])
+}
+
+/// A TOML array of strings
+fn array(items: &[String]) -> String {
+    let items: Vec<String> = items
+        .iter()
+        .map(|item| toml::Value::String(item.clone()).to_string())
+        .collect();
+    format!("[{}]", items.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_take_the_nearest_rank() {
+        let mut values = vec![5.0, 1.0, 4.0, 2.0, 3.0];
+        assert_eq!(percentile(&mut values, 95.0), Some(5.0));
+        assert_eq!(percentile(&mut values, 50.0), Some(3.0));
+        assert_eq!(percentile(&mut values, 0.0), Some(1.0));
+        assert_eq!(percentile(&mut [], 95.0), None);
+    }
+
+    #[test]
+    fn arrays_are_quoted_as_toml() {
+        assert_eq!(array(&[]), "[]");
+        assert_eq!(
+            array(&["vendor/**".to_string(), "build/**".to_string()]),
+            r#"["vendor/**", "build/**"]"#
+        );
+    }
+}
@@ -0,0 +1,391 @@
+use clap::Args;
+use color_eyre::Result;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{CodeLensRequest, Request as _};
+use lsp_types::{
+    CodeLens, CodeLensOptions, CodeLensParams, Command, Diagnostic, DiagnosticSeverity,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    NumberOrString, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+use crate::commands::source::read_spaces_from;
+use crate::config::Config;
+use crate::errors::CliError;
+use crate::extract::{extract_source, ExtractOptions, MetricsRow};
+use crate::gate::{self, parse_rule, Rule};
+use crate::language::LanguageArgs;
+use crate::suppress::Suppressions;
+
+/// Rules diagnostics are published for when neither `--fail-on` nor `archaeo.toml` give any
+const DEFAULT_RULES: &str = "cyclomatic>10";
+
+#[derive(Args)]
+pub struct LspCommand {
+    /// Rules to publish a warning for every function breaking, defaulting to the `fail_on`
+    /// of `archaeo.toml` in the working directory, or cyclomatic>10
+    #[arg(long, value_delimiter = ',', value_parser = parse_rule)]
+    fail_on: Vec<Rule>,
+
+    /// Metric columns shown in the code lens above every function
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "cyclomatic,cognitive,loc_sloc,mi_visual_studio"
+    )]
+    lens: Vec<String>,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+}
+
+impl LspCommand {
+    pub fn execute(mut self) -> Result<(), CliError> {
+        if self.fail_on.is_empty() {
+            self.fail_on = match Config::find(None)? {
+                Some((_, config)) if !config.fail_on.is_empty() => {
+                    config.rules().map_err(CliError::InvalidArgument)?
+                }
+                _ => vec![parse_rule(DEFAULT_RULES).map_err(CliError::InvalidArgument)?],
+            };
+        }
+        // Fails early on invalid --force-language or --map-ext values
+        self.language.reader()?;
+
+        let (connection, io_threads) = Connection::stdio();
+        let capabilities = ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+            code_lens_provider: Some(CodeLensOptions {
+                resolve_provider: Some(false),
+            }),
+            ..ServerCapabilities::default()
+        };
+        connection
+            .initialize(serde_json::to_value(capabilities)?)
+            .map_err(protocol)?;
+        info!(
+            "Language server started, warning on {}",
+            self.fail_on
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        self.serve(&connection)?;
+        drop(connection);
+        io_threads.join().map_err(protocol)?;
+        Ok(())
+    }
+
+    fn serve(&self, connection: &Connection) -> Result<(), CliError> {
+        let mut documents: HashMap<Url, Vec<MetricsRow>> = HashMap::new();
+        for message in &connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if connection.handle_shutdown(&request).map_err(protocol)? {
+                        return Ok(());
+                    }
+                    let response = self.respond(request, &documents);
+                    connection
+                        .sender
+                        .send(Message::Response(response))
+                        .map_err(protocol)?;
+                }
+                Message::Notification(notification) => {
+                    if let Some(published) = self.notify(notification, &mut documents)? {
+                        connection
+                            .sender
+                            .send(Message::Notification(published))
+                            .map_err(protocol)?;
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn respond(&self, request: Request, documents: &HashMap<Url, Vec<MetricsRow>>) -> Response {
+        if request.method != CodeLensRequest::METHOD {
+            return Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request {}", request.method),
+            );
+        }
+        let params: CodeLensParams = match serde_json::from_value(request.params) {
+            Ok(params) => params,
+            Err(err) => {
+                return Response::new_err(
+                    request.id,
+                    ErrorCode::InvalidParams as i32,
+                    err.to_string(),
+                )
+            }
+        };
+        let lenses: Vec<CodeLens> = documents
+            .get(&params.text_document.uri)
+            .map(|rows| rows.iter().map(|row| self.lens(row)).collect())
+            .unwrap_or_default();
+        Response::new_ok(request.id, lenses)
+    }
+
+    /// Tracks the rows of the open documents, returning the diagnostics to publish for a
+    /// changed one
+    fn notify(
+        &self,
+        notification: Notification,
+        documents: &mut HashMap<Url, Vec<MetricsRow>>,
+    ) -> Result<Option<Notification>, CliError> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                (params.text_document.uri, params.text_document.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params: DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                // Documents are synced in full, so the last change holds the whole text
+                let Some(change) = params.content_changes.pop() else {
+                    return Ok(None);
+                };
+                (params.text_document.uri, change.text)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                documents.remove(&params.text_document.uri);
+                return Ok(Some(publish(params.text_document.uri, Vec::new())));
+            }
+            _ => return Ok(None),
+        };
+
+        let path = uri
+            .to_file_path()
+            .unwrap_or_else(|_| PathBuf::from(uri.path()));
+        let options = ExtractOptions {
+            force_language: self.language.force_language.clone(),
+            map_ext: self.language.map_ext.clone(),
+            preprocess: self.language.preprocess.clone(),
+            define: self.language.define.clone(),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_source(&path, text.as_bytes(), &options).unwrap_or_else(|err| {
+            warn!(file = %path.display(), "Failed to extract: {}", err);
+            Vec::new()
+        });
+
+        let reader = self.language.reader()?;
+        let suppressions = match read_spaces_from(&path, text.as_bytes(), &reader) {
+            Ok(Some((_, source, space, _))) => Suppressions::new(&source, &space),
+            _ => Suppressions::default(),
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        let diagnostics = gate::check(&self.fail_on, &rows)
+            .into_iter()
+            .filter(|outcome| {
+                !suppressions.covers(outcome.start_line as usize, outcome.end_line as usize)
+            })
+            .flat_map(|outcome| {
+                let line = outcome.start_line.saturating_sub(1) as u32;
+                let width = lines
+                    .get(line as usize)
+                    .map_or(0, |text| text.encode_utf16().count() as u32);
+                outcome
+                    .violations
+                    .into_iter()
+                    .map(move |violation| Diagnostic {
+                        range: Range::new(Position::new(line, 0), Position::new(line, width)),
+                        severity: Some(DiagnosticSeverity::WARNING),
+                        code: Some(NumberOrString::String(violation.metric.clone())),
+                        source: Some("archaeo".to_string()),
+                        message: format!(
+                            "{} has a {} of {}, breaking {}",
+                            outcome.name, violation.metric, violation.value, violation.rule
+                        ),
+                        ..Diagnostic::default()
+                    })
+            })
+            .collect();
+
+        documents.insert(uri.clone(), rows);
+        Ok(Some(publish(uri, diagnostics)))
+    }
+
+    /// A lens above the function of `row` listing its `--lens` metrics
+    fn lens(&self, row: &MetricsRow) -> CodeLens {
+        let line = row
+            .get("start_line")
+            .and_then(Value::as_u64)
+            .unwrap_or(1)
+            .saturating_sub(1) as u32;
+        let title = self
+            .lens
+            .iter()
+            .filter_map(|metric| {
+                let value = row.get(metric)?;
+                let value = match value.as_f64() {
+                    Some(number) if number.fract() != 0.0 => format!("{:.1}", number),
+                    Some(number) => number.to_string(),
+                    None => value.to_string(),
+                };
+                Some(format!("{} {}", metric, value))
+            })
+            .collect::<Vec<_>>()
+            .join(" · ");
+        CodeLens {
+            range: Range::new(Position::new(line, 0), Position::new(line, 0)),
+            command: Some(Command {
+                title,
+                command: String::new(),
+                arguments: None,
+            }),
+            data: None,
+        }
+    }
+}
+
+fn publish(uri: Url, diagnostics: Vec<Diagnostic>) -> Notification {
+    Notification::new(
+        PublishDiagnostics::METHOD.to_string(),
+        PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        },
+    )
+}
+
+fn protocol(err: impl Display) -> CliError {
+    CliError::LspError(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use lsp_server::RequestId;
+    use lsp_types::notification::Notification as LspNotification;
+    use lsp_types::{TextDocumentIdentifier, TextDocumentItem};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        lsp: LspCommand,
+    }
+
+    const DOCUMENT: &str = "\
+int f(int x) {
+    if (x) return 1;
+    return 0;
+}
+
+// archaeo:ignore-next-function
+int g(int x) {
+    if (x) return 1;
+    return 0;
+}
+";
+
+    fn notification<N: LspNotification>(params: N::Params) -> Notification {
+        Notification::new(N::METHOD.to_string(), params)
+    }
+
+    fn diagnostics(published: Option<Notification>) -> Vec<Diagnostic> {
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(published.unwrap().params).unwrap();
+        params.diagnostics
+    }
+
+    #[test]
+    fn open_documents_get_diagnostics_and_lenses() {
+        let command = Cli::parse_from([
+            "archaeo",
+            "--fail-on",
+            "cyclomatic>1",
+            "--lens",
+            "cyclomatic,fn_args,missing",
+        ])
+        .lsp;
+        let uri = Url::parse("file:///work/a.c").unwrap();
+        let mut documents = HashMap::new();
+
+        let published = command
+            .notify(
+                notification::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                    text_document: TextDocumentItem::new(
+                        uri.clone(),
+                        "c".to_string(),
+                        1,
+                        DOCUMENT.to_string(),
+                    ),
+                }),
+                &mut documents,
+            )
+            .unwrap();
+        // g breaks the rule as well, but is suppressed
+        let opened = diagnostics(published);
+        assert_eq!(opened.len(), 1);
+        assert_eq!(
+            opened[0].message,
+            "f has a cyclomatic of 2, breaking cyclomatic>1"
+        );
+        assert_eq!(
+            opened[0].range,
+            Range::new(Position::new(0, 0), Position::new(0, 14))
+        );
+
+        let lens_request = |method: &str| {
+            Request::new(
+                RequestId::from(1),
+                method.to_string(),
+                CodeLensParams {
+                    text_document: TextDocumentIdentifier::new(uri.clone()),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                },
+            )
+        };
+        let response = command.respond(lens_request(CodeLensRequest::METHOD), &documents);
+        let lenses: Vec<CodeLens> = serde_json::from_value(response.result.unwrap()).unwrap();
+        let titles: Vec<(u32, String)> = lenses
+            .into_iter()
+            .map(|lens| (lens.range.start.line, lens.command.unwrap().title))
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                (0, "cyclomatic 2 · fn_args 1".to_string()),
+                (6, "cyclomatic 2 · fn_args 1".to_string()),
+            ]
+        );
+        let response = command.respond(lens_request("textDocument/hover"), &documents);
+        assert_eq!(
+            response.error.unwrap().code,
+            ErrorCode::MethodNotFound as i32
+        );
+
+        // Closing the document clears its diagnostics and lenses
+        let published = command
+            .notify(
+                notification::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+                    text_document: TextDocumentIdentifier::new(uri.clone()),
+                }),
+                &mut documents,
+            )
+            .unwrap();
+        assert!(diagnostics(published).is_empty());
+        assert!(documents.is_empty());
+    }
+}
@@ -0,0 +1,35 @@
+use clap::{Args, CommandFactory};
+use color_eyre::Result;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::cli::Cli;
+use crate::errors::CliError;
+
+#[derive(Args)]
+pub struct ManCommand {
+    /// Directory to write a page per command to, `archaeo.1` and `archaeo-<command>.1`.
+    /// Without it, the page of `archaeo` itself is printed
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+}
+
+impl ManCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let command = Cli::command().name("archaeo");
+        let Some(dir) = self.output_path else {
+            let mut stdout = io::stdout().lock();
+            clap_mangen::Man::new(command)
+                .render(&mut stdout)
+                .map_err(CliError::FileCreationError)?;
+            return stdout.flush().map_err(CliError::FileCreationError);
+        };
+
+        fs::create_dir_all(&dir).map_err(CliError::FileCreationError)?;
+        clap_mangen::generate_to(command, &dir).map_err(CliError::FileCreationError)?;
+        info!("Wrote man pages to {}", dir.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::errors::{check_input, CliError};
+use crate::extract::MetricsRow;
+use crate::output::write_csv;
+use crate::runs::{read_rows, run_files};
+use crate::schema::Schema;
+
+#[derive(Args)]
+pub struct MergeCommand {
+    /// Outputs of the runs to merge, each either a single output file or the directory a
+    /// run wrote its CSV, JSON or parquet outputs to
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// File to write the merged rows to, in the format given by its extension: csv, json,
+    /// or parquet when built with the `parquet` feature
+    #[arg(short, long, required = true)]
+    output_path: PathBuf,
+
+    /// Fill the columns some inputs lack with nulls instead of failing
+    #[arg(long, default_value = "false")]
+    union: bool,
+
+    /// Columns identifying a function when rows carry no `function_id` column. Only the
+    /// first row of every function is kept
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "source_file,name,start_line,end_line"
+    )]
+    key: Vec<String>,
+}
+
+/// What kind of values a column holds, where integers and floats are compatible
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Number,
+    Bool,
+    Text,
+}
+
+impl MergeCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        self.check_schema_versions()?;
+
+        let mut columns: Vec<String> = Vec::new();
+        let mut kinds: HashMap<String, Kind> = HashMap::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut rows: Vec<MetricsRow> = Vec::new();
+        let mut duplicates = 0;
+        let mut files = 0;
+
+        for input in &self.inputs {
+            for file in run_files(input) {
+                let file_rows = read_rows(&file)?;
+                let Some(first) = file_rows.first() else {
+                    continue;
+                };
+                files += 1;
+                self.check_columns(&file, first, &mut columns)?;
+
+                for row in file_rows {
+                    check_kinds(&file, &row, &mut kinds)?;
+                    if seen.insert(self.function_id(&row)) {
+                        rows.push(row);
+                    } else {
+                        duplicates += 1;
+                    }
+                }
+            }
+        }
+        if duplicates > 0 {
+            info!("Dropped {} duplicate functions", duplicates);
+        }
+
+        // Every row carries every column, in order, so the CSV header fits all of them
+        let rows: Vec<MetricsRow> = rows
+            .into_iter()
+            .map(|mut row| {
+                columns
+                    .iter()
+                    .map(|column| (column.clone(), row.remove(column).unwrap_or_default()))
+                    .collect()
+            })
+            .collect();
+
+        self.write(&rows)?;
+        info!(
+            "Merged {} rows from {} files into {}",
+            rows.len(),
+            files,
+            self.output_path.display()
+        );
+        Ok(())
+    }
+
+    /// Inputs recording a `schema.json` have to agree on its version
+    fn check_schema_versions(&self) -> Result<(), CliError> {
+        let mut version: Option<(u32, &Path)> = None;
+        for input in &self.inputs {
+            check_input(input)?;
+            let directory = if input.is_dir() {
+                input.as_path()
+            } else {
+                input.parent().unwrap_or(Path::new("."))
+            };
+            let Some(schema) = Schema::read(directory)? else {
+                continue;
+            };
+            match version {
+                Some((expected, first)) if expected != schema.schema_version => {
+                    return Err(CliError::SchemaMismatch(format!(
+                        "{} has schema version {} but {} has {}",
+                        first.display(),
+                        expected,
+                        directory.display(),
+                        schema.schema_version
+                    )))
+                }
+                Some(_) => {}
+                None => version = Some((schema.schema_version, directory)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Inputs have to share their columns, unless merging the union of them
+    fn check_columns(
+        &self,
+        file: &Path,
+        row: &MetricsRow,
+        columns: &mut Vec<String>,
+    ) -> Result<(), CliError> {
+        if columns.is_empty() {
+            columns.extend(row.keys().cloned());
+            return Ok(());
+        }
+
+        let missing: Vec<&str> = columns
+            .iter()
+            .filter(|column| !row.contains_key(*column))
+            .map(String::as_str)
+            .collect();
+        let extra: Vec<&String> = row.keys().filter(|key| !columns.contains(key)).collect();
+        if !self.union && (!missing.is_empty() || !extra.is_empty()) {
+            return Err(CliError::SchemaMismatch(format!(
+                "{} lacks columns [{}] and adds [{}] compared to the first input, \
+                 merge with --union to fill them with nulls",
+                file.display(),
+                missing.join(", "),
+                extra
+                    .iter()
+                    .map(|column| column.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        if !extra.is_empty() {
+            warn!(file = %file.display(), "Adding {} columns", extra.len());
+        }
+        let extra: Vec<String> = extra.into_iter().cloned().collect();
+        columns.extend(extra);
+        Ok(())
+    }
+
+    /// The `function_id` of a row, or its key columns joined otherwise
+    fn function_id(&self, row: &MetricsRow) -> String {
+        if let Some(id) = row.get("function_id").filter(|id| !id.is_null()) {
+            return id.to_string();
+        }
+        self.key
+            .iter()
+            .map(|column| row.get(column).map(Value::to_string).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\u{1f}")
+    }
+
+    fn write(&self, rows: &[MetricsRow]) -> Result<(), CliError> {
+        match self.output_path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => write_csv(File::create(&self.output_path)?, rows),
+            Some("json") => {
+                let mut writer = File::create(&self.output_path)?;
+                serde_json::to_writer_pretty(&mut writer, rows)?;
+                writeln!(writer)?;
+                Ok(())
+            }
+            #[cfg(feature = "parquet")]
+            Some("parquet") => crate::columnar::write_parquet(
+                &self.output_path,
+                &crate::columnar::infer_schema(rows),
+                rows,
+            ),
+            #[cfg(not(feature = "parquet"))]
+            Some("parquet") => Err(CliError::InvalidArgument(
+                "writing parquet needs archaeo built with the parquet feature".to_string(),
+            )),
+            _ => Err(CliError::InvalidArgument(format!(
+                "can't tell the output format of {}, expected a .csv, .json or .parquet file",
+                self.output_path.display()
+            ))),
+        }
+    }
+}
+
+/// Every column has to hold the same kind of values across inputs
+fn check_kinds(
+    file: &Path,
+    row: &MetricsRow,
+    kinds: &mut HashMap<String, Kind>,
+) -> Result<(), CliError> {
+    for (column, value) in row {
+        let kind = match value {
+            Value::Null => continue,
+            Value::Number(_) => Kind::Number,
+            Value::Bool(_) => Kind::Bool,
+            _ => Kind::Text,
+        };
+        match kinds.get(column) {
+            Some(&expected) if expected != kind => {
+                return Err(CliError::SchemaMismatch(format!(
+                    "{} holds {:?} values in {} where earlier inputs hold {:?}",
+                    column,
+                    kind,
+                    file.display(),
+                    expected
+                )))
+            }
+            Some(_) => {}
+            None => {
+                kinds.insert(column.clone(), kind);
+            }
+        }
+    }
+    Ok(())
+}
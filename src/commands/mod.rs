@@ -1 +1,31 @@
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod callgraph;
+pub mod completions;
+pub mod deps;
+pub mod ghidra;
+#[cfg(feature = "parquet")]
+pub mod hf_export;
+pub mod hotspots;
+pub mod init;
+pub mod lsp;
+pub mod man;
+pub mod merge;
+pub mod pair;
+pub mod patch;
+#[cfg(feature = "sqlite")]
+pub mod query;
+#[cfg(feature = "sqlite")]
+pub mod record;
+pub mod risk;
+pub mod scorecard;
+pub mod serve;
 pub mod source;
+pub mod stats;
+pub mod survival;
+pub mod symbols;
+pub mod trend;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod validate;
+pub mod visualize;
@@ -0,0 +1,190 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::errors::CliError;
+use crate::extract::MetricsRow;
+use crate::output::write_csv;
+use crate::runs::read_run;
+
+/// Columns that identify a row rather than measure it and so are never compared
+const IDENTIFYING: [&str; 2] = ["start_line", "end_line"];
+
+#[derive(Args)]
+pub struct PairCommand {
+    /// Output of the first run, e.g. the original source. Either a single output file or
+    /// the directory a `source` run wrote to
+    #[arg(short, long, required = true)]
+    left: PathBuf,
+
+    /// Output of the second run, e.g. the decompiled source, in the same form
+    #[arg(short, long, required = true)]
+    right: PathBuf,
+
+    /// Optional file to save the paired records to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the paired records
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    /// What functions are matched on. `name` pairs by function name alone, `file` by the
+    /// function name and the file name without its directory or extension
+    /// Options: name, file
+    #[arg(long, default_value = "name", value_parser = clap::builder::PossibleValuesParser::new(["name", "file"]))]
+    by: String,
+}
+
+impl PairCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let left = self.index(read_run(&self.left)?);
+        let right = self.index(read_run(&self.right)?);
+
+        let mut keys: Vec<&(String, String)> = left
+            .iter()
+            .filter(|(key, rows)| rows.len() == 1 && right.get(*key).is_some_and(|r| r.len() == 1))
+            .map(|(key, _)| key)
+            .collect();
+        keys.sort();
+
+        let ambiguous = left
+            .values()
+            .chain(right.values())
+            .filter(|rows| rows.len() > 1)
+            .count();
+        if ambiguous > 0 {
+            info!(
+                "Skipped {} functions with more than one match in a run",
+                ambiguous
+            );
+        }
+        info!(
+            "Paired {} of {} left and {} right functions",
+            keys.len(),
+            left.len(),
+            right.len()
+        );
+
+        let records: Vec<MetricsRow> = keys
+            .into_iter()
+            .map(|key| pair(&left[key][0], &right[key][0], ["left", "right"]))
+            .collect();
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &records)?,
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &records)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Groups the named rows of a run by the key they're paired on
+    fn index(&self, rows: Vec<MetricsRow>) -> HashMap<(String, String), Vec<MetricsRow>> {
+        let mut index: HashMap<(String, String), Vec<MetricsRow>> = HashMap::new();
+        for row in rows {
+            let name = match row.get("name").and_then(Value::as_str) {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => continue,
+            };
+            let file = match self.by.as_str() {
+                "file" => row
+                    .get("source_file")
+                    .and_then(Value::as_str)
+                    .and_then(|file| Path::new(file).file_stem())
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                _ => String::new(),
+            };
+            index.entry((name, file)).or_default().push(row);
+        }
+        index
+    }
+}
+
+/// A paired record holding both values of every metric the two rows share, along with
+/// the change from the first to the second. Columns are named after `sides`, e.g.
+/// `left_start_line` and `cyclomatic_sum_left`
+pub fn pair(first: &MetricsRow, second: &MetricsRow, sides: [&str; 2]) -> MetricsRow {
+    let [a, b] = sides;
+    let mut record = MetricsRow::new();
+    record.insert("name".to_string(), first["name"].clone());
+    for side in ["source_file", "start_line", "end_line"] {
+        record.insert(
+            format!("{}_{}", a, side),
+            first.get(side).cloned().unwrap_or_default(),
+        );
+        record.insert(
+            format!("{}_{}", b, side),
+            second.get(side).cloned().unwrap_or_default(),
+        );
+    }
+
+    for (column, value) in first {
+        if IDENTIFYING.contains(&column.as_str()) {
+            continue;
+        }
+        let (Some(x), Some(y)) = (value.as_f64(), second.get(column).and_then(Value::as_f64))
+        else {
+            continue;
+        };
+        record.insert(format!("{}_{}", column, a), value.clone());
+        record.insert(format!("{}_{}", column, b), second[column].clone());
+        record.insert(format!("{}_delta", column), json!(y - x));
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(value: Value) -> MetricsRow {
+        match value {
+            Value::Object(row) => row,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn paired_records_hold_both_sides_and_the_change() {
+        let first = row(json!({
+            "name": "f", "source_file": "a.c", "start_line": 1, "end_line": 4,
+            "kind": "function", "cyclomatic": 2.0, "sloc": 4.0, "renamed": 1.0,
+        }));
+        let second = row(json!({
+            "name": "f", "source_file": "a.dec.c", "start_line": 10, "end_line": 19,
+            "kind": "function", "cyclomatic": 5.0, "sloc": 10.0, "other": 3.0,
+        }));
+        let record = pair(&first, &second, ["left", "right"]);
+        // Only the metrics both rows have are compared
+        assert_eq!(
+            Value::Object(record),
+            json!({
+                "name": "f",
+                "left_source_file": "a.c", "right_source_file": "a.dec.c",
+                "left_start_line": 1, "right_start_line": 10,
+                "left_end_line": 4, "right_end_line": 19,
+                "cyclomatic_left": 2.0, "cyclomatic_right": 5.0, "cyclomatic_delta": 3.0,
+                "sloc_left": 4.0, "sloc_right": 10.0, "sloc_delta": 6.0,
+            })
+        );
+    }
+}
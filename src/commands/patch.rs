@@ -0,0 +1,211 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::errors::{check_input, CliError};
+use crate::extract::{extract_file, ExtractOptions, MetricsRow};
+use crate::git::{self, FileDiff};
+use crate::impact::{assess, HunkImpact};
+use crate::language::LanguageArgs;
+use crate::output::write_csv;
+
+#[derive(Args)]
+pub struct PatchCommand {
+    /// Unified diff to assess, e.g. the output of `git diff` or `diff -u`
+    #[arg(long, required = true)]
+    diff: PathBuf,
+
+    /// Directory the diff applies to. The files it changes are read from here before the
+    /// change, and patched in a temporary copy for after it
+    #[arg(long, default_value = ".")]
+    repo: PathBuf,
+
+    /// Metric columns to compare before and after the change
+    #[arg(long, value_delimiter = ',', default_value = "cyclomatic,cognitive")]
+    metrics: Vec<String>,
+
+    /// Optional file to save the impact to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the impact. `json` lists the functions of every hunk, `csv` writes
+    /// a row for every function the diff touches
+    /// Options: json, csv
+    #[arg(short, long, default_value = "json", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+}
+
+impl PatchCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        check_input(&self.diff)?;
+        check_input(&self.repo)?;
+        let text = fs::read_to_string(&self.diff)?;
+        let files = git::parse_diff(&text);
+        if files.is_empty() {
+            return Err(CliError::InvalidArgument(format!(
+                "{} holds no file headers",
+                self.diff.display()
+            )));
+        }
+
+        let options = ExtractOptions {
+            force_language: self.language.force_language.clone(),
+            map_ext: self.language.map_ext.clone(),
+            preprocess: self.language.preprocess.clone(),
+            define: self.language.define.clone(),
+            ..ExtractOptions::default()
+        };
+        let mut hunks = assess_patch(&self.repo, &text, &files, &self.metrics, &options)?;
+        let assessed = hunks.len();
+        hunks.retain(|hunk| !hunk.functions.is_empty());
+        info!(
+            "{} of {} hunks of {} files touch functions",
+            hunks.len(),
+            assessed,
+            files.len()
+        );
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &self.records(&hunks))?,
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &json!({ "hunks": hunks }))?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+        Ok(())
+    }
+
+    /// A row for every function the hunks touch, once however many hunks touch it
+    fn records(&self, hunks: &[HunkImpact]) -> Vec<MetricsRow> {
+        let mut seen = BTreeSet::new();
+        let mut records = Vec::new();
+        for hunk in hunks {
+            let path = hunk.new_path.as_ref().or(hunk.old_path.as_ref());
+            for function in &hunk.functions {
+                let key = (
+                    path,
+                    &function.name,
+                    function.base_lines,
+                    function.head_lines,
+                );
+                if !seen.insert(key) {
+                    continue;
+                }
+                let mut record = MetricsRow::new();
+                record.insert("source_file".to_string(), json!(path));
+                record.insert("name".to_string(), json!(function.name));
+                record.insert("status".to_string(), json!(function.status));
+                let (base_start, base_end) = function.base_lines.unzip();
+                let (head_start, head_end) = function.head_lines.unzip();
+                record.insert("base_start_line".to_string(), json!(base_start));
+                record.insert("base_end_line".to_string(), json!(base_end));
+                record.insert("head_start_line".to_string(), json!(head_start));
+                record.insert("head_end_line".to_string(), json!(head_end));
+                for metric in &self.metrics {
+                    let change = function.metrics.get(metric);
+                    record.insert(
+                        format!("{}_base", metric),
+                        json!(change.and_then(|change| change.base)),
+                    );
+                    record.insert(
+                        format!("{}_head", metric),
+                        json!(change.and_then(|change| change.head)),
+                    );
+                    record.insert(
+                        format!("{}_delta", metric),
+                        json!(change.and_then(|change| change.delta)),
+                    );
+                }
+                records.push(record);
+            }
+        }
+        records
+    }
+}
+
+/// Assesses the hunks of `diff`, parsed into `files`, against `repo` before the change and
+/// a patched copy of the files it changes after it
+pub fn assess_patch(
+    repo: &Path,
+    diff: &str,
+    files: &[FileDiff],
+    metrics: &[String],
+    options: &ExtractOptions,
+) -> Result<Vec<HunkImpact>, CliError> {
+    git::confine(files)?;
+    let root = repo.canonicalize()?;
+    // The files the diff changes are copied out of the repository and patched there
+    let patched = tempfile::Builder::new().prefix("archaeo-").tempdir()?;
+    let patched_root = patched.path().canonicalize()?;
+    for old_path in files.iter().filter_map(|file| file.old_path.as_ref()) {
+        let from = resolve(&root, old_path)?;
+        if from.is_file() {
+            let to = patched_root.join(old_path);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&from, &to)?;
+        }
+    }
+    git::apply(&patched_root, diff)?;
+
+    let mut hunks = Vec::new();
+    for file in files {
+        let base = match &file.old_path {
+            Some(old_path) => rows_of(&resolve(&root, old_path)?, options),
+            None => Vec::new(),
+        };
+        // The patch may have added a symlink of its own
+        let head = match &file.new_path {
+            Some(new_path) => rows_of(&resolve(&patched_root, new_path)?, options),
+            None => Vec::new(),
+        };
+        hunks.extend(assess(
+            file,
+            &base.iter().collect::<Vec<_>>(),
+            &head,
+            metrics,
+        ));
+    }
+    Ok(hunks)
+}
+
+/// `path` within the canonical `root`, refusing one whose symlinks lead out of it. Missing
+/// paths are left as they are, having nothing to read
+fn resolve(root: &Path, path: &str) -> Result<PathBuf, CliError> {
+    let joined = root.join(path);
+    match joined.canonicalize() {
+        Ok(resolved) if resolved.starts_with(root) => Ok(resolved),
+        Ok(_) => Err(CliError::InvalidArgument(format!(
+            "{} is outside of the repository",
+            path
+        ))),
+        Err(_) => Ok(joined),
+    }
+}
+
+/// The rows of `path`, none when it is missing or can't be extracted
+fn rows_of(path: &Path, options: &ExtractOptions) -> Vec<MetricsRow> {
+    if !path.is_file() {
+        return Vec::new();
+    }
+    extract_file(path, options).unwrap_or_else(|err| {
+        warn!(file = %path.display(), "Failed to extract: {}", err);
+        Vec::new()
+    })
+}
@@ -0,0 +1,112 @@
+use clap::Args;
+use color_eyre::Result;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::errors::CliError;
+use crate::history::{rates, Aggregate, Grouping, History, Selection};
+use crate::output::write_csv;
+
+#[derive(Args)]
+pub struct QueryCommand {
+    /// SQLite database `record` appended the runs to
+    #[arg(long, required = true)]
+    db: PathBuf,
+
+    /// Metric columns to follow across the runs
+    #[arg(long, value_delimiter = ',', default_value = "cyclomatic")]
+    metric: Vec<String>,
+
+    /// Only follow functions with this name
+    #[arg(long)]
+    function: Option<String>,
+
+    /// Only follow functions in this file, or below this directory, as recorded
+    #[arg(long)]
+    file: Option<String>,
+
+    /// Only these runs, in the order they were recorded. Every run when not provided
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// One row per function, per file or per run
+    /// Options: function, file, run
+    #[arg(long, default_value = "function", value_parser = clap::builder::PossibleValuesParser::new(["function", "file", "run"]))]
+    by: String,
+
+    /// How the metrics of the functions of a file or run are combined
+    /// Options: sum, mean, max
+    #[arg(long, default_value = "sum", value_parser = clap::builder::PossibleValuesParser::new(["sum", "mean", "max"]))]
+    aggregate: String,
+
+    /// One row per function, file or run with how fast every metric changes across the
+    /// runs instead: its latest value, `_velocity` and `_acceleration`, fastest growing
+    /// first
+    #[arg(long, default_value = "false")]
+    rates: bool,
+
+    /// Number of the latest runs the rates are taken over
+    #[arg(long, default_value = "3", requires = "rates", value_parser = clap::value_parser!(u64).range(1..))]
+    window: u64,
+
+    /// Number of the fastest growing functions, files or runs flagged `growing_fastest`
+    #[arg(long, default_value = "10", requires = "rates")]
+    fastest: usize,
+
+    /// Optional file to save the trend to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the trend
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+}
+
+impl QueryCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if !self.db.is_file() {
+            return Err(CliError::InvalidInputPath(format!(
+                "{} is not a database `archaeo record` wrote",
+                self.db.display()
+            )));
+        }
+        let history = History::open(&self.db)?;
+        let selection = Selection {
+            metrics: &self.metric,
+            function: self.function.as_deref(),
+            file: self.file.as_deref(),
+            tags: &self.tags,
+            grouping: match self.by.as_str() {
+                "file" => Grouping::File,
+                "run" => Grouping::Run,
+                _ => Grouping::Function,
+            },
+            aggregate: match self.aggregate.as_str() {
+                "mean" => Aggregate::Mean,
+                "max" => Aggregate::Max,
+                _ => Aggregate::Sum,
+            },
+        };
+        let mut rows = history.query(&selection)?;
+        if self.rates {
+            rows = rates(&rows, &selection, self.window as usize, self.fastest);
+        }
+        info!("Found {} rows", rows.len());
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &rows)?,
+            _ => {
+                serde_json::to_writer_pretty(&mut writer, &rows)?;
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
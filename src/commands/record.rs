@@ -0,0 +1,96 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::extract::{extract_path, ExtractOptions, MetricsRow};
+use crate::git;
+use crate::history::{History, RunInfo};
+use crate::language::LanguageArgs;
+use crate::runs::read_run;
+
+#[derive(Args)]
+pub struct RecordCommand {
+    /// SQLite database to append the run to, created if missing
+    #[arg(long, required = true)]
+    db: PathBuf,
+
+    /// Name of the run, e.g. the release it measures
+    #[arg(long, required = true)]
+    tag: String,
+
+    /// Path to the file or directory to extract and record. Functions are stored by their
+    /// path below it, so the same file matches across runs
+    #[arg(short, long, required_unless_present = "run", conflicts_with = "run")]
+    path: Option<PathBuf>,
+
+    /// Record the rows of an earlier `source` run instead of extracting them
+    #[arg(long)]
+    run: Option<PathBuf>,
+
+    /// Overwrite a run already recorded under the tag
+    #[arg(long, default_value = "false")]
+    replace: bool,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+impl RecordCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let (source, rows) = match (&self.path, &self.run) {
+            (Some(path), _) => (path, self.extract(path)?),
+            (None, Some(run)) => (run, read_run(run)?),
+            (None, None) => unreachable!("clap requires --path or --run"),
+        };
+        let revision = git::resolve(&directory(source), "HEAD").ok();
+        let run = RunInfo {
+            tag: self.tag.clone(),
+            recorded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            revision,
+            path: source.display().to_string(),
+        };
+
+        let mut history = History::open(&self.db)?;
+        let functions = history.record(&run, &rows, self.replace)?;
+        info!(
+            "Recorded {} functions as {} in {}",
+            functions,
+            self.tag,
+            self.db.display()
+        );
+        Ok(())
+    }
+
+    /// Extracts the rows below `path`, with their source files relative to it
+    fn extract(&self, path: &Path) -> Result<Vec<MetricsRow>, CliError> {
+        let options = ExtractOptions::new(&self.discovery, &self.language);
+        let mut rows = extract_path(path, &options)?;
+        let root = directory(path);
+        for row in &mut rows {
+            if let Some(file) = row.get("source_file").and_then(Value::as_str) {
+                let file = git::relative(Path::new(file), &root);
+                row.insert("source_file".to_string(), json!(file));
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// `path` when it is a directory, otherwise the directory it is in
+fn directory(path: &Path) -> PathBuf {
+    match path.parent() {
+        _ if path.is_dir() => path.to_path_buf(),
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
@@ -0,0 +1,311 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::discovery::DiscoveryArgs;
+use crate::errors::{check_input, CliError};
+use crate::extract::{extract_dir, ExtractOptions, MetricsRow};
+use crate::git::{self, days, Blame};
+use crate::language::LanguageArgs;
+use crate::output::write_csv;
+
+#[derive(Args)]
+pub struct RiskCommand {
+    /// Path to the git repository to report on. Its working tree is analysed, its lines
+    /// attributed as committed at `HEAD`
+    #[arg(long, default_value = ".")]
+    repo: PathBuf,
+
+    /// Metric column a function needs to reach --threshold on to be reported
+    #[arg(long, default_value = "cyclomatic")]
+    metric: String,
+
+    /// Complexity from which a function is reported
+    #[arg(long, default_value = "15")]
+    threshold: f64,
+
+    /// Share from 0 to 1 of the lines of a function its top author needs to have written
+    /// for it to count as known to a single author
+    #[arg(long, default_value = "0.8")]
+    share: f64,
+
+    /// Days without a commit after which an author counts as gone, counted back from the
+    /// latest commit of `HEAD`
+    #[arg(long, default_value = "180")]
+    stale_days: f64,
+
+    /// CODEOWNERS file to resolve the owners of every file from, reported in an `owner`
+    /// column. Patterns are relative to the repository it belongs to
+    #[arg(long)]
+    codeowners: Option<PathBuf>,
+
+    /// Optional file to save the reported functions to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the reported functions
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    /// Also write a markdown report of the functions at risk, e.g. for an issue or a wiki
+    #[arg(long)]
+    markdown: Option<PathBuf>,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+/// Who wrote the lines of a function
+#[derive(Debug, Default)]
+struct Authorship {
+    authors: usize,
+    /// Email and name of the author of the most lines
+    top: Option<(String, String)>,
+    share: f64,
+    last_changed: Option<i64>,
+}
+
+impl RiskCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if !(0.0..=1.0).contains(&self.share) {
+            return Err(CliError::InvalidArgument(
+                "--share must be between 0 and 1".to_string(),
+            ));
+        }
+        check_input(&self.repo)?;
+        let root = git::toplevel(&self.repo)?;
+        let now = git::history(&root, "HEAD", Some(1))?
+            .first()
+            .map_or(0, |commit| commit.time);
+        let last_commits = git::last_commits(&root)?;
+
+        let options = ExtractOptions {
+            codeowners: self.codeowners.clone(),
+            ..ExtractOptions::new(&self.discovery, &self.language)
+        };
+        let rows = extract_dir(&root, &options)?;
+        if rows
+            .first()
+            .is_some_and(|row| !row.get(&self.metric).is_some_and(Value::is_number))
+        {
+            return Err(CliError::InvalidArgument(format!(
+                "{} is not a numeric metric column",
+                self.metric
+            )));
+        }
+
+        let complex: Vec<(String, &MetricsRow)> = rows
+            .iter()
+            .filter(|row| {
+                row.get(&self.metric)
+                    .and_then(Value::as_f64)
+                    .is_some_and(|value| value >= self.threshold)
+            })
+            .map(|row| {
+                let file = row
+                    .get("source_file")
+                    .and_then(Value::as_str)
+                    .map(|file| git::relative(Path::new(file), &root))
+                    .unwrap_or_default();
+                (file, row)
+            })
+            .collect();
+        // Only the files of the complex functions are blamed
+        let files: BTreeSet<&str> = complex.iter().map(|(file, _)| file.as_str()).collect();
+        let blames: HashMap<&str, Vec<Blame>> = files
+            .par_iter()
+            .filter_map(|&file| match git::blame(&root, file) {
+                Ok(blame) => Some((file, blame)),
+                Err(err) => {
+                    warn!(file = %file, "Failed to blame: {}", err);
+                    None
+                }
+            })
+            .collect();
+
+        let mut records: Vec<MetricsRow> = complex
+            .iter()
+            .map(|(file, row)| {
+                let line = |column: &str| row.get(column).and_then(Value::as_u64).unwrap_or(0);
+                let lines = blames.get(file.as_str()).map_or(&[][..], |blame| {
+                    let start = (line("start_line") as usize)
+                        .saturating_sub(1)
+                        .min(blame.len());
+                    let end = (line("end_line") as usize).clamp(start, blame.len());
+                    &blame[start..end]
+                });
+                self.record(file, row, &authorship(lines), &last_commits, now)
+            })
+            .collect();
+        records.sort_by(|a, b| {
+            let value = |record: &MetricsRow, column: &str| {
+                record.get(column).and_then(Value::as_f64).unwrap_or(0.0)
+            };
+            (b["at_risk"] == true)
+                .cmp(&(a["at_risk"] == true))
+                .then_with(|| value(b, &self.metric).total_cmp(&value(a, &self.metric)))
+        });
+        let at_risk: Vec<&MetricsRow> = records
+            .iter()
+            .filter(|record| record["at_risk"] == true)
+            .collect();
+        info!(
+            "{} of {} functions over {} {} are known to a single author gone for {} days",
+            at_risk.len(),
+            records.len(),
+            self.metric,
+            self.threshold,
+            self.stale_days
+        );
+
+        if let Some(path) = &self.markdown {
+            let mut writer = File::create(path)?;
+            self.write_markdown(&mut writer, &at_risk, records.len())?;
+        }
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &records)?,
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &records)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+        Ok(())
+    }
+
+    /// The risk row of a complex function
+    fn record(
+        &self,
+        file: &str,
+        row: &MetricsRow,
+        authorship: &Authorship,
+        last_commits: &HashMap<String, i64>,
+        now: i64,
+    ) -> MetricsRow {
+        let (top_email, top_name) = authorship.top.clone().unzip();
+        let top_last_commit = top_email
+            .as_ref()
+            .and_then(|email| last_commits.get(email).copied());
+        let gone_for = top_last_commit.map(|time| days(now - time));
+        let at_risk = authorship.share >= self.share
+            && gone_for.is_some_and(|gone_for| gone_for >= self.stale_days);
+
+        let mut record = MetricsRow::new();
+        record.insert("source_file".to_string(), json!(file));
+        for column in ["name", "start_line", "end_line"] {
+            record.insert(
+                column.to_string(),
+                row.get(column).cloned().unwrap_or_default(),
+            );
+        }
+        record.insert(
+            self.metric.clone(),
+            row.get(&self.metric).cloned().unwrap_or_default(),
+        );
+        if self.codeowners.is_some() {
+            record.insert(
+                "owner".to_string(),
+                row.get("owner").cloned().unwrap_or_default(),
+            );
+        }
+        record.insert("authors".to_string(), json!(authorship.authors));
+        record.insert("top_author".to_string(), json!(top_name));
+        record.insert("top_author_email".to_string(), json!(top_email));
+        record.insert("top_author_share".to_string(), json!(authorship.share));
+        record.insert(
+            "top_author_last_commit_at".to_string(),
+            json!(top_last_commit),
+        );
+        record.insert("days_since_top_author_commit".to_string(), json!(gone_for));
+        record.insert(
+            "last_changed_at".to_string(),
+            json!(authorship.last_changed),
+        );
+        record.insert("at_risk".to_string(), json!(at_risk));
+        record
+    }
+
+    fn write_markdown(
+        &self,
+        writer: &mut dyn Write,
+        at_risk: &[&MetricsRow],
+        reported: usize,
+    ) -> Result<(), CliError> {
+        writeln!(writer, "# Knowledge risk\n")?;
+        writeln!(
+            writer,
+            "{} of the {} functions with a {} of at least {} were mostly written by a single \
+             author, who hasn't committed for {} days or more.\n",
+            at_risk.len(),
+            reported,
+            self.metric,
+            self.threshold,
+            self.stale_days
+        )?;
+        if at_risk.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            writer,
+            "| Function | File | {} | Top author | Share | Days since their last commit |",
+            self.metric
+        )?;
+        writeln!(writer, "|---|---|---:|---|---:|---:|")?;
+        for record in at_risk {
+            let text = |column: &str| match &record[column] {
+                Value::String(text) => text.replace('|', "\\|"),
+                Value::Null => String::new(),
+                value => value.to_string(),
+            };
+            writeln!(
+                writer,
+                "| `{}` | `{}:{}` | {} | {} | {:.0}% | {} |",
+                text("name"),
+                text("source_file"),
+                text("start_line"),
+                text(&self.metric),
+                text("top_author"),
+                record["top_author_share"].as_f64().unwrap_or(0.0) * 100.0,
+                text("days_since_top_author_commit"),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The authors of `lines` and the share of the one who wrote the most of them, the most
+/// recent one of those tied
+fn authorship(lines: &[Blame]) -> Authorship {
+    let mut authors: HashMap<&str, (usize, i64, &str)> = HashMap::new();
+    for line in lines {
+        let author = authors.entry(&line.email).or_insert((0, 0, &line.name));
+        author.0 += 1;
+        author.1 = author.1.max(line.time);
+    }
+    let top = authors
+        .iter()
+        .max_by(|a, b| (a.1 .0, a.1 .1, b.0).cmp(&(b.1 .0, b.1 .1, a.0)));
+    Authorship {
+        authors: authors.len(),
+        top: top.map(|(email, (_, _, name))| (email.to_string(), name.to_string())),
+        share: top.map_or(0.0, |(_, (count, _, _))| *count as f64 / lines.len() as f64),
+        last_changed: lines.iter().map(|line| line.time).max(),
+    }
+}
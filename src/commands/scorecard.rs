@@ -0,0 +1,177 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::commands::init::percentile;
+use crate::errors::CliError;
+use crate::extract::MetricsRow;
+use crate::output::write_csv;
+use crate::runs::read_run;
+
+#[derive(Args)]
+pub struct ScorecardCommand {
+    /// Output file of a `source` run, or the directory it wrote to. Rows are grouped by
+    /// their `project` column, all into one project when there is none
+    run: PathBuf,
+
+    /// Optional file to save the scorecard to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the scorecard
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    /// Cyclomatic complexity over which a function counts as complex
+    #[arg(long, default_value = "15")]
+    threshold: f64,
+
+    /// Number of the most complex functions of every project to list
+    #[arg(long, default_value = "5")]
+    worst: usize,
+}
+
+/// The functions of a project, as read back from its rows
+#[derive(Debug, Default)]
+struct Project {
+    files: BTreeSet<String>,
+    sloc: f64,
+    cyclomatic: Vec<f64>,
+    cognitive: Vec<f64>,
+    /// Maintainability index of every function weighted by its lines of code
+    weighted_mi: f64,
+    mi_sloc: f64,
+    /// The cyclomatic complexity, file, start line and name of every function
+    functions: Vec<(f64, String, u64, String)>,
+}
+
+/// Lowest maintainability index, on the 0 to 100 scale of `mi_visual_studio`, earning
+/// every grade
+const GRADES: [(f64, &str); 4] = [(60.0, "A"), (50.0, "B"), (40.0, "C"), (20.0, "D")];
+
+impl ScorecardCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let rows = read_run(&self.run)?;
+        let mut projects: BTreeMap<Option<String>, Project> = BTreeMap::new();
+        for row in &rows {
+            let name = row
+                .get("project")
+                .and_then(Value::as_str)
+                .filter(|project| !project.is_empty())
+                .map(str::to_string);
+            let project = projects.entry(name).or_default();
+            let number = |column: &str| {
+                row.get(column)
+                    .and_then(Value::as_f64)
+                    .filter(|value| value.is_finite())
+            };
+            let text = |column: &str| {
+                row.get(column)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+
+            let sloc = number("loc_sloc").unwrap_or(0.0);
+            project.files.insert(text("source_file"));
+            project.sloc += sloc;
+            if let Some(mi) = number("mi_visual_studio") {
+                project.weighted_mi += mi * sloc;
+                project.mi_sloc += sloc;
+            }
+            project.cognitive.extend(number("cognitive"));
+            if let Some(cyclomatic) = number("cyclomatic") {
+                project.cyclomatic.push(cyclomatic);
+                project.functions.push((
+                    cyclomatic,
+                    text("source_file"),
+                    row.get("start_line").and_then(Value::as_u64).unwrap_or(0),
+                    text("name"),
+                ));
+            }
+        }
+
+        let records: Vec<MetricsRow> = projects
+            .into_iter()
+            .map(|(name, project)| self.record(name, project))
+            .collect();
+        info!("Scored {} projects from {} rows", records.len(), rows.len());
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &records)?,
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &records)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+        Ok(())
+    }
+
+    /// The scorecard row of a project
+    fn record(&self, name: Option<String>, mut project: Project) -> MetricsRow {
+        let maintainability = if project.mi_sloc > 0.0 {
+            Some(project.weighted_mi / project.mi_sloc)
+        } else {
+            None
+        };
+        let grade = maintainability.map(|mi| {
+            GRADES
+                .iter()
+                .find(|(lowest, _)| mi >= *lowest)
+                .map_or("F", |(_, grade)| grade)
+        });
+        let complex = project
+            .cyclomatic
+            .iter()
+            .filter(|&&cyclomatic| cyclomatic > self.threshold)
+            .count();
+
+        project.functions.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then_with(|| (&a.1, a.2).cmp(&(&b.1, b.2)))
+        });
+        let worst: Vec<String> = project
+            .functions
+            .iter()
+            .take(self.worst)
+            .map(|(cyclomatic, file, line, name)| {
+                format!("{}:{} {} ({})", file, line, name, cyclomatic)
+            })
+            .collect();
+
+        let mut record = MetricsRow::new();
+        record.insert("project".to_string(), json!(name));
+        record.insert("files".to_string(), json!(project.files.len()));
+        record.insert("functions".to_string(), json!(project.functions.len()));
+        record.insert("loc_sloc".to_string(), json!(project.sloc));
+        for (metric, values) in [
+            ("cyclomatic", &mut project.cyclomatic),
+            ("cognitive", &mut project.cognitive),
+        ] {
+            record.insert(
+                format!("{}_median", metric),
+                json!(percentile(values, 50.0)),
+            );
+            record.insert(format!("{}_p90", metric), json!(percentile(values, 90.0)));
+            record.insert(format!("{}_max", metric), json!(percentile(values, 100.0)));
+        }
+        record.insert("complex_functions".to_string(), json!(complex));
+        record.insert("maintainability".to_string(), json!(maintainability));
+        record.insert("grade".to_string(), json!(grade));
+        record.insert("worst_offenders".to_string(), json!(worst.join("; ")));
+        record
+    }
+}
@@ -0,0 +1,582 @@
+use clap::Args;
+use color_eyre::Result;
+use crossbeam_channel::Sender;
+use rayon::prelude::*;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write as _;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{info, warn};
+
+use crate::commands::patch::assess_patch;
+use crate::commands::stats::StatsCommand;
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::extract::{extract_file, extract_path, ExtractOptions, MetricsRow};
+use crate::git;
+use crate::impact::{assess, same_file, ImpactRequest, DEFAULT_METRICS};
+use crate::language::LanguageArgs;
+use crate::webhook::{self, MergeRequest};
+
+#[derive(Args)]
+pub struct ServeCommand {
+    /// Path to the source code to watch
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// Address to serve the Prometheus `/metrics` endpoint on
+    #[arg(long, default_value = "127.0.0.1:9464")]
+    listen: String,
+
+    /// Seconds between re-extracting the metrics of the watched path
+    #[arg(long, default_value = "60")]
+    interval: u64,
+
+    /// Limits to count the functions above of, as `metric=limit`
+    #[arg(long, value_parser = parse_threshold, default_value = "cyclomatic=10")]
+    threshold: Vec<Threshold>,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+/// A limit on a metric column
+#[derive(Debug, Clone)]
+pub struct Threshold {
+    pub metric: String,
+    pub limit: f64,
+}
+
+fn parse_threshold(value: &str) -> Result<Threshold, String> {
+    let (metric, limit) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected metric=limit, got {}", value))?;
+    let limit = limit
+        .parse()
+        .map_err(|_| format!("invalid limit {} for {}", limit, metric))?;
+    Ok(Threshold {
+        metric: metric.to_string(),
+        limit,
+    })
+}
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Bytes of a request body past which it is turned away, so nobody reaching the server
+/// can exhaust its memory
+const BODY_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// Merge requests waiting to be reviewed after which events are turned away
+const REVIEW_QUEUE: usize = 16;
+
+/// Workers handling posts to `/impact` and `/webhook`
+const WORKERS: usize = 4;
+
+/// Posts waiting for a worker after which more are turned away
+const REQUEST_QUEUE: usize = 64;
+
+impl ServeCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if self.interval == 0 {
+            return Err(CliError::InvalidArgument(
+                "--interval must be at least 1".to_string(),
+            ));
+        }
+        let server = Server::http(&self.listen).map_err(|err| {
+            CliError::InvalidArgument(format!("failed to listen on {}: {}", self.listen, err))
+        })?;
+
+        let exposition = Arc::new(RwLock::new(self.refresh()?));
+        info!(
+            "Serving metrics of {} on http://{}/metrics",
+            self.path.display(),
+            self.listen
+        );
+
+        let command = Arc::new(self);
+        // Merge requests are reviewed one at a time in the background, up to a bounded
+        // number of them waiting, as forges don't wait long on webhooks
+        let (reviews, queue) = crossbeam_channel::bounded::<MergeRequest>(REVIEW_QUEUE);
+        let reviewer = command.clone();
+        thread::spawn(move || {
+            for request in queue {
+                if let Err(err) = reviewer.review(&request) {
+                    warn!(
+                        "Failed to review {}#{}: {}",
+                        request.name, request.number, err
+                    );
+                }
+            }
+        });
+        let interval = Duration::from_secs(command.interval);
+        let latest = exposition.clone();
+        let refresher = command.clone();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match refresher.refresh() {
+                Ok(text) => *latest.write().unwrap_or_else(|err| err.into_inner()) = text,
+                Err(err) => warn!(
+                    "Failed to refresh metrics, serving the previous ones: {}",
+                    err
+                ),
+            }
+        });
+
+        // Posts read a body and may assess a diff, so they are handled by workers, keeping
+        // the accept loop free to answer scrapes of `/metrics` right away
+        let (posts, pending) = crossbeam_channel::bounded::<Request>(REQUEST_QUEUE);
+        for _ in 0..WORKERS {
+            let (handler, pending, reviews) = (command.clone(), pending.clone(), reviews.clone());
+            thread::spawn(move || {
+                for mut request in pending {
+                    let response = handler.post(&mut request, &reviews);
+                    respond(request, response);
+                }
+            });
+        }
+
+        for request in server.incoming_requests() {
+            let method = request.method().clone();
+            let response = match (&method, route(request.url())) {
+                (Method::Get, "/metrics") => {
+                    let text = exposition
+                        .read()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .clone();
+                    Response::from_string(text).with_header(
+                        Header::from_bytes("Content-Type", CONTENT_TYPE)
+                            .expect("the content type is a valid header"),
+                    )
+                }
+                (Method::Post, "/impact" | "/webhook") => {
+                    if let Err(err) = posts.try_send(request) {
+                        let busy = Response::from_string("too many requests in flight\n")
+                            .with_status_code(503);
+                        respond(err.into_inner(), busy);
+                    }
+                    continue;
+                }
+                (Method::Get, "/") => Response::from_string(concat!(
+                    "archaeo: metrics under /metrics, POST a diff to /impact ",
+                    "and merge request events to /webhook\n"
+                )),
+                _ => Response::from_string("not found\n").with_status_code(404),
+            };
+            respond(request, response);
+        }
+        Ok(())
+    }
+
+    /// Handles a post to `/impact` or `/webhook` on a worker
+    fn post(
+        &self,
+        request: &mut Request,
+        reviews: &Sender<MergeRequest>,
+    ) -> Response<Cursor<Vec<u8>>> {
+        if route(request.url()) == "/webhook" {
+            let headers: Vec<(String, String)> = request
+                .headers()
+                .iter()
+                .map(|header| (header.field.to_string(), header.value.to_string()))
+                .collect();
+            return match read_body(request) {
+                Ok(body) => self.webhook(&headers, &body, reviews),
+                Err(response) => response,
+            };
+        }
+        match read_body(request) {
+            Ok(body) => match self.impact(&body) {
+                Ok(json) => Response::from_string(json).with_header(
+                    Header::from_bytes("Content-Type", "application/json")
+                        .expect("the content type is a valid header"),
+                ),
+                Err(err @ CliError::InvalidArgument(_)) => {
+                    Response::from_string(format!("{}\n", err)).with_status_code(400)
+                }
+                Err(err) => {
+                    warn!("Failed to assess a diff: {}", err);
+                    Response::from_string(format!("{}\n", err)).with_status_code(500)
+                }
+            },
+            Err(response) => response,
+        }
+    }
+
+    fn options(&self) -> ExtractOptions {
+        ExtractOptions::new(&self.discovery, &self.language)
+    }
+
+    /// Assesses the hunks of the diff in an `ImpactRequest` body against the watched path,
+    /// whose changed files are extracted again so the impact is never stale
+    fn impact(&self, body: &str) -> Result<String, CliError> {
+        let request: ImpactRequest = serde_json::from_str(body)
+            .map_err(|err| CliError::InvalidArgument(format!("invalid request: {}", err)))?;
+        let metrics: Vec<String> = if request.metrics.is_empty() {
+            DEFAULT_METRICS.iter().map(|m| m.to_string()).collect()
+        } else {
+            request.metrics
+        };
+        let files = git::parse_diff(&request.diff);
+        if files.is_empty() && !request.diff.trim().is_empty() {
+            return Err(CliError::InvalidArgument(
+                "the diff holds no file headers".to_string(),
+            ));
+        }
+        git::confine(&files)?;
+
+        let options = self.options();
+        let mut hunks = Vec::new();
+        for file in &files {
+            let base: Vec<&MetricsRow> = match &file.old_path {
+                Some(old_path) => request
+                    .base
+                    .iter()
+                    .filter(|row| {
+                        row.get("source_file")
+                            .and_then(|value| value.as_str())
+                            .is_some_and(|source_file| same_file(source_file, old_path))
+                    })
+                    .collect(),
+                None => Vec::new(),
+            };
+            let head = match &file.new_path {
+                Some(new_path) => {
+                    let path = if self.path.is_dir() {
+                        self.path.join(new_path)
+                    } else {
+                        self.path.clone()
+                    };
+                    if path.is_file() {
+                        extract_file(&path, &options).unwrap_or_else(|err| {
+                            warn!(file = %path.display(), "Failed to extract: {}", err);
+                            Vec::new()
+                        })
+                    } else {
+                        Vec::new()
+                    }
+                }
+                None => Vec::new(),
+            };
+            hunks.extend(assess(file, &base, &head, &metrics));
+        }
+        info!("Assessed {} hunks of {} files", hunks.len(), files.len());
+        Ok(serde_json::to_string(&json!({ "hunks": hunks }))?)
+    }
+
+    /// Acknowledges a merge request event, whose diff is then fetched, assessed against the
+    /// watched path and commented on by the worker taking the `reviews`
+    fn webhook(
+        &self,
+        headers: &[(String, String)],
+        body: &str,
+        reviews: &Sender<MergeRequest>,
+    ) -> Response<Cursor<Vec<u8>>> {
+        let Ok(secret) = env::var(webhook::SECRET_VAR) else {
+            return Response::from_string(format!(
+                "set {} to accept merge request events\n",
+                webhook::SECRET_VAR
+            ))
+            .with_status_code(403);
+        };
+        if !webhook::verify(headers, body, &secret) {
+            return Response::from_string("invalid signature\n").with_status_code(401);
+        }
+        let event = webhook::supported().and_then(|_| webhook::parse_event(headers, body));
+        let request = match event {
+            Ok(Some(request)) => request,
+            Ok(None) => return Response::from_string("ignored\n"),
+            Err(err) => return Response::from_string(format!("{}\n", err)).with_status_code(400),
+        };
+        if !self.path.is_dir() {
+            return Response::from_string(
+                "webhooks need --path to be a checkout of the repository\n",
+            )
+            .with_status_code(400);
+        }
+
+        let accepted = format!("assessing {}#{}\n", request.name, request.number);
+        match reviews.try_send(request) {
+            Ok(()) => Response::from_string(accepted).with_status_code(202),
+            Err(_) => Response::from_string("too many merge requests waiting for a review\n")
+                .with_status_code(503),
+        }
+    }
+
+    /// Fetches the diff of a merge request, assesses it against the watched path, taken as
+    /// a checkout of the branch it merges into, and comments on the functions it touches
+    fn review(&self, request: &MergeRequest) -> Result<(), CliError> {
+        let diff = webhook::fetch_diff(request)?;
+        let files = git::parse_diff(&diff);
+        let metrics: Vec<String> = DEFAULT_METRICS.iter().map(|m| m.to_string()).collect();
+        let mut hunks = assess_patch(&self.path, &diff, &files, &metrics, &self.options())?;
+        hunks.retain(|hunk| !hunk.functions.is_empty());
+        webhook::post_comment(request, &webhook::comment(&hunks, &metrics))?;
+        info!(
+            "Commented on {}#{}, {} hunks touch functions",
+            request.name,
+            request.number,
+            hunks.len()
+        );
+        Ok(())
+    }
+
+    /// Extracts the watched path again and renders the metrics in the Prometheus text format
+    fn refresh(&self) -> Result<String, CliError> {
+        let started = Instant::now();
+        let options = self.options();
+        let rows = extract_path(&self.path, &options)?;
+
+        let reader = self.language.reader()?;
+        let mut languages: BTreeMap<String, (usize, f64)> = BTreeMap::new();
+        let per_file: Vec<_> = self
+            .discovery
+            .discover(&self.path)?
+            .par_iter()
+            .filter_map(|fp| StatsCommand::file_stats(fp, &reader))
+            .collect();
+        for stats in per_file {
+            let entry = languages.entry(stats.language).or_default();
+            entry.0 += stats.files;
+            entry.1 += stats.sloc;
+        }
+
+        let text = self.render(&rows, &languages, started.elapsed());
+        info!("Refreshed metrics of {} functions", rows.len());
+        Ok(text)
+    }
+
+    fn render(
+        &self,
+        rows: &[MetricsRow],
+        languages: &BTreeMap<String, (usize, f64)>,
+        elapsed: Duration,
+    ) -> String {
+        let mut text = String::new();
+        let mut family = |name: &str, help: &str, samples: Vec<(String, f64)>| {
+            let _ = writeln!(text, "# HELP archaeo_{} {}", name, help);
+            let _ = writeln!(text, "# TYPE archaeo_{} gauge", name);
+            for (labels, value) in samples {
+                let _ = writeln!(text, "archaeo_{}{} {}", name, labels, value);
+            }
+        };
+
+        family(
+            "files",
+            "Source files by language",
+            languages
+                .iter()
+                .map(|(language, (files, _))| (labels(&[("language", language)]), *files as f64))
+                .collect(),
+        );
+        family(
+            "sloc",
+            "Source lines of code by language",
+            languages
+                .iter()
+                .map(|(language, (_, sloc))| (labels(&[("language", language)]), *sloc))
+                .collect(),
+        );
+        family(
+            "functions",
+            "Functions extracted",
+            vec![(String::new(), rows.len() as f64)],
+        );
+        family(
+            "functions_over_threshold",
+            "Functions whose metric is above the threshold",
+            self.threshold
+                .iter()
+                .map(|threshold| {
+                    let over = rows
+                        .iter()
+                        .filter(|row| {
+                            row.get(&threshold.metric)
+                                .and_then(|value| value.as_f64())
+                                .is_some_and(|value| value > threshold.limit)
+                        })
+                        .count();
+                    let limit = threshold.limit.to_string();
+                    (
+                        labels(&[("metric", &threshold.metric), ("threshold", &limit)]),
+                        over as f64,
+                    )
+                })
+                .collect(),
+        );
+        let mi: Vec<f64> = rows
+            .iter()
+            .filter_map(|row| row.get("mi_visual_studio").and_then(|value| value.as_f64()))
+            .filter(|value| value.is_finite())
+            .collect();
+        family(
+            "maintainability_index_mean",
+            "Mean Visual Studio maintainability index of the functions",
+            if mi.is_empty() {
+                Vec::new()
+            } else {
+                vec![(String::new(), mi.iter().sum::<f64>() / mi.len() as f64)]
+            },
+        );
+        family(
+            "refresh_duration_seconds",
+            "Seconds the last extraction took",
+            vec![(String::new(), elapsed.as_secs_f64())],
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs_f64())
+            .unwrap_or_default();
+        family(
+            "last_refresh_timestamp_seconds",
+            "Unix time of the last extraction",
+            vec![(String::new(), now)],
+        );
+        text
+    }
+}
+
+/// The body of `request`, or the response turning it away when it runs past `BODY_LIMIT`
+/// or can't be read
+fn read_body(request: &mut Request) -> Result<String, Response<Cursor<Vec<u8>>>> {
+    let too_large = || {
+        Response::from_string(format!(
+            "request bodies are limited to {} bytes\n",
+            BODY_LIMIT
+        ))
+        .with_status_code(413)
+    };
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > BODY_LIMIT)
+    {
+        return Err(too_large());
+    }
+    let mut body = String::new();
+    match request
+        .as_reader()
+        .take(BODY_LIMIT + 1)
+        .read_to_string(&mut body)
+    {
+        Ok(read) if read as u64 > BODY_LIMIT => Err(too_large()),
+        Ok(_) => Ok(body),
+        Err(err) => Err(Response::from_string(format!("{}\n", err)).with_status_code(500)),
+    }
+}
+
+/// The path `url` asks for, without its query string
+fn route(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(path, _)| path)
+}
+
+fn respond(request: Request, response: Response<Cursor<Vec<u8>>>) {
+    if let Err(err) = request.respond(response) {
+        warn!("Failed to respond: {}", err);
+    }
+}
+
+/// Formats a Prometheus label set, escaping the values
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        serve: ServeCommand,
+    }
+
+    #[test]
+    fn thresholds_are_parsed_as_metric_and_limit() {
+        let threshold = parse_threshold("cognitive=7.5").unwrap();
+        assert_eq!((threshold.metric.as_str(), threshold.limit), ("cognitive", 7.5));
+        assert_eq!(
+            parse_threshold("cyclomatic").unwrap_err(),
+            "expected metric=limit, got cyclomatic"
+        );
+        assert_eq!(
+            parse_threshold("cyclomatic=ten").unwrap_err(),
+            "invalid limit ten for cyclomatic"
+        );
+    }
+
+    #[test]
+    fn requests_are_routed_on_their_path_alone() {
+        assert_eq!(route("/metrics"), "/metrics");
+        assert_eq!(route("/metrics?format=prometheus&x=1"), "/metrics");
+        assert_eq!(route("/impact?"), "/impact");
+        assert_eq!(route("/?q"), "/");
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(
+            labels(&[("language", "c"), ("metric", "a\\b\"c\nd")]),
+            r#"{language="c",metric="a\\b\"c\nd"}"#
+        );
+    }
+
+    #[test]
+    fn renders_every_family_counting_functions_over_the_thresholds() {
+        let command = Cli::parse_from([
+            "archaeo",
+            "--path",
+            ".",
+            "--threshold",
+            "cyclomatic=2",
+            "--threshold",
+            "nargs=0",
+        ])
+        .serve;
+        let rows: Vec<MetricsRow> = serde_json::from_value(json!([
+            { "cyclomatic": 1, "nargs": 1, "mi_visual_studio": 40.0 },
+            { "cyclomatic": 3, "nargs": 0, "mi_visual_studio": 60.0 },
+            { "cyclomatic": 5, "mi_visual_studio": null },
+        ]))
+        .unwrap();
+        let languages = BTreeMap::from([("c".to_string(), (2, 120.0))]);
+
+        let text = command.render(&rows, &languages, Duration::from_millis(1500));
+        let samples: Vec<&str> = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter(|line| !line.starts_with("archaeo_last_refresh_timestamp_seconds"))
+            .collect();
+        assert_eq!(
+            samples,
+            [
+                r#"archaeo_files{language="c"} 2"#,
+                r#"archaeo_sloc{language="c"} 120"#,
+                "archaeo_functions 3",
+                r#"archaeo_functions_over_threshold{metric="cyclomatic",threshold="2"} 2"#,
+                r#"archaeo_functions_over_threshold{metric="nargs",threshold="0"} 1"#,
+                "archaeo_maintainability_index_mean 50",
+                "archaeo_refresh_duration_seconds 1.5",
+            ]
+        );
+        assert!(text.contains("# TYPE archaeo_functions gauge\n"));
+    }
+}
@@ -1,37 +1,116 @@
-use clap::Args;
+use clap::{ArgGroup, Args};
 use color_eyre::Result;
+use crossbeam_channel::RecvTimeoutError;
 use rayon::prelude::*;
-use rust_code_analysis::{get_function_spaces, guess_language, read_file};
+use rust_code_analysis::{action, Callback, ParserTrait};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt;
 use std::fs;
 use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
-use std::process::exit;
-
-use crate::errors::CliError;
-use archaeo_macros::ReplaceInfNan;
-use rust_code_analysis::FuncSpace;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+use crate::anonymous::{is_anonymous, synthetic_name, AnonymousNaming};
+use crate::availability::{self, Metric};
+use crate::budgets::Budgets;
+use crate::bugfix::{self, Bugfixes, FileBugfixes};
+use crate::build_info;
+use crate::callgraph::{build_edges, discover_calls, Coupling};
+use crate::config::Config;
+use crate::database;
+use crate::dataset::{Advisories, Labels, Split};
+use crate::decompiled;
+use crate::discovery::DiscoveryArgs;
+use crate::errors::{check_input, CliError};
+use crate::extract::{to_row, MetricsRow};
+use crate::functions::{FunctionNodes, Template};
+use crate::gate::{self, parse_rule, Outcome, Rule};
+use crate::generated::Skipped;
+use crate::git::{self, GitTree};
+use crate::interrupt;
+use crate::language::{Decoding, LanguageArgs, Source, SourceReader};
+use crate::manifest::Manifest;
+use crate::normalize::normalize;
+use crate::objc;
+use crate::output::{write_csv, RowStream};
+use crate::owners::CodeOwners;
+use crate::parse_errors::ParseErrors;
+use crate::paths::{self, OutputNames, PathNaming, PathStyle};
+use crate::plugins::{load_plugins, MetricPlugin, PluginRun};
+use crate::profile::{write_profile, FileProfile, Timings};
+use crate::projects::Projects;
+use crate::quality::Quality;
+use crate::query::{Captures, QuerySet};
+use crate::remote::{self, Staged};
+use crate::schema::{ColumnInfo, ColumnKind, MetricSchema, Schema};
+use crate::score::{self, parse_score, Score};
+use crate::suppress::Suppressions;
+use crate::telemetry;
+use crate::tree::SourceTree;
+use crate::units::TranslationUnits;
+use archaeo_macros::{MetricSchema, ReplaceInfNan};
+use rust_code_analysis::{FuncSpace, SpaceKind, LANG};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use tracing::{debug, error, info, warn};
-use walkdir::WalkDir;
 
-#[derive(Args)]
+#[derive(Args, Serialize)]
+#[command(group = ArgGroup::new("rules").multiple(true).args(["fail_on", "budgets"]))]
 pub struct SourceCommand {
     /// Path to the source code to be processed
     #[arg(short, long, required = true)]
     path: PathBuf,
 
-    /// Path to save generated outputs to
-    #[arg(short, long, required = true)]
+    /// Read the files of this revision straight from the object database of the git
+    /// repository at --path, which can be a bare mirror, instead of its working tree.
+    /// Files are named below --path as if the revision were checked out there
+    #[arg(long, conflicts_with = "manifest")]
+    git_rev: Option<String>,
+
+    /// Path to save generated outputs to. A postgres:// URL loads the rows into --table
+    /// instead. Not needed with `--fmt github`
+    #[arg(
+        short,
+        long,
+        required = false,
+        visible_alias = "output",
+        default_value_if("fmt", "github", "-")
+    )]
     output_path: PathBuf,
 
+    /// Table to load the rows into when the output is a PostgreSQL database, created if
+    /// needed. May be qualified by its schema
+    #[arg(long, default_value = "archaeo_metrics")]
+    table: String,
+
     /// Base directory for organizing outputs by matching subdirectories.
     /// When provided, subdirectories under this path will be preserved in the output structure
     #[arg(short, long)]
     base_dir: Option<String>,
 
-    /// Output format for the processed data
-    /// Options: json, csv
-    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    /// How source files are named in the `source_file` column: absolute, relative-to-root
+    /// (to --path) or basename. Outputs then mirror the names below the output path.
+    /// Defaults to the path as discovered below --path, with outputs named after the file
+    #[arg(long, conflicts_with = "base_dir", value_parser = clap::builder::PossibleValuesParser::new(["absolute", "relative-to-root", "basename"]))]
+    path_style: Option<String>,
+
+    /// Prefix to strip from the source file names after --path-style, e.g. a checkout
+    /// directory that differs between machines. Outputs mirror the stripped names
+    #[arg(long, conflicts_with = "base_dir")]
+    strip_prefix: Option<PathBuf>,
+
+    /// Output format for the processed data. `github` prints a GitHub Actions warning
+    /// annotation for every function breaking a --fail-on rule instead of writing outputs
+    /// Options: json, csv, github
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv", "github"]))]
     fmt: String,
 
     /// Preserve nested structure in output instead of flattening.
@@ -43,49 +122,273 @@ pub struct SourceCommand {
     /// When true, generates more comprehensive output with detailed analysis
     #[arg(long, default_value = "false")]
     extended: bool,
+
+    /// Emit the source text of every function in a `source` column
+    #[arg(long, default_value = "false")]
+    with_source: bool,
+
+    /// Path to a file of tree-sitter queries (.scm). Every capture becomes an extra
+    /// column counting its matches within each space. Captures starting with `_` are skipped
+    #[arg(long)]
+    query: Option<PathBuf>,
+
+    /// Metric plugins to run over every space, adding their columns to the output.
+    /// Either a built-in (todo_comments, max_nesting) or a path to a .wasm module when
+    /// built with the `wasm` feature
+    #[arg(long = "plugin", value_delimiter = ',')]
+    plugins: Vec<String>,
+
+    /// Only print the files that would be processed, their guessed language and where
+    /// their output would be written, without extracting anything
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+
+    /// Skip the files an earlier, interrupted run into the same output path already
+    /// completed, as recorded in its `.archaeo-completed` log
+    #[arg(long, default_value = "false")]
+    resume: bool,
+
+    /// Write how long reading, parsing, computing metrics and flattening took for every
+    /// file to this CSV, slowest first, and log the totals
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// Write the rows of every N input files into one shared `batch-NNNNN` output rather
+    /// than one output per file, recording which files went into which in batches.json.
+    /// Spares corpora of many small files a flood of tiny outputs
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["no_flatten", "base_dir", "translation_units"])]
+    batch: Option<u64>,
+
+    /// Give up on a file when reading, parsing and computing its metrics takes longer than
+    /// this many seconds, quarantining it. The abandoned parse runs on in the background
+    /// until it finishes, since tree-sitter can't be stopped midway, and the run fails once
+    /// more than 16 of those are running at once
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout_per_file: Option<u64>,
+
+    /// Exit with 2 when some files failed to process or were quarantined, rather than
+    /// succeeding as long as the run itself went through
+    #[arg(long, default_value = "false")]
+    summary_exit_codes: bool,
+
+    /// Write a manifest.json to the output path recording the options, versions, a hash
+    /// of the inputs and the produced output files
+    #[arg(long, default_value = "false")]
+    manifest: bool,
+
+    /// Add a normalized copy of every metric column of the schema, computed against the
+    /// whole corpus once all files are extracted. Annotations, captures and plugin columns
+    /// are left as they are. Options: zscore, minmax, percentile
+    #[arg(long, conflicts_with = "resume", value_parser = clap::builder::PossibleValuesParser::new(["zscore", "minmax", "percentile"]))]
+    normalize: Option<String>,
+
+    /// A composite score of the metric columns written to a column of its name, e.g.
+    /// `debt=0.5*normalized(cognitive)+0.3*normalized(loc_sloc)+0.2*churn`, and summed over
+    /// the functions of a file into `file_<name>`. See `src/score.rs` for the formulas
+    #[arg(long = "score", value_parser = parse_score, conflicts_with = "resume")]
+    scores: Vec<Score>,
+
+    /// Assign every row to a train, validation and test split with these ratios,
+    /// recorded in a `split` column
+    #[arg(long, value_delimiter = ',')]
+    split: Vec<f64>,
+
+    /// Whether whole files or individual functions are assigned to a split
+    /// Options: file, function
+    #[arg(long, default_value = "file", value_parser = clap::builder::PossibleValuesParser::new(["file", "function"]))]
+    split_by: String,
+
+    /// Only write these metric columns, each a column like `cyclomatic` or a family like
+    /// `halstead`, besides those identifying every function. --fail-on rules still see
+    /// every metric
+    #[arg(long, value_delimiter = ',')]
+    metrics: Vec<String>,
+
+    /// Write null rather than 0.0 for the metrics rust-code-analysis doesn't define for the
+    /// language of a file, e.g. Halstead and MI for Java, and record the ones it does in a
+    /// `metrics_supported` bitmask column
+    #[arg(long, default_value = "false")]
+    mask_unsupported: bool,
+
+    /// What closures, lambdas and other functions without a name are written as: a
+    /// synthetic name after where they start like `outer::lambda@3:17`, a null name, or
+    /// nothing, dropping their rows
+    /// Options: synthetic, null, drop
+    #[arg(long, default_value = "synthetic", value_parser = clap::builder::PossibleValuesParser::new(["synthetic", "null", "drop"]))]
+    anonymous: String,
+
+    /// Fold the explicit specializations of a C++ function template into the row of the
+    /// template, counting them in a `specializations` column, rather than writing rows of
+    /// their own. Specializations of templates defined in other files keep their rows
+    #[arg(long, default_value = "false")]
+    aggregate_specializations: bool,
+
+    /// Leave out the functions with more than this fraction of their bytes in nodes
+    /// tree-sitter failed to parse, whose metrics are computed over garbage. Every row
+    /// records its `parse_errors` and `parse_error_ratio` either way
+    #[arg(long, value_parser = parse_ratio)]
+    max_parse_error_ratio: Option<f64>,
+
+    /// What the non-finite metrics of empty or degenerate functions, e.g. a density over no
+    /// lines, are written as: zero, null, raw (NaN and inf as text) or a sentinel number
+    /// like -1. Defaults to zero
+    #[arg(long, allow_negative_numbers = true)]
+    non_finite: Option<NonFinite>,
+
+    /// CSV of labels to merge into the rows they match, e.g. bug-prone yes/no
+    #[arg(long)]
+    labels: Option<PathBuf>,
+
+    /// Columns the labels are matched to rows on, as they appear in the output.
+    /// Options: source_file, name, signature, start_line
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "source_file,name",
+        requires = "labels"
+    )]
+    join_on: Vec<String>,
+
+    /// JSON of security advisories to annotate the rows of the files and functions they
+    /// name with, in `advisories` and `advisory_count` columns. Either maps paths, or
+    /// `path#function`, to lists of ids like CVEs, or holds OSV records
+    #[arg(long)]
+    advisories: Option<PathBuf>,
+
+    /// Treat every file as decompiler pseudo-C (Ghidra, IDA, Binary Ninja): parse it as C,
+    /// strip decompiler banners and quirks, and record the binary in a `binary` column
+    /// taken from the `<binary>[@<function>].c` file name
+    #[arg(long, default_value = "false")]
+    decompiled: bool,
+
+    /// Group C/C++ headers with the source file sharing their name into one output per
+    /// translation unit, recording the unit in a `translation_unit` column
+    #[arg(long, default_value = "false")]
+    translation_units: bool,
+
+    /// CODEOWNERS file to resolve the owners of every file from, recorded in an `owner`
+    /// column. Patterns are relative to the repository it belongs to
+    #[arg(long)]
+    codeowners: Option<PathBuf>,
+
+    /// Count the commits of the history fixing something that changed every function and
+    /// file, in `bugfix_count` and `file_bugfix_count` columns. Fixes are the commits with a
+    /// message matching this regex, or words like `fix`, `bug` and `CVE` when not given
+    #[arg(long, num_args = 0..=1, default_missing_value = bugfix::DEFAULT_PATTERN)]
+    bugfixes: Option<String>,
+
+    /// Run the SZZ algorithm over the --bugfixes commits: blame the lines every fix removed
+    /// or changed on the commits introducing them, and count the commits changing every
+    /// function that introduced lines fixed later in a `bug_inducing_changes` column
+    #[arg(long, default_value = "false", requires = "bugfixes")]
+    szz: bool,
+
+    /// Write the outputs of every project, told by the nested git repository, `Cargo.toml`
+    /// or `CMakeLists.txt` at its root, below a directory named after it
+    #[arg(long, default_value = "false", conflicts_with = "batch")]
+    per_project_output: bool,
+
+    /// Fail the run when any function breaks one of these rules, e.g. `cyclomatic>15`.
+    /// Rules compare a metric column with `>`, `>=`, `<` or `<=`. Functions marked with an
+    /// `archaeo:ignore` comment within them, or an `archaeo:ignore-next-function` comment
+    /// before them, are exempt
+    #[arg(long, value_delimiter = ',', value_parser = parse_rule, conflicts_with = "no_flatten")]
+    fail_on: Vec<Rule>,
+
+    /// A budgets.toml of per-path metric limits, failing the run like --fail-on when any
+    /// function goes over the budget of its file. See `src/budgets.rs` for the format
+    #[arg(long, conflicts_with = "no_flatten")]
+    budgets: Option<PathBuf>,
+
+    /// Write a JUnit XML report of the --fail-on rules and budgets to this file, with a
+    /// failed test case for every function breaking one
+    #[arg(long, requires = "rules")]
+    report: Option<PathBuf>,
+
+    /// Leave the functions suppressed by `archaeo:ignore` comments out of the output as
+    /// well
+    #[arg(long, default_value = "false", conflicts_with = "no_suppressions")]
+    drop_suppressed: bool,
+
+    /// Don't honor `archaeo:ignore` comments, checking every function
+    #[arg(long, default_value = "false")]
+    no_suppressions: bool,
+
+    /// Project configuration filling in the extensions, excludes, --skip-generated and
+    /// --fail-on rules not given on the command line. Defaults to `archaeo.toml` in the
+    /// working directory when there is one
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
 }
 
 // Implementation for the base_dir functionality
 impl SourceCommand {
-    pub fn execute(mut self) -> Result<(), CliError> {
-        let extensions: Vec<String> = vec![
-            "cpp".to_string(),
-            "cc".to_string(),
-            "hpp".to_string(),
-            "c".to_string(),
-            "h".to_string(),
-        ];
+    /// Takes the options `explicit` says weren't given on the command line from the
+    /// project configuration
+    pub fn configure(&mut self, explicit: impl Fn(&str) -> bool) -> Result<(), CliError> {
+        let Some((_, config)) = Config::find(self.config.as_deref())? else {
+            return Ok(());
+        };
+        if !explicit("extensions") {
+            if let Some(extensions) = &config.extensions {
+                self.discovery.extensions = extensions.clone();
+            }
+        }
+        if !explicit("exclude") && !config.exclude.is_empty() {
+            self.discovery.exclude = config.exclude.clone();
+        }
+        if !explicit("skip_generated") {
+            if let Some(skip_generated) = config.skip_generated {
+                self.discovery.skip_generated = skip_generated;
+            }
+        }
+        if !explicit("fail_on") && !config.fail_on.is_empty() {
+            self.fail_on = config.rules().map_err(CliError::InvalidArgument)?;
+        }
+        if !explicit("scores") && !config.scores.is_empty() {
+            self.scores = config.scores().map_err(CliError::InvalidArgument)?;
+        }
+        Ok(())
+    }
 
+    pub fn execute(mut self) -> Result<(), CliError> {
         if self.no_flatten && self.fmt == "csv" {
             warn!("You have chosen the output format of CSV as well as not flattening. This is not supported \
             and the output format will be swap to JSON");
             self.fmt = "json".to_string();
         }
+        let mut staging = self.stage()?;
 
-        let mut filepaths = Vec::new();
-
-        if self.path.is_file() {
-            info!("Single file found...");
-            filepaths.push(self.path.clone());
-        } else if self.path.is_dir() {
-            info!("Multiple files found...");
-            for entry in WalkDir::new(&self.path)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(|e| e.ok())
-            {
-                if entry.file_type().is_file() {
-                    let path = entry.path();
-                    if Self::has_valid_extension(path, &extensions)
-                        && path.to_str().map_or(false, |p| !p.contains("test/"))
-                    {
-                        filepaths.push(path.to_path_buf());
-                    }
-                }
-            }
-        } else {
-            error!("The provided path is not a file or a dir. Exiting...");
-            exit(1)
+        // Checked up front, so a mistyped path doesn't leave an empty output directory
+        if self.discovery.tree.is_none() {
+            check_input(&self.path)?;
+        }
+        if let Some(rev) = &self.git_rev {
+            let tree = GitTree::open(&self.path, rev)?;
+            info!("Reading the files of {} from the object database", rev);
+            self.discovery.tree = Some(Arc::new(tree));
+        }
+        let reader = self
+            .language
+            .reader()?
+            .decompiled(self.decompiled)
+            .tree(self.discovery.tree.clone());
+        let schema = Schema::current(self.extended).select(&self.metrics)?;
+
+        let naming = PathNaming::new(
+            PathStyle::parse(self.path_style.as_deref()),
+            &self.path,
+            self.strip_prefix.as_deref(),
+        );
+        if self.dry_run {
+            let (filepaths, _) = self.discovery.discover_skipping(&self.path)?;
+            return self.plan(&filepaths, &reader, &naming);
         }
 
         if !self.output_path.exists() {
@@ -93,59 +396,477 @@ impl SourceCommand {
             fs::create_dir_all(&self.output_path)?;
         }
 
-        filepaths
-            .par_iter()
-            .try_for_each(|fp| self.extract_metrics(fp))?;
+        let completed = CompletedLog::open(&self.output_path.join(COMPLETED_LOG), self.resume)?;
+        interrupt::install();
+        // Fan-in needs the whole corpus, so resolve the call graph before extracting, reading
+        // the calls of every file while the walk goes on. Completed files are only skipped
+        // afterwards so resuming doesn't change it
+        let (filepaths, skipped, mut calls) = discover_calls(&self.discovery, &self.path, &reader)?;
+        // Keyed by the names rows are written with, for fan-in and fan-out lookups
+        for (file, _) in &mut calls {
+            *file = naming.display(Path::new(file.as_str()));
+        }
+        let coupling = Coupling::from_edges(&build_edges(&calls));
 
-        Ok(())
-    }
+        if self.normalize.is_some() && self.no_flatten {
+            warn!("Normalization is only supported for flattened output and will be skipped");
+            self.normalize = None;
+        }
+        if !self.scores.is_empty() && self.no_flatten {
+            warn!("Scores are only supported for flattened output and will be skipped");
+            self.scores.clear();
+        }
 
-    fn extract_metrics(&self, path: &PathBuf) -> Result<(), CliError> {
-        info!("Executing source command on file: {}", path.display());
+        let batches = self.batches(&filepaths, &completed);
+        let pending: Vec<&PathBuf> = batches
+            .iter()
+            .flat_map(|batch| batch.files.iter().copied())
+            .collect();
+        if self.resume {
+            info!(
+                "Resuming, skipping {} already completed files",
+                filepaths.len() - pending.len()
+            );
+        }
 
-        let source = read_file(path)
-            .map_err(|_| CliError::FailedProcessing(path.to_string_lossy().to_string()))?;
+        let output = staging.output.take();
+        let run = self.prepare_run(output, naming, reader, coupling, &filepaths, &schema)?;
+        let progress = Progress::new(pending.len());
+        batches
+            .par_iter()
+            .try_for_each(|batch| self.extract_batch(batch, &run, &completed, &progress))?;
+
+        let interrupted = interrupt::interrupted();
+        let failed = run.failed.load(Ordering::Relaxed);
+        if interrupted {
+            warn!(
+                "Interrupted, {} of {} files completed. Pass --resume to complete the rest",
+                filepaths.iter().filter(|fp| completed.contains(fp)).count(),
+                filepaths.len()
+            );
+        }
+        if let Some(path) = &self.profile {
+            write_profile(path, &mut run.profiles.lock().unwrap())?;
+        }
+        let partial = progress.partial.into_inner().unwrap();
+        let mut quarantined = progress.quarantined.into_inner().unwrap();
+        quarantined.sort();
+        let quarantine = write_quarantine(&self.output_path, &quarantined)?;
+        if let Some(path) = &quarantine {
+            warn!(
+                "{} files failed to read or timed out and were quarantined to {}. Pass --resume to retry them",
+                quarantined.len(),
+                path.display()
+            );
+        }
+        if let Some(path) = write_duplicates(&self.output_path, &skipped)? {
+            info!("Collapsed duplicates recorded in {}", path.display());
+        }
+        let abandoned: HashSet<&PathBuf> = quarantined.iter().map(|(fp, _)| *fp).collect();
+
+        // Normalizing or merging only part of the corpus would write different rows than
+        // the full run, so held back rows are dropped and their files redone on resume
+        if self.defers() && interrupted {
+            warn!("Not writing the rows held back for the whole corpus or translation units");
+        } else if self.defers() {
+            self.write_deferred(&run, &filepaths)?;
+            for fp in pending.iter().filter(|fp| !abandoned.contains(*fp)) {
+                completed.record(fp)?;
+            }
+        }
 
-        let language = if let Some(language) = guess_language(&source, path).0 {
-            language
-        } else {
-            return Err(CliError::FailedGuessLang(
-                path.to_string_lossy().to_string(),
+        if let Some(size) = self.batch {
+            let batched = self.batch_contents(size, &filepaths, &partial, &run);
+            let path = write_batches(&self.output_path, &batched)?;
+            info!("Batches written to {}", path.display());
+        }
+        schema.write(&self.output_path)?;
+
+        if self.manifest {
+            let mut outputs = progress.outputs.into_inner().unwrap();
+            // An interrupted run only accounts for the files it completed
+            let mut inputs = filepaths.clone();
+            if interrupted {
+                inputs.retain(|fp| completed.contains(fp));
+                outputs.retain(|output| output.exists());
+            }
+            inputs.retain(|fp| !abandoned.contains(fp));
+            let mut manifest = Manifest::new(&self, &inputs, &outputs, &skipped, self.resume)?;
+            manifest.interrupted = interrupted;
+            manifest.quarantined = quarantined
+                .iter()
+                .map(|(fp, reason)| Skipped {
+                    source_file: run.source_name(fp),
+                    reason: reason.clone(),
+                    duplicate_of: None,
+                })
+                .collect();
+            let path = manifest.write(&self.output_path)?;
+            info!("Manifest written to {}", path.display());
+        }
+
+        self.publish(&staging, run.output.as_ref())?;
+        if self.checks_rules() {
+            let outcomes = run.outcomes.into_inner().unwrap();
+            self.report_outcomes(outcomes, staging.annotations.is_some(), interrupted)?;
+        }
+
+        if interrupted {
+            return Err(CliError::Interrupted(
+                "pass --resume to complete the rest of the run".to_string(),
             ));
-        };
+        }
+        let failed = failed + quarantined.len();
+        if self.summary_exit_codes && failed > 0 {
+            return Err(CliError::FilesFailed(failed));
+        }
+        Ok(())
+    }
 
-        debug!("Source: {:?} bytes Language: {:?}", source.len(), language);
+    /// The state shared by the files of the run: what every file is read with, named as and
+    /// written to, and what its rows are annotated and checked with
+    fn prepare_run(
+        &self,
+        output: Option<Staged>,
+        naming: PathNaming,
+        reader: SourceReader,
+        coupling: Coupling,
+        filepaths: &[PathBuf],
+        schema: &Schema,
+    ) -> Result<SourceRun, CliError> {
+        let units = self
+            .translation_units
+            .then(|| TranslationUnits::new(filepaths));
+        let projects = Projects::find(&self.path, filepaths, self.discovery.tree.as_deref());
+        let outputs = match self.batch {
+            Some(_) => OutputNames::default(),
+            None => self.output_names(&naming, filepaths, units.as_ref(), &projects),
+        };
+        Ok(SourceRun {
+            output,
+            naming,
+            outputs,
+            units,
+            reader,
+            coupling,
+            queries: match &self.query {
+                Some(path) => Some(QuerySet::from_file(path)?),
+                None => None,
+            },
+            plugins: load_plugins(&self.plugins)?,
+            deferred: Mutex::new(Vec::new()),
+            split: if self.split.is_empty() {
+                None
+            } else {
+                Some(Split::new(
+                    &self.split,
+                    &self.split_by,
+                    self.discovery.seed,
+                )?)
+            },
+            labels: match &self.labels {
+                Some(path) => Some(Labels::from_file(path, &self.join_on)?),
+                None => None,
+            },
+            advisories: match &self.advisories {
+                Some(path) => Some(Advisories::from_file(path)?),
+                None => None,
+            },
+            owners: match &self.codeowners {
+                Some(path) => Some(CodeOwners::from_file(path)?),
+                None => None,
+            },
+            projects,
+            bugfixes: match &self.bugfixes {
+                Some(pattern) => {
+                    let rev = self.git_rev.as_deref().unwrap_or("HEAD");
+                    Some(Bugfixes::find(&self.repo_root()?, rev, pattern, self.szz)?)
+                }
+                None => None,
+            },
+            budgets: match &self.budgets {
+                Some(path) => Some(Budgets::from_file(path)?),
+                None => None,
+            },
+            outcomes: Mutex::new(Vec::new()),
+            profiles: Mutex::new(Vec::new()),
+            failed: AtomicUsize::new(0),
+            deselected: schema.deselected(),
+            metric_columns: schema.metric_columns(),
+        })
+    }
 
-        if let Some(space) = get_function_spaces(&language, source.clone(), path, None) {
-            debug!("Successfully extracted function metrics");
+    /// The files left to extract, in batches of `--batch` files sharing an output, or each
+    /// a batch of its own writing its own output. A batch is redone whole when resuming
+    /// unless every file of it completed
+    fn batches<'a>(&self, filepaths: &'a [PathBuf], completed: &CompletedLog) -> Vec<Batch<'a>> {
+        match self.batch {
+            Some(size) => filepaths
+                .chunks(size as usize)
+                .enumerate()
+                .filter(|(_, files)| files.iter().any(|fp| !completed.contains(fp)))
+                .map(|(index, files)| Batch {
+                    path: Some(self.batch_path(index)),
+                    files: files.iter().collect(),
+                })
+                .collect(),
+            None => filepaths
+                .iter()
+                .filter(|fp| !completed.contains(fp))
+                .map(|fp| Batch {
+                    path: None,
+                    files: vec![fp],
+                })
+                .collect(),
+        }
+    }
 
-            // Fix the filepath ending
-            let output_filename = match self.fmt.as_str() {
-                "csv" if self.extended => format!(
-                    "{}-extended.csv",
-                    path.file_stem().unwrap().to_string_lossy()
-                ),
-                "json" if self.extended => format!(
-                    "{}-extended.json",
-                    path.file_stem().unwrap().to_string_lossy()
-                ),
-                "csv" => format!("{}.csv", path.file_stem().unwrap().to_string_lossy()),
-                "json" => format!("{}.json", path.file_stem().unwrap().to_string_lossy()),
-                _ => {
-                    unreachable!("Invalid format")
+    /// Extracts the files of `batch` one after the other, until an interrupt leaves the
+    /// rest for `--resume`. Batched files only complete along with the rest of their batch
+    fn extract_batch<'a>(
+        &self,
+        batch: &Batch<'a>,
+        run: &SourceRun,
+        completed: &CompletedLog,
+        progress: &Progress<'a>,
+    ) -> Result<(), CliError> {
+        let mut output = batch.path.clone().map(|path| Output::new(path, &self.fmt));
+        let mut written = false;
+        let mut extracted = 0;
+        let mut abandoned = Vec::new();
+        for fp in &batch.files {
+            // Files already being extracted finish, the rest are left for `--resume`
+            if interrupt::interrupted() {
+                break;
+            }
+            let result = self.extract_metrics(fp, run, output.as_mut());
+            match self.settle(fp, result, output.is_some(), completed, progress)? {
+                Settled::Written => written = true,
+                Settled::Quarantined => abandoned.push(*fp),
+                Settled::Failed => {
+                    run.failed.fetch_add(1, Ordering::Relaxed);
                 }
-            };
+                Settled::Empty => {}
+            }
+            extracted += 1;
+        }
 
-            // Determine output path with proper directory structure
-            let output_path = self.determine_output_path(path, &output_filename)?;
+        let Some(output) = output else {
+            return Ok(());
+        };
+        let path = output.path.clone();
+        output.finish()?;
+        if written {
+            run.publish(&path);
+            progress.outputs.lock().unwrap().push(path.clone());
+        }
+        if extracted < batch.files.len() {
+            progress.partial.lock().unwrap().insert(path, extracted);
+        } else if !self.defers() {
+            for fp in batch.files.iter().filter(|fp| !abandoned.contains(fp)) {
+                completed.record(fp)?;
+            }
+        }
+        Ok(())
+    }
 
-            // Ensure the parent directory exists
-            if let Some(parent) = output_path.parent() {
-                if !parent.exists() {
-                    fs::create_dir_all(parent)?;
-                    debug!("Created output directory: {}", parent.display());
+    /// Settles the `result` of extracting `fp`. Files failing to read once retried, or
+    /// taking longer than `--timeout-per-file`, are quarantined rather than failing or
+    /// stalling the run, and files that can't be processed are logged and left out. Only
+    /// errors writing the outputs fail the run. Files writing an output of their own
+    /// complete with it, unless their rows are held back
+    fn settle<'a>(
+        &self,
+        fp: &'a PathBuf,
+        result: Result<Option<PathBuf>, CliError>,
+        batched: bool,
+        completed: &CompletedLog,
+        progress: &Progress<'a>,
+    ) -> Result<Settled, CliError> {
+        let quarantine = match &result {
+            Err(CliError::ReadFailed(_, reason)) => Some(reason.clone()),
+            Err(CliError::TimedOut(_, secs)) => Some(format!("timed out after {}s", secs)),
+            _ => None,
+        };
+        let settled = match (&result, quarantine) {
+            (_, Some(reason)) => {
+                warn!(file = %fp.display(), "Quarantined: {}", reason);
+                progress.quarantined.lock().unwrap().push((fp, reason));
+                telemetry::file_failed();
+                Ok(Settled::Quarantined)
+            }
+            // Retrying won't change these either, so they complete like the files the
+            // metrics come back empty for
+            (Err(err @ (CliError::FailedGuessLang(_) | CliError::FailedProcessing(_))), None) => {
+                error!(file = %fp.display(), "Failed to process: {}", err);
+                telemetry::file_failed();
+                if !batched && !self.defers() {
+                    completed.record(fp)?;
+                }
+                Ok(Settled::Failed)
+            }
+            (Ok(output), None) if !batched => {
+                // Deferred files only complete once written after the loop
+                if !self.defers() {
+                    completed.record(fp)?;
                 }
+                progress.outputs.lock().unwrap().extend(output.clone());
+                Ok(Settled::Empty)
+            }
+            (Ok(Some(_)), None) => Ok(Settled::Written),
+            (Ok(None), None) => Ok(Settled::Empty),
+            (Err(_), None) => {
+                telemetry::file_failed();
+                result.map(|_| Settled::Empty)
             }
+        };
+        telemetry::file_processed();
+        let remaining = progress.remaining.fetch_sub(1, Ordering::Relaxed) - 1;
+        telemetry::queue_depth(remaining);
+        settled
+    }
+
+    /// Normalizes and scores the rows held back for the whole corpus, then writes them,
+    /// the rows of the files of a translation unit into one output
+    fn write_deferred(&self, run: &SourceRun, filepaths: &[PathBuf]) -> Result<(), CliError> {
+        let mut deferred = std::mem::take(&mut *run.deferred.lock().unwrap());
+        if let Some(method) = &self.normalize {
+            info!("Normalizing {} files with {}...", deferred.len(), method);
+            normalize(
+                &mut deferred
+                    .iter_mut()
+                    .flat_map(|(_, rows)| rows.iter_mut())
+                    .collect::<Vec<_>>(),
+                &run.metric_columns,
+                method,
+            );
+        }
+        if !self.scores.is_empty() {
+            let churn = self.churn(filepaths, |fp| run.source_name(fp))?;
+            score::apply(
+                &mut deferred
+                    .iter_mut()
+                    .flat_map(|(_, rows)| rows.iter_mut())
+                    .collect::<Vec<_>>(),
+                &self.scores,
+                &churn,
+            );
+        }
+
+        // Files of the same translation unit share an output
+        let mut merged: BTreeMap<PathBuf, Vec<MetricsRow>> = BTreeMap::new();
+        for (output_path, rows) in deferred {
+            merged.entry(output_path).or_default().extend(rows);
+        }
+        for rows in merged.values_mut() {
+            rows.sort_by(|a, b| {
+                let file = |row: &MetricsRow| row["source_file"].as_str().map(str::to_string);
+                file(a).cmp(&file(b))
+            });
+        }
+        merged
+            .par_iter()
+            .try_for_each(|(output_path, rows)| self.write_rows(output_path, rows))
+    }
+
+    /// The source files of every batch of `size` files, as far as an interrupt let the
+    /// `partial` ones be extracted
+    fn batch_contents(
+        &self,
+        size: u64,
+        filepaths: &[PathBuf],
+        partial: &BTreeMap<PathBuf, usize>,
+        run: &SourceRun,
+    ) -> Vec<(PathBuf, Vec<String>)> {
+        filepaths
+            .chunks(size as usize)
+            .enumerate()
+            .map(|(index, files)| {
+                let path = self.batch_path(index);
+                let extracted = partial.get(&path).copied().unwrap_or(files.len());
+                let names = files[..extracted]
+                    .iter()
+                    .map(|fp| run.source_name(fp))
+                    .collect();
+                (path, names)
+            })
+            .collect()
+    }
+
+    /// Writes the functions checked against the rules to the `--report` and, with `--fmt
+    /// github`, as annotations, failing the run when any of them broke a rule. An
+    /// interrupted run only reports them
+    fn report_outcomes(
+        &self,
+        mut outcomes: Vec<Outcome>,
+        annotate: bool,
+        interrupted: bool,
+    ) -> Result<(), CliError> {
+        outcomes
+            .sort_by(|a, b| (&a.source_file, a.start_line).cmp(&(&b.source_file, b.start_line)));
+        if let Some(report) = &self.report {
+            gate::write_junit(File::create(report)?, &outcomes)?;
+            info!("JUnit report written to {}", report.display());
+        }
+        if annotate {
+            let mut stdout = std::io::stdout().lock();
+            gate::write_annotations(&mut stdout, &outcomes)?;
+            stdout.flush()?;
+        }
+        if interrupted {
+            return Ok(());
+        }
+        gate::verdict(&outcomes)
+    }
+
+    /// Extracts the rows of `path` into `batch` when given, or into an output of its own
+    fn extract_metrics(
+        &self,
+        path: &Path,
+        run: &SourceRun,
+        batch: Option<&mut Output>,
+    ) -> Result<Option<PathBuf>, CliError> {
+        info!("Executing source command on file: {}", path.display());
+
+        let (spaces, timings) = match self.timeout_per_file {
+            Some(secs) => read_spaces_within(path, &run.reader, Duration::from_secs(secs))?,
+            None => read_spaces_timed(path, &run.reader)?,
+        };
+        if let Some(spaces) = spaces {
+            debug!("Successfully extracted function metrics");
+            let flattening = Instant::now();
+            let name = |path: &Path| run.source_name(path);
+            let settings = ContextSettings {
+                name: &name,
+                mask_unsupported: self.mask_unsupported,
+                non_finite: self.non_finite,
+                anonymous: AnonymousNaming::parse(&self.anonymous),
+                aggregate_specializations: self.aggregate_specializations,
+                max_parse_error_ratio: self.max_parse_error_ratio,
+                decompiled: self.decompiled,
+                with_source: self.with_source,
+                no_suppressions: self.no_suppressions,
+                drop_suppressed: self.drop_suppressed,
+                queries: run.queries.as_ref(),
+                plugins: &run.plugins,
+                units: run.units.as_ref(),
+                owners: run.owners.as_ref(),
+                projects: &run.projects,
+                bugfixes: run.bugfixes.as_ref(),
+                tree: self.discovery.tree.as_deref(),
+                split: run.split.as_ref(),
+                labels: run.labels.as_ref(),
+                advisories: run.advisories.as_ref(),
+            };
+            let (context, suppressions) = settings.context(path, &spaces, &run.coupling);
+            let (_, source, space, _) = &spaces;
+
+            let mut own = None;
+            let output = match batch {
+                Some(output) => output,
+                None => own.insert(self.own_output(path, run)?),
+            };
+            let output_path = output.path.clone();
 
             if self.no_flatten {
                 match self.fmt.as_str() {
@@ -153,499 +874,2021 @@ impl SourceCommand {
                         error!("Not possible!")
                     }
                     "json" => {
-                        serde_json::to_writer_pretty(File::create(&output_path).unwrap(), &space)?;
-                        debug!("All saved to JSON at {:?}", output_path.display())
+                        serde_json::to_writer_pretty(File::create(&output_path)?, &space)?;
+                        debug!("All saved to JSON at {:?}", output_path.display());
+                        run.publish(&output_path);
                     }
                     _ => {}
                 }
             } else {
-                let flattened = if self.extended {
-                    let mut flattened: Vec<FlattenedMetricsExtended> = Vec::new();
-
-                    flatten_spaces_extended(
-                        &space.spaces,
-                        &Some(path.to_string_lossy().to_string()),
-                        &mut flattened,
-                    );
-
-                    if flattened.is_empty() {
-                        debug!("No function metrics extracted for {}", path.display());
-                        return Ok(());
-                    }
-                    MetricsType::Extended(flattened)
-                } else {
-                    let mut flattened: Vec<FlattenedMetrics> = Vec::new();
-
-                    flatten_spaces(
-                        &space.spaces,
-                        &Some(path.to_string_lossy().to_string()),
-                        &mut flattened,
-                    );
-
-                    if flattened.is_empty() {
-                        debug!("No function metrics extracted for {}", path.display());
-                        return Ok(());
+                let (budget, limits) = match &run.budgets {
+                    Some(budgets) => {
+                        let (name, limits) = budgets.budget_of(path);
+                        (Some(name.to_string()), limits)
                     }
-                    MetricsType::Regular(flattened)
+                    None => (None, &[][..]),
                 };
-
-                match self.fmt.as_str() {
-                    "csv" => {
-                        let file = File::create(&output_path)?;
-                        let mut writer = csv::Writer::from_writer(file);
-                        match &flattened {
-                            MetricsType::Extended(metrics) => {
-                                for entry in metrics {
-                                    writer.serialize(entry)?
-                                }
-                            }
-                            MetricsType::Regular(metrics) => {
-                                for entry in metrics {
-                                    writer.serialize(entry)?
-                                }
-                            }
-                        }
-                        writer.flush()?;
-                        debug!("All saved to CSV at {}", output_path.display())
-                    }
-                    "json" => {
-                        match &flattened {
-                            MetricsType::Extended(metrics) => {
-                                serde_json::to_writer_pretty(
-                                    File::create(&output_path).unwrap(),
-                                    &metrics,
-                                )?;
-                            }
-                            MetricsType::Regular(metrics) => {
-                                serde_json::to_writer_pretty(
-                                    File::create(&output_path).unwrap(),
-                                    &metrics,
-                                )?;
-                            }
+                let rules: Vec<Rule> = self.fail_on.iter().chain(limits).cloned().collect();
+
+                // Rows are checked and written out one at a time as the spaces are
+                // flattened, unless they are held back for the whole corpus or translation
+                // unit. The output file is only created once there is a row for it
+                let mut outcomes = Vec::new();
+                let mut deferred = Vec::new();
+                let mut rows = 0;
+                visit_spaces(&space.spaces, &context, &mut |space, identity| {
+                    let mut row = if self.extended {
+                        to_row(&FlattenedMetricsExtended::from_space(
+                            space, identity, &context,
+                        ))?
+                    } else {
+                        to_row(&FlattenedMetrics::from_space(space, identity, &context))?
+                    };
+                    rows += 1;
+
+                    if self.checks_rules() {
+                        for mut outcome in gate::check(&rules, std::slice::from_ref(&row)) {
+                            outcome.suppressed = suppressions
+                                .covers(outcome.start_line as usize, outcome.end_line as usize);
+                            outcome.budget = budget.clone();
+                            outcomes.push(outcome);
                         }
-                        debug!("All saved to JSON at {}", output_path.display())
                     }
-                    _ => {
-                        unreachable!("Invalid format provided.")
+                    row.retain(|column, _| !run.deselected.contains(column));
+
+                    if self.defers() {
+                        deferred.push(row);
+                        return Ok(());
                     }
+                    output.write(&row)
+                })?;
+
+                if self.profile.is_some() {
+                    run.profiles.lock().unwrap().push(FileProfile::new(
+                        run.source_name(path),
+                        source.len(),
+                        rows,
+                        timings,
+                        flattening.elapsed(),
+                    ));
+                }
+                if rows == 0 {
+                    debug!("No function metrics extracted for {}", path.display());
+                    return Ok(None);
+                }
+                run.outcomes.lock().unwrap().extend(outcomes);
+
+                // Hold the rows back until the whole corpus or translation unit is known
+                if self.defers() {
+                    run.deferred
+                        .lock()
+                        .unwrap()
+                        .push((output_path.clone(), deferred));
+                } else if let Some(own) = own {
+                    own.finish()?;
+                    run.publish(&output_path);
                 }
             }
 
-            Ok(())
+            Ok(Some(output_path))
         } else {
-            error!("Failed to process: {}", path.display());
-            Ok(())
+            error!(file = %path.display(), "Failed to process");
+            telemetry::file_failed();
+            run.failed.fetch_add(1, Ordering::Relaxed);
+            Ok(None)
         }
     }
 
-    fn determine_output_path(
+    /// The output planned for the rows of `path`, or of its translation unit
+    fn own_output(&self, path: &Path, run: &SourceRun) -> Result<Output, CliError> {
+        let unit_path = self.unit_path(path, run.units.as_ref());
+        let output_path = run
+            .outputs
+            .get(&unit_path)
+            .ok_or_else(|| {
+                CliError::FailedProcessing(format!("No output planned for {}", unit_path.display()))
+            })?
+            .to_path_buf();
+
+        // Ensure the parent directory exists
+        if let Some(parent) = output_path.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)?;
+                debug!("Created output directory: {}", parent.display());
+            }
+        }
+        Ok(Output::new(output_path, &self.fmt))
+    }
+
+    /// Points the run at local stand-ins for an output path that isn't a directory: a
+    /// scratch directory with `--fmt github`, CSVs staged for a PostgreSQL database, and
+    /// outputs staged for object storage. An object storage `--path` is read straight from
+    /// the store
+    fn stage(&mut self) -> Result<Staging, CliError> {
+        let annotations = if self.fmt == "github" {
+            Some(self.stage_annotations()?)
+        } else {
+            None
+        };
+        let database = if database::is_postgres(&self.output_path) {
+            Some(self.stage_database()?)
+        } else {
+            None
+        };
+        if remote::is_remote(&self.path) {
+            self.open_input()?;
+        }
+        let output = if remote::is_remote(&self.output_path) {
+            let staged = remote::staging(&self.output_path.to_string_lossy())?;
+            self.output_path = staged.path().to_path_buf();
+            Some(staged)
+        } else {
+            None
+        };
+        Ok(Staging {
+            annotations,
+            database,
+            output,
+        })
+    }
+
+    /// Hands the outputs staged for object storage or PostgreSQL over to them
+    fn publish(&self, staging: &Staging, output: Option<&Staged>) -> Result<(), CliError> {
+        if let Some(output) = output {
+            remote::upload(output)?;
+        }
+        if let Some((url, staged)) = &staging.database {
+            database::copy_outputs(url, &self.table, staged.path())?;
+        }
+        Ok(())
+    }
+
+    /// Points the walk at the objects below the object store `--path`, fetched from the
+    /// store as they are read rather than copied out of it first
+    fn open_input(&mut self) -> Result<(), CliError> {
+        if self.git_rev.is_some() {
+            return Err(CliError::InvalidArgument(
+                "--git-rev reads a repository on disk rather than object storage".to_string(),
+            ));
+        }
+        self.discovery.tree = Some(remote::open(&self.path.to_string_lossy())?);
+        Ok(())
+    }
+
+    /// Points the run at a staging directory of flattened CSV outputs, later copied into
+    /// the PostgreSQL database given as the output path
+    fn stage_database(&mut self) -> Result<(String, TempDir), CliError> {
+        let url = self.output_path.to_string_lossy().to_string();
+        database::check_supported(&url)?;
+        if self.resume || self.manifest || self.no_flatten {
+            return Err(CliError::InvalidArgument(
+                "--resume, --manifest and --no-flatten need an output directory rather than a database"
+                    .to_string(),
+            ));
+        }
+        self.fmt = "csv".to_string();
+
+        let staged = tempfile::Builder::new().prefix("archaeo-").tempdir()?;
+        self.output_path = staged.path().to_path_buf();
+        Ok((url, staged))
+    }
+
+    /// Points the run at a scratch directory of CSV outputs, as `--fmt github` only prints
+    /// the functions breaking a rule
+    fn stage_annotations(&mut self) -> Result<TempDir, CliError> {
+        if !self.checks_rules() {
+            return Err(CliError::InvalidArgument(
+                "--fmt github annotates the functions breaking --fail-on rules or --budgets, give either"
+                    .to_string(),
+            ));
+        }
+        if self.resume || self.manifest {
+            return Err(CliError::InvalidArgument(
+                "--resume and --manifest need an output directory rather than --fmt github"
+                    .to_string(),
+            ));
+        }
+        self.fmt = "csv".to_string();
+
+        let staged = tempfile::Builder::new().prefix("archaeo-").tempdir()?;
+        self.output_path = staged.path().to_path_buf();
+        Ok(staged)
+    }
+
+    /// Whether functions are checked against `--fail-on` rules or `--budgets`
+    fn checks_rules(&self) -> bool {
+        !self.fail_on.is_empty() || self.budgets.is_some()
+    }
+
+    /// Whether rows are held back and written once every file is extracted, for
+    /// normalization or merging translation units
+    fn defers(&self) -> bool {
+        !self.no_flatten
+            && (self.normalize.is_some() || !self.scores.is_empty() || self.translation_units)
+    }
+
+    /// The commits touching every file in the history of `HEAD`, by the name its rows are
+    /// written with, when a `--score` refers to `churn`
+    fn churn(
         &self,
-        input_path: &PathBuf,
-        output_filename: &str,
-    ) -> Result<PathBuf, CliError> {
-        if self.base_dir.is_none() {
-            return Ok(self.output_path.join(output_filename));
+        filepaths: &[PathBuf],
+        source_name: impl Fn(&Path) -> String,
+    ) -> Result<HashMap<String, f64>, CliError> {
+        if !self.scores.iter().any(Score::uses_churn) {
+            return Ok(HashMap::new());
+        }
+        let root = self.repo_root()?;
+        let history = git::churn(&root, None)?;
+        Ok(filepaths
+            .iter()
+            .map(|fp| {
+                let file = repo_path(self.discovery.tree.as_deref(), &root, fp);
+                let commits = history.get(&file).map_or(0, |churn| churn.commits);
+                (source_name(fp), commits as f64)
+            })
+            .collect())
+    }
+
+    /// Root of the git repository --path is in, or --path itself with --git-rev
+    fn repo_root(&self) -> Result<PathBuf, CliError> {
+        if self.discovery.tree.is_some() {
+            return Ok(self.path.clone());
+        }
+        let dir = if self.path.is_file() {
+            self.path.parent().unwrap_or(Path::new("."))
+        } else {
+            self.path.as_path()
+        };
+        let root = git::toplevel(dir)?;
+        Ok(root.canonicalize().unwrap_or(root))
+    }
+
+    /// Writes rows held back for normalization or translation units
+    fn write_rows(&self, output_path: &Path, rows: &[MetricsRow]) -> Result<(), CliError> {
+        match self.fmt.as_str() {
+            "csv" => write_csv(File::create(output_path)?, rows)?,
+            "json" => serde_json::to_writer_pretty(File::create(output_path)?, rows)?,
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+        debug!("All saved to {}", output_path.display());
+        Ok(())
+    }
+
+    /// Output of the batch numbered `index`
+    fn batch_path(&self, index: usize) -> PathBuf {
+        let name = format!("batch-{:05}", index);
+        self.output_path.join(self.output_filename(&name))
+    }
+
+    /// Name of the output of a source with the encoded `stem`
+    fn output_filename(&self, stem: &str) -> String {
+        match self.fmt.as_str() {
+            "csv" if self.extended => format!("{}-extended.csv", stem),
+            "json" if self.extended => format!("{}-extended.json", stem),
+            "csv" => format!("{}.csv", stem),
+            "json" => format!("{}.json", stem),
+            _ => {
+                unreachable!("Invalid format")
+            }
+        }
+    }
+
+    /// Prints the language guessed for every discovered file and where its output
+    /// would be written, without extracting anything
+    fn plan(
+        &self,
+        filepaths: &[PathBuf],
+        reader: &SourceReader,
+        naming: &PathNaming,
+    ) -> Result<(), CliError> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .from_writer(std::io::stdout());
+        writer.write_record(["source_file", "language", "output_file"])?;
+
+        let units = self
+            .translation_units
+            .then(|| TranslationUnits::new(filepaths));
+        let projects = Projects::find(&self.path, filepaths, self.discovery.tree.as_deref());
+        let outputs = self.output_names(naming, filepaths, units.as_ref(), &projects);
+        let mut unknown = 0;
+        for (index, path) in filepaths.iter().enumerate() {
+            let language = reader
+                .read_decoded(path)
+                .ok()
+                .map(|(language, _, decoding)| decoding.choice.name(&language));
+            let output = match self.batch {
+                Some(size) => self.batch_path(index / size as usize),
+                None => outputs
+                    .get(&self.unit_path(path, units.as_ref()))
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+            };
+            let (language, output) = match language {
+                Some(language) => (language.to_string(), output.to_string_lossy().to_string()),
+                None => {
+                    unknown += 1;
+                    ("unknown".to_string(), String::new())
+                }
+            };
+            writer.write_record([naming.display(path).as_str(), &language, &output])?;
+        }
+        writer.flush()?;
+
+        info!(
+            "Would process {} files, {} of which have no recognised language",
+            filepaths.len(),
+            unknown
+        );
+        Ok(())
+    }
+
+    /// Where the rows of `path` are written, the source file of its translation unit when
+    /// headers are grouped with their sources
+    fn unit_path(&self, path: &Path, units: Option<&TranslationUnits>) -> PathBuf {
+        match units {
+            Some(units) if !self.no_flatten => PathBuf::from(units.unit(&path.to_string_lossy())),
+            _ => path.to_path_buf(),
         }
+    }
 
-        let base_dir = PathBuf::from(self.base_dir.as_ref().unwrap());
+    /// Plans the output of every source, or of their translation units
+    fn output_names(
+        &self,
+        naming: &PathNaming,
+        filepaths: &[PathBuf],
+        units: Option<&TranslationUnits>,
+        projects: &Projects,
+    ) -> OutputNames {
+        let sources: Vec<PathBuf> = filepaths
+            .iter()
+            .map(|path| self.unit_path(path, units))
+            .collect();
+        OutputNames::new(sources.iter().map(PathBuf::as_path), |source, stem| {
+            let output_path = if self.per_project_output {
+                self.output_path.join(projects.output_dir(source))
+            } else {
+                self.output_path.clone()
+            };
+            self.determine_output_path(&output_path, naming, source, &self.output_filename(stem))
+        })
+    }
 
-        let input_str = input_path.to_string_lossy().to_string();
-        let base_str = base_dir.to_string_lossy().to_string();
+    fn determine_output_path(
+        &self,
+        output_path: &Path,
+        naming: &PathNaming,
+        input_path: &Path,
+        output_filename: &str,
+    ) -> PathBuf {
+        if naming.mirrors() {
+            return output_path
+                .join(naming.output_dir(input_path))
+                .join(output_filename);
+        }
+        let Some(base_dir) = &self.base_dir else {
+            return output_path.join(output_filename);
+        };
 
-        if !input_str.starts_with(&base_str) {
+        // Compared by component, so separators and the case of drive letters don't matter
+        let Ok(relative_path) = input_path.strip_prefix(base_dir) else {
             debug!(
                 "File {} is not under base_dir {}, using root output directory",
                 input_path.display(),
-                base_dir.display()
+                base_dir
             );
-            return Ok(self.output_path.join(output_filename));
-        }
-
-        let relative_path = input_path.strip_prefix(&base_dir).map_err(|_| {
-            CliError::FailedProcessing(format!(
-                "Failed to strip prefix from path: {}",
-                input_path.display()
-            ))
-        })?;
+            return output_path.join(output_filename);
+        };
 
         let top_level_dir = relative_path
             .components()
             .next()
-            .map(|c| c.as_os_str().to_string_lossy().to_string());
+            .map(|c| paths::encode_name(c.as_os_str()));
 
         if let Some(dir) = top_level_dir {
-            let sub_output_path = self.output_path.join(dir);
+            let sub_output_path = output_path.join(dir);
 
-            Ok(sub_output_path.join(output_filename))
+            sub_output_path.join(output_filename)
         } else {
-            Ok(self.output_path.join(output_filename))
+            output_path.join(output_filename)
         }
     }
+}
 
-    // Helper function to check file extensions
-    fn has_valid_extension(path: &Path, extensions: &[String]) -> bool {
-        if let Some(extension) = path.extension() {
-            if let Some(ext_str) = extension.to_str() {
-                return extensions
-                    .iter()
-                    .any(|valid_ext| ext_str.eq_ignore_ascii_case(valid_ext));
-            }
-        }
-        false
-    }
+/// Local stand-ins for the output path and `--path` when they aren't directories
+struct Staging {
+    /// Scratch directory of the outputs of `--fmt github`, which only prints annotations
+    annotations: Option<TempDir>,
+    /// The PostgreSQL URL given as the output path, and the CSVs staged for it
+    database: Option<(String, TempDir)>,
+    /// The outputs staged for an output path in object storage, until they are handed to
+    /// the run
+    output: Option<Staged>,
 }
 
-enum MetricsType {
-    Extended(Vec<FlattenedMetricsExtended>),
-    Regular(Vec<FlattenedMetrics>),
+/// Input files extracted one after the other by a single worker, into a shared
+/// `batch-NNNNN` output with `--batch`. Unbatched files are batches of one writing their
+/// own output
+struct Batch<'a> {
+    path: Option<PathBuf>,
+    files: Vec<&'a PathBuf>,
 }
 
-pub trait ReplaceInfNan {
-    fn replace_inf_nan(&mut self);
+/// What the workers extracting the batches report back
+struct Progress<'a> {
+    /// Files left to extract, for the queue depth telemetry
+    remaining: AtomicUsize,
+    /// Outputs written, for the manifest
+    outputs: Mutex<Vec<PathBuf>>,
+    /// Batches cut short by an interrupt, with how many of their files were extracted
+    partial: Mutex<BTreeMap<PathBuf, usize>>,
+    /// Files still failing to read once retried, or taking longer than
+    /// `--timeout-per-file`, with why
+    quarantined: Mutex<Vec<(&'a PathBuf, String)>>,
 }
 
-impl ReplaceInfNan for f64 {
-    fn replace_inf_nan(&mut self) {
-        if self.is_infinite() || self.is_nan() {
-            *self = 0.0;
+impl Progress<'_> {
+    fn new(pending: usize) -> Self {
+        telemetry::queue_depth(pending);
+        Self {
+            remaining: AtomicUsize::new(pending),
+            outputs: Mutex::new(Vec::new()),
+            partial: Mutex::new(BTreeMap::new()),
+            quarantined: Mutex::new(Vec::new()),
         }
     }
 }
 
-// Flattended Structure
-#[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize, ReplaceInfNan)]
-pub struct FlattenedMetrics {
-    pub name: Option<String>,
-    pub source_file: Option<String>,
-    pub start_line: usize,
-    pub end_line: usize,
-    pub kind: String,
-    pub parent_name: Option<String>,
+/// How extracting a file turned out for its batch
+enum Settled {
+    /// Rows of the file went into the output of its batch
+    Written,
+    /// The file wrote nothing to the output of its batch
+    Empty,
+    /// The file failed to read or timed out, and is left out of the run
+    Quarantined,
+    /// The file couldn't be processed, e.g. its language couldn't be guessed
+    Failed,
+}
 
-    // NArgs
-    pub fn_args: f64,
-    pub closure_args: f64,
+/// State shared by every file of a run
+struct SourceRun {
+    /// The outputs staged for an output path in object storage, each uploaded once written
+    output: Option<Staged>,
+    /// `--path-style` and `--strip-prefix`
+    naming: PathNaming,
+    /// The output of every source, or of every translation unit
+    outputs: OutputNames,
+    reader: SourceReader,
+    units: Option<TranslationUnits>,
+    coupling: Coupling,
+    queries: Option<QuerySet>,
+    plugins: Vec<Box<dyn MetricPlugin>>,
+    /// Rows held back for `--normalize`, along with the file they are written to
+    deferred: Mutex<Vec<(PathBuf, Vec<MetricsRow>)>>,
+    split: Option<Split>,
+    labels: Option<Labels>,
+    advisories: Option<Advisories>,
+    owners: Option<CodeOwners>,
+    /// The project roots below --path
+    projects: Projects,
+    /// The `--bugfixes` commits of the history
+    bugfixes: Option<Bugfixes>,
+    budgets: Option<Budgets>,
+    /// Functions checked against `--fail-on` and `--budgets`
+    outcomes: Mutex<Vec<Outcome>>,
+    /// Timings of every file for `--profile`
+    profiles: Mutex<Vec<FileProfile>>,
+    /// How many files failed to process
+    failed: AtomicUsize,
+    /// Metric columns `--metrics` leaves out of the rows
+    deselected: HashSet<String>,
+    /// Metric columns of the rows, the ones `--normalize` scales
+    metric_columns: Vec<String>,
+}
 
-    // Exits
-    pub nexits: f64,
+impl SourceRun {
+    fn source_name(&self, path: &Path) -> String {
+        self.naming.display(path)
+    }
 
-    // Cognitive
-    pub cognitive: f64,
+    /// Starts uploading the finished output `path` when the outputs go to object storage
+    fn publish(&self, path: &Path) {
+        if let Some(output) = &self.output {
+            output.publish(path);
+        }
+    }
+}
 
-    // Cyclomatic
-    pub cyclomatic: f64,
+/// Parses a `--max-parse-error-ratio`, between 0 and 1
+fn parse_ratio(value: &str) -> Result<f64, String> {
+    let ratio: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid ratio {}", value))?;
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err(format!("the ratio must be between 0 and 1, got {}", value));
+    }
+    Ok(ratio)
+}
 
-    // Halstead
-    pub halstead_n1: f64,
-    pub halstead_N1: f64,
-    pub halstead_n2: f64,
-    pub halstead_N2: f64,
-    pub halstead_length: f64,
-    pub halstead_estimated_program_length: f64,
-    pub halstead_purity_ratio: f64,
-    pub halstead_vocabulary: f64,
-    pub halstead_volume: f64,
-    pub halstead_difficulty: f64,
-    pub halstead_level: f64,
-    pub halstead_effort: f64,
-    pub halstead_time: f64,
-    pub halstead_bugs: f64,
+/// Name of the file in the output path mapping every `--batch` output to its source files
+pub const BATCHES: &str = "batches.json";
+
+/// Writes the source files of every batch that has an output, returning where they went.
+/// Batches whose files had no functions are left out
+fn write_batches(
+    directory: &Path,
+    batches: &[(PathBuf, Vec<String>)],
+) -> Result<PathBuf, CliError> {
+    let mapping: BTreeMap<String, &Vec<String>> = batches
+        .iter()
+        .filter(|(output, _)| output.exists())
+        .map(|(output, files)| {
+            let name = output.file_name().unwrap_or_default();
+            (name.to_string_lossy().to_string(), files)
+        })
+        .collect();
+    let path = directory.join(BATCHES);
+    serde_json::to_writer_pretty(File::create(&path)?, &mapping)?;
+    Ok(path)
+}
 
-    // Loc
-    pub loc_sloc: f64,
-    pub loc_ploc: f64,
-    pub loc_lloc: f64,
-    pub loc_cloc: f64,
-    pub loc_blank: f64,
+/// Directory in the output path listing the files a run failed to read or timed out on
+pub const QUARANTINE: &str = "quarantine";
+
+/// Lists the files that failed to read or timed out in `quarantine/files.txt`, one per line, with why in
+/// `quarantine/reasons.tsv`, returning the directory. An earlier listing is cleared first,
+/// so it only ever covers the latest run
+fn write_quarantine(
+    directory: &Path,
+    quarantined: &[(&PathBuf, String)],
+) -> Result<Option<PathBuf>, CliError> {
+    let path = directory.join(QUARANTINE);
+    if path.exists() {
+        fs::remove_dir_all(&path)?;
+    }
+    if quarantined.is_empty() {
+        return Ok(None);
+    }
+    fs::create_dir_all(&path)?;
+    let mut files = BufWriter::new(File::create(path.join("files.txt"))?);
+    let mut reasons = BufWriter::new(File::create(path.join("reasons.tsv"))?);
+    for (fp, reason) in quarantined {
+        writeln!(files, "{}", fp.to_string_lossy())?;
+        writeln!(reasons, "{}\t{}", fp.to_string_lossy(), reason)?;
+    }
+    files.flush()?;
+    reasons.flush()?;
+    Ok(Some(path))
+}
 
-    // Nom
-    pub nom_functions: f64,
-    pub nom_closures: f64,
-    pub nom_total: f64,
+/// Name of the file in the output path listing every file `--dedup` collapsed, with the
+/// file it duplicates
+pub const DUPLICATES: &str = "duplicates.tsv";
 
-    // Mi
-    pub mi_original: f64,
-    pub mi_sei: f64,
-    pub mi_visual_studio: f64,
+/// Writes the files `--dedup` collapsed, returning where they went when there were any
+fn write_duplicates(directory: &Path, skipped: &[Skipped]) -> Result<Option<PathBuf>, CliError> {
+    let path = directory.join(DUPLICATES);
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    let duplicates: Vec<(&str, &str)> = skipped
+        .iter()
+        .filter_map(|skipped| {
+            Some((
+                skipped.source_file.as_str(),
+                skipped.duplicate_of.as_deref()?,
+            ))
+        })
+        .collect();
+    if duplicates.is_empty() {
+        return Ok(None);
+    }
+    let mut writer = BufWriter::new(File::create(&path)?);
+    for (duplicate, first) in duplicates {
+        writeln!(writer, "{}\t{}", duplicate, first)?;
+    }
+    writer.flush()?;
+    Ok(Some(path))
 }
 
-// Flattened Extended structure
-#[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize, ReplaceInfNan)]
-pub struct FlattenedMetricsExtended {
-    pub name: Option<String>,
-    pub source_file: Option<String>,
-    pub start_line: usize,
-    pub end_line: usize,
-    pub kind: String,
-    pub parent_name: Option<String>,
+/// An output rows are streamed to, only created once the first row arrives
+struct Output {
+    path: PathBuf,
+    json: bool,
+    stream: Option<RowStream<BufWriter<File>>>,
+}
+
+impl Output {
+    fn new(path: PathBuf, fmt: &str) -> Self {
+        Self {
+            path,
+            json: fmt == "json",
+            stream: None,
+        }
+    }
+
+    fn write(&mut self, row: &MetricsRow) -> Result<(), CliError> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                let file = BufWriter::new(File::create(&self.path)?);
+                self.stream.insert(if self.json {
+                    RowStream::json(file)
+                } else {
+                    RowStream::csv(file)
+                })
+            }
+        };
+        stream.write(row)
+    }
+
+    fn finish(self) -> Result<(), CliError> {
+        if let Some(stream) = self.stream {
+            stream.finish()?;
+            debug!("All saved to {}", self.path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Name of the log in the output path listing the source files a run has completed
+pub const COMPLETED_LOG: &str = ".archaeo-completed";
+
+/// Append-only record of the source files a run has completed, one path per line, so an
+/// interrupted run can be resumed
+struct CompletedLog {
+    completed: Mutex<HashSet<String>>,
+    file: Mutex<File>,
+}
+
+impl CompletedLog {
+    /// Opens the log at `path`, keeping its entries when resuming and starting afresh
+    /// otherwise
+    fn open(path: &Path, resume: bool) -> Result<Self, CliError> {
+        let completed = if resume && path.exists() {
+            fs::read_to_string(path)?
+                .lines()
+                .map(str::to_string)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(resume)
+            .write(true)
+            .truncate(!resume)
+            .open(path)?;
+
+        Ok(Self {
+            completed: Mutex::new(completed),
+            file: Mutex::new(file),
+        })
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        let completed = self.completed.lock().unwrap();
+        completed.contains(path.to_string_lossy().as_ref())
+    }
+
+    fn record(&self, path: &Path) -> Result<(), CliError> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", path.to_string_lossy())?;
+        file.flush()?;
+        let mut completed = self.completed.lock().unwrap();
+        completed.insert(path.to_string_lossy().to_string());
+        Ok(())
+    }
+}
+
+/// The language, decoded source, spaces and decoding of a file
+pub type FileSpaces = (LANG, Source, FuncSpace, Decoding);
+
+/// Reads `path`, guesses its language and computes the metrics of all its spaces.
+/// The language and source are handed back alongside the spaces for metrics computed
+/// from the text
+pub fn read_spaces(path: &Path, reader: &SourceReader) -> Result<Option<FileSpaces>, CliError> {
+    read_spaces_timed(path, reader).map(|(spaces, _)| spaces)
+}
+
+/// Like `read_spaces`, also timing the read, the parse and the metrics of the file
+pub fn read_spaces_timed(
+    path: &Path,
+    reader: &SourceReader,
+) -> Result<(Option<FileSpaces>, Timings), CliError> {
+    let started = Instant::now();
+    let read = reader.read_source(path)?;
+    let read_time = started.elapsed();
+    let (spaces, mut timings) = spaces_of(path, read);
+    timings.read = read_time;
+    Ok((spaces, timings))
+}
+
+/// How many parses given up on may run on in the background at once. Past it the run fails
+/// rather than leaving a thread behind for every file that hangs the parser
+pub const MAX_ABANDONED_PARSES: usize = 16;
+
+/// Parses given up on by `read_spaces_within` that are still running
+static ABANDONED_PARSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Like `read_spaces_timed`, giving up on `path` once `timeout` has passed. A parse can't
+/// be interrupted, so it is left to finish on a thread of its own, and once more than
+/// `MAX_ABANDONED_PARSES` of those are running the file fails with `TooManyTimeouts`
+pub fn read_spaces_within(
+    path: &Path,
+    reader: &SourceReader,
+    timeout: Duration,
+) -> Result<(Option<FileSpaces>, Timings), CliError> {
+    let (file, reader) = (path.to_path_buf(), reader.clone());
+    run_within(
+        path,
+        move || read_spaces_timed(&file, &reader),
+        timeout,
+        &ABANDONED_PARSES,
+        MAX_ABANDONED_PARSES,
+    )
+}
+
+/// Runs `work` for `path` on a thread of its own, giving up on it once `timeout` has
+/// passed. Work given up on is counted in `abandoned` until it finishes
+fn run_within<T: Send + 'static>(
+    path: &Path,
+    work: impl FnOnce() -> Result<T, CliError> + Send + 'static,
+    timeout: Duration,
+    abandoned: &'static AtomicUsize,
+    cap: usize,
+) -> Result<T, CliError> {
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    // Whether the work was given up on, deciding under the lock who counts it finishing
+    let given_up = Arc::new(Mutex::new(false));
+    thread::spawn({
+        let given_up = given_up.clone();
+        move || {
+            let result = work();
+            let given_up = given_up.lock().unwrap();
+            if *given_up {
+                abandoned.fetch_sub(1, Ordering::SeqCst);
+            } else {
+                let _ = sender.send(result);
+            }
+        }
+    });
+    let file = || path.to_string_lossy().to_string();
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            let mut given_up = given_up.lock().unwrap();
+            // The work may have finished since the timeout
+            if let Ok(result) = receiver.try_recv() {
+                return result;
+            }
+            *given_up = true;
+            if abandoned.fetch_add(1, Ordering::SeqCst) >= cap {
+                return Err(CliError::TooManyTimeouts(cap));
+            }
+            Err(CliError::TimedOut(file(), timeout.as_secs()))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(CliError::FailedProcessing(file())),
+    }
+}
+
+/// Like `read_spaces`, for the contents of `path` held in memory
+pub fn read_spaces_from(
+    path: &Path,
+    bytes: &[u8],
+    reader: &SourceReader,
+) -> Result<Option<FileSpaces>, CliError> {
+    let (language, source, decoding) = reader.decode_bytes(path, bytes.to_vec())?;
+    Ok(spaces_of(path, (language, Source::Owned(source), decoding)).0)
+}
+
+fn spaces_of(
+    path: &Path,
+    (language, source, decoding): (LANG, Source, Decoding),
+) -> (Option<FileSpaces>, Timings) {
+    debug!("Source: {:?} bytes Language: {:?}", source.len(), language);
+
+    // rust-code-analysis takes the source it parses, so it gets a copy of its own
+    let started = Instant::now();
+    let (space, parsed) =
+        action::<TimedSpaces>(&language, source.to_vec(), path, None, path.to_path_buf());
+    let timings = Timings {
+        read: Duration::ZERO,
+        parse: parsed.duration_since(started),
+        metrics: parsed.elapsed(),
+    };
+    (
+        space.map(|space| (language, source, space, decoding)),
+        timings,
+    )
+}
+
+/// Computes the spaces of a file like `get_function_spaces`, also handing back when its
+/// parse finished, just before the metrics are computed
+struct TimedSpaces;
+
+impl Callback for TimedSpaces {
+    type Res = (Option<FuncSpace>, Instant);
+    type Cfg = PathBuf;
+
+    fn call<T: ParserTrait>(path: Self::Cfg, parser: &T) -> Self::Res {
+        let parsed = Instant::now();
+        (rust_code_analysis::metrics(parser, &path), parsed)
+    }
+}
+
+/// State shared by every space flattened out of a single file
+pub struct FileContext<'a> {
+    pub source_file: String,
+    /// Length in characters of every line of the file
+    pub line_lengths: Vec<usize>,
+    pub coupling: &'a Coupling,
+    /// Matches of the user supplied `--query` captures, empty when none were given
+    pub captures: Captures,
+    /// The `--plugin` metrics to compute for every space
+    pub plugins: Option<PluginRun<'a>>,
+    /// The `--split` to assign every space to
+    pub split: Option<&'a Split>,
+    /// The `--labels` to merge into every space
+    pub labels: Option<&'a Labels>,
+    /// The `--advisories` to annotate every space with
+    pub advisories: Option<&'a Advisories>,
+    /// Lines of the file, when the source text of every space should be emitted
+    pub source_lines: Option<Vec<String>>,
+    /// The binary decompiled output was produced from
+    pub binary: Option<String>,
+    /// The translation unit the file belongs to
+    pub translation_unit: Option<String>,
+    /// The `--codeowners` owners of the file, `Some(None)` when no rule owns it
+    pub owner: Option<Option<String>>,
+    /// The project the file belongs to, `Some(None)` when it's outside of any
+    pub project: Option<Option<String>>,
+    /// The `--bugfixes` commits touching the file
+    pub bugfixes: Option<FileBugfixes<'a>>,
+    /// Functions left out of the output by `--drop-suppressed`
+    pub dropped: Option<Suppressions>,
+    /// How the file was decoded, reported in `file_encoding` and `had_decode_errors`
+    pub decoding: Option<Decoding>,
+    /// The language the file was parsed as, reported in `language` and `grammar_version`
+    pub language: Option<LANG>,
+    /// What becomes of functions without a name
+    pub anonymous: AnonymousNaming,
+    /// The functions of the file in its syntax tree, giving anonymous ones their column and
+    /// every one a `signature`
+    pub functions: Option<FunctionNodes>,
+    /// Fold the specializations of C++ function templates into the row of the template
+    pub aggregate_specializations: bool,
+    /// Leave out the functions with more of their bytes in parse errors than this
+    pub max_parse_error_ratio: Option<f64>,
+    /// The `metrics_supported` bitmask of the language of the file, when the metrics it
+    /// doesn't define are masked to null
+    pub supported: Option<u32>,
+    /// What non-finite metrics are written as, the `#[replace_with]` of their field when
+    /// not given
+    pub non_finite: Option<NonFinite>,
+}
+
+/// What the context of every file of a run is built from, loaded once for the run. Shared
+/// by `archaeo source` and the library, so both write the same rows for a file
+pub struct ContextSettings<'a> {
+    /// Names the files of the run in the `source_file` and `translation_unit` columns
+    pub name: &'a (dyn Fn(&Path) -> String + Sync),
+    pub mask_unsupported: bool,
+    pub non_finite: Option<NonFinite>,
+    pub anonymous: AnonymousNaming,
+    pub aggregate_specializations: bool,
+    pub max_parse_error_ratio: Option<f64>,
+    /// The files are decompiler pseudo-C, recording their binary in a `binary` column
+    pub decompiled: bool,
+    /// Emit the source text of every space in a `source` column
+    pub with_source: bool,
+    /// Don't honor `archaeo:ignore` comments
+    pub no_suppressions: bool,
+    /// Leave the functions suppressed by `archaeo:ignore` comments out of the rows
+    pub drop_suppressed: bool,
+    pub queries: Option<&'a QuerySet>,
+    pub plugins: &'a [Box<dyn MetricPlugin>],
+    pub units: Option<&'a TranslationUnits>,
+    pub owners: Option<&'a CodeOwners>,
+    pub projects: &'a Projects,
+    pub bugfixes: Option<&'a Bugfixes>,
+    /// The revision the files are read from, naming them in the `bugfixes` repository
+    pub tree: Option<&'a dyn SourceTree>,
+    pub split: Option<&'a Split>,
+    pub labels: Option<&'a Labels>,
+    pub advisories: Option<&'a Advisories>,
+}
+
+impl<'a> ContextSettings<'a> {
+    /// The context of the `spaces` of `path`, along with the functions suppressed in it
+    pub fn context(
+        &self,
+        path: &Path,
+        (language, source, space, decoding): &'a FileSpaces,
+        coupling: &'a Coupling,
+    ) -> (FileContext<'a>, Suppressions) {
+        let mut context = FileContext::new((self.name)(path), source, coupling);
+        context.decoding = Some(decoding.clone());
+        context.language = Some(*language);
+        if self.mask_unsupported {
+            context.mask_unsupported(language);
+        }
+        context.non_finite = self.non_finite;
+        context.anonymous = self.anonymous;
+        context.functions = Some(FunctionNodes::new(language, source));
+        context.aggregate_specializations = self.aggregate_specializations;
+        context.max_parse_error_ratio = self.max_parse_error_ratio;
+        if self.decompiled {
+            context.binary = Some(decompiled::binary_name(path));
+        }
+        if let Some(units) = self.units {
+            let file = path.to_string_lossy();
+            context.translation_unit = Some((self.name)(Path::new(units.unit(&file))));
+        }
+        context.owner = self.owners.map(|owners| owners.owner(path));
+        context.project = Some(self.projects.project(path));
+        if let Some(bugfixes) = self.bugfixes {
+            context.bugfixes = Some(bugfixes.file(repo_path(self.tree, bugfixes.root(), path)));
+        }
+        let suppressions = if self.no_suppressions {
+            Suppressions::default()
+        } else {
+            Suppressions::new(source, space)
+        };
+        if self.drop_suppressed && !suppressions.is_empty() {
+            context.dropped = Some(suppressions.clone());
+        }
+        context.split = self.split;
+        context.labels = self.labels;
+        context.advisories = self.advisories;
+        if let Some(queries) = self.queries {
+            context.captures = queries.captures(language, source);
+        }
+        if !self.plugins.is_empty() {
+            context.plugins = PluginRun::new(self.plugins, *language, source);
+            if context.plugins.is_none() {
+                warn!(file = %path.display(), "Failed to run plugins");
+            }
+        }
+        if self.with_source {
+            context.with_source(source);
+        }
+        (context, suppressions)
+    }
+}
+
+/// Path of `file` relative to the repository at `root`, as git names it, or as the `tree`
+/// read names it
+pub fn repo_path(tree: Option<&dyn SourceTree>, root: &Path, file: &Path) -> String {
+    match tree {
+        Some(tree) => paths::slashed(tree.relative(file)),
+        None => git::relative(&file.canonicalize().unwrap_or(file.to_path_buf()), root),
+    }
+}
+
+/// Line length statistics over a range of lines
+#[derive(Debug, Default)]
+pub struct LineLengths {
+    pub max: f64,
+    pub avg: f64,
+    pub over_120: f64,
+}
+
+impl<'a> FileContext<'a> {
+    pub fn new(source_file: String, source: &[u8], coupling: &'a Coupling) -> Self {
+        let line_lengths = String::from_utf8_lossy(source)
+            .lines()
+            .map(|line| line.chars().count())
+            .collect();
+
+        Self {
+            source_file,
+            line_lengths,
+            coupling,
+            captures: Captures::default(),
+            plugins: None,
+            split: None,
+            labels: None,
+            advisories: None,
+            source_lines: None,
+            binary: None,
+            translation_unit: None,
+            owner: None,
+            project: None,
+            bugfixes: None,
+            dropped: None,
+            decoding: None,
+            language: None,
+            anonymous: AnonymousNaming::Synthetic,
+            functions: None,
+            aggregate_specializations: false,
+            max_parse_error_ratio: None,
+            supported: None,
+            non_finite: None,
+        }
+    }
+
+    /// Masks the metrics `language` doesn't define to null rather than 0.0
+    pub fn mask_unsupported(&mut self, language: &LANG) {
+        self.supported = Some(availability::supported(language));
+    }
+
+    /// `value`, or null when the metrics it is computed from are masked
+    pub fn measure(&self, metrics: &[Metric], value: f64) -> Option<f64> {
+        let defined = self
+            .supported
+            .is_none_or(|supported| metrics.iter().all(|metric| supported & metric.bit() != 0));
+        defined.then_some(value)
+    }
+
+    /// Emit the source text of every space in a `source` column
+    pub fn with_source(&mut self, source: &[u8]) {
+        self.source_lines = Some(
+            String::from_utf8_lossy(source)
+                .split_inclusive('\n')
+                .map(str::to_string)
+                .collect(),
+        );
+    }
+
+    /// Dataset columns attached to a space, such as its signature, split and labels
+    pub fn annotations(
+        &self,
+        space: &FuncSpace,
+        identity: &SpaceIdentity,
+    ) -> BTreeMap<String, Value> {
+        let name = identity.name.as_deref();
+        let mut annotations = BTreeMap::new();
+        annotations.insert("qualified_name".to_string(), json!(identity.qualified_name));
+        let (parse_errors, parse_error_ratio) = self
+            .functions
+            .as_ref()
+            .and_then(FunctionNodes::parse_errors)
+            .map(|errors| errors.between(space.start_line, space.end_line))
+            .unzip();
+        let quality = Quality::of(parse_errors, self.decoding.as_ref());
+        annotations.insert("quality".to_string(), json!(quality.name()));
+        if let Some(functions) = &self.functions {
+            annotations.insert("signature".to_string(), json!(identity.signature));
+            let errors = functions.parse_errors();
+            annotations.insert("parse_errors".to_string(), json!(parse_errors));
+            annotations.insert("parse_error_ratio".to_string(), json!(parse_error_ratio));
+            let (count, ratio) = errors.map(ParseErrors::file).unzip();
+            annotations.insert("file_parse_errors".to_string(), json!(count));
+            annotations.insert("file_parse_error_ratio".to_string(), json!(ratio));
+        }
+        if self.aggregate_specializations {
+            annotations.insert(
+                "specializations".to_string(),
+                json!(identity.specializations),
+            );
+        }
+        if let Some(binary) = &self.binary {
+            annotations.insert("binary".to_string(), json!(binary));
+        }
+        if let Some(decoding) = &self.decoding {
+            annotations.insert("file_encoding".to_string(), json!(decoding.encoding));
+            annotations.insert("had_decode_errors".to_string(), json!(decoding.had_errors));
+            annotations.insert(
+                "language_resolution".to_string(),
+                json!(decoding.choice.resolution.name()),
+            );
+            annotations.insert(
+                "language_candidates".to_string(),
+                json!(decoding.choice.candidates.join(",")),
+            );
+        }
+        if let Some(language) = &self.language {
+            let choice = self.decoding.as_ref().map(|decoding| &decoding.choice);
+            annotations.insert(
+                "language".to_string(),
+                json!(choice.map_or(language.get_name(), |choice| choice.name(language))),
+            );
+            annotations.insert(
+                "grammar_version".to_string(),
+                json!(build_info::grammar_version(language)),
+            );
+        }
+        if let Some(unit) = &self.translation_unit {
+            annotations.insert("translation_unit".to_string(), json!(unit));
+        }
+        if let Some(owner) = &self.owner {
+            annotations.insert("owner".to_string(), json!(owner));
+        }
+        if let Some(project) = &self.project {
+            annotations.insert("project".to_string(), json!(project));
+        }
+        if let Some(bugfixes) = &self.bugfixes {
+            let fixes = bugfixes.between(space.start_line, space.end_line);
+            annotations.insert("bugfix_count".to_string(), json!(fixes.fixes));
+            if let Some(inducing) = fixes.inducing {
+                annotations.insert("bug_inducing_changes".to_string(), json!(inducing));
+            }
+            annotations.insert("file_bugfix_count".to_string(), json!(bugfixes.count()));
+        }
+        if let Some(supported) = self.supported {
+            annotations.insert("metrics_supported".to_string(), json!(supported));
+        }
+        if let Some(split) = self.split {
+            annotations.insert(
+                "split".to_string(),
+                json!(split.assign(&self.source_file, name, space.start_line)),
+            );
+        }
+        if let Some(labels) = self.labels {
+            annotations.extend(labels.lookup(
+                &self.source_file,
+                name,
+                identity.signature.as_deref(),
+                space.start_line,
+            ));
+        }
+        if let Some(advisories) = self.advisories {
+            let ids = advisories.lookup(&self.source_file, name);
+            annotations.insert("advisory_count".to_string(), json!(ids.len()));
+            annotations.insert(
+                "advisories".to_string(),
+                json!(Some(ids.join(",")).filter(|ids| !ids.is_empty())),
+            );
+        }
+        if let Some(lines) = &self.source_lines {
+            let start = (space.start_line.max(1) - 1).min(lines.len());
+            let end = space.end_line.min(lines.len()).max(start);
+            annotations.insert("source".to_string(), json!(lines[start..end].concat()));
+        }
+        annotations
+    }
+
+    /// Query capture counts and plugin metrics of a space
+    pub fn extra_columns(&self, space: &FuncSpace) -> BTreeMap<String, f64> {
+        let mut columns: BTreeMap<String, f64> = self
+            .captures
+            .counts(space.start_line, space.end_line)
+            .into_iter()
+            .collect();
+        if let Some(plugins) = &self.plugins {
+            columns.extend(plugins.compute(space));
+        }
+        columns
+    }
+
+    /// Whether the plugins and suppressions let `space` through to the output
+    pub fn keep(&self, space: &FuncSpace) -> bool {
+        let dropped = self
+            .dropped
+            .as_ref()
+            .is_some_and(|dropped| dropped.covers(space.start_line, space.end_line));
+        !dropped
+            && self
+                .plugins
+                .as_ref()
+                .is_none_or(|plugins| plugins.keep(space))
+    }
+
+    /// Statistics for the 1-based, inclusive line range of a space
+    pub fn line_lengths(&self, start_line: usize, end_line: usize) -> LineLengths {
+        let start = start_line.max(1) - 1;
+        let end = end_line.min(self.line_lengths.len());
+        if start >= end {
+            return LineLengths::default();
+        }
+
+        let lines = &self.line_lengths[start..end];
+        LineLengths {
+            max: *lines.iter().max().unwrap_or(&0) as f64,
+            avg: lines.iter().sum::<usize>() as f64 / lines.len() as f64,
+            over_120: lines.iter().filter(|len| **len > 120).count() as f64,
+        }
+    }
+}
+
+/// What non-finite values, from divisions by zero and logarithms of zero, are written as
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum NonFinite {
+    Zero,
+    /// A number no metric takes, like -1
+    Sentinel(f64),
+    /// An empty CSV cell or JSON null
+    Null,
+    /// `NaN`, `inf` or `-inf` as text, as JSON has no numbers for them
+    Raw,
+}
+
+impl FromStr for NonFinite {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "zero" => Ok(NonFinite::Zero),
+            "null" => Ok(NonFinite::Null),
+            "raw" => Ok(NonFinite::Raw),
+            _ => match value.parse::<f64>() {
+                Ok(sentinel) if sentinel.is_finite() => Ok(NonFinite::Sentinel(sentinel)),
+                _ => Err(format!(
+                    "expected zero, null, raw or a sentinel number, got {}",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for NonFinite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NonFinite::Zero => write!(f, "zero"),
+            NonFinite::Sentinel(sentinel) => write!(f, "{}", sentinel),
+            NonFinite::Null => write!(f, "null"),
+            NonFinite::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+impl From<NonFinite> for String {
+    fn from(policy: NonFinite) -> Self {
+        policy.to_string()
+    }
+}
+
+impl TryFrom<String> for NonFinite {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+pub trait ReplaceInfNan {
+    /// Applies `policy` to every non-finite field, or the `#[replace_with]` policy of the
+    /// field when none is given, returning the columns and values of the ones kept raw
+    fn replace_inf_nan(&mut self, policy: Option<NonFinite>) -> Vec<(&'static str, f64)>;
+}
+
+impl<T: ReplaceInfNan> ReplaceInfNan for Option<T> {
+    fn replace_inf_nan(&mut self, policy: Option<NonFinite>) -> Vec<(&'static str, f64)> {
+        self.as_mut()
+            .map(|value| value.replace_inf_nan(policy))
+            .unwrap_or_default()
+    }
+}
+
+impl<T: ReplaceInfNan> ReplaceInfNan for Vec<T> {
+    fn replace_inf_nan(&mut self, policy: Option<NonFinite>) -> Vec<(&'static str, f64)> {
+        self.iter_mut()
+            .flat_map(|value| value.replace_inf_nan(policy))
+            .collect()
+    }
+}
+
+/// A metric value a `NonFinite` policy applies to
+pub trait Finite {
+    fn is_finite(&self) -> bool;
+
+    /// Replaces the value if it is non-finite, handing it back when `policy` keeps it raw
+    fn replace_non_finite(&mut self, policy: NonFinite) -> Option<f64>;
+}
+
+impl Finite for f64 {
+    fn is_finite(&self) -> bool {
+        f64::is_finite(*self)
+    }
+
+    fn replace_non_finite(&mut self, policy: NonFinite) -> Option<f64> {
+        if Finite::is_finite(self) {
+            return None;
+        }
+        match policy {
+            NonFinite::Zero => *self = 0.0,
+            NonFinite::Sentinel(sentinel) => *self = sentinel,
+            // serde_json writes non-finite numbers out as null
+            NonFinite::Null => *self = f64::NAN,
+            NonFinite::Raw => return Some(*self),
+        }
+        None
+    }
+}
+
+impl Finite for f32 {
+    fn is_finite(&self) -> bool {
+        f32::is_finite(*self)
+    }
+
+    fn replace_non_finite(&mut self, policy: NonFinite) -> Option<f64> {
+        let mut value = *self as f64;
+        let kept = value.replace_non_finite(policy);
+        *self = value as f32;
+        kept
+    }
+}
+
+impl<T: Finite> Finite for Option<T> {
+    fn is_finite(&self) -> bool {
+        self.as_ref().is_none_or(Finite::is_finite)
+    }
+
+    fn replace_non_finite(&mut self, policy: NonFinite) -> Option<f64> {
+        match self {
+            Some(value) if !value.is_finite() && policy == NonFinite::Null => {
+                *self = None;
+                None
+            }
+            Some(value) => value.replace_non_finite(policy),
+            None => None,
+        }
+    }
+}
+
+/// Raw elements stay non-finite, so come out as null rather than text
+impl<T: Finite> Finite for Vec<T> {
+    fn is_finite(&self) -> bool {
+        self.iter().all(Finite::is_finite)
+    }
+
+    fn replace_non_finite(&mut self, policy: NonFinite) -> Option<f64> {
+        for value in self.iter_mut() {
+            value.replace_non_finite(policy);
+        }
+        None
+    }
+}
+
+/// Generates `FlattenedMetrics` and `FlattenedMetricsExtended`, and their `from_space`, from
+/// one table of metric columns in output order, so adding a metric is a one-line change.
+///
+/// The table opens with `|space, context| { .. }`, naming the arguments of `from_space` and
+/// binding whatever the entries share. Every entry after it is a column of both structs, or
+/// of the extended one alone when it starts with `extended`. `name: [Families] value` is an
+/// `Option<f64>`, nulled by `FileContext::measure` when a language doesn't define one of the
+/// families, and `name = value` an `f64` taken as is. Attributes are kept on the field
+macro_rules! flattened_metrics {
+    (|$space:ident, $context:ident| $setup:tt $($table:tt)*) => {
+        flattened_metrics!(@split [$space $context] $setup [] [] $($table)*);
+    };
+
+    // Sort the entries into the columns of each struct
+    (@split [$space:ident $context:ident] $setup:tt [$($regular:tt)*] [$($extended:tt)*]
+        $(#[$attr:meta])* extended $field:ident: [$($family:ident),+] $value:expr, $($rest:tt)*) => {
+        flattened_metrics!(@split [$space $context] $setup [$($regular)*] [$($extended)*
+            { $(#[$attr])* $field: Option<f64> = $context.measure(&[$(Metric::$family),+], $value) }
+        ] $($rest)*);
+    };
+    (@split [$space:ident $context:ident] $setup:tt [$($regular:tt)*] [$($extended:tt)*]
+        $(#[$attr:meta])* extended $field:ident = $value:expr, $($rest:tt)*) => {
+        flattened_metrics!(@split [$space $context] $setup [$($regular)*] [$($extended)*
+            { $(#[$attr])* $field: f64 = $value }
+        ] $($rest)*);
+    };
+    (@split [$space:ident $context:ident] $setup:tt [$($regular:tt)*] [$($extended:tt)*]
+        $(#[$attr:meta])* $field:ident: [$($family:ident),+] $value:expr, $($rest:tt)*) => {
+        flattened_metrics!(@split [$space $context] $setup [$($regular)*
+            { $(#[$attr])* $field: Option<f64> = $context.measure(&[$(Metric::$family),+], $value) }
+        ] [$($extended)*
+            { $(#[$attr])* $field: Option<f64> = $context.measure(&[$(Metric::$family),+], $value) }
+        ] $($rest)*);
+    };
+    (@split [$space:ident $context:ident] $setup:tt [$($regular:tt)*] [$($extended:tt)*]
+        $(#[$attr:meta])* $field:ident = $value:expr, $($rest:tt)*) => {
+        flattened_metrics!(@split [$space $context] $setup [$($regular)*
+            { $(#[$attr])* $field: f64 = $value }
+        ] [$($extended)*
+            { $(#[$attr])* $field: f64 = $value }
+        ] $($rest)*);
+    };
+    (@split $arguments:tt $setup:tt [$($regular:tt)*] [$($extended:tt)*]) => {
+        flattened_metrics!(@struct FlattenedMetrics $arguments $setup $($regular)*);
+        flattened_metrics!(@struct FlattenedMetricsExtended $arguments $setup $($extended)*);
+    };
+
+    (@struct $name:ident [$space:ident $context:ident] { $($setup:tt)* }
+        $({ $(#[$attr:meta])* $field:ident: $ty:ty = $value:expr })*) => {
+        #[allow(non_snake_case)]
+        #[derive(Debug, Serialize, Deserialize, ReplaceInfNan, MetricSchema)]
+        pub struct $name {
+            #[metric(identifier)]
+            #[replace_inf_nan(skip)]
+            pub name: Option<String>,
+            #[metric(identifier)]
+            #[replace_inf_nan(skip)]
+            pub source_file: Option<String>,
+            #[metric(identifier)]
+            #[replace_inf_nan(skip)]
+            pub start_line: usize,
+            #[metric(identifier)]
+            #[replace_inf_nan(skip)]
+            pub end_line: usize,
+            #[metric(identifier)]
+            #[replace_inf_nan(skip)]
+            pub kind: String,
+            #[metric(identifier)]
+            #[replace_inf_nan(skip)]
+            pub parent_name: Option<String>,
+
+            $($(#[$attr])* pub $field: $ty,)*
+
+            // Custom query captures and plugins
+            #[serde(flatten)]
+            #[replace_inf_nan(skip)]
+            pub extra: BTreeMap<String, f64>,
+
+            // Dataset annotations
+            #[serde(flatten)]
+            #[replace_inf_nan(skip)]
+            pub annotations: BTreeMap<String, Value>,
+        }
+
+        impl $name {
+            pub fn from_space(
+                $space: &FuncSpace,
+                identity: SpaceIdentity,
+                $context: &FileContext,
+            ) -> Self {
+                $($setup)*
+
+                let annotations = $context.annotations($space, &identity);
+                let mut obj = Self {
+                    name: identity.name.clone(),
+                    source_file: Some($context.source_file.clone()),
+                    start_line: $space.start_line,
+                    end_line: $space.end_line,
+                    kind: identity.kind.clone(),
+                    parent_name: identity.name,
+                    $($field: $value,)*
+                    extra: $context.extra_columns($space),
+                    annotations,
+                };
+
+                // Scan through struct members and replace nan/inf's, with 0.0 unless
+                // --non-finite says otherwise. Raw ones go out as text, overriding their
+                // column in place
+                for (column, value) in obj.replace_inf_nan($context.non_finite) {
+                    obj.annotations
+                        .insert(column.to_string(), json!(value.to_string()));
+                }
+
+                obj
+            }
+        }
+    };
+}
+
+// Flattened structures, the extended one with the `_sum`, `_average`, `_min` and `_max` of
+// every family across the nested spaces too
+flattened_metrics! {
+    |space, context| {
+        let (fan_in, fan_out) = space_coupling(space, context);
+        let line_lengths = context.line_lengths(space.start_line, space.end_line);
+    }
 
     // NArgs
-    pub fn_args: f64,
-    pub closure_args: f64,
-    pub nargs_total_functions: f64,
-    pub nargs_total_closures: f64,
-    pub nargs_average_functions: f64,
-    pub nargs_average_closures: f64,
-    pub nargs_total: f64,
-    pub nargs_average: f64,
-    pub nargs_functions_min: f64,
-    pub nargs_functions_max: f64,
-    pub nargs_closures_min: f64,
-    pub nargs_closures_max: f64,
+    #[metric(family = "nargs")]
+    fn_args: [NArgs] space.metrics.nargs.fn_args(),
+    #[metric(family = "nargs")]
+    closure_args: [NArgs] space.metrics.nargs.closure_args(),
+    extended nargs_total_functions: [NArgs] space.metrics.nargs.fn_args_sum(),
+    extended nargs_total_closures: [NArgs] space.metrics.nargs.closure_args_sum(),
+    extended nargs_average_functions: [NArgs] space.metrics.nargs.fn_args_average(),
+    extended nargs_average_closures: [NArgs] space.metrics.nargs.closure_args_average(),
+    extended nargs_total: [NArgs] space.metrics.nargs.nargs_total(),
+    extended nargs_average: [NArgs] space.metrics.nargs.nargs_average(),
+    extended nargs_functions_min: [NArgs] space.metrics.nargs.fn_args_min(),
+    extended nargs_functions_max: [NArgs] space.metrics.nargs.fn_args_max(),
+    extended nargs_closures_min: [NArgs] space.metrics.nargs.closure_args_min(),
+    extended nargs_closures_max: [NArgs] space.metrics.nargs.closure_args_max(),
 
     // Exits
-    pub nexits: f64,
-    pub nexits_sum: f64,
-    pub nexits_average: f64,
-    pub nexits_min: f64,
-    pub nexits_max: f64,
+    nexits: [Exits] space.metrics.nexits.exit(),
+    extended nexits_sum: [Exits] space.metrics.nexits.exit_sum(),
+    extended nexits_average: [Exits] space.metrics.nexits.exit_average(),
+    extended nexits_min: [Exits] space.metrics.nexits.exit_min(),
+    extended nexits_max: [Exits] space.metrics.nexits.exit_max(),
 
     // Cognitive
-    pub cognitive: f64,
-    pub cognitive_sum: f64,
-    pub cognitive_average: f64,
-    pub cognitive_min: f64,
-    pub cognitive_max: f64,
+    cognitive: [Cognitive] space.metrics.cognitive.cognitive(),
+    extended cognitive_sum: [Cognitive] space.metrics.cognitive.cognitive_sum(),
+    extended cognitive_average: [Cognitive] space.metrics.cognitive.cognitive_average(),
+    extended cognitive_min: [Cognitive] space.metrics.cognitive.cognitive_min(),
+    extended cognitive_max: [Cognitive] space.metrics.cognitive.cognitive_max(),
 
     // Cyclomatic
-    pub cyclomatic: f64,
-    pub cyclomatic_sum: f64,
-    pub cyclomatic_average: f64,
-    pub cyclomatic_min: f64,
-    pub cyclomatic_max: f64,
+    cyclomatic: [Cyclomatic] space.metrics.cyclomatic.cyclomatic(),
+    extended cyclomatic_sum: [Cyclomatic] space.metrics.cyclomatic.cyclomatic_sum(),
+    extended cyclomatic_average: [Cyclomatic] space.metrics.cyclomatic.cyclomatic_average(),
+    extended cyclomatic_min: [Cyclomatic] space.metrics.cyclomatic.cyclomatic_min(),
+    extended cyclomatic_max: [Cyclomatic] space.metrics.cyclomatic.cyclomatic_max(),
+
+    // Coupling
+    #[metric(family = "coupling")]
+    fan_in = fan_in,
+    #[metric(family = "coupling")]
+    fan_out = fan_out,
 
     // Halstead
-    pub halstead_n1: f64,
-    pub halstead_N1: f64,
-    pub halstead_n2: f64,
-    pub halstead_N2: f64,
-    pub halstead_length: f64,
-    pub halstead_estimated_program_length: f64,
-    pub halstead_purity_ratio: f64,
-    pub halstead_vocabulary: f64,
-    pub halstead_volume: f64,
-    pub halstead_difficulty: f64,
-    pub halstead_level: f64,
-    pub halstead_effort: f64,
-    pub halstead_time: f64,
-    pub halstead_bugs: f64,
+    halstead_n1: [Halstead] space.metrics.halstead.u_operators(),
+    halstead_N1: [Halstead] space.metrics.halstead.operators(),
+    halstead_n2: [Halstead] space.metrics.halstead.u_operands(),
+    halstead_N2: [Halstead] space.metrics.halstead.operands(),
+    halstead_length: [Halstead] space.metrics.halstead.length(),
+    halstead_estimated_program_length: [Halstead] space.metrics.halstead.estimated_program_length(),
+    halstead_purity_ratio: [Halstead] space.metrics.halstead.purity_ratio(),
+    halstead_vocabulary: [Halstead] space.metrics.halstead.vocabulary(),
+    halstead_volume: [Halstead] space.metrics.halstead.volume(),
+    halstead_difficulty: [Halstead] space.metrics.halstead.difficulty(),
+    halstead_level: [Halstead] space.metrics.halstead.level(),
+    halstead_effort: [Halstead] space.metrics.halstead.effort(),
+    halstead_time: [Halstead] space.metrics.halstead.time(),
+    halstead_bugs: [Halstead] space.metrics.halstead.bugs(),
 
     // Loc
-    pub loc_sloc: f64,
-    pub loc_ploc: f64,
-    pub loc_lloc: f64,
-    pub loc_cloc: f64,
-    pub loc_blank: f64,
+    loc_sloc: [Loc] space.metrics.loc.sloc(),
+    loc_ploc: [Loc] space.metrics.loc.ploc(),
+    loc_lloc: [Loc] space.metrics.loc.lloc(),
+    loc_cloc: [Loc] space.metrics.loc.cloc(),
+    loc_blank: [Loc] space.metrics.loc.blank(),
+
+    // Line length
+    #[metric(family = "line_length")]
+    line_len_max = line_lengths.max,
+    #[metric(family = "line_length")]
+    line_len_avg = line_lengths.avg,
+    #[metric(family = "line_length")]
+    lines_over_120 = line_lengths.over_120,
 
     // Nom
-    pub nom_functions: f64,
-    pub nom_closures: f64,
-    pub nom_total: f64,
-    pub nom_functions_min: f64,
-    pub nom_functions_max: f64,
-    pub nom_closures_min: f64,
-    pub nom_closures_max: f64,
+    nom_functions: [Nom] space.metrics.nom.functions(),
+    nom_closures: [Nom] space.metrics.nom.closures(),
+    nom_total: [Nom] space.metrics.nom.total(),
+    extended nom_functions_min: [Nom] space.metrics.nom.functions_min(),
+    extended nom_functions_max: [Nom] space.metrics.nom.functions_max(),
+    extended nom_closures_min: [Nom] space.metrics.nom.closures_min(),
+    extended nom_closures_max: [Nom] space.metrics.nom.closures_max(),
 
     // Mi
-    pub mi_original: f64,
-    pub mi_sei: f64,
-    pub mi_visual_studio: f64,
+    mi_original: [Mi] space.metrics.mi.mi_original(),
+    mi_sei: [Mi] space.metrics.mi.mi_sei(),
+    mi_visual_studio: [Mi] space.metrics.mi.mi_visual_studio(),
+
+    // Density, zero for empty spaces unless --non-finite says otherwise
+    #[replace_with(zero)]
+    #[metric(family = "density")]
+    cyclomatic_per_sloc: [Cyclomatic, Loc]
+        space.metrics.cyclomatic.cyclomatic() / space.metrics.loc.sloc(),
+    #[replace_with(zero)]
+    #[metric(family = "density")]
+    cognitive_per_sloc: [Cognitive, Loc]
+        space.metrics.cognitive.cognitive() / space.metrics.loc.sloc(),
+    #[replace_with(zero)]
+    #[metric(family = "density")]
+    halstead_volume_per_lloc: [Halstead, Loc]
+        space.metrics.halstead.volume() / space.metrics.loc.lloc(),
 }
 
-impl FlattenedMetricsExtended {
-    pub fn from_space(
-        space: &FuncSpace,
-        parent_name: Option<String>,
-        source_file: Option<String>,
-    ) -> Self {
-        let mut obj = Self {
-            name: space.name.clone(),
-            source_file,
-            start_line: space.start_line,
-            end_line: space.end_line,
-            kind: space.kind.clone().to_string(),
-            parent_name,
-
-            // NArgs
-            fn_args: space.metrics.nargs.fn_args(),
-            closure_args: space.metrics.nargs.closure_args(),
-            nargs_total_functions: space.metrics.nargs.fn_args_sum(),
-            nargs_total_closures: space.metrics.nargs.closure_args_sum(),
-            nargs_average_functions: space.metrics.nargs.fn_args_average(),
-            nargs_average_closures: space.metrics.nargs.closure_args_average(),
-            nargs_total: space.metrics.nargs.nargs_total(),
-            nargs_average: space.metrics.nargs.nargs_average(),
-            nargs_functions_min: space.metrics.nargs.fn_args_min(),
-            nargs_functions_max: space.metrics.nargs.fn_args_max(),
-            nargs_closures_min: space.metrics.nargs.closure_args_min(),
-            nargs_closures_max: space.metrics.nargs.closure_args_max(),
-
-            // Exits
-            nexits: space.metrics.nexits.exit(),
-            nexits_sum: space.metrics.nexits.exit_sum(),
-            nexits_average: space.metrics.nexits.exit_average(),
-            nexits_min: space.metrics.nexits.exit_min(),
-            nexits_max: space.metrics.nexits.exit_max(),
-
-            // Cognitive
-            cognitive: space.metrics.cognitive.cognitive(),
-            cognitive_sum: space.metrics.cognitive.cognitive_sum(),
-            cognitive_average: space.metrics.cognitive.cognitive_average(),
-            cognitive_min: space.metrics.cognitive.cognitive_min(),
-            cognitive_max: space.metrics.cognitive.cognitive_max(),
-
-            // Cyclomatic
-            cyclomatic: space.metrics.cyclomatic.cyclomatic(),
-            cyclomatic_sum: space.metrics.cyclomatic.cyclomatic_sum(),
-            cyclomatic_average: space.metrics.cyclomatic.cyclomatic_average(),
-            cyclomatic_min: space.metrics.cyclomatic.cyclomatic_min(),
-            cyclomatic_max: space.metrics.cyclomatic.cyclomatic_max(),
-
-            // Halstead
-            halstead_n1: space.metrics.halstead.u_operators(),
-            halstead_N1: space.metrics.halstead.operators(),
-            halstead_n2: space.metrics.halstead.u_operands(),
-            halstead_N2: space.metrics.halstead.operands(),
-            halstead_length: space.metrics.halstead.length(),
-            halstead_estimated_program_length: space.metrics.halstead.estimated_program_length(),
-            halstead_purity_ratio: space.metrics.halstead.purity_ratio(),
-            halstead_vocabulary: space.metrics.halstead.vocabulary(),
-            halstead_volume: space.metrics.halstead.volume(),
-            halstead_difficulty: space.metrics.halstead.difficulty(),
-            halstead_level: space.metrics.halstead.level(),
-            halstead_effort: space.metrics.halstead.effort(),
-            halstead_time: space.metrics.halstead.time(),
-            halstead_bugs: space.metrics.halstead.bugs(),
-
-            // Loc
-            loc_sloc: space.metrics.loc.sloc(),
-            loc_ploc: space.metrics.loc.ploc(),
-            loc_lloc: space.metrics.loc.lloc(),
-            loc_cloc: space.metrics.loc.cloc(),
-            loc_blank: space.metrics.loc.blank(),
-
-            // Nom
-            nom_functions: space.metrics.nom.functions(),
-            nom_closures: space.metrics.nom.closures(),
-            nom_total: space.metrics.nom.total(),
-            nom_functions_min: space.metrics.nom.functions_min(),
-            nom_functions_max: space.metrics.nom.functions_max(),
-            nom_closures_min: space.metrics.nom.closures_min(),
-            nom_closures_max: space.metrics.nom.closures_max(),
-
-            // Mi
-            mi_original: space.metrics.mi.mi_original(),
-            mi_sei: space.metrics.mi.mi_sei(),
-            mi_visual_studio: space.metrics.mi.mi_visual_studio(),
-        };
+/// Coupling only applies to function spaces, everything else reports zero
+fn space_coupling(space: &FuncSpace, context: &FileContext) -> (f64, f64) {
+    match space.kind {
+        SpaceKind::Function => (
+            context.coupling.fan_in(
+                &context.source_file,
+                space.name.as_deref().unwrap_or_default(),
+            ),
+            context
+                .coupling
+                .fan_out(&context.source_file, space.start_line),
+        ),
+        _ => (0.0, 0.0),
+    }
+}
 
-        // Scan through struct members and replace nan/inf's with 0.0
-        obj.replace_inf_nan();
+/// What tells the row of a space apart beyond its lines, as `visit_spaces` resolves it
+#[derive(Debug, Clone, Default)]
+pub struct SpaceIdentity {
+    /// The name of the space, a synthetic one for anonymous functions unless they're
+    /// written with a null name
+    pub name: Option<String>,
+    /// The name qualified by the namespaces and classes the space is in, e.g.
+    /// `geometry::Circle::area`
+    pub qualified_name: Option<String>,
+    /// The kind of space, telling C++ function templates and their specializations apart
+    /// from other functions
+    pub kind: String,
+    /// The declaration of a function up to its body, telling overloads apart
+    pub signature: Option<String>,
+    /// Number of specializations folded into the row of a function template
+    pub specializations: Option<usize>,
+}
 
-        obj
+impl SpaceIdentity {
+    /// The space as rust-code-analysis names it, without a signature. Objective-C methods
+    /// are named after their selector
+    pub fn of(space: &FuncSpace) -> Self {
+        let name = space
+            .name
+            .as_ref()
+            .map(|name| objc::selector(name).unwrap_or_else(|| name.clone()));
+        Self {
+            name: name.clone(),
+            qualified_name: name,
+            kind: space.kind.to_string(),
+            signature: None,
+            specializations: None,
+        }
     }
 }
 
-fn flatten_spaces_extended(
+/// Calls `visit` with every space `context` keeps, depth first, along with the name, kind
+/// and signature its row is given
+pub fn visit_spaces<E>(
     spaces: &[FuncSpace],
-    source_name: &Option<String>,
-    flattened: &mut Vec<FlattenedMetricsExtended>,
-) {
+    context: &FileContext,
+    visit: &mut impl FnMut(&FuncSpace, SpaceIdentity) -> Result<(), E>,
+) -> Result<(), E> {
+    visit_nested(spaces, None, None, context, &mut HashMap::new(), visit)
+}
+
+/// `visit_spaces` of the spaces nested in the one named `parent`, within the namespace or
+/// class qualified as `scope`, counting the functions seen on every pair of lines so far
+/// in `seen` to find their nodes
+fn visit_nested<E>(
+    spaces: &[FuncSpace],
+    parent: Option<&str>,
+    scope: Option<&str>,
+    context: &FileContext,
+    seen: &mut HashMap<(usize, usize), usize>,
+    visit: &mut impl FnMut(&FuncSpace, SpaceIdentity) -> Result<(), E>,
+) -> Result<(), E> {
     for space in spaces {
-        flattened.push(FlattenedMetricsExtended::from_space(
-            space,
-            Some(space.name.clone().unwrap_or("no_name_found".to_string())),
-            Some(source_name.clone().unwrap()),
-        ));
+        let mut identity = SpaceIdentity::of(space);
+        let anonymous = is_anonymous(space);
+        let mut folded = false;
+        let mut unparsed = false;
+        if space.kind == SpaceKind::Function {
+            let nth = seen.entry((space.start_line, space.end_line)).or_default();
+            let node = context
+                .functions
+                .as_ref()
+                .and_then(|functions| functions.get(space, *nth));
+            identity.signature = node.map(|node| node.signature.clone());
+            if let (Some(functions), Some((template, template_name))) = (
+                &context.functions,
+                node.and_then(|node| node.template.as_ref()),
+            ) {
+                identity.kind = template.kind(&identity.kind);
+                if context.aggregate_specializations {
+                    match template {
+                        Template::Definition => {
+                            identity.specializations =
+                                Some(functions.specializations(template_name))
+                        }
+                        Template::Specialization => {
+                            folded = functions.defines_template(template_name)
+                        }
+                    }
+                }
+            }
+            if let (Some(max), Some(errors)) = (
+                context.max_parse_error_ratio,
+                context
+                    .functions
+                    .as_ref()
+                    .and_then(FunctionNodes::parse_errors),
+            ) {
+                let (_, ratio) = errors.between(space.start_line, space.end_line);
+                if ratio > max {
+                    debug!(
+                        function = space.name.as_deref().unwrap_or_default(),
+                        "Skipping, {:.2} of it failed to parse", ratio
+                    );
+                    unparsed = true;
+                }
+            }
+            if anonymous {
+                identity.name = match context.anonymous {
+                    AnonymousNaming::Synthetic => Some(synthetic_name(parent, space, node, *nth)),
+                    AnonymousNaming::Null | AnonymousNaming::Drop => None,
+                };
+            }
+            *nth += 1;
+        }
+        identity.qualified_name = identity.name.as_ref().map(|name| match scope {
+            Some(scope) => format!("{}::{}", scope, name),
+            None => name.clone(),
+        });
+        let dropped = folded || unparsed || anonymous && context.anonymous == AnonymousNaming::Drop;
+        let name = identity.name.clone();
+        let scopes = !matches!(
+            space.kind,
+            SpaceKind::Function | SpaceKind::Unit | SpaceKind::Unknown
+        );
+        let nested_scope = if scopes {
+            identity.qualified_name.clone()
+        } else {
+            None
+        };
+        if !dropped && context.keep(space) {
+            visit(space, identity)?;
+        }
 
         // Recursively process nested spaces
-        flatten_spaces_extended(&space.spaces, source_name, flattened);
+        visit_nested(
+            &space.spaces,
+            name.as_deref().or(parent),
+            nested_scope.as_deref().or(scope),
+            context,
+            seen,
+            visit,
+        )?;
     }
+    Ok(())
 }
 
-impl FlattenedMetrics {
-    pub fn from_space(
-        space: &FuncSpace,
-        parent_name: Option<String>,
-        source_file: Option<String>,
-    ) -> Self {
-        let mut obj = Self {
-            name: space.name.clone(),
-            source_file,
-            start_line: space.start_line,
-            end_line: space.end_line,
-            kind: space.kind.clone().to_string(),
-            parent_name,
-
-            // NArgs
-            fn_args: space.metrics.nargs.fn_args(),
-            closure_args: space.metrics.nargs.closure_args(),
-
-            // Exits
-            nexits: space.metrics.nexits.exit(),
-
-            // Cognitive
-            cognitive: space.metrics.cognitive.cognitive(),
-
-            // Cyclomatic
-            cyclomatic: space.metrics.cyclomatic.cyclomatic(),
-
-            // Halstead
-            halstead_n1: space.metrics.halstead.u_operators(),
-            halstead_N1: space.metrics.halstead.operators(),
-            halstead_n2: space.metrics.halstead.u_operands(),
-            halstead_N2: space.metrics.halstead.operands(),
-            halstead_length: space.metrics.halstead.length(),
-            halstead_estimated_program_length: space.metrics.halstead.estimated_program_length(),
-            halstead_purity_ratio: space.metrics.halstead.purity_ratio(),
-            halstead_vocabulary: space.metrics.halstead.vocabulary(),
-            halstead_volume: space.metrics.halstead.volume(),
-            halstead_difficulty: space.metrics.halstead.difficulty(),
-            halstead_level: space.metrics.halstead.level(),
-            halstead_effort: space.metrics.halstead.effort(),
-            halstead_time: space.metrics.halstead.time(),
-            halstead_bugs: space.metrics.halstead.bugs(),
-
-            // Loc
-            loc_sloc: space.metrics.loc.sloc(),
-            loc_ploc: space.metrics.loc.ploc(),
-            loc_lloc: space.metrics.loc.lloc(),
-            loc_cloc: space.metrics.loc.cloc(),
-            loc_blank: space.metrics.loc.blank(),
-
-            // Nom
-            nom_functions: space.metrics.nom.functions(),
-            nom_closures: space.metrics.nom.closures(),
-            nom_total: space.metrics.nom.total(),
-
-            // Mi
-            mi_original: space.metrics.mi.mi_original(),
-            mi_sei: space.metrics.mi.mi_sei(),
-            mi_visual_studio: space.metrics.mi.mi_visual_studio(),
-        };
-
-        // Scan through struct members and replace nan/inf's with 0.0
-        obj.replace_inf_nan();
-
-        obj
-    }
+pub fn flatten_spaces_extended(
+    spaces: &[FuncSpace],
+    context: &FileContext,
+    flattened: &mut Vec<FlattenedMetricsExtended>,
+) {
+    let _ = visit_spaces(spaces, context, &mut |space, identity| {
+        flattened.push(FlattenedMetricsExtended::from_space(
+            space, identity, context,
+        ));
+        Ok::<_, Infallible>(())
+    });
 }
 
-fn flatten_spaces(
+pub fn flatten_spaces(
     spaces: &[FuncSpace],
-    source_name: &Option<String>,
+    context: &FileContext,
     flattened: &mut Vec<FlattenedMetrics>,
 ) {
-    for space in spaces {
-        flattened.push(FlattenedMetrics::from_space(
-            space,
-            Some(space.name.clone().unwrap_or("no_name_found".to_string())),
-            Some(source_name.clone().unwrap()),
-        ));
+    let _ = visit_spaces(spaces, context, &mut |space, identity| {
+        flattened.push(FlattenedMetrics::from_space(space, identity, context));
+        Ok::<_, Infallible>(())
+    });
+}
 
-        // Recursively process nested spaces
-        flatten_spaces(&space.spaces, source_name, flattened);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_lengths_cover_the_inclusive_lines_of_a_space() {
+        let coupling = Coupling::default();
+        let long = format!("    {};", "x".repeat(130));
+        let source = format!("int f() {{\n{}\n    return 0;\n}}\nint g;\n", long);
+        let context = FileContext::new("a.c".to_string(), source.as_bytes(), &coupling);
+
+        let lengths = context.line_lengths(1, 4);
+        assert_eq!(lengths.max, 135.0);
+        assert_eq!(lengths.avg, (9 + 135 + 13 + 1) as f64 / 4.0);
+        assert_eq!(lengths.over_120, 1.0);
+        assert_eq!(context.line_lengths(5, 5).max, 6.0);
+    }
+
+    #[test]
+    fn line_lengths_count_characters_and_clamp_to_the_file() {
+        let coupling = Coupling::default();
+        let context = FileContext::new("a.c".to_string(), "ü€😀\nab\n".as_bytes(), &coupling);
+        assert_eq!(context.line_lengths(1, 1).max, 3.0);
+        // Spaces reaching past the end of the file only cover the lines of it
+        assert_eq!(context.line_lengths(0, 9).avg, 2.5);
+        let empty = context.line_lengths(7, 9);
+        assert_eq!((empty.max, empty.avg, empty.over_120), (0.0, 0.0, 0.0));
+    }
+
+    #[derive(Debug, PartialEq, ReplaceInfNan)]
+    struct Policies {
+        zero: f64,
+        #[replace_with(-1)]
+        sentinel: f64,
+        #[replace_with(raw)]
+        raw: f64,
+        finite: f64,
+    }
+
+    impl Policies {
+        fn new() -> Self {
+            Policies {
+                zero: f64::NAN,
+                sentinel: f64::INFINITY,
+                raw: f64::NEG_INFINITY,
+                finite: 2.5,
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_policies_are_parsed_and_printed() {
+        for (value, policy) in [
+            ("zero", NonFinite::Zero),
+            ("null", NonFinite::Null),
+            ("raw", NonFinite::Raw),
+            ("-1", NonFinite::Sentinel(-1.0)),
+            ("0.5", NonFinite::Sentinel(0.5)),
+        ] {
+            assert_eq!(value.parse::<NonFinite>(), Ok(policy));
+            assert_eq!(policy.to_string(), value);
+        }
+        for value in ["inf", "NaN", "none"] {
+            assert_eq!(
+                value.parse::<NonFinite>(),
+                Err(format!(
+                    "expected zero, null, raw or a sentinel number, got {}",
+                    value
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn fields_fall_back_to_their_replace_with_policy() {
+        let mut metrics = Policies::new();
+        assert_eq!(metrics.replace_inf_nan(None), [("raw", f64::NEG_INFINITY)]);
+        assert_eq!((metrics.zero, metrics.sentinel), (0.0, -1.0));
+        assert_eq!(metrics.finite, 2.5);
+
+        // A policy given on the command line applies to every field
+        let mut metrics = Policies::new();
+        assert!(metrics
+            .replace_inf_nan(Some(NonFinite::Sentinel(-9.0)))
+            .is_empty());
+        assert_eq!(
+            metrics,
+            Policies {
+                zero: -9.0,
+                sentinel: -9.0,
+                raw: -9.0,
+                finite: 2.5,
+            }
+        );
+
+        let mut metrics = Policies::new();
+        assert_eq!(metrics.replace_inf_nan(Some(NonFinite::Raw)).len(), 3);
+        let mut metrics = Policies::new();
+        metrics.replace_inf_nan(Some(NonFinite::Null));
+        assert!(metrics.zero.is_nan() && metrics.sentinel.is_nan() && metrics.raw.is_nan());
+        assert_eq!(
+            serde_json::to_value(metrics.raw).unwrap(),
+            serde_json::Value::Null
+        );
+    }
+
+    #[derive(Debug, ReplaceInfNan)]
+    struct Inner {
+        #[replace_with(raw)]
+        density: Option<f64>,
+    }
+
+    #[derive(Debug, ReplaceInfNan)]
+    struct Wrapped {
+        single: f32,
+        optional: Option<f64>,
+        many: Vec<f64>,
+        #[replace_inf_nan(nested)]
+        inner: Option<Inner>,
+        #[replace_inf_nan(nested)]
+        inners: Vec<Inner>,
+        #[replace_inf_nan(skip)]
+        skipped: f64,
+    }
+
+    fn wrapped() -> Wrapped {
+        Wrapped {
+            single: f32::NAN,
+            optional: Some(f64::INFINITY),
+            many: vec![1.0, f64::NAN],
+            inner: Some(Inner {
+                density: Some(f64::NAN),
+            }),
+            inners: vec![
+                Inner {
+                    density: Some(f64::INFINITY),
+                },
+                Inner { density: None },
+            ],
+            skipped: f64::NAN,
+        }
+    }
+
+    #[test]
+    fn wrapped_and_nested_fields_are_replaced_too() {
+        let mut metrics = wrapped();
+        let kept = metrics.replace_inf_nan(None);
+        assert_eq!(metrics.single, 0.0);
+        assert_eq!(metrics.optional, Some(0.0));
+        assert_eq!(metrics.many, [1.0, 0.0]);
+        assert!(metrics.skipped.is_nan());
+        // Nested fields keep their own fallback, reporting the raw ones
+        assert_eq!(kept.len(), 2);
+        assert!(kept.iter().all(|(column, _)| *column == "density"));
+        assert_eq!(metrics.inners[1].density, None);
+
+        let mut metrics = wrapped();
+        metrics.replace_inf_nan(Some(NonFinite::Null));
+        assert!(metrics.single.is_nan());
+        assert_eq!(metrics.optional, None);
+        assert_eq!(metrics.inner.unwrap().density, None);
+        assert_eq!(metrics.inners[0].density, None);
+        // Elements of a Vec can't be dropped, so stay non-finite and come out as null
+        assert!(metrics.many[1].is_nan());
+    }
+
+    #[test]
+    fn extended_columns_interleave_with_the_regular_ones_in_table_order() {
+        let regular: Vec<String> = FlattenedMetrics::columns()
+            .into_iter()
+            .map(|column| column.name)
+            .collect();
+        let extended: Vec<String> = FlattenedMetricsExtended::columns()
+            .into_iter()
+            .map(|column| column.name)
+            .collect();
+        // Every regular column is in the extended struct, in the same order
+        let mut rest = extended.iter();
+        for name in &regular {
+            assert!(
+                rest.any(|extended| extended == name),
+                "{} out of order",
+                name
+            );
+        }
+        let at = |name: &str| extended.iter().position(|column| column == name).unwrap();
+        assert_eq!(at("closure_args") + 1, at("nargs_total_functions"));
+        assert_eq!(at("nexits") + 1, at("nexits_sum"));
+        assert_eq!(at("nom_total") + 1, at("nom_functions_min"));
+        assert!(!regular.contains(&"cyclomatic_max".to_string()));
+    }
+
+    #[test]
+    fn completed_files_are_known_as_soon_as_they_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(COMPLETED_LOG);
+        let log = CompletedLog::open(&path, false).unwrap();
+        assert!(!log.contains(Path::new("a.c")));
+        // An interrupted run counts what it completed from the log it kept in memory
+        log.record(Path::new("a.c")).unwrap();
+        assert!(log.contains(Path::new("a.c")));
+        assert!(!log.contains(Path::new("b.c")));
+        drop(log);
+
+        let resumed = CompletedLog::open(&path, true).unwrap();
+        assert!(resumed.contains(Path::new("a.c")));
+        resumed.record(Path::new("b.c")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a.c\nb.c\n");
+
+        let fresh = CompletedLog::open(&path, false).unwrap();
+        assert!(!fresh.contains(Path::new("a.c")));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn quarantine_lists_only_the_files_of_the_latest_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (PathBuf::from("src/a.c"), PathBuf::from("src/b.c"));
+        let quarantined = vec![
+            (&a, "timed out".to_string()),
+            (&b, "stale handle".to_string()),
+        ];
+
+        let path = write_quarantine(dir.path(), &quarantined).unwrap().unwrap();
+        assert_eq!(path, dir.path().join(QUARANTINE));
+        let files = fs::read_to_string(path.join("files.txt")).unwrap();
+        assert_eq!(files, "src/a.c\nsrc/b.c\n");
+        let reasons = fs::read_to_string(path.join("reasons.tsv")).unwrap();
+        assert_eq!(reasons, "src/a.c\ttimed out\nsrc/b.c\tstale handle\n");
+
+        // A run reading everything clears what an earlier one left
+        assert_eq!(write_quarantine(dir.path(), &[]).unwrap(), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn the_run_fails_once_too_many_timed_out_parses_are_still_running() {
+        static ABANDONED: AtomicUsize = AtomicUsize::new(0);
+        let (release, hung) = crossbeam_channel::unbounded::<()>();
+        let path = Path::new("hangs.c");
+        let hang = || {
+            let hung = hung.clone();
+            move || hung.recv().map_err(|_| CliError::FailedProcessing(String::new()))
+        };
+        let within = |work| run_within(path, work, Duration::from_millis(10), &ABANDONED, 2);
+
+        for _ in 0..2 {
+            assert!(matches!(within(hang()), Err(CliError::TimedOut(..))));
+        }
+        assert!(matches!(within(hang()), Err(CliError::TooManyTimeouts(2))));
+        assert_eq!(ABANDONED.load(Ordering::SeqCst), 3);
+
+        // Finishing parses are no longer counted, and work done in time is handed back
+        drop(release);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while ABANDONED.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(ABANDONED.load(Ordering::SeqCst), 0);
+        assert!(matches!(within(hang()), Err(CliError::FailedProcessing(_))));
     }
 }
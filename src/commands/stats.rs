@@ -0,0 +1,167 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use rust_code_analysis::get_function_spaces;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::language::{LanguageArgs, SourceReader};
+use tracing::{debug, info, warn};
+
+#[derive(Args)]
+pub struct StatsCommand {
+    /// Path to the source code to be summarised
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// Optional file to save the summary to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the summary
+    /// Options: table, json, csv
+    #[arg(short, long, default_value = "table", value_parser = clap::builder::PossibleValuesParser::new(["table", "json", "csv"]))]
+    fmt: String,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+/// Line counts for a single language
+#[derive(Debug, Default, Serialize)]
+pub struct LanguageStats {
+    pub language: String,
+    pub files: usize,
+    pub sloc: f64,
+    pub cloc: f64,
+    pub blank: f64,
+}
+
+impl LanguageStats {
+    fn add(&mut self, other: &LanguageStats) {
+        self.files += other.files;
+        self.sloc += other.sloc;
+        self.cloc += other.cloc;
+        self.blank += other.blank;
+    }
+}
+
+impl StatsCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let filepaths = self.discovery.discover(&self.path)?;
+        let reader = self.language.reader()?;
+        info!("Summarising {} files...", filepaths.len());
+
+        let per_file: Vec<LanguageStats> = filepaths
+            .par_iter()
+            .filter_map(|fp| Self::file_stats(fp, &reader))
+            .collect();
+
+        let mut per_language: BTreeMap<String, LanguageStats> = BTreeMap::new();
+        for stats in &per_file {
+            per_language
+                .entry(stats.language.clone())
+                .or_insert_with(|| LanguageStats {
+                    language: stats.language.clone(),
+                    ..Default::default()
+                })
+                .add(stats);
+        }
+        let summary: Vec<LanguageStats> = per_language.into_values().collect();
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match self.fmt.as_str() {
+            "table" => Self::write_table(&mut writer, &summary)?,
+            "csv" => {
+                let mut writer = csv::Writer::from_writer(writer);
+                for entry in &summary {
+                    writer.serialize(entry)?
+                }
+                writer.flush()?;
+            }
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &summary)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Line counts of a single file, `None` when it can't be read or parsed
+    pub fn file_stats(path: &Path, reader: &SourceReader) -> Option<LanguageStats> {
+        let (language, source, decoding) = match reader.read_decoded(path) {
+            Ok(read) => read,
+            Err(CliError::FailedGuessLang(_)) => {
+                warn!(file = %path.display(), "Failed to guess programming lang");
+                return None;
+            }
+            Err(_) => {
+                warn!(file = %path.display(), "Failed to read");
+                return None;
+            }
+        };
+
+        let space = get_function_spaces(&language, source, path, None)?;
+        debug!("Summarised {}", path.display());
+
+        Some(LanguageStats {
+            language: decoding.choice.name(&language).to_string(),
+            files: 1,
+            sloc: space.metrics.loc.sloc(),
+            cloc: space.metrics.loc.cloc(),
+            blank: space.metrics.loc.blank(),
+        })
+    }
+
+    fn write_table(writer: &mut dyn Write, summary: &[LanguageStats]) -> Result<(), CliError> {
+        let mut total = LanguageStats {
+            language: "SUM".to_string(),
+            ..Default::default()
+        };
+        for stats in summary {
+            total.add(stats);
+        }
+
+        let rule = "-".repeat(62);
+        writeln!(writer, "{}", rule)?;
+        writeln!(
+            writer,
+            "{:<20}{:>10}{:>12}{:>10}{:>10}",
+            "Language", "files", "sloc", "comment", "blank"
+        )?;
+        writeln!(writer, "{}", rule)?;
+        for stats in summary {
+            Self::write_row(writer, stats)?;
+        }
+        writeln!(writer, "{}", rule)?;
+        Self::write_row(writer, &total)?;
+        writeln!(writer, "{}", rule)?;
+
+        Ok(())
+    }
+
+    fn write_row(writer: &mut dyn Write, stats: &LanguageStats) -> Result<(), CliError> {
+        writeln!(
+            writer,
+            "{:<20}{:>10}{:>12}{:>10}{:>10}",
+            stats.language, stats.files, stats.sloc, stats.cloc, stats.blank
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use serde_json::{json, Map, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::commands::init::percentile;
+use crate::discovery::DiscoveryArgs;
+use crate::errors::{check_input, CliError};
+use crate::extract::{extract_source, ExtractOptions, MetricsRow};
+use crate::git::{self, days, Commit, GitTree};
+use crate::language::LanguageArgs;
+use crate::output::write_csv;
+use crate::paths;
+use crate::tree::SourceTree;
+
+#[derive(Args)]
+pub struct SurvivalCommand {
+    /// Path to the git repository to follow the functions of through its history
+    #[arg(long, default_value = ".")]
+    repo: PathBuf,
+
+    /// Revision whose first-parent history is followed
+    #[arg(long, default_value = "HEAD")]
+    rev: String,
+
+    /// Only follow the latest commits of the history. Functions already there at the
+    /// first of them count as introduced by it
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_commits: Option<u64>,
+
+    /// Optional file to save the lifespan of every function to. Printed to stdout when not
+    /// provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the lifespans
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    /// Optional JSON file to save lifespan statistics to: how many functions were
+    /// introduced and removed, how long they lived, and the share surviving a given age
+    #[arg(long)]
+    summary: Option<PathBuf>,
+
+    /// Cyclomatic complexity from which a function counts as complex
+    #[arg(long, default_value = "10")]
+    threshold: f64,
+
+    /// Age in days from which a function counts as old rather than young
+    #[arg(long, default_value = "365")]
+    old_days: f64,
+
+    /// Days without a change after which a function counts as stable rather than churning
+    #[arg(long, default_value = "180")]
+    stable_days: f64,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+/// A function from the commit introducing it to the one removing it, if any
+struct Life {
+    file: String,
+    name: String,
+    introduced: (String, i64),
+    removed: Option<(String, i64)>,
+    /// Commits changing its body after introducing it
+    changes: u64,
+    last_changed: i64,
+    body: u64,
+    /// Its row at the last commit it was in
+    row: MetricsRow,
+}
+
+/// Ages in days the share of functions surviving is reported at
+const AGES: [u64; 5] = [30, 90, 180, 365, 730];
+
+impl SurvivalCommand {
+    pub fn execute(mut self) -> Result<(), CliError> {
+        check_input(&self.repo)?;
+        let commits = git::history(
+            &self.repo,
+            &self.rev,
+            self.max_commits.map(|limit| limit as usize),
+        )?;
+        let Some(last) = commits.last().cloned() else {
+            return Err(CliError::GitError(format!("{} has no commits", self.rev)));
+        };
+
+        let mut lives: Vec<Life> = Vec::new();
+        // The living functions of every file, by name and occurrence of the name
+        let mut alive: HashMap<String, HashMap<(String, usize), usize>> = HashMap::new();
+        for (at, commit) in commits.iter().enumerate() {
+            let tree = Arc::new(GitTree::open(&self.repo, &commit.hash)?);
+            self.discovery.tree = Some(tree.clone());
+            let files: BTreeSet<String> = self
+                .discovery
+                .discover(&self.repo)?
+                .iter()
+                .map(|file| paths::slashed(tree.relative(file)))
+                .collect();
+            // Every file at the first commit followed, as the history before it is not
+            let changed: BTreeSet<&String> = match at {
+                0 => files.iter().collect(),
+                _ => commit.changed.iter().collect(),
+            };
+            let extracted = self.extract(tree.as_ref(), &files, &changed);
+            for file in changed {
+                let rows = match extracted.get(file.as_str()) {
+                    Some(rows) => rows.as_slice(),
+                    // Its functions are left as they were when the file fails to extract
+                    None if files.contains(file.as_str()) => continue,
+                    None => &[],
+                };
+                Self::advance(
+                    &mut lives,
+                    alive.entry(file.clone()).or_default(),
+                    file,
+                    rows,
+                    commit,
+                );
+            }
+        }
+        let removed = lives.iter().filter(|life| life.removed.is_some()).count();
+        info!(
+            "Followed {} functions through {} commits, {} removed",
+            lives.len(),
+            commits.len(),
+            removed
+        );
+
+        let records: Vec<MetricsRow> = lives.iter().map(|life| self.record(life, &last)).collect();
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &records)?,
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &records)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+
+        if let Some(path) = &self.summary {
+            let mut writer = File::create(path)?;
+            serde_json::to_writer_pretty(
+                &mut writer,
+                &summary(&lives, &records, &last, commits.len()),
+            )?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Extracts the `changed` files among the discovered `files` of a commit, keyed by
+    /// their path in the repository
+    fn extract(
+        &self,
+        tree: &dyn SourceTree,
+        files: &BTreeSet<String>,
+        changed: &BTreeSet<&String>,
+    ) -> HashMap<String, Vec<MetricsRow>> {
+        let options = ExtractOptions {
+            // Bodies are compared to tell the commits changing a function
+            with_source: true,
+            force_language: self.language.force_language.clone(),
+            map_ext: self.language.map_ext.clone(),
+            preprocess: self.language.preprocess.clone(),
+            define: self.language.define.clone(),
+            ..ExtractOptions::default()
+        };
+        changed
+            .par_iter()
+            .filter(|file| files.contains(file.as_str()))
+            .filter_map(|file| {
+                let path = self.repo.join(file.as_str());
+                let rows = tree
+                    .read(&path)
+                    .map_err(CliError::from)
+                    .and_then(|source| extract_source(&path, &source, &options));
+                match rows {
+                    Ok(rows) => Some((file.to_string(), rows)),
+                    Err(err) => {
+                        warn!(file = %file, "Failed to extract: {}", err);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Moves the functions of `file` on to its `rows` at `commit`: the ones missing are
+    /// removed by it, the new ones introduced, and the ones whose body differs changed
+    fn advance(
+        lives: &mut Vec<Life>,
+        alive: &mut HashMap<(String, usize), usize>,
+        file: &str,
+        rows: &[MetricsRow],
+        commit: &Commit,
+    ) {
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        let mut now: HashMap<(String, usize), usize> = HashMap::new();
+        for row in rows {
+            let name = match row.get("name").and_then(Value::as_str) {
+                Some(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            let occurrence = seen.entry(name).or_default();
+            let key = (name.to_string(), *occurrence);
+            *occurrence += 1;
+
+            let body = body_hash(row);
+            let mut row = row.clone();
+            row.remove("source");
+            let index = match alive.remove(&key) {
+                Some(index) => {
+                    let life = &mut lives[index];
+                    if life.body != body {
+                        life.changes += 1;
+                        life.last_changed = commit.time;
+                        life.body = body;
+                    }
+                    life.row = row;
+                    index
+                }
+                None => {
+                    lives.push(Life {
+                        file: file.to_string(),
+                        name: name.to_string(),
+                        introduced: (commit.hash.clone(), commit.time),
+                        removed: None,
+                        changes: 0,
+                        last_changed: commit.time,
+                        body,
+                        row,
+                    });
+                    lives.len() - 1
+                }
+            };
+            now.insert(key, index);
+        }
+        for index in alive.values() {
+            lives[*index].removed = Some((commit.hash.clone(), commit.time));
+        }
+        *alive = now;
+    }
+
+    /// The lifespan row of a function, aged up to the `last` commit followed
+    fn record(&self, life: &Life, last: &Commit) -> MetricsRow {
+        let end = life.removed.as_ref().map_or(last.time, |(_, time)| *time);
+        let age = days(end - life.introduced.1);
+        let unchanged = days(end - life.last_changed);
+        let cyclomatic = life.row.get("cyclomatic").and_then(Value::as_f64);
+        let complex = cyclomatic.is_some_and(|cyclomatic| cyclomatic >= self.threshold);
+        let profile = life.removed.is_none().then(|| {
+            let age = if age >= self.old_days { "old" } else { "young" };
+            let churn = if unchanged >= self.stable_days {
+                "stable"
+            } else {
+                "churning"
+            };
+            format!("{}-{}", age, churn)
+        });
+
+        let mut record = MetricsRow::new();
+        record.insert("source_file".to_string(), json!(life.file));
+        record.insert("name".to_string(), json!(life.name));
+        record.insert(
+            "kind".to_string(),
+            life.row.get("kind").cloned().unwrap_or_default(),
+        );
+        record.insert("introduced".to_string(), json!(life.introduced.0));
+        record.insert("introduced_at".to_string(), json!(life.introduced.1));
+        let (removed, removed_at) = life.removed.clone().unzip();
+        record.insert("removed".to_string(), json!(removed));
+        record.insert("removed_at".to_string(), json!(removed_at));
+        record.insert("alive".to_string(), json!(life.removed.is_none()));
+        record.insert("age_days".to_string(), json!(age));
+        record.insert("changes".to_string(), json!(life.changes));
+        record.insert("last_changed_at".to_string(), json!(life.last_changed));
+        record.insert("days_since_change".to_string(), json!(unchanged));
+        record.insert("cyclomatic".to_string(), json!(cyclomatic));
+        record.insert(
+            "cognitive".to_string(),
+            life.row.get("cognitive").cloned().unwrap_or_default(),
+        );
+        record.insert("complex".to_string(), json!(complex));
+        record.insert("profile".to_string(), json!(profile));
+        record
+    }
+}
+
+/// A hash of the source of the function of a row
+fn body_hash(row: &MetricsRow) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    row.get("source")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Lifespan statistics over every function. The share surviving an age is the
+/// Kaplan-Meier estimate, counting the functions still alive as far as they got
+fn summary(lives: &[Life], records: &[MetricsRow], last: &Commit, commits: usize) -> Value {
+    // The age of every function, and whether it ended in removal
+    let mut spans: Vec<(f64, bool)> = lives
+        .iter()
+        .map(|life| {
+            let end = life.removed.as_ref().map_or(last.time, |(_, time)| *time);
+            (days(end - life.introduced.1), life.removed.is_some())
+        })
+        .collect();
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)));
+
+    // The estimate drops at every age a function was removed at
+    let mut survival: Vec<(f64, f64)> = Vec::new();
+    let mut surviving = 1.0;
+    let mut at_risk = spans.len();
+    let mut index = 0;
+    while index < spans.len() {
+        let age = spans[index].0;
+        let same_age = spans[index..]
+            .iter()
+            .take_while(|(other, _)| *other == age)
+            .count();
+        let removed = spans[index..index + same_age]
+            .iter()
+            .filter(|(_, removed)| *removed)
+            .count();
+        if removed > 0 {
+            surviving *= 1.0 - removed as f64 / at_risk as f64;
+            survival.push((age, surviving));
+        }
+        at_risk -= same_age;
+        index += same_age;
+    }
+    let surviving_at = |days: f64| {
+        survival
+            .iter()
+            .take_while(|(age, _)| *age <= days)
+            .last()
+            .map_or(1.0, |(_, surviving)| *surviving)
+    };
+    let followed = spans.last().map_or(0.0, |(age, _)| *age);
+    let mut survival_at = Map::new();
+    for age in AGES {
+        // Unknown past the oldest function followed
+        let estimate = (age as f64 <= followed).then(|| surviving_at(age as f64));
+        survival_at.insert(format!("{}_days", age), json!(estimate));
+    }
+
+    let mut lifespans: Vec<f64> = spans
+        .iter()
+        .filter(|(_, removed)| *removed)
+        .map(|(age, _)| *age)
+        .collect();
+    let mut ages: Vec<f64> = spans
+        .iter()
+        .filter(|(_, removed)| !removed)
+        .map(|(age, _)| *age)
+        .collect();
+    // How the complex functions still alive divide among the profiles
+    let mut complex: BTreeMap<&str, usize> = BTreeMap::new();
+    for record in records {
+        if record["complex"] == true {
+            if let Some(profile) = record["profile"].as_str() {
+                *complex.entry(profile).or_default() += 1;
+            }
+        }
+    }
+    json!({
+        "commits": commits,
+        "functions": lives.len(),
+        "alive": ages.len(),
+        "removed": lifespans.len(),
+        "median_lifespan_days": percentile(&mut lifespans, 50.0),
+        "median_age_days": percentile(&mut ages, 50.0),
+        "median_survival_days": survival
+            .iter()
+            .find(|(_, surviving)| *surviving <= 0.5)
+            .map(|(age, _)| *age),
+        "survival": survival_at,
+        "complex_profiles": complex,
+    })
+}
@@ -0,0 +1,162 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use rust_code_analysis::LANG;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tree_sitter::Node;
+
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::language::{LanguageArgs, SourceReader};
+use crate::syntax::{parse, signature, symbol_kind, symbol_name};
+use tracing::{debug, info, warn};
+
+#[derive(Args)]
+pub struct SymbolsCommand {
+    /// Path to the source code to be indexed
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// Optional file to save the symbol index to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the symbol index
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+/// A single definition found within a source file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: Option<String>,
+    pub source_file: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub kind: String,
+    pub parent_name: Option<String>,
+    pub signature: Option<String>,
+}
+
+impl SymbolsCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let filepaths = self.discovery.discover(&self.path)?;
+        let reader = self.language.reader()?;
+        info!("Indexing symbols for {} files...", filepaths.len());
+
+        let mut per_file: Vec<(PathBuf, Vec<Symbol>)> = filepaths
+            .par_iter()
+            .filter_map(|fp| file_symbols(fp, &reader).map(|symbols| (fp.clone(), symbols)))
+            .collect();
+        per_file.sort_by(|a, b| a.0.cmp(&b.0));
+        let symbols: Vec<Symbol> = per_file.into_iter().flat_map(|(_, s)| s).collect();
+
+        let writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match self.fmt.as_str() {
+            "csv" => {
+                let mut writer = csv::Writer::from_writer(writer);
+                for entry in &symbols {
+                    writer.serialize(entry)?
+                }
+                writer.flush()?;
+            }
+            "json" => {
+                let mut writer = writer;
+                serde_json::to_writer_pretty(&mut writer, &symbols)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn file_symbols(path: &Path, reader: &SourceReader) -> Option<Vec<Symbol>> {
+    let (language, source) = match reader.read(path) {
+        Ok(read) => read,
+        Err(CliError::FailedGuessLang(_)) => {
+            warn!(file = %path.display(), "Failed to guess programming lang");
+            return None;
+        }
+        Err(_) => {
+            warn!(file = %path.display(), "Failed to read");
+            return None;
+        }
+    };
+
+    let tree = match parse(&language, &source) {
+        Some(tree) => tree,
+        None => {
+            warn!(file = %path.display(), "Failed to parse");
+            return None;
+        }
+    };
+
+    let symbols = extract_symbols(
+        &language,
+        tree.root_node(),
+        &source,
+        &path.to_string_lossy(),
+    );
+    debug!("Found {} symbols in {}", symbols.len(), path.display());
+
+    Some(symbols)
+}
+
+/// Walks the syntax tree rooted at `root` and collects every definition it contains
+pub fn extract_symbols(lang: &LANG, root: Node, source: &[u8], source_file: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let mut stack: Vec<(Node, Option<String>)> = vec![(root, None)];
+
+    while let Some((node, parent_name)) = stack.pop() {
+        let mut child_parent = parent_name.clone();
+
+        if let Some(kind) = symbol_kind(lang, &node) {
+            let name = symbol_name(lang, &node, source);
+            let signature = if kind == "function" {
+                Some(signature(&node, source))
+            } else {
+                None
+            };
+
+            if name.is_some() {
+                child_parent = name.clone();
+            }
+
+            symbols.push(Symbol {
+                name,
+                source_file: source_file.to_string(),
+                start_line: node.start_position().row + 1,
+                end_line: node.end_position().row + 1,
+                kind: kind.to_string(),
+                parent_name,
+                signature,
+            });
+        }
+
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.named_children(&mut cursor).collect();
+        for child in children.into_iter().rev() {
+            stack.push((child, child_parent.clone()));
+        }
+    }
+
+    symbols
+}
@@ -0,0 +1,430 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::callgraph::simple_name;
+use crate::commands::pair::pair;
+use crate::discovery::DiscoveryArgs;
+use crate::errors::{check_input, CliError};
+use crate::extract::{extract_dir, ExtractOptions, MetricsRow};
+use crate::git::{self, LineMap};
+use crate::language::LanguageArgs;
+use crate::output::write_csv;
+
+#[derive(Args)]
+pub struct TrendCommand {
+    /// Path to the git repository to compare two revisions of
+    #[arg(long, default_value = ".")]
+    repo: PathBuf,
+
+    /// Revision to compare from, e.g. a tag, branch or commit
+    #[arg(long, required = true)]
+    from: String,
+
+    /// Revision to compare to
+    #[arg(long, required = true)]
+    to: String,
+
+    /// Optional file to save the changed functions to. Printed to stdout when not provided
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+
+    /// Output format for the changed functions
+    /// Options: json, csv
+    #[arg(short, long, default_value = "csv", value_parser = clap::builder::PossibleValuesParser::new(["json", "csv"]))]
+    fmt: String,
+
+    /// Compare the extended metric columns
+    #[arg(long, default_value = "false")]
+    extended: bool,
+
+    /// Don't match the functions left over after matching by file and name as renamed or
+    /// moved ones
+    #[arg(long, default_value = "false")]
+    no_renames: bool,
+
+    /// Similarity from 0 to 1 a left over function needs to count as renamed or moved, a
+    /// weighted mix of the similarity of the bodies and of the names
+    #[arg(long, default_value = "0.7")]
+    rename_threshold: f64,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+/// Rows of one revision grouped by file and function name
+type Index = BTreeMap<(String, String), Vec<MetricsRow>>;
+
+/// Weight of the similarity of the bodies in the similarity of two functions, the rest
+/// going to the similarity of their names
+const BODY_WEIGHT: f64 = 0.75;
+
+impl TrendCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if !(0.0..=1.0).contains(&self.rename_threshold) {
+            return Err(CliError::InvalidArgument(
+                "--rename-threshold must be between 0 and 1".to_string(),
+            ));
+        }
+        check_input(&self.repo)?;
+        let from = git::resolve(&self.repo, &self.from)?;
+        let to = git::resolve(&self.repo, &self.to)?;
+        let before = self.extract_at(&from)?;
+        let mut after = self.extract_at(&to)?;
+        let line_maps = git::line_maps(&self.repo, &from, &to)?;
+
+        let mut matched = 0;
+        let mut records: Vec<MetricsRow> = Vec::new();
+        let mut removed: Vec<&MetricsRow> = Vec::new();
+        for (key, rows) in &before {
+            let Some(candidates) = after.get_mut(key) else {
+                removed.extend(rows);
+                continue;
+            };
+            let line_map = line_maps.get(&key.0);
+            for (at, row) in rows.iter().enumerate() {
+                let Some(index) = closest(row, candidates, line_map) else {
+                    removed.extend(&rows[at..]);
+                    break;
+                };
+                let counterpart = candidates.swap_remove(index);
+                matched += 1;
+
+                let record = pair(row, &counterpart, ["before", "after"]);
+                if changed(&record) {
+                    records.push(annotate(record, &counterpart, "modified", None));
+                }
+            }
+        }
+
+        let modified = records.len();
+        let added: Vec<MetricsRow> = after.into_values().flatten().collect();
+        let pairs = if self.no_renames {
+            Vec::new()
+        } else {
+            self.match_renames(&removed, &added)
+        };
+        let mut removed_used = vec![false; removed.len()];
+        let mut added_used = vec![false; added.len()];
+        for &(r, a, similarity) in &pairs {
+            let (row, counterpart) = (removed[r], &added[a]);
+            let status = if row["name"] == counterpart["name"] {
+                "moved"
+            } else {
+                "renamed"
+            };
+            let record = pair(row, counterpart, ["before", "after"]);
+            records.push(annotate(record, counterpart, status, Some(similarity)));
+            removed_used[r] = true;
+            added_used[a] = true;
+        }
+        // The functions left over are gone from one side
+        for (row, _) in removed.iter().zip(removed_used).filter(|(_, used)| !used) {
+            records.push(unpaired(row, "removed"));
+        }
+        for (row, _) in added.iter().zip(added_used).filter(|(_, used)| !used) {
+            records.push(unpaired(row, "added"));
+        }
+        info!(
+            "Matched {} functions between {} and {}, {} changed, {} renamed or moved, {} removed or added",
+            matched + pairs.len(),
+            self.from,
+            self.to,
+            modified,
+            pairs.len(),
+            records.len() - modified - pairs.len()
+        );
+
+        let mut writer: Box<dyn Write> = match &self.output_path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match self.fmt.as_str() {
+            "csv" => write_csv(writer, &records)?,
+            "json" => {
+                serde_json::to_writer_pretty(&mut writer, &records)?;
+                writeln!(writer)?;
+            }
+            _ => {
+                unreachable!("Invalid format provided.")
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pairs the removed functions with the added ones they were most likely renamed or
+    /// moved to, most similar first, as long as they are similar enough. Pairs are given
+    /// as the indices of both functions and their similarity
+    fn match_renames(
+        &self,
+        removed: &[&MetricsRow],
+        added: &[MetricsRow],
+    ) -> Vec<(usize, usize, f64)> {
+        let removed: Vec<Fingerprint> = removed.iter().map(|row| Fingerprint::of(row)).collect();
+        let added: Vec<Fingerprint> = added.iter().map(Fingerprint::of).collect();
+
+        // The least body similarity that can still reach the threshold with identical names
+        let least = (self.rename_threshold - (1.0 - BODY_WEIGHT)) / BODY_WEIGHT;
+        let mut candidates: Vec<(usize, usize, f64)> = removed
+            .par_iter()
+            .enumerate()
+            .flat_map_iter(|(r, before)| {
+                added.iter().enumerate().filter_map(move |(a, after)| {
+                    let similarity = before.similarity(after, least)?;
+                    (similarity >= self.rename_threshold).then_some((r, a, similarity))
+                })
+            })
+            .collect();
+        candidates.sort_by(|x, y| y.2.total_cmp(&x.2).then((x.0, x.1).cmp(&(y.0, y.1))));
+
+        let mut removed_used = vec![false; removed.len()];
+        let mut added_used = vec![false; added.len()];
+        let mut pairs = Vec::new();
+        for (r, a, similarity) in candidates {
+            if removed_used[r] || added_used[a] {
+                continue;
+            }
+            removed_used[r] = true;
+            added_used[a] = true;
+            pairs.push((r, a, similarity));
+        }
+        pairs
+    }
+
+    /// Extracts the metrics of every function at `commit`, keyed by its path within the
+    /// repository and its name
+    fn extract_at(&self, commit: &str) -> Result<Index, CliError> {
+        let tree = git::checkout(&self.repo, commit)?;
+        let options = ExtractOptions {
+            extended: self.extended,
+            // The bodies of the functions are compared to match renamed ones
+            with_source: !self.no_renames,
+            ..ExtractOptions::new(&self.discovery, &self.language)
+        };
+
+        let mut index = Index::new();
+        for mut row in extract_dir(tree.path(), &options)? {
+            let name = match row.get("name").and_then(Value::as_str) {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => continue,
+            };
+            let file = row
+                .get("source_file")
+                .and_then(Value::as_str)
+                .map(|file| git::relative(Path::new(file), tree.path()))
+                .unwrap_or_default();
+            row.insert("source_file".to_string(), json!(file));
+            index.entry((file, name)).or_default().push(row);
+        }
+        Ok(index)
+    }
+}
+
+/// What a function body is compared by: its shingles, runs of three tokens, with the name
+/// of the function masked so renaming it, e.g. in a recursive call, changes nothing
+struct Fingerprint {
+    name: String,
+    shingles: HashSet<u64>,
+}
+
+impl Fingerprint {
+    fn of(row: &MetricsRow) -> Self {
+        let name = simple_name(row.get("name").and_then(Value::as_str).unwrap_or(""));
+        let source = row.get("source").and_then(Value::as_str).unwrap_or("");
+
+        let mut tokens: Vec<&str> = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+            rest = &rest[start..];
+            let word = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let end = match word {
+                0 => rest.chars().next().map_or(1, char::len_utf8),
+                _ => word,
+            };
+            let token = &rest[..end];
+            tokens.push(if token == name { "\0" } else { token });
+            rest = &rest[end..];
+        }
+
+        let shingles = tokens
+            .windows(3.min(tokens.len()).max(1))
+            .map(|window| {
+                let mut hasher = DefaultHasher::new();
+                window.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        Fingerprint {
+            name: name.to_string(),
+            shingles,
+        }
+    }
+
+    /// The weighted similarity of the bodies and names, `None` when the bodies are less
+    /// similar than `least`
+    fn similarity(&self, other: &Fingerprint, least: f64) -> Option<f64> {
+        let (small, large) = if self.shingles.len() <= other.shingles.len() {
+            (&self.shingles, &other.shingles)
+        } else {
+            (&other.shingles, &self.shingles)
+        };
+        if large.is_empty() || (small.len() as f64) < least * large.len() as f64 {
+            return None;
+        }
+        let shared = small
+            .iter()
+            .filter(|shingle| large.contains(shingle))
+            .count();
+        let body = shared as f64 / (small.len() + large.len() - shared) as f64;
+        if body < least {
+            return None;
+        }
+        Some(BODY_WEIGHT * body + (1.0 - BODY_WEIGHT) * name_similarity(&self.name, &other.name))
+    }
+}
+
+/// One minus the edit distance between the names, relative to the longer one
+fn name_similarity(a: &str, b: &str) -> f64 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(x != y);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+/// Adds how a paired function changed to its record: the name it has afterwards, whether
+/// it was modified, renamed or moved, and how similar it is for renamed and moved ones
+fn annotate(
+    record: MetricsRow,
+    after: &MetricsRow,
+    status: &str,
+    similarity: Option<f64>,
+) -> MetricsRow {
+    let mut annotated = MetricsRow::new();
+    for (column, value) in record {
+        let name = column == "name";
+        annotated.insert(column, value);
+        if name {
+            annotated.insert("after_name".to_string(), after["name"].clone());
+            annotated.insert("status".to_string(), json!(status));
+            annotated.insert("similarity".to_string(), json!(similarity));
+        }
+    }
+    annotated
+}
+
+/// The record of a function only found on one side, `removed` from the first revision or
+/// `added` in the second, with the columns of the other side left empty
+fn unpaired(row: &MetricsRow, status: &str) -> MetricsRow {
+    let gone = if status == "removed" {
+        "after"
+    } else {
+        "before"
+    };
+    let mut record = pair(row, row, ["before", "after"]);
+    for (column, value) in record.iter_mut() {
+        if column.starts_with(&format!("{}_", gone))
+            || column.ends_with(&format!("_{}", gone))
+            || column.ends_with("_delta")
+        {
+            *value = Value::Null;
+        }
+    }
+    let after = if status == "removed" {
+        json!({ "name": null })
+    } else {
+        json!({ "name": row["name"] })
+    };
+    let after = after.as_object().cloned().unwrap_or_default();
+    annotate(record, &after, status, None)
+}
+
+/// The candidate starting closest to where `row` moved to, for functions sharing a name
+/// within a file such as overloads
+fn closest(
+    row: &MetricsRow,
+    candidates: &[MetricsRow],
+    line_map: Option<&LineMap>,
+) -> Option<usize> {
+    let start =
+        |row: &MetricsRow| row.get("start_line").and_then(Value::as_u64).unwrap_or(0) as usize;
+    let moved = match line_map {
+        Some(line_map) => line_map.map(start(row)),
+        None => start(row),
+    };
+    candidates
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, candidate)| start(candidate).abs_diff(moved))
+        .map(|(index, _)| index)
+}
+
+/// Whether any metric of a paired record differs between the revisions
+fn changed(record: &MetricsRow) -> bool {
+    record
+        .iter()
+        .filter(|(column, _)| column.ends_with("_delta"))
+        .any(|(_, delta)| delta.as_f64().is_some_and(|delta| delta != 0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(name: &str, source: &str) -> Fingerprint {
+        let row = json!({ "name": name, "source": source });
+        Fingerprint::of(row.as_object().unwrap())
+    }
+
+    #[test]
+    fn names_are_compared_by_edit_distance() {
+        assert_eq!(name_similarity("parse", "parse"), 1.0);
+        assert_eq!(name_similarity("", ""), 1.0);
+        assert_eq!(name_similarity("abcd", "abed"), 0.75);
+        assert_eq!(name_similarity("ab", ""), 0.0);
+    }
+
+    #[test]
+    fn bodies_too_different_to_reach_the_threshold_are_pruned() {
+        let body = "int f(int a) { if (a > 1) { return f(a - 1); } return a; }";
+        let original = fingerprint("f", body);
+        // The name is masked, so a recursive function renamed keeps the same body
+        let renamed = fingerprint("g", &body.replace("f(", "g("));
+        assert_eq!(original.similarity(&renamed, 0.0), Some(BODY_WEIGHT));
+        assert_eq!(original.similarity(&original, 1.0), Some(1.0));
+
+        let other = fingerprint("f", "int f(int a) { while (a) { a--; } return 0; }");
+        let similarity = original.similarity(&other, 0.0).unwrap();
+        assert!(similarity > 1.0 - BODY_WEIGHT && similarity < 1.0);
+        // Below the least body similarity, nothing is computed
+        let body = (similarity - (1.0 - BODY_WEIGHT)) / BODY_WEIGHT;
+        assert_eq!(original.similarity(&other, body + 0.01), None);
+        assert!(original.similarity(&other, body - 0.01).is_some());
+        assert_eq!(original.similarity(&fingerprint("f", ""), 0.5), None);
+    }
+}
@@ -0,0 +1,57 @@
+use clap::Args;
+use color_eyre::Result;
+use std::path::PathBuf;
+
+use crate::errors::CliError;
+use crate::runs::read_run;
+use crate::tui::explore;
+
+#[derive(Args)]
+pub struct TuiCommand {
+    /// Output of the run to explore, either a single output file or the directory a run
+    /// wrote its CSV, JSON or parquet outputs to
+    #[arg(required = true)]
+    run: PathBuf,
+
+    /// Metric columns of the function table, after the name and file of every function
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "cyclomatic,cognitive,nexits,fn_args,loc_sloc,halstead_effort,mi_visual_studio"
+    )]
+    columns: Vec<String>,
+
+    /// Directory the source files of the run are relative to, for showing the source of a
+    /// function. Not needed for runs written with --with-source
+    #[arg(long)]
+    root: Option<PathBuf>,
+}
+
+impl TuiCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        let rows = read_run(&self.run)?;
+        let Some(first) = rows.first() else {
+            return Err(CliError::InvalidArgument(format!(
+                "{} holds no rows",
+                self.run.display()
+            )));
+        };
+        let missing: Vec<&String> = self
+            .columns
+            .iter()
+            .filter(|column| !first.contains_key(*column))
+            .collect();
+        if !missing.is_empty() {
+            return Err(CliError::InvalidArgument(format!(
+                "the rows of {} have no {} column",
+                self.run.display(),
+                missing
+                    .iter()
+                    .map(|c| c.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        explore(rows, self.columns, self.root)
+    }
+}
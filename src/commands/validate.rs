@@ -0,0 +1,142 @@
+use clap::Args;
+use color_eyre::Result;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::errors::{check_input, CliError};
+use crate::runs::{read_rows, run_files};
+use crate::schema::{ColumnKind, Schema, SCHEMA, SCHEMA_VERSION};
+
+#[derive(Args)]
+pub struct ValidateCommand {
+    /// Output file to check against the current schema, or the directory a `source` run
+    /// wrote to in order to check every output in it
+    path: PathBuf,
+
+    /// Check the extended columns. Taken from the `schema.json` of the run when there is
+    /// one and guessed from the columns otherwise
+    #[arg(long)]
+    extended: Option<bool>,
+}
+
+impl ValidateCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        check_input(&self.path)?;
+        let directory = if self.path.is_dir() {
+            self.path.clone()
+        } else {
+            self.path.parent().unwrap_or(Path::new(".")).to_path_buf()
+        };
+        let recorded = Schema::read(&directory)?;
+        match &recorded {
+            Some(schema) if schema.schema_version != SCHEMA_VERSION => {
+                return Err(CliError::SchemaMismatch(format!(
+                    "{} was written by archaeo {} with schema version {}, the current version is {}",
+                    directory.join(SCHEMA).display(),
+                    schema.archaeo_version,
+                    schema.schema_version,
+                    SCHEMA_VERSION
+                )))
+            }
+            Some(_) => {}
+            None => warn!(
+                "No {} found in {}, only checking columns",
+                SCHEMA,
+                directory.display()
+            ),
+        }
+
+        let files = run_files(&self.path);
+        let mut invalid = 0;
+        for file in &files {
+            let problems = self.check(file, recorded.as_ref())?;
+            if problems.is_empty() {
+                println!("{}: ok", file.display());
+            } else {
+                invalid += 1;
+                for problem in problems {
+                    println!("{}: {}", file.display(), problem);
+                }
+            }
+        }
+        info!("Validated {} files", files.len());
+
+        if invalid > 0 {
+            return Err(CliError::SchemaMismatch(format!(
+                "{} of {} files don't match schema version {}",
+                invalid,
+                files.len(),
+                SCHEMA_VERSION
+            )));
+        }
+        Ok(())
+    }
+
+    /// Everything about `file` that doesn't match the current schema
+    fn check(&self, file: &Path, recorded: Option<&Schema>) -> Result<Vec<String>, CliError> {
+        let columns = match columns(file)? {
+            Some(columns) => columns,
+            None => return Ok(vec!["no header or rows to check".to_string()]),
+        };
+        let extended = self
+            .extended
+            .or(recorded.map(|schema| schema.extended))
+            .unwrap_or_else(|| columns.iter().any(|column| column == "cyclomatic_sum"));
+        let schema = Schema::current(extended)
+            .select(recorded.map_or(&[][..], |schema| &schema.metrics[..]))?;
+
+        let mut problems = Vec::new();
+        let missing: Vec<&str> = schema
+            .columns
+            .iter()
+            .filter(|column| !columns.contains(column))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            problems.push(format!("missing columns {}", missing.join(", ")));
+        } else if columns[..schema.columns.len()] != schema.columns[..] {
+            problems.push(format!(
+                "columns out of order, expected them to start with {}",
+                schema.columns.join(", ")
+            ));
+        }
+
+        for (index, row) in read_rows(file)?.iter().enumerate() {
+            let bad = schema.types.iter().find_map(|column| {
+                let value = row.get(&column.name)?;
+                let numeric = matches!(column.kind, ColumnKind::Integer | ColumnKind::Float);
+                match value {
+                    Value::Null if !column.nullable => Some("a null"),
+                    Value::Null => None,
+                    _ if numeric && !value.is_number() => Some("a non-numeric"),
+                    _ => None,
+                }
+                .map(|problem| (problem, column))
+            });
+            if let Some((problem, column)) = bad {
+                problems.push(format!(
+                    "row {} has {} {}: {}",
+                    index + 1,
+                    problem,
+                    column.name,
+                    row[&column.name]
+                ));
+                break;
+            }
+        }
+        Ok(problems)
+    }
+}
+
+/// The columns of an output in order, from the header of a CSV or the first row of JSON
+fn columns(file: &Path) -> Result<Option<Vec<String>>, CliError> {
+    if file.extension().is_some_and(|ext| ext == "csv") {
+        let mut reader = csv::Reader::from_path(file)?;
+        let headers: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+        return Ok((!headers.is_empty()).then_some(headers));
+    }
+    Ok(read_rows(file)?
+        .first()
+        .map(|row| row.keys().cloned().collect()))
+}
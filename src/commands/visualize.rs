@@ -0,0 +1,209 @@
+use clap::Args;
+use color_eyre::Result;
+use rayon::prelude::*;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::commands::stats::StatsCommand;
+use crate::discovery::DiscoveryArgs;
+use crate::errors::CliError;
+use crate::extract::{extract_dir, ExtractOptions};
+use crate::git;
+use crate::language::LanguageArgs;
+use crate::treemap::{self, Legend, Tile};
+
+#[derive(Args)]
+pub struct VisualizeCommand {
+    /// Path to the directory to draw
+    #[arg(short, long, required = true)]
+    path: PathBuf,
+
+    /// File to write the SVG treemap to
+    #[arg(short, long, required = true)]
+    output_path: PathBuf,
+
+    /// What colours a file: a metric column combined over its functions, or `churn`, the
+    /// commits touching it. Maintainability index columns (`mi_*`) colour low values hot
+    #[arg(long, default_value = "cyclomatic")]
+    color: String,
+
+    /// How the metric of the functions of a file is combined
+    /// Options: max, mean, sum
+    #[arg(long, default_value = "max", value_parser = clap::builder::PossibleValuesParser::new(["max", "mean", "sum"]))]
+    aggregate: String,
+
+    /// Only count churn from commits after this date, e.g. "1 year ago" or 2024-01-01
+    #[arg(long)]
+    since: Option<String>,
+
+    #[command(flatten)]
+    language: LanguageArgs,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+}
+
+const CHURN: &str = "churn";
+
+impl VisualizeCommand {
+    pub fn execute(self) -> Result<(), CliError> {
+        if !self.path.is_dir() {
+            return Err(CliError::InvalidInputPath(format!(
+                "{} is not a directory",
+                self.path.display()
+            )));
+        }
+        let path = self.path.canonicalize()?;
+        let colours = match self.color.as_str() {
+            CHURN => self.churn(&path)?,
+            _ => self.metric(&path)?,
+        };
+
+        // Every file is sized by its lines of code, not just those of its functions
+        let reader = self.language.reader()?;
+        let sizes: Vec<(String, f64)> = self
+            .discovery
+            .discover(&path)?
+            .par_iter()
+            .filter_map(|fp| {
+                let stats = StatsCommand::file_stats(fp, &reader)?;
+                Some((git::relative(fp, &path), stats.sloc))
+            })
+            .collect();
+
+        let values: Vec<f64> = sizes
+            .iter()
+            .filter_map(|(file, _)| colours.get(file).copied())
+            .collect();
+        let low = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let reversed = self.color.starts_with("mi_");
+        let heat = |value: f64| {
+            let heat = if high > low {
+                (value - low) / (high - low)
+            } else {
+                0.0
+            };
+            if reversed {
+                1.0 - heat
+            } else {
+                heat
+            }
+        };
+
+        let tiles: Vec<Tile> = sizes
+            .iter()
+            .map(|(file, sloc)| {
+                let value = colours.get(file).copied();
+                Tile {
+                    label: file.clone(),
+                    size: *sloc,
+                    heat: value.map(heat).unwrap_or(0.0),
+                    tooltip: format!(
+                        "{}\nsloc: {}\n{}: {}",
+                        file,
+                        sloc,
+                        self.legend_label(),
+                        value.map_or("no functions".to_string(), |value| value.to_string())
+                    ),
+                }
+            })
+            .collect();
+
+        let (cool, hot) = match (values.is_empty(), reversed) {
+            (true, _) => (0.0, 0.0),
+            (false, true) => (high, low),
+            (false, false) => (low, high),
+        };
+        let legend = Legend {
+            label: self.legend_label(),
+            cool,
+            hot,
+        };
+        let title = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        let mut writer = BufWriter::new(File::create(&self.output_path)?);
+        treemap::write_svg(&mut writer, &title, &legend, &tiles)?;
+        info!(
+            "Drew {} files into {}",
+            tiles.len(),
+            self.output_path.display()
+        );
+        Ok(())
+    }
+
+    fn legend_label(&self) -> String {
+        match self.color.as_str() {
+            CHURN => match &self.since {
+                Some(since) => format!("commits since {}", since),
+                None => "commits".to_string(),
+            },
+            _ => format!("{} ({} per file)", self.color, self.aggregate),
+        }
+    }
+
+    /// The commits touching every file, keyed by its path below `path`
+    fn churn(&self, path: &Path) -> Result<BTreeMap<String, f64>, CliError> {
+        let root = git::toplevel(path)?;
+        let root = root.canonicalize().unwrap_or(root);
+        let churn = git::churn(&root, self.since.as_deref())?;
+        let prefix = git::relative(path, &root);
+        Ok(churn
+            .into_iter()
+            .filter_map(|(file, churn)| {
+                let file = if prefix.is_empty() {
+                    file
+                } else {
+                    file.strip_prefix(&format!("{}/", prefix))?.to_string()
+                };
+                Some((file, churn.commits as f64))
+            })
+            .collect())
+    }
+
+    /// The metric combined over the functions of every file, keyed by its path below `path`
+    fn metric(&self, path: &Path) -> Result<BTreeMap<String, f64>, CliError> {
+        let options = ExtractOptions::new(&self.discovery, &self.language);
+        let rows = extract_dir(path, &options)?;
+        if rows
+            .first()
+            .is_some_and(|row| !row.get(&self.color).is_some_and(Value::is_number))
+        {
+            return Err(CliError::InvalidArgument(format!(
+                "{} is neither churn nor a numeric metric column",
+                self.color
+            )));
+        }
+
+        let mut files: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        for row in &rows {
+            let (Some(file), Some(value)) = (
+                row.get("source_file").and_then(Value::as_str),
+                row.get(&self.color).and_then(Value::as_f64),
+            ) else {
+                continue;
+            };
+            files
+                .entry(git::relative(Path::new(file), path))
+                .or_default()
+                .push(value);
+        }
+        Ok(files
+            .into_iter()
+            .map(|(file, values)| {
+                let value = match self.aggregate.as_str() {
+                    "mean" => values.iter().sum::<f64>() / values.len() as f64,
+                    "sum" => values.iter().sum(),
+                    _ => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                };
+                (file, value)
+            })
+            .collect())
+    }
+}
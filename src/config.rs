@@ -0,0 +1,70 @@
+//! The `archaeo.toml` project configuration `init` scaffolds, filling in the options of
+//! `source` the command line leaves out
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::discovery::parse_glob;
+use crate::errors::CliError;
+use crate::gate::{parse_rule, Rule};
+use crate::score::Score;
+
+/// Name of the configuration read from the working directory
+pub const CONFIG: &str = "archaeo.toml";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// File extensions to process when walking a directory
+    pub extensions: Option<Vec<String>>,
+    /// Globs of paths to leave out
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Skip binary, generated, minified and amalgamated files
+    pub skip_generated: Option<bool>,
+    /// `--fail-on` rules
+    #[serde(default)]
+    pub fail_on: Vec<String>,
+    /// `--score` formulas by the name of their column
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scores: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Reads `path`, or `archaeo.toml` in the working directory when it exists
+    pub fn find(path: Option<&Path>) -> Result<Option<(PathBuf, Self)>, CliError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None if Path::new(CONFIG).is_file() => PathBuf::from(CONFIG),
+            None => return Ok(None),
+        };
+        let config = Self::from_file(&path)?;
+        Ok(Some((path, config)))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, CliError> {
+        let invalid =
+            |message: String| CliError::InvalidArgument(format!("{}: {}", path.display(), message));
+        let config: Self =
+            toml::from_str(&fs::read_to_string(path)?).map_err(|err| invalid(err.to_string()))?;
+        for pattern in &config.exclude {
+            parse_glob(pattern).map_err(invalid)?;
+        }
+        config.rules().map_err(invalid)?;
+        config.scores().map_err(invalid)?;
+        Ok(config)
+    }
+
+    pub fn rules(&self) -> Result<Vec<Rule>, String> {
+        self.fail_on.iter().map(|rule| parse_rule(rule)).collect()
+    }
+
+    pub fn scores(&self) -> Result<Vec<Score>, String> {
+        self.scores
+            .iter()
+            .map(|(name, formula)| Score::new(name, formula))
+            .collect()
+    }
+}
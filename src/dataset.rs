@@ -0,0 +1,455 @@
+//! Helpers for turning extracted metrics into ML datasets
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+
+use crate::errors::CliError;
+use crate::output::parse_field;
+
+/// Assigns rows to train/validation/test splits by hashing them with a seed, so the
+/// same row always lands in the same split for a given seed
+#[derive(Debug, Clone)]
+pub struct Split {
+    /// Upper bound of every split on [0, 1), paired with its name
+    bounds: Vec<(&'static str, f64)>,
+    by_function: bool,
+    seed: u64,
+}
+
+impl Split {
+    /// `ratios` are the train, validation and test shares in that order. Two ratios split
+    /// into train and test only
+    pub fn new(ratios: &[f64], split_by: &str, seed: u64) -> Result<Self, CliError> {
+        let names: &[&'static str] = match ratios.len() {
+            1 => &["train"],
+            2 => &["train", "test"],
+            3 => &["train", "validation", "test"],
+            _ => {
+                return Err(CliError::InvalidArgument(
+                    "--split takes one to three ratios, e.g. 0.8,0.1,0.1".to_string(),
+                ))
+            }
+        };
+        let total: f64 = ratios.iter().sum();
+        if ratios.iter().any(|ratio| *ratio < 0.0) || total <= 0.0 {
+            return Err(CliError::InvalidArgument(
+                "--split ratios must be positive".to_string(),
+            ));
+        }
+
+        let mut upper = 0.0;
+        let bounds = names
+            .iter()
+            .zip(ratios)
+            .map(|(name, ratio)| {
+                upper += ratio / total;
+                (*name, upper)
+            })
+            .collect();
+
+        Ok(Self {
+            bounds,
+            by_function: split_by == "function",
+            seed,
+        })
+    }
+
+    /// The split a row belongs to. When splitting by file every row of a file shares one
+    /// split so functions of the same file never leak across splits
+    pub fn assign(&self, source_file: &str, name: Option<&str>, start_line: usize) -> &'static str {
+        let mut hasher = Sha256::new();
+        hasher.update(self.seed.to_le_bytes());
+        hasher.update(source_file.as_bytes());
+        if self.by_function {
+            hasher.update([0]);
+            hasher.update(name.unwrap_or_default().as_bytes());
+            hasher.update(start_line.to_le_bytes());
+        }
+        let digest = hasher.finalize();
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        let position = (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64;
+
+        self.bounds
+            .iter()
+            .find(|(_, upper)| position < *upper)
+            .or(self.bounds.last())
+            .map(|(name, _)| *name)
+            .unwrap_or("train")
+    }
+}
+
+/// Columns rows can be joined with labels on
+pub const JOIN_COLUMNS: [&str; 4] = ["source_file", "name", "signature", "start_line"];
+
+/// Externally supplied labels merged into the rows they match. Every other column of
+/// the labels file is added to the output, empty for rows without a label
+#[derive(Debug)]
+pub struct Labels {
+    join_on: Vec<String>,
+    columns: Vec<String>,
+    rows: HashMap<Vec<String>, Vec<Value>>,
+}
+
+impl Labels {
+    pub fn from_file(path: &Path, join_on: &[String]) -> Result<Self, CliError> {
+        if let Some(column) = join_on
+            .iter()
+            .find(|column| !JOIN_COLUMNS.contains(&column.as_str()))
+        {
+            return Err(CliError::InvalidArgument(format!(
+                "can't join labels on {}, expected one of {}",
+                column,
+                JOIN_COLUMNS.join(", ")
+            )));
+        }
+
+        let mut reader = csv::Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let mut key_indices = Vec::new();
+        for column in join_on {
+            match headers.iter().position(|header| header == column) {
+                Some(index) => key_indices.push(index),
+                None => {
+                    return Err(CliError::InvalidArgument(format!(
+                        "{} has no {} column to join on",
+                        path.display(),
+                        column
+                    )))
+                }
+            }
+        }
+        let value_indices: Vec<usize> = (0..headers.len())
+            .filter(|index| !key_indices.contains(index))
+            .collect();
+
+        let mut rows = HashMap::new();
+        for record in reader.records() {
+            let record = record?;
+            let key: Vec<String> = key_indices
+                .iter()
+                .map(|index| record.get(*index).unwrap_or_default().to_string())
+                .collect();
+            let values = value_indices
+                .iter()
+                .map(|index| parse_field(record.get(*index).unwrap_or_default()))
+                .collect();
+            if rows.insert(key.clone(), values).is_some() {
+                warn!("Duplicate label for {:?}, keeping the last one", key);
+            }
+        }
+
+        Ok(Self {
+            join_on: join_on.to_vec(),
+            columns: value_indices
+                .iter()
+                .map(|index| headers[*index].to_string())
+                .collect(),
+            rows,
+        })
+    }
+
+    /// The label columns of the row identified by the join columns
+    pub fn lookup(
+        &self,
+        source_file: &str,
+        name: Option<&str>,
+        signature: Option<&str>,
+        start_line: usize,
+    ) -> Vec<(String, Value)> {
+        let key: Vec<String> = self
+            .join_on
+            .iter()
+            .map(|column| match column.as_str() {
+                "source_file" => source_file.to_string(),
+                "name" => name.unwrap_or_default().to_string(),
+                "signature" => signature.unwrap_or_default().to_string(),
+                _ => start_line.to_string(),
+            })
+            .collect();
+
+        match self.rows.get(&key) {
+            Some(values) => self.columns.iter().cloned().zip(values.clone()).collect(),
+            None => self
+                .columns
+                .iter()
+                .map(|column| (column.clone(), Value::Null))
+                .collect(),
+        }
+    }
+}
+
+/// The files and functions named by security advisories
+#[derive(Debug)]
+struct Advisory {
+    /// Path of a file or directory, matched against the end of the source files
+    path: String,
+    /// Names of the affected functions, every function of the path when empty
+    symbols: Vec<String>,
+    /// Identifiers of the advisory, e.g. its CVE and GHSA ids
+    ids: Vec<String>,
+}
+
+/// Security advisories joined with the rows of the files and functions they name, in
+/// `advisories` and `advisory_count` columns
+#[derive(Debug)]
+pub struct Advisories {
+    advisories: Vec<Advisory>,
+}
+
+impl Advisories {
+    /// Reads either a JSON object mapping paths, or `path#function`, to lists of advisory
+    /// ids, or OSV records with the files and functions in the `path` and `symbols` of the
+    /// `ecosystem_specific.imports` of what they affect. OSV records can come alone, in an
+    /// array or in the `vulns` of an osv.dev query response
+    pub fn from_file(path: &Path) -> Result<Self, CliError> {
+        let invalid =
+            |reason: String| CliError::InvalidArgument(format!("{}: {}", path.display(), reason));
+        let value: Value = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| invalid(err.to_string()))?;
+        let records = match value {
+            Value::Object(mut object) if object.contains_key("vulns") => {
+                match object.remove("vulns") {
+                    Some(Value::Array(records)) => records,
+                    _ => return Err(invalid("`vulns` is not an array".to_string())),
+                }
+            }
+            Value::Object(object) if object.contains_key("id") => vec![Value::Object(object)],
+            Value::Object(object) => return Self::from_map(object).map_err(invalid),
+            Value::Array(records) => records,
+            _ => return Err(invalid("expected an object or an array".to_string())),
+        };
+
+        let mut advisories = Vec::new();
+        for record in &records {
+            let Some(id) = record.get("id").and_then(Value::as_str) else {
+                return Err(invalid("an OSV record has no id".to_string()));
+            };
+            let mut ids = vec![id.to_string()];
+            ids.extend(strings(record.get("aliases")));
+            let imports = record
+                .get("affected")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|affected| affected.pointer("/ecosystem_specific/imports"))
+                .filter_map(Value::as_array)
+                .flatten();
+            for import in imports {
+                let Some(path) = import.get("path").and_then(Value::as_str) else {
+                    continue;
+                };
+                advisories.push(Advisory {
+                    path: path.to_string(),
+                    symbols: strings(import.get("symbols")),
+                    ids: ids.clone(),
+                });
+            }
+        }
+        Ok(Self { advisories })
+    }
+
+    fn from_map(object: serde_json::Map<String, Value>) -> Result<Self, String> {
+        let mut advisories = Vec::new();
+        for (key, ids) in object {
+            if !ids
+                .as_array()
+                .is_some_and(|ids| ids.iter().all(Value::is_string))
+            {
+                return Err(format!("the advisories of {} are not a list of ids", key));
+            }
+            let (path, symbols) = match key.rsplit_once('#') {
+                Some((path, function)) => (path.to_string(), vec![function.to_string()]),
+                None => (key, Vec::new()),
+            };
+            advisories.push(Advisory {
+                path,
+                symbols,
+                ids: strings(Some(&ids)),
+            });
+        }
+        Ok(Self { advisories })
+    }
+
+    /// The ids of the advisories naming the function `name` of `source_file`, or its file
+    pub fn lookup(&self, source_file: &str, name: Option<&str>) -> Vec<String> {
+        let file = format!("/{}", source_file.replace('\\', "/"));
+        let mut ids: Vec<String> = self
+            .advisories
+            .iter()
+            .filter(|advisory| {
+                let path = format!("/{}", advisory.path.trim_matches('/'));
+                file.ends_with(&path) || file.contains(&format!("{}/", path))
+            })
+            .filter(|advisory| {
+                advisory.symbols.is_empty()
+                    || name.is_some_and(|name| {
+                        advisory.symbols.iter().any(|symbol| {
+                            symbol == name
+                                || symbol.ends_with(&format!("::{}", name))
+                                || symbol.ends_with(&format!(".{}", name))
+                        })
+                    })
+            })
+            .flat_map(|advisory| advisory.ids.iter().cloned())
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+}
+
+/// The strings of a JSON array
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn shares(split: &Split, files: usize) -> HashMap<&'static str, usize> {
+        let mut shares = HashMap::new();
+        for file in 0..files {
+            *shares
+                .entry(split.assign(&format!("src/{}.c", file), Some("f"), 1))
+                .or_default() += 1;
+        }
+        shares
+    }
+
+    #[test]
+    fn rows_are_split_by_their_ratios() {
+        let split = Split::new(&[0.8, 0.1, 0.1], "file", 0).unwrap();
+        let counts = shares(&split, 10_000);
+        assert!((7_700..8_300).contains(&counts["train"]), "{:?}", counts);
+        assert!((800..1_200).contains(&counts["validation"]), "{:?}", counts);
+        assert!((800..1_200).contains(&counts["test"]), "{:?}", counts);
+
+        // Ratios are shares of their sum, and two of them leave out validation
+        let scaled = Split::new(&[8.0, 1.0, 1.0], "file", 0).unwrap();
+        assert_eq!(shares(&scaled, 10_000), counts);
+        let halves = shares(&Split::new(&[1.0, 1.0], "file", 0).unwrap(), 1_000);
+        assert_eq!(halves.len(), 2);
+        assert!((400..600).contains(&halves["test"]), "{:?}", halves);
+    }
+
+    #[test]
+    fn splits_are_fixed_by_the_seed() {
+        let split = Split::new(&[0.5, 0.5], "function", 7).unwrap();
+        let again = Split::new(&[0.5, 0.5], "function", 7).unwrap();
+        let reseeded = Split::new(&[0.5, 0.5], "function", 8).unwrap();
+        let assign = |split: &Split| {
+            (0..64)
+                .map(|line| split.assign("a.c", Some("f"), line))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(assign(&split), assign(&again));
+        assert_ne!(assign(&split), assign(&reseeded));
+    }
+
+    #[test]
+    fn functions_of_a_file_share_its_split_unless_split_by_function() {
+        let by_file = Split::new(&[0.5, 0.5], "file", 0).unwrap();
+        let by_function = Split::new(&[0.5, 0.5], "function", 0).unwrap();
+        let splits = |split: &Split| {
+            let mut splits: Vec<&str> = (0..64)
+                .map(|line| split.assign("a.c", Some(&format!("f{}", line)), line))
+                .collect();
+            splits.sort_unstable();
+            splits.dedup();
+            splits
+        };
+        assert_eq!(splits(&by_file).len(), 1);
+        assert_eq!(splits(&by_function), ["test", "train"]);
+    }
+
+    #[test]
+    fn ratios_must_be_one_to_three_positive_shares() {
+        for ratios in [&[][..], &[0.25; 4], &[0.9, -0.1], &[0.0, 0.0]] {
+            assert!(
+                matches!(
+                    Split::new(ratios, "file", 0),
+                    Err(CliError::InvalidArgument(_))
+                ),
+                "{:?}",
+                ratios
+            );
+        }
+    }
+
+    fn labels(csv: &str, join_on: &[&str]) -> Result<Labels, CliError> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, csv.as_bytes()).unwrap();
+        let join_on: Vec<String> = join_on.iter().map(|column| column.to_string()).collect();
+        Labels::from_file(file.path(), &join_on)
+    }
+
+    #[test]
+    fn labels_are_merged_into_the_rows_they_match() {
+        let labels = labels(
+            "source_file,name,buggy,severity\na.c,f,1,high\na.c,g,0,\nb.c,f,2.5,low\n",
+            &["source_file", "name"],
+        )
+        .unwrap();
+        assert_eq!(
+            labels.lookup("a.c", Some("f"), None, 1),
+            [
+                ("buggy".to_string(), json!(1)),
+                ("severity".to_string(), json!("high"))
+            ]
+        );
+        // Empty cells and rows without a label are null
+        assert_eq!(
+            labels.lookup("a.c", Some("g"), None, 1),
+            [
+                ("buggy".to_string(), json!(0)),
+                ("severity".to_string(), Value::Null)
+            ]
+        );
+        assert_eq!(labels.lookup("b.c", Some("f"), None, 1)[0].1, json!(2.5));
+        assert_eq!(
+            labels.lookup("c.c", Some("f"), None, 1),
+            [
+                ("buggy".to_string(), Value::Null),
+                ("severity".to_string(), Value::Null)
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_join_on_lines_and_keep_the_last_duplicate() {
+        let labels = labels(
+            "start_line,name,label\n3,f,old\n3,f,new\n9,f,other\n",
+            &["name", "start_line"],
+        )
+        .unwrap();
+        assert_eq!(
+            labels.lookup("a.c", Some("f"), None, 3),
+            [("label".to_string(), json!("new"))]
+        );
+        assert_eq!(
+            labels.lookup("b.c", Some("f"), None, 9),
+            [("label".to_string(), json!("other"))]
+        );
+    }
+
+    #[test]
+    fn labels_need_the_columns_they_are_joined_on() {
+        let missing = labels("name,label\nf,1\n", &["source_file", "name"]).unwrap_err();
+        assert!(missing.to_string().contains("has no source_file column"));
+        let unknown = labels("name,label\nf,1\n", &["kind"]).unwrap_err();
+        assert!(unknown.to_string().contains("can't join labels on kind"));
+    }
+}
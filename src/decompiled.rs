@@ -0,0 +1,251 @@
+//! Preprocessing of decompiler pseudo-C (Ghidra, IDA/Hex-Rays, Binary Ninja) so it parses
+//! as plain C.
+//!
+//! Everything is blanked out in place with spaces, keeping newlines, so line numbers and
+//! line lengths still match the original file.
+
+use std::path::Path;
+
+/// Calling conventions and annotations decompilers emit that are not valid C
+const QUIRK_KEYWORDS: &[&str] = &[
+    "__cdecl",
+    "__clrcall",
+    "__fastcall",
+    "__hidden",
+    "__high",
+    "__noreturn",
+    "__pascal",
+    "__pure",
+    "__ptr32",
+    "__ptr64",
+    "__return_ptr",
+    "__stdcall",
+    "__struct_ptr",
+    "__thiscall",
+    "__unaligned",
+    "__usercall",
+    "__userpurge",
+    "__vectorcall",
+    "__cppobj",
+];
+
+/// Smooths over the pseudo-C quirks of decompiler output:
+///
+/// - the banner comments ahead of the first declaration and IDA's `//----- (address) ---`
+///   function separators are removed
+/// - calling conventions and annotations such as `__fastcall` or `__spoils<ecx>` are removed
+/// - IDA's register locations (`a1@<ecx>`) are removed
+/// - `::` in qualified names becomes `__`
+pub fn preprocess(source: &[u8]) -> Vec<u8> {
+    let mut out = source.to_vec();
+    strip_banner(&mut out);
+
+    let mut i = 0;
+    while i < out.len() {
+        let c = out[i];
+        if c == b'/' && out.get(i + 1) == Some(&b'/') {
+            let end = line_end(&out, i);
+            if out[i..end].starts_with(b"//-----") {
+                blank(&mut out, i, end);
+            }
+            i = end;
+        } else if c == b'@' && out.get(i + 1) == Some(&b'<') {
+            let end = closing(&out, i + 1, b'<', b'>');
+            blank(&mut out, i, end);
+            i = end;
+        } else if c == b':' && out.get(i + 1) == Some(&b':') {
+            out[i] = b'_';
+            out[i + 1] = b'_';
+            i += 2;
+        } else if is_ident_start(c) && (i == 0 || !is_ident(out[i - 1])) {
+            let end = (i..out.len())
+                .find(|&j| !is_ident(out[j]))
+                .unwrap_or(out.len());
+            let word = &out[i..end];
+            if QUIRK_KEYWORDS.iter().any(|k| k.as_bytes() == word) {
+                blank(&mut out, i, end);
+            } else if word == b"__spoils" && out.get(end) == Some(&b'<') {
+                let close = closing(&out, end, b'<', b'>');
+                blank(&mut out, i, close);
+                i = close;
+                continue;
+            } else if word == b"__asm" {
+                // Inline assembly blocks have no C equivalent
+                let brace = (end..out.len()).find(|&j| !out[j].is_ascii_whitespace());
+                if let Some(brace) = brace.filter(|&j| out[j] == b'{') {
+                    let close = closing(&out, brace, b'{', b'}');
+                    blank(&mut out, i, close);
+                    i = close;
+                    continue;
+                }
+            }
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+
+    out
+}
+
+/// Name of the binary a decompiled file came from, following the `<binary>[@<function>].<ext>`
+/// naming convention, e.g. `libfoo.so@FUN_00401000.c` or `libfoo.so.c`
+pub fn binary_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match stem.split_once('@') {
+        Some((binary, _)) => binary.to_string(),
+        None => stem,
+    }
+}
+
+/// Blanks the comments ahead of the first non-comment token
+fn strip_banner(out: &mut [u8]) {
+    let mut i = 0;
+    loop {
+        while i < out.len() && out[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if out[i..].starts_with(b"//") {
+            let end = line_end(out, i);
+            blank(out, i, end);
+            i = end;
+        } else if out[i..].starts_with(b"/*") {
+            let end = out[i + 2..]
+                .windows(2)
+                .position(|w| w == b"*/")
+                .map_or(out.len(), |p| i + 2 + p + 2);
+            blank(out, i, end);
+            i = end;
+        } else {
+            return;
+        }
+    }
+}
+
+/// Index just past the `close` matching the `open` at `start`, or the end of the source
+fn closing(out: &[u8], start: usize, open: u8, close: u8) -> usize {
+    let mut depth = 0;
+    for (j, &c) in out.iter().enumerate().skip(start) {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return j + 1;
+            }
+        }
+    }
+    out.len()
+}
+
+fn line_end(out: &[u8], start: usize) -> usize {
+    out[start..]
+        .iter()
+        .position(|&c| c == b'\n')
+        .map_or(out.len(), |p| start + p)
+}
+
+fn blank(out: &mut [u8], start: usize, end: usize) {
+    for c in &mut out[start..end] {
+        if *c != b'\n' && *c != b'\r' {
+            *c = b' ';
+        }
+    }
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+fn is_ident(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// Function address embedded in a decompiler-generated name such as `FUN_00401000`,
+/// `sub_401000` or `main@0x401000`, as `0x`-prefixed lowercase hex
+pub fn address(name: &str) -> Option<String> {
+    name.split(['@', '_', '-', '.']).find_map(|token| {
+        let digits = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .or_else(|| {
+                // Bare hex needs to look like an address rather than a word
+                (token.len() >= 6 && token.bytes().any(|c| c.is_ascii_digit())).then_some(token)
+            })?;
+        (!digits.is_empty() && digits.bytes().all(|c| c.is_ascii_hexdigit()))
+            .then(|| format!("0x{}", digits.to_ascii_lowercase()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocessed(source: &str) -> String {
+        let out = preprocess(source.as_bytes());
+        assert_eq!(out.len(), source.len());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn banners_and_separators_are_blanked_keeping_the_lines() {
+        let source = "/* This file was generated by the Hex-Rays decompiler. */\n// Tool: IDA\n\n//----- (00401000) ----\nint f() { return 0; } // kept\n";
+        let out = preprocessed(source);
+        assert_eq!(out.lines().count(), source.lines().count());
+        assert!(out.lines().take(4).all(|line| line.trim().is_empty()));
+        assert_eq!(out.lines().nth(4), Some("int f() { return 0; } // kept"));
+    }
+
+    #[test]
+    fn quirks_are_blanked_until_the_source_parses_as_c() {
+        assert_eq!(
+            preprocessed("int __fastcall f(int a1@<ecx>, __int64 a2)"),
+            format!(
+                "int {} f(int a1{}, __int64 a2)",
+                " ".repeat(10),
+                " ".repeat(6)
+            )
+        );
+        assert_eq!(
+            preprocessed("void __spoils<ecx,edx> g(void)"),
+            format!("void {} g(void)", " ".repeat(17))
+        );
+        assert_eq!(
+            preprocessed("int CFoo::bar(CFoo *this)"),
+            "int CFoo__bar(CFoo *this)"
+        );
+        // Keywords only match as whole words
+        assert_eq!(preprocessed("int my__cdecl;"), "int my__cdecl;");
+    }
+
+    #[test]
+    fn inline_assembly_blocks_are_blanked() {
+        assert_eq!(
+            preprocessed("void f() {\n  __asm { mov eax, {1} }\n  return;\n}"),
+            format!("void f() {{\n{}\n  return;\n}}", " ".repeat(24))
+        );
+        // Without a block there is nothing to blank
+        assert_eq!(preprocessed("__asm(\"nop\");"), "__asm(\"nop\");");
+    }
+
+    #[test]
+    fn binaries_are_named_after_the_file() {
+        assert_eq!(
+            binary_name(Path::new("out/libfoo.so@FUN_00401000.c")),
+            "libfoo.so"
+        );
+        assert_eq!(binary_name(Path::new("libfoo.so.c")), "libfoo.so");
+    }
+
+    #[test]
+    fn addresses_are_read_from_generated_names() {
+        assert_eq!(address("FUN_00401000").as_deref(), Some("0x00401000"));
+        assert_eq!(address("sub_40A1F0").as_deref(), Some("0x40a1f0"));
+        assert_eq!(address("main@0x401000").as_deref(), Some("0x401000"));
+        assert_eq!(address("parse_header"), None);
+        assert_eq!(address("decade"), None);
+    }
+}
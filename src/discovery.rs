@@ -0,0 +1,709 @@
+use clap::Args;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use rayon::prelude::*;
+use rust_code_analysis::get_from_ext;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+use crate::errors::{check_input, CliError};
+use crate::generated::{generated_reason, generated_reason_of, Skipped};
+use crate::paths::has_extension;
+use crate::tree::SourceTree;
+
+/// How many walked files may wait for the rayon pool before the walk blocks
+const DISCOVERY_QUEUE: usize = 4096;
+
+/// The files a walk kept, each with what was made of it, and those it skipped
+pub type Discovered<T> = (Vec<(PathBuf, T)>, Vec<Skipped>);
+
+/// What became of a walked file
+enum Found<T> {
+    /// Along with the hash of its contents for `--dedup`
+    Kept(T, Option<Vec<u8>>),
+    Generated(String),
+    OutOfShard,
+    OutOfSample,
+}
+
+#[derive(Args, Clone, Serialize)]
+pub struct DiscoveryArgs {
+    /// Comma separated list of file extensions to process when walking a directory
+    #[arg(long, value_delimiter = ',', default_value = "cpp,cc,hpp,c,h,m,mm")]
+    pub extensions: Vec<String>,
+
+    /// Walk the extensions of a common stack instead of --extensions, leaving out its
+    /// build outputs and dependency directories on top of --exclude
+    /// Options: cpp, rust, python, web, jvm, all
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(["cpp", "rust", "python", "web", "jvm", "all"]), conflicts_with = "extensions")]
+    pub preset: Option<String>,
+
+    /// Follow symbolic links when walking a directory. Links resolving outside of the
+    /// walked directory and links looping back onto their ancestors are skipped
+    #[arg(long, default_value = "false")]
+    pub follow_symlinks: bool,
+
+    /// Maximum depth to descend to below the walked directory
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+
+    /// Don't cross into other file systems when walking a directory
+    #[arg(long, default_value = "false")]
+    pub one_file_system: bool,
+
+    /// Skip binary, generated, minified and amalgamated files, as told by `@generated`
+    /// style markers, very long lines or their size
+    #[arg(long, default_value = "false")]
+    pub skip_generated: bool,
+
+    /// Only process the first in path order of the files with the same contents, e.g. a header
+    /// reached through symlinks or vendored in several places, recording the rest in
+    /// duplicates.tsv as duplicates of it
+    #[arg(long, default_value = "false")]
+    pub dedup: bool,
+
+    /// Size in MB above which --skip-generated skips a file
+    #[arg(long, default_value = "5")]
+    pub max_file_size: u64,
+
+    /// Only process shard i of N (0 <= i < N), partitioning the files by a hash of their
+    /// path below the walked directory so every machine agrees on it. Fan-in only counts
+    /// callers within the shard
+    #[arg(long, value_parser = parse_shard)]
+    pub shard: Option<Shard>,
+
+    /// Globs of paths below the walked directory to leave out, e.g. `vendor/**`. A
+    /// directory matching one is not descended into
+    #[arg(long, value_delimiter = ',', value_parser = parse_glob)]
+    pub exclude: Vec<String>,
+
+    /// Only process this fraction of the files, e.g. 0.05, picked by a hash of their path
+    /// below the walked directory and --seed. The same seed always picks the same files,
+    /// and a larger fraction picks the files of a smaller one and more
+    #[arg(long, value_parser = parse_fraction)]
+    pub sample: Option<f64>,
+
+    /// Only process N files from every directory directly below the walked directory, e.g.
+    /// every project of a corpus, and N of the files right in it, so every project
+    /// contributes equally. Picked by --seed like --sample
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["sample", "sample_per_lang"])]
+    pub sample_per_dir: Option<u64>,
+
+    /// Only process N files of every language, told by their extension, so every
+    /// language contributes equally. Picked by --seed like --sample
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "sample")]
+    pub sample_per_lang: Option<u64>,
+
+    /// Seed for --sample, and for the split assignment where there is one
+    #[arg(long, default_value = "0")]
+    pub seed: u64,
+
+    /// The tree whose files are walked instead of the directory, e.g. the revision of
+    /// `--git-rev` or the objects below an object store `--path`
+    #[arg(skip)]
+    #[serde(skip)]
+    pub tree: Option<Arc<dyn SourceTree>>,
+}
+
+/// The extensions `--preset` walks for every stack, only those rust-code-analysis guesses
+/// a language for, and the directories it leaves out
+const PRESETS: [(&str, &[&str], &[&str]); 5] = [
+    (
+        "cpp",
+        &["c", "cc", "cpp", "cxx", "h", "hh", "hpp", "hxx", "inc"],
+        &["**/build", "**/cmake-build-*", "**/third_party"],
+    ),
+    ("rust", &["rs"], &["**/target"]),
+    (
+        "python",
+        &["py"],
+        &[
+            "**/__pycache__",
+            "**/.venv",
+            "**/venv",
+            "**/.tox",
+            "**/site-packages",
+        ],
+    ),
+    (
+        "web",
+        &["js", "jsm", "ts", "tsx"],
+        &[
+            "**/node_modules",
+            "**/bower_components",
+            "**/dist",
+            "**/*.min.js",
+        ],
+    ),
+    ("jvm", &["java"], &["**/target", "**/build", "**/.gradle"]),
+];
+
+/// The `PRESETS` `preset` bundles, every one of them for `all`
+fn presets(preset: &str) -> impl Iterator<Item = &(&str, &[&str], &[&str])> {
+    PRESETS
+        .iter()
+        .filter(move |(name, _, _)| preset == "all" || *name == preset)
+}
+
+/// One of `count` deterministic partitions of the discovered files
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// Whether `relative`, a path below the walked directory, falls into this shard
+    pub fn contains(&self, relative: &Path) -> bool {
+        path_hash(relative, &[]) % self.count == self.index
+    }
+}
+
+/// Whether `relative`, a path below the walked directory, is among the `fraction` of the
+/// files `--sample` keeps for `seed`
+pub fn sampled(relative: &Path, fraction: f64, seed: u64) -> bool {
+    fraction >= 1.0 || (path_hash(relative, &sample_salt(seed)) as f64) < fraction * u64::MAX as f64
+}
+
+/// The salt sampled paths are hashed with, the seed behind a tag of its own. `--split`
+/// hashes paths with the bare seed, which would otherwise put every sampled file in the
+/// first split
+fn sample_salt(seed: u64) -> Vec<u8> {
+    [&b"archaeo-sample"[..], &seed.to_le_bytes()].concat()
+}
+
+/// A hash of `relative` with `/` separators whatever the platform, salted with `salt`, so
+/// every machine agrees on it
+fn path_hash(relative: &Path, salt: &[u8]) -> u64 {
+    let path = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(path.as_bytes());
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// What `--sample-per-dir` and `--sample-per-lang` group the files by
+#[derive(Clone, Copy)]
+enum Stratum {
+    Dir,
+    Lang,
+}
+
+impl Stratum {
+    fn name(self) -> &'static str {
+        match self {
+            Stratum::Dir => "directories",
+            Stratum::Lang => "languages",
+        }
+    }
+
+    /// The group of `relative`, a path below the walked directory. Languages are told by
+    /// the extension alone, as nothing has been read yet
+    fn of(self, relative: &Path) -> String {
+        match self {
+            Stratum::Dir => match relative.parent().and_then(|parent| parent.iter().next()) {
+                Some(dir) => dir.to_string_lossy().to_string(),
+                None => String::new(),
+            },
+            Stratum::Lang => {
+                let ext = relative
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                get_from_ext(&ext).map_or(ext, |language| language.get_name().to_string())
+            }
+        }
+    }
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid fraction {}", value))?;
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err(format!(
+            "the fraction must be above 0 and at most 1, got {}",
+            value
+        ));
+    }
+    Ok(fraction)
+}
+
+fn parse_shard(value: &str) -> Result<Shard, String> {
+    let (index, count) = value
+        .split_once('/')
+        .ok_or_else(|| format!("expected i/N, got {}", value))?;
+    let index: u64 = index
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid shard index {}", index))?;
+    let count: u64 = count
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid shard count {}", count))?;
+    if count == 0 || index >= count {
+        return Err(format!(
+            "shard index must be below the count, got {}",
+            value
+        ));
+    }
+    Ok(Shard { index, count })
+}
+
+impl DiscoveryArgs {
+    /// The extensions to walk, those of the `--preset` when there is one
+    pub fn extensions(&self) -> Vec<String> {
+        match &self.preset {
+            Some(preset) => unique(presets(preset).flat_map(|(_, extensions, _)| *extensions)),
+            None => self.extensions.clone(),
+        }
+    }
+
+    /// The `--exclude` globs along with those of the `--preset`
+    pub fn excludes(&self) -> Vec<String> {
+        let preset = self.preset.iter().flat_map(|preset| presets(preset));
+        let mut excludes = self.exclude.clone();
+        excludes.extend(unique(preset.flat_map(|(_, _, exclude)| *exclude)));
+        excludes
+    }
+
+    /// Collects the files under `path` that should be processed. A single file is
+    /// always returned as-is, directories are walked and filtered by extension. Anything
+    /// else is an error.
+    pub fn discover(&self, path: &Path) -> Result<Vec<PathBuf>, CliError> {
+        Ok(self.discover_skipping(path)?.0)
+    }
+
+    /// Like `discover`, also returning the files `--skip-generated` left out
+    pub fn discover_skipping(&self, path: &Path) -> Result<(Vec<PathBuf>, Vec<Skipped>), CliError> {
+        let (found, skipped) = self.discover_visiting(path, |_| ())?;
+        Ok((found.into_iter().map(|(fp, _)| fp).collect(), skipped))
+    }
+
+    /// Like `discover_skipping`, handing every file to `visit` on the rayon pool as soon as
+    /// the walk finds it rather than once it is over, so walking a slow network file system
+    /// overlaps with the work done per file. Files come back in the order they were walked,
+    /// each with what `visit` made of it
+    pub fn discover_visiting<T: Send>(
+        &self,
+        path: &Path,
+        visit: impl Fn(&Path) -> T + Sync,
+    ) -> Result<Discovered<T>, CliError> {
+        // The root of a tree needn't be on disk, its files being read through the tree
+        if self.tree.is_none() {
+            check_input(path)?;
+        }
+        let walks_dir = self.tree.is_some() || path.is_dir();
+        let shard = self.shard.filter(|_| walks_dir);
+        let sample = self.sample.filter(|_| walks_dir);
+        let stratified = self.stratified().filter(|_| walks_dir);
+        let max_bytes = self.max_file_size.saturating_mul(1024 * 1024);
+
+        let (sender, receiver) = crossbeam_channel::bounded(DISCOVERY_QUEUE);
+        let mut walked: Vec<(usize, PathBuf, Found<T>)> = thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut index = 0;
+                let mut send = |fp, in_sample| {
+                    let _ = sender.send((index, fp, in_sample));
+                    index += 1;
+                };
+                match stratified {
+                    // Every file has to be known before any can be picked
+                    Some((stratum, size)) => {
+                        let mut files = Vec::new();
+                        self.walk(path, &mut |fp| files.push(fp));
+                        let picked = self.pick(path, &files, stratum, size);
+                        for fp in &files {
+                            send(fp.clone(), picked.contains(fp.as_path()));
+                        }
+                    }
+                    None => self.walk(path, &mut |fp| send(fp, true)),
+                }
+            });
+            receiver
+                .into_iter()
+                .par_bridge()
+                .map(|(index, fp, in_sample)| {
+                    let relative = fp.strip_prefix(path).unwrap_or(&fp);
+                    let found = if shard.is_some_and(|shard| !shard.contains(relative)) {
+                        Found::OutOfShard
+                    } else if !in_sample
+                        || sample.is_some_and(|fraction| !sampled(relative, fraction, self.seed))
+                    {
+                        Found::OutOfSample
+                    } else if let Some(reason) = self
+                        .skip_generated
+                        .then(|| match &self.tree {
+                            Some(tree) => tree
+                                .read(&fp)
+                                .ok()
+                                .and_then(|content| generated_reason_of(&content, max_bytes)),
+                            None => generated_reason(&fp, max_bytes),
+                        })
+                        .flatten()
+                    {
+                        Found::Generated(reason)
+                    } else {
+                        let digest = self.dedup.then(|| self.digest(&fp)).flatten();
+                        Found::Kept(visit(&fp), digest)
+                    };
+                    (index, fp, found)
+                })
+                .collect()
+        });
+        walked.sort_by_key(|(index, _, _)| *index);
+
+        if let Some(shard) = shard {
+            let outside = walked
+                .iter()
+                .filter(|(_, _, found)| matches!(found, Found::OutOfShard))
+                .count();
+            info!(
+                "Processing {} of {} files in shard {}/{}",
+                walked.len() - outside,
+                walked.len(),
+                shard.index,
+                shard.count
+            );
+        }
+        if let Some(fraction) = sample {
+            let considered = walked
+                .iter()
+                .filter(|(_, _, found)| !matches!(found, Found::OutOfShard))
+                .count();
+            let sampled = walked
+                .iter()
+                .filter(|(_, _, found)| !matches!(found, Found::OutOfShard | Found::OutOfSample))
+                .count();
+            info!(
+                "Sampled {} of {} files with --sample {} --seed {}",
+                sampled, considered, fraction, self.seed
+            );
+        }
+
+        let mut kept = Vec::new();
+        let mut skipped = Vec::new();
+        // The walk order differs between file systems, so the first in path order is kept
+        let mut firsts: HashMap<&[u8], &Path> = HashMap::new();
+        for (_, fp, found) in &walked {
+            if let Found::Kept(_, Some(digest)) = found {
+                let first = firsts.entry(digest).or_insert(fp);
+                *first = (*first).min(fp.as_path());
+            }
+        }
+        let firsts: HashMap<Vec<u8>, PathBuf> = firsts
+            .into_iter()
+            .map(|(digest, first)| (digest.to_vec(), first.to_path_buf()))
+            .collect();
+        let mut duplicates = 0;
+        for (_, fp, found) in walked {
+            let source_file = fp.to_string_lossy().to_string();
+            match found {
+                Found::Kept(visited, Some(digest)) => match firsts.get(&digest) {
+                    Some(first) if *first != fp => {
+                        debug!(file = %fp.display(), "Collapsing duplicate of {}", first.display());
+                        let first = first.to_string_lossy().to_string();
+                        skipped.push(Skipped {
+                            source_file,
+                            reason: format!("duplicate of {}", first),
+                            duplicate_of: Some(first),
+                        });
+                        duplicates += 1;
+                    }
+                    _ => kept.push((fp, visited)),
+                },
+                Found::Kept(visited, None) => kept.push((fp, visited)),
+                Found::Generated(reason) => {
+                    warn!(file = %fp.display(), reason = %reason, "Skipping generated file");
+                    skipped.push(Skipped {
+                        source_file,
+                        reason,
+                        duplicate_of: None,
+                    });
+                }
+                Found::OutOfShard | Found::OutOfSample => {}
+            }
+        }
+        if skipped.len() > duplicates {
+            info!("Skipped {} generated files", skipped.len() - duplicates);
+        }
+        if duplicates > 0 {
+            info!(
+                "Collapsed {} files duplicating the contents of another",
+                duplicates
+            );
+        }
+        Ok((kept, skipped))
+    }
+
+    /// A hash of the contents of `fp`, read from the tree when there is one
+    fn digest(&self, fp: &Path) -> Option<Vec<u8>> {
+        let content = match &self.tree {
+            Some(tree) => tree.read(fp).ok()?,
+            None => fs::read(fp).ok()?,
+        };
+        Some(Sha256::digest(&content).to_vec())
+    }
+
+    /// What `--sample-per-dir` or `--sample-per-lang` groups the files by, and how many of
+    /// every group it keeps
+    fn stratified(&self) -> Option<(Stratum, usize)> {
+        let size = |size: u64| usize::try_from(size).unwrap_or(usize::MAX);
+        match (self.sample_per_dir, self.sample_per_lang) {
+            (Some(size_of), _) => Some((Stratum::Dir, size(size_of))),
+            (None, Some(size_of)) => Some((Stratum::Lang, size(size_of))),
+            (None, None) => None,
+        }
+    }
+
+    /// The `size` files of every group whose paths below `root` hash lowest for the seed,
+    /// so a larger size picks the files of a smaller one and more. Groups are picked from
+    /// before sharding, so the shards of a cluster split one sample between them
+    fn pick<'a>(
+        &self,
+        root: &Path,
+        files: &'a [PathBuf],
+        stratum: Stratum,
+        size: usize,
+    ) -> HashSet<&'a Path> {
+        let mut groups: BTreeMap<String, Vec<(u64, &Path)>> = BTreeMap::new();
+        for fp in files {
+            let relative = fp.strip_prefix(root).unwrap_or(fp);
+            groups
+                .entry(stratum.of(relative))
+                .or_default()
+                .push((path_hash(relative, &sample_salt(self.seed)), fp));
+        }
+
+        let mut picked = HashSet::new();
+        for group in groups.values_mut() {
+            group.sort();
+            picked.extend(group.iter().take(size).map(|(_, fp)| *fp));
+        }
+        info!(
+            "Sampled {} of {} files, at most {} from each of {} {}, with --seed {}",
+            picked.len(),
+            files.len(),
+            size,
+            groups.len(),
+            stratum.name(),
+            self.seed
+        );
+        picked
+    }
+
+    /// Walks `path`, calling `found` with every file passing the extension filter
+    fn walk(&self, path: &Path, found: &mut dyn FnMut(PathBuf)) {
+        if let Some(tree) = &self.tree {
+            info!("Multiple files found in the revision...");
+            let excluded = exclude_set(&self.excludes());
+            let extensions = self.extensions();
+            for file in tree.files() {
+                let relative = tree.relative(&file);
+                // Like the walk, a directory matching an exclude leaves out all below it
+                let excluded = relative
+                    .ancestors()
+                    .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                    .any(|ancestor| excluded.is_match(ancestor));
+                if !excluded
+                    && self
+                        .max_depth
+                        .is_none_or(|depth| relative.components().count() <= depth)
+                    && has_extension(&file, &extensions)
+                    && !file.to_string_lossy().contains("test/")
+                {
+                    found(file);
+                }
+            }
+        } else if path.is_file() {
+            info!("Single file found...");
+            found(path.to_path_buf());
+        } else {
+            info!("Multiple files found...");
+            let root = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            let excluded = exclude_set(&self.excludes());
+            let extensions = self.extensions();
+            let mut walker = WalkDir::new(path)
+                .follow_links(self.follow_symlinks)
+                .same_file_system(self.one_file_system);
+            if let Some(depth) = self.max_depth {
+                walker = walker.max_depth(depth);
+            }
+
+            let entries = walker.into_iter().filter_entry(|entry| {
+                let relative = entry.path().strip_prefix(path).unwrap_or(entry.path());
+                (relative.as_os_str().is_empty() || !excluded.is_match(relative))
+                    && (!entry.path_is_symlink() || within(&root, entry.path()))
+            });
+            for entry in entries.filter_map(|e| match e {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    warn!("Skipping {}", err);
+                    None
+                }
+            }) {
+                if entry.file_type().is_file() {
+                    let path = entry.path();
+                    if has_extension(path, &extensions) && !path.to_string_lossy().contains("test/")
+                    {
+                        found(path.to_path_buf());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(pattern.trim_end_matches('/'))
+        .literal_separator(true)
+        .build()
+}
+
+/// Checks a glob parses, handing it back as is
+pub fn parse_glob(value: &str) -> Result<String, String> {
+    glob(value)
+        .map(|_| value.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/// `items` as owned strings without repeats, in the order they first come in
+fn unique<'a>(items: impl Iterator<Item = &'a &'a str>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    items
+        .filter(|item| seen.insert(**item))
+        .map(|item| item.to_string())
+        .collect()
+}
+
+/// Compiles the `--exclude` globs, where `*` doesn't cross directories. They were checked
+/// when parsed
+fn exclude_set(patterns: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.iter().filter_map(|pattern| glob(pattern).ok()) {
+        builder.add(pattern);
+    }
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+/// Whether `path` resolves to somewhere under `root`
+fn within(root: &Path, path: &Path) -> bool {
+    match path.canonicalize() {
+        Ok(target) if target.starts_with(root) => true,
+        _ => {
+            warn!(file = %path.display(), "Skipping symlink leading outside of the tree");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shards_are_parsed_as_an_index_below_the_count() {
+        assert_eq!(parse_shard("1/3"), Ok(Shard { index: 1, count: 3 }));
+        assert_eq!(parse_shard(" 0 / 1 "), Ok(Shard { index: 0, count: 1 }));
+        assert_eq!(parse_shard("3"), Err("expected i/N, got 3".to_string()));
+        assert_eq!(parse_shard("a/3"), Err("invalid shard index a".to_string()));
+        assert!(parse_shard("3/3").is_err());
+        assert!(parse_shard("0/0").is_err());
+    }
+
+    #[test]
+    fn every_file_falls_into_exactly_one_shard() {
+        let files: Vec<PathBuf> = (0..200)
+            .map(|n| PathBuf::from(format!("src/dir{}/file{}.c", n % 7, n)))
+            .collect();
+        let shards: Vec<Shard> = (0..4).map(|index| Shard { index, count: 4 }).collect();
+        for file in &files {
+            assert_eq!(shards.iter().filter(|s| s.contains(file)).count(), 1);
+        }
+        // Roughly evenly, and the same whichever separators the platform uses
+        for shard in &shards {
+            let size = files.iter().filter(|file| shard.contains(file)).count();
+            assert!((25..=75).contains(&size), "{}", size);
+        }
+        assert_eq!(
+            path_hash(Path::new("src/a.c"), &[]),
+            path_hash(&Path::new("src").join("a.c"), &[])
+        );
+    }
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        discovery: DiscoveryArgs,
+    }
+
+    #[test]
+    fn visited_files_come_back_in_walk_order_with_what_was_made_of_them() {
+        let dir = tempfile::tempdir().unwrap();
+        for n in 0..200 {
+            let sub = dir.path().join(format!("dir{}", n % 5));
+            fs::create_dir_all(&sub).unwrap();
+            fs::write(sub.join(format!("f{}.c", n)), format!("int f{}(void);\n", n)).unwrap();
+        }
+        fs::write(dir.path().join("gen.c"), "// @generated\nint g;\n").unwrap();
+        fs::write(dir.path().join("notes.txt"), "not source\n").unwrap();
+
+        let discovery = <Cli as clap::Parser>::parse_from([
+            "archaeo",
+            "--skip-generated",
+            "--shard",
+            "1/2",
+        ])
+        .discovery;
+        let visited = std::sync::atomic::AtomicUsize::new(0);
+        let (found, skipped) = discovery
+            .discover_visiting(dir.path(), |fp| {
+                visited.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                fs::read_to_string(fp).unwrap()
+            })
+            .unwrap();
+
+        let mut walked = Vec::new();
+        discovery.walk(dir.path(), &mut |fp| walked.push(fp));
+        let shard = Shard { index: 1, count: 2 };
+        let expected: Vec<&PathBuf> = walked
+            .iter()
+            .filter(|fp| shard.contains(fp.strip_prefix(dir.path()).unwrap()))
+            .filter(|fp| !fp.ends_with("gen.c"))
+            .collect();
+        assert_eq!(found.iter().map(|(fp, _)| fp).collect::<Vec<_>>(), expected);
+        assert_eq!(visited.into_inner(), found.len());
+        for (fp, contents) in &found {
+            assert_eq!(*contents, fs::read_to_string(fp).unwrap());
+        }
+
+        // The generated file is only looked at, and skipped, within its shard
+        let generated = dir.path().join("gen.c").to_string_lossy().to_string();
+        assert_eq!(
+            skipped.iter().map(|s| &s.source_file).collect::<Vec<_>>(),
+            shard
+                .contains(Path::new("gen.c"))
+                .then_some(&generated)
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            discovery.discover(dir.path()).unwrap(),
+            expected.into_iter().cloned().collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::path::Path;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -5,6 +6,15 @@ pub enum CliError {
     #[error("FailedProcessingError: Failed to process: {0}")]
     FailedProcessing(String),
 
+    #[error("Failed to read {0}: {1}")]
+    ReadFailed(String, String),
+
+    #[error("Timed out on {0} after {1}s")]
+    TimedOut(String, u64),
+
+    #[error("More than {0} files timed out and are still parsing in the background")]
+    TooManyTimeouts(usize),
+
     #[error("Failed to guess programming lang of {0}")]
     FailedGuessLang(String),
 
@@ -17,10 +27,110 @@ pub enum CliError {
     #[error("Failed to create CSV: {0}")]
     CSVError(csv::Error),
 
+    #[error("Failed to export: {0}")]
+    ExportError(String),
+
+    #[error("Failed to read git repository: {0}")]
+    GitError(String),
+
+    #[error("Output does not match the schema: {0}")]
+    SchemaMismatch(String),
+
+    #[error("Failed to access object storage: {0}")]
+    RemoteError(String),
+
+    #[error("Failed to load into the database: {0}")]
+    DatabaseError(String),
+
+    #[error("Failed to reach the merge request API: {0}")]
+    WebhookError(String),
+
+    #[error("Language server protocol error: {0}")]
+    LspError(String),
+
+    #[error("Thresholds exceeded: {0}")]
+    ThresholdExceeded(String),
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("Invalid input path: {0}")]
+    InvalidInputPath(String),
+
+    #[error("Failed to compile tree-sitter query: {0}")]
+    InvalidQuery(String),
+
+    #[error("Failed to load plugin: {0}")]
+    InvalidPlugin(String),
+
+    /// Bad arguments, or `--help` and `--version` asking to print something else instead
+    #[error("{0}")]
+    Usage(clap::Error),
+
+    #[error("{0} files failed")]
+    FilesFailed(usize),
+
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
+
     #[error(transparent)]
     Other(#[from] color_eyre::Report),
 }
 
+/// Exit codes of the `archaeo` binary, so CI can tell a failed check from a broken tool
+pub mod exit_code {
+    pub const SUCCESS: i32 = 0;
+    /// Bad arguments or configuration
+    pub const USAGE: i32 = 1;
+    /// Some files failed to process, with `--summary-exit-codes`
+    pub const FILES_FAILED: i32 = 2;
+    /// Functions broke `--fail-on` rules or `--budgets`
+    pub const THRESHOLD_EXCEEDED: i32 = 3;
+    /// An output didn't match the schema
+    pub const SCHEMA_MISMATCH: i32 = 4;
+    /// Anything else going wrong, from IO to the database
+    pub const FAILURE: i32 = 5;
+    /// Stopped by Ctrl-C, as a shell reports SIGINT
+    pub const INTERRUPTED: i32 = 130;
+}
+
+impl CliError {
+    /// The code the binary exits with when a command fails with this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // clap exits with 2 for bad arguments, which `archaeo` keeps for failed files
+            CliError::Usage(err) if !err.use_stderr() => exit_code::SUCCESS,
+            CliError::Usage(_)
+            | CliError::InvalidArgument(_)
+            | CliError::InvalidInputPath(_)
+            | CliError::InvalidQuery(_)
+            | CliError::InvalidPlugin(_) => exit_code::USAGE,
+            CliError::FilesFailed(_) => exit_code::FILES_FAILED,
+            CliError::ThresholdExceeded(_) => exit_code::THRESHOLD_EXCEEDED,
+            CliError::SchemaMismatch(_) => exit_code::SCHEMA_MISMATCH,
+            CliError::Interrupted(_) => exit_code::INTERRUPTED,
+            _ => exit_code::FAILURE,
+        }
+    }
+}
+
+/// Fails with `InvalidInputPath` unless there is a file or directory at `path`
+pub fn check_input(path: &Path) -> Result<(), CliError> {
+    if path.is_file() || path.is_dir() {
+        return Ok(());
+    }
+    Err(CliError::InvalidInputPath(format!(
+        "{} is not a file or a directory",
+        path.display()
+    )))
+}
+
+impl From<clap::Error> for CliError {
+    fn from(err: clap::Error) -> CliError {
+        CliError::Usage(err)
+    }
+}
+
 impl From<serde_json::Error> for CliError {
     fn from(err: serde_json::Error) -> CliError {
         CliError::SerdeError(err)
@@ -38,3 +148,29 @@ impl From<csv::Error> for CliError {
         CliError::CSVError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_kind_of_failure_exits_with_its_documented_code() {
+        let code = |err: CliError| err.exit_code();
+        assert_eq!(code(CliError::InvalidArgument(String::new())), exit_code::USAGE);
+        assert_eq!(code(CliError::InvalidPlugin(String::new())), exit_code::USAGE);
+        assert_eq!(code(CliError::FilesFailed(2)), exit_code::FILES_FAILED);
+        assert_eq!(
+            code(CliError::ThresholdExceeded(String::new())),
+            exit_code::THRESHOLD_EXCEEDED
+        );
+        assert_eq!(
+            code(CliError::SchemaMismatch(String::new())),
+            exit_code::SCHEMA_MISMATCH
+        );
+        assert_eq!(code(CliError::Interrupted(String::new())), 130);
+        // Whatever isn't a usage error or a failed check is the tool breaking
+        assert_eq!(code(CliError::DatabaseError(String::new())), exit_code::FAILURE);
+        let io = std::io::Error::other("disk full");
+        assert_eq!(code(io.into()), exit_code::FAILURE);
+    }
+}
@@ -0,0 +1,451 @@
+//! Metric extraction as a library, for embedding archaeo without going through the CLI
+//! and its output files.
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::anonymous::AnonymousNaming;
+use crate::bugfix::Bugfixes;
+use crate::callgraph::{build_edges, corpus_calls, source_calls, Coupling};
+use crate::commands::source::{
+    flatten_spaces, flatten_spaces_extended, read_spaces, read_spaces_from, ContextSettings,
+    FlattenedMetrics, FlattenedMetricsExtended, NonFinite,
+};
+use crate::dataset::{Advisories, Labels, Split};
+use crate::discovery::{DiscoveryArgs, Shard};
+use crate::errors::CliError;
+use crate::git;
+use crate::language::{LanguageArgs, SourceReader};
+use crate::owners::CodeOwners;
+use crate::plugins::load_plugins;
+use crate::projects::Projects;
+use crate::query::QuerySet;
+use crate::units::TranslationUnits;
+
+/// A flattened metrics row, keyed by column name in output order
+pub type MetricsRow = Map<String, Value>;
+
+/// Mirrors the options of the `source` command. Missing fields take their defaults when
+/// deserialized
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExtractOptions {
+    /// File extensions to process when walking a directory
+    pub extensions: Vec<String>,
+    /// Follow symbolic links that stay within the walked directory
+    pub follow_symlinks: bool,
+    /// Maximum depth to descend to below the walked directory
+    pub max_depth: Option<usize>,
+    /// Don't cross into other file systems when walking a directory
+    pub one_file_system: bool,
+    /// Skip binary, generated, minified and amalgamated files when walking a directory
+    pub skip_generated: bool,
+    /// Only extract the first in path order of the files with the same contents
+    pub dedup: bool,
+    /// Size in MB above which `skip_generated` skips a file
+    pub max_file_size: u64,
+    /// Only extract this shard of the files when walking a directory
+    pub shard: Option<Shard>,
+    /// Fraction of the files to extract when walking a directory, picked by `seed`
+    pub sample: Option<f64>,
+    /// Number of files to extract from every directory directly below the walked one
+    pub sample_per_dir: Option<u64>,
+    /// Number of files of every language to extract when walking a directory
+    pub sample_per_lang: Option<u64>,
+    /// Globs of paths below the walked directory to leave out
+    pub exclude: Vec<String>,
+    /// Emit the extended metric columns
+    pub extended: bool,
+    /// File of tree-sitter queries whose captures are counted per space
+    pub query: Option<PathBuf>,
+    /// Metric plugins to run over every space
+    pub plugins: Vec<String>,
+    /// Train, validation and test ratios to assign rows a `split` with
+    pub split: Vec<f64>,
+    /// Whether whole files or individual functions are assigned to a split
+    pub split_by: String,
+    /// Seed for the samples and the split assignment
+    pub seed: u64,
+    /// CSV of labels to merge into the rows they match
+    pub labels: Option<PathBuf>,
+    /// Columns labels are matched to rows on
+    pub join_on: Vec<String>,
+    /// Emit the source text of every space in a `source` column
+    pub with_source: bool,
+    /// Write null for the metrics the language of a file doesn't define, adding a
+    /// `metrics_supported` bitmask column
+    pub mask_unsupported: bool,
+    /// What non-finite metrics are written as, zero when not given
+    pub non_finite: Option<NonFinite>,
+    /// What functions without a name are written as, `synthetic`, `null` or `drop`
+    pub anonymous: String,
+    /// Fold the specializations of C++ function templates into the row of the template
+    pub aggregate_specializations: bool,
+    /// Leave out the functions with more of their bytes in parse errors than this
+    pub max_parse_error_ratio: Option<f64>,
+    /// Treat every file as decompiler pseudo-C, recording its binary in a `binary` column
+    pub decompiled: bool,
+    /// Language to parse every file as instead of guessing it
+    pub force_language: Option<String>,
+    /// Per-extension language overrides, e.g. `inl=cpp`
+    pub map_ext: Vec<String>,
+    /// Evaluate C/C++ preprocessor conditionals first, `strip-inactive` or `expand`
+    pub preprocess: Option<String>,
+    /// Macros conditionals are evaluated against, as `NAME` or `NAME=value`
+    pub define: Vec<String>,
+    /// Memory-map source files over 1 MiB instead of reading them in
+    pub mmap: bool,
+    /// Group C/C++ headers with their source file in a `translation_unit` column
+    pub translation_units: bool,
+    /// CODEOWNERS file to resolve the `owner` column from
+    pub codeowners: Option<PathBuf>,
+    /// JSON of security advisories to annotate the rows of the files and functions they
+    /// name with
+    pub advisories: Option<PathBuf>,
+    /// Regex matching the messages of the bug fixing commits to count for every function,
+    /// in the history of the repository the files are in
+    pub bugfixes: Option<String>,
+    /// Run the SZZ algorithm over the `bugfixes` commits
+    pub szz: bool,
+    /// Leave the functions suppressed by `archaeo:ignore` comments out of the rows
+    pub drop_suppressed: bool,
+    /// Don't honor `archaeo:ignore` comments
+    pub no_suppressions: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            extensions: ["cpp", "cc", "hpp", "c", "h", "m", "mm"]
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect(),
+            follow_symlinks: false,
+            max_depth: None,
+            one_file_system: false,
+            skip_generated: false,
+            dedup: false,
+            max_file_size: 5,
+            shard: None,
+            sample: None,
+            sample_per_dir: None,
+            sample_per_lang: None,
+            exclude: Vec::new(),
+            extended: false,
+            query: None,
+            plugins: Vec::new(),
+            split: Vec::new(),
+            split_by: "file".to_string(),
+            seed: 0,
+            labels: None,
+            join_on: vec!["source_file".to_string(), "name".to_string()],
+            with_source: false,
+            mask_unsupported: false,
+            non_finite: None,
+            anonymous: "synthetic".to_string(),
+            aggregate_specializations: false,
+            max_parse_error_ratio: None,
+            decompiled: false,
+            force_language: None,
+            map_ext: Vec::new(),
+            preprocess: None,
+            define: Vec::new(),
+            mmap: false,
+            translation_units: false,
+            codeowners: None,
+            advisories: None,
+            bugfixes: None,
+            szz: false,
+            drop_suppressed: false,
+            no_suppressions: false,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// The options of a command walking its files with the `discovery` flags and reading
+    /// them with the `language` ones, every other option at its default
+    pub fn new(discovery: &DiscoveryArgs, language: &LanguageArgs) -> Self {
+        Self {
+            extensions: discovery.extensions(),
+            follow_symlinks: discovery.follow_symlinks,
+            max_depth: discovery.max_depth,
+            one_file_system: discovery.one_file_system,
+            skip_generated: discovery.skip_generated,
+            dedup: discovery.dedup,
+            max_file_size: discovery.max_file_size,
+            shard: discovery.shard,
+            sample: discovery.sample,
+            sample_per_dir: discovery.sample_per_dir,
+            sample_per_lang: discovery.sample_per_lang,
+            seed: discovery.seed,
+            exclude: discovery.excludes(),
+            force_language: language.force_language.clone(),
+            map_ext: language.map_ext.clone(),
+            preprocess: language.preprocess.clone(),
+            define: language.define.clone(),
+            mmap: language.mmap,
+            ..Self::default()
+        }
+    }
+}
+
+/// Extracts the flattened metrics of every space in `path`. Fan-in only counts
+/// callers within the file itself.
+pub fn extract_file(path: &Path, options: &ExtractOptions) -> Result<Vec<MetricsRow>, CliError> {
+    if !path.is_file() {
+        return Err(CliError::FailedProcessing(format!(
+            "{} is not a file",
+            path.display()
+        )));
+    }
+    extract(path, &[path.to_path_buf()], None, options)
+}
+
+/// Like `extract_file`, for the contents of `path` held in memory, e.g. an unsaved editor
+/// buffer. `path` only needs to exist for the options reading it, like `codeowners`
+pub fn extract_source(
+    path: &Path,
+    source: &[u8],
+    options: &ExtractOptions,
+) -> Result<Vec<MetricsRow>, CliError> {
+    extract(path, &[path.to_path_buf()], Some(source), options)
+}
+
+/// Extracts the flattened metrics of every matching file under `path`, resolving
+/// coupling across all of them
+pub fn extract_dir(path: &Path, options: &ExtractOptions) -> Result<Vec<MetricsRow>, CliError> {
+    if !path.is_dir() {
+        return Err(CliError::FailedProcessing(format!(
+            "{} is not a directory",
+            path.display()
+        )));
+    }
+    let discovery = DiscoveryArgs {
+        extensions: options.extensions.clone(),
+        preset: None,
+        follow_symlinks: options.follow_symlinks,
+        max_depth: options.max_depth,
+        one_file_system: options.one_file_system,
+        skip_generated: options.skip_generated,
+        dedup: options.dedup,
+        max_file_size: options.max_file_size,
+        shard: options.shard,
+        sample: options.sample,
+        sample_per_dir: options.sample_per_dir,
+        sample_per_lang: options.sample_per_lang,
+        seed: options.seed,
+        exclude: options.exclude.clone(),
+        tree: None,
+    };
+    extract(path, &discovery.discover(path)?, None, options)
+}
+
+/// Dispatches to `extract_file` or `extract_dir` depending on what `path` points at
+pub fn extract_path(path: &Path, options: &ExtractOptions) -> Result<Vec<MetricsRow>, CliError> {
+    if path.is_dir() {
+        extract_dir(path, options)
+    } else {
+        extract_file(path, options)
+    }
+}
+
+/// Extracts `filepaths` found under `root`, the only one of them being read from
+/// `contents` when given
+fn extract(
+    root: &Path,
+    filepaths: &[PathBuf],
+    contents: Option<&[u8]>,
+    options: &ExtractOptions,
+) -> Result<Vec<MetricsRow>, CliError> {
+    let reader = LanguageArgs {
+        force_language: options.force_language.clone(),
+        map_ext: options.map_ext.clone(),
+        preprocess: options.preprocess.clone(),
+        define: options.define.clone(),
+        mmap: options.mmap,
+        ..Default::default()
+    }
+    .reader()?
+    .decompiled(options.decompiled);
+    let calls = match (contents, filepaths) {
+        (Some(bytes), [path]) => source_calls(path, bytes, &reader),
+        _ => corpus_calls(filepaths, &reader),
+    };
+    let coupling = Coupling::from_edges(&build_edges(&calls));
+    let queries = match &options.query {
+        Some(path) => Some(QuerySet::from_file(path)?),
+        None => None,
+    };
+    let plugins = load_plugins(&options.plugins)?;
+    let split = if options.split.is_empty() {
+        None
+    } else {
+        Some(Split::new(&options.split, &options.split_by, options.seed)?)
+    };
+    let labels = match &options.labels {
+        Some(path) => Some(Labels::from_file(path, &options.join_on)?),
+        None => None,
+    };
+    let advisories = match &options.advisories {
+        Some(path) => Some(Advisories::from_file(path)?),
+        None => None,
+    };
+    let units = options
+        .translation_units
+        .then(|| TranslationUnits::new(filepaths));
+    let owners = match &options.codeowners {
+        Some(path) => Some(CodeOwners::from_file(path)?),
+        None => None,
+    };
+    let projects = Projects::find(root, filepaths, None);
+    let bugfixes = match &options.bugfixes {
+        Some(pattern) => {
+            let dir = if root.is_dir() {
+                root
+            } else {
+                root.parent().unwrap_or(Path::new("."))
+            };
+            let repo = git::toplevel(dir)?;
+            let repo = repo.canonicalize().unwrap_or(repo);
+            Some(Bugfixes::find(&repo, "HEAD", pattern, options.szz)?)
+        }
+        None => None,
+    };
+    let name = |path: &Path| path.to_string_lossy().to_string();
+    let settings = ContextSettings {
+        name: &name,
+        mask_unsupported: options.mask_unsupported,
+        non_finite: options.non_finite,
+        anonymous: AnonymousNaming::parse(&options.anonymous),
+        aggregate_specializations: options.aggregate_specializations,
+        max_parse_error_ratio: options.max_parse_error_ratio,
+        decompiled: options.decompiled,
+        with_source: options.with_source,
+        no_suppressions: options.no_suppressions,
+        drop_suppressed: options.drop_suppressed,
+        queries: queries.as_ref(),
+        plugins: &plugins,
+        units: units.as_ref(),
+        owners: owners.as_ref(),
+        projects: &projects,
+        bugfixes: bugfixes.as_ref(),
+        tree: None,
+        split: split.as_ref(),
+        labels: labels.as_ref(),
+        advisories: advisories.as_ref(),
+    };
+
+    let rows: Vec<Vec<MetricsRow>> = filepaths
+        .par_iter()
+        .map(|fp| {
+            file_rows(
+                fp,
+                contents,
+                options.extended,
+                &reader,
+                &coupling,
+                &settings,
+            )
+        })
+        .collect::<Result<_, CliError>>()?;
+
+    Ok(rows.into_iter().flatten().collect())
+}
+
+fn file_rows(
+    path: &Path,
+    contents: Option<&[u8]>,
+    extended: bool,
+    reader: &SourceReader,
+    coupling: &Coupling,
+    settings: &ContextSettings,
+) -> Result<Vec<MetricsRow>, CliError> {
+    let spaces = match contents {
+        Some(bytes) => read_spaces_from(path, bytes, reader),
+        None => read_spaces(path, reader),
+    };
+    let spaces = match spaces {
+        Ok(Some(spaces)) => spaces,
+        Ok(None) | Err(CliError::FailedGuessLang(_)) => {
+            warn!(file = %path.display(), "Failed to process");
+            return Ok(Vec::new());
+        }
+        Err(err) => return Err(err),
+    };
+
+    let (context, _) = settings.context(path, &spaces, coupling);
+    let (_, _, space, _) = &spaces;
+    if extended {
+        let mut flattened: Vec<FlattenedMetricsExtended> = Vec::new();
+        flatten_spaces_extended(&space.spaces, &context, &mut flattened);
+        to_rows(&flattened)
+    } else {
+        let mut flattened: Vec<FlattenedMetrics> = Vec::new();
+        flatten_spaces(&space.spaces, &context, &mut flattened);
+        to_rows(&flattened)
+    }
+}
+
+/// Serializes flattened metrics into rows
+pub fn to_rows<T: Serialize>(flattened: &[T]) -> Result<Vec<MetricsRow>, CliError> {
+    flattened.iter().map(to_row).collect()
+}
+
+/// Serializes one row of flattened metrics
+pub fn to_row<T: Serialize>(flattened: &T) -> Result<MetricsRow, CliError> {
+    match serde_json::to_value(flattened)? {
+        Value::Object(row) => Ok(row),
+        _ => unreachable!("Flattened metrics are always serialized as objects"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        discovery: DiscoveryArgs,
+        #[command(flatten)]
+        language: LanguageArgs,
+    }
+
+    fn parsed(args: &[&str]) -> ExtractOptions {
+        let cli = <Cli as clap::Parser>::parse_from(["archaeo"].iter().chain(args));
+        ExtractOptions::new(&cli.discovery, &cli.language)
+    }
+
+    #[test]
+    fn options_carry_every_discovery_and_language_flag() {
+        let options = parsed(&[
+            "--preset",
+            "rust",
+            "--shard",
+            "1/2",
+            "--sample",
+            "0.25",
+            "--seed",
+            "7",
+            "--map-ext",
+            "inl=cpp",
+            "--mmap",
+        ]);
+        assert_eq!(options.extensions, ["rs"]);
+        assert_eq!(options.exclude, ["**/target"]);
+        assert_eq!(options.shard, Some(Shard { index: 1, count: 2 }));
+        assert_eq!(options.sample, Some(0.25));
+        assert_eq!(options.seed, 7);
+        assert_eq!(options.map_ext, ["inl=cpp"]);
+        assert!(options.mmap);
+
+        let options = parsed(&["--sample-per-dir", "3"]);
+        assert_eq!(options.sample_per_dir, Some(3));
+        let options = parsed(&["--sample-per-lang", "2"]);
+        assert_eq!(options.sample_per_lang, Some(2));
+    }
+}
@@ -0,0 +1,204 @@
+//! Serving extracted rows over Arrow Flight, so clients such as pyarrow can `do_get` them as
+//! record batches. Needs the `flight` feature
+
+use arrow_array::RecordBatch;
+use arrow_schema::SchemaRef;
+
+use crate::errors::CliError;
+
+/// Name of the only flight, also accepted as its ticket and descriptor path
+pub const FLIGHT: &str = "metrics";
+
+/// Serves `batches` on `address` until the process is stopped
+pub fn serve(address: &str, schema: SchemaRef, batches: Vec<RecordBatch>) -> Result<(), CliError> {
+    #[cfg(feature = "flight")]
+    return server::serve(address, schema, batches);
+    #[cfg(not(feature = "flight"))]
+    {
+        let _ = (address, schema, batches);
+        Err(CliError::InvalidArgument(
+            "serving Arrow Flight needs archaeo built with the flight feature".to_string(),
+        ))
+    }
+}
+
+#[cfg(feature = "flight")]
+mod server {
+    use arrow_array::RecordBatch;
+    use arrow_flight::encode::FlightDataEncoderBuilder;
+    use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+    use arrow_flight::{
+        Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint,
+        FlightInfo, HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaAsIpc,
+        SchemaResult, Ticket,
+    };
+    use arrow_ipc::writer::IpcWriteOptions;
+    use arrow_schema::SchemaRef;
+    use futures::stream::BoxStream;
+    use futures::{StreamExt, TryStreamExt};
+    use std::sync::Arc;
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status, Streaming};
+    use tracing::info;
+
+    use super::FLIGHT;
+    use crate::errors::CliError;
+
+    struct Metrics {
+        schema: SchemaRef,
+        batches: Arc<Vec<RecordBatch>>,
+    }
+
+    impl Metrics {
+        fn info(&self) -> Result<FlightInfo, Status> {
+            let rows: usize = self.batches.iter().map(RecordBatch::num_rows).sum();
+            Ok(FlightInfo::new()
+                .try_with_schema(&self.schema)
+                .map_err(|err| Status::internal(err.to_string()))?
+                .with_descriptor(FlightDescriptor::new_path(vec![FLIGHT.to_string()]))
+                .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(FLIGHT)))
+                .with_total_records(rows as i64)
+                .with_ordered(true))
+        }
+    }
+
+    /// Only the `metrics` flight is known
+    fn check_descriptor(descriptor: &FlightDescriptor) -> Result<(), Status> {
+        match descriptor.path.as_slice() {
+            [] => Ok(()),
+            [path] if path == FLIGHT => Ok(()),
+            _ => Err(Status::not_found(format!(
+                "no flight {}, the only one is {}",
+                descriptor.path.join("/"),
+                FLIGHT
+            ))),
+        }
+    }
+
+    #[tonic::async_trait]
+    impl FlightService for Metrics {
+        type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+        type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+        type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+        type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+        type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+        type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+        type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+
+        async fn handshake(
+            &self,
+            _request: Request<Streaming<HandshakeRequest>>,
+        ) -> Result<Response<Self::HandshakeStream>, Status> {
+            Err(Status::unimplemented("handshake isn't needed"))
+        }
+
+        async fn list_flights(
+            &self,
+            _request: Request<Criteria>,
+        ) -> Result<Response<Self::ListFlightsStream>, Status> {
+            let info = self.info()?;
+            Ok(Response::new(futures::stream::iter([Ok(info)]).boxed()))
+        }
+
+        async fn get_flight_info(
+            &self,
+            request: Request<FlightDescriptor>,
+        ) -> Result<Response<FlightInfo>, Status> {
+            check_descriptor(request.get_ref())?;
+            Ok(Response::new(self.info()?))
+        }
+
+        async fn poll_flight_info(
+            &self,
+            _request: Request<FlightDescriptor>,
+        ) -> Result<Response<PollInfo>, Status> {
+            Err(Status::unimplemented("the flight is always complete"))
+        }
+
+        async fn get_schema(
+            &self,
+            request: Request<FlightDescriptor>,
+        ) -> Result<Response<SchemaResult>, Status> {
+            check_descriptor(request.get_ref())?;
+            let options = IpcWriteOptions::default();
+            let schema = SchemaAsIpc::new(&self.schema, &options)
+                .try_into()
+                .map_err(|err: arrow_schema::ArrowError| Status::internal(err.to_string()))?;
+            Ok(Response::new(schema))
+        }
+
+        async fn do_get(
+            &self,
+            request: Request<Ticket>,
+        ) -> Result<Response<Self::DoGetStream>, Status> {
+            let ticket = &request.get_ref().ticket;
+            if ticket.as_ref() != FLIGHT.as_bytes() {
+                return Err(Status::not_found(format!(
+                    "no flight {}, the only one is {}",
+                    String::from_utf8_lossy(ticket),
+                    FLIGHT
+                )));
+            }
+            info!("Streaming {} batches to a client", self.batches.len());
+            let batches = self.batches.as_ref().clone();
+            let stream = FlightDataEncoderBuilder::new()
+                .with_schema(self.schema.clone())
+                .build(futures::stream::iter(batches.into_iter().map(Ok)))
+                .map_err(Status::from);
+            Ok(Response::new(stream.boxed()))
+        }
+
+        async fn do_put(
+            &self,
+            _request: Request<Streaming<FlightData>>,
+        ) -> Result<Response<Self::DoPutStream>, Status> {
+            Err(Status::unimplemented("the flight is read only"))
+        }
+
+        async fn do_action(
+            &self,
+            _request: Request<Action>,
+        ) -> Result<Response<Self::DoActionStream>, Status> {
+            Err(Status::unimplemented("there are no actions"))
+        }
+
+        async fn list_actions(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<Self::ListActionsStream>, Status> {
+            Ok(Response::new(futures::stream::empty().boxed()))
+        }
+
+        async fn do_exchange(
+            &self,
+            _request: Request<Streaming<FlightData>>,
+        ) -> Result<Response<Self::DoExchangeStream>, Status> {
+            Err(Status::unimplemented("the flight is read only"))
+        }
+    }
+
+    pub fn serve(
+        address: &str,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> Result<(), CliError> {
+        let address = address
+            .parse()
+            .map_err(|_| CliError::InvalidArgument(format!("invalid address {}", address)))?;
+        let service = Metrics {
+            schema,
+            batches: Arc::new(batches),
+        };
+
+        info!("Serving the {} flight on {}", FLIGHT, address);
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(
+                Server::builder()
+                    .add_service(FlightServiceServer::new(service))
+                    .serve(address),
+            )
+            .map_err(|err| CliError::ExportError(err.to_string()))
+    }
+}
@@ -0,0 +1,197 @@
+//! The function spaces of a file as found in its syntax tree, for what rust-code-analysis
+//! doesn't report about them: the column they start at, their signature and whether they're
+//! C++ templates, and where the file failed to parse
+
+use rust_code_analysis::{FuncSpace, LANG};
+use std::collections::{HashMap, HashSet};
+use tree_sitter::Node;
+
+use crate::objc;
+use crate::parse_errors::ParseErrors;
+use crate::syntax::{collapse_whitespace, parse, signature, symbol_name};
+
+/// A function as found in the syntax tree
+#[derive(Debug, Clone)]
+pub struct FunctionNode {
+    /// 1-based column the function starts at
+    pub column: usize,
+    /// The declaration up to the body, e.g. `int area(const Shape &shape) const`
+    pub signature: String,
+    /// Whether it's a C++ function template or one of its explicit specializations, with
+    /// the name of the template it belongs to
+    pub template: Option<(Template, String)>,
+}
+
+/// The part a function plays in a C++ template
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Template {
+    /// `template <typename T> T max(T a, T b)`
+    Definition,
+    /// `template <> int max<int>(int a, int b)`
+    Specialization,
+}
+
+impl Template {
+    /// The `kind` of a space of `kind` playing this part, e.g. `function_template`
+    pub fn kind(self, kind: &str) -> String {
+        match self {
+            Template::Definition => format!("{}_template", kind),
+            Template::Specialization => format!("{}_specialization", kind),
+        }
+    }
+}
+
+/// The functions of a single file, found by the lines they span
+#[derive(Debug, Default)]
+pub struct FunctionNodes {
+    /// Functions starting and ending on every pair of 1-based lines, in the order
+    /// rust-code-analysis finds them
+    by_lines: HashMap<(usize, usize), Vec<FunctionNode>>,
+    /// Names of the function templates defined in the file
+    templates: HashSet<String>,
+    /// Number of explicit specializations of every function template
+    specializations: HashMap<String, usize>,
+    /// Where the file failed to parse
+    parse_errors: Option<ParseErrors>,
+}
+
+impl FunctionNodes {
+    /// Parses `source` again to find the nodes of the spaces rust-code-analysis makes
+    /// functions of, none when the language has no grammar here
+    pub fn new(language: &LANG, source: &[u8]) -> Self {
+        let mut functions = Self::default();
+        if let Some(tree) = parse(language, source) {
+            functions.parse_errors = Some(ParseErrors::new(&tree, source));
+            let mut stack = vec![tree.root_node()];
+            while let Some(node) = stack.pop() {
+                if is_function_space(language, &node) {
+                    let template = match language {
+                        LANG::Cpp => template_of(&node, source),
+                        _ => None,
+                    };
+                    match &template {
+                        Some((Template::Definition, name)) => {
+                            functions.templates.insert(name.clone());
+                        }
+                        Some((Template::Specialization, name)) => {
+                            *functions.specializations.entry(name.clone()).or_default() += 1;
+                        }
+                        None => {}
+                    }
+                    functions
+                        .by_lines
+                        .entry((node.start_position().row + 1, node.end_position().row + 1))
+                        .or_default()
+                        .push(FunctionNode {
+                            column: node.start_position().column + 1,
+                            signature: objc_signature(function_signature(&node, source)),
+                            template,
+                        });
+                }
+                let mut cursor = node.walk();
+                let children: Vec<Node> = node.named_children(&mut cursor).collect();
+                stack.extend(children.into_iter().rev());
+            }
+        }
+        functions
+    }
+
+    /// The node of the `nth` function space spanning the lines of `space`
+    pub fn get(&self, space: &FuncSpace, nth: usize) -> Option<&FunctionNode> {
+        self.by_lines
+            .get(&(space.start_line, space.end_line))
+            .and_then(|nodes| nodes.get(nth))
+    }
+
+    /// Whether the function template `name` is defined in the file
+    pub fn defines_template(&self, name: &str) -> bool {
+        self.templates.contains(name)
+    }
+
+    /// Where the file failed to parse, unless the language has no grammar here
+    pub fn parse_errors(&self) -> Option<&ParseErrors> {
+        self.parse_errors.as_ref()
+    }
+
+    /// Number of explicit specializations of the function template `name` in the file
+    pub fn specializations(&self, name: &str) -> usize {
+        self.specializations.get(name).copied().unwrap_or_default()
+    }
+}
+
+/// Whether the C++ function at `node` is a template, going by the `template` declaration
+/// around it, along with the name of the template without any template arguments. An empty
+/// parameter list, `template <>`, makes an explicit specialization
+fn template_of(node: &Node, source: &[u8]) -> Option<(Template, String)> {
+    let declaration = node
+        .parent()
+        .filter(|parent| parent.kind() == "template_declaration")?;
+    let template = if declaration
+        .child_by_field_name("parameters")
+        .is_some_and(|parameters| parameters.named_child_count() > 0)
+    {
+        Template::Definition
+    } else {
+        Template::Specialization
+    };
+    let name = symbol_name(&LANG::Cpp, node, source)?;
+    Some((template, strip_template_arguments(&name)))
+}
+
+/// `name` without the template arguments in it, e.g. `Stack::push` for `Stack<T>::push`.
+/// Operators are left as they are, their `<` and `>` aren't brackets
+pub fn strip_template_arguments(name: &str) -> String {
+    if name.contains("operator") {
+        return name.to_string();
+    }
+    let mut stripped = String::new();
+    let mut depth = 0usize;
+    for c in name.chars() {
+        match c {
+            '<' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            c if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped.trim().to_string()
+}
+
+/// The `signature` of the function at `node`, leaving out the member initializers of C++
+/// constructors
+fn function_signature(node: &Node, source: &[u8]) -> String {
+    let mut cursor = node.walk();
+    let initializers = node
+        .children(&mut cursor)
+        .find(|child| child.kind() == "field_initializer_list");
+    match initializers {
+        Some(initializers) => collapse_whitespace(&String::from_utf8_lossy(
+            &source[node.start_byte()..initializers.start_byte()],
+        )),
+        None => signature(node, source),
+    }
+}
+
+/// `signature` as written in Objective-C when it's that of a method `objc::preprocess`
+/// rewrote
+fn objc_signature(signature: String) -> String {
+    objc::signature(&signature).unwrap_or(signature)
+}
+
+/// Whether rust-code-analysis makes a function space of `node`
+fn is_function_space(language: &LANG, node: &Node) -> bool {
+    match language {
+        LANG::Cpp | LANG::Python => node.kind() == "function_definition",
+        LANG::Rust => matches!(node.kind(), "function_item" | "closure_expression"),
+        LANG::Mozjs | LANG::Javascript | LANG::Typescript | LANG::Tsx => matches!(
+            node.kind(),
+            "function"
+                | "function_declaration"
+                | "generator_function"
+                | "generator_function_declaration"
+                | "method_definition"
+                | "arrow_function"
+        ),
+        _ => false,
+    }
+}
@@ -0,0 +1,498 @@
+//! `--fail-on` rules checked against every extracted function, failing the run when any
+//! function breaks one, and the JUnit report of the outcome for CI servers. Functions with
+//! an `archaeo:ignore` comment are exempt
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use tracing::{info, warn};
+
+use crate::errors::CliError;
+use crate::extract::MetricsRow;
+
+/// How a metric is compared to the limit of a rule
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Comparison {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    AtLeast,
+    #[serde(rename = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    AtMost,
+}
+
+impl Comparison {
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Above => ">",
+            Comparison::AtLeast => ">=",
+            Comparison::Below => "<",
+            Comparison::AtMost => "<=",
+        }
+    }
+
+    /// The comparison breaking a limit given with this one
+    fn negated(self) -> Self {
+        match self {
+            Comparison::Above => Comparison::AtMost,
+            Comparison::AtLeast => Comparison::Below,
+            Comparison::Below => Comparison::AtLeast,
+            Comparison::AtMost => Comparison::Above,
+        }
+    }
+
+    fn holds(self, value: f64, limit: f64) -> bool {
+        match self {
+            Comparison::Above => value > limit,
+            Comparison::AtLeast => value >= limit,
+            Comparison::Below => value < limit,
+            Comparison::AtMost => value <= limit,
+        }
+    }
+}
+
+/// A condition on a metric column that fails a function, e.g. `cyclomatic>15`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub metric: String,
+    pub comparison: Comparison,
+    pub limit: f64,
+    /// The budget the rule enforces the limit of, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget: Option<String>,
+}
+
+impl Rule {
+    /// Whether `row` breaks the rule. Rows lacking the metric pass it
+    pub fn breaks(&self, row: &MetricsRow) -> bool {
+        row.get(&self.metric)
+            .and_then(|value| value.as_f64())
+            .is_some_and(|value| self.comparison.holds(value, self.limit))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.budget {
+            Some(budget) => write!(
+                f,
+                "the {} budget of {}{}{}",
+                budget,
+                self.metric,
+                self.comparison.negated().symbol(),
+                self.limit
+            ),
+            None => write!(
+                f,
+                "{}{}{}",
+                self.metric,
+                self.comparison.symbol(),
+                self.limit
+            ),
+        }
+    }
+}
+
+/// Parses a rule of the form `<metric><op><limit>`, with `>`, `>=`, `<` or `<=` as the op
+pub fn parse_rule(value: &str) -> Result<Rule, String> {
+    let at = value
+        .find(['<', '>'])
+        .ok_or_else(|| format!("expected e.g. cyclomatic>15, got {}", value))?;
+    let (metric, rest) = value.split_at(at);
+    let (comparison, limit) = if let Some(limit) = rest.strip_prefix(">=") {
+        (Comparison::AtLeast, limit)
+    } else if let Some(limit) = rest.strip_prefix("<=") {
+        (Comparison::AtMost, limit)
+    } else if let Some(limit) = rest.strip_prefix('>') {
+        (Comparison::Above, limit)
+    } else {
+        (Comparison::Below, &rest[1..])
+    };
+    let metric = metric.trim();
+    if metric.is_empty() {
+        return Err(format!("missing the metric of {}", value));
+    }
+    let limit = limit
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid limit {} in {}", limit, value))?;
+    Ok(Rule {
+        metric: metric.to_string(),
+        comparison,
+        limit,
+        budget: None,
+    })
+}
+
+/// Parses a limit of `budget` a metric has to stay within, e.g. `cyclomatic<=25`, into the
+/// rule breaking it
+pub fn parse_limit(value: &str, budget: &str) -> Result<Rule, String> {
+    let rule = parse_rule(value)?;
+    Ok(Rule {
+        comparison: rule.comparison.negated(),
+        budget: Some(budget.to_string()),
+        ..rule
+    })
+}
+
+/// A rule a function breaks, with the value it has
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub rule: String,
+    pub metric: String,
+    pub value: f64,
+    pub budget: Option<String>,
+}
+
+/// A function the rules were checked against
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub source_file: String,
+    pub name: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    pub violations: Vec<Violation>,
+    /// Exempt from the rules by a suppression comment
+    pub suppressed: bool,
+    /// The budget that applies to the function
+    pub budget: Option<String>,
+}
+
+impl Outcome {
+    pub fn failed(&self) -> bool {
+        !self.suppressed && !self.violations.is_empty()
+    }
+}
+
+/// Checks every row against every rule. Rows lacking the metric of a rule pass it
+pub fn check(rules: &[Rule], rows: &[MetricsRow]) -> Vec<Outcome> {
+    rows.iter()
+        .map(|row| {
+            let text = |column: &str| row.get(column).and_then(|v| v.as_str()).unwrap_or("");
+            let line = |column: &str| row.get(column).and_then(|v| v.as_u64()).unwrap_or(0);
+            let violations = rules
+                .iter()
+                .filter(|rule| rule.breaks(row))
+                .map(|rule| Violation {
+                    rule: rule.to_string(),
+                    metric: rule.metric.clone(),
+                    value: row[&rule.metric].as_f64().unwrap_or_default(),
+                    budget: rule.budget.clone(),
+                })
+                .collect();
+            Outcome {
+                source_file: text("source_file").to_string(),
+                name: text("name").to_string(),
+                start_line: line("start_line"),
+                end_line: line("end_line"),
+                violations,
+                suppressed: false,
+                budget: None,
+            }
+        })
+        .collect()
+}
+
+/// Result of a run with rules, failing when any function broke one of them
+pub fn verdict(outcomes: &[Outcome]) -> Result<(), CliError> {
+    for outcome in outcomes.iter().filter(|outcome| outcome.failed()) {
+        for violation in &outcome.violations {
+            warn!(
+                file = %outcome.source_file,
+                line = outcome.start_line,
+                "{} breaks {} with {}",
+                outcome.name,
+                violation.rule,
+                violation.value
+            );
+        }
+    }
+    let suppressed = outcomes
+        .iter()
+        .filter(|outcome| outcome.suppressed && !outcome.violations.is_empty())
+        .count();
+    if suppressed > 0 {
+        info!(
+            "Ignored rules broken by {} suppressed functions",
+            suppressed
+        );
+    }
+    let mut budgets: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for outcome in outcomes {
+        if let Some(budget) = &outcome.budget {
+            let counts = budgets.entry(budget).or_default();
+            counts.0 += 1;
+            let over = outcome.failed()
+                && outcome
+                    .violations
+                    .iter()
+                    .any(|violation| violation.budget.is_some());
+            counts.1 += over as usize;
+        }
+    }
+    for (budget, (functions, over)) in budgets {
+        info!(
+            "Budget {}: {} of {} functions over",
+            budget, over, functions
+        );
+    }
+
+    let failed = outcomes.iter().filter(|outcome| outcome.failed()).count();
+    if failed == 0 {
+        return Ok(());
+    }
+    Err(CliError::ThresholdExceeded(format!(
+        "{} of {} functions break a --fail-on rule or budget",
+        failed,
+        outcomes.len()
+    )))
+}
+
+/// Writes a JUnit report with a test suite per source file and a test case per function,
+/// failed when the function breaks any rule and skipped when it is suppressed
+pub fn write_junit<W: Write>(mut writer: W, outcomes: &[Outcome]) -> Result<(), CliError> {
+    let mut files: BTreeMap<&str, Vec<&Outcome>> = BTreeMap::new();
+    for outcome in outcomes {
+        files.entry(&outcome.source_file).or_default().push(outcome);
+    }
+    let failures = |functions: &[&Outcome]| functions.iter().filter(|o| o.failed()).count();
+    let skipped = |functions: &[&Outcome]| functions.iter().filter(|o| o.suppressed).count();
+    let all: Vec<&Outcome> = outcomes.iter().collect();
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<testsuites name="archaeo" tests="{}" failures="{}" skipped="{}">"#,
+        outcomes.len(),
+        failures(&all),
+        skipped(&all)
+    )?;
+    for (file, functions) in &files {
+        writeln!(
+            writer,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}">"#,
+            escape(file),
+            functions.len(),
+            failures(functions),
+            skipped(functions)
+        )?;
+        for outcome in functions {
+            write!(
+                writer,
+                r#"    <testcase classname="{}" name="{}:{}" file="{}" line="{}""#,
+                escape(file),
+                escape(&outcome.name),
+                outcome.start_line,
+                escape(file),
+                outcome.start_line
+            )?;
+            if outcome.suppressed {
+                writeln!(writer, ">")?;
+                writeln!(
+                    writer,
+                    r#"      <skipped message="suppressed by an archaeo:ignore comment"/>"#
+                )?;
+                writeln!(writer, "    </testcase>")?;
+                continue;
+            }
+            if !outcome.failed() {
+                writeln!(writer, "/>")?;
+                continue;
+            }
+            writeln!(writer, ">")?;
+            for violation in &outcome.violations {
+                writeln!(
+                    writer,
+                    r#"      <failure type="{}" message="{} is {}, breaking {}">{}:{}-{}</failure>"#,
+                    escape(&violation.metric),
+                    escape(&violation.metric),
+                    violation.value,
+                    escape(&violation.rule),
+                    escape(file),
+                    outcome.start_line,
+                    outcome.end_line
+                )?;
+            }
+            writeln!(writer, "    </testcase>")?;
+        }
+        writeln!(writer, "  </testsuite>")?;
+    }
+    writeln!(writer, "</testsuites>")?;
+    Ok(())
+}
+
+/// Writes a GitHub Actions `::warning` workflow command for every rule a function breaks,
+/// shown inline on the lines of the function in pull requests. Files are taken relative to
+/// the working directory, the checkout in a workflow
+pub fn write_annotations<W: Write>(mut writer: W, outcomes: &[Outcome]) -> Result<(), CliError> {
+    for outcome in outcomes.iter().filter(|outcome| outcome.failed()) {
+        let file = outcome.source_file.trim_start_matches("./");
+        for violation in &outcome.violations {
+            writeln!(
+                writer,
+                "::warning file={},line={},endLine={},title={}::{}",
+                escape_property(file),
+                outcome.start_line,
+                outcome.end_line,
+                escape_property(&format!("archaeo {}", violation.rule)),
+                escape_data(&format!(
+                    "{} has a {} of {}, breaking {}",
+                    outcome.name, violation.metric, violation.value, violation.rule
+                ))
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An outcome of `check` for a function named `name` in `file`
+    fn outcome(file: &str, name: &str, rules: &[&str], cyclomatic: f64) -> Outcome {
+        let rules: Vec<Rule> = rules.iter().map(|rule| parse_rule(rule).unwrap()).collect();
+        let row = serde_json::json!({
+            "source_file": file,
+            "name": name,
+            "start_line": 3,
+            "end_line": 9,
+            "cyclomatic": cyclomatic,
+        });
+        check(&rules, &[row.as_object().unwrap().clone()]).remove(0)
+    }
+
+    #[test]
+    fn rules_are_parsed_with_every_comparison() {
+        let parsed = |value: &str| {
+            let rule = parse_rule(value).unwrap();
+            (rule.metric, rule.comparison, rule.limit)
+        };
+        assert_eq!(
+            parsed("cyclomatic>15"),
+            ("cyclomatic".to_string(), Comparison::Above, 15.0)
+        );
+        assert_eq!(
+            parsed("cognitive >= 7.5"),
+            ("cognitive".to_string(), Comparison::AtLeast, 7.5)
+        );
+        assert_eq!(
+            parsed("mi_visual_studio<20"),
+            ("mi_visual_studio".to_string(), Comparison::Below, 20.0)
+        );
+        assert_eq!(
+            parsed("nargs<=-1"),
+            ("nargs".to_string(), Comparison::AtMost, -1.0)
+        );
+        assert_eq!(
+            parse_rule("cyclomatic >= 15").unwrap().to_string(),
+            "cyclomatic>=15"
+        );
+    }
+
+    #[test]
+    fn broken_rules_are_refused() {
+        assert_eq!(
+            parse_rule("cyclomatic=15").unwrap_err(),
+            "expected e.g. cyclomatic>15, got cyclomatic=15"
+        );
+        assert_eq!(parse_rule(">15").unwrap_err(), "missing the metric of >15");
+        assert_eq!(
+            parse_rule("cyclomatic>=ten").unwrap_err(),
+            "invalid limit ten in cyclomatic>=ten"
+        );
+        assert!(parse_rule("cyclomatic>").is_err());
+        assert!(parse_rule("cyclomatic<>1").is_err());
+    }
+
+    #[test]
+    fn rows_lacking_the_metric_pass() {
+        let rule = parse_rule("cognitive>1").unwrap();
+        let row = serde_json::json!({ "cognitive": null });
+        assert!(!rule.breaks(row.as_object().unwrap()));
+        assert!(!outcome("a.c", "f", &["cognitive>1"], 20.0).failed());
+        assert!(outcome("a.c", "f", &["cyclomatic>=20"], 20.0).failed());
+        assert!(!outcome("a.c", "f", &["cyclomatic>20"], 20.0).failed());
+    }
+
+    #[test]
+    fn junit_reports_escape_hostile_names() {
+        let mut suppressed = outcome("b.c", "ok", &["cyclomatic>1"], 5.0);
+        suppressed.suppressed = true;
+        let outcomes = [
+            outcome(
+                "a&<\"'>.c",
+                "operator<<&\"x\"",
+                &["cyclomatic>1", "cyclomatic>=2"],
+                5.0,
+            ),
+            outcome("a&<\"'>.c", "g", &["cyclomatic>10"], 5.0),
+            suppressed,
+        ];
+        let mut report = Vec::new();
+        write_junit(&mut report, &outcomes).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="archaeo" tests="3" failures="1" skipped="1">
+  <testsuite name="a&amp;&lt;&quot;&apos;&gt;.c" tests="2" failures="1" skipped="0">
+    <testcase classname="a&amp;&lt;&quot;&apos;&gt;.c" name="operator&lt;&lt;&amp;&quot;x&quot;:3" file="a&amp;&lt;&quot;&apos;&gt;.c" line="3">
+      <failure type="cyclomatic" message="cyclomatic is 5, breaking cyclomatic&gt;1">a&amp;&lt;&quot;&apos;&gt;.c:3-9</failure>
+      <failure type="cyclomatic" message="cyclomatic is 5, breaking cyclomatic&gt;=2">a&amp;&lt;&quot;&apos;&gt;.c:3-9</failure>
+    </testcase>
+    <testcase classname="a&amp;&lt;&quot;&apos;&gt;.c" name="g:3" file="a&amp;&lt;&quot;&apos;&gt;.c" line="3"/>
+  </testsuite>
+  <testsuite name="b.c" tests="1" failures="0" skipped="1">
+    <testcase classname="b.c" name="ok:3" file="b.c" line="3">
+      <skipped message="suppressed by an archaeo:ignore comment"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+
+        let mut report = Vec::new();
+        write_junit(&mut report, &[]).unwrap();
+        assert!(String::from_utf8(report)
+            .unwrap()
+            .contains(r#"tests="0" failures="0" skipped="0">"#));
+    }
+
+    #[test]
+    fn annotations_escape_workflow_command_properties_and_data() {
+        let outcomes = [
+            outcome("./src/a,b:c.c", "f:g,h%\n", &["cyclomatic>1"], 5.0),
+            outcome("./src/a,b:c.c", "passing", &["cyclomatic>10"], 5.0),
+        ];
+        let mut annotations = Vec::new();
+        write_annotations(&mut annotations, &outcomes).unwrap();
+        assert_eq!(
+            String::from_utf8(annotations).unwrap(),
+            "::warning file=src/a%2Cb%3Ac.c,line=3,endLine=9,title=archaeo cyclomatic>1::\
+             f:g,h%25%0A has a cyclomatic of 5, breaking cyclomatic>1\n"
+        );
+        assert_eq!(escape_data("50%\r\n"), "50%25%0D%0A");
+        assert_eq!(escape_property("a:b,c%"), "a%3Ab%2Cc%25");
+    }
+}
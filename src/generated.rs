@@ -0,0 +1,116 @@
+//! Heuristics for telling apart hand-written sources from binary, generated, minified
+//! and amalgamated files, whose metrics would skew a corpus
+
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// Markers generators leave in the header of their output
+const GENERATED_MARKERS: [&str; 6] = [
+    "@generated",
+    "do not edit",
+    "code generated",
+    "auto-generated",
+    "autogenerated",
+    "generated by",
+];
+
+/// Number of leading lines searched for markers
+const HEADER_LINES: usize = 20;
+
+/// Lines longer than this only come out of minifiers
+const MINIFIED_LINE_LENGTH: usize = 1000;
+
+/// A file left out of a run along with why
+#[derive(Debug, Clone, Serialize)]
+pub struct Skipped {
+    pub source_file: String,
+    pub reason: String,
+    /// The first walked file with the same contents, when `--dedup` collapsed it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+}
+
+/// Why `path` looks binary or generated, if it does
+pub fn generated_reason(path: &Path, max_bytes: u64) -> Option<String> {
+    let size = fs::metadata(path).ok()?.len();
+    if size > max_bytes {
+        return Some(format!("larger than {} bytes ({} bytes)", max_bytes, size));
+    }
+    generated_reason_of(&fs::read(path).ok()?, max_bytes)
+}
+
+/// Like `generated_reason`, for the contents of a file held in memory
+pub fn generated_reason_of(content: &[u8], max_bytes: u64) -> Option<String> {
+    let size = content.len() as u64;
+    if size > max_bytes {
+        return Some(format!("larger than {} bytes ({} bytes)", max_bytes, size));
+    }
+    if content.iter().take(8000).any(|&byte| byte == 0) {
+        return Some("binary".to_string());
+    }
+
+    let text = String::from_utf8_lossy(content);
+    for line in text.lines().take(HEADER_LINES) {
+        let line = line.to_lowercase();
+        if line.contains("amalgamation") {
+            return Some("amalgamated".to_string());
+        }
+        if let Some(marker) = GENERATED_MARKERS.iter().find(|m| line.contains(*m)) {
+            return Some(format!("generated ({} marker)", marker));
+        }
+    }
+
+    let longest = text.lines().map(|line| line.chars().count()).max()?;
+    (longest > MINIFIED_LINE_LENGTH).then(|| format!("minified (line of {} characters)", longest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hand_written_sources_have_no_reason() {
+        let source = b"// Copyright notice\nint f() {\n    return 0;\n}\n";
+        assert_eq!(generated_reason_of(source, 1024), None);
+        assert_eq!(generated_reason_of(b"", 1024), None);
+    }
+
+    #[test]
+    fn markers_in_the_header_tell_generated_and_amalgamated_files() {
+        assert_eq!(
+            generated_reason_of(b"// @generated by protoc\nint f();\n", 1024).as_deref(),
+            Some("generated (@generated marker)")
+        );
+        assert_eq!(
+            generated_reason_of(b"/* DO NOT EDIT */\n", 1024).as_deref(),
+            Some("generated (do not edit marker)")
+        );
+        assert_eq!(
+            generated_reason_of(b"/* SQLite amalgamation */\n", 1024).as_deref(),
+            Some("amalgamated")
+        );
+
+        // Only the header is searched
+        let mut late = "int f();\n".repeat(HEADER_LINES);
+        late.push_str("// generated by hand\n");
+        assert_eq!(generated_reason_of(late.as_bytes(), 1024), None);
+    }
+
+    #[test]
+    fn binary_minified_and_large_files_are_told_by_their_contents() {
+        assert_eq!(
+            generated_reason_of(b"ELF\0\0\x01", 1024).as_deref(),
+            Some("binary")
+        );
+        let minified = "x".repeat(MINIFIED_LINE_LENGTH + 1);
+        assert_eq!(
+            generated_reason_of(minified.as_bytes(), 4096).as_deref(),
+            Some("minified (line of 1001 characters)")
+        );
+        assert_eq!(
+            generated_reason_of(minified.as_bytes(), 1000).as_deref(),
+            Some("larger than 1000 bytes (1001 bytes)")
+        );
+    }
+}
@@ -0,0 +1,644 @@
+//! Reading revisions of a git repository through the `git` executable
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+use crate::errors::CliError;
+use crate::tree::SourceTree;
+
+/// Why git couldn't be run when it isn't installed
+const NOT_INSTALLED: &str = "git was not found on PATH, install it to read revisions and history";
+
+/// The error of a git that couldn't be started, telling when it isn't installed
+fn not_run(err: io::Error) -> CliError {
+    if err.kind() == io::ErrorKind::NotFound {
+        return CliError::GitError(NOT_INSTALLED.to_string());
+    }
+    CliError::GitError(format!("failed to run git: {}", err))
+}
+
+/// Runs git in `repo`, returning its standard output
+fn git(repo: &Path, args: &[&str]) -> Result<Vec<u8>, CliError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["-c", "core.quotepath=off"])
+        .args(args)
+        .output()
+        .map_err(not_run)?;
+    if !output.status.success() {
+        return Err(CliError::GitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Resolves `rev` to the full hash of the commit it names
+pub fn resolve(repo: &Path, rev: &str) -> Result<String, CliError> {
+    let commit = format!("{}^{{commit}}", rev);
+    let stdout =
+        git(repo, &["rev-parse", "--verify", "--quiet", &commit]).map_err(|err| match err {
+            CliError::GitError(message) if message == NOT_INSTALLED => CliError::GitError(message),
+            _ => CliError::GitError(format!("unknown revision {}", rev)),
+        })?;
+    Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+}
+
+/// Unpacks the tree of `commit` into a temporary directory, removed once dropped
+pub fn checkout(repo: &Path, commit: &str) -> Result<TempDir, CliError> {
+    let dir = tempfile::Builder::new().prefix("archaeo-").tempdir()?;
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["archive", "--format=tar", commit])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(not_run)?;
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    tar::Archive::new(stdout).unpack(dir.path())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(CliError::GitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(dir)
+}
+
+/// The files of a commit read straight from the object database of a repository, which
+/// can be bare, rather than from a checkout. They're named below the repository as if it
+/// were checked out there
+#[derive(Debug)]
+pub struct GitTree {
+    root: PathBuf,
+    /// The blob of every file below the root, leaving out symlinks and submodules
+    blobs: BTreeMap<PathBuf, String>,
+    /// The `git cat-file --batch` blobs are read through, started on the first read
+    batch: Mutex<Option<CatFile>>,
+}
+
+#[derive(Debug)]
+struct CatFile {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Drop for CatFile {
+    fn drop(&mut self) {
+        // git exits once its input is closed
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+impl GitTree {
+    /// Lists the files of `rev` in `repo`
+    pub fn open(repo: &Path, rev: &str) -> Result<Self, CliError> {
+        let commit = resolve(repo, rev)?;
+        let listing = git(repo, &["ls-tree", "-r", "-z", "--full-tree", &commit])?;
+        let mut blobs = BTreeMap::new();
+        for entry in listing.split(|&c| c == 0).filter(|entry| !entry.is_empty()) {
+            // Paths are kept as the bytes git has them, which needn't be UTF-8
+            let Some(tab) = entry.iter().position(|&c| c == b'\t') else {
+                continue;
+            };
+            let (meta, path) = (String::from_utf8_lossy(&entry[..tab]), &entry[tab + 1..]);
+            let mut fields = meta.split(' ');
+            if let (Some(mode), Some("blob"), Some(oid)) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                if mode != "120000" {
+                    blobs.insert(path_from_bytes(path), oid.to_string());
+                }
+            }
+        }
+        Ok(Self {
+            root: repo.to_path_buf(),
+            blobs,
+            batch: Mutex::new(None),
+        })
+    }
+}
+
+/// The path a git path names, unchanged on unix where paths are bytes
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    return PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(bytes));
+    #[cfg(not(unix))]
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+impl SourceTree for GitTree {
+    fn files(&self) -> Vec<PathBuf> {
+        self.blobs
+            .keys()
+            .map(|relative| self.root.join(relative))
+            .collect()
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.blobs.contains_key(self.relative(path))
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    /// Reads the blob of the file `path` names below the repository
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let oid = self.blobs.get(self.relative(path)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "not in the tree of the revision")
+        })?;
+        let mut batch = self.batch.lock().unwrap_or_else(|err| err.into_inner());
+        if batch.is_none() {
+            let mut child = Command::new("git")
+                .arg("-C")
+                .arg(&self.root)
+                .args(["cat-file", "--batch"])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+            let stdin = child.stdin.take();
+            let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+            *batch = Some(CatFile {
+                child,
+                stdin,
+                stdout,
+            });
+        }
+        let read = batch.as_mut().expect("started above").read(oid);
+        // The output can't be followed anymore once a read fails, a retry starts afresh
+        if read.is_err() {
+            *batch = None;
+        }
+        read
+    }
+}
+
+impl CatFile {
+    fn read(&mut self, oid: &str) -> io::Result<Vec<u8>> {
+        let stdin = self.stdin.as_mut().expect("open until dropped");
+        writeln!(stdin, "{}", oid)?;
+        stdin.flush()?;
+
+        // `<oid> blob <size>` followed by the contents and a newline
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        let size = header
+            .split_whitespace()
+            .nth(2)
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| io::Error::other(format!("unexpected git output {}", header.trim())))?;
+        let mut content = vec![0; size];
+        self.stdout.read_exact(&mut content)?;
+        self.stdout.read_exact(&mut [0; 1])?;
+        Ok(content)
+    }
+}
+
+/// Where the lines of a file moved to between two revisions, from the hunks of their diff
+#[derive(Debug, Default)]
+pub struct LineMap {
+    /// `(old_start, old_count, new_start, new_count)` of every hunk, in order
+    hunks: Vec<(usize, usize, usize, usize)>,
+}
+
+impl LineMap {
+    /// The line `line` of the old revision is at in the new one. Lines within a changed
+    /// hunk map to the line at the same offset into its replacement, or its last line
+    pub fn map(&self, line: usize) -> usize {
+        let mut offset: i64 = 0;
+        for &(old_start, old_count, new_start, new_count) in &self.hunks {
+            // A hunk without old lines inserts after `old_start`, one without new lines
+            // deletes after `new_start`
+            let old_end = if old_count == 0 {
+                old_start + 1
+            } else {
+                old_start + old_count
+            };
+            let new_end = if new_count == 0 {
+                new_start + 1
+            } else {
+                new_start + new_count
+            };
+            if line >= old_end {
+                offset = new_end as i64 - old_end as i64;
+            } else if line >= old_start && old_count > 0 {
+                return new_start + (line - old_start).min(new_count.saturating_sub(1));
+            } else {
+                break;
+            }
+        }
+        (line as i64 + offset).max(1) as usize
+    }
+}
+
+/// The line maps of every file changed between `from` and `to`, keyed by its path in `from`
+pub fn line_maps(repo: &Path, from: &str, to: &str) -> Result<HashMap<String, LineMap>, CliError> {
+    let stdout = git(
+        repo,
+        &[
+            "diff",
+            "-U0",
+            "--no-color",
+            "--no-ext-diff",
+            "--no-renames",
+            from,
+            to,
+        ],
+    )?;
+
+    Ok(parse_diff(&String::from_utf8_lossy(&stdout))
+        .into_iter()
+        .filter_map(|file| Some((file.old_path.clone()?, file.line_map())))
+        .collect())
+}
+
+/// Applies the unified `diff` to the files below `dir`, taking the `a/` and `b/` prefixes of
+/// git off its paths when it has them
+pub fn apply(dir: &Path, diff: &str) -> Result<(), CliError> {
+    // A repository of its own keeps git from applying the diff to one `dir` is nested in
+    git(dir, &["init", "-q"])?;
+    let mut patch = tempfile::NamedTempFile::new()?;
+    patch.write_all(diff.as_bytes())?;
+    let prefixed = diff
+        .lines()
+        .any(|line| line.starts_with("--- a/") || line.starts_with("+++ b/"));
+    let strip = if prefixed { "-p1" } else { "-p0" };
+    let patch = patch.path().to_string_lossy();
+    git(dir, &["apply", "--whitespace=nowarn", strip, &patch])?;
+    Ok(())
+}
+
+/// The hunks a unified diff changes a file with
+#[derive(Debug, Default, Clone)]
+pub struct FileDiff {
+    /// Path of the file before the change, `None` when the diff creates it
+    pub old_path: Option<String>,
+    /// Path of the file after the change, `None` when the diff deletes it
+    pub new_path: Option<String>,
+    /// `(old_start, old_count, new_start, new_count)` of every hunk, in order
+    pub hunks: Vec<(usize, usize, usize, usize)>,
+}
+
+impl FileDiff {
+    pub fn line_map(&self) -> LineMap {
+        LineMap {
+            hunks: self.hunks.clone(),
+        }
+    }
+}
+
+/// Parses the files and hunks of a unified diff, as written by `git diff` or `diff -u`. The
+/// `a/` and `b/` prefixes of git are dropped from the paths
+pub fn parse_diff(text: &str) -> Vec<FileDiff> {
+    let path = |header: &str, prefix: &str| -> Option<String> {
+        // `diff -u` follows the path with a tab and the modification time
+        let path = header.split('\t').next().unwrap_or(header).trim();
+        match path {
+            "/dev/null" => None,
+            _ => Some(path.strip_prefix(prefix).unwrap_or(path).to_string()),
+        }
+    };
+
+    let mut files: Vec<FileDiff> = Vec::new();
+    // Old and new lines left in the body of the current hunk, which may look like headers
+    let (mut old_left, mut new_left): (usize, usize) = (0, 0);
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        if old_left > 0 || new_left > 0 {
+            match line.chars().next() {
+                Some('-') => old_left = old_left.saturating_sub(1),
+                Some('+') => new_left = new_left.saturating_sub(1),
+                Some('\\') => {}
+                _ => {
+                    old_left = old_left.saturating_sub(1);
+                    new_left = new_left.saturating_sub(1);
+                }
+            }
+            continue;
+        }
+        if let Some(old) = line.strip_prefix("--- ") {
+            let Some(new) = lines.peek().and_then(|next| next.strip_prefix("+++ ")) else {
+                continue;
+            };
+            files.push(FileDiff {
+                old_path: path(old, "a/"),
+                new_path: path(new, "b/"),
+                hunks: Vec::new(),
+            });
+            lines.next();
+        } else if let Some(header) = line.strip_prefix("@@ ") {
+            let (Some(file), Some(hunk)) = (files.last_mut(), parse_hunk(header)) else {
+                continue;
+            };
+            (old_left, new_left) = (hunk.1, hunk.3);
+            file.hunks.push(hunk);
+        }
+    }
+    files
+}
+
+/// Parses `-a,b +c,d @@ ...` into `(a, b, c, d)`, where a missing count is one
+fn parse_hunk(header: &str) -> Option<(usize, usize, usize, usize)> {
+    let mut ranges = header.split_whitespace();
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(ranges.next()?.strip_prefix('-')?)?;
+    let (new_start, new_count) = range(ranges.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_count, new_start, new_count))
+}
+
+/// Whether the relative `path` stays below the directory it is joined to
+pub fn inside(path: &Path) -> bool {
+    path.components().all(|component| {
+        !matches!(
+            component,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    })
+}
+
+/// Refuses the diffed `files` whose paths leave the repository the diff applies to, as
+/// diffs arriving over `serve` are untrusted
+pub fn confine(files: &[FileDiff]) -> Result<(), CliError> {
+    match files
+        .iter()
+        .flat_map(|file| [&file.old_path, &file.new_path])
+        .flatten()
+        .find(|path| !inside(Path::new(path)))
+    {
+        Some(path) => Err(CliError::InvalidArgument(format!(
+            "{} is outside of the repository",
+            path
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Path of `file` within the checked out or working `tree`, with `/` separators like git's
+pub fn relative(file: &Path, tree: &Path) -> String {
+    file.strip_prefix(tree)
+        .unwrap_or(file)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The root of the working tree `repo` is in
+pub fn toplevel(repo: &Path) -> Result<PathBuf, CliError> {
+    let stdout = git(repo, &["rev-parse", "--show-toplevel"])?;
+    Ok(PathBuf::from(String::from_utf8_lossy(&stdout).trim()))
+}
+
+/// How often a file changed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Churn {
+    /// Commits touching the file
+    pub commits: u64,
+    /// Lines added and deleted over those commits
+    pub lines: u64,
+}
+
+/// The churn of every file in the history of `HEAD`, optionally only counting commits
+/// after `since` (anything `git log --since` accepts), keyed by its path in the repository
+pub fn churn(repo: &Path, since: Option<&str>) -> Result<HashMap<String, Churn>, CliError> {
+    let mut args = vec!["log", "--numstat", "--no-renames", "--format=%H"];
+    let since = since.map(|since| format!("--since={}", since));
+    if let Some(since) = &since {
+        args.push(since);
+    }
+    let stdout = git(repo, &args)?;
+
+    let mut churn: HashMap<String, Churn> = HashMap::new();
+    for line in String::from_utf8_lossy(&stdout).lines() {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(added), Some(deleted), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let file = churn.entry(path.to_string()).or_default();
+        file.commits += 1;
+        // Binary files are listed with `-` for both counts
+        file.lines += added.parse::<u64>().unwrap_or(0) + deleted.parse::<u64>().unwrap_or(0);
+    }
+    Ok(churn)
+}
+
+/// A commit of the first-parent history of a revision
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub hash: String,
+    /// Seconds since the Unix epoch the commit was made at
+    pub time: i64,
+    /// Paths of the files the commit changed, merges compared to their first parent
+    pub changed: Vec<String>,
+}
+
+/// The first-parent history of `rev`, oldest first, only its latest `limit` commits when
+/// given
+pub fn history(repo: &Path, rev: &str, limit: Option<usize>) -> Result<Vec<Commit>, CliError> {
+    let commit = resolve(repo, rev)?;
+    let mut args = vec![
+        "log",
+        "--first-parent",
+        "--reverse",
+        "--no-renames",
+        "--diff-merges=first-parent",
+        "--name-only",
+        "--format=%x00%H %ct",
+    ];
+    let limit = limit.map(|limit| format!("--max-count={}", limit));
+    if let Some(limit) = &limit {
+        args.push(limit);
+    }
+    args.push(&commit);
+    let stdout = git(repo, &args)?;
+
+    let mut commits = Vec::new();
+    for entry in String::from_utf8_lossy(&stdout).split('\0').skip(1) {
+        let mut lines = entry.lines();
+        let Some((hash, time)) = lines.next().and_then(|line| line.split_once(' ')) else {
+            continue;
+        };
+        commits.push(Commit {
+            hash: hash.to_string(),
+            time: time.parse().unwrap_or(0),
+            changed: lines
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        });
+    }
+    Ok(commits)
+}
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+/// `seconds` between two commit times in days, to a tenth
+pub fn days(seconds: i64) -> f64 {
+    (seconds.max(0) as f64 / SECONDS_PER_DAY * 10.0).round() / 10.0
+}
+
+/// The author of a line, as `git blame` tells it
+#[derive(Debug, Clone)]
+pub struct Blame {
+    /// Email of the author, lowercased, which tells authors apart
+    pub email: String,
+    pub name: String,
+    /// Seconds since the Unix epoch the line was last changed at
+    pub time: i64,
+}
+
+/// The author of every line of `file`, a path relative to `repo`, as committed at `HEAD`
+pub fn blame(repo: &Path, file: &str) -> Result<Vec<Blame>, CliError> {
+    let stdout = git(repo, &["blame", "--line-porcelain", "HEAD", "--", file])?;
+    let mut lines = Vec::new();
+    let (mut email, mut name, mut time) = (String::new(), String::new(), 0);
+    for line in String::from_utf8_lossy(&stdout).lines() {
+        if line.starts_with('\t') {
+            lines.push(Blame {
+                email: email.clone(),
+                name: name.clone(),
+                time,
+            });
+        } else if let Some(mail) = line.strip_prefix("author-mail ") {
+            email = mail.trim_matches(['<', '>']).to_lowercase();
+        } else if let Some(author) = line.strip_prefix("author ") {
+            name = author.to_string();
+        } else if let Some(at) = line.strip_prefix("author-time ") {
+            time = at.parse().unwrap_or(0);
+        }
+    }
+    Ok(lines)
+}
+
+/// The time of the latest commit of every author in the history of `HEAD`, by their
+/// lowercased email
+pub fn last_commits(repo: &Path) -> Result<HashMap<String, i64>, CliError> {
+    let stdout = git(repo, &["log", "--format=%aE %at"])?;
+    let mut last: HashMap<String, i64> = HashMap::new();
+    for line in String::from_utf8_lossy(&stdout).lines() {
+        let Some((email, time)) = line.rsplit_once(' ') else {
+            continue;
+        };
+        let time = time.parse().unwrap_or(0);
+        let latest = last.entry(email.to_lowercase()).or_insert(time);
+        *latest = (*latest).max(time);
+    }
+    Ok(last)
+}
+
+/// A commit of the history of a revision along with its message
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub hash: String,
+    pub message: String,
+    /// Paths of the files the commit changed
+    pub changed: Vec<String>,
+}
+
+/// The commits of the history of `rev` other than merges, with their messages
+pub fn messages(repo: &Path, rev: &str) -> Result<Vec<Message>, CliError> {
+    let commit = resolve(repo, rev)?;
+    let stdout = git(
+        repo,
+        &[
+            "log",
+            "--no-merges",
+            "--no-renames",
+            "--name-only",
+            "--format=%x00%H%x01%B%x01",
+            &commit,
+        ],
+    )?;
+
+    let mut commits = Vec::new();
+    for entry in String::from_utf8_lossy(&stdout).split('\0').skip(1) {
+        let mut fields = entry.splitn(3, '\x01');
+        let (Some(hash), Some(message), Some(changed)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        commits.push(Message {
+            hash: hash.to_string(),
+            message: message.trim().to_string(),
+            changed: changed
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        });
+    }
+    Ok(commits)
+}
+
+/// The files `commit` changed from its first parent, or added when it has none, without
+/// context lines or changes to whitespace only, so the old lines of every hunk are lines it
+/// removed or changed and the new ones lines it added or changed
+pub fn commit_diff(repo: &Path, commit: &str) -> Result<Vec<FileDiff>, CliError> {
+    let stdout = git(
+        repo,
+        &[
+            "show",
+            "--format=",
+            "--no-ext-diff",
+            "--no-renames",
+            "--diff-merges=first-parent",
+            "-w",
+            "-U0",
+            commit,
+        ],
+    )?;
+    Ok(parse_diff(&String::from_utf8_lossy(&stdout)))
+}
+
+/// The commits that last changed the `(start, count)` ranges of lines of `file` as of
+/// `rev`, each with the path the file had in it, ignoring changes to whitespace only
+pub fn blame_lines(
+    repo: &Path,
+    rev: &str,
+    file: &str,
+    ranges: &[(usize, usize)],
+) -> Result<Vec<(String, String)>, CliError> {
+    let ranges: Vec<String> = ranges
+        .iter()
+        .map(|(start, count)| format!("-L{},+{}", start, count))
+        .collect();
+    let mut args = vec!["blame", "--porcelain", "-w"];
+    args.extend(ranges.iter().map(String::as_str));
+    args.extend([rev, "--", file]);
+    let stdout = git(repo, &args)?;
+
+    let mut commits = Vec::new();
+    let mut commit = None;
+    for line in String::from_utf8_lossy(&stdout).lines() {
+        let first = line.split(' ').next().unwrap_or_default();
+        // Object ids are 40 hex digits long in SHA-1 repositories, 64 in SHA-256 ones
+        if matches!(first.len(), 40 | 64) && first.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            commit = Some(first.to_string());
+        } else if let (Some(path), Some(hash)) = (line.strip_prefix("filename "), &commit) {
+            commits.push((hash.clone(), path.to_string()));
+        }
+    }
+    commits.sort();
+    commits.dedup();
+    Ok(commits)
+}
@@ -0,0 +1,381 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::errors::CliError;
+
+pub type Attributes = BTreeMap<String, Value>;
+
+#[derive(Debug, Default)]
+pub struct GraphNode {
+    pub label: String,
+    pub attributes: Attributes,
+}
+
+#[derive(Debug)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub attributes: Attributes,
+}
+
+/// A directed graph with arbitrary node and edge attributes, written out in a format
+/// Graphviz, Gephi or NetworkX can load directly
+#[derive(Debug)]
+pub struct Graph {
+    pub name: String,
+    pub nodes: BTreeMap<String, GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl Graph {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            nodes: BTreeMap::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    /// Returns the node with `id`, creating it with `label` if it doesn't exist yet
+    pub fn node(&mut self, id: &str, label: &str) -> &mut GraphNode {
+        self.nodes
+            .entry(id.to_string())
+            .or_insert_with(|| GraphNode {
+                label: label.to_string(),
+                attributes: Attributes::new(),
+            })
+    }
+
+    pub fn edge(&mut self, source: &str, target: &str, attributes: Attributes) {
+        self.edges.push(GraphEdge {
+            source: source.to_string(),
+            target: target.to_string(),
+            attributes,
+        });
+    }
+
+    pub fn write(&self, fmt: &str, writer: &mut dyn Write) -> Result<(), CliError> {
+        match fmt {
+            "dot" => self.write_dot(writer),
+            "graphml" => self.write_graphml(writer),
+            "node-link" => self.write_node_link(writer),
+            _ => {
+                unreachable!("Invalid graph format provided.")
+            }
+        }
+    }
+
+    fn write_dot(&self, writer: &mut dyn Write) -> Result<(), CliError> {
+        writeln!(writer, "digraph \"{}\" {{", dot_escape(&self.name))?;
+        for (id, node) in &self.nodes {
+            let mut attributes = vec![format!("label=\"{}\"", dot_escape(&node.label))];
+            attributes.extend(dot_attributes(&node.attributes));
+            writeln!(
+                writer,
+                "    \"{}\" [{}];",
+                dot_escape(id),
+                attributes.join(", ")
+            )?;
+        }
+        for edge in &self.edges {
+            let attributes = dot_attributes(&edge.attributes);
+            writeln!(
+                writer,
+                "    \"{}\" -> \"{}\"{};",
+                dot_escape(&edge.source),
+                dot_escape(&edge.target),
+                if attributes.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", attributes.join(", "))
+                }
+            )?;
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    fn write_graphml(&self, writer: &mut dyn Write) -> Result<(), CliError> {
+        let node_keys = attribute_types(self.nodes.values().map(|n| &n.attributes));
+        let edge_keys = attribute_types(self.edges.iter().map(|e| &e.attributes));
+
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="n_label" for="node" attr.name="label" attr.type="string"/>"#
+        )?;
+        for (prefix, target, keys) in [("n", "node", &node_keys), ("e", "edge", &edge_keys)] {
+            for (key, ty) in keys {
+                writeln!(
+                    writer,
+                    r#"  <key id="{}_{}" for="{}" attr.name="{}" attr.type="{}"/>"#,
+                    prefix,
+                    xml_escape(key),
+                    target,
+                    xml_escape(key),
+                    ty
+                )?;
+            }
+        }
+        writeln!(
+            writer,
+            r#"  <graph id="{}" edgedefault="directed">"#,
+            xml_escape(&self.name)
+        )?;
+        for (id, node) in &self.nodes {
+            write!(
+                writer,
+                r#"    <node id="{}"><data key="n_label">{}</data>"#,
+                xml_escape(id),
+                xml_escape(&node.label)
+            )?;
+            write_graphml_data(writer, "n", &node.attributes)?;
+            writeln!(writer, "</node>")?;
+        }
+        for edge in &self.edges {
+            write!(
+                writer,
+                r#"    <edge source="{}" target="{}">"#,
+                xml_escape(&edge.source),
+                xml_escape(&edge.target)
+            )?;
+            write_graphml_data(writer, "e", &edge.attributes)?;
+            writeln!(writer, "</edge>")?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")?;
+        Ok(())
+    }
+
+    /// The node-link layout understood by `networkx.node_link_graph`
+    fn write_node_link(&self, writer: &mut dyn Write) -> Result<(), CliError> {
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .map(|(id, node)| {
+                let mut object: Map<String, Value> = node.attributes.clone().into_iter().collect();
+                object.insert("id".to_string(), json!(id));
+                object.insert("label".to_string(), json!(node.label));
+                Value::Object(object)
+            })
+            .collect();
+        let links: Vec<Value> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                let mut object: Map<String, Value> = edge.attributes.clone().into_iter().collect();
+                object.insert("source".to_string(), json!(edge.source));
+                object.insert("target".to_string(), json!(edge.target));
+                Value::Object(object)
+            })
+            .collect();
+
+        let graph = json!({
+            "directed": true,
+            "multigraph": false,
+            "graph": { "name": self.name },
+            "nodes": nodes,
+            "links": links,
+        });
+        serde_json::to_writer_pretty(&mut *writer, &graph)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// Turns the numeric columns of a metrics row into node attributes, leaving out
+/// identifying columns such as the name or source file
+pub fn metric_attributes<T: Serialize>(row: &T) -> Attributes {
+    match serde_json::to_value(row) {
+        Ok(Value::Object(object)) => object
+            .into_iter()
+            .filter(|(key, value)| value.is_number() && key != "start_line" && key != "end_line")
+            .collect(),
+        _ => Attributes::new(),
+    }
+}
+
+fn dot_attributes(attributes: &Attributes) -> Vec<String> {
+    attributes
+        .iter()
+        .map(|(key, value)| match value {
+            Value::String(s) => format!("{}=\"{}\"", key, dot_escape(s)),
+            other => format!("{}={}", key, other),
+        })
+        .collect()
+}
+
+/// GraphML needs every attribute declared up front with a single type
+fn attribute_types<'a>(
+    attributes: impl Iterator<Item = &'a Attributes>,
+) -> BTreeMap<String, &'static str> {
+    let mut types = BTreeMap::new();
+    for attributes in attributes {
+        for (key, value) in attributes {
+            let ty = match value {
+                Value::Bool(_) => "boolean",
+                Value::Number(n) if n.is_f64() => "double",
+                Value::Number(_) => "long",
+                _ => "string",
+            };
+            let entry = types.entry(key.clone()).or_insert(ty);
+            if *entry != ty {
+                let numeric = |ty: &str| ty == "long" || ty == "double";
+                *entry = if numeric(entry) && numeric(ty) {
+                    "double"
+                } else {
+                    "string"
+                };
+            }
+        }
+    }
+    types
+}
+
+fn write_graphml_data(
+    writer: &mut dyn Write,
+    prefix: &str,
+    attributes: &Attributes,
+) -> Result<(), CliError> {
+    for (key, value) in attributes {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        write!(
+            writer,
+            r#"<data key="{}_{}">{}</data>"#,
+            prefix,
+            xml_escape(key),
+            xml_escape(&value)
+        )?;
+    }
+    Ok(())
+}
+
+fn dot_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> Graph {
+        let mut graph = Graph::new("calls \"of\" <x>");
+        graph.node("a.c:f", "f").attributes = Attributes::from([
+            ("cyclomatic".to_string(), json!(2.5)),
+            ("path".to_string(), json!("dir\\a.c")),
+        ]);
+        graph.node("a.c:g", "g<T>").attributes =
+            Attributes::from([("cyclomatic".to_string(), json!(3))]);
+        graph.edge(
+            "a.c:f",
+            "a.c:g",
+            Attributes::from([("calls".to_string(), json!(2))]),
+        );
+        graph
+    }
+
+    fn written(graph: &Graph, fmt: &str) -> String {
+        let mut out = Vec::new();
+        graph.write(fmt, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn dot_quotes_and_escapes_ids_labels_and_strings() {
+        assert_eq!(
+            written(&graph(), "dot"),
+            "digraph \"calls \\\"of\\\" <x>\" {\n    \
+             \"a.c:f\" [label=\"f\", cyclomatic=2.5, path=\"dir\\\\a.c\"];\n    \
+             \"a.c:g\" [label=\"g<T>\", cyclomatic=3];\n    \
+             \"a.c:f\" -> \"a.c:g\" [calls=2];\n}\n"
+        );
+    }
+
+    #[test]
+    fn graphml_declares_every_attribute_with_one_type() {
+        let graphml = written(&graph(), "graphml");
+        // Integers and floats of the same attribute widen to doubles
+        assert!(graphml.contains(
+            r#"<key id="n_cyclomatic" for="node" attr.name="cyclomatic" attr.type="double"/>"#
+        ));
+        assert!(graphml
+            .contains(r#"<key id="n_path" for="node" attr.name="path" attr.type="string"/>"#));
+        assert!(graphml
+            .contains(r#"<key id="e_calls" for="edge" attr.name="calls" attr.type="long"/>"#));
+        assert!(graphml
+            .contains(r#"<graph id="calls &quot;of&quot; &lt;x&gt;" edgedefault="directed">"#));
+        assert!(graphml.contains(
+            r#"<node id="a.c:g"><data key="n_label">g&lt;T&gt;</data><data key="n_cyclomatic">3</data></node>"#
+        ));
+        assert!(graphml.contains(
+            r#"<edge source="a.c:f" target="a.c:g"><data key="e_calls">2</data></edge>"#
+        ));
+    }
+
+    #[test]
+    fn attributes_mixing_numbers_with_anything_else_are_strings() {
+        let nodes = [
+            Attributes::from([("x".to_string(), json!(1))]),
+            Attributes::from([("x".to_string(), json!("one"))]),
+            Attributes::from([("y".to_string(), json!(true))]),
+            Attributes::from([("y".to_string(), json!(1))]),
+            Attributes::from([("z".to_string(), json!(1))]),
+            Attributes::from([("z".to_string(), json!(false))]),
+        ];
+        let types = attribute_types(nodes.iter());
+        assert_eq!(types["x"], "string");
+        assert_eq!(types["y"], "string");
+        assert_eq!(types["z"], "string");
+    }
+
+    #[test]
+    fn node_links_load_as_a_directed_graph() {
+        let graph: Value = serde_json::from_str(&written(&graph(), "node-link")).unwrap();
+        assert_eq!(graph["directed"], true);
+        assert_eq!(graph["graph"]["name"], "calls \"of\" <x>");
+        assert_eq!(
+            graph["nodes"][1],
+            json!({"id": "a.c:g", "label": "g<T>", "cyclomatic": 3})
+        );
+        assert_eq!(
+            graph["links"][0],
+            json!({"source": "a.c:f", "target": "a.c:g", "calls": 2})
+        );
+    }
+
+    #[test]
+    fn metric_attributes_leave_out_identifying_columns() {
+        let row = json!({
+            "name": "f",
+            "source_file": "a.c",
+            "start_line": 1,
+            "end_line": 9,
+            "cyclomatic": 4.0,
+            "sloc": 9.0,
+        });
+        assert_eq!(
+            metric_attributes(&row),
+            Attributes::from([
+                ("cyclomatic".to_string(), json!(4.0)),
+                ("sloc".to_string(), json!(9.0)),
+            ])
+        );
+    }
+}
@@ -0,0 +1,726 @@
+//! A SQLite store of tagged runs, so metrics can be followed across releases. Needs the
+//! `sqlite` feature
+//!
+//! Every run is a row of `runs`, its functions rows of `functions` and their numeric metrics
+//! rows of `metrics`, one per function and metric, so trends are a join away in plain SQL.
+
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::errors::CliError;
+use crate::extract::MetricsRow;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    tag TEXT NOT NULL UNIQUE,
+    recorded_at INTEGER NOT NULL,
+    revision TEXT,
+    path TEXT NOT NULL,
+    archaeo_version TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS functions (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    source_file TEXT NOT NULL,
+    name TEXT NOT NULL,
+    start_line INTEGER,
+    end_line INTEGER
+);
+CREATE INDEX IF NOT EXISTS functions_by_name ON functions(source_file, name);
+CREATE INDEX IF NOT EXISTS functions_by_run ON functions(run_id);
+CREATE TABLE IF NOT EXISTS metrics (
+    function_id INTEGER NOT NULL REFERENCES functions(id) ON DELETE CASCADE,
+    metric TEXT NOT NULL,
+    value REAL NOT NULL,
+    PRIMARY KEY (function_id, metric)
+) WITHOUT ROWID;
+";
+
+/// Columns identifying a function rather than measuring it
+const KEYS: [&str; 4] = ["source_file", "name", "start_line", "end_line"];
+
+/// What a run was recorded from
+#[derive(Debug)]
+pub struct RunInfo {
+    pub tag: String,
+    /// Seconds since the Unix epoch
+    pub recorded_at: u64,
+    /// Commit the recorded tree was checked out at, if it is a git repository
+    pub revision: Option<String>,
+    pub path: String,
+}
+
+/// How `query` groups the functions of every run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grouping {
+    Function,
+    File,
+    Run,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Sum,
+    Mean,
+    Max,
+}
+
+/// Which functions and metrics `query` pulls
+#[derive(Debug)]
+pub struct Selection<'a> {
+    pub metrics: &'a [String],
+    /// Only functions with this name
+    pub function: Option<&'a str>,
+    /// Only functions in this file, or below this directory
+    pub file: Option<&'a str>,
+    /// Only these runs. Every run when empty
+    pub tags: &'a [String],
+    pub grouping: Grouping,
+    pub aggregate: Aggregate,
+}
+
+pub struct History {
+    connection: Connection,
+}
+
+fn database_error(err: rusqlite::Error) -> CliError {
+    CliError::DatabaseError(err.to_string())
+}
+
+impl History {
+    /// Opens the store at `path`, creating it or its tables as needed
+    pub fn open(path: &Path) -> Result<Self, CliError> {
+        let connection = Connection::open(path).map_err(database_error)?;
+        connection
+            .execute_batch("PRAGMA foreign_keys = ON;")
+            .and_then(|_| connection.execute_batch(SCHEMA))
+            .map_err(database_error)?;
+        Ok(History { connection })
+    }
+
+    /// Appends the rows of a run under its tag in one transaction, returning how many
+    /// functions were stored. An existing run with the same tag is an error unless
+    /// `replace` is set, in which case it is dropped first
+    pub fn record(
+        &mut self,
+        run: &RunInfo,
+        rows: &[MetricsRow],
+        replace: bool,
+    ) -> Result<usize, CliError> {
+        let transaction = self.connection.transaction().map_err(database_error)?;
+        let existing: Option<i64> = transaction
+            .query_row("SELECT id FROM runs WHERE tag = ?1", [&run.tag], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map_err(database_error)?;
+        if existing.is_some() {
+            if !replace {
+                return Err(CliError::InvalidArgument(format!(
+                    "A run tagged {} is already recorded, pass --replace to overwrite it",
+                    run.tag
+                )));
+            }
+            transaction
+                .execute("DELETE FROM runs WHERE tag = ?1", [&run.tag])
+                .map_err(database_error)?;
+        }
+
+        transaction
+            .execute(
+                "INSERT INTO runs (tag, recorded_at, revision, path, archaeo_version)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    run.tag,
+                    run.recorded_at as i64,
+                    run.revision,
+                    run.path,
+                    env!("CARGO_PKG_VERSION")
+                ],
+            )
+            .map_err(database_error)?;
+        let run_id = transaction.last_insert_rowid();
+        {
+            let mut function = transaction
+                .prepare(
+                    "INSERT INTO functions (run_id, source_file, name, start_line, end_line)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                )
+                .map_err(database_error)?;
+            let mut metric = transaction
+                .prepare("INSERT OR REPLACE INTO metrics (function_id, metric, value) VALUES (?1, ?2, ?3)")
+                .map_err(database_error)?;
+            for row in rows {
+                let text = |column: &str| row.get(column).and_then(Value::as_str).unwrap_or("");
+                let line = |column: &str| row.get(column).and_then(Value::as_i64);
+                let function_id = function
+                    .insert(params![
+                        run_id,
+                        text("source_file"),
+                        text("name"),
+                        line("start_line"),
+                        line("end_line")
+                    ])
+                    .map_err(database_error)?;
+                for (column, value) in row {
+                    let Some(value) = value.as_f64().filter(|value| value.is_finite()) else {
+                        continue;
+                    };
+                    if KEYS.contains(&column.as_str()) {
+                        continue;
+                    }
+                    metric
+                        .execute(params![function_id, column, value])
+                        .map_err(database_error)?;
+                }
+            }
+        }
+        transaction.commit().map_err(database_error)?;
+        Ok(rows.len())
+    }
+
+    /// The selected metrics of every selected run, in the order the runs were recorded
+    pub fn query(&self, selection: &Selection) -> Result<Vec<MetricsRow>, CliError> {
+        let mut sql = String::from(
+            "SELECT r.id, r.tag, r.recorded_at, r.revision, f.id, f.source_file, f.name,
+                    f.start_line, m.metric, m.value
+             FROM runs r
+             JOIN functions f ON f.run_id = r.id
+             LEFT JOIN metrics m ON m.function_id = f.id AND m.metric IN (",
+        );
+        let mut values: Vec<&str> = Vec::new();
+        sql.push_str(&placeholders(selection.metrics.len()));
+        values.extend(selection.metrics.iter().map(String::as_str));
+        sql.push_str(") WHERE 1 = 1");
+        if let Some(function) = selection.function {
+            sql.push_str(" AND f.name = ?");
+            values.push(function);
+        }
+        let directory = selection
+            .file
+            .map(|file| format!("{}/%", file.trim_end_matches('/')));
+        if let (Some(file), Some(directory)) = (selection.file, &directory) {
+            sql.push_str(" AND (f.source_file = ? OR f.source_file LIKE ?)");
+            values.push(file);
+            values.push(directory);
+        }
+        if !selection.tags.is_empty() {
+            sql.push_str(" AND r.tag IN (");
+            sql.push_str(&placeholders(selection.tags.len()));
+            sql.push(')');
+            values.extend(selection.tags.iter().map(String::as_str));
+        }
+        sql.push_str(" ORDER BY r.id, f.source_file, f.start_line, f.id");
+
+        let mut statement = self.connection.prepare(&sql).map_err(database_error)?;
+        let mut results = statement
+            .query(params_from_iter(values))
+            .map_err(database_error)?;
+
+        // Functions in query order, each with the metrics found for it
+        let mut functions: Vec<(Function, BTreeMap<String, f64>)> = Vec::new();
+        while let Some(result) = results.next().map_err(database_error)? {
+            let function_id: i64 = result.get(4).map_err(database_error)?;
+            if functions
+                .last()
+                .is_none_or(|(last, _)| last.id != function_id)
+            {
+                functions.push((
+                    Function {
+                        run: result.get(0).map_err(database_error)?,
+                        tag: result.get(1).map_err(database_error)?,
+                        recorded_at: result.get(2).map_err(database_error)?,
+                        revision: result.get(3).map_err(database_error)?,
+                        id: function_id,
+                        source_file: result.get(5).map_err(database_error)?,
+                        name: result.get(6).map_err(database_error)?,
+                        start_line: result.get(7).map_err(database_error)?,
+                    },
+                    BTreeMap::new(),
+                ));
+            }
+            let metric: Option<String> = result.get(8).map_err(database_error)?;
+            let value: Option<f64> = result.get(9).map_err(database_error)?;
+            if let (Some(metric), Some(value), Some((_, metrics))) =
+                (metric, value, functions.last_mut())
+            {
+                metrics.insert(metric, value);
+            }
+        }
+
+        Ok(match selection.grouping {
+            Grouping::Function => functions
+                .iter()
+                .map(|(function, metrics)| {
+                    let mut row = function.run_columns();
+                    row.insert("source_file".to_string(), json!(function.source_file));
+                    row.insert("name".to_string(), json!(function.name));
+                    row.insert("start_line".to_string(), json!(function.start_line));
+                    for metric in selection.metrics {
+                        row.insert(metric.clone(), json!(metrics.get(metric)));
+                    }
+                    row
+                })
+                .collect(),
+            Grouping::File | Grouping::Run => {
+                let mut groups: Vec<(MetricsRow, Vec<&BTreeMap<String, f64>>)> = Vec::new();
+                let mut last: Option<(i64, &str)> = None;
+                for (function, metrics) in &functions {
+                    let key = match selection.grouping {
+                        Grouping::File => (function.run, function.source_file.as_str()),
+                        _ => (function.run, ""),
+                    };
+                    if last != Some(key) {
+                        let mut row = function.run_columns();
+                        if selection.grouping == Grouping::File {
+                            row.insert("source_file".to_string(), json!(function.source_file));
+                        }
+                        groups.push((row, Vec::new()));
+                        last = Some(key);
+                    }
+                    if let Some((_, members)) = groups.last_mut() {
+                        members.push(metrics);
+                    }
+                }
+                groups
+                    .into_iter()
+                    .map(|(mut row, members)| {
+                        row.insert("functions".to_string(), json!(members.len()));
+                        for metric in selection.metrics {
+                            let values: Vec<f64> = members
+                                .iter()
+                                .filter_map(|metrics| metrics.get(metric).copied())
+                                .collect();
+                            row.insert(
+                                metric.clone(),
+                                json!(aggregate(&values, selection.aggregate)),
+                            );
+                        }
+                        row
+                    })
+                    .collect()
+            }
+        })
+    }
+}
+
+/// A function of a run, as `query` reads it back
+struct Function {
+    run: i64,
+    tag: String,
+    recorded_at: i64,
+    revision: Option<String>,
+    id: i64,
+    source_file: String,
+    name: String,
+    start_line: Option<i64>,
+}
+
+impl Function {
+    fn run_columns(&self) -> MetricsRow {
+        let mut row = Map::new();
+        row.insert("tag".to_string(), json!(self.tag));
+        row.insert("recorded_at".to_string(), json!(self.recorded_at));
+        row.insert("revision".to_string(), json!(self.revision));
+        row
+    }
+}
+
+/// How fast the metrics of every function, file or run of `query` rows change across the
+/// runs recording it, as the mean first (`_velocity`) and second (`_acceleration`)
+/// differences per run over the last `window` runs. Rows follow the velocity of the first
+/// metric, fastest first, and the `fastest` growing ones are flagged `growing_fastest`
+pub fn rates(
+    rows: &[MetricsRow],
+    selection: &Selection,
+    window: usize,
+    fastest: usize,
+) -> Vec<MetricsRow> {
+    let keys: &[&str] = match selection.grouping {
+        Grouping::Function => &["source_file", "name"],
+        Grouping::File => &["source_file"],
+        Grouping::Run => &[],
+    };
+    // The rows of every function in run order, a function first seen in a run counted once
+    let mut series: Vec<(Vec<&Value>, Vec<&MetricsRow>)> = Vec::new();
+    let mut at: BTreeMap<Vec<String>, usize> = BTreeMap::new();
+    for row in rows {
+        let key: Vec<&Value> = keys.iter().map(|key| &row[*key]).collect();
+        let index = *at
+            .entry(key.iter().map(|value| value.to_string()).collect())
+            .or_insert_with(|| {
+                series.push((key, Vec::new()));
+                series.len() - 1
+            });
+        let runs = &mut series[index].1;
+        if runs.last().is_none_or(|last| last["tag"] != row["tag"]) {
+            runs.push(row);
+        }
+    }
+
+    let mut rates: Vec<(Option<f64>, MetricsRow)> = series
+        .into_iter()
+        .map(|(key, runs)| {
+            let mut rate = Map::new();
+            for (column, value) in keys.iter().zip(key) {
+                rate.insert(column.to_string(), value.clone());
+            }
+            rate.insert("runs".to_string(), json!(runs.len()));
+            rate.insert("first_tag".to_string(), runs[0]["tag"].clone());
+            rate.insert("last_tag".to_string(), runs[runs.len() - 1]["tag"].clone());
+            let mut first = None;
+            for (index, metric) in selection.metrics.iter().enumerate() {
+                // Runs missing the metric keep their place, leaving out the differences
+                // they take part in rather than pairing up the runs around them
+                let values: Vec<Option<f64>> = runs
+                    .iter()
+                    .map(|row| row.get(metric).and_then(Value::as_f64))
+                    .collect();
+                let recent = &values[values.len().saturating_sub(window + 1)..];
+                let first_differences: Vec<Option<f64>> = recent
+                    .windows(2)
+                    .map(|pair| Some(pair[1]? - pair[0]?))
+                    .collect();
+                let second_differences: Vec<f64> = first_differences
+                    .windows(2)
+                    .filter_map(|pair| Some(pair[1]? - pair[0]?))
+                    .collect();
+                let first_differences: Vec<f64> = first_differences.into_iter().flatten().collect();
+                let velocity = aggregate(&first_differences, Aggregate::Mean);
+                if index == 0 {
+                    first = velocity;
+                }
+                rate.insert(metric.clone(), json!(values.last().copied().flatten()));
+                rate.insert(format!("{}_velocity", metric), json!(velocity));
+                rate.insert(
+                    format!("{}_acceleration", metric),
+                    json!(aggregate(&second_differences, Aggregate::Mean)),
+                );
+            }
+            (first, rate)
+        })
+        .collect();
+
+    rates.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => b.total_cmp(a),
+        _ => b.is_some().cmp(&a.is_some()),
+    });
+    rates
+        .into_iter()
+        .enumerate()
+        .map(|(rank, (velocity, mut rate))| {
+            let growing = rank < fastest && velocity.is_some_and(|velocity| velocity > 0.0);
+            rate.insert("growing_fastest".to_string(), json!(growing));
+            rate
+        })
+        .collect()
+}
+
+fn aggregate(values: &[f64], aggregate: Aggregate) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(match aggregate {
+        Aggregate::Sum => values.iter().sum(),
+        Aggregate::Mean => values.iter().sum::<f64>() / values.len() as f64,
+        Aggregate::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+    })
+}
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(tag: &str, name: &str, cyclomatic: Option<f64>) -> MetricsRow {
+        let mut row = Map::new();
+        row.insert("tag".to_string(), json!(tag));
+        row.insert("source_file".to_string(), json!("a.c"));
+        row.insert("name".to_string(), json!(name));
+        if let Some(cyclomatic) = cyclomatic {
+            row.insert("cyclomatic".to_string(), json!(cyclomatic));
+        }
+        row
+    }
+
+    /// The rows of every run in order, from the value of every function in every run
+    fn runs(series: &[(&str, &[Option<f64>])]) -> Vec<MetricsRow> {
+        let runs = series
+            .iter()
+            .map(|(_, values)| values.len())
+            .max()
+            .unwrap_or(0);
+        (0..runs)
+            .flat_map(|run| {
+                series.iter().filter_map(move |(name, values)| {
+                    let value = values.get(run)?;
+                    Some(row(&format!("v{}", run), name, *value))
+                })
+            })
+            .collect()
+    }
+
+    fn rates_of(rows: &[MetricsRow], window: usize, fastest: usize) -> Vec<MetricsRow> {
+        let metrics = ["cyclomatic".to_string()];
+        let selection = Selection {
+            metrics: &metrics,
+            function: None,
+            file: None,
+            tags: &[],
+            grouping: Grouping::Function,
+            aggregate: Aggregate::Sum,
+        };
+        rates(rows, &selection, window, fastest)
+    }
+
+    fn columns(rate: &MetricsRow) -> (&str, Value, Value, Value, Value) {
+        (
+            rate["name"].as_str().unwrap(),
+            rate["cyclomatic"].clone(),
+            rate["cyclomatic_velocity"].clone(),
+            rate["cyclomatic_acceleration"].clone(),
+            rate["growing_fastest"].clone(),
+        )
+    }
+
+    #[test]
+    fn growing_flat_and_shrinking_functions_are_ranked_by_velocity() {
+        let rows = runs(&[
+            ("flat", &[Some(4.0), Some(4.0), Some(4.0), Some(4.0)]),
+            ("shrinking", &[Some(9.0), Some(7.0), Some(5.0), Some(3.0)]),
+            ("growing", &[Some(1.0), Some(2.0), Some(4.0), Some(7.0)]),
+        ]);
+        let rates = rates_of(&rows, 3, 10);
+        assert_eq!(
+            rates.iter().map(columns).collect::<Vec<_>>(),
+            [
+                ("growing", json!(7.0), json!(2.0), json!(1.0), json!(true)),
+                ("flat", json!(4.0), json!(0.0), json!(0.0), json!(false)),
+                (
+                    "shrinking",
+                    json!(3.0),
+                    json!(-2.0),
+                    json!(0.0),
+                    json!(false)
+                ),
+            ]
+        );
+        assert_eq!(rates[0]["runs"], json!(4));
+        assert_eq!(rates[0]["first_tag"], json!("v0"));
+        assert_eq!(rates[0]["last_tag"], json!("v3"));
+
+        // Only the fastest growing ones are flagged
+        let rows = runs(&[
+            ("slow", &[Some(1.0), Some(2.0)]),
+            ("fast", &[Some(1.0), Some(5.0)]),
+        ]);
+        let flagged = rates_of(&rows, 3, 1);
+        let flagged: Vec<_> = flagged
+            .iter()
+            .map(|rate| (rate["name"].clone(), rate["growing_fastest"].clone()))
+            .collect();
+        assert_eq!(
+            flagged,
+            [(json!("fast"), json!(true)), (json!("slow"), json!(false))]
+        );
+    }
+
+    #[test]
+    fn windows_of_one_run_have_no_acceleration() {
+        let rows = runs(&[("growing", &[Some(1.0), Some(2.0), Some(4.0), Some(7.0)])]);
+        assert_eq!(
+            rates_of(&rows, 1, 10)
+                .iter()
+                .map(columns)
+                .collect::<Vec<_>>(),
+            [("growing", json!(7.0), json!(3.0), Value::Null, json!(true))]
+        );
+    }
+
+    #[test]
+    fn functions_of_a_single_run_have_no_velocity_and_sort_last() {
+        let rows = runs(&[
+            ("shrinking", &[Some(3.0), Some(1.0)]),
+            ("new", &[Some(10.0)]),
+        ]);
+        let rates = rates_of(&rows, 3, 10);
+        assert_eq!(
+            rates.iter().map(columns).collect::<Vec<_>>(),
+            [
+                (
+                    "shrinking",
+                    json!(1.0),
+                    json!(-2.0),
+                    Value::Null,
+                    json!(false)
+                ),
+                ("new", json!(10.0), Value::Null, Value::Null, json!(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_missing_a_metric_keep_the_others_in_place() {
+        // Pairing 1 with 10 over the missing run would make a velocity of 9
+        let rows = runs(&[("gap", &[Some(0.0), Some(1.0), None, Some(10.0), Some(12.0)])]);
+        assert_eq!(
+            rates_of(&rows, 4, 10)
+                .iter()
+                .map(columns)
+                .collect::<Vec<_>>(),
+            [("gap", json!(12.0), json!(1.5), Value::Null, json!(true))]
+        );
+    }
+
+    fn recorded(tag: &str) -> RunInfo {
+        RunInfo {
+            tag: tag.to_string(),
+            recorded_at: 1_700_000_000,
+            revision: None,
+            path: ".".to_string(),
+        }
+    }
+
+    fn function(file: &str, name: &str, start_line: i64, cyclomatic: f64) -> MetricsRow {
+        json!({
+            "source_file": file,
+            "name": name,
+            "start_line": start_line,
+            "end_line": start_line + 5,
+            "cyclomatic": cyclomatic,
+            "kind": "function",
+        })
+        .as_object()
+        .unwrap()
+        .clone()
+    }
+
+    fn selection<'a>(
+        metrics: &'a [String],
+        tags: &'a [String],
+        grouping: Grouping,
+    ) -> Selection<'a> {
+        Selection {
+            metrics,
+            function: None,
+            file: None,
+            tags,
+            grouping,
+            aggregate: Aggregate::Sum,
+        }
+    }
+
+    #[test]
+    fn runs_are_recorded_once_per_tag_unless_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::open(&dir.path().join("history.db")).unwrap();
+        let rows = [function("a.c", "f", 1, 2.0)];
+        assert_eq!(history.record(&recorded("v1"), &rows, false).unwrap(), 1);
+        assert!(matches!(
+            history.record(&recorded("v1"), &rows, false),
+            Err(CliError::InvalidArgument(_))
+        ));
+
+        let rows = [function("a.c", "f", 1, 3.0), function("a.c", "g", 9, 1.0)];
+        assert_eq!(history.record(&recorded("v1"), &rows, true).unwrap(), 2);
+        let metrics = ["cyclomatic".to_string()];
+        let queried = history
+            .query(&selection(&metrics, &[], Grouping::Function))
+            .unwrap();
+        let values: Vec<(&Value, &Value)> = queried
+            .iter()
+            .map(|row| (&row["name"], &row["cyclomatic"]))
+            .collect();
+        assert_eq!(
+            values,
+            [(&json!("f"), &json!(3.0)), (&json!("g"), &json!(1.0))]
+        );
+    }
+
+    /// The tag, file and metrics of the rows `selection` queries
+    fn tagged_values(
+        history: &History,
+        selection: &Selection,
+    ) -> Vec<(Value, Value, Value, Value)> {
+        history
+            .query(selection)
+            .unwrap()
+            .into_iter()
+            .map(|row| {
+                (
+                    row["tag"].clone(),
+                    row["source_file"].clone(),
+                    row["cyclomatic"].clone(),
+                    row["missing"].clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn queries_select_functions_files_and_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::open(&dir.path().join("history.db")).unwrap();
+        history
+            .record(
+                &recorded("v1"),
+                &[
+                    function("src/a.c", "f", 1, 2.0),
+                    function("src/a.c", "g", 9, f64::NAN),
+                    function("src2/b.c", "f", 1, 5.0),
+                ],
+                false,
+            )
+            .unwrap();
+        history
+            .record(&recorded("v2"), &[function("src/a.c", "f", 1, 4.0)], false)
+            .unwrap();
+        let metrics = ["cyclomatic".to_string(), "missing".to_string()];
+
+        // Files match themselves or a directory above them, not a prefix of their name
+        let mut below = selection(&metrics, &[], Grouping::Function);
+        below.file = Some("src/");
+        below.function = Some("f");
+        let queried = tagged_values(&history, &below);
+        assert_eq!(
+            queried,
+            [
+                (json!("v1"), json!("src/a.c"), json!(2.0), Value::Null),
+                (json!("v2"), json!("src/a.c"), json!(4.0), Value::Null),
+            ]
+        );
+
+        // Functions lacking a value count towards the file, not towards its aggregates
+        let tags = ["v1".to_string()];
+        let files = history
+            .query(&selection(&metrics, &tags, Grouping::File))
+            .unwrap();
+        let files: Vec<(&Value, &Value, &Value)> = files
+            .iter()
+            .map(|row| (&row["source_file"], &row["functions"], &row["cyclomatic"]))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (&json!("src/a.c"), &json!(2), &json!(2.0)),
+                (&json!("src2/b.c"), &json!(1), &json!(5.0)),
+            ]
+        );
+
+        let mut runs = selection(&metrics, &[], Grouping::Run);
+        runs.aggregate = Aggregate::Max;
+        let runs: Vec<(Value, Value)> = history
+            .query(&runs)
+            .unwrap()
+            .iter()
+            .map(|row| (row["tag"].clone(), row["cyclomatic"].clone()))
+            .collect();
+        assert_eq!(runs, [(json!("v1"), json!(5.0)), (json!("v2"), json!(4.0))]);
+    }
+}
@@ -0,0 +1,227 @@
+//! The complexity impact of every hunk of a diff: the functions it touches, with their
+//! metrics before the change, from the rows of a base run, and after it
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::extract::MetricsRow;
+use crate::git::FileDiff;
+
+/// Metrics compared when a request names none
+pub const DEFAULT_METRICS: [&str; 2] = ["cyclomatic", "cognitive"];
+
+/// A diff to assess, with the rows of the revision it applies to
+#[derive(Debug, Deserialize)]
+pub struct ImpactRequest {
+    /// A unified diff, e.g. the output of `git diff main`
+    pub diff: String,
+    /// Rows of an earlier run over the base revision
+    pub base: Vec<MetricsRow>,
+    /// Metric columns to compare
+    #[serde(default)]
+    pub metrics: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Added,
+    Changed,
+    Removed,
+}
+
+/// A metric of a function before and after the change
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub base: Option<f64>,
+    pub head: Option<f64>,
+    pub delta: Option<f64>,
+}
+
+/// A function a hunk touches
+#[derive(Debug, Serialize)]
+pub struct FunctionImpact {
+    pub name: String,
+    pub status: Status,
+    /// Lines of the function before the change, unless it is added
+    pub base_lines: Option<(u64, u64)>,
+    /// Lines of the function after the change, unless it is removed
+    pub head_lines: Option<(u64, u64)>,
+    pub metrics: BTreeMap<String, Change>,
+    /// What the change does to the metrics, e.g. "raises cyclomatic of parse from 8 → 17"
+    pub messages: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HunkImpact {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_start: usize,
+    pub new_count: usize,
+    pub functions: Vec<FunctionImpact>,
+}
+
+/// The functions of a file, by their lines
+struct Function<'a> {
+    row: &'a MetricsRow,
+    name: &'a str,
+    start: u64,
+    end: u64,
+}
+
+impl<'a> Function<'a> {
+    fn of(rows: impl IntoIterator<Item = &'a MetricsRow>) -> Vec<Self> {
+        rows.into_iter()
+            .map(|row| {
+                let line = |column: &str| row.get(column).and_then(Value::as_u64).unwrap_or(0);
+                Function {
+                    row,
+                    name: row.get("name").and_then(Value::as_str).unwrap_or(""),
+                    start: line("start_line"),
+                    end: line("end_line"),
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the function overlaps the `count` lines from `start`, or holds the line a
+    /// hunk without any lines on this side sits after
+    fn touches(&self, start: usize, count: usize) -> bool {
+        let (start, end) = match count {
+            0 => (start.max(1), start.max(1)),
+            _ => (start, start + count - 1),
+        };
+        self.start <= end as u64 && start as u64 <= self.end
+    }
+
+    fn value(&self, metric: &str) -> Option<f64> {
+        self.row.get(metric).and_then(Value::as_f64)
+    }
+}
+
+/// Whether the source file of a row is `path`, a path within the repository the diff is of
+pub fn same_file(source_file: &str, path: &str) -> bool {
+    let source_file = source_file.replace('\\', "/");
+    let source_file = source_file.trim_start_matches("./");
+    source_file == path || source_file.ends_with(&format!("/{}", path))
+}
+
+/// Assesses every hunk of `file`, given the rows of the file before the change and the rows
+/// of it after the change
+pub fn assess(
+    file: &FileDiff,
+    base: &[&MetricsRow],
+    head: &[MetricsRow],
+    metrics: &[String],
+) -> Vec<HunkImpact> {
+    let base = Function::of(base.iter().copied());
+    let head = Function::of(head);
+    let line_map = file.line_map();
+
+    file.hunks
+        .iter()
+        .map(|&(old_start, old_count, new_start, new_count)| {
+            let mut pairs: Vec<(Option<&Function>, Option<&Function>)> = Vec::new();
+            for after in head.iter().filter(|f| f.touches(new_start, new_count)) {
+                let before = counterpart(after, &base, |f| line_map.map(f.start as usize));
+                pairs.push((before, Some(after)));
+            }
+            for before in base.iter().filter(|f| f.touches(old_start, old_count)) {
+                let paired = pairs
+                    .iter()
+                    .any(|(b, _)| b.is_some_and(|b| std::ptr::eq(b, before)));
+                if paired {
+                    continue;
+                }
+                let mapped = line_map.map(before.start as usize) as u64;
+                let after = head
+                    .iter()
+                    .filter(|f| f.name == before.name)
+                    .min_by_key(|f| f.start.abs_diff(mapped));
+                let duplicate = after.is_some_and(|after| {
+                    pairs
+                        .iter()
+                        .any(|(_, a)| a.is_some_and(|a| std::ptr::eq(a, after)))
+                });
+                if !duplicate {
+                    pairs.push((Some(before), after));
+                }
+            }
+
+            HunkImpact {
+                old_path: file.old_path.clone(),
+                new_path: file.new_path.clone(),
+                old_start,
+                old_count,
+                new_start,
+                new_count,
+                functions: pairs
+                    .into_iter()
+                    .map(|(before, after)| function_impact(before, after, metrics))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// The function of `base` a function after the change was, the one with its name starting
+/// closest to it once mapped through the diff
+fn counterpart<'b, 'a>(
+    after: &Function,
+    base: &'b [Function<'a>],
+    mapped: impl Fn(&Function) -> usize,
+) -> Option<&'b Function<'a>> {
+    base.iter()
+        .filter(|f| f.name == after.name)
+        .min_by_key(|f| (mapped(f) as u64).abs_diff(after.start))
+}
+
+fn function_impact(
+    before: Option<&Function>,
+    after: Option<&Function>,
+    metrics: &[String],
+) -> FunctionImpact {
+    let status = match (before, after) {
+        (None, _) => Status::Added,
+        (_, None) => Status::Removed,
+        _ => Status::Changed,
+    };
+    let name = after.or(before).map(|f| f.name).unwrap_or("").to_string();
+
+    let mut changes = BTreeMap::new();
+    let mut messages = Vec::new();
+    for metric in metrics {
+        let base = before.and_then(|f| f.value(metric));
+        let head = after.and_then(|f| f.value(metric));
+        let delta = base.zip(head).map(|(base, head)| head - base);
+        match (status, base, head, delta) {
+            (Status::Changed, Some(base), Some(head), Some(delta)) if delta != 0.0 => {
+                let verb = if delta > 0.0 { "raises" } else { "lowers" };
+                messages.push(format!(
+                    "{} {} of {} from {} → {}",
+                    verb, metric, name, base, head
+                ));
+            }
+            (Status::Added, _, Some(head), _) => {
+                messages.push(format!("adds {} with a {} of {}", name, metric, head))
+            }
+            _ => {}
+        }
+        changes.insert(metric.clone(), Change { base, head, delta });
+    }
+    if status == Status::Removed {
+        messages.push(format!("removes {}", name));
+    }
+
+    FunctionImpact {
+        name,
+        status,
+        base_lines: before.map(|f| (f.start, f.end)),
+        head_lines: after.map(|f| (f.start, f.end)),
+        metrics: changes,
+        messages,
+    }
+}
@@ -0,0 +1,31 @@
+//! Ctrl-C handling for long runs. The first interrupt lets the files being extracted finish
+//! and their outputs be flushed, the second exits straight away
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use tracing::warn;
+
+use crate::errors::exit_code;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+/// Catches Ctrl-C for the rest of the process, so `interrupted` can be polled between files
+pub fn install() {
+    INSTALL.call_once(|| {
+        let installed = ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(exit_code::INTERRUPTED);
+            }
+            warn!("Interrupted, finishing the files in progress. Press Ctrl-C again to exit now");
+        });
+        if let Err(err) = installed {
+            warn!("Failed to install the Ctrl-C handler: {}", err);
+        }
+    });
+}
+
+/// Whether Ctrl-C was pressed since `install`
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,646 @@
+//! Resolving the language every file is parsed as and preparing its source for parsing
+
+use chardetng::EncodingDetector;
+use clap::Args;
+use encoding_rs::{Encoding, UTF_8};
+use memmap2::Mmap;
+use rust_code_analysis::{get_from_ext, guess_language, LANG};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::decompiled;
+use crate::errors::CliError;
+use crate::objc;
+use crate::preprocess::Preprocessor;
+use crate::tree::SourceTree;
+
+#[derive(Args, Clone, Default, Serialize)]
+pub struct LanguageArgs {
+    /// Parse every file as this language instead of guessing it, given by name or extension.
+    /// Options: cpp (also used for C), java, javascript, python, rust, typescript, tsx
+    #[arg(long)]
+    pub force_language: Option<String>,
+
+    /// Comma separated overrides of the language of an extension, e.g. inl=cpp,tpp=cpp.
+    /// Files still need to be included by --extensions when walking a directory
+    #[arg(long, value_delimiter = ',')]
+    pub map_ext: Vec<String>,
+
+    /// Dialect to parse headers as when their extension could be C, C++ or Objective-C and
+    /// nothing in them tells, e.g. a `.h` without classes or `@interface`s
+    /// Options: c, cpp, objc
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(["c", "cpp", "objc"]))]
+    pub prefer_language: Option<String>,
+
+    /// Evaluate preprocessor conditionals in C/C++ before parsing, blanking inactive
+    /// branches. `expand` also substitutes object-like macros
+    /// Options: strip-inactive, expand
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(["strip-inactive", "expand"]))]
+    pub preprocess: Option<String>,
+
+    /// Comma separated macros conditionals are evaluated against, as NAME or NAME=value
+    #[arg(long, value_delimiter = ',', requires = "preprocess")]
+    pub define: Vec<String>,
+
+    /// Memory-map source files over 1 MiB instead of reading them in, sparing a copy of
+    /// every large UTF-8 file. A file changing while it is mapped can crash the run
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// How many times to retry reading a file that failed with an IO error, e.g. on a
+    /// flaky network filesystem
+    #[arg(long, default_value = "3")]
+    pub read_retries: u32,
+
+    /// Milliseconds to wait before the first retry of a failed read, doubling with every
+    /// retry after it
+    #[arg(long, default_value = "100")]
+    pub retry_backoff: u64,
+}
+
+impl LanguageArgs {
+    pub fn reader(&self) -> Result<SourceReader, CliError> {
+        let reader = SourceReader::new(self.force_language.as_deref(), &self.map_ext)?
+            .prefer(self.prefer_language.as_deref())
+            .mmap(self.mmap)
+            .retry(self.read_retries, Duration::from_millis(self.retry_backoff));
+        match &self.preprocess {
+            Some(mode) => Ok(reader.preprocess(Preprocessor::new(mode, &self.define)?)),
+            None => Ok(reader),
+        }
+    }
+}
+
+/// Reads source files along with the language they should be parsed as
+#[derive(Debug, Clone, Default)]
+pub struct SourceReader {
+    force: Option<LANG>,
+    extensions: HashMap<String, LANG>,
+    decompiled: bool,
+    prefer: Option<&'static str>,
+    tree: Option<Arc<dyn SourceTree>>,
+    preprocessor: Option<Preprocessor>,
+    mmap: bool,
+    retries: u32,
+    backoff: Duration,
+}
+
+/// Files smaller than this are read in even with `--mmap`, mapping them doesn't pay off
+const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// The contents of a file as parsed, either decoded into memory or, for a large UTF-8 file
+/// under `--mmap`, its memory map up to the newline it is trimmed to
+pub enum Source {
+    Owned(Vec<u8>),
+    Mapped(Mmap, usize),
+}
+
+impl Deref for Source {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Source::Owned(source) => source,
+            Source::Mapped(map, len) => &map[..*len],
+        }
+    }
+}
+
+impl SourceReader {
+    pub fn new(force_language: Option<&str>, map_ext: &[String]) -> Result<Self, CliError> {
+        let force = force_language.map(parse_language).transpose()?;
+
+        let mut extensions = HashMap::new();
+        for mapping in map_ext {
+            let (ext, language) = mapping.split_once('=').ok_or_else(|| {
+                CliError::InvalidArgument(format!(
+                    "--map-ext expects extension=language, got {}",
+                    mapping
+                ))
+            })?;
+            extensions.insert(
+                ext.trim_start_matches('.').to_lowercase(),
+                parse_language(language)?,
+            );
+        }
+
+        Ok(Self {
+            force,
+            extensions,
+            decompiled: false,
+            prefer: None,
+            tree: None,
+            preprocessor: None,
+            mmap: false,
+            retries: 0,
+            backoff: Duration::ZERO,
+        })
+    }
+
+    /// Treat every file as decompiler pseudo-C, parsed as C once preprocessed unless
+    /// another language is forced
+    pub fn decompiled(mut self, decompiled: bool) -> Self {
+        self.decompiled = decompiled;
+        self
+    }
+
+    /// Parse ambiguous headers as `dialect`, an `--prefer-language`, when nothing in them
+    /// tells
+    pub fn prefer(mut self, dialect: Option<&str>) -> Self {
+        self.prefer = dialect.map(|dialect| match dialect {
+            "cpp" => CPP,
+            "objc" => OBJC,
+            _ => C,
+        });
+        self
+    }
+
+    /// Read files from the object database of a git repository at a revision rather than
+    /// from the file system
+    pub fn tree(mut self, tree: Option<Arc<dyn SourceTree>>) -> Self {
+        self.tree = tree;
+        self
+    }
+
+    /// Evaluate the preprocessor conditionals of C/C++ files
+    pub fn preprocess(mut self, preprocessor: Preprocessor) -> Self {
+        self.preprocessor = Some(preprocessor);
+        self
+    }
+
+    /// Memory-map large files in `read_source` rather than reading them in
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Retry failed reads `retries` times, waiting `backoff` before the first retry and
+    /// twice as long before every one after
+    pub fn retry(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Reads `path` and resolves its language: a forced language wins over an extension
+    /// override, which wins over guessing
+    pub fn read(&self, path: &Path) -> Result<(LANG, Vec<u8>), CliError> {
+        self.read_decoded(path)
+            .map(|(language, source, _)| (language, source))
+    }
+
+    /// Like `read`, also reporting how the file was decoded to UTF-8
+    pub fn read_decoded(&self, path: &Path) -> Result<(LANG, Vec<u8>, Decoding), CliError> {
+        let bytes = match &self.tree {
+            Some(tree) => self.retrying(path, || tree.read(path))?,
+            None => self.retrying(path, || fs::read(path))?,
+        };
+        self.decode_bytes(path, bytes)
+    }
+
+    /// Like `read_decoded`, mapping `path` instead with `--mmap` when it is large and needs
+    /// no decoding or preprocessing, so the source is never copied onto the heap
+    pub fn read_source(&self, path: &Path) -> Result<(LANG, Source, Decoding), CliError> {
+        let large = fs::metadata(path).is_ok_and(|metadata| metadata.len() >= MMAP_THRESHOLD);
+        if !self.mmap || !large || self.decompiled || self.tree.is_some() {
+            return self
+                .read_decoded(path)
+                .map(|(language, source, decoding)| (language, Source::Owned(source), decoding));
+        }
+
+        let map = self.retrying(path, || {
+            let file = File::open(path)?;
+            // SAFETY: the map is only ever read. Changes to the file while it is mapped
+            // are the risk `--mmap` documents
+            unsafe { Mmap::map(&file) }
+        })?;
+        let trailing = map.iter().rev().take_while(|&&c| c == b'\n').count();
+        let plain =
+            trailing > 0 && Encoding::for_bom(&map).is_none() && std::str::from_utf8(&map).is_ok();
+        let language = self
+            .language_of(path, &map)
+            .map(|language| (language, self.choice(path, language, &map)));
+        match language {
+            Some((language, choice))
+                if plain
+                    && !(language == LANG::Cpp
+                        && (self.preprocessor.is_some() || choice.objc())) =>
+            {
+                let len = map.len() - trailing + 1;
+                let decoding = Decoding {
+                    encoding: UTF_8.name(),
+                    had_errors: false,
+                    choice,
+                };
+                Ok((language, Source::Mapped(map, len), decoding))
+            }
+            _ => self
+                .decode_bytes(path, map.to_vec())
+                .map(|(language, source, decoding)| (language, Source::Owned(source), decoding)),
+        }
+    }
+
+    /// Like `read_decoded`, for the contents of `path` held in memory, e.g. an unsaved
+    /// editor buffer
+    pub fn decode_bytes(
+        &self,
+        path: &Path,
+        bytes: Vec<u8>,
+    ) -> Result<(LANG, Vec<u8>, Decoding), CliError> {
+        let (source, mut decoding) = decode(bytes);
+        let source = if self.decompiled {
+            decompiled::preprocess(&source)
+        } else {
+            source
+        };
+
+        let Some(language) = self.language_of(path, &source) else {
+            return Err(CliError::FailedGuessLang(
+                path.to_string_lossy().to_string(),
+            ));
+        };
+        decoding.choice = self.choice(path, language, &source);
+        let source = match language {
+            LANG::Cpp if !self.decompiled && decoding.choice.objc() => objc::preprocess(&source),
+            _ => source,
+        };
+
+        match (language, &self.preprocessor) {
+            (LANG::Cpp, Some(preprocessor)) => {
+                Ok((LANG::Cpp, preprocessor.apply(&source), decoding))
+            }
+            (language, _) => Ok((language, source, decoding)),
+        }
+    }
+
+    /// Runs `read` until it succeeds or the retries run out. Missing and forbidden files
+    /// fail straight away, retrying won't change them
+    fn retrying<T>(&self, path: &Path, read: impl Fn() -> io::Result<T>) -> Result<T, CliError> {
+        let mut retry = 0;
+        loop {
+            match read() {
+                Ok(value) => return Ok(value),
+                Err(err)
+                    if retry < self.retries
+                        && !matches!(
+                            err.kind(),
+                            io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                        ) =>
+                {
+                    let delay = self.backoff.saturating_mul(1 << retry.min(16));
+                    warn!(file = %path.display(), "Failed to read, retrying in {:?}: {}", delay, err);
+                    thread::sleep(delay);
+                    retry += 1;
+                }
+                Err(err) => {
+                    return Err(CliError::ReadFailed(
+                        path.to_string_lossy().to_string(),
+                        err.to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// How the `language` `path` is parsed as was chosen, and the dialects of C, C++ and
+    /// Objective-C its `source` could be written in when its extension is ambiguous
+    fn choice(&self, path: &Path, language: LANG, source: &[u8]) -> LanguageChoice {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        let resolution = match ext.as_ref().and_then(|ext| self.extensions.get(ext)) {
+            _ if self.force.is_some() || self.decompiled => Resolution::Forced,
+            Some(_) => Resolution::Mapped,
+            None if ext.as_ref().and_then(|ext| get_from_ext(ext)) == Some(language) => {
+                Resolution::Extension
+            }
+            None => Resolution::Guessed,
+        };
+        let name = language.get_name();
+        match (resolution, ext.as_deref()) {
+            (Resolution::Extension, Some("h" | "inc")) => header_dialects(source, self.prefer),
+            (Resolution::Extension, Some("c")) => LanguageChoice::new(resolution, vec![C]),
+            (Resolution::Extension, Some("m" | "mm")) => {
+                LanguageChoice::new(resolution, vec![OBJC])
+            }
+            (Resolution::Extension, _) if language == LANG::Cpp => {
+                LanguageChoice::new(resolution, vec![CPP])
+            }
+            _ => LanguageChoice::new(resolution, vec![name]),
+        }
+    }
+
+    /// The language `path` is parsed as, guessed from `source` when nothing says otherwise
+    fn language_of(&self, path: &Path, source: &[u8]) -> Option<LANG> {
+        let mapped = path
+            .extension()
+            .and_then(|ext| self.extensions.get(&ext.to_string_lossy().to_lowercase()));
+        match (self.force, mapped) {
+            (Some(language), _) | (None, Some(&language)) => Some(language),
+            _ if self.decompiled => Some(LANG::Cpp),
+            // rust-code-analysis panics on extensions that aren't UTF-8, which can't name a
+            // language anyway
+            _ if path.extension().is_some_and(|ext| ext.to_str().is_none()) => {
+                guess_language(source, path.with_extension("")).0
+            }
+            _ => guess_language(source, path).0,
+        }
+    }
+}
+
+/// Dialects of the C/C++ grammar told apart in `language_candidates`
+const C: &str = "c";
+const CPP: &str = "c++";
+const OBJC: &str = "obj-c";
+
+/// What lines opening with these mark a header as C++ or Objective-C
+const CPP_MARKERS: &[&str] = &[
+    "class ",
+    "namespace ",
+    "template",
+    "public:",
+    "protected:",
+    "private:",
+    "using ",
+    "virtual ",
+    "explicit ",
+    "friend ",
+    "constexpr ",
+    "extern \"C++\"",
+];
+const OBJC_MARKERS: &[&str] = &[
+    "@interface",
+    "@protocol",
+    "@end",
+    "@property",
+    "@class",
+    "#import",
+];
+
+/// The dialects a header with an ambiguous extension could be written in, going by the
+/// lines opening with C++ or Objective-C markers. Any Objective-C makes it Objective-C,
+/// as it doesn't parse until rewritten. Without markers of either, C and C++ tie and
+/// `prefer` breaks the tie, C otherwise
+fn header_dialects(source: &[u8], prefer: Option<&'static str>) -> LanguageChoice {
+    let text = String::from_utf8_lossy(source);
+    let count = |markers: &[&str]| {
+        text.lines()
+            .map(str::trim_start)
+            .filter(|line| markers.iter().any(|marker| line.starts_with(marker)))
+            .count()
+    };
+    let (cpp, objc) = (count(CPP_MARKERS), count(OBJC_MARKERS));
+    match (cpp, objc, prefer) {
+        (0, 0, Some(prefer)) => {
+            let mut candidates = vec![prefer];
+            candidates.extend([C, CPP].into_iter().filter(|&dialect| dialect != prefer));
+            LanguageChoice::new(Resolution::Preferred, candidates)
+        }
+        (0, 0, None) => LanguageChoice::new(Resolution::Extension, vec![C, CPP]),
+        (0, _, _) => LanguageChoice::new(Resolution::Markers, vec![OBJC, C]),
+        (_, 0, _) => LanguageChoice::new(Resolution::Markers, vec![CPP, C]),
+        _ => LanguageChoice::new(Resolution::Markers, vec![OBJC, CPP, C]),
+    }
+}
+
+/// Resolves a language by extension (`cpp`, `py`, ...) or name (`python`, `rust`, ...)
+fn parse_language(name: &str) -> Result<LANG, CliError> {
+    let name = name.trim().to_lowercase();
+    get_from_ext(&name)
+        .or_else(|| LANG::into_enum_iter().find(|language| language.get_name() == name))
+        .ok_or_else(|| CliError::InvalidArgument(format!("unknown language {}", name)))
+}
+
+/// What the language of a file was resolved from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    /// `--force-language` or `--decompiled`
+    Forced,
+    /// `--map-ext`
+    Mapped,
+    /// The extension of the file
+    Extension,
+    /// C++ or Objective-C markers in a header whose extension could be either
+    Markers,
+    /// `--prefer-language`, for a header whose extension and contents could be either
+    Preferred,
+    /// The contents of the file, e.g. an editor mode line, as its extension names no
+    /// language or another one
+    Guessed,
+}
+
+impl Resolution {
+    pub fn name(self) -> &'static str {
+        match self {
+            Resolution::Forced => "forced",
+            Resolution::Mapped => "mapped",
+            Resolution::Extension => "extension",
+            Resolution::Markers => "markers",
+            Resolution::Preferred => "preferred",
+            Resolution::Guessed => "guessed",
+        }
+    }
+}
+
+/// How the language of a file was chosen, reported in `language_resolution` and
+/// `language_candidates`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageChoice {
+    pub resolution: Resolution,
+    /// The languages or dialects the file could be, the one it was taken for first, e.g.
+    /// `c++` and `c` for a header declaring classes
+    pub candidates: Vec<&'static str>,
+}
+
+impl LanguageChoice {
+    fn new(resolution: Resolution, candidates: Vec<&'static str>) -> Self {
+        Self {
+            resolution,
+            candidates,
+        }
+    }
+
+    /// Whether the file was taken for Objective-C, rewritten into C++ before parsing
+    pub fn objc(&self) -> bool {
+        self.candidates.first() == Some(&OBJC)
+    }
+
+    /// Name of the `language` the file is parsed as, `obj-c/c++` for Objective-C rather
+    /// than the `c/c++` of the grammar it's parsed with
+    pub fn name(&self, language: &LANG) -> &'static str {
+        match language {
+            LANG::Cpp if self.objc() => "obj-c/c++",
+            language => language.get_name(),
+        }
+    }
+}
+
+impl Default for LanguageChoice {
+    fn default() -> Self {
+        Self::new(Resolution::Extension, Vec::new())
+    }
+}
+
+/// How the bytes of a file were decoded to UTF-8, and how the language they're parsed as
+/// was chosen
+#[derive(Debug, Clone)]
+pub struct Decoding {
+    pub encoding: &'static str,
+    /// Whether some bytes were invalid in the encoding and replaced
+    pub had_errors: bool,
+    pub choice: LanguageChoice,
+}
+
+/// Decodes `bytes` to UTF-8, going by a byte order mark when there is one and detecting
+/// the encoding otherwise. Invalid sequences are replaced rather than failing the file.
+/// Like `rust_code_analysis::read_file`, the result ends in exactly one newline
+fn decode(bytes: Vec<u8>) -> (Vec<u8>, Decoding) {
+    let encoding = match Encoding::for_bom(&bytes) {
+        Some((encoding, _)) => encoding,
+        None if std::str::from_utf8(&bytes).is_ok() => UTF_8,
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(&bytes, true);
+            detector.guess(None, true)
+        }
+    };
+
+    // Valid UTF-8 decodes to itself, so the bytes are reused rather than copied
+    let (text, encoding, had_errors) = encoding.decode(&bytes);
+    let mut source = match text {
+        Cow::Borrowed(text) if text.len() == bytes.len() => bytes,
+        text => text.into_owned().into_bytes(),
+    };
+
+    let trailing = source.iter().rev().take_while(|&&c| c == b'\n').count();
+    if trailing > 0 {
+        source.truncate(source.len() - trailing + 1);
+    } else {
+        source.push(b'\n');
+    }
+
+    (
+        source,
+        Decoding {
+            encoding: encoding.name(),
+            had_errors,
+            choice: LanguageChoice::default(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_is_reused_and_ends_in_exactly_one_newline() {
+        let (source, decoding) = decode(b"int f();\n\n\n".to_vec());
+        assert_eq!(source, b"int f();\n");
+        assert_eq!(decoding.encoding, "UTF-8");
+        assert!(!decoding.had_errors);
+
+        let (source, _) = decode(b"int f();".to_vec());
+        assert_eq!(source, b"int f();\n");
+    }
+
+    #[test]
+    fn byte_order_marks_choose_the_encoding() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("int \u{e9};".encode_utf16().flat_map(u16::to_le_bytes));
+        let (source, decoding) = decode(bytes);
+        assert_eq!(source, "int \u{e9};\n".as_bytes());
+        assert_eq!(decoding.encoding, "UTF-16LE");
+        assert!(!decoding.had_errors);
+    }
+
+    #[test]
+    fn legacy_encodings_are_detected_without_a_byte_order_mark() {
+        // "café" in Latin-1, which isn't valid UTF-8
+        let (source, decoding) = decode(b"// caf\xe9 cr\xe8me\nint f();\n".to_vec());
+        assert_eq!(source, "// caf\u{e9} cr\u{e8}me\nint f();\n".as_bytes());
+        assert_eq!(decoding.encoding, "windows-1252");
+        assert!(!decoding.had_errors);
+    }
+
+    #[test]
+    fn invalid_sequences_are_replaced_rather_than_failing() {
+        let (source, decoding) = decode(b"\xEF\xBB\xBFint \xFF;\n".to_vec());
+        assert_eq!(source, "int \u{fffd};\n".as_bytes());
+        assert_eq!(decoding.encoding, "UTF-8");
+        assert!(decoding.had_errors);
+    }
+
+    #[test]
+    fn large_utf8_files_are_mapped_as_they_would_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let body = "int f(void) { return 0; }\n".repeat(50_000);
+        let write = |name: &str, contents: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        };
+        let large = write("large.c", &format!("{}\n\n", body));
+        let unterminated = write("unterminated.c", body.trim_end());
+        let small = write("small.c", "int f(void);\n");
+
+        let reader = SourceReader::new(None, &[]).unwrap();
+        let mapped = SourceReader::new(None, &[]).unwrap().mmap(true);
+        for path in [&large, &unterminated, &small] {
+            let (language, read, _) = reader.read_source(path).unwrap();
+            let (mapped_language, source, decoding) = mapped.read_source(path).unwrap();
+            assert_eq!(mapped_language, language);
+            assert_eq!(*source, *read);
+            assert_eq!(decoding.encoding, "UTF-8");
+            let is_mapped = matches!(source, Source::Mapped(..));
+            // Files lacking the newline the parser needs are read in to add it
+            assert_eq!(is_mapped, path == &large, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn failed_reads_are_retried_until_they_succeed_or_run_out() {
+        let reader = SourceReader::new(None, &[])
+            .unwrap()
+            .retry(2, Duration::from_millis(1));
+        let path = Path::new("flaky.c");
+        let attempts = &std::cell::Cell::new(0);
+        let flaky = |failures: u32| {
+            attempts.set(0);
+            move || {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() > failures {
+                    Ok(attempts.get())
+                } else {
+                    Err(io::Error::new(io::ErrorKind::TimedOut, "stale handle"))
+                }
+            }
+        };
+
+        assert_eq!(reader.retrying(path, flaky(2)).unwrap(), 3);
+        let failed = reader.retrying(path, flaky(3)).unwrap_err();
+        assert!(matches!(
+            failed,
+            CliError::ReadFailed(file, reason) if file == "flaky.c" && reason == "stale handle"
+        ));
+        assert_eq!(attempts.get(), 3);
+
+        // Retrying won't make a missing file appear
+        attempts.set(0);
+        let missing = reader.retrying(path, || -> io::Result<()> {
+            attempts.set(attempts.get() + 1);
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert!(matches!(missing, Err(CliError::ReadFailed(..))));
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -0,0 +1,56 @@
+pub mod anonymous;
+pub mod availability;
+pub mod budgets;
+pub mod bugfix;
+pub mod build_info;
+pub mod callgraph;
+pub mod cli;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+pub mod commands;
+pub mod config;
+pub mod database;
+pub mod dataset;
+pub mod decompiled;
+pub mod discovery;
+pub mod errors;
+pub mod extract;
+#[cfg(feature = "arrow")]
+pub mod flight;
+pub mod functions;
+pub mod gate;
+pub mod generated;
+pub mod git;
+pub mod graph_export;
+#[cfg(feature = "sqlite")]
+pub mod history;
+pub mod impact;
+pub mod interrupt;
+pub mod language;
+pub mod logging;
+pub mod manifest;
+pub mod normalize;
+pub mod objc;
+pub mod output;
+pub mod owners;
+pub mod parse_errors;
+pub mod paths;
+pub mod plugins;
+pub mod preprocess;
+pub mod profile;
+pub mod projects;
+pub mod quality;
+pub mod query;
+pub mod remote;
+pub mod runs;
+pub mod schema;
+pub mod score;
+pub mod suppress;
+pub mod syntax;
+pub mod telemetry;
+pub mod tree;
+pub mod treemap;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod units;
+pub mod webhook;
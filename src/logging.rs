@@ -0,0 +1,43 @@
+use clap::Args;
+use tracing::Level;
+
+#[derive(Args)]
+pub struct LoggingArgs {
+    /// Format of the log output written to stderr
+    /// Options: pretty, json
+    #[arg(long, global = true, default_value = "pretty", value_parser = clap::builder::PossibleValuesParser::new(["pretty", "json"]))]
+    log_format: String,
+
+    /// Most verbose level to log
+    /// Options: error, warn, info, debug, trace
+    #[arg(long, global = true, default_value = "info", value_parser = clap::builder::PossibleValuesParser::new(["error", "warn", "info", "debug", "trace"]))]
+    log_level: String,
+
+    /// Only log errors, overriding --log-level
+    #[arg(short, long, global = true, default_value = "false")]
+    quiet: bool,
+}
+
+impl LoggingArgs {
+    /// Installs the global subscriber. Logs always go to stderr so they never mix with
+    /// results written to stdout
+    pub fn init(&self) {
+        let level = if self.quiet {
+            Level::ERROR
+        } else {
+            self.log_level.parse().unwrap_or(Level::INFO)
+        };
+
+        let builder = tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(std::io::stderr);
+
+        match self.log_format.as_str() {
+            "json" => builder.json().init(),
+            "pretty" => builder.init(),
+            _ => {
+                unreachable!("Invalid log format provided.")
+            }
+        }
+    }
+}
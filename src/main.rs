@@ -1,21 +1,41 @@
-use clap::Parser;
+use archaeo::cli;
+use archaeo::errors::CliError;
 use color_eyre::Result;
-mod cli;
-mod commands;
-mod errors;
 
 fn main() -> Result<()> {
     // Initialize error handling
     color_eyre::install()?;
 
-    // Initialize logging
-    tracing_subscriber::fmt::init();
-
     // Parse command line arguments
-    let cli = cli::Cli::parse();
+    let cli = cli::Cli::parse_configured().unwrap_or_else(|err| fail(err));
+
+    // Initialize logging
+    cli.logging.init();
 
-    // Execute the matched command
-    cli.execute()?;
+    // Execute the matched command, exporting progress metrics until it finishes. The
+    // telemetry is flushed before failing since exiting skips destructors
+    let result = {
+        let _telemetry = cli.telemetry.init();
+        cli.execute()
+    };
+    if let Err(err) = result {
+        fail(err);
+    }
 
     Ok(())
 }
+
+/// Reports `err` and exits with its code
+fn fail(err: CliError) -> ! {
+    let code = err.exit_code();
+    match err {
+        // clap prints usage errors, `--help` and `--version` itself
+        CliError::Usage(err) => {
+            let _ = err.print();
+        }
+        // An interrupted run has already logged what it left for `--resume`
+        CliError::Interrupted(_) => {}
+        err => eprintln!("Error: {:?}", color_eyre::Report::new(err)),
+    }
+    std::process::exit(code)
+}
@@ -0,0 +1,135 @@
+use rayon::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::build_info::BuildInfo;
+use crate::errors::CliError;
+use crate::generated::Skipped;
+use crate::schema::SCHEMA_VERSION;
+
+/// Name of the manifest written to the output path
+pub const MANIFEST: &str = "manifest.json";
+
+/// `rust-code-analysis` is pinned to an exact version in Cargo.toml
+pub const RUST_CODE_ANALYSIS_VERSION: &str = "0.0.24";
+
+/// Provenance of a run, enough to tell whether two datasets were built the same way
+#[derive(Debug, Serialize)]
+pub struct Manifest<T: Serialize> {
+    pub archaeo_version: &'static str,
+    pub rust_code_analysis_version: &'static str,
+    /// Commit the binary was built from
+    pub git_hash: &'static str,
+    pub grammar_versions: BTreeMap<&'static str, &'static str>,
+    /// Optional features the binary was built with
+    pub features: Vec<&'static str>,
+    /// Version of the schema of the output rows
+    pub schema_version: u32,
+    /// Seconds since the Unix epoch the run finished at
+    pub created_at: u64,
+    pub command_line: Vec<String>,
+    pub options: T,
+    pub inputs: InputSummary,
+    /// Whether the run resumed an earlier one, in which case `outputs` only lists the
+    /// files written by this run
+    pub resumed: bool,
+    pub outputs: Vec<String>,
+    /// Whether Ctrl-C stopped the run, in which case `inputs` only covers the files it
+    /// completed
+    pub interrupted: bool,
+    /// Files `--skip-generated` left out, with why
+    pub skipped: Vec<Skipped>,
+    /// Files that failed to read however often they were retried, with the last error, or
+    /// that hit `--timeout-per-file`
+    pub quarantined: Vec<Skipped>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InputSummary {
+    pub files: usize,
+    pub bytes: u64,
+    /// SHA-256 over the sorted paths and SHA-256 of every input file
+    pub sha256: String,
+}
+
+impl<T: Serialize> Manifest<T> {
+    pub fn new(
+        options: T,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+        skipped: &[Skipped],
+        resumed: bool,
+    ) -> Result<Self, CliError> {
+        let mut outputs: Vec<String> = outputs
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        outputs.sort();
+        outputs.dedup();
+
+        let build = BuildInfo::current();
+        Ok(Self {
+            archaeo_version: build.archaeo_version,
+            rust_code_analysis_version: build.rust_code_analysis_version,
+            git_hash: build.git_hash,
+            grammar_versions: build.grammar_versions,
+            features: build.features,
+            schema_version: SCHEMA_VERSION,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            command_line: std::env::args().collect(),
+            options,
+            inputs: InputSummary::new(inputs)?,
+            resumed,
+            outputs,
+            interrupted: false,
+            skipped: skipped.to_vec(),
+            quarantined: Vec::new(),
+        })
+    }
+
+    pub fn write(&self, directory: &Path) -> Result<PathBuf, CliError> {
+        let path = directory.join(MANIFEST);
+        serde_json::to_writer_pretty(File::create(&path)?, self)?;
+        Ok(path)
+    }
+}
+
+impl InputSummary {
+    fn new(inputs: &[PathBuf]) -> Result<Self, CliError> {
+        let mut hashed: Vec<(String, u64, Vec<u8>)> = inputs
+            .par_iter()
+            .map(|path| {
+                let content = fs::read(path)?;
+                Ok((
+                    path.to_string_lossy().to_string(),
+                    content.len() as u64,
+                    Sha256::digest(&content).to_vec(),
+                ))
+            })
+            .collect::<Result<_, CliError>>()?;
+        hashed.sort();
+
+        let mut hasher = Sha256::new();
+        for (path, _, digest) in &hashed {
+            hasher.update(path.as_bytes());
+            hasher.update([0]);
+            hasher.update(digest);
+        }
+
+        Ok(Self {
+            files: hashed.len(),
+            bytes: hashed.iter().map(|(_, bytes, _)| bytes).sum(),
+            sha256: hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        })
+    }
+}
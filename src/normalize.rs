@@ -0,0 +1,183 @@
+use serde_json::{json, Map, Value};
+
+/// Appends a `<column>_<method>` column for every one of the metric `columns` of `rows`,
+/// computed against the statistics of that column across all rows. Null values are left
+/// out of the statistics and stay null
+///
+/// - `zscore`: distance from the mean in standard deviations
+/// - `minmax`: position between the minimum (0) and maximum (1)
+/// - `percentile`: percentage of rows with a value at or below this one
+pub fn normalize(rows: &mut [&mut Map<String, Value>], columns: &[String], method: &str) {
+    // Masked metrics are null, a column masked in every row has nothing to scale against
+    let columns: Vec<&String> = columns
+        .iter()
+        .filter(|column| {
+            rows.iter()
+                .any(|row| row.get(*column).is_some_and(Value::is_number))
+        })
+        .collect();
+
+    for column in columns {
+        let values: Vec<Option<f64>> = rows
+            .iter()
+            .map(|row| match row.get(column) {
+                Some(Value::Null) => None,
+                value => Some(value.and_then(Value::as_f64).unwrap_or(0.0)),
+            })
+            .collect();
+        let present: Vec<f64> = values.iter().flatten().copied().collect();
+        let scale = Scale::new(&present, method);
+        let name = format!("{}_{}", column, method);
+        for (row, value) in rows.iter_mut().zip(values) {
+            row.insert(name.clone(), json!(value.map(|value| scale.apply(value))));
+        }
+    }
+}
+
+/// The statistics of a column a `normalize` method scales its values by
+pub enum Scale {
+    ZScore { mean: f64, std_dev: f64 },
+    MinMax { min: f64, range: f64 },
+    Percentile { sorted: Vec<f64> },
+}
+
+impl Scale {
+    pub fn new(values: &[f64], method: &str) -> Self {
+        match method {
+            "zscore" => {
+                let count = values.len() as f64;
+                let mean = values.iter().sum::<f64>() / count;
+                let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count;
+                Scale::ZScore {
+                    mean,
+                    std_dev: variance.sqrt(),
+                }
+            }
+            "minmax" => {
+                let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+                let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                Scale::MinMax {
+                    min,
+                    range: max - min,
+                }
+            }
+            "percentile" => {
+                let mut sorted = values.to_vec();
+                sorted.sort_by(f64::total_cmp);
+                Scale::Percentile { sorted }
+            }
+            _ => {
+                unreachable!("Invalid normalization method provided.")
+            }
+        }
+    }
+
+    /// Constant columns normalize to 0
+    pub fn apply(&self, value: f64) -> f64 {
+        match self {
+            Scale::ZScore { mean, std_dev } if *std_dev > 0.0 => (value - mean) / std_dev,
+            Scale::MinMax { min, range } if *range > 0.0 => (value - min) / range,
+            Scale::Percentile { sorted } => {
+                let at_or_below = sorted.partition_point(|v| *v <= value);
+                at_or_below as f64 / sorted.len() as f64 * 100.0
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Schema;
+
+    fn rows(values: &[Value]) -> Vec<Map<String, Value>> {
+        values
+            .iter()
+            .map(|value| {
+                let mut row = Map::new();
+                row.insert("name".to_string(), json!("f"));
+                row.insert("start_line".to_string(), json!(1));
+                row.insert("cyclomatic".to_string(), value.clone());
+                row
+            })
+            .collect()
+    }
+
+    fn normalized(values: &[Value], method: &str) -> Vec<Value> {
+        let mut rows = rows(values);
+        let columns = ["cyclomatic".to_string()];
+        normalize(&mut rows.iter_mut().collect::<Vec<_>>(), &columns, method);
+        rows.iter()
+            .map(|row| row[&format!("cyclomatic_{}", method)].clone())
+            .collect()
+    }
+
+    #[test]
+    fn columns_are_scaled_against_every_row() {
+        let values = [json!(1.0), json!(2.0), json!(3.0), json!(6.0)];
+        let std_dev = 3.5f64.sqrt();
+        assert_eq!(
+            normalized(&values, "zscore"),
+            [
+                json!(-2.0 / std_dev),
+                json!(-1.0 / std_dev),
+                json!(0.0),
+                json!(3.0 / std_dev)
+            ]
+        );
+        assert_eq!(
+            normalized(&values, "minmax"),
+            [json!(0.0), json!(0.2), json!(0.4), json!(1.0)]
+        );
+        // Ties share the percentage at or below them
+        let values = [json!(1.0), json!(2.0), json!(2.0), json!(4.0)];
+        assert_eq!(
+            normalized(&values, "percentile"),
+            [json!(25.0), json!(75.0), json!(75.0), json!(100.0)]
+        );
+    }
+
+    #[test]
+    fn constant_columns_normalize_to_zero() {
+        let values = [json!(3.0), json!(3.0)];
+        assert_eq!(normalized(&values, "zscore"), [json!(0.0), json!(0.0)]);
+        assert_eq!(normalized(&values, "minmax"), [json!(0.0), json!(0.0)]);
+    }
+
+    #[test]
+    fn nulls_stay_null_and_out_of_the_statistics() {
+        let values = [json!(null), json!(2.0), json!(4.0)];
+        assert_eq!(
+            normalized(&values, "minmax"),
+            [json!(null), json!(0.0), json!(1.0)]
+        );
+        assert_eq!(
+            normalized(&values, "percentile"),
+            [json!(null), json!(50.0), json!(100.0)]
+        );
+    }
+
+    #[test]
+    fn only_metric_columns_are_normalized() {
+        let mut rows = rows(&[json!(1.0), json!(2.0)]);
+        for (row, count) in rows.iter_mut().zip([0, 3]) {
+            row.insert("metrics_supported".to_string(), json!(255));
+            row.insert("advisory_count".to_string(), json!(count));
+        }
+        let columns = Schema::current(false).metric_columns();
+        normalize(&mut rows.iter_mut().collect::<Vec<_>>(), &columns, "zscore");
+        let columns: Vec<&String> = rows[0].keys().collect();
+        assert_eq!(
+            columns,
+            [
+                "name",
+                "start_line",
+                "cyclomatic",
+                "metrics_supported",
+                "advisory_count",
+                "cyclomatic_zscore"
+            ]
+        );
+    }
+}
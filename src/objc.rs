@@ -0,0 +1,399 @@
+//! Rewriting of Objective-C (`.m`) and Objective-C++ (`.mm`) so it parses as C++, as
+//! rust-code-analysis has no grammar of its own for them.
+//!
+//! Every `@implementation` becomes a class and its methods member functions, named after
+//! an encoding of their selector that `selector` and `signature` decode again. Interfaces,
+//! protocols and property declarations are blanked out, and the `@` of literals and
+//! keywords dropped. Newlines are kept so line numbers still match the original file, but
+//! the lines of method headers and `@implementation` change length.
+
+/// Prefix of the function names instance methods are rewritten to
+const INSTANCE: &str = "__objc_i_";
+/// Prefix of the function names class methods are rewritten to
+const CLASS: &str = "__objc_c_";
+
+/// Rewrites Objective-C `source` into C++:
+///
+/// - `@implementation Name` becomes `class Name {` and its `@end` becomes `};`, with the
+///   braces around instance variables dropped
+/// - method definitions such as `- (int)add:(int)a to:(int)b {` become
+///   `int __objc_i_add_Cto_C(int a, int b) {`
+/// - `@interface` and `@protocol` blocks, `@class`, `@property`, `@synthesize` and
+///   `@dynamic` declarations and method declarations are blanked out
+/// - `@selector(...)`, `@protocol(...)` and `@encode(...)` become `0`, `@try` and `@catch`
+///   lose their `@`, `@finally`, `@autoreleasepool` and `@synchronized (...)` are blanked
+///   leaving their block, and the `@` of literals such as `@"text"` or `@[a, b]` is blanked
+/// - blocks `^(int x) { ... }` become lambdas
+pub fn preprocess(source: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(source.len());
+    // Braces open in the output, and how many were open in the body of the class being
+    // rewritten
+    let mut depth = 0usize;
+    let mut class_depth = None;
+    // Closing braces of instance variable blocks to skip over
+    let mut dropped = Vec::new();
+    let mut line_start = true;
+
+    let mut i = 0;
+    while i < source.len() {
+        let c = source[i];
+        if let Some(end) = literal_end(source, i) {
+            out.extend_from_slice(&source[i..end]);
+            line_start = false;
+            i = end;
+            continue;
+        }
+        match c {
+            b'\n' => {
+                out.push(c);
+                line_start = true;
+                i += 1;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => {
+                out.push(c);
+                i += 1;
+                continue;
+            }
+            b'@' => {
+                let end = word_end(source, i + 1);
+                let word = &source[i + 1..end];
+                let next = next_token(source, end);
+                i = match word {
+                    b"interface" | b"protocol" if source.get(next) != Some(&b'(') => {
+                        // Forward declarations end at their `;`, blocks at their `@end`
+                        let stop = match declaration_end(source, end) {
+                            Some(stop) => stop,
+                            None => find(source, end, b"@end").map_or(source.len(), |at| at + 4),
+                        };
+                        blank(&mut out, &source[i..stop]);
+                        stop
+                    }
+                    b"class" | b"property" | b"synthesize" | b"dynamic" => {
+                        let stop = find(source, end, b";").map_or(source.len(), |at| at + 1);
+                        blank(&mut out, &source[i..stop]);
+                        stop
+                    }
+                    b"implementation" => {
+                        let name_end = word_end(source, next);
+                        let name = String::from_utf8_lossy(&source[next..name_end]);
+                        out.extend_from_slice(format!("class {} {{", name).as_bytes());
+                        depth += 1;
+                        class_depth = Some(depth);
+                        // A category, `@implementation Name (Category)`
+                        let mut stop = name_end;
+                        let after = next_token(source, name_end);
+                        if source.get(after) == Some(&b'(') {
+                            stop = closing(source, after, b'(', b')');
+                        }
+                        blank(&mut out, &source[name_end..stop]);
+                        // Instance variables, `@implementation Name { int count; }`
+                        let brace = next_token(source, stop);
+                        if source.get(brace) == Some(&b'{') {
+                            blank(&mut out, &source[stop..=brace]);
+                            dropped.push(closing(source, brace, b'{', b'}') - 1);
+                            stop = brace + 1;
+                        }
+                        stop
+                    }
+                    b"end" if class_depth.is_some() => {
+                        out.extend_from_slice(b"};");
+                        blank(&mut out, &source[i + 2..end]);
+                        depth = depth.saturating_sub(1);
+                        class_depth = None;
+                        end
+                    }
+                    b"try" | b"catch" => {
+                        out.push(b' ');
+                        i + 1
+                    }
+                    b"finally" | b"autoreleasepool" => {
+                        blank(&mut out, &source[i..end]);
+                        end
+                    }
+                    b"synchronized" | b"selector" | b"protocol" | b"encode" => {
+                        let stop = match source.get(next) {
+                            Some(b'(') => closing(source, next, b'(', b')'),
+                            _ => end,
+                        };
+                        if word != b"synchronized" {
+                            out.push(b'0');
+                            blank(&mut out, &source[i + 1..stop]);
+                        } else {
+                            blank(&mut out, &source[i..stop]);
+                        }
+                        stop
+                    }
+                    _ => {
+                        out.push(b' ');
+                        i + 1
+                    }
+                };
+            }
+            b'-' | b'+' if line_start && class_depth == Some(depth) => {
+                let stop = source[i..]
+                    .iter()
+                    .position(|&c| c == b'{' || c == b';')
+                    .map_or(source.len(), |at| i + at);
+                let header = &source[i..stop];
+                match source.get(stop) {
+                    Some(b'{') => {
+                        out.extend_from_slice(rewrite_header(header).as_bytes());
+                        out.extend(header.iter().filter(|&&c| c == b'\n'));
+                        i = stop;
+                    }
+                    // A declaration of a method defined elsewhere
+                    _ => {
+                        let stop = (stop + 1).min(source.len());
+                        blank(&mut out, &source[i..stop]);
+                        i = stop;
+                    }
+                }
+            }
+            b'{' => {
+                out.push(c);
+                depth += 1;
+                i += 1;
+            }
+            b'}' if dropped.last() == Some(&i) => {
+                dropped.pop();
+                out.push(b' ');
+                i += 1;
+            }
+            b'}' => {
+                out.push(c);
+                depth = depth.saturating_sub(1);
+                i += 1;
+            }
+            b'^' if is_block(source, &out, i) => {
+                out.extend_from_slice(b"[]");
+                i += 1;
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+        line_start = false;
+    }
+    out
+}
+
+/// The selector of a method rewritten by `preprocess` from the name of its function, e.g.
+/// `add:to:` for `__objc_i_add_Cto_C`, or `None` for any other name
+pub fn selector(name: &str) -> Option<String> {
+    let encoded = name
+        .strip_prefix(INSTANCE)
+        .or_else(|| name.strip_prefix(CLASS))?;
+    let mut selector = String::new();
+    let mut chars = encoded.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('_', Some('C')) => {
+                selector.push(':');
+                chars.next();
+            }
+            ('_', Some('U')) => {
+                selector.push('_');
+                chars.next();
+            }
+            (c, _) => selector.push(c),
+        }
+    }
+    Some(selector)
+}
+
+/// The signature of a method rewritten by `preprocess` as written in Objective-C, e.g.
+/// `- (int)add:(int)a to:(int)b` for `int __objc_i_add_Cto_C(int a, int b)`, or `None` for
+/// any other signature
+pub fn signature(signature: &str) -> Option<String> {
+    let at = signature.find(INSTANCE).or_else(|| signature.find(CLASS))?;
+    let sign = if signature[at..].starts_with(INSTANCE) {
+        '-'
+    } else {
+        '+'
+    };
+    let return_type = signature[..at].trim();
+    let open = at + signature[at..].find('(')?;
+    let close = signature.rfind(')').filter(|&close| close > open)?;
+    let selector = selector(&signature[at..open])?;
+
+    let parameters: Vec<(&str, &str)> = signature[open + 1..close]
+        .split(',')
+        .map(str::trim)
+        .filter(|parameter| !parameter.is_empty())
+        .map(|parameter| {
+            let name = parameter
+                .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .map_or(0, |at| at + 1);
+            (parameter[..name].trim(), &parameter[name..])
+        })
+        .collect();
+    let mut rewritten = format!("{} ({})", sign, return_type);
+    if parameters.is_empty() {
+        rewritten.push_str(&selector);
+    } else {
+        let parts = selector.split_terminator(':');
+        let pieces: Vec<String> = parts
+            .zip(&parameters)
+            .map(|(part, (ty, name))| format!("{}:({}){}", part, ty, name))
+            .collect();
+        rewritten.push_str(&pieces.join(" "));
+    }
+    Some(rewritten)
+}
+
+/// The C++ declaration of the method whose `header` runs from its `-` or `+` up to its body
+fn rewrite_header(header: &[u8]) -> String {
+    let header = String::from_utf8_lossy(header);
+    let prefix = if header.starts_with('+') {
+        CLASS
+    } else {
+        INSTANCE
+    };
+    let mut rest = header[1..].trim_start();
+    let return_type = match rest.strip_prefix('(') {
+        Some(inner) => {
+            let close = inner.find(')').unwrap_or(inner.len());
+            rest = inner.get(close + 1..).unwrap_or_default();
+            inner[..close].trim().to_string()
+        }
+        None => "id".to_string(),
+    };
+
+    let mut selector = String::new();
+    let mut parameters = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        let part_end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let (part, after) = rest.split_at(part_end);
+        let Some(after) = after.trim_start().strip_prefix(':') else {
+            // The last part of a selector without parameters, or attributes after it
+            if parameters.is_empty() {
+                selector.push_str(part);
+            }
+            break;
+        };
+        selector.push_str(part);
+        selector.push(':');
+        let mut after = after.trim_start();
+        let ty = match after.strip_prefix('(') {
+            Some(inner) => {
+                let close = inner.find(')').unwrap_or(inner.len());
+                after = inner.get(close + 1..).unwrap_or_default();
+                inner[..close].trim().to_string()
+            }
+            None => "id".to_string(),
+        };
+        after = after.trim_start();
+        let name_end = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(after.len());
+        parameters.push(format!("{} {}", ty, &after[..name_end]));
+        rest = &after[name_end..];
+    }
+
+    let mut name = String::from(prefix);
+    for c in selector.chars() {
+        match c {
+            ':' => name.push_str("_C"),
+            '_' => name.push_str("_U"),
+            c => name.push(c),
+        }
+    }
+    format!("{} {}({}) ", return_type, name, parameters.join(", "))
+}
+
+/// Index just past the comment, string or character literal starting at `start`, if one does
+fn literal_end(source: &[u8], start: usize) -> Option<usize> {
+    match (source[start], source.get(start + 1)) {
+        (b'/', Some(b'/')) => Some(
+            source[start..]
+                .iter()
+                .position(|&c| c == b'\n')
+                .map_or(source.len(), |at| start + at),
+        ),
+        (b'/', Some(b'*')) => {
+            Some(find(source, start + 2, b"*/").map_or(source.len(), |at| at + 2))
+        }
+        (quote @ (b'"' | b'\''), _) => {
+            let mut i = start + 1;
+            while i < source.len() && source[i] != quote && source[i] != b'\n' {
+                i += if source[i] == b'\\' { 2 } else { 1 };
+            }
+            Some((i + 1).min(source.len()))
+        }
+        _ => None,
+    }
+}
+
+/// Index of the `;` ending an `@interface` or `@protocol` forward declaration starting at
+/// `start`, when it is one rather than a block
+fn declaration_end(source: &[u8], start: usize) -> Option<usize> {
+    let stop = source[start..]
+        .iter()
+        .position(|&c| matches!(c, b';' | b'{' | b'\n' | b'<' | b'('))
+        .map(|at| start + at)?;
+    (source[stop] == b';').then_some(stop + 1)
+}
+
+/// Whether the `^` at `at` starts a block rather than being an exclusive or, going by what
+/// comes before and after it
+fn is_block(source: &[u8], out: &[u8], at: usize) -> bool {
+    let next = next_token(source, at + 1);
+    let previous = out.iter().rev().find(|c| !c.is_ascii_whitespace());
+    matches!(source.get(next), Some(b'(' | b'{'))
+        && previous.is_none_or(|c| matches!(c, b'(' | b',' | b'=' | b':' | b'[' | b'{' | b';'))
+}
+
+fn find(source: &[u8], start: usize, needle: &[u8]) -> Option<usize> {
+    source
+        .get(start..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|at| start + at)
+}
+
+/// Index just past the `close` matching the `open` at `start`, or the end of the source
+fn closing(source: &[u8], start: usize, open: u8, close: u8) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < source.len() {
+        if let Some(end) = literal_end(source, i) {
+            i = end;
+            continue;
+        }
+        if source[i] == open {
+            depth += 1;
+        } else if source[i] == close {
+            depth -= 1;
+            if depth == 0 {
+                return i + 1;
+            }
+        }
+        i += 1;
+    }
+    source.len()
+}
+
+fn next_token(source: &[u8], start: usize) -> usize {
+    (start..source.len())
+        .find(|&i| !source[i].is_ascii_whitespace())
+        .unwrap_or(source.len())
+}
+
+fn word_end(source: &[u8], start: usize) -> usize {
+    (start..source.len())
+        .find(|&i| !(source[i].is_ascii_alphanumeric() || source[i] == b'_'))
+        .unwrap_or(source.len())
+}
+
+/// Appends `text` to `out` blanked with spaces, keeping its newlines
+fn blank(out: &mut Vec<u8>, text: &[u8]) {
+    out.extend(text.iter().map(|&c| match c {
+        b'\n' | b'\r' => c,
+        _ => b' ',
+    }));
+}
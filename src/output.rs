@@ -0,0 +1,167 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::Write;
+
+use crate::errors::CliError;
+
+/// Writes `rows` as CSV with a header taken from the first row. Rows go through
+/// `serde_json::Value` rather than `csv::Writer::serialize` as the latter can't handle
+/// the flattened, dynamically named columns some rows carry.
+pub fn write_csv<W: Write, T: Serialize>(writer: W, rows: &[T]) -> Result<(), CliError> {
+    let mut stream = RowStream::csv(writer);
+    for row in rows {
+        stream.write(row)?;
+    }
+    stream.finish()
+}
+
+/// Writes rows one at a time as they are produced, so memory stays flat however many rows
+/// there are. CSV comes out as `write_csv` writes it and JSON as the pretty-printed array
+/// `serde_json::to_writer_pretty` would
+pub enum RowStream<W: Write> {
+    Csv {
+        writer: Box<csv::Writer<W>>,
+        rows: usize,
+    },
+    Json {
+        writer: W,
+        rows: usize,
+    },
+}
+
+impl<W: Write> RowStream<W> {
+    pub fn csv(writer: W) -> Self {
+        RowStream::Csv {
+            writer: Box::new(csv::Writer::from_writer(writer)),
+            rows: 0,
+        }
+    }
+
+    pub fn json(writer: W) -> Self {
+        RowStream::Json { writer, rows: 0 }
+    }
+
+    pub fn write<T: Serialize>(&mut self, row: &T) -> Result<(), CliError> {
+        let object = match serde_json::to_value(row)? {
+            Value::Object(object) => object,
+            other => {
+                return Err(CliError::FailedProcessing(format!(
+                    "Expected a row to serialize to an object, got {}",
+                    other
+                )))
+            }
+        };
+
+        match self {
+            RowStream::Csv { writer, rows } => {
+                if *rows == 0 {
+                    writer.write_record(object.keys())?;
+                }
+                writer.write_record(object.values().map(csv_field))?;
+                *rows += 1;
+            }
+            RowStream::Json { writer, rows } => {
+                writer.write_all(if *rows == 0 { b"[\n" } else { b",\n" })?;
+                // Strings escape their newlines, so every line of the row is indented as an
+                // element of the array
+                let pretty = serde_json::to_string_pretty(&object)?;
+                for (index, line) in pretty.lines().enumerate() {
+                    if index > 0 {
+                        writer.write_all(b"\n")?;
+                    }
+                    write!(writer, "  {}", line)?;
+                }
+                *rows += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the array or flushes the last CSV records
+    pub fn finish(self) -> Result<(), CliError> {
+        match self {
+            RowStream::Csv { mut writer, .. } => writer.flush()?,
+            RowStream::Json { mut writer, rows } => {
+                writer.write_all(if rows == 0 { b"[]" } else { b"\n]" })?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn csv_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Reverses `csv_field`, reading whole numbers back as integers, other numbers as floats
+/// and empty fields as null
+pub fn parse_field(value: &str) -> Value {
+    if let Ok(number) = value.parse::<i64>() {
+        return json!(number);
+    }
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => json!(number),
+        _ if value.is_empty() => Value::Null,
+        _ => json!(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of `rows` streamed as JSON, or as CSV
+    fn streamed(json: bool, rows: &[Value]) -> String {
+        let mut out = Vec::new();
+        let mut writer = if json {
+            RowStream::json(&mut out)
+        } else {
+            RowStream::csv(&mut out)
+        };
+        for row in rows {
+            writer.write(row).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_streams_match_the_pretty_printed_array() {
+        let rows = [
+            json!({ "name": "f", "cyclomatic": 2.0, "source": "int f() {\n}" }),
+            json!({ "name": "g", "cyclomatic": null, "nested": { "a": [1, 2] } }),
+        ];
+        for count in 0..=rows.len() {
+            assert_eq!(
+                streamed(true, &rows[..count]),
+                serde_json::to_string_pretty(&rows[..count]).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn csv_streams_write_the_header_once() {
+        let rows = [
+            json!({ "name": "f", "cyclomatic": 2.0, "owner": null }),
+            json!({ "name": "a,b", "cyclomatic": 1, "owner": "@x" }),
+        ];
+        assert_eq!(
+            streamed(false, &rows),
+            "name,cyclomatic,owner\nf,2.0,\n\"a,b\",1,@x\n"
+        );
+        assert_eq!(streamed(false, &[]), "");
+        assert!(RowStream::csv(Vec::new()).write(&json!([1])).is_err());
+    }
+
+    #[test]
+    fn fields_are_read_back_as_they_were_written() {
+        for value in [json!(3), json!(2.5), Value::Null, json!("inf"), json!("f")] {
+            assert_eq!(parse_field(&csv_field(&value)), value);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Resolving the owners of a source file from a GitHub style CODEOWNERS file, for the
+//! `owner` column
+
+use globset::{GlobBuilder, GlobMatcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::CliError;
+
+/// The rules of a CODEOWNERS file, where the last rule matching a path decides its owners
+#[derive(Debug)]
+pub struct CodeOwners {
+    /// Directory the patterns are relative to
+    root: PathBuf,
+    rules: Vec<(Vec<GlobMatcher>, String)>,
+}
+
+impl CodeOwners {
+    pub fn from_file(path: &Path) -> Result<Self, CliError> {
+        let text = fs::read_to_string(path)?;
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next() else {
+                continue;
+            };
+            let owners: Vec<&str> = fields.take_while(|field| !field.starts_with('#')).collect();
+            let matchers = globs(pattern).map_err(|err| {
+                CliError::InvalidArgument(format!(
+                    "{}:{}: invalid pattern {}: {}",
+                    path.display(),
+                    number + 1,
+                    pattern,
+                    err
+                ))
+            })?;
+            rules.push((matchers, owners.join(" ")));
+        }
+
+        Ok(Self {
+            root: root_of(path),
+            rules,
+        })
+    }
+
+    /// Owners of `file`, separated by spaces. `None` when no rule matches it, or the last
+    /// matching rule leaves it unowned
+    pub fn owner(&self, file: &Path) -> Option<String> {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let relative = file.strip_prefix(&self.root).unwrap_or(&file);
+        self.rules
+            .iter()
+            .rev()
+            .find(|(matchers, _)| matchers.iter().any(|glob| glob.is_match(relative)))
+            .map(|(_, owners)| owners.clone())
+            .filter(|owners| !owners.is_empty())
+    }
+}
+
+/// CODEOWNERS files live in the repository root, `.github/` or `docs/`
+fn root_of(path: &Path) -> PathBuf {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let directory = path.parent().unwrap_or(Path::new("."));
+    match directory.file_name().and_then(|name| name.to_str()) {
+        Some(".github") | Some("docs") => directory.parent().unwrap_or(directory).to_path_buf(),
+        _ => directory.to_path_buf(),
+    }
+}
+
+/// Globs matching what a gitignore style pattern does in CODEOWNERS: a pattern without a
+/// slash matches at any depth, one naming a directory matches everything below it. Unlike
+/// in gitignore, one ending in a wildcard, e.g. `docs/*`, only matches the files it names
+/// and not those nested below them
+fn globs(pattern: &str) -> Result<Vec<GlobMatcher>, globset::Error> {
+    let directory_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    let base = if anchored {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+    let last = pattern.rsplit('/').next().unwrap_or(pattern);
+    let wildcard = last.contains(['*', '?', '[']);
+
+    let mut patterns = Vec::new();
+    if directory_only || !wildcard {
+        patterns.push(format!("{}/**", base));
+    }
+    if !directory_only {
+        patterns.push(base);
+    }
+    patterns
+        .iter()
+        .map(|pattern| {
+            GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map(|glob| glob.compile_matcher())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// The owners of every one of `files` under the root of a repository with `codeowners`
+    fn owners(codeowners: &str, files: &[&str]) -> Vec<Option<String>> {
+        let repo = TempDir::new().unwrap();
+        let path = repo.path().join("CODEOWNERS");
+        fs::write(&path, codeowners).unwrap();
+        let owners = CodeOwners::from_file(&path).unwrap();
+        let root = repo.path().canonicalize().unwrap();
+        files
+            .iter()
+            .map(|file| owners.owner(&root.join(file)))
+            .collect()
+    }
+
+    fn some(owners: &str) -> Option<String> {
+        Some(owners.to_string())
+    }
+
+    #[test]
+    fn patterns_without_a_slash_match_at_any_depth() {
+        assert_eq!(
+            owners("*.c @c-team", &["a.c", "src/deep/b.c", "a.h", "src/c.h"]),
+            [some("@c-team"), some("@c-team"), None, None]
+        );
+        assert_eq!(
+            owners(
+                "build @ops",
+                &["build", "build/x.c", "src/build/y.c", "builds/z.c"]
+            ),
+            [some("@ops"), some("@ops"), some("@ops"), None]
+        );
+    }
+
+    #[test]
+    fn anchored_directories_match_everything_below_them() {
+        assert_eq!(
+            owners(
+                "/src/ @core",
+                &["src/a.c", "src/nested/b.c", "lib/src/c.c", "src"]
+            ),
+            [some("@core"), some("@core"), None, None]
+        );
+        assert_eq!(
+            owners("/lib/vendor @vendor", &["lib/vendor/a.c", "lib/vendored.c"]),
+            [some("@vendor"), None]
+        );
+    }
+
+    #[test]
+    fn trailing_wildcards_leave_nested_files_out() {
+        assert_eq!(
+            owners(
+                "docs/* @writers",
+                &[
+                    "docs/getting-started.md",
+                    "docs/build-app/troubleshooting.md"
+                ]
+            ),
+            [some("@writers"), None]
+        );
+        assert_eq!(
+            owners("docs/** @writers", &["docs/build-app/troubleshooting.md"]),
+            [some("@writers")]
+        );
+    }
+
+    #[test]
+    fn the_last_matching_rule_wins_even_without_owners() {
+        let codeowners =
+            "# Everything\n* @everyone\n\n/src/ @core @lead # the core\n/src/generated/\n";
+        assert_eq!(
+            owners(codeowners, &["README.md", "src/a.c", "src/generated/b.c"]),
+            [some("@everyone"), some("@core @lead"), None]
+        );
+    }
+
+    #[test]
+    fn codeowners_under_github_or_docs_are_relative_to_the_repository() {
+        let repo = TempDir::new().unwrap();
+        fs::create_dir(repo.path().join(".github")).unwrap();
+        let path = repo.path().join(".github/CODEOWNERS");
+        fs::write(&path, "/src/ @core\n").unwrap();
+        let owners = CodeOwners::from_file(&path).unwrap();
+        let root = repo.path().canonicalize().unwrap();
+        assert_eq!(owners.owner(&root.join("src/a.c")), some("@core"));
+
+        fs::write(&path, "[ @core\n").unwrap();
+        let err = CodeOwners::from_file(&path).unwrap_err().to_string();
+        assert!(err.contains("CODEOWNERS:1: invalid pattern ["), "{}", err);
+    }
+}
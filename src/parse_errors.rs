@@ -0,0 +1,91 @@
+//! Where tree-sitter failed to parse a file. The metrics of code in error nodes are
+//! computed over whatever the parser made of it, so their density tells how far the rows
+//! of a file or function can be trusted
+
+use tree_sitter::Tree;
+
+/// The error and missing nodes of a file, counted per line so any span of lines can be
+/// looked up
+#[derive(Debug, Default)]
+pub struct ParseErrors {
+    /// Error and missing nodes starting before every 0-based line, and in the whole file
+    /// at the end
+    nodes: Vec<usize>,
+    /// Bytes covered by error nodes before every 0-based line, and in the whole file at the
+    /// end
+    error_bytes: Vec<usize>,
+    /// Bytes before every 0-based line, and in the whole file at the end
+    bytes: Vec<usize>,
+}
+
+impl ParseErrors {
+    pub fn new(tree: &Tree, source: &[u8]) -> Self {
+        let lines = source.iter().filter(|&&c| c == b'\n').count() + 1;
+        let mut nodes_on = vec![0usize; lines];
+        // Error nodes don't nest once their children are skipped, so every byte is in one
+        // at most
+        let mut covered = vec![0isize; source.len() + 1];
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.is_error() || node.is_missing() {
+                nodes_on[node.start_position().row.min(lines - 1)] += 1;
+                let range = node.byte_range();
+                covered[range.start.min(source.len())] += 1;
+                covered[range.end.min(source.len())] -= 1;
+            } else if node.has_error() {
+                let mut cursor = node.walk();
+                stack.extend(node.children(&mut cursor));
+            }
+        }
+
+        let mut errors = Self {
+            nodes: Vec::with_capacity(lines + 1),
+            error_bytes: Vec::with_capacity(lines + 1),
+            bytes: Vec::with_capacity(lines + 1),
+        };
+        let (mut nodes, mut error_bytes, mut depth) = (0, 0, 0);
+        let mut line = 0;
+        errors.push(0, 0, 0);
+        for (i, &c) in source.iter().enumerate() {
+            depth += covered[i];
+            if depth > 0 {
+                error_bytes += 1;
+            }
+            if c == b'\n' {
+                nodes += nodes_on[line];
+                line += 1;
+                errors.push(nodes, error_bytes, i + 1);
+            }
+        }
+        if line < lines {
+            nodes += nodes_on[line];
+            errors.push(nodes, error_bytes, source.len());
+        }
+        errors
+    }
+
+    /// Number of error nodes starting on the 1-based lines `start` to `end`, and the
+    /// fraction of their bytes in error nodes
+    pub fn between(&self, start: usize, end: usize) -> (usize, f64) {
+        let last = self.bytes.len().saturating_sub(1);
+        let start = start.saturating_sub(1).min(last);
+        let end = end.min(last).max(start);
+        let bytes = self.bytes[end] - self.bytes[start];
+        let ratio = match bytes {
+            0 => 0.0,
+            bytes => (self.error_bytes[end] - self.error_bytes[start]) as f64 / bytes as f64,
+        };
+        (self.nodes[end] - self.nodes[start], ratio)
+    }
+
+    /// `between` the first and the last line of the file
+    pub fn file(&self) -> (usize, f64) {
+        self.between(1, self.bytes.len())
+    }
+
+    fn push(&mut self, nodes: usize, error_bytes: usize, bytes: usize) {
+        self.nodes.push(nodes);
+        self.error_bytes.push(error_bytes);
+        self.bytes.push(bytes);
+    }
+}
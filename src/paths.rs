@@ -0,0 +1,308 @@
+//! How source files are named in the `source_file` column and laid out below the output
+//! path, so rows from runs on different machines can be joined. Windows drive letters, UNC
+//! shares and reserved file names are handled whatever the platform, as outputs written on
+//! one are often read on another
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+/// Longest encoded name in bytes before it's shortened, leaving room for the extension of
+/// an output within the 255 bytes most file systems allow
+const MAX_NAME: usize = 200;
+
+/// Names Windows reserves for devices, in any case and with any extension
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathStyle {
+    /// The path as discovered below `--path`
+    Given,
+    Absolute,
+    /// Relative to the walked `--path`, or the directory holding it when it's a file
+    RelativeToRoot,
+    /// The file name alone
+    Basename,
+}
+
+impl PathStyle {
+    /// Parses a `--path-style`, already checked by clap
+    pub fn parse(style: Option<&str>) -> Self {
+        match style {
+            Some("absolute") => Self::Absolute,
+            Some("relative-to-root") => Self::RelativeToRoot,
+            Some("basename") => Self::Basename,
+            _ => Self::Given,
+        }
+    }
+}
+
+pub struct PathNaming {
+    style: PathStyle,
+    root: PathBuf,
+    strip_prefix: Option<PathBuf>,
+}
+
+impl PathNaming {
+    pub fn new(style: PathStyle, root: &Path, strip_prefix: Option<&Path>) -> Self {
+        let root = if root.is_file() {
+            root.parent().unwrap_or(Path::new("")).to_path_buf()
+        } else {
+            root.to_path_buf()
+        };
+        Self {
+            style,
+            root,
+            strip_prefix: strip_prefix.map(Path::to_path_buf),
+        }
+    }
+
+    /// Whether a `--path-style` was given
+    pub fn styles(&self) -> bool {
+        self.style != PathStyle::Given
+    }
+
+    /// Whether outputs mirror the names of their sources below the output path, rather
+    /// than being named after the file alone
+    pub fn mirrors(&self) -> bool {
+        self.styles() || self.strip_prefix.is_some()
+    }
+
+    /// Name of `path` in the output, styled and then stripped of `--strip-prefix`
+    pub fn name(&self, path: &Path) -> PathBuf {
+        let styled = match self.style {
+            PathStyle::Given => path.to_path_buf(),
+            PathStyle::Absolute => absolute(path),
+            PathStyle::RelativeToRoot => match path.strip_prefix(&self.root) {
+                Ok(relative) => relative.to_path_buf(),
+                // Paths and roots given in different forms, e.g. only one of them absolute
+                Err(_) => absolute(path)
+                    .strip_prefix(absolute(&self.root))
+                    .map_or_else(|_| path.to_path_buf(), Path::to_path_buf),
+            },
+            PathStyle::Basename => path.file_name().map_or_else(PathBuf::new, PathBuf::from),
+        };
+        match &self.strip_prefix {
+            Some(prefix) => styled
+                .strip_prefix(prefix)
+                .map_or(styled.clone(), Path::to_path_buf),
+            None => styled,
+        }
+    }
+
+    /// `name` of `path` as written to `source_file`, with `/` separators when styled
+    pub fn display(&self, path: &Path) -> String {
+        let name = self.name(path);
+        if self.styles() {
+            slashed(&name)
+        } else {
+            name.to_string_lossy().to_string()
+        }
+    }
+
+    /// Directory below the output path the output of `path` goes into when outputs mirror
+    /// their sources. A drive letter or UNC share becomes a directory of its own, and the
+    /// root and `..` are dropped so no output lands outside of the output path
+    pub fn output_dir(&self, path: &Path) -> PathBuf {
+        let name = self.name(path);
+        let mut dir = PathBuf::new();
+        for component in name.parent().into_iter().flat_map(Path::components) {
+            match component {
+                Component::Prefix(prefix) => match prefix.kind() {
+                    Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+                        dir.push(char::from(drive).to_string())
+                    }
+                    Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+                        dir.push(encode_name(server));
+                        dir.push(encode_name(share));
+                    }
+                    Prefix::Verbatim(part) | Prefix::DeviceNS(part) => dir.push(encode_name(part)),
+                },
+                Component::Normal(part) => dir.push(encode_name(part)),
+                Component::RootDir | Component::CurDir | Component::ParentDir => {}
+            }
+        }
+        dir
+    }
+}
+
+/// `path` with `/` separators, the way `source_file` is written with a `--path-style`.
+/// Drive letters are kept as `C:` and UNC shares as `//server/share`, and the `\\?\`
+/// prefix of the canonical paths of Windows is dropped
+pub fn slashed(path: &Path) -> String {
+    let mut slashed = String::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+                    slashed.push(char::from(drive));
+                    slashed.push(':');
+                }
+                Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+                    slashed.push_str(&format!(
+                        "//{}/{}",
+                        server.to_string_lossy(),
+                        share.to_string_lossy()
+                    ));
+                }
+                Prefix::Verbatim(part) | Prefix::DeviceNS(part) => {
+                    slashed.push_str(&part.to_string_lossy())
+                }
+            },
+            Component::RootDir => slashed.push('/'),
+            component => {
+                if !slashed.is_empty() && !slashed.ends_with('/') {
+                    slashed.push('/');
+                }
+                slashed.push_str(&component.as_os_str().to_string_lossy());
+            }
+        }
+    }
+    slashed
+}
+
+/// `name` as a file name any platform can create, losslessly. Characters Windows forbids,
+/// `%`, control characters and bytes that aren't UTF-8 are percent-encoded, as are trailing
+/// dots and spaces Windows would drop and the first character of reserved device names
+/// such as `con` or `NUL.tar`. Names longer than `MAX_NAME` are cut short and end in a hash
+/// of the whole name instead
+pub fn encode_name(name: &OsStr) -> String {
+    let mut encoded = String::new();
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' | '%' => {
+                    encoded.push_str(&format!("%{:02X}", c as u32))
+                }
+                c if c.is_control() => {
+                    let mut utf8 = [0; 4];
+                    for byte in c.encode_utf8(&mut utf8).bytes() {
+                        encoded.push_str(&format!("%{:02X}", byte));
+                    }
+                }
+                c => encoded.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    let kept = encoded.trim_end_matches(['.', ' ']).len();
+    let trailing: String = encoded[kept..]
+        .bytes()
+        .map(|byte| format!("%{:02X}", byte))
+        .collect();
+    encoded.replace_range(kept.., &trailing);
+
+    let base = encoded.split('.').next().unwrap_or_default();
+    if RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(base))
+    {
+        let first = encoded.remove(0);
+        encoded.insert_str(0, &format!("%{:02X}", first as u32));
+    }
+
+    if encoded.len() > MAX_NAME {
+        let hash = short_hash(encoded.as_bytes(), 16);
+        let mut cut = MAX_NAME - hash.len() - 1;
+        while !encoded.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        encoded.truncate(cut);
+        encoded.push('~');
+        encoded.push_str(&hash);
+    }
+    encoded
+}
+
+/// The first `len` hex digits of the SHA-256 of `bytes`
+fn short_hash(bytes: &[u8], len: usize) -> String {
+    let digest: String = Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    digest[..len].to_string()
+}
+
+/// The output file of every source of a run, named after the stem of the source. Sources
+/// whose outputs would land on the same file, e.g. `stack.c` and `stack.h` or two `util.c`
+/// in different directories of a flat output, have a hash of their path appended to tell
+/// them apart. Names are compared ignoring case, for case-insensitive file systems
+#[derive(Default)]
+pub struct OutputNames {
+    outputs: HashMap<PathBuf, PathBuf>,
+}
+
+impl OutputNames {
+    /// `place` puts the output of a source, named after the given encoded stem, where it
+    /// goes below the output path
+    pub fn new<'a>(
+        sources: impl IntoIterator<Item = &'a Path>,
+        place: impl Fn(&Path, &str) -> PathBuf,
+    ) -> Self {
+        let mut planned: BTreeMap<String, Vec<(&Path, String, PathBuf)>> = BTreeMap::new();
+        let mut seen = HashSet::new();
+        for source in sources {
+            if !seen.insert(source) {
+                continue;
+            }
+            let stem = encode_name(stem_of(source));
+            let output = place(source, &stem);
+            planned
+                .entry(output.to_string_lossy().to_lowercase())
+                .or_default()
+                .push((source, stem, output));
+        }
+
+        let mut outputs = HashMap::new();
+        for (_, sharing) in planned {
+            let shared = sharing.len() > 1;
+            for (source, stem, output) in sharing {
+                let output = if shared {
+                    let hash = short_hash(source.as_os_str().as_encoded_bytes(), 8);
+                    place(source, &format!("{}-{}", stem, hash))
+                } else {
+                    output
+                };
+                outputs.insert(source.to_path_buf(), output);
+            }
+        }
+        Self { outputs }
+    }
+
+    pub fn get(&self, source: &Path) -> Option<&Path> {
+        self.outputs.get(source).map(PathBuf::as_path)
+    }
+}
+
+/// The stem of `path`, or its whole file name when it has none
+fn stem_of(path: &Path) -> &OsStr {
+    path.file_stem()
+        .or_else(|| path.file_name())
+        .unwrap_or(OsStr::new("unnamed"))
+}
+
+/// Whether the extension of `path` is one of `extensions`, ignoring case and a leading
+/// `.` on them, so `--extensions c` matches `MAIN.C` and `.cpp` matches `shapes.cpp`
+pub fn has_extension<S: AsRef<str>>(path: &Path, extensions: &[S]) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy();
+        extensions
+            .iter()
+            .any(|valid| ext.eq_ignore_ascii_case(valid.as_ref().trim_start_matches('.')))
+    })
+}
+
+/// `path` with symlinks resolved when it exists, made absolute lexically otherwise
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,235 @@
+use rust_code_analysis::{FuncSpace, SpaceKind, LANG};
+use tree_sitter::{Node, Tree};
+
+use crate::errors::CliError;
+use crate::syntax::parse;
+
+#[cfg(feature = "wasm")]
+mod wasm;
+
+/// Everything a plugin gets to see of a single space
+pub struct PluginInput<'a> {
+    pub language: LANG,
+    /// Source text of the lines the space covers
+    pub source: &'a str,
+    /// Outermost syntax node spanning exactly the lines of the space, or the root of the
+    /// file when there is none (e.g. for the unit space)
+    pub node: Node<'a>,
+    /// The space along with the metrics `rust-code-analysis` computed for it
+    pub space: &'a FuncSpace,
+}
+
+/// A user-defined metric computed for every space and emitted as extra output columns.
+///
+/// New plugins implement this trait and are added to `builtin_plugins`, behind a cargo
+/// feature if they pull in extra dependencies, rather than forking the flattened structs.
+pub trait MetricPlugin: Send + Sync {
+    /// Name the plugin is selected by with `--plugin`
+    fn name(&self) -> &str;
+
+    /// Every column the plugin may emit so all rows share the same columns
+    fn columns(&self) -> Vec<String>;
+
+    /// Values for the space. Columns left out are reported as 0
+    fn compute(&self, input: &PluginInput) -> Vec<(String, f64)>;
+
+    /// Whether the space should be written out at all
+    fn keep(&self, _input: &PluginInput) -> bool {
+        true
+    }
+}
+
+/// Counts the TODO, FIXME and XXX markers within a space
+struct TodoComments;
+
+impl MetricPlugin for TodoComments {
+    fn name(&self) -> &str {
+        "todo_comments"
+    }
+
+    fn columns(&self) -> Vec<String> {
+        vec!["todo_comments".to_string()]
+    }
+
+    fn compute(&self, input: &PluginInput) -> Vec<(String, f64)> {
+        let count = input
+            .source
+            .lines()
+            .filter(|line| ["TODO", "FIXME", "XXX"].iter().any(|m| line.contains(m)))
+            .count();
+        vec![("todo_comments".to_string(), count as f64)]
+    }
+}
+
+/// Deepest nesting of blocks within a function, the function body itself being level 1
+struct MaxNesting;
+
+impl MetricPlugin for MaxNesting {
+    fn name(&self) -> &str {
+        "max_nesting"
+    }
+
+    fn columns(&self) -> Vec<String> {
+        vec!["max_nesting".to_string()]
+    }
+
+    fn compute(&self, input: &PluginInput) -> Vec<(String, f64)> {
+        if input.space.kind != SpaceKind::Function {
+            return Vec::new();
+        }
+        let block = match input.language {
+            LANG::Cpp => "compound_statement",
+            LANG::Mozjs | LANG::Javascript | LANG::Typescript | LANG::Tsx => "statement_block",
+            _ => "block",
+        };
+
+        let mut deepest = 0;
+        let mut stack = vec![(input.node, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let depth = if node.kind() == block {
+                depth + 1
+            } else {
+                depth
+            };
+            deepest = deepest.max(depth);
+            for child in node.named_children(&mut node.walk()) {
+                stack.push((child, depth));
+            }
+        }
+        vec![("max_nesting".to_string(), deepest as f64)]
+    }
+}
+
+fn builtin_plugins() -> Vec<Box<dyn MetricPlugin>> {
+    vec![Box::new(TodoComments), Box::new(MaxNesting)]
+}
+
+/// Resolves plugin names given on the command line into plugins. Names ending in
+/// `.wasm` or `.wat` are loaded as WebAssembly modules
+pub fn load_plugins(names: &[String]) -> Result<Vec<Box<dyn MetricPlugin>>, CliError> {
+    let mut available = builtin_plugins();
+    let mut plugins: Vec<Box<dyn MetricPlugin>> = Vec::new();
+
+    for name in names {
+        if name.ends_with(".wasm") || name.ends_with(".wat") {
+            plugins.push(load_wasm(name)?);
+            continue;
+        }
+
+        match available.iter().position(|plugin| plugin.name() == name) {
+            Some(index) => plugins.push(available.swap_remove(index)),
+            None => {
+                return Err(CliError::InvalidPlugin(format!(
+                    "{} (available: {})",
+                    name,
+                    builtin_plugins()
+                        .iter()
+                        .map(|plugin| plugin.name().to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )))
+            }
+        }
+    }
+
+    Ok(plugins)
+}
+
+#[cfg(feature = "wasm")]
+fn load_wasm(path: &str) -> Result<Box<dyn MetricPlugin>, CliError> {
+    Ok(Box::new(wasm::WasmPlugin::load(std::path::Path::new(
+        path,
+    ))?))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn load_wasm(path: &str) -> Result<Box<dyn MetricPlugin>, CliError> {
+    Err(CliError::InvalidPlugin(format!(
+        "{} (archaeo was built without the `wasm` feature)",
+        path
+    )))
+}
+
+/// The plugins to run over a single file along with its parsed syntax tree
+pub struct PluginRun<'a> {
+    plugins: &'a [Box<dyn MetricPlugin>],
+    language: LANG,
+    lines: Vec<&'a str>,
+    tree: Tree,
+}
+
+impl<'a> PluginRun<'a> {
+    pub fn new(
+        plugins: &'a [Box<dyn MetricPlugin>],
+        language: LANG,
+        source: &'a [u8],
+    ) -> Option<Self> {
+        let text = std::str::from_utf8(source).ok()?;
+        Some(Self {
+            plugins,
+            language,
+            lines: text.split_inclusive('\n').collect(),
+            tree: parse(&language, source)?,
+        })
+    }
+
+    /// Runs every plugin over `space`, reporting all their columns
+    pub fn compute(&self, space: &FuncSpace) -> Vec<(String, f64)> {
+        let source = self.space_source(space);
+        let input = self.input(space, &source);
+
+        let mut values = Vec::new();
+        for plugin in self.plugins {
+            let computed = plugin.compute(&input);
+            for column in plugin.columns() {
+                let value = computed
+                    .iter()
+                    .find(|(name, _)| *name == column)
+                    .map_or(0.0, |(_, value)| *value);
+                values.push((column, value));
+            }
+        }
+        values
+    }
+
+    /// Whether every plugin agrees to keep `space`
+    pub fn keep(&self, space: &FuncSpace) -> bool {
+        let source = self.space_source(space);
+        let input = self.input(space, &source);
+        self.plugins.iter().all(|plugin| plugin.keep(&input))
+    }
+
+    fn space_source(&self, space: &FuncSpace) -> String {
+        let start = (space.start_line.max(1) - 1).min(self.lines.len());
+        let end = space.end_line.min(self.lines.len()).max(start);
+        self.lines[start..end].concat()
+    }
+
+    fn input<'b>(&'b self, space: &'b FuncSpace, source: &'b str) -> PluginInput<'b> {
+        let start_row = space.start_line.max(1) - 1;
+        let end_row = space.end_line.max(1) - 1;
+        PluginInput {
+            language: self.language,
+            source,
+            node: space_node(self.tree.root_node(), start_row, end_row),
+            space,
+        }
+    }
+}
+
+/// Finds the outermost node starting on `start_row` and ending on `end_row`
+fn space_node(root: Node, start_row: usize, end_row: usize) -> Node {
+    let mut node = root;
+    'descend: loop {
+        if node.start_position().row == start_row && node.end_position().row == end_row {
+            return node;
+        }
+        for child in node.named_children(&mut node.walk()) {
+            if child.start_position().row <= start_row && child.end_position().row >= end_row {
+                node = child;
+                continue 'descend;
+            }
+        }
+        return root;
+    }
+}
@@ -0,0 +1,192 @@
+//! Metric plugins loaded from WebAssembly modules.
+//!
+//! Data crosses the boundary as UTF-8 JSON in the module's linear memory. A module
+//! exports:
+//!
+//! ```text
+//! (memory (export "memory") ...)
+//! (func (export "alloc") (param $len i32) (result i32))            ;; `len` writable bytes
+//! (func (export "columns") (result i64))                           ;; ["column", ...]
+//! (func (export "compute") (param $ptr i32) (param $len i32) (result i64))  ;; {"column": 1.0}
+//! (func (export "filter") (param $ptr i32) (param $len i32) (result i32))   ;; optional, 0 drops
+//! ```
+//!
+//! Results are returned as `(ptr << 32) | len`. `compute` and `filter` receive
+//! `{"language", "name", "kind", "start_line", "end_line", "source", "metrics"}` where
+//! `metrics` are the base metrics of the space as `rust-code-analysis` serializes them.
+//! Modules must not import anything and a fresh instance is used for every call.
+
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use wasmtime::{Engine, Instance, Module, Store};
+
+use super::{MetricPlugin, PluginInput};
+use crate::errors::CliError;
+
+pub struct WasmPlugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+    columns: Vec<String>,
+    has_filter: bool,
+}
+
+impl WasmPlugin {
+    pub fn load(path: &Path) -> Result<Self, CliError> {
+        let invalid =
+            |err: wasmtime::Error| CliError::InvalidPlugin(format!("{}: {}", path.display(), err));
+
+        let engine = Engine::default();
+        let module = Module::from_file(&engine, path).map_err(invalid)?;
+        let has_filter = module.get_export("filter").is_some();
+
+        let mut plugin = Self {
+            name: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            engine,
+            module,
+            columns: Vec::new(),
+            has_filter,
+        };
+
+        plugin.columns = match plugin.call_json("columns", None).map_err(invalid)? {
+            Value::Array(columns) => columns
+                .into_iter()
+                .filter_map(|column| column.as_str().map(str::to_string))
+                .collect(),
+            other => {
+                return Err(CliError::InvalidPlugin(format!(
+                    "{}: columns must return a JSON array of names, got {}",
+                    path.display(),
+                    other
+                )))
+            }
+        };
+
+        Ok(plugin)
+    }
+
+    /// Calls `export` on a fresh instance, optionally passing `input`, and decodes the
+    /// JSON it hands back
+    fn call_json(&self, export: &str, input: Option<&[u8]>) -> wasmtime::Result<Value> {
+        let (mut store, instance) = self.instantiate()?;
+        let packed = match input {
+            Some(input) => {
+                let (ptr, len) = write_input(&mut store, &instance, input)?;
+                instance
+                    .get_typed_func::<(i32, i32), i64>(&mut store, export)?
+                    .call(&mut store, (ptr, len))?
+            }
+            None => instance
+                .get_typed_func::<(), i64>(&mut store, export)?
+                .call(&mut store, ())?,
+        };
+
+        let ptr = (packed as u64 >> 32) as usize;
+        let len = (packed as u64 & 0xffff_ffff) as usize;
+        let memory = memory(&mut store, &instance)?;
+        let bytes = memory
+            .data(&store)
+            .get(ptr..ptr + len)
+            .ok_or_else(|| wasmtime::Error::msg(format!("{} returned out of bounds", export)))?;
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    fn instantiate(&self) -> wasmtime::Result<(Store<()>, Instance)> {
+        let mut store = Store::new(&self.engine, ());
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        Ok((store, instance))
+    }
+
+    fn input_json(input: &PluginInput) -> Vec<u8> {
+        json!({
+            "language": input.language.get_name(),
+            "name": input.space.name,
+            "kind": input.space.kind.to_string(),
+            "start_line": input.space.start_line,
+            "end_line": input.space.end_line,
+            "source": input.source,
+            "metrics": input.space.metrics,
+        })
+        .to_string()
+        .into_bytes()
+    }
+}
+
+impl MetricPlugin for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn columns(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+
+    fn compute(&self, input: &PluginInput) -> Vec<(String, f64)> {
+        match self.call_json("compute", Some(&Self::input_json(input))) {
+            Ok(Value::Object(values)) => numbers(values),
+            Ok(other) => {
+                tracing::warn!(
+                    "{}: compute returned {} instead of an object",
+                    self.name,
+                    other
+                );
+                Vec::new()
+            }
+            Err(err) => {
+                tracing::warn!("{}: compute failed: {}", self.name, err);
+                Vec::new()
+            }
+        }
+    }
+
+    fn keep(&self, input: &PluginInput) -> bool {
+        if !self.has_filter {
+            return true;
+        }
+
+        let run = || -> wasmtime::Result<i32> {
+            let (mut store, instance) = self.instantiate()?;
+            let (ptr, len) = write_input(&mut store, &instance, &Self::input_json(input))?;
+            instance
+                .get_typed_func::<(i32, i32), i32>(&mut store, "filter")?
+                .call(&mut store, (ptr, len))
+        };
+        match run() {
+            Ok(keep) => keep != 0,
+            Err(err) => {
+                tracing::warn!("{}: filter failed: {}", self.name, err);
+                true
+            }
+        }
+    }
+}
+
+fn memory(store: &mut Store<()>, instance: &Instance) -> wasmtime::Result<wasmtime::Memory> {
+    instance
+        .get_memory(&mut *store, "memory")
+        .ok_or_else(|| wasmtime::Error::msg("module does not export its memory"))
+}
+
+/// Copies `input` into memory handed out by the module's `alloc`
+fn write_input(
+    store: &mut Store<()>,
+    instance: &Instance,
+    input: &[u8],
+) -> wasmtime::Result<(i32, i32)> {
+    let len = input.len() as i32;
+    let ptr = instance
+        .get_typed_func::<i32, i32>(&mut *store, "alloc")?
+        .call(&mut *store, len)?;
+    memory(store, instance)?.write(&mut *store, ptr as usize, input)?;
+    Ok((ptr, len))
+}
+
+fn numbers(values: Map<String, Value>) -> Vec<(String, f64)> {
+    values
+        .into_iter()
+        .filter_map(|(name, value)| value.as_f64().map(|value| (name, value)))
+        .collect()
+}
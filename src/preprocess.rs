@@ -0,0 +1,553 @@
+//! A minimal C preprocessor for conditionals, so code behind an inactive `#ifdef` doesn't
+//! end up in the metrics.
+//!
+//! Lines are blanked rather than removed to keep line numbers intact. Only `#if`,
+//! `#ifdef`, `#ifndef`, `#elif`, `#else` and `#endif` are evaluated, against the `--define`d
+//! macros and the object-like `#define`s and `#undef`s seen along the way. Conditions that
+//! can't be evaluated, e.g. calls to function-like macros, are treated as false.
+
+use std::collections::HashMap;
+use tracing::debug;
+
+use crate::errors::CliError;
+
+#[derive(Debug, Clone)]
+pub struct Preprocessor {
+    /// Substitute object-like macros in the code left active
+    expand: bool,
+    defines: HashMap<String, String>,
+}
+
+/// One level of `#if` nesting
+struct Conditional {
+    parent_active: bool,
+    /// Whether a branch of the conditional was already taken
+    taken: bool,
+    active: bool,
+}
+
+impl Preprocessor {
+    /// `mode` is either `strip-inactive` or `expand`. Defines are given as `NAME` (set to
+    /// 1) or `NAME=value`
+    pub fn new(mode: &str, defines: &[String]) -> Result<Self, CliError> {
+        let expand = match mode {
+            "strip-inactive" => false,
+            "expand" => true,
+            other => {
+                return Err(CliError::InvalidArgument(format!(
+                    "unknown preprocess mode {}, expected strip-inactive or expand",
+                    other
+                )))
+            }
+        };
+        let defines = defines
+            .iter()
+            .map(|define| match define.split_once('=') {
+                Some((name, value)) => (name.trim().to_string(), value.trim().to_string()),
+                None => (define.trim().to_string(), "1".to_string()),
+            })
+            .collect();
+        Ok(Self { expand, defines })
+    }
+
+    pub fn apply(&self, source: &[u8]) -> Vec<u8> {
+        let text = String::from_utf8_lossy(source);
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        let mut defines = self.defines.clone();
+        let mut stack: Vec<Conditional> = Vec::new();
+        let mut in_comment = false;
+        let mut out = String::with_capacity(text.len());
+
+        let mut i = 0;
+        while i < lines.len() {
+            // Directives continue onto the next line after a trailing backslash
+            let mut end = i;
+            while end + 1 < lines.len() && lines[end].trim_end().ends_with('\\') {
+                end += 1;
+            }
+            let active = stack.last().is_none_or(|c| c.active);
+
+            let directive = (!in_comment)
+                .then(|| lines[i].trim_start().strip_prefix('#'))
+                .flatten()
+                .map(|_| {
+                    lines[i..=end]
+                        .iter()
+                        .map(|line| line.trim_end().trim_end_matches('\\'))
+                        .collect::<Vec<&str>>()
+                        .join(" ")
+                });
+            let Some(directive) = directive else {
+                if active {
+                    out.push_str(&self.expand_line(lines[i], &defines, &mut in_comment));
+                } else {
+                    blank_line(&mut out, lines[i]);
+                }
+                i += 1;
+                continue;
+            };
+
+            let directive = directive.trim_start().trim_start_matches('#').trim_start();
+            let (keyword, rest) = directive
+                .split_once(|c: char| c.is_whitespace() || c == '(')
+                .map_or((directive, ""), |(k, _)| (k, &directive[k.len()..]));
+            let rest = rest.trim();
+
+            let conditional = match keyword {
+                "if" | "ifdef" | "ifndef" => {
+                    let condition = active
+                        && match keyword {
+                            "ifdef" => defines.contains_key(first_word(rest)),
+                            "ifndef" => !defines.contains_key(first_word(rest)),
+                            _ => evaluate(rest, &defines),
+                        };
+                    stack.push(Conditional {
+                        parent_active: active,
+                        taken: condition,
+                        active: condition,
+                    });
+                    true
+                }
+                "elif" => {
+                    if let Some(frame) = stack.last_mut() {
+                        frame.active =
+                            frame.parent_active && !frame.taken && evaluate(rest, &defines);
+                        frame.taken |= frame.active;
+                    }
+                    true
+                }
+                "else" => {
+                    if let Some(frame) = stack.last_mut() {
+                        frame.active = frame.parent_active && !frame.taken;
+                        frame.taken = true;
+                    }
+                    true
+                }
+                "endif" => {
+                    stack.pop();
+                    true
+                }
+                _ => false,
+            };
+
+            if active && !conditional {
+                match keyword {
+                    "define" => {
+                        let name = first_word(rest);
+                        // Function-like macros are only tracked as defined
+                        let value = match rest[name.len()..].strip_prefix('(') {
+                            Some(_) => String::new(),
+                            None => rest[name.len()..].trim().to_string(),
+                        };
+                        defines.insert(name.to_string(), value);
+                    }
+                    "undef" => {
+                        defines.remove(first_word(rest));
+                    }
+                    _ => {}
+                }
+            }
+
+            for line in &lines[i..=end] {
+                if conditional || !active {
+                    blank_line(&mut out, line);
+                } else {
+                    out.push_str(line);
+                }
+            }
+            i = end + 1;
+        }
+
+        out.into_bytes()
+    }
+
+    /// Substitutes object-like macros in `line`, outside of literals and comments
+    fn expand_line(
+        &self,
+        line: &str,
+        defines: &HashMap<String, String>,
+        in_comment: &mut bool,
+    ) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if *in_comment {
+                if c == '*' && chars.peek().is_some_and(|(_, next)| *next == '/') {
+                    chars.next();
+                    *in_comment = false;
+                    out.push_str("*/");
+                } else {
+                    out.push(c);
+                }
+            } else if c == '/' && chars.peek().is_some_and(|(_, next)| *next == '/') {
+                out.push_str(&line[start..]);
+                break;
+            } else if c == '/' && chars.peek().is_some_and(|(_, next)| *next == '*') {
+                chars.next();
+                *in_comment = true;
+                out.push_str("/*");
+            } else if c == '"' || c == '\'' {
+                out.push(c);
+                while let Some((_, next)) = chars.next() {
+                    out.push(next);
+                    if next == '\\' {
+                        if let Some((_, escaped)) = chars.next() {
+                            out.push(escaped);
+                        }
+                    } else if next == c {
+                        break;
+                    }
+                }
+            } else if self.expand && (c.is_alphabetic() || c == '_') {
+                let mut end = start + c.len_utf8();
+                while let Some((index, next)) = chars.peek() {
+                    if next.is_alphanumeric() || *next == '_' {
+                        end = index + next.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let word = &line[start..end];
+                match defines.get(word) {
+                    Some(value) if !value.is_empty() => out.push_str(value),
+                    _ => out.push_str(word),
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+}
+
+fn blank_line(out: &mut String, line: &str) {
+    if line.ends_with("\r\n") {
+        out.push_str("\r\n");
+    } else if line.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn first_word(text: &str) -> &str {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+/// Evaluates a `#if` condition, treating anything it can't make sense of as false
+fn evaluate(expression: &str, defines: &HashMap<String, String>) -> bool {
+    let mut parser = Parser {
+        tokens: tokenize(expression),
+        position: 0,
+        defines,
+        depth: 0,
+    };
+    match parser.expression(0) {
+        Some(value) if parser.position == parser.tokens.len() => value != 0,
+        _ => {
+            debug!("Treating unsupported condition `{}` as false", expression);
+            false
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Vec<String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            break;
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            if ["&&", "||", "==", "!=", "<=", ">=", "<<", ">>"].contains(&pair.as_str()) {
+                tokens.push(pair);
+                i += 2;
+            } else {
+                tokens.push(c.to_string());
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<String>,
+    position: usize,
+    defines: &'a HashMap<String, String>,
+    /// Guards against macros expanding to themselves
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Precedence climbing over the binary operators
+    fn expression(&mut self, min_precedence: u8) -> Option<i64> {
+        let mut left = self.unary()?;
+        while let Some(precedence) = self.peek().and_then(precedence) {
+            if precedence < min_precedence {
+                break;
+            }
+            let operator = self.next()?;
+            let right = self.expression(precedence + 1)?;
+            left = match operator.as_str() {
+                "||" => ((left != 0) || (right != 0)) as i64,
+                "&&" => ((left != 0) && (right != 0)) as i64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                ">" => (left > right) as i64,
+                "<=" => (left <= right) as i64,
+                ">=" => (left >= right) as i64,
+                "<<" => left.checked_shl(right as u32)?,
+                ">>" => left.checked_shr(right as u32)?,
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" => left.checked_div(right)?,
+                "%" => left.checked_rem(right)?,
+                _ => return None,
+            };
+        }
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<i64> {
+        match self.next()?.as_str() {
+            "!" => Some((self.unary()? == 0) as i64),
+            "-" => Some(self.unary()?.wrapping_neg()),
+            "+" => self.unary(),
+            "~" => Some(!self.unary()?),
+            "(" => {
+                let value = self.expression(0)?;
+                (self.next()? == ")").then_some(value)
+            }
+            "defined" => {
+                let parenthesised = self.peek() == Some("(");
+                if parenthesised {
+                    self.next();
+                }
+                let name = self.next()?;
+                if parenthesised && self.next()? != ")" {
+                    return None;
+                }
+                Some(self.defines.contains_key(&name) as i64)
+            }
+            token if token.starts_with(|c: char| c.is_ascii_digit()) => number(token),
+            // Function-like macro calls can't be evaluated
+            _ if self.peek() == Some("(") => None,
+            name => match self.defines.get(name) {
+                Some(value) if self.depth < 16 => {
+                    let mut parser = Parser {
+                        tokens: tokenize(value),
+                        position: 0,
+                        defines: self.defines,
+                        depth: self.depth + 1,
+                    };
+                    match parser.expression(0) {
+                        Some(value) if parser.position == parser.tokens.len() => Some(value),
+                        _ => Some(0),
+                    }
+                }
+                _ => Some(0),
+            },
+        }
+    }
+}
+
+fn precedence(token: &str) -> Option<u8> {
+    Some(match token {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+/// Parses an integer literal, ignoring `u`/`l` suffixes
+fn number(token: &str) -> Option<i64> {
+    let digits = token.trim_end_matches(['u', 'U', 'l', 'L']);
+    if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8).ok()
+    } else {
+        digits.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocess(mode: &str, defines: &[&str], source: &str) -> String {
+        let defines: Vec<String> = defines.iter().map(|define| define.to_string()).collect();
+        let preprocessor = Preprocessor::new(mode, &defines).unwrap();
+        String::from_utf8(preprocessor.apply(source.as_bytes())).unwrap()
+    }
+
+    /// The lines left active, with the numbers they are at
+    fn active(defines: &[&str], source: &str) -> Vec<(usize, String)> {
+        let out = preprocess("strip-inactive", defines, source);
+        assert_eq!(out.lines().count(), source.lines().count());
+        out.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(number, line)| (number + 1, line.to_string()))
+            .collect()
+    }
+
+    fn condition(expression: &str, defines: &[(&str, &str)]) -> bool {
+        let defines = defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        evaluate(expression, &defines)
+    }
+
+    #[test]
+    fn nested_conditionals_keep_the_lines_of_the_branches_taken() {
+        let source = "#if A\na;\n#if B\nab;\n#else\na_not_b;\n#endif\n#elif C\nc;\n#else\nnone;\n#endif\nall;\n";
+        let lines = |defines: &[&str]| -> Vec<String> {
+            active(defines, source)
+                .into_iter()
+                .map(|(_, line)| line)
+                .collect()
+        };
+        assert_eq!(lines(&["A", "B"]), ["a;", "ab;", "all;"]);
+        assert_eq!(lines(&["A"]), ["a;", "a_not_b;", "all;"]);
+        // The first branch taken wins, even when a later one holds too
+        assert_eq!(lines(&["A", "C"]), ["a;", "a_not_b;", "all;"]);
+        assert_eq!(lines(&["C", "B"]), ["c;", "all;"]);
+        assert_eq!(lines(&[]), ["none;", "all;"]);
+        // Line numbers are kept
+        assert_eq!(active(&["C"], source)[0], (9, "c;".to_string()));
+    }
+
+    #[test]
+    fn defines_are_tracked_along_the_way() {
+        let source = "#define LEVEL 2\n#ifdef LEVEL\nset;\n#endif\n#undef LEVEL\n#ifndef LEVEL\nunset;\n#endif\n#define F(x) x\n#if defined(F) && F(1)\ncall;\n#endif\n";
+        assert_eq!(
+            active(&[], source),
+            [
+                (1, "#define LEVEL 2".to_string()),
+                (3, "set;".to_string()),
+                (5, "#undef LEVEL".to_string()),
+                (7, "unset;".to_string()),
+                (9, "#define F(x) x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_defines_hold_values() {
+        let source = "#if VERSION >= 3\nnew;\n#else\nold;\n#endif\n";
+        let lines = |defines: &[&str]| active(defines, source)[0].1.clone();
+        assert_eq!(lines(&["VERSION=3"]), "new;");
+        assert_eq!(lines(&["VERSION = 2"]), "old;");
+        // Defines without a value are 1
+        assert_eq!(lines(&["VERSION"]), "old;");
+        assert_eq!(lines(&[]), "old;");
+    }
+
+    #[test]
+    fn conditions_follow_the_c_precedence() {
+        let defines = [("X", "2"), ("Y", "X + 1"), ("SELF", "SELF")];
+        assert!(condition("defined(X) && X > 1", &defines));
+        assert!(condition("defined X && Y == 3", &defines));
+        assert!(!condition("defined(Z) && Z > 1", &defines));
+        assert!(condition("!defined(Z) || Z", &defines));
+        assert!(condition("1 + 2 * 3 == 7", &[]));
+        assert!(condition("(1 + 2) * 3 == 9", &[]));
+        assert!(condition("1 << 2 + 1 == 8", &[]));
+        assert!(condition("1 | 2 & 0", &[]));
+        assert!(condition("-1 < 0 && ~0 == -1", &[]));
+        assert!(condition("0x10 == 16 && 010 == 8 && 10UL == 10", &[]));
+        assert!(condition("7 / 2 == 3 && 7 % 2 == 1", &[]));
+        // Undefined names are 0
+        assert!(!condition("UNDEFINED", &[]));
+        assert!(condition("UNDEFINED == 0", &[]));
+        assert!(!condition("SELF", &defines));
+    }
+
+    #[test]
+    fn conditions_that_cant_be_evaluated_are_false() {
+        assert!(!condition("F(1)", &[("F", "")]));
+        assert!(!condition("1 / 0", &[]));
+        assert!(!condition("(1", &[]));
+        assert!(!condition("1 2", &[]));
+        assert!(!condition("", &[]));
+        assert!(!condition("defined(X", &[("X", "1")]));
+    }
+
+    #[test]
+    fn unbalanced_directives_are_tolerated() {
+        // Stray `#else` and `#endif` are blanked and leave the code active
+        assert_eq!(
+            active(&[], "#endif\na;\n#else\nb;\n"),
+            [(2, "a;".to_string()), (4, "b;".to_string())]
+        );
+        // An unterminated conditional runs to the end of the file
+        assert_eq!(active(&[], "a;\n#if 0\nb;\nc;\n"), [(1, "a;".to_string())]);
+        // `#elif` without a conditional is ignored
+        assert_eq!(active(&[], "#elif 1\na;\n"), [(2, "a;".to_string())]);
+    }
+
+    #[test]
+    fn directives_continue_and_macros_expand_outside_of_literals() {
+        let source = "#if defined(A) && \\\n    A > 1\nint x = A; // A\n#endif\n";
+        assert_eq!(
+            preprocess("strip-inactive", &["A=2"], source),
+            "\n\nint x = A; // A\n\n"
+        );
+        assert_eq!(
+            preprocess("expand", &["A=2"], source),
+            "\n\nint x = 2; // A\n\n"
+        );
+        assert_eq!(
+            preprocess("expand", &["A=2"], "s = \"A\"; /* A\nA */ A;\n"),
+            "s = \"A\"; /* A\nA */ 2;\n"
+        );
+        assert!(Preprocessor::new("everything", &[]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Where the time of a `source` run goes, file by file, for `--profile`
+
+use serde::Serialize;
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+use crate::errors::CliError;
+use crate::output::write_csv;
+
+/// How long reading a file and computing its spaces took
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    /// Reading and decoding the file
+    pub read: Duration,
+    /// Building its syntax tree
+    pub parse: Duration,
+    /// Computing the metrics of its spaces from the tree
+    pub metrics: Duration,
+}
+
+/// A row of the profile
+#[derive(Debug, Serialize)]
+pub struct FileProfile {
+    pub source_file: String,
+    pub bytes: usize,
+    pub functions: usize,
+    pub read_ms: f64,
+    pub parse_ms: f64,
+    pub metrics_ms: f64,
+    /// Building, checking and writing out the rows, queries and plugins included
+    pub flatten_ms: f64,
+}
+
+impl FileProfile {
+    pub fn new(
+        source_file: String,
+        bytes: usize,
+        functions: usize,
+        timings: Timings,
+        flatten: Duration,
+    ) -> Self {
+        // To the microsecond
+        let ms = |duration: Duration| (duration.as_secs_f64() * 1e6).round() / 1e3;
+        Self {
+            source_file,
+            bytes,
+            functions,
+            read_ms: ms(timings.read),
+            parse_ms: ms(timings.parse),
+            metrics_ms: ms(timings.metrics),
+            flatten_ms: ms(flatten),
+        }
+    }
+
+    fn total_ms(&self) -> f64 {
+        self.read_ms + self.parse_ms + self.metrics_ms + self.flatten_ms
+    }
+}
+
+/// Writes the profile of every file to `path` as CSV, slowest first, and logs how the
+/// time adds up over the run. Files are processed in parallel, so the totals exceed the
+/// wall-clock time of the run
+pub fn write_profile(path: &Path, profiles: &mut [FileProfile]) -> Result<(), CliError> {
+    profiles.sort_by(|a, b| b.total_ms().total_cmp(&a.total_ms()));
+    write_csv(File::create(path)?, profiles)?;
+
+    let total = |phase: fn(&FileProfile) -> f64| profiles.iter().map(phase).sum::<f64>();
+    info!(
+        "Profiled {} files: {:.1}ms reading, {:.1}ms parsing, {:.1}ms computing metrics, {:.1}ms flattening. Written to {}",
+        profiles.len(),
+        total(|profile| profile.read_ms),
+        total(|profile| profile.parse_ms),
+        total(|profile| profile.metrics_ms),
+        total(|profile| profile.flatten_ms),
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(source_file: &str, parse_us: u64) -> FileProfile {
+        let timings = Timings {
+            read: Duration::from_micros(1500),
+            parse: Duration::from_micros(parse_us),
+            metrics: Duration::from_nanos(2_400),
+        };
+        FileProfile::new(source_file.to_string(), 10, 1, timings, Duration::ZERO)
+    }
+
+    #[test]
+    fn timings_are_rounded_to_the_microsecond() {
+        let profile = profile("a.c", 250);
+        assert_eq!(
+            (profile.read_ms, profile.parse_ms, profile.metrics_ms, profile.flatten_ms),
+            (1.5, 0.25, 0.002, 0.0)
+        );
+        assert!((profile.total_ms() - 1.752).abs() < 1e-12);
+    }
+
+    #[test]
+    fn profiles_are_written_slowest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.csv");
+        let mut profiles = vec![profile("fast.c", 1), profile("slow.c", 9000)];
+        write_profile(&path, &mut profiles).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "source_file,bytes,functions,read_ms,parse_ms,metrics_ms,flatten_ms",
+                "slow.c,10,1,1.5,9.0,0.002,0.0",
+                "fast.c,10,1,1.5,0.001,0.002,0.0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! The projects of a monorepo, for the `project` column: the directories holding a nested
+//! git repository, a `Cargo.toml` or a `CMakeLists.txt`, every file belonging to the
+//! innermost one around it
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+use crate::tree::SourceTree;
+
+/// Entries marking the directory they're in as the root of a project. A `.git` file is
+/// the checkout of a submodule
+const MARKERS: [&str; 3] = [".git", "Cargo.toml", "CMakeLists.txt"];
+
+/// The project roots found below a walked directory
+#[derive(Debug, Default)]
+pub struct Projects {
+    root: PathBuf,
+    roots: BTreeSet<PathBuf>,
+}
+
+impl Projects {
+    /// Looks for the markers in every directory from `root` down to the directories of
+    /// `files`, in the tree when reading one
+    pub fn find(root: &Path, files: &[PathBuf], tree: Option<&dyn SourceTree>) -> Self {
+        let root = if root.is_file() {
+            root.parent().unwrap_or(Path::new("")).to_path_buf()
+        } else {
+            root.to_path_buf()
+        };
+        let mut seen = BTreeSet::new();
+        let mut roots = BTreeSet::new();
+        for file in files {
+            for dir in file.ancestors().skip(1) {
+                if !dir.starts_with(&root) || !seen.insert(dir.to_path_buf()) {
+                    break;
+                }
+                let marked = MARKERS.iter().any(|marker| match tree {
+                    Some(tree) => tree.contains(&dir.join(marker)),
+                    None => dir.join(marker).exists(),
+                });
+                if marked {
+                    roots.insert(dir.to_path_buf());
+                }
+            }
+        }
+        Self { root, roots }
+    }
+
+    /// The project `path` belongs to, named by its root relative to the walked directory
+    /// with `/` separators, `.` for the walked directory itself. None outside of any
+    pub fn project(&self, path: &Path) -> Option<String> {
+        let root = path
+            .ancestors()
+            .skip(1)
+            .find(|dir| self.roots.contains(*dir))?;
+        match root.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => Some(".".to_string()),
+            Ok(relative) => Some(paths::slashed(relative)),
+            Err(_) => None,
+        }
+    }
+
+    /// Directory below the output path the outputs of `path` go into with
+    /// `--per-project-output`, the output path itself outside of any project
+    pub fn output_dir(&self, path: &Path) -> PathBuf {
+        match self.project(path) {
+            Some(project) if project != "." => project
+                .split('/')
+                .map(|part| paths::encode_name(part.as_ref()))
+                .collect(),
+            _ => PathBuf::new(),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! The `quality` flag of every row, so consumers can filter out the rows computed from
+//! source that wasn't parsed cleanly, decoded losslessly or told apart by its extension
+
+use crate::language::{Decoding, Resolution};
+
+/// How far the metrics of a row can be trusted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quality {
+    Ok,
+    /// Tree-sitter found parse errors within the space
+    PartialParse,
+    /// Bytes of the file were invalid in its encoding and replaced
+    DecodeLossy,
+    /// The language of the file was guessed from its contents
+    GuessedLanguageLowConfidence,
+}
+
+impl Quality {
+    /// The quality of a row, the first of partial parse, lossy decoding and a guessed
+    /// language that holds for it
+    pub fn of(parse_errors: Option<usize>, decoding: Option<&Decoding>) -> Self {
+        if parse_errors.is_some_and(|errors| errors > 0) {
+            Quality::PartialParse
+        } else if decoding.is_some_and(|decoding| decoding.had_errors) {
+            Quality::DecodeLossy
+        } else if decoding.is_some_and(|decoding| decoding.choice.resolution == Resolution::Guessed)
+        {
+            Quality::GuessedLanguageLowConfidence
+        } else {
+            Quality::Ok
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Quality::Ok => "ok",
+            Quality::PartialParse => "partial_parse",
+            Quality::DecodeLossy => "decode_lossy",
+            Quality::GuessedLanguageLowConfidence => "guessed_language_low_confidence",
+        }
+    }
+}
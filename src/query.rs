@@ -0,0 +1,164 @@
+use rust_code_analysis::LANG;
+use std::fs;
+use std::path::Path;
+use tracing::{debug, warn};
+use tree_sitter::{Query, QueryCursor};
+
+use crate::errors::CliError;
+use crate::syntax::{parse, ts_language};
+
+/// User supplied tree-sitter queries, compiled for every language they are valid for
+pub struct QuerySet {
+    names: Vec<String>,
+    compiled: Vec<(LANG, Query)>,
+}
+
+/// The lines (1-based, sorted) every capture was matched on within a file
+#[derive(Debug, Default)]
+pub struct Captures {
+    pub lines: Vec<(String, Vec<usize>)>,
+}
+
+impl QuerySet {
+    /// Compiles the queries in `path` against every supported grammar. Captures starting
+    /// with `_` are treated as private helpers (e.g. for predicates) and not reported.
+    pub fn from_file(path: &Path) -> Result<Self, CliError> {
+        let source = fs::read_to_string(path)?;
+
+        let mut compiled = Vec::new();
+        let mut errors = Vec::new();
+        for lang in LANG::into_enum_iter() {
+            if let Some(language) = ts_language(&lang) {
+                match Query::new(language, &source) {
+                    Ok(query) => compiled.push((lang, query)),
+                    Err(err) => errors.push(format!("{}: {:?}", lang.get_name(), err)),
+                }
+            }
+        }
+
+        let names: Vec<String> = match compiled.first() {
+            Some((_, query)) => query
+                .capture_names()
+                .iter()
+                .filter(|name| !name.starts_with('_'))
+                .cloned()
+                .collect(),
+            None => {
+                return Err(CliError::InvalidQuery(format!(
+                    "{} ({})",
+                    path.display(),
+                    errors.join(", ")
+                )))
+            }
+        };
+
+        debug!(
+            "Compiled {} for {} languages with captures {:?}",
+            path.display(),
+            compiled.len(),
+            names
+        );
+
+        Ok(Self { names, compiled })
+    }
+
+    /// Runs the queries over `source`. Every capture is reported, with no lines when the
+    /// queries don't apply to `lang`, so all rows share the same columns.
+    pub fn captures(&self, lang: &LANG, source: &[u8]) -> Captures {
+        let mut lines: Vec<(String, Vec<usize>)> = self
+            .names
+            .iter()
+            .map(|name| (name.clone(), Vec::new()))
+            .collect();
+
+        let query = match self.compiled.iter().find(|(l, _)| l == lang) {
+            Some((_, query)) => query,
+            None => {
+                warn!("Queries are not valid for {}", lang.get_name());
+                return Captures { lines };
+            }
+        };
+        let tree = match parse(lang, source) {
+            Some(tree) => tree,
+            None => return Captures { lines },
+        };
+
+        let capture_names = query.capture_names();
+        let mut cursor = QueryCursor::new();
+        for found in cursor.matches(query, tree.root_node(), |node| &source[node.byte_range()]) {
+            for capture in found.captures {
+                let name = &capture_names[capture.index as usize];
+                if let Some((_, capture_lines)) = lines.iter_mut().find(|(n, _)| n == name) {
+                    capture_lines.push(capture.node.start_position().row + 1);
+                }
+            }
+        }
+
+        for (_, capture_lines) in lines.iter_mut() {
+            capture_lines.sort_unstable();
+        }
+
+        Captures { lines }
+    }
+}
+
+impl Captures {
+    /// Number of matches of each capture starting within the inclusive line range
+    pub fn counts(&self, start_line: usize, end_line: usize) -> Vec<(String, f64)> {
+        self.lines
+            .iter()
+            .map(|(name, lines)| {
+                let start = lines.partition_point(|line| *line < start_line);
+                let end = lines.partition_point(|line| *line <= end_line);
+                (name.clone(), end.saturating_sub(start) as f64)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn queries(source: &str) -> Result<QuerySet, CliError> {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(source.as_bytes()).unwrap();
+        QuerySet::from_file(file.path())
+    }
+
+    const SOURCE: &[u8] =
+        b"int f(int a) {\n  if (a) { g(); }\n  return h(a) + g();\n}\nint k() { return 0; }\n";
+
+    #[test]
+    fn captures_are_counted_within_the_lines_of_a_space() {
+        let queries = queries("(call_expression) @calls\n(if_statement) @_branch\n").unwrap();
+        let captures = queries.captures(&LANG::Cpp, SOURCE);
+        // Private captures are only there for predicates
+        assert_eq!(captures.lines, [("calls".to_string(), vec![2, 3, 3])]);
+        assert_eq!(captures.counts(1, 4), [("calls".to_string(), 3.0)]);
+        assert_eq!(captures.counts(3, 3), [("calls".to_string(), 2.0)]);
+        assert_eq!(captures.counts(5, 5), [("calls".to_string(), 0.0)]);
+    }
+
+    #[test]
+    fn queries_not_valid_for_a_language_report_no_matches() {
+        let queries = queries("(if_statement) @branches\n(with_statement) @withs\n").unwrap();
+        let captures = queries.captures(&LANG::Cpp, SOURCE);
+        assert_eq!(
+            captures.counts(1, 5),
+            [("branches".to_string(), 0.0), ("withs".to_string(), 0.0)]
+        );
+        let captures = queries.captures(&LANG::Python, b"if a:\n    pass\n");
+        assert_eq!(captures.counts(1, 2)[0], ("branches".to_string(), 1.0));
+    }
+
+    #[test]
+    fn queries_invalid_for_every_language_fail_to_load() {
+        assert!(matches!(
+            queries("(no_such_node) @x\n"),
+            Err(CliError::InvalidQuery(_))
+        ));
+    }
+}
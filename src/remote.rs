@@ -0,0 +1,444 @@
+//! `s3://` and `gs://` inputs and outputs. The objects of an input are read straight from
+//! the store as the run reads them, and outputs are staged in a local directory, every one
+//! uploaded in the background as soon as it is written. Objects are streamed to and from
+//! the store in chunks rather than buffered whole, many at a time. Needs the `object-store`
+//! feature, credentials come from the usual `AWS_*` and `GOOGLE_*` environment variables
+
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+use crate::errors::CliError;
+use crate::tree::SourceTree;
+
+const SCHEMES: [&str; 2] = ["s3://", "gs://"];
+
+/// Objects transferred at once
+#[cfg(feature = "object-store")]
+const CONCURRENCY: usize = 16;
+
+/// Whether `path` names an object store location rather than a local path
+pub fn is_remote(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    SCHEMES.iter().any(|scheme| path.starts_with(scheme))
+}
+
+/// The object at `url`, or every object below it, named below `url` and only fetched from
+/// the store once read
+pub fn open(url: &str) -> Result<Arc<dyn SourceTree>, CliError> {
+    #[cfg(feature = "object-store")]
+    return Ok(Arc::new(store::ObjectTree::open(url)?));
+    #[cfg(not(feature = "object-store"))]
+    Err(unsupported(url))
+}
+
+/// A local staging directory of outputs for an object store location, removed once dropped
+pub struct Staged {
+    pub url: String,
+    dir: TempDir,
+    #[cfg(feature = "object-store")]
+    uploads: store::Uploads,
+}
+
+impl Staged {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Starts uploading the finished output `file` in the background, while the run goes on
+    pub fn publish(&self, file: &Path) {
+        #[cfg(feature = "object-store")]
+        self.uploads.start(self.path(), file);
+        #[cfg(not(feature = "object-store"))]
+        let _ = file;
+    }
+}
+
+/// An empty staging directory for outputs uploaded to `url`
+pub fn staging(url: &str) -> Result<Staged, CliError> {
+    #[cfg(feature = "object-store")]
+    return store::staging(url);
+    #[cfg(not(feature = "object-store"))]
+    Err(unsupported(url))
+}
+
+/// Uploads every file in the staging directory not published yet, waiting for the uploads
+/// still running
+pub fn upload(staged: &Staged) -> Result<(), CliError> {
+    #[cfg(feature = "object-store")]
+    return staged.uploads.finish(staged.path(), &staged.url);
+    #[cfg(not(feature = "object-store"))]
+    Err(unsupported(&staged.url))
+}
+
+#[cfg(not(feature = "object-store"))]
+fn unsupported(url: &str) -> CliError {
+    CliError::InvalidArgument(format!(
+        "{} is in object storage, which needs archaeo built with the object-store feature",
+        url
+    ))
+}
+
+#[cfg(feature = "object-store")]
+mod store {
+    use futures::TryStreamExt;
+    use object_store::aws::AmazonS3Builder;
+    use object_store::buffered::BufWriter;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+    use object_store::path::Path as ObjectPath;
+    use object_store::{ObjectMeta, ObjectStore};
+    use std::collections::{BTreeMap, HashSet};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncWriteExt;
+    use tokio::runtime::Runtime;
+    use tokio::sync::Semaphore;
+    use tokio::task::JoinHandle;
+    use tracing::info;
+    use walkdir::WalkDir;
+
+    use super::{Staged, CONCURRENCY};
+    use crate::errors::CliError;
+    use crate::paths;
+    use crate::tree::SourceTree;
+
+    fn remote_error(err: impl std::fmt::Display) -> CliError {
+        CliError::RemoteError(err.to_string())
+    }
+
+    /// Connects to the bucket of `url`, returning it along with the key prefix
+    fn connect(url: &str) -> Result<(Arc<dyn ObjectStore>, String), CliError> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| CliError::InvalidArgument(format!("invalid URL {}", url)))?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        let store: Arc<dyn ObjectStore> = match scheme {
+            "s3" => Arc::new(
+                AmazonS3Builder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()
+                    .map_err(remote_error)?,
+            ),
+            "gs" => Arc::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()
+                    .map_err(remote_error)?,
+            ),
+            _ => {
+                return Err(CliError::InvalidArgument(format!(
+                    "unsupported object store {}",
+                    url
+                )))
+            }
+        };
+        Ok((store, prefix.trim_matches('/').to_string()))
+    }
+
+    fn runtime() -> Result<tokio::runtime::Runtime, CliError> {
+        Ok(tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?)
+    }
+
+    /// The objects at or below a URL, listed up front and fetched one at a time as the run
+    /// reads them, so extracting starts without waiting on a copy of the whole prefix
+    #[derive(Debug)]
+    pub struct ObjectTree {
+        /// The URL the objects are named below
+        root: String,
+        store: Arc<dyn ObjectStore>,
+        /// The key of every object, by its path below the URL with `/` separators
+        objects: BTreeMap<String, ObjectPath>,
+        runtime: Runtime,
+    }
+
+    impl ObjectTree {
+        pub fn open(url: &str) -> Result<Self, CliError> {
+            let (store, prefix) = connect(url)?;
+            Self::list(store, url, &prefix)
+        }
+
+        /// Lists the objects of `store` at or below the key `prefix`, naming them below `url`
+        pub fn list(
+            store: Arc<dyn ObjectStore>,
+            url: &str,
+            prefix: &str,
+        ) -> Result<Self, CliError> {
+            let runtime = runtime()?;
+            let location = ObjectPath::from(prefix);
+            let listed: Vec<ObjectMeta> = runtime
+                .block_on(
+                    store
+                        .list((!prefix.is_empty()).then_some(&location))
+                        .try_collect(),
+                )
+                .map_err(remote_error)?;
+
+            // A URL naming a single object is a tree of that object alone
+            let objects = if listed.is_empty() {
+                runtime
+                    .block_on(store.head(&location))
+                    .map_err(remote_error)?;
+                BTreeMap::from([(String::new(), location)])
+            } else {
+                listed
+                    .into_iter()
+                    .map(|meta| {
+                        let relative = meta
+                            .location
+                            .as_ref()
+                            .strip_prefix(prefix)
+                            .unwrap_or(meta.location.as_ref())
+                            .trim_start_matches('/')
+                            .to_string();
+                        (relative, meta.location)
+                    })
+                    .collect()
+            };
+            info!("Found {} objects at {}", objects.len(), url);
+            Ok(Self {
+                root: url.trim_end_matches('/').to_string(),
+                store,
+                objects,
+                runtime,
+            })
+        }
+    }
+
+    impl SourceTree for ObjectTree {
+        fn files(&self) -> Vec<PathBuf> {
+            self.objects
+                .keys()
+                .map(|relative| match relative.is_empty() {
+                    true => PathBuf::from(&self.root),
+                    false => PathBuf::from(format!("{}/{}", self.root, relative)),
+                })
+                .collect()
+        }
+
+        fn contains(&self, path: &Path) -> bool {
+            self.objects
+                .contains_key(&paths::slashed(self.relative(path)))
+        }
+
+        fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+            path.strip_prefix(&self.root).unwrap_or(path)
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            let key = self
+                .objects
+                .get(&paths::slashed(self.relative(path)))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not an object listed"))?;
+            let bytes = self
+                .runtime
+                .block_on(async { self.store.get(key).await?.bytes().await })
+                .map_err(io::Error::other)?;
+            Ok(bytes.to_vec())
+        }
+    }
+
+    pub fn staging(url: &str) -> Result<Staged, CliError> {
+        let (store, prefix) = connect(url)?;
+        Ok(Staged {
+            url: url.to_string(),
+            dir: tempfile::Builder::new().prefix("archaeo-").tempdir()?,
+            uploads: Uploads::new(store, prefix)?,
+        })
+    }
+
+    /// Outputs uploading in the background, at most `CONCURRENCY` of them at once
+    pub struct Uploads {
+        store: Arc<dyn ObjectStore>,
+        prefix: String,
+        runtime: Runtime,
+        slots: Arc<Semaphore>,
+        /// Files whose upload was started, so none is uploaded twice
+        started: Mutex<HashSet<PathBuf>>,
+        running: Mutex<Vec<JoinHandle<Result<(), CliError>>>>,
+    }
+
+    impl Uploads {
+        pub fn new(store: Arc<dyn ObjectStore>, prefix: String) -> Result<Self, CliError> {
+            Ok(Self {
+                store,
+                prefix,
+                runtime: runtime()?,
+                slots: Arc::new(Semaphore::new(CONCURRENCY)),
+                started: Mutex::new(HashSet::new()),
+                running: Mutex::new(Vec::new()),
+            })
+        }
+
+        /// Starts uploading `file` of the staging directory `root`, unless it already was
+        pub fn start(&self, root: &Path, file: &Path) {
+            if !self.started.lock().unwrap().insert(file.to_path_buf()) {
+                return;
+            }
+            let (store, slots) = (self.store.clone(), self.slots.clone());
+            let (local, key) = (file.to_path_buf(), key(root, file, &self.prefix));
+            let upload = self.runtime.spawn(async move {
+                let _slot = slots.acquire_owned().await.map_err(remote_error)?;
+                put(store, local, key).await
+            });
+            self.running.lock().unwrap().push(upload);
+        }
+
+        /// Uploads the files of the staging directory `root` not started yet, and waits for
+        /// every upload to `url` to finish
+        pub fn finish(&self, root: &Path, url: &str) -> Result<(), CliError> {
+            let files = WalkDir::new(root)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_file());
+            for entry in files {
+                self.start(root, entry.path());
+            }
+
+            let running = std::mem::take(&mut *self.running.lock().unwrap());
+            info!("Waiting on {} uploads to {}...", running.len(), url);
+            self.runtime.block_on(async {
+                for upload in running {
+                    upload.await.map_err(remote_error)??;
+                }
+                Ok(())
+            })
+        }
+    }
+
+    /// The key a file in the staging directory is uploaded to
+    fn key(root: &Path, file: &Path, prefix: &str) -> ObjectPath {
+        let relative = file
+            .strip_prefix(root)
+            .unwrap_or(file)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if prefix.is_empty() {
+            ObjectPath::from(relative)
+        } else {
+            ObjectPath::from(format!("{}/{}", prefix, relative))
+        }
+    }
+
+    /// Streams `local` to `key`, as a multipart upload once it outgrows a single request
+    async fn put(
+        store: Arc<dyn ObjectStore>,
+        local: PathBuf,
+        key: ObjectPath,
+    ) -> Result<(), CliError> {
+        let mut file = tokio::fs::File::open(&local).await?;
+        let mut writer = BufWriter::new(store, key);
+        tokio::io::copy(&mut file, &mut writer).await?;
+        writer.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "object-store")]
+    use std::path::PathBuf;
+
+    #[test]
+    fn only_object_store_schemes_are_remote() {
+        assert!(is_remote(Path::new("s3://bucket/corpus")));
+        assert!(is_remote(Path::new("gs://bucket")));
+        assert!(!is_remote(Path::new("corpus/s3://bucket")));
+        assert!(!is_remote(Path::new("/data/corpus")));
+    }
+
+    #[cfg(not(feature = "object-store"))]
+    #[test]
+    fn object_stores_need_the_object_store_feature() {
+        let err = staging("gs://bucket/out").err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("needs archaeo built with the object-store feature"));
+        assert!(open("s3://bucket/corpus").is_err());
+    }
+
+    #[cfg(feature = "object-store")]
+    #[test]
+    fn only_s3_and_gcs_urls_are_connected_to() {
+        assert!(matches!(
+            store::ObjectTree::open("azure://container/corpus"),
+            Err(CliError::InvalidArgument(_))
+        ));
+    }
+
+    #[cfg(feature = "object-store")]
+    fn in_memory(objects: &[(&str, &str)]) -> Arc<dyn object_store::ObjectStore> {
+        use object_store::ObjectStore;
+        let memory = object_store::memory::InMemory::new();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for (key, content) in objects {
+            let key = object_store::path::Path::from(*key);
+            runtime
+                .block_on(memory.put(&key, content.to_string().into()))
+                .unwrap();
+        }
+        Arc::new(memory)
+    }
+
+    #[cfg(feature = "object-store")]
+    #[test]
+    fn objects_are_named_below_their_url_and_read_from_the_store() {
+        let store = in_memory(&[
+            ("corpus/a.c", "int a;\n"),
+            ("corpus/src/b.c", "int b;\n"),
+            ("corpus-old/c.c", "int c;\n"),
+        ]);
+        let tree = store::ObjectTree::list(store.clone(), "s3://bucket/corpus/", "corpus").unwrap();
+        let files = tree.files();
+        assert_eq!(
+            files,
+            [
+                PathBuf::from("s3://bucket/corpus/a.c"),
+                PathBuf::from("s3://bucket/corpus/src/b.c")
+            ]
+        );
+        assert_eq!(tree.relative(&files[1]), Path::new("src/b.c"));
+        assert!(tree.contains(&files[1]));
+        assert_eq!(tree.read(&files[1]).unwrap(), b"int b;\n");
+        assert!(tree.read(Path::new("s3://bucket/corpus/c.c")).is_err());
+
+        // A URL naming one object is a tree of that object alone
+        let url = "s3://bucket/corpus/a.c";
+        let single = store::ObjectTree::list(store, url, "corpus/a.c").unwrap();
+        assert_eq!(single.files(), [PathBuf::from(url)]);
+        assert_eq!(single.read(Path::new(url)).unwrap(), b"int a;\n");
+    }
+
+    #[cfg(feature = "object-store")]
+    #[test]
+    fn outputs_are_uploaded_once_each() {
+        use object_store::ObjectStore;
+        let store = in_memory(&[]);
+        let uploads = store::Uploads::new(store.clone(), "run".to_string()).unwrap();
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        let early = dir.path().join("src").join("a.csv");
+        std::fs::write(&early, "name\na\n").unwrap();
+        uploads.start(dir.path(), &early);
+        uploads.start(dir.path(), &early);
+        std::fs::write(dir.path().join("schema.json"), "{}").unwrap();
+        uploads.finish(dir.path(), "s3://bucket/run").unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut keys: Vec<String> = runtime
+            .block_on(futures::TryStreamExt::try_collect::<Vec<_>>(
+                store.list(None),
+            ))
+            .unwrap()
+            .into_iter()
+            .map(|meta| meta.location.to_string())
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["run/schema.json", "run/src/a.csv"]);
+    }
+}
@@ -0,0 +1,98 @@
+//! Reading back the metric rows an earlier `source` run wrote out
+
+use serde_json::Value;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::commands::source::BATCHES;
+use crate::errors::{check_input, CliError};
+use crate::extract::MetricsRow;
+use crate::manifest::MANIFEST;
+use crate::output::parse_field;
+use crate::schema::SCHEMA;
+
+/// Reads every row of a run, either a single CSV, JSON or parquet output or a directory
+/// of them. Files are read in path order and unflattened JSON outputs are skipped
+pub fn read_run(path: &Path) -> Result<Vec<MetricsRow>, CliError> {
+    check_input(path)?;
+    let mut rows = Vec::new();
+    for file in run_files(path) {
+        rows.extend(read_rows(&file)?);
+    }
+    Ok(rows)
+}
+
+/// The output files of a run, in path order
+pub fn run_files(path: &Path) -> Vec<PathBuf> {
+    if path.is_file() {
+        return vec![path.to_path_buf()];
+    }
+
+    let mut files: Vec<PathBuf> = WalkDir::new(path)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            !name.starts_with('.')
+                && name != MANIFEST
+                && name != SCHEMA
+                && name != BATCHES
+                && matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("csv") | Some("json") | Some("parquet")
+                )
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Reads the rows of a single CSV, JSON or parquet output
+pub fn read_rows(path: &Path) -> Result<Vec<MetricsRow>, CliError> {
+    if path.extension().is_some_and(|ext| ext == "parquet") {
+        #[cfg(feature = "parquet")]
+        return crate::columnar::read_parquet(path);
+        #[cfg(not(feature = "parquet"))]
+        {
+            warn!(file = %path.display(), "Skipping parquet output, built without the parquet feature");
+            return Ok(Vec::new());
+        }
+    }
+
+    if path.extension().is_some_and(|ext| ext == "json") {
+        let value: Value = serde_json::from_reader(File::open(path)?)?;
+        return Ok(match value {
+            Value::Array(rows) => rows
+                .into_iter()
+                .filter_map(|row| match row {
+                    Value::Object(row) => Some(row),
+                    _ => None,
+                })
+                .collect(),
+            _ => {
+                warn!(file = %path.display(), "Skipping output that isn't flattened");
+                Vec::new()
+            }
+        });
+    }
+
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        rows.push(
+            headers
+                .iter()
+                .zip(record.iter())
+                .map(|(header, field)| (header.to_string(), parse_field(field)))
+                .collect(),
+        );
+    }
+    Ok(rows)
+}
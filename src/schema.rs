@@ -0,0 +1,243 @@
+//! The versioned schema of the rows `source` writes out, recorded in a `schema.json` next
+//! to its outputs so downstream loaders can tell when the columns changed
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::commands::source::{FlattenedMetrics, FlattenedMetricsExtended};
+use crate::errors::CliError;
+
+/// Bumped whenever a column of the rows is added, removed, renamed or reordered
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Name of the schema written to the output path
+pub const SCHEMA: &str = "schema.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Schema {
+    pub schema_version: u32,
+    pub archaeo_version: String,
+    pub extended: bool,
+    /// The columns every row starts with, in order. Annotation, query, plugin and
+    /// normalized columns follow them depending on the options of the run
+    pub columns: Vec<String>,
+    /// Which of `columns` hold numbers
+    pub numeric: Vec<String>,
+    /// The type, nullability and metric family of every one of `columns`
+    #[serde(default)]
+    pub types: Vec<ColumnInfo>,
+    /// The `--metrics` the columns were narrowed to, every metric when empty
+    #[serde(default)]
+    pub metrics: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnKind {
+    Integer,
+    Float,
+    Text,
+    Boolean,
+}
+
+/// A column of the rows, as described by `MetricSchema`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub kind: ColumnKind,
+    /// Whether the column may be null, e.g. a metric `--mask-unsupported` masks
+    pub nullable: bool,
+    /// The metric family the column belongs to, e.g. `halstead`, or none for the columns
+    /// identifying a row
+    pub family: Option<String>,
+}
+
+/// The columns a flattened row type serializes to, derived with `archaeo_macros::MetricSchema`
+pub trait MetricSchema {
+    fn columns() -> Vec<ColumnInfo>;
+}
+
+impl Schema {
+    pub fn current(extended: bool) -> Self {
+        let types = if extended {
+            FlattenedMetricsExtended::columns()
+        } else {
+            FlattenedMetrics::columns()
+        };
+        Self {
+            schema_version: SCHEMA_VERSION,
+            archaeo_version: env!("CARGO_PKG_VERSION").to_string(),
+            extended,
+            columns: types.iter().map(|column| column.name.clone()).collect(),
+            numeric: types
+                .iter()
+                .filter(|column| matches!(column.kind, ColumnKind::Integer | ColumnKind::Float))
+                .map(|column| column.name.clone())
+                .collect(),
+            types,
+            metrics: Vec::new(),
+        }
+    }
+
+    /// Narrows the metric columns to `metrics`, each a column like `cyclomatic` or a family
+    /// like `halstead`. The columns identifying a row are always kept
+    pub fn select(mut self, metrics: &[String]) -> Result<Self, CliError> {
+        if metrics.is_empty() {
+            return Ok(self);
+        }
+        let unknown: Vec<&str> = metrics
+            .iter()
+            .filter(|metric| {
+                !self.types.iter().any(|column| {
+                    column.family.is_some()
+                        && (column.name == **metric || column.family.as_ref() == Some(metric))
+                })
+            })
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            let mut families: Vec<&str> = self
+                .types
+                .iter()
+                .filter_map(|column| column.family.as_deref())
+                .collect();
+            families.dedup();
+            return Err(CliError::InvalidArgument(format!(
+                "unknown metrics {}, expected metric columns or one of the families {}",
+                unknown.join(", "),
+                families.join(", ")
+            )));
+        }
+
+        self.types.retain(|column| match &column.family {
+            Some(family) => metrics
+                .iter()
+                .any(|metric| *metric == column.name || metric == family),
+            None => true,
+        });
+        self.columns
+            .retain(|name| self.types.iter().any(|c| c.name == *name));
+        self.numeric
+            .retain(|name| self.types.iter().any(|c| c.name == *name));
+        self.metrics = metrics.to_vec();
+        Ok(self)
+    }
+
+    /// Metric columns of the full rows `select` left out
+    pub fn deselected(&self) -> HashSet<String> {
+        Self::current(self.extended)
+            .columns
+            .into_iter()
+            .filter(|column| !self.columns.contains(column))
+            .collect()
+    }
+
+    /// The numeric columns of a metric family, leaving out the ones identifying a row and
+    /// anything added to the rows beyond the schema, like annotations
+    pub fn metric_columns(&self) -> Vec<String> {
+        self.types
+            .iter()
+            .filter(|column| column.family.is_some())
+            .filter(|column| matches!(column.kind, ColumnKind::Integer | ColumnKind::Float))
+            .map(|column| column.name.clone())
+            .collect()
+    }
+
+    pub fn write(&self, directory: &Path) -> Result<PathBuf, CliError> {
+        let path = directory.join(SCHEMA);
+        serde_json::to_writer_pretty(File::create(&path)?, self)?;
+        Ok(path)
+    }
+
+    /// Reads the schema recorded in `directory`, if there is one
+    pub fn read(directory: &Path) -> Result<Option<Self>, CliError> {
+        let path = directory.join(SCHEMA);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_reader(File::open(path)?)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column<'a>(schema: &'a Schema, name: &str) -> &'a ColumnInfo {
+        schema
+            .types
+            .iter()
+            .find(|column| column.name == name)
+            .unwrap_or_else(|| panic!("no column {}", name))
+    }
+
+    #[test]
+    fn derived_columns_carry_their_kind_and_family() {
+        let schema = Schema::current(false);
+        assert_eq!(
+            schema.columns[..6],
+            ["name", "source_file", "start_line", "end_line", "kind", "parent_name"]
+        );
+        assert!(schema.types[..6].iter().all(|column| column.family.is_none()));
+        assert_eq!(
+            column(&schema, "start_line"),
+            &ColumnInfo {
+                name: "start_line".to_string(),
+                kind: ColumnKind::Integer,
+                nullable: false,
+                family: None,
+            }
+        );
+        // Metrics a language may not define are nullable, the family is the name up to
+        // its first `_` unless given
+        let family = |name: &str| column(&schema, name).family.as_deref();
+        assert!(column(&schema, "cyclomatic").nullable);
+        assert!(!column(&schema, "fan_in").nullable);
+        assert_eq!(family("halstead_N1"), Some("halstead"));
+        assert_eq!(family("fn_args"), Some("nargs"));
+        assert_eq!(family("cyclomatic_per_sloc"), Some("density"));
+        assert!(!schema.columns.iter().any(|name| name == "cyclomatic_sum"));
+        assert!(!schema.numeric.iter().any(|name| name == "kind"));
+
+        let extended = Schema::current(true);
+        assert_eq!(column(&extended, "cyclomatic_sum").family.as_deref(), Some("cyclomatic"));
+    }
+
+    #[test]
+    fn metrics_narrow_the_columns_to_their_families() {
+        let metrics = ["halstead".to_string(), "cyclomatic".to_string()];
+        let schema = Schema::current(false).select(&metrics).unwrap();
+        assert!(schema.columns.starts_with(&[
+            "name".to_string(),
+            "source_file".to_string(),
+            "start_line".to_string(),
+        ]));
+        assert!(schema.columns.iter().any(|name| name == "halstead_volume"));
+        assert!(schema.columns.iter().any(|name| name == "cyclomatic"));
+        // Columns of another family are left out, even when named after the metric
+        assert!(!schema.columns.iter().any(|name| name == "cyclomatic_per_sloc"));
+        assert_eq!(schema.columns.len(), schema.types.len());
+        assert_eq!(schema.metrics, metrics);
+
+        let deselected = schema.deselected();
+        assert!(deselected.contains("cognitive") && deselected.contains("cyclomatic_per_sloc"));
+        assert!(!deselected.contains("name") && !deselected.contains("halstead_bugs"));
+    }
+
+    #[test]
+    fn unknown_metrics_are_refused() {
+        let err = Schema::current(false)
+            .select(&["cyclomatic".to_string(), "bogus".to_string(), "name".to_string()])
+            .unwrap_err();
+        let CliError::InvalidArgument(message) = err else {
+            panic!("{:?}", err);
+        };
+        assert!(
+            message.starts_with("unknown metrics bogus, name, expected metric columns"),
+            "{}",
+            message
+        );
+    }
+}
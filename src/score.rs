@@ -0,0 +1,346 @@
+//! Composite scores over the metric columns, e.g. a tech debt score weighing complexity,
+//! size and churn, given as formulas like
+//! `0.5*normalized(cognitive) + 0.3*normalized(loc_sloc) + 0.2*churn`
+//!
+//! Formulas combine numbers and columns with `+`, `-`, `*`, `/` and parentheses. `churn`
+//! is the number of commits touching the file of a row. A column can be scaled against the
+//! whole corpus with `normalized` (0 to 1 between its minimum and maximum), `zscore` or
+//! `percentile`
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use tracing::warn;
+
+use crate::extract::MetricsRow;
+use crate::normalize::Scale;
+
+/// The virtual column counting the commits touching the file of a row
+pub const CHURN: &str = "churn";
+
+/// A named score, written to a column of its name for every function and summed over the
+/// functions of a file into `file_<name>`
+#[derive(Debug, Clone, Serialize)]
+pub struct Score {
+    pub name: String,
+    pub formula: String,
+    #[serde(skip)]
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(f64),
+    Column(String),
+    /// A column scaled by the `normalize` method of the function it's given to
+    Scaled(&'static str, String),
+    Negated(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+/// The functions scaling a column, with the `normalize` method they scale it by
+const FUNCTIONS: [(&str, &str); 3] = [
+    ("normalized", "minmax"),
+    ("zscore", "zscore"),
+    ("percentile", "percentile"),
+];
+
+impl Score {
+    /// Parses `formula` into the score `name`
+    pub fn new(name: &str, formula: &str) -> Result<Self, String> {
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!(
+                "score names may only have letters, digits and _, got {:?}",
+                name
+            ));
+        }
+        let tokens = tokenize(formula)?;
+        let mut parser = Parser { tokens, at: 0 };
+        let expr = parser.sum()?;
+        if let Some(token) = parser.tokens.get(parser.at) {
+            return Err(format!("unexpected {} in {}", token, formula));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            formula: formula.trim().to_string(),
+            expr,
+        })
+    }
+
+    /// Whether the formula refers to `churn`, which needs the history of the repository
+    pub fn uses_churn(&self) -> bool {
+        self.expr.columns().contains(CHURN)
+    }
+}
+
+/// Parses a score of the form `<name>=<formula>`
+pub fn parse_score(value: &str) -> Result<Score, String> {
+    let (name, formula) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected e.g. debt=0.5*cognitive+0.5*churn, got {}", value))?;
+    Score::new(name, formula)
+}
+
+/// Adds the columns of every score to `rows`, scaling columns against all of them. `churn`
+/// holds the commits of every file by its `source_file`. Rows lacking a column the score
+/// refers to score null
+pub fn apply(rows: &mut [&mut MetricsRow], scores: &[Score], churn: &HashMap<String, f64>) {
+    for score in scores {
+        let value_of = |row: &MetricsRow, column: &str| match column {
+            CHURN => row
+                .get("source_file")
+                .and_then(Value::as_str)
+                .map(|file| churn.get(file).copied().unwrap_or(0.0)),
+            _ => row.get(column).and_then(Value::as_f64),
+        };
+        for column in score.expr.columns() {
+            if !rows.iter().any(|row| value_of(row, column).is_some()) {
+                warn!(
+                    "Score {} refers to {}, which no row has",
+                    score.name, column
+                );
+            }
+        }
+
+        let mut scales = HashMap::new();
+        for (method, column) in score.expr.scaled() {
+            let values: Vec<f64> = rows
+                .iter()
+                .filter_map(|row| value_of(row, column))
+                .collect();
+            scales.insert((method, column.to_string()), Scale::new(&values, method));
+        }
+
+        let mut files: HashMap<String, Option<f64>> = HashMap::new();
+        for row in rows.iter_mut() {
+            let value = score.expr.eval(&|column| value_of(row, column), &scales);
+            let file = row
+                .get("source_file")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            let total = files.entry(file).or_default();
+            if let Some(value) = value {
+                *total = Some(total.unwrap_or(0.0) + value);
+            }
+            row.insert(score.name.clone(), json!(value));
+        }
+        for row in rows.iter_mut() {
+            let file = row
+                .get("source_file")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let total = files.get(file).copied().flatten();
+            row.insert(format!("file_{}", score.name), json!(total));
+        }
+    }
+}
+
+impl Expr {
+    /// The columns the expression refers to
+    fn columns(&self) -> BTreeSet<&str> {
+        let mut columns = BTreeSet::new();
+        self.visit(&mut |expr| match expr {
+            Expr::Column(column) | Expr::Scaled(_, column) => {
+                columns.insert(column.as_str());
+            }
+            _ => {}
+        });
+        columns
+    }
+
+    /// The columns scaled against the corpus, with the method scaling them
+    fn scaled(&self) -> BTreeSet<(&'static str, &str)> {
+        let mut scaled = BTreeSet::new();
+        self.visit(&mut |expr| {
+            if let Expr::Scaled(method, column) = expr {
+                scaled.insert((*method, column.as_str()));
+            }
+        });
+        scaled
+    }
+
+    fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Expr)) {
+        f(self);
+        match self {
+            Expr::Negated(expr) => expr.visit(f),
+            Expr::Binary(_, left, right) => {
+                left.visit(f);
+                right.visit(f);
+            }
+            _ => {}
+        }
+    }
+
+    /// The value of the expression for a row, None when it's lacking a column. Division by
+    /// zero is null rather than infinite
+    fn eval(
+        &self,
+        value_of: &dyn Fn(&str) -> Option<f64>,
+        scales: &HashMap<(&'static str, String), Scale>,
+    ) -> Option<f64> {
+        match self {
+            Expr::Number(number) => Some(*number),
+            Expr::Column(column) => value_of(column),
+            Expr::Scaled(method, column) => {
+                let scale = scales.get(&(*method, column.clone()))?;
+                value_of(column).map(|value| scale.apply(value))
+            }
+            Expr::Negated(expr) => expr.eval(value_of, scales).map(|value| -value),
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(value_of, scales)?;
+                let right = right.eval(value_of, scales)?;
+                match op {
+                    '+' => Some(left + right),
+                    '-' => Some(left - right),
+                    '*' => Some(left * right),
+                    _ => Some(left / right).filter(|value| value.is_finite()),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Ident(ident) => write!(f, "{}", ident),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+fn tokenize(formula: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = formula.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                number.push(c);
+                chars.next();
+            }
+            let number = number
+                .parse()
+                .map_err(|_| format!("invalid number {} in {}", number, formula))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+            {
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(ident));
+        } else if "+-*/()".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            return Err(format!("unexpected {} in {}", c, formula));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens of a formula, `*` and `/` binding tighter than `+`
+/// and `-`
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        let matches = self.tokens.get(self.at) == Some(&Token::Symbol(symbol));
+        if matches {
+            self.at += 1;
+        }
+        matches
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                '+'
+            } else if self.eat('-') {
+                '-'
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        loop {
+            let op = if self.eat('*') {
+                '*'
+            } else if self.eat('/') {
+                '/'
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.factor()?));
+        }
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Symbol('-')) => Ok(Expr::Negated(Box::new(self.factor()?))),
+            Some(Token::Symbol('(')) => {
+                let expr = self.sum()?;
+                if self.eat(')') {
+                    Ok(expr)
+                } else {
+                    Err("missing )".to_string())
+                }
+            }
+            Some(Token::Ident(ident)) if self.eat('(') => {
+                let method = FUNCTIONS
+                    .iter()
+                    .find(|(function, _)| *function == ident)
+                    .map(|(_, method)| *method)
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown function {}, expected one of normalized, zscore, percentile",
+                            ident
+                        )
+                    })?;
+                let column = match self.next() {
+                    Some(Token::Ident(column)) => column,
+                    _ => return Err(format!("{} takes a column", ident)),
+                };
+                if self.eat(')') {
+                    Ok(Expr::Scaled(method, column))
+                } else {
+                    Err(format!("{} takes a single column", ident))
+                }
+            }
+            Some(Token::Ident(column)) => Ok(Expr::Column(column)),
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("unexpected end of the formula".to_string()),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! `archaeo:ignore` comments exempting functions from the `--fail-on` rules. The markers are
+//! matched in any kind of comment: `archaeo:ignore` within a function suppresses the
+//! innermost function around it, `archaeo:ignore-next-function` the next function to start
+//! after it
+
+use rust_code_analysis::FuncSpace;
+use std::collections::HashSet;
+
+const IGNORE: &str = "archaeo:ignore";
+const IGNORE_NEXT: &str = "archaeo:ignore-next-function";
+
+/// The suppressed functions of a file, by their 1-based start and end lines
+#[derive(Debug, Clone, Default)]
+pub struct Suppressions {
+    ranges: HashSet<(usize, usize)>,
+}
+
+impl Suppressions {
+    /// Finds the markers in `source` and the functions of `root` they suppress
+    pub fn new(source: &[u8], root: &FuncSpace) -> Self {
+        let mut ignore = Vec::new();
+        let mut ignore_next = Vec::new();
+        for (index, line) in String::from_utf8_lossy(source).lines().enumerate() {
+            if line.contains(IGNORE_NEXT) {
+                ignore_next.push(index + 1);
+            } else if line.contains(IGNORE) {
+                ignore.push(index + 1);
+            }
+        }
+        if ignore.is_empty() && ignore_next.is_empty() {
+            return Self::default();
+        }
+
+        let mut spaces = Vec::new();
+        collect(&root.spaces, 0, &mut spaces);
+
+        let mut ranges = HashSet::new();
+        for line in ignore {
+            // The innermost function is the deepest one containing the line
+            let innermost = spaces
+                .iter()
+                .filter(|(start, end, _)| (*start..=*end).contains(&line))
+                .max_by_key(|(_, _, depth)| *depth);
+            if let Some((start, end, _)) = innermost {
+                ranges.insert((*start, *end));
+            }
+        }
+        for line in ignore_next {
+            let next = spaces
+                .iter()
+                .filter(|(start, _, _)| *start > line)
+                .min_by_key(|(start, _, depth)| (*start, *depth));
+            if let Some((start, end, _)) = next {
+                ranges.insert((*start, *end));
+            }
+        }
+        Self { ranges }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Whether the function spanning these lines is suppressed
+    pub fn covers(&self, start_line: usize, end_line: usize) -> bool {
+        self.ranges.contains(&(start_line, end_line))
+    }
+}
+
+/// The line range and nesting depth of every space below the file
+fn collect(spaces: &[FuncSpace], depth: usize, out: &mut Vec<(usize, usize, usize)>) {
+    for space in spaces {
+        out.push((space.start_line, space.end_line, depth));
+        collect(&space.spaces, depth + 1, out);
+    }
+}
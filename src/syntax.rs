@@ -0,0 +1,137 @@
+use rust_code_analysis::LANG;
+use tree_sitter::{Language, Node, Parser, Tree};
+
+/// Maps a `rust-code-analysis` language onto the tree-sitter grammar it is built on
+/// so the raw syntax tree can be walked without computing any metrics.
+pub fn ts_language(lang: &LANG) -> Option<Language> {
+    match lang {
+        LANG::Cpp => Some(tree_sitter_mozcpp::language()),
+        LANG::Rust => Some(tree_sitter_rust::language()),
+        LANG::Python => Some(tree_sitter_python::language()),
+        LANG::Java => Some(tree_sitter_java::language()),
+        LANG::Mozjs => Some(tree_sitter_mozjs::language()),
+        LANG::Javascript => Some(tree_sitter_javascript::language()),
+        LANG::Typescript => Some(tree_sitter_typescript::language_typescript()),
+        LANG::Tsx => Some(tree_sitter_typescript::language_tsx()),
+        _ => None,
+    }
+}
+
+/// Parses `source` into a tree-sitter syntax tree
+pub fn parse(lang: &LANG, source: &[u8]) -> Option<Tree> {
+    let mut parser = Parser::new();
+    parser.set_language(ts_language(lang)?).ok()?;
+    parser.parse(source, None)
+}
+
+/// Returns the text covered by `node`, lossily decoded
+pub fn node_text(node: &Node, source: &[u8]) -> String {
+    String::from_utf8_lossy(&source[node.byte_range()]).to_string()
+}
+
+/// Collapses all runs of whitespace (including newlines) into a single space
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Maps a syntax node onto the symbol kind it defines, if any
+pub fn symbol_kind(lang: &LANG, node: &Node) -> Option<&'static str> {
+    let has_body = node.child_by_field_name("body").is_some();
+
+    match lang {
+        LANG::Cpp => match node.kind() {
+            "function_definition" => Some("function"),
+            "class_specifier" if has_body => Some("class"),
+            "struct_specifier" if has_body => Some("struct"),
+            "union_specifier" if has_body => Some("union"),
+            "enum_specifier" if has_body => Some("enum"),
+            "namespace_definition" => Some("namespace"),
+            _ => None,
+        },
+        LANG::Rust => match node.kind() {
+            "function_item" => Some("function"),
+            "struct_item" => Some("struct"),
+            "enum_item" => Some("enum"),
+            "union_item" => Some("union"),
+            "trait_item" => Some("trait"),
+            "impl_item" => Some("impl"),
+            "mod_item" => Some("namespace"),
+            _ => None,
+        },
+        LANG::Python => match node.kind() {
+            "function_definition" => Some("function"),
+            "class_definition" => Some("class"),
+            _ => None,
+        },
+        LANG::Java => match node.kind() {
+            "method_declaration" | "constructor_declaration" => Some("function"),
+            "class_declaration" => Some("class"),
+            "interface_declaration" => Some("interface"),
+            "enum_declaration" => Some("enum"),
+            _ => None,
+        },
+        LANG::Mozjs | LANG::Javascript | LANG::Typescript | LANG::Tsx => match node.kind() {
+            "function_declaration" | "generator_function_declaration" | "method_definition" => {
+                Some("function")
+            }
+            "class_declaration" => Some("class"),
+            "interface_declaration" => Some("interface"),
+            "enum_declaration" => Some("enum"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Extracts the name of the definition at `node`, if it has one
+pub fn symbol_name(lang: &LANG, node: &Node, source: &[u8]) -> Option<String> {
+    match (lang, node.kind()) {
+        (LANG::Cpp, "function_definition") => {
+            cpp_declarator_name(node.child_by_field_name("declarator")?, source)
+        }
+        (LANG::Rust, "impl_item") => {
+            let ty = node_text(&node.child_by_field_name("type")?, source);
+            match node.child_by_field_name("trait") {
+                Some(tr) => Some(format!("{} for {}", node_text(&tr, source), ty)),
+                None => Some(ty),
+            }
+        }
+        _ => node
+            .child_by_field_name("name")
+            .map(|name| node_text(&name, source)),
+    }
+}
+
+/// Unwraps the nested C/C++ declarators (pointer, reference, function, ...) until the
+/// identifier naming the function is reached
+fn cpp_declarator_name(mut declarator: Node, source: &[u8]) -> Option<String> {
+    loop {
+        match declarator.kind() {
+            "identifier"
+            | "field_identifier"
+            | "qualified_identifier"
+            | "scoped_identifier"
+            | "destructor_name"
+            | "operator_name"
+            | "template_function" => return Some(node_text(&declarator, source)),
+            _ => {
+                declarator = match declarator.child_by_field_name("declarator") {
+                    Some(inner) => inner,
+                    None => declarator.named_child(0)?,
+                }
+            }
+        }
+    }
+}
+
+/// Everything from the start of the definition up to its body, on a single line
+pub fn signature(node: &Node, source: &[u8]) -> String {
+    let end = node
+        .child_by_field_name("body")
+        .map_or(node.end_byte(), |body| body.start_byte());
+    let text = String::from_utf8_lossy(&source[node.start_byte()..end]);
+    collapse_whitespace(&text)
+        .trim_end_matches(':')
+        .trim_end()
+        .to_string()
+}
@@ -0,0 +1,130 @@
+//! Progress metrics for long runs, exported over OTLP when built with the `telemetry`
+//! feature. Without it recording is a no-op.
+
+use clap::Args;
+
+#[derive(Args)]
+pub struct TelemetryArgs {
+    /// OTLP/HTTP endpoint to export progress metrics (files processed, failures, queue
+    /// depth) to, e.g. http://localhost:4318/v1/metrics. Requires the `telemetry` feature
+    #[arg(long, global = true)]
+    otlp_endpoint: Option<String>,
+}
+
+/// Flushes any pending metrics when dropped
+pub struct TelemetryGuard {
+    #[cfg(feature = "telemetry")]
+    provider: Option<opentelemetry_sdk::metrics::SdkMeterProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "telemetry")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                tracing::warn!("Failed to flush telemetry: {}", err);
+            }
+        }
+    }
+}
+
+impl TelemetryArgs {
+    #[cfg(feature = "telemetry")]
+    pub fn init(&self) -> TelemetryGuard {
+        let provider =
+            self.otlp_endpoint
+                .as_ref()
+                .and_then(|endpoint| match otlp::install(endpoint) {
+                    Ok(provider) => Some(provider),
+                    Err(err) => {
+                        tracing::warn!("Failed to set up telemetry export: {}", err);
+                        None
+                    }
+                });
+        TelemetryGuard { provider }
+    }
+
+    #[cfg(not(feature = "telemetry"))]
+    pub fn init(&self) -> TelemetryGuard {
+        if self.otlp_endpoint.is_some() {
+            tracing::warn!(
+                "archaeo was built without the `telemetry` feature, not exporting metrics"
+            );
+        }
+        TelemetryGuard {}
+    }
+}
+
+/// Records a file that was processed, whether or not it succeeded
+pub fn file_processed() {
+    #[cfg(feature = "telemetry")]
+    if let Some(instruments) = otlp::INSTRUMENTS.get() {
+        instruments.processed.add(1, &[]);
+    }
+}
+
+/// Records a file that failed to process
+pub fn file_failed() {
+    #[cfg(feature = "telemetry")]
+    if let Some(instruments) = otlp::INSTRUMENTS.get() {
+        instruments.failed.add(1, &[]);
+    }
+}
+
+/// Records the number of files still waiting to be processed
+pub fn queue_depth(_remaining: usize) {
+    #[cfg(feature = "telemetry")]
+    if let Some(instruments) = otlp::INSTRUMENTS.get() {
+        instruments.queue_depth.record(_remaining as u64, &[]);
+    }
+}
+
+#[cfg(feature = "telemetry")]
+mod otlp {
+    use opentelemetry::metrics::{Counter, Gauge, MeterProvider};
+    use opentelemetry_otlp::{MetricExporter, WithExportConfig};
+    use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+    use opentelemetry_sdk::Resource;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    pub struct Instruments {
+        pub processed: Counter<u64>,
+        pub failed: Counter<u64>,
+        pub queue_depth: Gauge<u64>,
+    }
+
+    pub static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+
+    pub fn install(endpoint: &str) -> Result<SdkMeterProvider, Box<dyn std::error::Error>> {
+        let exporter = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()?;
+        let reader = PeriodicReader::builder(exporter)
+            .with_interval(Duration::from_secs(10))
+            .build();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(Resource::builder().with_service_name("archaeo").build())
+            .build();
+
+        let meter = provider.meter("archaeo");
+        let _ = INSTRUMENTS.set(Instruments {
+            processed: meter
+                .u64_counter("archaeo.files.processed")
+                .with_description("Files processed, including those that failed")
+                .build(),
+            failed: meter
+                .u64_counter("archaeo.files.failed")
+                .with_description("Files that failed to process")
+                .build(),
+            queue_depth: meter
+                .u64_gauge("archaeo.queue.depth")
+                .with_description("Files still waiting to be processed")
+                .build(),
+        });
+
+        Ok(provider)
+    }
+}
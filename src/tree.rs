@@ -0,0 +1,22 @@
+//! Files read from somewhere other than the local file system, like the tree of a git
+//! revision or the objects below an object store URL
+
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The files of a tree, named below a root as if they were on disk there. Walking and
+/// reading go through the tree instead of the file system whenever there is one
+pub trait SourceTree: Debug + Send + Sync {
+    /// The files of the tree, below the root
+    fn files(&self) -> Vec<PathBuf>;
+
+    /// Whether the file `path` names below the root is in the tree
+    fn contains(&self, path: &Path) -> bool;
+
+    /// `path` relative to the root it's named below
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path;
+
+    /// Reads the file `path` names below the root
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+}
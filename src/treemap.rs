@@ -0,0 +1,414 @@
+//! Self-contained HTML and SVG treemaps, laid out with the squarified algorithm of Bruls,
+//! Huizing and van Wijk so tiles stay close to square
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::errors::CliError;
+
+const WIDTH: f64 = 1200.0;
+const HEIGHT: f64 = 800.0;
+
+/// Height of the title of an SVG treemap, and of the directory names above their tiles
+const HEADER: f64 = 16.0;
+/// Space between a directory and the tiles inside it
+const PADDING: f64 = 2.0;
+/// Height of the colour legend below an SVG treemap
+const LEGEND: f64 = 40.0;
+
+/// A tile of the treemap, its area proportional to `size` and its colour going from
+/// yellow to red as `heat` goes from 0 to 1
+#[derive(Debug, Clone)]
+pub struct Tile {
+    pub label: String,
+    pub size: f64,
+    pub heat: f64,
+    /// Shown when hovering the tile
+    pub tooltip: String,
+}
+
+/// What the colours of an SVG treemap stand for, with the values at both ends of the scale
+#[derive(Debug, Clone)]
+pub struct Legend {
+    pub label: String,
+    /// The value of a tile with a heat of 0
+    pub cool: f64,
+    /// The value of a tile with a heat of 1
+    pub hot: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+/// Writes a standalone HTML page holding a treemap of `tiles`
+pub fn write_html(writer: &mut dyn Write, title: &str, tiles: &[Tile]) -> Result<(), CliError> {
+    let mut tiles: Vec<&Tile> = tiles.iter().filter(|tile| tile.size > 0.0).collect();
+    tiles.sort_by(|a, b| b.size.total_cmp(&a.size));
+    let total: f64 = tiles.iter().map(|tile| tile.size).sum();
+    let areas: Vec<f64> = tiles
+        .iter()
+        .map(|tile| tile.size / total * WIDTH * HEIGHT)
+        .collect();
+    let rects = squarify(
+        &areas,
+        Rect {
+            x: 0.0,
+            y: 0.0,
+            w: WIDTH,
+            h: HEIGHT,
+        },
+    );
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{}</title>", escape(title))?;
+    writeln!(
+        writer,
+        "<style>body{{font-family:sans-serif}}.map{{position:relative;width:{}px;height:{}px}}\
+         .tile{{position:absolute;box-sizing:border-box;border:1px solid #fff;overflow:hidden;\
+         font-size:11px;padding:2px}}</style>",
+        WIDTH, HEIGHT
+    )?;
+    writeln!(writer, "</head>\n<body>\n<h1>{}</h1>", escape(title))?;
+    writeln!(writer, "<div class=\"map\">")?;
+    for (tile, rect) in tiles.iter().zip(&rects) {
+        let heat = tile.heat.clamp(0.0, 1.0);
+        writeln!(
+            writer,
+            "<div class=\"tile\" style=\"left:{:.1}px;top:{:.1}px;width:{:.1}px;height:{:.1}px;\
+             background:{}\" title=\"{}\">{}</div>",
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            fill(heat),
+            escape(&tile.tooltip),
+            escape(&tile.label)
+        )?;
+    }
+    writeln!(writer, "</div>\n</body>\n</html>")?;
+    Ok(())
+}
+
+/// A directory of an SVG treemap, or a file when it holds a tile
+#[derive(Default)]
+struct Node<'a> {
+    size: f64,
+    tile: Option<&'a Tile>,
+    children: BTreeMap<&'a str, Node<'a>>,
+}
+
+/// Writes a standalone SVG image holding a treemap of `tiles`, nested by directory. The
+/// labels of the tiles are their paths, separated by `/`
+pub fn write_svg(
+    writer: &mut dyn Write,
+    title: &str,
+    legend: &Legend,
+    tiles: &[Tile],
+) -> Result<(), CliError> {
+    let mut root = Node::default();
+    for tile in tiles.iter().filter(|tile| tile.size > 0.0) {
+        let mut node = &mut root;
+        node.size += tile.size;
+        for part in tile.label.split('/').filter(|part| !part.is_empty()) {
+            node = node.children.entry(part).or_default();
+            node.size += tile.size;
+        }
+        node.tile = Some(tile);
+    }
+
+    let height = HEADER * 2.0 + HEIGHT + LEGEND;
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="11">"#,
+        WIDTH, height
+    )?;
+    writeln!(
+        writer,
+        r#"<text x="0" y="{}" font-size="16" font-weight="bold">{}</text>"#,
+        HEADER + 4.0,
+        escape(title)
+    )?;
+    if root.size > 0.0 {
+        let bounds = Rect {
+            x: 0.0,
+            y: HEADER * 2.0,
+            w: WIDTH,
+            h: HEIGHT,
+        };
+        write_children(writer, &root, bounds)?;
+    }
+
+    // A gradient across the scale, from the value of the coolest tiles to the hottest
+    let top = HEADER * 2.0 + HEIGHT + 8.0;
+    writeln!(
+        writer,
+        r#"<defs><linearGradient id="heat">{}</linearGradient></defs>"#,
+        (0..=4)
+            .map(|step| {
+                let heat = step as f64 / 4.0;
+                format!(r#"<stop offset="{}" stop-color="{}"/>"#, heat, fill(heat))
+            })
+            .collect::<String>()
+    )?;
+    writeln!(
+        writer,
+        r#"<rect x="0" y="{}" width="240" height="12" fill="url(#heat)"/>"#,
+        top
+    )?;
+    writeln!(
+        writer,
+        r#"<text x="0" y="{}">{}</text><text x="240" y="{}" text-anchor="end">{}</text>"#,
+        top + 26.0,
+        number(legend.cool),
+        top + 26.0,
+        number(legend.hot)
+    )?;
+    writeln!(
+        writer,
+        r#"<text x="252" y="{}">{}</text>"#,
+        top + 11.0,
+        escape(&legend.label)
+    )?;
+    writeln!(writer, "</svg>")?;
+    Ok(())
+}
+
+/// Lays out the children of `node` within `bounds`, largest first
+fn write_children(writer: &mut dyn Write, node: &Node, bounds: Rect) -> Result<(), CliError> {
+    let mut children: Vec<(&&str, &Node)> = node.children.iter().collect();
+    children.sort_by(|a, b| b.1.size.total_cmp(&a.1.size));
+    let areas: Vec<f64> = children
+        .iter()
+        .map(|(_, child)| child.size / node.size * bounds.w * bounds.h)
+        .collect();
+    for ((name, child), rect) in children.iter().zip(squarify(&areas, bounds)) {
+        write_node(writer, name.to_string(), child, rect)?;
+    }
+    Ok(())
+}
+
+fn write_node(
+    writer: &mut dyn Write,
+    mut name: String,
+    mut node: &Node,
+    rect: Rect,
+) -> Result<(), CliError> {
+    if let (Some(tile), true) = (node.tile, node.children.is_empty()) {
+        writeln!(
+            writer,
+            r##"<g><title>{}</title><svg x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}"><rect width="100%" height="100%" fill="{}" stroke="#fff"/><text x="3" y="12">{}</text></svg></g>"##,
+            escape(&tile.tooltip),
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            fill(tile.heat.clamp(0.0, 1.0)),
+            escape(&name)
+        )?;
+        return Ok(());
+    }
+
+    // Chains of directories holding a single directory share one header
+    while let (1, None) = (node.children.len(), node.tile) {
+        let (child_name, child) = node.children.iter().next().expect("one child");
+        if child.children.is_empty() {
+            break;
+        }
+        name = format!("{}/{}", name, child_name);
+        node = child;
+    }
+    writeln!(
+        writer,
+        r##"<g><title>{}</title><svg x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}"><rect width="100%" height="100%" fill="#ddd" stroke="#fff"/><text x="3" y="12" font-weight="bold">{}/</text></svg></g>"##,
+        escape(&name),
+        rect.x,
+        rect.y,
+        rect.w,
+        rect.h,
+        escape(&name)
+    )?;
+    let inner = Rect {
+        x: rect.x + PADDING,
+        y: rect.y + HEADER,
+        w: rect.w - 2.0 * PADDING,
+        h: rect.h - HEADER - PADDING,
+    };
+    // Directories too small to hold their header are drawn as a plain tile
+    if inner.w > 0.0 && inner.h > 0.0 {
+        write_children(writer, node, inner)?;
+    }
+    Ok(())
+}
+
+/// Lays out `areas`, sorted largest first, into `bounds`. Tiles are added to a row along
+/// the shorter side of the remaining space for as long as that improves its worst aspect
+/// ratio, after which the row is fixed and the next one starts
+fn squarify(areas: &[f64], mut bounds: Rect) -> Vec<Rect> {
+    let mut rects = Vec::with_capacity(areas.len());
+    let mut start = 0;
+    while start < areas.len() {
+        let side = bounds.w.min(bounds.h);
+        let mut end = start + 1;
+        while end < areas.len()
+            && worst(&areas[start..=end], side) <= worst(&areas[start..end], side)
+        {
+            end += 1;
+        }
+
+        let row = &areas[start..end];
+        let sum: f64 = row.iter().sum();
+        if bounds.w >= bounds.h {
+            // A column along the left edge
+            let width = sum / bounds.h;
+            let mut y = bounds.y;
+            for area in row {
+                let height = area / width;
+                rects.push(Rect {
+                    x: bounds.x,
+                    y,
+                    w: width,
+                    h: height,
+                });
+                y += height;
+            }
+            bounds.x += width;
+            bounds.w -= width;
+        } else {
+            // A row along the top edge
+            let height = sum / bounds.w;
+            let mut x = bounds.x;
+            for area in row {
+                let width = area / height;
+                rects.push(Rect {
+                    x,
+                    y: bounds.y,
+                    w: width,
+                    h: height,
+                });
+                x += width;
+            }
+            bounds.y += height;
+            bounds.h -= height;
+        }
+        start = end;
+    }
+    rects
+}
+
+/// The worst aspect ratio of the tiles in `row` when laid out along `side`
+fn worst(row: &[f64], side: f64) -> f64 {
+    let sum: f64 = row.iter().sum();
+    let (min, max) = row.iter().fold((f64::MAX, f64::MIN), |(min, max), &a| {
+        (min.min(a), max.max(a))
+    });
+    let side = side * side;
+    let sum = sum * sum;
+    (side * max / sum).max(sum / (side * min))
+}
+
+/// The colour of a tile, going from yellow to red as `heat` goes from 0 to 1
+fn fill(heat: f64) -> String {
+    format!(
+        "hsl({:.0},90%,{:.0}%)",
+        60.0 - 60.0 * heat,
+        85.0 - 35.0 * heat
+    )
+}
+
+fn number(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(label: &str, size: f64) -> Tile {
+        Tile {
+            label: label.to_string(),
+            size,
+            heat: 0.5,
+            tooltip: format!("{} <{}>", label, size),
+        }
+    }
+
+    #[test]
+    fn squarified_tiles_keep_their_areas_within_the_bounds() {
+        // The example of the paper, a 6 by 4 rectangle
+        let areas = [6.0, 6.0, 4.0, 3.0, 2.0, 2.0, 1.0];
+        let bounds = Rect {
+            x: 0.0,
+            y: 0.0,
+            w: 6.0,
+            h: 4.0,
+        };
+        let rects = squarify(&areas, bounds);
+        assert_eq!(rects.len(), areas.len());
+        for (rect, area) in rects.iter().zip(areas) {
+            assert!((rect.w * rect.h - area).abs() < 1e-9, "{:?}", rect);
+            assert!(rect.x >= 0.0 && rect.x + rect.w <= 6.0 + 1e-9, "{:?}", rect);
+            assert!(rect.y >= 0.0 && rect.y + rect.h <= 4.0 + 1e-9, "{:?}", rect);
+        }
+        // The two largest share a column along the shorter side
+        assert_eq!((rects[0].x, rects[0].y, rects[0].w, rects[0].h), (0.0, 0.0, 3.0, 2.0));
+        assert_eq!((rects[1].x, rects[1].y, rects[1].w, rects[1].h), (0.0, 2.0, 3.0, 2.0));
+        assert_eq!((rects[2].x, rects[2].y), (3.0, 0.0));
+    }
+
+    #[test]
+    fn svg_tiles_nest_by_directory_and_escape_their_text() {
+        let tiles = [
+            tile("src/a/b.c", 3.0),
+            tile("src/a/c.c", 1.0),
+            tile("top.c", 2.0),
+            tile("empty.c", 0.0),
+        ];
+        let legend = Legend {
+            label: "cyclomatic <max>".to_string(),
+            cool: 1.0,
+            hot: 7.5,
+        };
+        let mut svg = Vec::new();
+        write_svg(&mut svg, "a & b", &legend, &tiles).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+
+        // A directory holding a single directory shares its header
+        assert!(svg.contains(r#"font-weight="bold">src/a/</text>"#), "{}", svg);
+        assert!(!svg.contains(">src/</text>"), "{}", svg);
+        assert_eq!(svg.matches("<g>").count(), 4);
+        assert!(svg.contains("<title>src/a/b.c &lt;3&gt;</title>"));
+        assert!(!svg.contains("empty.c"));
+        assert!(svg.contains(r#"font-weight="bold">a &amp; b</text>"#));
+        assert!(svg.contains(">cyclomatic &lt;max&gt;</text>"));
+        assert!(svg.contains(r#"text-anchor="end">7.50</text>"#));
+    }
+
+    #[test]
+    fn html_tiles_leave_out_empty_files() {
+        let mut html = Vec::new();
+        write_html(&mut html, "<run>", &[tile("a.c", 1.0), tile("b.c", 0.0)]).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("<title>&lt;run&gt;</title>"));
+        assert_eq!(html.matches("class=\"tile\"").count(), 1);
+        assert!(html.contains(
+            "left:0.0px;top:0.0px;width:1200.0px;height:800.0px;background:hsl(30,90%,68%)"
+        ));
+    }
+}
@@ -0,0 +1,537 @@
+//! Terminal explorer of the rows of a run: a sortable, filterable table of the functions,
+//! a histogram of the selected metric, and the source of a function next to its metrics
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Bar, BarChart, BarGroup, Block, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use crate::errors::CliError;
+use crate::extract::MetricsRow;
+use crate::gate::{parse_rule, Rule};
+
+/// Buckets of the histogram
+const BINS: usize = 16;
+
+const HELP: &str = "↑↓ move  ←→ column  s sort  / filter  h histogram  enter source  q quit";
+
+/// Explores `rows` until the user quits, showing the `metrics` columns in the table. Source
+/// files are read relative to `root`, unless the rows carry their source
+pub fn explore(
+    rows: Vec<MetricsRow>,
+    metrics: Vec<String>,
+    root: Option<PathBuf>,
+) -> Result<(), CliError> {
+    if !io::stdout().is_terminal() {
+        return Err(CliError::InvalidArgument(
+            "tui needs an interactive terminal".to_string(),
+        ));
+    }
+    let mut app = App::new(rows, metrics, root);
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// A filter term, either a rule like `cyclomatic>10` the rows have to break or text their
+/// name or file has to contain
+enum Term {
+    Rule(Rule),
+    Text(String),
+}
+
+impl Term {
+    fn parse(term: &str) -> Self {
+        if term.contains(['<', '>']) {
+            match parse_rule(term) {
+                Ok(rule) => Term::Rule(rule),
+                Err(_) => Term::Text(term.to_lowercase()),
+            }
+        } else {
+            Term::Text(term.to_lowercase())
+        }
+    }
+
+    fn matches(&self, row: &MetricsRow) -> bool {
+        match self {
+            Term::Rule(rule) => rule.breaks(row),
+            Term::Text(text) => ["name", "source_file"].iter().any(|column| {
+                row.get(*column)
+                    .and_then(|value| value.as_str())
+                    .is_some_and(|value| value.to_lowercase().contains(text))
+            }),
+        }
+    }
+}
+
+/// The function drilled down into
+struct Detail {
+    row: usize,
+    source: Vec<String>,
+    scroll: u16,
+}
+
+struct App {
+    rows: Vec<MetricsRow>,
+    /// Columns of the table, the name and file followed by the metrics
+    columns: Vec<String>,
+    /// The rows passing the filter, in display order
+    visible: Vec<usize>,
+    table: TableState,
+    /// The column sorted by, descending or not
+    sort: Option<(usize, bool)>,
+    filter: String,
+    /// Whether the filter is being typed
+    editing: bool,
+    histogram: bool,
+    detail: Option<Detail>,
+    root: Option<PathBuf>,
+}
+
+impl App {
+    fn new(rows: Vec<MetricsRow>, metrics: Vec<String>, root: Option<PathBuf>) -> Self {
+        let mut columns = vec!["name".to_string(), "source_file".to_string()];
+        columns.extend(metrics);
+        let mut app = Self {
+            visible: (0..rows.len()).collect(),
+            rows,
+            columns,
+            table: TableState::default()
+                .with_selected(0)
+                .with_selected_column(2),
+            sort: None,
+            filter: String::new(),
+            editing: false,
+            histogram: false,
+            detail: None,
+            root,
+        };
+        app.refresh();
+        app
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), CliError> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Press && self.handle(key.code) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reacts to a key, returning whether to quit
+    fn handle(&mut self, code: KeyCode) -> bool {
+        if self.editing {
+            match code {
+                KeyCode::Enter => self.editing = false,
+                KeyCode::Esc => {
+                    self.editing = false;
+                    self.filter.clear();
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => return false,
+            }
+            self.refresh();
+            return false;
+        }
+
+        if let Some(detail) = &mut self.detail {
+            match code {
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Backspace => self.detail = None,
+                KeyCode::Down | KeyCode::Char('j') => {
+                    detail.scroll = detail.scroll.saturating_add(1)
+                }
+                KeyCode::Up | KeyCode::Char('k') => detail.scroll = detail.scroll.saturating_sub(1),
+                KeyCode::PageDown => detail.scroll = detail.scroll.saturating_add(20),
+                KeyCode::PageUp => detail.scroll = detail.scroll.saturating_sub(20),
+                _ => {}
+            }
+            return false;
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Down | KeyCode::Char('j') => self.table.scroll_down_by(1),
+            KeyCode::Up | KeyCode::Char('k') => self.table.scroll_up_by(1),
+            KeyCode::PageDown => self.table.scroll_down_by(20),
+            KeyCode::PageUp => self.table.scroll_up_by(20),
+            KeyCode::Home => self.table.select_first(),
+            KeyCode::End => self.table.select_last(),
+            KeyCode::Right => self.table.select_next_column(),
+            KeyCode::Left => self.table.select_previous_column(),
+            KeyCode::Char('s') => {
+                let column = self.column();
+                self.sort = match self.sort {
+                    Some((sorted, descending)) if sorted == column => Some((column, !descending)),
+                    _ => Some((column, true)),
+                };
+                self.refresh();
+            }
+            KeyCode::Char('/') => self.editing = true,
+            KeyCode::Char('h') => self.histogram = !self.histogram,
+            KeyCode::Enter => {
+                if let Some(&row) = self.table.selected().and_then(|at| self.visible.get(at)) {
+                    self.detail = Some(Detail {
+                        row,
+                        source: self.source(&self.rows[row]),
+                        scroll: 0,
+                    });
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// The selected column, within the columns of the table
+    fn column(&self) -> usize {
+        self.table
+            .selected_column()
+            .unwrap_or(0)
+            .min(self.columns.len() - 1)
+    }
+
+    /// Filters and sorts the rows again
+    fn refresh(&mut self) {
+        let terms: Vec<Term> = self
+            .filter
+            .split([' ', ','])
+            .filter(|term| !term.is_empty())
+            .map(Term::parse)
+            .collect();
+        let rows = &self.rows;
+        self.visible = (0..rows.len())
+            .filter(|&at| terms.iter().all(|term| term.matches(&rows[at])))
+            .collect();
+        if let Some((column, descending)) = self.sort {
+            let column = &self.columns[column];
+            self.visible.sort_by(|&a, &b| {
+                let (a, b) = (rows[a].get(column), rows[b].get(column));
+                // Missing values stay last whichever way the column is sorted
+                if descending && a.is_some() && b.is_some() {
+                    compare(b, a)
+                } else {
+                    compare(a, b)
+                }
+            });
+        }
+        if self.visible.is_empty() {
+            self.table.select(None);
+        } else {
+            let at = self.table.selected().unwrap_or(0);
+            self.table.select(Some(at.min(self.visible.len() - 1)));
+        }
+    }
+
+    /// The lines of the function, from the row or its source file
+    fn source(&self, row: &MetricsRow) -> Vec<String> {
+        if let Some(source) = row.get("source").and_then(|value| value.as_str()) {
+            return source.lines().map(str::to_string).collect();
+        }
+        let Some(file) = row.get("source_file").and_then(|value| value.as_str()) else {
+            return vec!["The row has no source_file".to_string()];
+        };
+        let path = match &self.root {
+            Some(root) => root.join(file),
+            None => Path::new(file).to_path_buf(),
+        };
+        let text = match fs::read(&path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(err) => return vec![format!("Failed to read {}: {}", path.display(), err)],
+        };
+        let line = |column: &str| row.get(column).and_then(|value| value.as_u64());
+        let start = line("start_line").unwrap_or(1).max(1) as usize;
+        let end = line("end_line").map_or(usize::MAX, |end| end as usize);
+        text.lines()
+            .enumerate()
+            .skip(start - 1)
+            .take(end.saturating_sub(start) + 1)
+            .map(|(index, line)| format!("{:>6} {}", index + 1, line))
+            .collect()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, footer] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let footer_text = match (self.editing, &self.detail) {
+            (true, _) => format!("/{}▏ (enter apply, esc clear)", self.filter),
+            (false, Some(_)) => "↑↓ scroll  esc back".to_string(),
+            (false, None) => HELP.to_string(),
+        };
+        frame.render_widget(Paragraph::new(footer_text).reversed(), footer);
+
+        if let Some(detail) = &self.detail {
+            self.draw_detail(frame, body, detail);
+            return;
+        }
+        if self.histogram {
+            let [table, histogram] =
+                Layout::vertical([Constraint::Min(5), Constraint::Length(14)]).areas(body);
+            self.draw_table(frame, table);
+            self.draw_histogram(frame, histogram);
+        } else {
+            self.draw_table(frame, body);
+        }
+    }
+
+    fn draw_table(&mut self, frame: &mut Frame, area: Rect) {
+        let header = Row::new(self.columns.iter().enumerate().map(|(at, column)| {
+            let arrow = match self.sort {
+                Some((sorted, true)) if sorted == at => " ▼",
+                Some((sorted, false)) if sorted == at => " ▲",
+                _ => "",
+            };
+            Cell::from(format!("{}{}", column, arrow))
+        }))
+        .bold();
+        let rows = self.visible.iter().map(|&at| {
+            let row = &self.rows[at];
+            Row::new(self.columns.iter().map(|column| match column.as_str() {
+                "source_file" => {
+                    let file = row.get(column).and_then(|v| v.as_str()).unwrap_or("");
+                    let line = row.get("start_line").map(display).unwrap_or_default();
+                    Cell::from(format!("{}:{}", file, line))
+                }
+                _ => Cell::from(row.get(column).map(display).unwrap_or_default()),
+            }))
+        });
+        let mut widths = vec![Constraint::Fill(2), Constraint::Fill(3)];
+        widths.extend(
+            self.columns[2..]
+                .iter()
+                .map(|column| Constraint::Length(column.chars().count().clamp(6, 16) as u16 + 2)),
+        );
+
+        let title = format!(
+            " {} of {} functions{} ",
+            self.visible.len(),
+            self.rows.len(),
+            if self.filter.is_empty() {
+                String::new()
+            } else {
+                format!(" matching {}", self.filter)
+            }
+        );
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .column_highlight_style(Style::new().add_modifier(Modifier::BOLD).underlined())
+            .cell_highlight_style(Style::new().add_modifier(Modifier::BOLD));
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn draw_histogram(&self, frame: &mut Frame, area: Rect) {
+        let column = &self.columns[self.column()];
+        let values: Vec<f64> = self
+            .visible
+            .iter()
+            .filter_map(|&at| self.rows[at].get(column).and_then(|value| value.as_f64()))
+            .filter(|value| value.is_finite())
+            .collect();
+        let block = Block::bordered().title(format!(" {} ", column));
+        if values.is_empty() {
+            let text = format!("{} holds no numbers", column);
+            frame.render_widget(Paragraph::new(text).block(block), area);
+            return;
+        }
+
+        let (low, high) = values.iter().fold((f64::MAX, f64::MIN), |(low, high), &v| {
+            (low.min(v), high.max(v))
+        });
+        let width = if high > low {
+            (high - low) / BINS as f64
+        } else {
+            1.0
+        };
+        let mut counts = [0u64; BINS];
+        for value in &values {
+            let bin = ((value - low) / width) as usize;
+            counts[bin.min(BINS - 1)] += 1;
+        }
+        let bars: Vec<Bar> = counts
+            .iter()
+            .enumerate()
+            .map(|(bin, count)| {
+                Bar::default()
+                    .value(*count)
+                    .label(Line::from(short(low + bin as f64 * width)))
+            })
+            .collect();
+        let bar_width = (area.width.saturating_sub(2) / BINS as u16).saturating_sub(1);
+        let chart = BarChart::default()
+            .block(block)
+            .data(BarGroup::default().bars(&bars))
+            .bar_width(bar_width.max(1))
+            .bar_gap(1);
+        frame.render_widget(chart, area);
+    }
+
+    fn draw_detail(&self, frame: &mut Frame, area: Rect, detail: &Detail) {
+        let row = &self.rows[detail.row];
+        let [source, metrics] =
+            Layout::horizontal([Constraint::Min(40), Constraint::Length(44)]).areas(area);
+
+        let name = row.get("name").map(display).unwrap_or_default();
+        let lines: Vec<Line> = detail
+            .source
+            .iter()
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::bordered().title(format!(" {} ", name)))
+                .scroll((detail.scroll, 0)),
+            source,
+        );
+
+        let rows = row
+            .iter()
+            .filter(|(column, _)| *column != "source")
+            .map(|(column, value)| {
+                Row::new(vec![
+                    Cell::from(Span::raw(column.as_str()).bold()),
+                    Cell::from(display(value)),
+                ])
+            });
+        let table = Table::new(rows, [Constraint::Length(26), Constraint::Fill(1)])
+            .block(Block::bordered().title(" metrics "));
+        frame.render_widget(table, metrics);
+    }
+}
+
+/// Orders numbers numerically and anything else as text, with missing values last
+fn compare(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => display(a).cmp(&display(b)),
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if number.is_f64() => short(float),
+            _ => number.to_string(),
+        },
+        other => other.to_string(),
+    }
+}
+
+/// A number with at most two decimals
+fn short(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn app(rows: Value) -> App {
+        let rows: Vec<MetricsRow> = serde_json::from_value(rows).unwrap();
+        App::new(rows, vec!["cyclomatic".to_string()], None)
+    }
+
+    fn names(app: &App) -> Vec<&str> {
+        app.visible
+            .iter()
+            .map(|&at| app.rows[at]["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn filters_match_rules_or_the_name_and_file() {
+        let mut app = app(json!([
+            { "name": "parse", "source_file": "src/a.c", "cyclomatic": 12 },
+            { "name": "Print", "source_file": "src/b.c", "cyclomatic": 3 },
+            { "name": "main", "source_file": "tools/parser.c", "cyclomatic": 20 },
+        ]));
+        for c in "/PAR".chars() {
+            app.handle(KeyCode::Char(c));
+        }
+        assert_eq!(names(&app), ["parse", "main"]);
+
+        app.handle(KeyCode::Backspace);
+        for c in " cyclomatic>10".chars() {
+            app.handle(KeyCode::Char(c));
+        }
+        app.handle(KeyCode::Enter);
+        assert_eq!(names(&app), ["parse", "main"]);
+        assert!(!app.editing);
+
+        // Leaving the filter clears it
+        app.handle(KeyCode::Char('/'));
+        app.handle(KeyCode::Esc);
+        assert_eq!(names(&app), ["parse", "Print", "main"]);
+
+        app.handle(KeyCode::Char('/'));
+        app.handle(KeyCode::Char('z'));
+        assert!(names(&app).is_empty());
+        assert_eq!(app.table.selected(), None);
+    }
+
+    #[test]
+    fn sorting_toggles_the_direction_and_keeps_missing_values_last() {
+        let mut app = app(json!([
+            { "name": "a", "cyclomatic": 2 },
+            { "name": "b" },
+            { "name": "c", "cyclomatic": 10 },
+            { "name": "d", "cyclomatic": 1.5 },
+        ]));
+        app.handle(KeyCode::Char('s'));
+        assert_eq!(names(&app), ["c", "a", "d", "b"]);
+        app.handle(KeyCode::Char('s'));
+        assert_eq!(names(&app), ["d", "a", "c", "b"]);
+
+        app.handle(KeyCode::Left);
+        app.handle(KeyCode::Left);
+        app.handle(KeyCode::Char('s'));
+        assert_eq!(names(&app), ["d", "c", "b", "a"]);
+    }
+
+    #[test]
+    fn sources_are_the_lines_of_the_function() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("a.c"), "int x;\nint f() {\n  return 0;\n}\n").unwrap();
+        let app = App::new(Vec::new(), Vec::new(), Some(root.path().to_path_buf()));
+        let row = |row: Value| -> MetricsRow { serde_json::from_value(row).unwrap() };
+
+        assert_eq!(
+            app.source(&row(json!({ "source_file": "a.c", "start_line": 2, "end_line": 4 }))),
+            ["     2 int f() {", "     3   return 0;", "     4 }"]
+        );
+        assert_eq!(
+            app.source(&row(json!({ "source": "int g() {}\n", "source_file": "b.c" }))),
+            ["int g() {}"]
+        );
+        assert_eq!(app.source(&row(json!({}))), ["The row has no source_file"]);
+        assert!(app.source(&row(json!({ "source_file": "missing.c" })))[0]
+            .starts_with("Failed to read"));
+    }
+}
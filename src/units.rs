@@ -0,0 +1,96 @@
+//! Grouping C/C++ headers with the source files implementing them into translation units
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::paths::has_extension;
+
+const HEADERS: [&str; 5] = ["h", "hh", "hpp", "hxx", "inl"];
+const SOURCES: [&str; 6] = ["c", "cc", "cpp", "cxx", "m", "mm"];
+
+/// The translation unit of every file, named after its source file. A header joins the
+/// source sharing its stem, from the same directory if there is one there or the only one
+/// in the corpus otherwise (e.g. `include/foo.h` and `src/foo.cpp`). Anything else is its
+/// own unit
+pub struct TranslationUnits {
+    units: HashMap<String, String>,
+}
+
+impl TranslationUnits {
+    pub fn new(filepaths: &[PathBuf]) -> Self {
+        let mut sources: HashMap<String, Vec<&PathBuf>> = HashMap::new();
+        for path in filepaths
+            .iter()
+            .filter(|path| has_extension(path, &SOURCES))
+        {
+            sources.entry(stem(path)).or_default().push(path);
+        }
+
+        let units = filepaths
+            .iter()
+            .map(|path| {
+                let unit = match sources.get(&stem(path)) {
+                    Some(candidates) if has_extension(path, &HEADERS) => candidates
+                        .iter()
+                        .find(|source| source.parent() == path.parent())
+                        .or_else(|| (candidates.len() == 1).then(|| &candidates[0]))
+                        .copied()
+                        .unwrap_or(path),
+                    _ => path,
+                };
+                (
+                    path.to_string_lossy().to_string(),
+                    unit.to_string_lossy().to_string(),
+                )
+            })
+            .collect();
+
+        Self { units }
+    }
+
+    pub fn unit<'a>(&'a self, source_file: &'a str) -> &'a str {
+        self.units
+            .get(source_file)
+            .map_or(source_file, String::as_str)
+    }
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(files: &[&str]) -> TranslationUnits {
+        TranslationUnits::new(&files.iter().map(PathBuf::from).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn headers_join_the_source_sharing_their_stem() {
+        let units = units(&["src/foo.cpp", "src/foo.h", "include/bar.hpp", "lib/bar.cc"]);
+        assert_eq!(units.unit("src/foo.h"), "src/foo.cpp");
+        assert_eq!(units.unit("src/foo.cpp"), "src/foo.cpp");
+        // The only source of that stem anywhere in the corpus
+        assert_eq!(units.unit("include/bar.hpp"), "lib/bar.cc");
+    }
+
+    #[test]
+    fn headers_with_several_sources_elsewhere_are_their_own_unit() {
+        let units = units(&["a/util.c", "b/util.c", "include/util.h", "a/util.h"]);
+        assert_eq!(units.unit("include/util.h"), "include/util.h");
+        // One in the same directory still wins
+        assert_eq!(units.unit("a/util.h"), "a/util.c");
+    }
+
+    #[test]
+    fn everything_else_is_its_own_unit() {
+        let units = units(&["foo.c", "foo.rs", "foo.txt"]);
+        assert_eq!(units.unit("foo.rs"), "foo.rs");
+        assert_eq!(units.unit("foo.txt"), "foo.txt");
+        assert_eq!(units.unit("unknown.h"), "unknown.h");
+    }
+}
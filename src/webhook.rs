@@ -0,0 +1,560 @@
+//! Merge request events of GitHub and GitLab, for the `/webhook` endpoint of `serve`. The
+//! diff of every opened or updated merge request is fetched from the forge, assessed, and
+//! commented on. Needs the `webhook` feature, tokens come from the `GITHUB_TOKEN` and
+//! `GITLAB_TOKEN` environment variables. Events need to be signed with the secret of
+//! `ARCHAEO_WEBHOOK_SECRET`, and the APIs called are never taken from them
+
+use hmac::{Hmac, KeyInit, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+use crate::errors::CliError;
+use crate::impact::HunkImpact;
+
+/// Environment variable holding the secret events are signed with. Events are refused
+/// when it is not set
+pub const SECRET_VAR: &str = "ARCHAEO_WEBHOOK_SECRET";
+
+/// Environment variable overriding the API of GitHub, e.g. for GitHub Enterprise Server
+pub const GITHUB_API_VAR: &str = "GITHUB_API_URL";
+
+/// Environment variable overriding the API of GitLab, e.g. `https://gitlab.example.com/api/v4`
+pub const GITLAB_API_VAR: &str = "GITLAB_API_URL";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+}
+
+impl Forge {
+    /// Environment variable holding the token the API is called with
+    pub fn token_var(self) -> &'static str {
+        match self {
+            Forge::GitHub => "GITHUB_TOKEN",
+            Forge::GitLab => "GITLAB_TOKEN",
+        }
+    }
+
+    /// Base URL of the API, from its environment variable or the public instance
+    fn api(self) -> String {
+        let (var, default) = match self {
+            Forge::GitHub => (GITHUB_API_VAR, "https://api.github.com"),
+            Forge::GitLab => (GITLAB_API_VAR, "https://gitlab.com/api/v4"),
+        };
+        std::env::var(var)
+            .unwrap_or_else(|_| default.to_string())
+            .trim_end_matches('/')
+            .to_string()
+    }
+}
+
+/// The merge request an event is about, with the API endpoints reaching it, built from the
+/// configured API rather than the URLs in the event
+#[derive(Debug, Clone)]
+pub struct MergeRequest {
+    pub forge: Forge,
+    /// `owner/repo` on GitHub, the path with its namespace on GitLab
+    pub name: String,
+    pub number: u64,
+    /// URL the diff is fetched from
+    pub diff_url: String,
+    /// URL comments are posted to
+    pub comments_url: String,
+}
+
+/// Actions of GitHub `pull_request` events changing the diff
+const GITHUB_ACTIONS: [&str; 3] = ["opened", "reopened", "synchronize"];
+
+/// Actions of GitLab merge request events changing the diff. `update` only does when it
+/// comes with an `oldrev`, rather than for edits of the title or labels
+const GITLAB_ACTIONS: [&str; 3] = ["open", "reopen", "update"];
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(field, _)| field.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Whether an event was sent by a forge knowing `secret`: GitHub signs the body with it in
+/// `X-Hub-Signature-256`, GitLab sends it as is in `X-Gitlab-Token`
+pub fn verify(headers: &[(String, String)], body: &str, secret: &str) -> bool {
+    if let Some(signature) = header(headers, "X-Hub-Signature-256") {
+        let Some(signature) = signature.strip_prefix("sha256=").and_then(unhex) else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+            return false;
+        };
+        mac.update(body.as_bytes());
+        return mac.verify_slice(&signature).is_ok();
+    }
+    header(headers, "X-Gitlab-Token").is_some_and(|token| same(token.as_bytes(), secret.as_bytes()))
+}
+
+/// Compares secrets in a time independent of where they differ
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// The bytes of the hex digits of `text`
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+/// The merge request an event opened or updated. None for every other event, e.g. the
+/// ping GitHub sends when the webhook is added, which is acknowledged and ignored
+pub fn parse_event(
+    headers: &[(String, String)],
+    body: &str,
+) -> Result<Option<MergeRequest>, CliError> {
+    let invalid = |what: &str| CliError::InvalidArgument(format!("event without {}", what));
+    let forge = match (
+        header(headers, "X-GitHub-Event"),
+        header(headers, "X-Gitlab-Event"),
+    ) {
+        (Some("pull_request"), _) => Forge::GitHub,
+        (_, Some("Merge Request Hook")) => Forge::GitLab,
+        (Some(_), _) | (_, Some(_)) => return Ok(None),
+        (None, None) => {
+            return Err(CliError::InvalidArgument(
+                "expected an X-GitHub-Event or X-Gitlab-Event header".to_string(),
+            ))
+        }
+    };
+    let event: Value = serde_json::from_str(body)
+        .map_err(|err| CliError::InvalidArgument(format!("invalid event: {}", err)))?;
+    let text = |pointer: &str| {
+        event
+            .pointer(pointer)
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid(pointer))
+    };
+    let number = |pointer: &str| {
+        event
+            .pointer(pointer)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| invalid(pointer))
+    };
+
+    match forge {
+        Forge::GitHub => {
+            if !GITHUB_ACTIONS.contains(&text("/action")?) {
+                return Ok(None);
+            }
+            let name = text("/repository/full_name")?;
+            if !repository_name(name) {
+                return Err(CliError::InvalidArgument(format!(
+                    "invalid repository name {}",
+                    name
+                )));
+            }
+            let number = number("/pull_request/number")?;
+            let repository = format!("{}/repos/{}", forge.api(), name);
+            Ok(Some(MergeRequest {
+                forge,
+                name: name.to_string(),
+                number,
+                diff_url: format!("{}/pulls/{}", repository, number),
+                comments_url: format!("{}/issues/{}/comments", repository, number),
+            }))
+        }
+        Forge::GitLab => {
+            let action = text("/object_attributes/action")?;
+            let pushed = event.pointer("/object_attributes/oldrev").is_some();
+            if !GITLAB_ACTIONS.contains(&action) || (action == "update" && !pushed) {
+                return Ok(None);
+            }
+            let project = number("/project/id")?;
+            let iid = number("/object_attributes/iid")?;
+            let merge_request = format!(
+                "{}/projects/{}/merge_requests/{}",
+                forge.api(),
+                project,
+                iid
+            );
+            Ok(Some(MergeRequest {
+                forge,
+                name: text("/project/path_with_namespace")?.to_string(),
+                number: iid,
+                diff_url: format!("{}/changes", merge_request),
+                comments_url: format!("{}/notes", merge_request),
+            }))
+        }
+    }
+}
+
+/// Whether `name` is an `owner/repo` of GitHub, which can't reach other endpoints of the
+/// API when put in a path
+fn repository_name(name: &str) -> bool {
+    let mut parts = name.split('/');
+    let part = |part: Option<&str>| {
+        part.is_some_and(|part| {
+            !part.is_empty()
+                && !part.starts_with('.')
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        })
+    };
+    part(parts.next()) && part(parts.next()) && parts.next().is_none()
+}
+
+/// The unified diff of the `changes` GitLab lists for a merge request, whose diffs lack the
+/// headers naming their files
+pub fn gitlab_diff(changes: &Value) -> String {
+    let mut diff = String::new();
+    for change in changes["changes"].as_array().into_iter().flatten() {
+        let path = |key: &str| change[key].as_str().unwrap_or_default();
+        let flag = |key: &str| change[key].as_bool().unwrap_or(false);
+        let old = if flag("new_file") {
+            "/dev/null".to_string()
+        } else {
+            format!("a/{}", path("old_path"))
+        };
+        let new = if flag("deleted_file") {
+            "/dev/null".to_string()
+        } else {
+            format!("b/{}", path("new_path"))
+        };
+        let hunks = path("diff");
+        let _ = writeln!(diff, "--- {}\n+++ {}", old, new);
+        diff.push_str(hunks);
+        if !hunks.ends_with('\n') {
+            diff.push('\n');
+        }
+    }
+    diff
+}
+
+/// The markdown comment on the functions the hunks of a merge request touch, listing what
+/// the change does to their metrics
+pub fn comment(hunks: &[HunkImpact], metrics: &[String]) -> String {
+    let mut seen = BTreeSet::new();
+    let mut lines = Vec::new();
+    for hunk in hunks {
+        let path = hunk
+            .new_path
+            .as_ref()
+            .or(hunk.old_path.as_ref())
+            .map_or("", String::as_str);
+        for function in &hunk.functions {
+            for message in &function.messages {
+                if seen.insert((path, &function.name, message)) {
+                    lines.push(format!(
+                        "| `{}` | `{}` | {} |",
+                        path, function.name, message
+                    ));
+                }
+            }
+        }
+    }
+
+    let functions: BTreeSet<_> = hunks
+        .iter()
+        .flat_map(|hunk| {
+            hunk.functions
+                .iter()
+                .map(move |function| (&hunk.new_path, &function.name))
+        })
+        .collect();
+    let mut comment = "### archaeo complexity impact\n\n".to_string();
+    if lines.is_empty() {
+        let _ = writeln!(
+            comment,
+            "The {} functions this change touches keep their {}.",
+            functions.len(),
+            metrics.join(", ")
+        );
+    } else {
+        let _ = writeln!(
+            comment,
+            "This change touches {} functions.\n\n| File | Function | Impact |\n|---|---|---|",
+            functions.len()
+        );
+        for line in lines {
+            let _ = writeln!(comment, "{}", line);
+        }
+    }
+    comment
+}
+
+/// Fetches the unified diff of a merge request
+#[cfg_attr(not(feature = "webhook"), allow(unused_variables))]
+pub fn fetch_diff(request: &MergeRequest) -> Result<String, CliError> {
+    #[cfg(feature = "webhook")]
+    return client::fetch_diff(request);
+    #[cfg(not(feature = "webhook"))]
+    Err(unsupported())
+}
+
+/// Posts a comment of markdown `body` on a merge request
+#[cfg_attr(not(feature = "webhook"), allow(unused_variables))]
+pub fn post_comment(request: &MergeRequest, body: &str) -> Result<(), CliError> {
+    #[cfg(feature = "webhook")]
+    return client::post_comment(request, body);
+    #[cfg(not(feature = "webhook"))]
+    Err(unsupported())
+}
+
+/// Fails unless archaeo is built with the `webhook` feature, checked before an event is
+/// acknowledged
+pub fn supported() -> Result<(), CliError> {
+    if cfg!(feature = "webhook") {
+        Ok(())
+    } else {
+        Err(unsupported())
+    }
+}
+
+fn unsupported() -> CliError {
+    CliError::InvalidArgument(
+        "merge request webhooks need archaeo built with the webhook feature".to_string(),
+    )
+}
+
+#[cfg(feature = "webhook")]
+mod client {
+    use reqwest::blocking::{Client, RequestBuilder};
+    use serde_json::{json, Value};
+
+    use super::{gitlab_diff, Forge, MergeRequest};
+    use crate::errors::CliError;
+
+    fn failed(err: reqwest::Error) -> CliError {
+        CliError::WebhookError(err.to_string())
+    }
+
+    /// A request to the API of the forge, authorized with the token from its environment
+    /// variable
+    fn call(
+        request: &MergeRequest,
+        method: reqwest::Method,
+        url: &str,
+    ) -> Result<RequestBuilder, CliError> {
+        let var = request.forge.token_var();
+        let token = std::env::var(var)
+            .map_err(|_| CliError::WebhookError(format!("{} is not set", var)))?;
+        let client = Client::builder()
+            .user_agent(concat!("archaeo/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(failed)?;
+        let builder = client.request(method, url);
+        Ok(match request.forge {
+            Forge::GitHub => builder
+                .bearer_auth(token)
+                .header("X-GitHub-Api-Version", "2022-11-28"),
+            Forge::GitLab => builder.header("PRIVATE-TOKEN", token),
+        })
+    }
+
+    pub fn fetch_diff(request: &MergeRequest) -> Result<String, CliError> {
+        let builder = call(request, reqwest::Method::GET, &request.diff_url)?;
+        match request.forge {
+            Forge::GitHub => builder
+                .header("Accept", "application/vnd.github.diff")
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|response| response.text())
+                .map_err(failed),
+            Forge::GitLab => {
+                let changes: Value = builder
+                    .send()
+                    .and_then(|response| response.error_for_status())
+                    .and_then(|response| response.json())
+                    .map_err(failed)?;
+                Ok(gitlab_diff(&changes))
+            }
+        }
+    }
+
+    pub fn post_comment(request: &MergeRequest, body: &str) -> Result<(), CliError> {
+        call(request, reqwest::Method::POST, &request.comments_url)?
+            .json(&json!({ "body": body }))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(failed)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact::{FunctionImpact, Status};
+    use std::collections::BTreeMap;
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn github_signatures_are_checked_against_the_body() {
+        // The example of the GitHub documentation on validating webhook deliveries
+        let signed = headers(&[(
+            "X-Hub-Signature-256",
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+        )]);
+        let secret = "It's a Secret to Everybody";
+        assert!(verify(&signed, "Hello, World!", secret));
+        assert!(!verify(&signed, "Hello, World?", secret));
+        assert!(!verify(&signed, "Hello, World!", "another secret"));
+        assert!(!verify(
+            &headers(&[("X-Hub-Signature-256", "sha256=75710")]),
+            "Hello, World!",
+            secret
+        ));
+        assert!(!verify(&[], "Hello, World!", secret));
+    }
+
+    #[test]
+    fn gitlab_tokens_are_compared_with_the_secret() {
+        assert!(verify(
+            &headers(&[("X-Gitlab-Token", "s3cret")]),
+            "{}",
+            "s3cret"
+        ));
+        assert!(!verify(
+            &headers(&[("X-Gitlab-Token", "s3cre")]),
+            "{}",
+            "s3cret"
+        ));
+    }
+
+    #[test]
+    fn github_events_reach_the_api_not_the_urls_they_name() {
+        let github = headers(&[("X-GitHub-Event", "pull_request")]);
+        let event = |action: &str, name: &str| {
+            serde_json::json!({
+                "action": action,
+                "repository": { "full_name": name },
+                "pull_request": {
+                    "number": 7,
+                    "url": "https://attacker.example/diff",
+                    "comments_url": "https://attacker.example/comments"
+                }
+            })
+            .to_string()
+        };
+        let request = parse_event(&github, &event("synchronize", "octo/app"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.forge, Forge::GitHub);
+        assert_eq!((request.name.as_str(), request.number), ("octo/app", 7));
+        assert!(request.diff_url.ends_with("/repos/octo/app/pulls/7"));
+        assert!(request
+            .comments_url
+            .ends_with("/repos/octo/app/issues/7/comments"));
+        assert!(!request.diff_url.contains("attacker"));
+
+        assert!(parse_event(&github, &event("closed", "octo/app"))
+            .unwrap()
+            .is_none());
+        assert!(parse_event(&github, &event("opened", "octo/../../user")).is_err());
+        let ping = headers(&[("X-GitHub-Event", "ping")]);
+        assert!(parse_event(&ping, "{}").unwrap().is_none());
+        assert!(parse_event(&[], "{}").is_err());
+    }
+
+    #[test]
+    fn gitlab_events_changing_the_diff_are_reviewed() {
+        let gitlab = headers(&[("X-Gitlab-Event", "Merge Request Hook")]);
+        let event = |attributes: Value| {
+            serde_json::json!({
+                "project": {
+                    "id": 42,
+                    "path_with_namespace": "group/app",
+                    "web_url": "https://attacker.example/group/app"
+                },
+                "object_attributes": attributes
+            })
+            .to_string()
+        };
+        let request = parse_event(
+            &gitlab,
+            &event(serde_json::json!({ "action": "open", "iid": 3 })),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.forge, Forge::GitLab);
+        assert_eq!((request.name.as_str(), request.number), ("group/app", 3));
+        assert!(request
+            .diff_url
+            .ends_with("/projects/42/merge_requests/3/changes"));
+        assert!(request
+            .comments_url
+            .ends_with("/projects/42/merge_requests/3/notes"));
+        assert!(!request.diff_url.contains("attacker"));
+
+        let edited = event(serde_json::json!({ "action": "update", "iid": 3 }));
+        assert!(parse_event(&gitlab, &edited).unwrap().is_none());
+        let pushed = event(serde_json::json!({ "action": "update", "iid": 3, "oldrev": "abc" }));
+        assert!(parse_event(&gitlab, &pushed).unwrap().is_some());
+        let merged = event(serde_json::json!({ "action": "merge", "iid": 3 }));
+        assert!(parse_event(&gitlab, &merged).unwrap().is_none());
+    }
+
+    #[test]
+    fn comments_list_the_impact_on_every_function_once() {
+        let function = |name: &str, messages: &[&str]| FunctionImpact {
+            name: name.to_string(),
+            status: Status::Changed,
+            base_lines: Some((1, 3)),
+            head_lines: Some((1, 6)),
+            metrics: BTreeMap::new(),
+            messages: messages.iter().map(|message| message.to_string()).collect(),
+        };
+        let hunk = |functions: Vec<FunctionImpact>| HunkImpact {
+            old_path: Some("src/a.c".to_string()),
+            new_path: Some("src/a.c".to_string()),
+            old_start: 1,
+            old_count: 3,
+            new_start: 1,
+            new_count: 6,
+            functions,
+        };
+        let metrics = ["cyclomatic".to_string()];
+        let raised = "raises cyclomatic of f from 1 → 2";
+        let hunks = [
+            hunk(vec![function("f", &[raised]), function("g", &[])]),
+            hunk(vec![function("f", &[raised])]),
+        ];
+        assert_eq!(
+            comment(&hunks, &metrics),
+            concat!(
+                "### archaeo complexity impact\n\n",
+                "This change touches 2 functions.\n\n",
+                "| File | Function | Impact |\n|---|---|---|\n",
+                "| `src/a.c` | `f` | raises cyclomatic of f from 1 → 2 |\n",
+            )
+        );
+        assert_eq!(
+            comment(&[hunk(vec![function("g", &[])])], &metrics),
+            "### archaeo complexity impact\n\nThe 1 functions this change touches keep their cyclomatic.\n"
+        );
+    }
+
+    #[test]
+    fn gitlab_changes_become_a_unified_diff() {
+        let changes = serde_json::json!({ "changes": [
+            { "old_path": "a.c", "new_path": "a.c", "diff": "@@ -1 +1 @@\n-a\n+b" },
+            { "old_path": "b.c", "new_path": "b.c", "new_file": true, "diff": "@@ -0,0 +1 @@\n+c\n" }
+        ]});
+        assert_eq!(
+            gitlab_diff(&changes),
+            "--- a/a.c\n+++ b/a.c\n@@ -1 +1 @@\n-a\n+b\n--- /dev/null\n+++ b/b.c\n@@ -0,0 +1 @@\n+c\n"
+        );
+    }
+}
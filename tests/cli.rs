@@ -0,0 +1,3451 @@
+//! End-to-end runs of the `archaeo` binary over the corpora in `tests/fixtures`
+
+use archaeo::schema::Schema;
+use assert_cmd::Command;
+use predicates::prelude::*;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn archaeo() -> Command {
+    let mut command = Command::new(assert_cmd::cargo::cargo_bin!("archaeo"));
+    command.arg("--quiet");
+    command
+}
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// The header and rows of a CSV output
+fn read_csv(path: &Path) -> (Vec<String>, Vec<Vec<String>>) {
+    let mut reader = csv::Reader::from_path(path).unwrap();
+    let header = reader.headers().unwrap().iter().map(String::from).collect();
+    let rows = reader
+        .records()
+        .map(|record| record.unwrap().iter().map(String::from).collect())
+        .collect();
+    (header, rows)
+}
+
+fn read_json(path: &Path) -> Value {
+    read_json_str(&fs::read_to_string(path).unwrap())
+}
+
+fn read_json_str(text: &str) -> Value {
+    serde_json::from_str(text).unwrap()
+}
+
+/// Runs `source` over `fixture` into a fresh output directory
+fn source(fixture_name: &str, args: &[&str]) -> TempDir {
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(fixture(fixture_name))
+        .arg("-o")
+        .arg(output.path())
+        .args(args)
+        .assert()
+        .success();
+    output
+}
+
+/// A directory of `files`, given by their path below it and contents
+fn corpus(files: &[(&str, &str)]) -> TempDir {
+    let corpus = TempDir::new().unwrap();
+    for (file, contents) in files {
+        let path = corpus.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    corpus
+}
+
+/// Runs git in `repo` as a fixed author, failing the test when it fails
+fn git(repo: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args(["-c", "user.email=a@b", "-c", "user.name=a"])
+        .args(args)
+        .current_dir(repo)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {:?}", args);
+}
+
+/// Writes `files`, removing those given no contents, and commits them in `repo`
+fn commit(repo: &Path, files: &[(&str, Option<&str>)], message: &str) {
+    for (file, contents) in files {
+        let path = repo.join(file);
+        match contents {
+            Some(contents) => fs::write(&path, contents).unwrap(),
+            None => fs::remove_file(&path).unwrap(),
+        }
+    }
+    git(repo, &["add", "-A"]);
+    git(repo, &["commit", "-q", "-m", message]);
+}
+
+#[test]
+fn csv_rows_follow_the_schema() {
+    let output = source("c", &[]);
+    let columns = Schema::current(false).columns;
+
+    // The schema columns lead, followed by the decoding, parse errors and language choice of
+    // the file, its project and the parse errors, qualified name, quality and signature of
+    // the function
+    let (header, rows) = read_csv(&output.path().join("main.csv"));
+    assert_eq!(header[..columns.len()], columns);
+    assert_eq!(
+        header[columns.len()..],
+        [
+            "file_encoding",
+            "file_parse_error_ratio",
+            "file_parse_errors",
+            "grammar_version",
+            "had_decode_errors",
+            "language",
+            "language_candidates",
+            "language_resolution",
+            "parse_error_ratio",
+            "parse_errors",
+            "project",
+            "qualified_name",
+            "quality",
+            "signature"
+        ]
+    );
+    let names: Vec<&str> = rows.iter().map(|row| row[0].as_str()).collect();
+    assert_eq!(names, ["sum_positive", "main"]);
+    for row in &rows {
+        assert_eq!(row[columns.len() + 2], "0");
+        assert_eq!(row[columns.len() + 3], "tree-sitter-mozcpp@0.19.5");
+        assert_eq!(row[columns.len() + 5], "c/c++");
+        assert_eq!(row[columns.len() + 7], "extension");
+        assert_eq!(row[columns.len() + 10], "");
+    }
+    assert_eq!(rows[1][columns.len() + 12], "ok");
+    assert_eq!(rows[1][columns.len() + 13], "int main(void)");
+    assert!(output.path().join("stack_impl.csv").exists());
+
+    let schema: Schema =
+        serde_json::from_value(read_json(&output.path().join("schema.json"))).unwrap();
+    assert_eq!(schema.columns, columns);
+    assert!(!schema.extended);
+
+    archaeo()
+        .arg("validate")
+        .arg(output.path())
+        .assert()
+        .success();
+}
+
+#[test]
+fn json_rows_follow_the_schema() {
+    let output = source("cpp", &["--fmt", "json"]);
+    let columns = Schema::current(false).columns;
+
+    let rows = read_json(&output.path().join("shapes.json"));
+    let rows = rows.as_array().unwrap();
+    assert!(!rows.is_empty());
+    for row in rows {
+        let keys: Vec<&String> = row.as_object().unwrap().keys().collect();
+        assert_eq!(keys[..columns.len()], columns.iter().collect::<Vec<_>>());
+    }
+    assert!(rows.iter().any(|row| row["name"] == "total_area"));
+    assert!(rows
+        .iter()
+        .any(|row| row["qualified_name"] == "geometry::Circle::area"));
+
+    archaeo()
+        .arg("validate")
+        .arg(output.path())
+        .assert()
+        .success();
+}
+
+#[test]
+fn extended_rows_add_columns() {
+    let output = source("rust", &["--extensions", "rs", "--extended"]);
+    let regular = Schema::current(false).columns;
+
+    let (header, rows) = read_csv(&output.path().join("lib-extended.csv"));
+    let extended = Schema::current(true).columns;
+    assert_eq!(header[..extended.len()], extended);
+    assert!(header.len() > regular.len());
+    assert!(regular.iter().all(|column| header.contains(column)));
+    assert!(rows.iter().any(|row| row[0] == "classify"));
+
+    let schema: Schema =
+        serde_json::from_value(read_json(&output.path().join("schema.json"))).unwrap();
+    assert!(schema.extended);
+    archaeo()
+        .arg("validate")
+        .arg(output.path())
+        .assert()
+        .success();
+}
+
+#[test]
+fn no_flatten_keeps_the_nested_spaces() {
+    let output = source("cpp", &["--fmt", "json", "--no-flatten"]);
+
+    let space = read_json(&output.path().join("shapes.json"));
+    assert_eq!(space["kind"], "unit");
+    let namespace = &space["spaces"][0];
+    assert_eq!(namespace["name"], "geometry");
+    let classes: Vec<&Value> = namespace["spaces"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|space| &space["name"])
+        .collect();
+    assert!(classes.contains(&&Value::from("Circle")));
+    assert!(space["metrics"]["cyclomatic"].is_object());
+}
+
+#[test]
+fn no_flatten_swaps_csv_for_json() {
+    let output = source("cpp", &["--no-flatten"]);
+    assert!(output.path().join("shapes.json").exists());
+    assert!(!output.path().join("shapes.csv").exists());
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let output = TempDir::new().unwrap();
+    let planned = output.path().join("planned");
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(fixture("c"))
+        .arg("-o")
+        .arg(&planned)
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("main.c").and(predicate::str::contains("main.csv")));
+    assert!(!planned.exists());
+}
+
+#[test]
+fn missing_path_is_a_usage_error() {
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(fixture("missing"))
+        .arg("-o")
+        .arg(output.path().join("out"))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Invalid input path"));
+    assert!(!output.path().join("out").exists());
+}
+
+#[test]
+fn missing_inputs_are_usage_errors_for_every_command() {
+    let missing = fixture("missing");
+    let output = TempDir::new().unwrap();
+    let out = output.path().join("out");
+    let missing = missing.to_str().unwrap();
+    let out = out.to_str().unwrap();
+    for args in [
+        vec!["stats", "--path", missing],
+        vec!["validate", missing],
+        vec!["merge", missing, "-o", out],
+        vec!["visualize", "--path", missing, "-o", out],
+        vec!["hotspots", "--repo", missing],
+    ] {
+        archaeo()
+            .args(&args)
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains("Invalid input path"));
+    }
+}
+
+#[test]
+fn bad_arguments_are_usage_errors() {
+    archaeo()
+        .args(["source", "--path", ".", "-o", ".", "--fmt", "xml"])
+        .assert()
+        .code(1);
+    archaeo()
+        .args(["source", "--path", ".", "-o", ".", "--batch", "0"])
+        .assert()
+        .code(1);
+    archaeo()
+        .args(["source", "--path", ".", "-o", ".", "--timeout-per-file", "0"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn files_that_fail_are_left_out_and_counted_in_the_exit_code() {
+    let corpus = corpus(&[
+        ("a.c", "int f(int a) { return a; }\n"),
+        ("b.txt", "no language to guess here\n"),
+    ]);
+    let run = |args: &[&str]| {
+        let output = TempDir::new().unwrap();
+        let assert = archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .args(["--extensions", "c,txt"])
+            .args(args)
+            .assert();
+        (assert, output)
+    };
+
+    // The other files are still extracted, only `--summary-exit-codes` fails the run
+    let (assert, output) = run(&[]);
+    assert.success();
+    let (_, rows) = read_csv(&output.path().join("a.csv"));
+    assert_eq!(rows.len(), 1);
+    let (assert, output) = run(&["--summary-exit-codes"]);
+    assert
+        .code(2)
+        .stderr(predicate::str::contains("1 files failed"));
+    let (_, rows) = read_csv(&output.path().join("a.csv"));
+    assert_eq!(rows.len(), 1);
+    assert!(!output.path().join("b.csv").exists());
+
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(fixture("c"))
+        .arg("-o")
+        .arg(TempDir::new().unwrap().path())
+        .arg("--summary-exit-codes")
+        .assert()
+        .code(0);
+}
+
+#[test]
+fn broken_rules_fail_with_their_own_code() {
+    let output = TempDir::new().unwrap();
+    let report = output.path().join("junit.xml");
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(fixture("c"))
+        .arg("-o")
+        .arg(output.path())
+        .args(["--fail-on", "cyclomatic>2", "--report"])
+        .arg(&report)
+        .assert()
+        .code(3);
+    assert!(fs::read_to_string(report).unwrap().contains("sum_positive"));
+}
+
+#[test]
+fn outputs_off_the_schema_fail_validation() {
+    let output = source("c", &[]);
+    fs::write(
+        output.path().join("main.csv"),
+        "name,cyclomatic\nmain,1.0\n",
+    )
+    .unwrap();
+    archaeo()
+        .arg("validate")
+        .arg(output.path())
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn validation_reports_what_is_off_the_schema() {
+    let output = source("c", &[]);
+    let validate = |path: &Path| archaeo().arg("validate").arg(path).assert();
+
+    // Single files are checked against the schema.json beside them
+    validate(&output.path().join("main.csv"))
+        .success()
+        .stdout(predicate::str::ends_with("main.csv: ok\n"));
+
+    let main = output.path().join("main.csv");
+    let (mut header, mut rows) = read_csv(&main);
+    let write = |header: &[String], rows: &[Vec<String>]| {
+        let mut writer = csv::Writer::from_path(&main).unwrap();
+        writer.write_record(header).unwrap();
+        for row in rows {
+            writer.write_record(row).unwrap();
+        }
+        writer.flush().unwrap();
+    };
+    let cyclomatic = header
+        .iter()
+        .position(|column| column == "cyclomatic")
+        .unwrap();
+    rows[1][cyclomatic] = "high".to_string();
+    write(&header, &rows);
+    validate(&main).code(4).stdout(predicate::str::contains(
+        "main.csv: row 2 has a non-numeric cyclomatic: \"high\"",
+    ));
+
+    rows[1][cyclomatic] = "1.0".to_string();
+    header.swap(0, 1);
+    write(&header, &rows);
+    validate(&main)
+        .code(4)
+        .stdout(predicate::str::contains("main.csv: columns out of order"));
+
+    // Runs written with another schema version are turned away before their files
+    let schema = output.path().join("schema.json");
+    let mut recorded = read_json(&schema);
+    recorded["schema_version"] = Value::from(0);
+    fs::write(&schema, recorded.to_string()).unwrap();
+    validate(output.path())
+        .code(4)
+        .stderr(predicate::str::contains("with schema version 0"));
+
+    // Without one, only the columns are checked
+    fs::remove_file(&schema).unwrap();
+    validate(&output.path().join("stack_impl.csv")).success();
+}
+
+#[test]
+fn labels_join_on_signatures_telling_overloads_apart() {
+    let corpus = TempDir::new().unwrap();
+    fs::write(
+        corpus.path().join("area.cpp"),
+        "int area(int w) { return w * w; }\ndouble area(double w, double h) { return w * h; }\n",
+    )
+    .unwrap();
+    let labels = corpus.path().join("labels.csv");
+    fs::write(
+        &labels,
+        "name,signature,buggy\narea,\"double area(double w, double h)\",yes\n",
+    )
+    .unwrap();
+
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .args(["--join-on", "name,signature", "--labels"])
+        .arg(&labels)
+        .assert()
+        .success();
+    let (header, rows) = read_csv(&output.path().join("area.csv"));
+    let buggy = header.iter().position(|column| column == "buggy").unwrap();
+    let labelled: Vec<&str> = rows.iter().map(|row| row[buggy].as_str()).collect();
+    assert_eq!(labelled, ["", "yes"]);
+}
+
+#[test]
+fn advisories_annotate_the_files_and_functions_they_name() {
+    let corpus = TempDir::new().unwrap();
+    fs::create_dir(corpus.path().join("lib")).unwrap();
+    fs::write(
+        corpus.path().join("lib/parse.c"),
+        "int parse_header(void) { return 0; }\nint parse_body(void) { return 1; }\n",
+    )
+    .unwrap();
+    fs::write(
+        corpus.path().join("util.c"),
+        "int helper(void) { return 2; }\n",
+    )
+    .unwrap();
+    let map = corpus.path().join("advisories.json");
+    fs::write(
+        &map,
+        r#"{"lib/parse.c#parse_header": ["CVE-2021-0001"], "util.c": ["CVE-2020-0002", "CVE-2021-0001"]}"#,
+    )
+    .unwrap();
+    let osv = corpus.path().join("osv.json");
+    fs::write(
+        &osv,
+        r#"{"vulns": [{"id": "GHSA-aaaa-bbbb-cccc", "aliases": ["CVE-2022-0003"],
+            "affected": [{"ecosystem_specific": {"imports": [{"path": "lib", "symbols": ["parse_body"]}]}}]}]}"#,
+    )
+    .unwrap();
+
+    let annotated = |advisories: &Path| -> Vec<[String; 3]> {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .arg("--advisories")
+            .arg(advisories)
+            .assert()
+            .success();
+        ["parse.csv", "util.csv"]
+            .iter()
+            .flat_map(|file| {
+                let (header, rows) = read_csv(&output.path().join(file));
+                let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+                let (name, ids, count) = (
+                    column("name"),
+                    column("advisories"),
+                    column("advisory_count"),
+                );
+                rows.into_iter()
+                    .map(move |row| [row[name].clone(), row[ids].clone(), row[count].clone()])
+            })
+            .collect()
+    };
+    assert_eq!(
+        annotated(&map),
+        [
+            ["parse_header", "CVE-2021-0001", "1"],
+            ["parse_body", "", "0"],
+            ["helper", "CVE-2020-0002,CVE-2021-0001", "2"],
+        ]
+        .map(|row| row.map(String::from))
+    );
+    assert_eq!(
+        annotated(&osv),
+        [
+            ["parse_header", "", "0"],
+            ["parse_body", "CVE-2022-0003,GHSA-aaaa-bbbb-cccc", "2"],
+            ["helper", "", "0"],
+        ]
+        .map(|row| row.map(String::from))
+    );
+}
+
+#[test]
+fn template_specializations_are_told_apart_or_folded() {
+    let corpus = TempDir::new().unwrap();
+    fs::write(
+        corpus.path().join("max.cpp"),
+        "template <typename T>\nT maxof(T a, T b) { return a > b ? a : b; }\n\
+         template <>\nint maxof<int>(int a, int b) { return a > b ? a : b; }\n\
+         int plain() { return 0; }\n",
+    )
+    .unwrap();
+    let rows_of = |args: &[&str]| {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .args(args)
+            .assert()
+            .success();
+        read_csv(&output.path().join("max.csv"))
+    };
+
+    let (_, rows) = rows_of(&[]);
+    let kinds: Vec<(&str, &str)> = rows
+        .iter()
+        .map(|row| (row[0].as_str(), row[4].as_str()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("maxof", "function_template"),
+            ("maxof<int>", "function_specialization"),
+            ("plain", "function")
+        ]
+    );
+
+    let (header, rows) = rows_of(&["--aggregate-specializations"]);
+    let count = header
+        .iter()
+        .position(|column| column == "specializations")
+        .unwrap();
+    let folded: Vec<(&str, &str)> = rows
+        .iter()
+        .map(|row| (row[0].as_str(), row[count].as_str()))
+        .collect();
+    assert_eq!(folded, [("maxof", "1"), ("plain", "")]);
+}
+
+#[test]
+fn functions_failing_to_parse_are_flagged_or_skipped() {
+    let corpus = TempDir::new().unwrap();
+    fs::write(
+        corpus.path().join("broken.c"),
+        "int ok(int a) {\n    return a + 1;\n}\n\n\
+         int broken(int a) {\n    int x = = 3 +;\n    return ) a;\n}\n",
+    )
+    .unwrap();
+    let rows_of = |args: &[&str]| {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .args(args)
+            .assert()
+            .success();
+        read_csv(&output.path().join("broken.csv"))
+    };
+
+    let (header, rows) = rows_of(&[]);
+    let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+    let errors: Vec<(&str, &str)> = rows
+        .iter()
+        .map(|row| (row[0].as_str(), row[column("parse_errors")].as_str()))
+        .collect();
+    assert_eq!(errors[0], ("ok", "0"));
+    assert_eq!(errors[1].0, "broken");
+    assert_ne!(errors[1].1, "0");
+    let quality: Vec<&str> = rows
+        .iter()
+        .map(|row| row[column("quality")].as_str())
+        .collect();
+    assert_eq!(quality, ["ok", "partial_parse"]);
+    let ratio: f64 = rows[1][column("parse_error_ratio")].parse().unwrap();
+    let file_ratio: f64 = rows[1][column("file_parse_error_ratio")].parse().unwrap();
+    assert!(ratio > file_ratio && file_ratio > 0.0);
+
+    let (_, rows) = rows_of(&["--max-parse-error-ratio", "0"]);
+    let names: Vec<&str> = rows.iter().map(|row| row[0].as_str()).collect();
+    assert_eq!(names, ["ok"]);
+}
+
+#[test]
+fn languages_guessed_from_the_contents_lower_the_quality() {
+    let corpus = TempDir::new().unwrap();
+    let tool = corpus.path().join("tool");
+    fs::write(&tool, "# -*- mode: python -*-\ndef run(x):\n    return x\n").unwrap();
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(&tool)
+        .arg("-o")
+        .arg(output.path())
+        .assert()
+        .success();
+    let (header, rows) = read_csv(&output.path().join("tool.csv"));
+    let quality = header
+        .iter()
+        .position(|column| column == "quality")
+        .unwrap();
+    assert_eq!(rows[0][quality], "guessed_language_low_confidence");
+}
+
+#[test]
+fn ambiguous_headers_are_told_apart_by_their_markers_or_preference() {
+    let corpus = TempDir::new().unwrap();
+    fs::write(
+        corpus.path().join("shape.h"),
+        "class Shape {\npublic:\n    int sides() { return 0; }\n};\n",
+    )
+    .unwrap();
+    fs::write(
+        corpus.path().join("util.h"),
+        "static int twice(int x) { return 2 * x; }\n",
+    )
+    .unwrap();
+    fs::write(
+        corpus.path().join("view.h"),
+        "#import <Foundation/Foundation.h>\n@interface View : NSObject\n@end\n\
+         static int one(void) { return 1; }\n",
+    )
+    .unwrap();
+    let choices_of = |args: &[&str]| -> Vec<(String, String, String)> {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .args(args)
+            .assert()
+            .success();
+        ["shape", "util", "view"]
+            .iter()
+            .map(|name| {
+                let (header, rows) = read_csv(&output.path().join(format!("{}.csv", name)));
+                let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+                let row = rows.last().unwrap();
+                (
+                    row[column("language")].clone(),
+                    row[column("language_resolution")].clone(),
+                    row[column("language_candidates")].clone(),
+                )
+            })
+            .collect()
+    };
+    let choice = |language: &str, resolution: &str, candidates: &str| {
+        (
+            language.to_string(),
+            resolution.to_string(),
+            candidates.to_string(),
+        )
+    };
+
+    assert_eq!(
+        choices_of(&[]),
+        [
+            choice("c/c++", "markers", "c++,c"),
+            choice("c/c++", "extension", "c,c++"),
+            choice("obj-c/c++", "markers", "obj-c,c"),
+        ]
+    );
+    assert_eq!(
+        choices_of(&["--prefer-language", "cpp"])[1],
+        choice("c/c++", "preferred", "c++,c")
+    );
+}
+
+#[test]
+fn objective_c_methods_are_named_after_their_selectors() {
+    let corpus = TempDir::new().unwrap();
+    fs::write(
+        corpus.path().join("Calc.m"),
+        "#import <Foundation/Foundation.h>\n\n\
+         @interface Calc : NSObject\n- (int)add:(int)a to:(int)b;\n@end\n\n\
+         @implementation Calc\n\
+         - (int)add:(int)a to:(int)b {\n    if (a > b) {\n        return a + b;\n    }\n    return b + a;\n}\n\n\
+         + (NSString *)label {\n    return @\"calc\";\n}\n@end\n",
+    )
+    .unwrap();
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .assert()
+        .success();
+
+    let (header, rows) = read_csv(&output.path().join("Calc.csv"));
+    let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+    let methods: Vec<(&str, &str, &str, &str)> = rows
+        .iter()
+        .map(|row| {
+            (
+                row[0].as_str(),
+                row[4].as_str(),
+                row[column("start_line")].as_str(),
+                row[column("signature")].as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        methods,
+        [
+            ("Calc", "class", "7", ""),
+            ("add:to:", "function", "8", "- (int)add:(int)a to:(int)b"),
+            ("label", "function", "15", "+ (NSString *)label"),
+        ]
+    );
+    assert!(rows
+        .iter()
+        .all(|row| row[column("language")] == "obj-c/c++"));
+}
+
+#[test]
+fn composite_scores_weigh_the_metric_columns() {
+    let config = TempDir::new().unwrap();
+    let config = config.path().join("archaeo.toml");
+    fs::write(
+        &config,
+        "[scores]\ndebt = \"0.5*normalized(cognitive) + 0.5*normalized(loc_sloc)\"\n",
+    )
+    .unwrap();
+    let output = source("c", &["--config", config.to_str().unwrap()]);
+
+    let (header, rows) = read_csv(&output.path().join("main.csv"));
+    let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+    let scores: Vec<(&str, f64, f64)> = rows
+        .iter()
+        .map(|row| {
+            (
+                row[0].as_str(),
+                row[column("debt")].parse().unwrap(),
+                row[column("file_debt")].parse().unwrap(),
+            )
+        })
+        .collect();
+    // sum_positive has the most cognitive complexity and lines of the corpus
+    assert_eq!(scores[0].0, "sum_positive");
+    assert_eq!(scores[0].1, 1.0);
+    assert!(scores[1].1 > 0.0 && scores[1].1 < 1.0);
+    assert_eq!(scores[0].2, scores[0].1 + scores[1].1);
+    assert_eq!(scores[1].2, scores[0].2);
+
+    // Given on the command line, scores replace those of the configuration
+    let output = source(
+        "c",
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "--score",
+            "size=loc_sloc/2",
+        ],
+    );
+    let (header, rows) = read_csv(&output.path().join("main.csv"));
+    assert!(!header.contains(&"debt".to_string()));
+    let size = header.iter().position(|column| column == "size").unwrap();
+    assert_eq!(rows[0][size], "8.0");
+
+    archaeo()
+        .args(["source", "--path", ".", "-o", "."])
+        .args(["--score", "debt=median(cognitive)"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("unknown function median"));
+}
+
+#[test]
+fn anonymous_functions_are_named_nulled_or_dropped() {
+    let names_of = |naming: &str| -> Vec<String> {
+        let output = source("rust", &["--extensions", "rs", "--anonymous", naming]);
+        let (_, rows) = read_csv(&output.path().join("lib.csv"));
+        rows.into_iter().map(|row| row[0].clone()).collect()
+    };
+    let synthetic = names_of("synthetic");
+    assert!(synthetic.contains(&"word_counts::lambda@4:17".to_string()));
+    let nulled = names_of("null");
+    assert_eq!(nulled.len(), synthetic.len());
+    assert_eq!(nulled.iter().filter(|name| name.is_empty()).count(), 2);
+    assert_eq!(names_of("drop").len(), synthetic.len() - 2);
+}
+
+#[test]
+fn samples_are_deterministic_and_nested() {
+    let outputs_of = |fraction: &str| -> Vec<String> {
+        let output = source(
+            "",
+            &[
+                "--extensions",
+                "c,cpp,rs",
+                "--sample",
+                fraction,
+                "--seed",
+                "3",
+            ],
+        );
+        let mut outputs: Vec<String> = fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".csv"))
+            .collect();
+        outputs.sort();
+        outputs
+    };
+    let half = outputs_of("0.5");
+    assert_eq!(half, outputs_of("0.5"));
+    let all = outputs_of("1");
+    assert!(half.len() < all.len());
+    assert!(half.iter().all(|name| all.contains(name)));
+
+    archaeo()
+        .args(["source", "--path", ".", "-o", ".", "--sample", "1.5"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn sampled_files_fall_into_every_split() {
+    let corpus = TempDir::new().unwrap();
+    for i in 0..100 {
+        fs::write(
+            corpus.path().join(format!("f{}.c", i)),
+            format!("int f{}(void) {{\n  return 0;\n}}\n", i),
+        )
+        .unwrap();
+    }
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .args(["--extensions", "c", "--sample", "0.3", "--split", "0.5,0.25,0.25"])
+        .args(["--path-style", "relative-to-root"])
+        .assert()
+        .success();
+
+    let mut splits = std::collections::BTreeMap::new();
+    for entry in fs::read_dir(output.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "csv") {
+            let (header, rows) = read_csv(&path);
+            let column = header.iter().position(|name| name == "split").unwrap();
+            for row in rows {
+                *splits.entry(row[column].clone()).or_insert(0) += 1;
+            }
+        }
+    }
+    // The sample is drawn independently of the split the same seed assigns files to
+    assert_eq!(
+        splits.keys().collect::<Vec<_>>(),
+        ["test", "train", "validation"],
+        "{:?}",
+        splits
+    );
+}
+
+#[test]
+fn stratified_samples_take_as_many_files_from_every_group() {
+    let corpus = TempDir::new().unwrap();
+    let main = fs::read(fixture("c/main.c")).unwrap();
+    for project in ["a", "b", "c"] {
+        for i in 0..4 {
+            let path = corpus
+                .path()
+                .join(format!("{}/src/{}{}.c", project, project, i));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, &main).unwrap();
+        }
+    }
+    let outputs_of = |args: &[&str]| -> Vec<String> {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .args(args)
+            .assert()
+            .success();
+        fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".csv"))
+            .collect()
+    };
+
+    let two = outputs_of(&["--sample-per-dir", "2"]);
+    for project in ["a", "b", "c"] {
+        assert_eq!(
+            two.iter().filter(|name| name.starts_with(project)).count(),
+            2
+        );
+    }
+    let three = outputs_of(&["--sample-per-dir", "3"]);
+    assert_eq!(three.len(), 9);
+    assert!(two.iter().all(|name| three.contains(name)));
+    assert_eq!(outputs_of(&["--sample-per-lang", "5"]).len(), 5);
+}
+
+#[test]
+fn duplicated_files_are_collapsed_into_the_first() {
+    let corpus = TempDir::new().unwrap();
+    let header = "static int clamp(int v) { return v < 0 ? 0 : v; }\n";
+    for (file, content) in [
+        ("include/util.h", header),
+        ("vendor/a/util.h", header),
+        ("vendor/b/util.h", header),
+        ("main.c", "int main(void) { return 0; }\n"),
+    ] {
+        let path = corpus.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    let outputs_of = |args: &[&str]| -> (TempDir, usize) {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .args(args)
+            .assert()
+            .success();
+        let csvs = fs::read_dir(output.path())
+            .unwrap()
+            .filter(|entry| {
+                let path = entry.as_ref().unwrap().path();
+                path.extension().is_some_and(|ext| ext == "csv")
+            })
+            .count();
+        (output, csvs)
+    };
+
+    let (output, csvs) = outputs_of(&[]);
+    assert_eq!(csvs, 4);
+    assert!(!output.path().join("duplicates.tsv").exists());
+
+    let (output, csvs) = outputs_of(&["--dedup"]);
+    assert_eq!(csvs, 2);
+    let first = corpus.path().join("include/util.h");
+    let mut duplicates: Vec<String> = fs::read_to_string(output.path().join("duplicates.tsv"))
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    duplicates.sort();
+    assert_eq!(
+        duplicates,
+        ["vendor/a/util.h", "vendor/b/util.h"].map(|duplicate| format!(
+            "{}\t{}",
+            corpus.path().join(duplicate).display(),
+            first.display()
+        ))
+    );
+}
+
+#[test]
+fn presets_pick_the_extensions_and_excludes_of_a_stack() {
+    let corpus = TempDir::new().unwrap();
+    for file in [
+        "src/lib.rs",
+        "target/debug/build.rs",
+        "web/app.ts",
+        "web/node_modules/dep/index.js",
+        "native/main.c",
+    ] {
+        let path = corpus.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+    let planned = |preset: &str| -> String {
+        let output = archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(corpus.path().join("out"))
+            .args(["--dry-run", "--preset", preset])
+            .assert()
+            .success();
+        String::from_utf8(output.get_output().stdout.clone()).unwrap()
+    };
+
+    let rust = planned("rust");
+    assert!(rust.contains("lib.rs"));
+    assert!(!rust.contains("build.rs") && !rust.contains("main.c"));
+    let all = planned("all");
+    assert!(all.contains("lib.rs") && all.contains("app.ts") && all.contains("main.c"));
+    assert!(!all.contains("build.rs") && !all.contains("index.js"));
+
+    archaeo()
+        .args(["source", "--path", ".", "-o", ".", "--preset", "rust"])
+        .args(["--extensions", "rs"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn init_scaffolds_a_configuration_source_reads() {
+    let repo = corpus(&[
+        (
+            "src/a.c",
+            "int f(void) { return 0; }\nint g(int x) { if (x) return 1; return 0; }\n",
+        ),
+        ("src/b.h", "int f(void);\n"),
+        ("vendor/lib/x.c", "int x(void) { return 1; }\n"),
+        (".hidden/y.c", "int y(void) { return 1; }\n"),
+        ("README.md", "# a\n"),
+    ]);
+    let config = repo.path().join("archaeo.toml");
+    let init = || {
+        let mut command = archaeo();
+        command
+            .arg("init")
+            .arg("--path")
+            .arg(repo.path())
+            .arg("-o")
+            .arg(&config);
+        command
+    };
+    init().assert().success();
+
+    let text = fs::read_to_string(&config).unwrap();
+    assert!(text.contains("extensions = [\"c\", \"h\"]\n"), "{}", text);
+    assert!(text.contains("exclude = [\"vendor/**\"]\n"), "{}", text);
+    assert!(text.contains("skip_generated = true\n"), "{}", text);
+    assert!(text.contains("fail_on = [\"cyclomatic>"), "{}", text);
+
+    // It is left alone unless forced
+    init().assert().code(1);
+    init().arg("--force").assert().success();
+
+    let output = archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(repo.path())
+        .arg("-o")
+        .arg(repo.path().join("out"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--dry-run")
+        .assert()
+        .success();
+    let planned = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    assert!(planned.contains("a.c"), "{}", planned);
+    assert!(!planned.contains("x.c"), "{}", planned);
+}
+
+#[test]
+fn functions_are_followed_from_introduction_to_removal() {
+    let repo = TempDir::new().unwrap();
+    let commit = |date: &str, source: &str| {
+        fs::write(repo.path().join("lib.c"), source).unwrap();
+        for args in [&["add", "."][..], &["commit", "-q", "-m", date]] {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.email=a@b", "-c", "user.name=a"])
+                .args(args)
+                .env("GIT_AUTHOR_DATE", date)
+                .env("GIT_COMMITTER_DATE", date)
+                .current_dir(repo.path())
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?}", args);
+        }
+    };
+    let status = std::process::Command::new("git")
+        .args(["init", "-q"])
+        .current_dir(repo.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let two = "int two(int a) { if (a) { return 2; } return a; }\n";
+    commit(
+        "2020-01-01T00:00:00Z",
+        &format!("int one(void) {{ return 1; }}\n{}", two),
+    );
+    commit(
+        "2020-04-10T00:00:00Z",
+        &format!("int one(void) {{ return 11; }}\n{}", two),
+    );
+    commit(
+        "2021-02-04T00:00:00Z",
+        "int one(void) { return 11; }\nint three(void) { return 3; }\n",
+    );
+
+    let output = TempDir::new().unwrap();
+    let lifespans = output.path().join("survival.csv");
+    let summary = output.path().join("summary.json");
+    archaeo()
+        .arg("survival")
+        .arg("--repo")
+        .arg(repo.path())
+        .arg("-o")
+        .arg(&lifespans)
+        .arg("--summary")
+        .arg(&summary)
+        .args(["--threshold", "1"])
+        .assert()
+        .success();
+
+    let (header, rows) = read_csv(&lifespans);
+    let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+    let life = |name: &str| {
+        let row = rows.iter().find(|row| row[column("name")] == name).unwrap();
+        [
+            "alive",
+            "age_days",
+            "changes",
+            "days_since_change",
+            "profile",
+        ]
+        .map(|name| row[column(name)].clone())
+    };
+    assert_eq!(life("one"), ["true", "400.0", "1", "300.0", "old-stable"]);
+    assert_eq!(life("two"), ["false", "400.0", "0", "400.0", ""]);
+    assert_eq!(life("three"), ["true", "0.0", "0", "0.0", "young-churning"]);
+
+    let summary: Value = serde_json::from_str(&fs::read_to_string(summary).unwrap()).unwrap();
+    assert_eq!(summary["functions"], 3);
+    assert_eq!(summary["removed"], 1);
+    assert_eq!(summary["median_lifespan_days"], 400.0);
+    assert_eq!(summary["survival"]["365_days"], 1.0);
+    assert_eq!(summary["survival"]["730_days"], Value::Null);
+    assert_eq!(summary["complex_profiles"]["old-stable"], 1);
+    assert_eq!(summary["complex_profiles"]["young-churning"], 1);
+}
+
+#[test]
+fn complex_functions_of_authors_gone_quiet_are_at_risk() {
+    let repo = TempDir::new().unwrap();
+    let git = |args: &[&str], author: &str, date: &str| {
+        let status = std::process::Command::new("git")
+            .args(["-c", &format!("user.email={}@example.com", author)])
+            .args(["-c", &format!("user.name={}", author)])
+            .args(args)
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .current_dir(repo.path())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    };
+    let branchy = |name: &str| {
+        format!(
+            "int {}(int a) {{\n  if (a) {{ return 1; }}\n  if (a > 1) {{ return 2; }}\n  return a;\n}}\n",
+            name
+        )
+    };
+    git(&["init", "-q"], "", "");
+    fs::write(repo.path().join("old.c"), branchy("legacy")).unwrap();
+    git(&["add", "."], "", "");
+    git(
+        &["commit", "-q", "-m", "old"],
+        "gone",
+        "2020-01-01T00:00:00Z",
+    );
+    fs::write(
+        repo.path().join("new.c"),
+        branchy("fresh") + "int easy(void) { return 0; }\n",
+    )
+    .unwrap();
+    git(&["add", "."], "", "");
+    git(
+        &["commit", "-q", "-m", "new"],
+        "here",
+        "2021-01-01T00:00:00Z",
+    );
+
+    let output = TempDir::new().unwrap();
+    let report = output.path().join("risk.csv");
+    let markdown = output.path().join("risk.md");
+    archaeo()
+        .arg("risk")
+        .arg("--repo")
+        .arg(repo.path())
+        .args(["--threshold", "3"])
+        .arg("-o")
+        .arg(&report)
+        .arg("--markdown")
+        .arg(&markdown)
+        .assert()
+        .success();
+
+    let (header, rows) = read_csv(&report);
+    let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+    let reported: Vec<[&str; 4]> = rows
+        .iter()
+        .map(|row| {
+            [
+                row[column("name")].as_str(),
+                row[column("top_author")].as_str(),
+                row[column("days_since_top_author_commit")].as_str(),
+                row[column("at_risk")].as_str(),
+            ]
+        })
+        .collect();
+    assert_eq!(
+        reported,
+        [
+            ["legacy", "gone", "366.0", "true"],
+            ["fresh", "here", "0.0", "false"]
+        ]
+    );
+    let markdown = fs::read_to_string(markdown).unwrap();
+    assert!(markdown.contains("| `legacy` | `old.c:1` | 3.0 | gone | 100% | 366.0 |"));
+    assert!(!markdown.contains("fresh"));
+}
+
+#[test]
+fn hotspots_rank_files_by_their_complexity_and_churn() {
+    let repo = TempDir::new().unwrap();
+    let g = |n: u32| format!("int g(void) {{\n  return {};\n}}\n", n);
+    git(repo.path(), &["init", "-q"]);
+    commit(
+        repo.path(),
+        &[
+            (
+                "a.c",
+                Some("int f(int a) {\n  if (a) {\n    return 1;\n  }\n  if (a > 2) {\n    return 2;\n  }\n  return 0;\n}\n"),
+            ),
+            ("b.c", Some(&g(0))),
+        ],
+        "1",
+    );
+    commit(repo.path(), &[("b.c", Some(&g(1)))], "2");
+    commit(repo.path(), &[("b.c", Some(&g(2)))], "3");
+    let ranked = |args: &[&str]| -> Vec<(String, f64)> {
+        let output = archaeo()
+            .arg("hotspots")
+            .arg("--repo")
+            .arg(repo.path())
+            .args(["--fmt", "json"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        read_json_str(&String::from_utf8(output.stdout).unwrap())
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| {
+                (
+                    row["source_file"].as_str().unwrap().to_string(),
+                    row["score"].as_f64().unwrap(),
+                )
+            })
+            .collect()
+    };
+    let file = |name: &str, score: f64| (name.to_string(), score);
+
+    // a.c is three times as complex, b.c was changed three times as often
+    assert_eq!(
+        ranked(&[]),
+        [file("a.c", 1.0 / 3.0), file("b.c", 1.0 / 3.0)]
+    );
+    assert_eq!(
+        ranked(&["--complexity-weight", "0"]),
+        [file("b.c", 1.0), file("a.c", 1.0 / 3.0)]
+    );
+    assert_eq!(
+        ranked(&["--churn-weight", "0", "--top", "1"]),
+        [file("a.c", 1.0)]
+    );
+    // 9 lines were added to a.c and 3 + 2 + 2 to b.c
+    assert_eq!(
+        ranked(&["--churn", "lines"]),
+        [file("a.c", 1.0), file("b.c", 7.0 / 27.0)]
+    );
+
+    let treemap = repo.path().join("hotspots.html");
+    ranked(&["--treemap", treemap.to_str().unwrap()]);
+    let html = fs::read_to_string(treemap).unwrap();
+    assert!(html.contains("a.c") && html.contains("b.c"));
+    archaeo()
+        .arg("hotspots")
+        .arg("--repo")
+        .arg(repo.path())
+        .args(["--metric", "name"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "name is not a numeric metric column",
+        ));
+}
+
+#[test]
+fn git_revisions_are_read_without_checking_them_out() {
+    let repo = TempDir::new().unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.email=a@b", "-c", "user.name=a"])
+            .args(args)
+            .current_dir(repo.path())
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    };
+    git(&["init", "-q", "work"]);
+    let work = repo.path().join("work");
+    fs::write(work.join("lib.c"), "int one(void) { return 1; }\n").unwrap();
+    git(&["-C", "work", "add", "."]);
+    git(&["-C", "work", "commit", "-q", "-m", "one"]);
+    fs::write(
+        work.join("lib.c"),
+        "int one(void) { return 1; }\nint two(void) { return 2; }\n",
+    )
+    .unwrap();
+    git(&["-C", "work", "commit", "-q", "-a", "-m", "two"]);
+    git(&["clone", "-q", "--bare", "work", "bare.git"]);
+
+    let functions_at = |rev: &str| -> Vec<String> {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(repo.path().join("bare.git"))
+            .arg("-o")
+            .arg(output.path())
+            .args(["--git-rev", rev])
+            .assert()
+            .success();
+        let (header, rows) = read_csv(&output.path().join("lib.csv"));
+        let name = header.iter().position(|column| column == "name").unwrap();
+        rows.into_iter().map(|row| row[name].clone()).collect()
+    };
+    assert_eq!(functions_at("HEAD~1"), ["one"]);
+    assert_eq!(functions_at("HEAD"), ["one", "two"]);
+
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(repo.path().join("bare.git"))
+        .arg("-o")
+        .arg(repo.path().join("out"))
+        .args(["--git-rev", "no-such-rev"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no-such-rev"));
+}
+
+#[cfg(unix)]
+#[test]
+fn git_revisions_read_files_whose_names_are_not_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // Both names would be `bad\u{FFFD}.c` decoded lossily
+    let repo = TempDir::new().unwrap();
+    fs::write(
+        repo.path().join(OsStr::from_bytes(b"bad\xff.c")),
+        "int f(void) { return 1; }\n",
+    )
+    .unwrap();
+    fs::write(
+        repo.path().join(OsStr::from_bytes(b"bad\xfe.c")),
+        "int g(void) { return 2; }\n",
+    )
+    .unwrap();
+    git(repo.path(), &["init", "-q"]);
+    git(repo.path(), &["add", "-A"]);
+    git(repo.path(), &["commit", "-q", "-m", "bad"]);
+
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(repo.path())
+        .arg("-o")
+        .arg(output.path())
+        .args(["--git-rev", "HEAD"])
+        .assert()
+        .success();
+    for (file, function) in [("bad%FF.csv", "f"), ("bad%FE.csv", "g")] {
+        let (header, rows) = read_csv(&output.path().join(file));
+        let name = header.iter().position(|column| column == "name").unwrap();
+        assert_eq!(rows[0][name], function);
+    }
+}
+
+/// A repository whose history fixes `two` twice, tidies `one` and adds both in a commit
+/// the fixes blame, then moves them down, with objects named by `object_format`
+fn fixed_repository(object_format: &str) -> TempDir {
+    let repo = TempDir::new().unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.email=a@b", "-c", "user.name=a"])
+            .args(args)
+            .current_dir(repo.path())
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    };
+    let commit = |one: &str, two: &str, message: &str| {
+        fs::write(
+            repo.path().join("lib.c"),
+            format!(
+                "int one(void) {{\n  return {};\n}}\n\nint two(void) {{\n  return {};\n}}\n",
+                one, two
+            ),
+        )
+        .unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", message]);
+    };
+    git(&["init", "-q", &format!("--object-format={}", object_format)]);
+    commit("1", "2", "Add one and two");
+    commit("1", "3", "Fix two being off by one");
+    commit("10", "3", "Tidy up one");
+    commit("10", "4", "two: handle the CVE-2024-1234 case");
+    // Moves both functions down, so the lines of the fixes have to be followed
+    let text = fs::read_to_string(repo.path().join("lib.c")).unwrap();
+    fs::write(repo.path().join("lib.c"), format!("// Numbers\n\n{}", text)).unwrap();
+    git(&["commit", "-q", "-a", "-m", "Document the numbers"]);
+    repo
+}
+
+/// The name, `count` column and `file_bugfix_count` of every function of `lib.c` in `repo`
+fn bugfix_counts(repo: &Path, args: &[&str], count: &str) -> Vec<[String; 3]> {
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(repo)
+        .arg("-o")
+        .arg(output.path())
+        .args(args)
+        .assert()
+        .success();
+    let (header, rows) = read_csv(&output.path().join("lib.csv"));
+    let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+    rows.into_iter()
+        .map(|row| {
+            [
+                row[column("name")].clone(),
+                row[column(count)].clone(),
+                row[column("file_bugfix_count")].clone(),
+            ]
+        })
+        .collect()
+}
+
+#[test]
+fn functions_touched_by_fixing_and_inducing_commits_are_counted() {
+    let repo = fixed_repository("sha1");
+    assert_eq!(
+        bugfix_counts(repo.path(), &["--bugfixes"], "bugfix_count"),
+        [["one", "0", "2"], ["two", "2", "2"]].map(|row| row.map(String::from))
+    );
+    assert_eq!(
+        bugfix_counts(repo.path(), &["--bugfixes", "^Tidy"], "bugfix_count"),
+        [["one", "1", "1"], ["two", "0", "1"]].map(|row| row.map(String::from))
+    );
+    // The first fix blames the commit adding both functions, the second the first fix
+    assert_eq!(
+        bugfix_counts(
+            repo.path(),
+            &["--bugfixes", "--szz"],
+            "bug_inducing_changes"
+        ),
+        [["one", "1", "2"], ["two", "2", "2"]].map(|row| row.map(String::from))
+    );
+}
+
+#[test]
+fn inducing_commits_are_blamed_in_sha256_repositories() {
+    let repo = fixed_repository("sha256");
+    assert_eq!(
+        bugfix_counts(
+            repo.path(),
+            &["--bugfixes", "--szz"],
+            "bug_inducing_changes"
+        ),
+        [["one", "1", "2"], ["two", "2", "2"]].map(|row| row.map(String::from))
+    );
+}
+
+#[test]
+fn monorepo_projects_are_recorded_sharded_and_scored() {
+    let corpus = TempDir::new().unwrap();
+    for file in [
+        "engine/Cargo.toml",
+        "engine/ffi/shim.c",
+        "tools/cli/CMakeLists.txt",
+        "tools/cli/main.c",
+        "vendor/zlib/.git/HEAD",
+        "vendor/zlib/inflate.c",
+        "scripts/gen.c",
+    ] {
+        let path = corpus.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "int f(void) { return 0; }\n").unwrap();
+    }
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .arg("--per-project-output")
+        .assert()
+        .success();
+
+    for (output_file, project) in [
+        ("engine/shim.csv", "engine"),
+        ("tools/cli/main.csv", "tools/cli"),
+        ("vendor/zlib/inflate.csv", "vendor/zlib"),
+        ("gen.csv", ""),
+    ] {
+        let (header, rows) = read_csv(&output.path().join(output_file));
+        let column = header
+            .iter()
+            .position(|column| column == "project")
+            .unwrap();
+        assert!(
+            rows.iter().all(|row| row[column] == project),
+            "{}",
+            output_file
+        );
+    }
+
+    let scorecard = output.path().join("scorecard.json");
+    archaeo()
+        .arg("scorecard")
+        .arg(output.path())
+        .arg("-o")
+        .arg(&scorecard)
+        .args(["--fmt", "json", "--worst", "1"])
+        .assert()
+        .success();
+    let projects = read_json(&scorecard);
+    let projects = projects.as_array().unwrap();
+    let names: Vec<Option<&str>> = projects
+        .iter()
+        .map(|project| project["project"].as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [None, Some("engine"), Some("tools/cli"), Some("vendor/zlib")]
+    );
+    for project in projects {
+        assert_eq!(project["files"], 1);
+        assert_eq!(project["cyclomatic_max"], 1.0);
+        assert_eq!(project["grade"], "A");
+        assert!(project["worst_offenders"]
+            .as_str()
+            .unwrap()
+            .ends_with(" f (1)"));
+    }
+}
+
+#[test]
+fn patches_report_the_metrics_of_the_functions_they_touch() {
+    let repo = TempDir::new().unwrap();
+    fs::create_dir(repo.path().join("src")).unwrap();
+    fs::write(
+        repo.path().join("src/a.c"),
+        "int f(int a) {\n  return a;\n}\n\nint g(void) {\n  return 0;\n}\n",
+    )
+    .unwrap();
+    let patch = repo.path().join("change.patch");
+    fs::write(
+        &patch,
+        "--- a/src/a.c\n+++ b/src/a.c\n@@ -1,3 +1,6 @@\n int f(int a) {\n-  return a;\n+  if (a > 0) {\n+    return a;\n+  }\n+  return -a;\n }\n",
+    )
+    .unwrap();
+
+    let output = repo.path().join("impact.csv");
+    archaeo()
+        .arg("patch")
+        .arg("--diff")
+        .arg(&patch)
+        .arg("--repo")
+        .arg(repo.path())
+        .arg("-o")
+        .arg(&output)
+        .args(["--fmt", "csv", "--metrics", "cyclomatic"])
+        .assert()
+        .success();
+    let (header, rows) = read_csv(&output);
+    assert_eq!(
+        header,
+        [
+            "source_file",
+            "name",
+            "status",
+            "base_start_line",
+            "base_end_line",
+            "head_start_line",
+            "head_end_line",
+            "cyclomatic_base",
+            "cyclomatic_head",
+            "cyclomatic_delta"
+        ]
+    );
+    // g sits outside of the hunk
+    assert_eq!(
+        rows,
+        [["src/a.c", "f", "changed", "1", "3", "1", "6", "1.0", "2.0", "1.0"]]
+    );
+    // The repository is left as it was
+    assert!(fs::read_to_string(repo.path().join("src/a.c"))
+        .unwrap()
+        .contains("  return a;\n}"));
+
+    fs::write(repo.path().join("src/a.c"), "int other(void);\n").unwrap();
+    archaeo()
+        .arg("patch")
+        .arg("--diff")
+        .arg(&patch)
+        .arg("--repo")
+        .arg(repo.path())
+        .assert()
+        .failure();
+
+    // Paths leaving the repository are refused before anything is read
+    let escape = repo.path().join("escape.patch");
+    fs::write(
+        &escape,
+        "--- a/../secret.c\n+++ b/../secret.c\n@@ -1 +1 @@\n-int s;\n+int t;\n",
+    )
+    .unwrap();
+    archaeo()
+        .arg("patch")
+        .arg("--diff")
+        .arg(&escape)
+        .arg("--repo")
+        .arg(repo.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("outside of the repository"));
+}
+
+#[cfg(unix)]
+#[test]
+fn patches_are_refused_on_symlinks_leading_out_of_the_repository() {
+    use std::os::unix::fs::symlink;
+    let repo = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    let secret = "int secret(int a) {\n  return a;\n}\n";
+    fs::write(outside.path().join("secret.c"), secret).unwrap();
+    symlink(outside.path().join("secret.c"), repo.path().join("leak.c")).unwrap();
+    fs::write(repo.path().join("a.c"), secret).unwrap();
+    symlink("a.c", repo.path().join("alias.c")).unwrap();
+
+    let patch = |file: &str| {
+        let patch = repo.path().join(format!("{}.patch", file));
+        fs::write(
+            &patch,
+            format!(
+                "--- a/{0}\n+++ b/{0}\n@@ -1,3 +1,3 @@\n int secret(int a) {{\n-  return a;\n+  return -a;\n }}\n",
+                file
+            ),
+        )
+        .unwrap();
+        archaeo()
+            .arg("patch")
+            .arg("--diff")
+            .arg(patch)
+            .arg("--repo")
+            .arg(repo.path())
+            .assert()
+    };
+    patch("leak.c")
+        .failure()
+        .stderr(predicate::str::contains("leak.c is outside of the repository"));
+    assert_eq!(
+        fs::read_to_string(outside.path().join("secret.c")).unwrap(),
+        secret
+    );
+
+    // Symlinks within the repository are read like the files they lead to
+    let output = patch("alias.c").success().get_output().stdout.clone();
+    let impact = read_json_str(&String::from_utf8(output).unwrap());
+    assert_eq!(impact["hunks"][0]["functions"][0]["name"], "secret");
+}
+
+/// A `serve` process, killed when dropped
+struct Server {
+    child: std::process::Child,
+    address: String,
+}
+
+impl Server {
+    /// Serves `path` on a free port, once the first extraction is done
+    fn start(path: &Path) -> Self {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("archaeo"))
+            .args(["--quiet", "serve", "--path"])
+            .arg(path)
+            .args(["--listen", &address])
+            .spawn()
+            .unwrap();
+        let server = Self { child, address };
+        for _ in 0..200 {
+            if std::net::TcpStream::connect(&server.address).is_ok() {
+                return server;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        panic!("serve didn't listen on {}", server.address);
+    }
+
+    /// The status code and body of the response to posting `body` to `url`
+    fn post(&self, url: &str, body: &[u8]) -> (u16, String) {
+        self.send(url, &format!("Content-Length: {}", body.len()), body)
+    }
+
+    /// The status code and body of the response to getting `url`
+    fn get(&self, url: &str) -> (u16, String) {
+        self.request("GET", url, "Content-Length: 0", b"")
+    }
+
+    /// The status code and body of the response to posting `body` to `url`, framed as
+    /// `header` tells
+    fn send(&self, url: &str, header: &str, body: &[u8]) -> (u16, String) {
+        self.request("POST", url, header, body)
+    }
+
+    fn request(&self, method: &str, url: &str, header: &str, body: &[u8]) -> (u16, String) {
+        use std::io::{Read, Write};
+        let mut stream = std::net::TcpStream::connect(&self.address).unwrap();
+        // A server stuck on another request fails the test rather than hanging it
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(30)))
+            .unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\n{}\r\nConnection: close\r\n\r\n",
+            method, url, self.address, header
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response.split(' ').nth(1).unwrap().parse().unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map_or("", |(_, body)| body)
+            .to_string();
+        (status, body)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn served_impacts_stay_inside_the_watched_path() {
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("a.c"), "int g(int a) {\n  return a;\n}\n").unwrap();
+    let secret = TempDir::new().unwrap();
+    fs::write(secret.path().join("s.c"), "int s(void) {\n  return 0;\n}\n").unwrap();
+    let server = Server::start(repo.path());
+
+    let request = |old: &str, new: &str| {
+        serde_json::json!({
+            "diff": format!("--- {}\n+++ {}\n@@ -1,3 +1,3 @@\n-int f(int a) {{\n+int g(int a) {{\n   return a;\n }}\n", old, new),
+            "base": [],
+        })
+        .to_string()
+    };
+    let (status, body) = server.post("/impact", request("a/a.c", "b/a.c").as_bytes());
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        read_json_str(&body)["hunks"][0]["functions"][0]["name"],
+        "g"
+    );
+
+    let escape = format!(
+        "../{}/s.c",
+        secret.path().file_name().unwrap().to_str().unwrap()
+    );
+    for (old, new) in [
+        (format!("a/{}", escape), format!("b/{}", escape)),
+        (
+            "/dev/null".to_string(),
+            format!("{}/s.c", secret.path().display()),
+        ),
+    ] {
+        let (status, body) = server.post("/impact", request(&old, &new).as_bytes());
+        assert_eq!(status, 400, "{}", body);
+        assert!(body.contains("outside of the repository"), "{}", body);
+    }
+}
+
+#[test]
+fn served_request_bodies_are_limited() {
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("a.c"), "int f(void) {\n  return 0;\n}\n").unwrap();
+    let server = Server::start(repo.path());
+    let limit = 16 * 1024 * 1024;
+
+    // Told by their length before anything is read
+    for url in ["/impact", "/webhook"] {
+        let (status, body) = server.send(url, &format!("Content-Length: {}", limit + 1), b"");
+        assert_eq!(status, 413, "{}", body);
+    }
+    // Or once the limit is read, when they are sent without one
+    let mut chunked = format!("{:x}\r\n", limit + 1).into_bytes();
+    chunked.resize(chunked.len() + limit + 1, b' ');
+    chunked.extend_from_slice(b"\r\n0\r\n\r\n");
+    let (status, body) = server.send("/impact", "Transfer-Encoding: chunked", &chunked);
+    assert_eq!(status, 413, "{}", body);
+
+    let (status, _) = server.post("/impact", br#"{"diff": "", "base": []}"#);
+    assert_eq!(status, 200);
+}
+
+#[test]
+fn served_metrics_answer_while_a_post_is_still_being_read() {
+    use std::io::Write;
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("a.c"), "int f(void) {\n  return 0;\n}\n").unwrap();
+    let server = Server::start(repo.path());
+
+    // A post whose body never arrives holds up the worker reading it
+    let mut stalled = std::net::TcpStream::connect(&server.address).unwrap();
+    write!(
+        stalled,
+        "POST /impact HTTP/1.1\r\nHost: {}\r\nContent-Length: 100000\r\n\r\n{{",
+        server.address
+    )
+    .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    let (status, body) = server.get("/metrics?format=prometheus");
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.contains("archaeo_functions 1\n"), "{}", body);
+    let (status, _) = server.get("/metrics/");
+    assert_eq!(status, 404);
+    let (status, body) = server.post("/impact?dry-run", br#"{"diff": "", "base": []}"#);
+    assert_eq!(status, 200, "{}", body);
+    drop(stalled);
+}
+
+#[test]
+fn trends_list_the_functions_changed_between_two_revisions() {
+    let repo = TempDir::new().unwrap();
+    git(repo.path(), &["init", "-q"]);
+    commit(
+        repo.path(),
+        &[(
+            "a.c",
+            Some("int keep(void) {\n  return 1;\n}\n\nint grow(int a) {\n  return a;\n}\n"),
+        )],
+        "v1",
+    );
+    git(repo.path(), &["tag", "v1"]);
+    // keep only moves down, grow gains a branch
+    commit(
+        repo.path(),
+        &[(
+            "a.c",
+            Some("// header\n\nint keep(void) {\n  return 1;\n}\n\nint grow(int a) {\n  if (a > 1) {\n    return a * 2;\n  }\n  return a;\n}\n"),
+        )],
+        "v2",
+    );
+
+    let output = archaeo()
+        .arg("trend")
+        .arg("--repo")
+        .arg(repo.path())
+        .args(["--from", "v1", "--to", "HEAD", "--fmt", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let rows = read_json_str(&String::from_utf8(output.stdout).unwrap());
+    let rows = rows.as_array().unwrap();
+    assert_eq!(rows.len(), 1);
+    let grow = &rows[0];
+    assert_eq!(grow["name"], "grow");
+    assert_eq!(grow["status"], "modified");
+    assert_eq!(grow["before_start_line"], 5);
+    assert_eq!(grow["after_start_line"], 7);
+    assert_eq!(grow["cyclomatic_before"], 1.0);
+    assert_eq!(grow["cyclomatic_after"], 2.0);
+    assert_eq!(grow["cyclomatic_delta"], 1.0);
+    assert_eq!(grow["nexits_delta"], 1.0);
+}
+
+#[test]
+fn renamed_and_moved_functions_are_matched_by_their_bodies() {
+    let repo = TempDir::new().unwrap();
+    let parse = |name: &str| {
+        format!(
+            "int {}(const char *buffer, int length) {{\n  int total = 0;\n  for (int i = 0; i < length; i++) {{\n    if (buffer[i] == ':') {{\n      total += i;\n    }}\n  }}\n  return total;\n}}\n\nint keep(void) {{\n  return 1;\n}}\n",
+            name
+        )
+    };
+    let checksum = "int checksum(const char *data, int size) {\n  int sum = 0;\n  for (int i = 0; i < size; i++) {\n    sum ^= data[i] << (i % 8);\n  }\n  return sum;\n}\n";
+    git(repo.path(), &["init", "-q"]);
+    commit(
+        repo.path(),
+        &[
+            ("a.c", Some(&parse("parse_header"))),
+            (
+                "b.c",
+                Some(&format!(
+                    "{}\nint dropped(int x) {{\n  return x * 3;\n}}\n",
+                    checksum
+                )),
+            ),
+        ],
+        "v1",
+    );
+    git(repo.path(), &["tag", "v1"]);
+    commit(
+        repo.path(),
+        &[
+            ("a.c", Some(&parse("parse_headers"))),
+            ("b.c", Some("int fresh(void) {\n  return 2;\n}\n")),
+            ("c.c", Some(checksum)),
+        ],
+        "v2",
+    );
+
+    let statuses = |args: &[&str]| -> Vec<[String; 5]> {
+        let output = TempDir::new().unwrap();
+        let path = output.path().join("trend.csv");
+        archaeo()
+            .arg("trend")
+            .arg("--repo")
+            .arg(repo.path())
+            .args(["--from", "v1", "--to", "HEAD", "-o"])
+            .arg(&path)
+            .args(args)
+            .assert()
+            .success();
+        let (header, rows) = read_csv(&path);
+        let column = |name: &str| header.iter().position(|column| column == name).unwrap();
+        rows.iter()
+            .map(|row| {
+                [
+                    "status",
+                    "name",
+                    "after_name",
+                    "before_source_file",
+                    "after_source_file",
+                ]
+                .map(|name| row[column(name)].clone())
+            })
+            .collect()
+    };
+    let row = |status: &str, name: &str, after: &str, from: &str, to: &str| {
+        [status, name, after, from, to].map(String::from)
+    };
+
+    // keep is left out, as it didn't change
+    assert_eq!(
+        statuses(&[]),
+        [
+            // Most similar first
+            row("moved", "checksum", "checksum", "b.c", "c.c"),
+            row("renamed", "parse_header", "parse_headers", "a.c", "a.c"),
+            row("removed", "dropped", "", "b.c", ""),
+            row("added", "fresh", "fresh", "", "b.c"),
+        ]
+    );
+    assert_eq!(
+        statuses(&["--no-renames"]),
+        [
+            row("removed", "parse_header", "", "a.c", ""),
+            row("removed", "checksum", "", "b.c", ""),
+            row("removed", "dropped", "", "b.c", ""),
+            row("added", "parse_headers", "parse_headers", "", "a.c"),
+            row("added", "fresh", "fresh", "", "b.c"),
+            row("added", "checksum", "checksum", "", "c.c"),
+        ]
+    );
+    // Nothing is similar enough to a threshold of 1 but the moved function, whose body and
+    // name are the same
+    assert_eq!(statuses(&["--rename-threshold", "1"])[0][0], "moved");
+    assert_eq!(statuses(&["--rename-threshold", "1"]).len(), 5);
+}
+
+/// Merges `inputs` into a CSV, returning its header and rows
+fn merged(inputs: &[&Path], args: &[&str]) -> (Vec<String>, Vec<Vec<String>>) {
+    let output = TempDir::new().unwrap();
+    let path = output.path().join("merged.csv");
+    archaeo()
+        .arg("merge")
+        .args(inputs)
+        .arg("-o")
+        .arg(&path)
+        .args(args)
+        .assert()
+        .success();
+    read_csv(&path)
+}
+
+#[test]
+fn shards_merge_back_into_the_whole_run() {
+    let whole = source("c", &[]);
+    let shards: Vec<TempDir> = ["0/2", "1/2"]
+        .iter()
+        .map(|shard| source("c", &["--shard", shard]))
+        .collect();
+    let shards: Vec<&Path> = shards.iter().map(|shard| shard.path()).collect();
+    let (_, all) = merged(&[whole.path()], &[]);
+    let (_, rows) = merged(&shards, &[]);
+    assert_eq!(rows.len(), all.len());
+    // Merging a run with itself keeps every function once
+    let (_, rows) = merged(&[whole.path(), whole.path()], &[]);
+    assert_eq!(rows.len(), all.len());
+
+    // Runs recording different schema versions don't merge
+    let schema = shards[1].join("schema.json");
+    let mut recorded = read_json(&schema);
+    recorded["schema_version"] = Value::from(recorded["schema_version"].as_u64().unwrap() + 1);
+    fs::write(&schema, recorded.to_string()).unwrap();
+    archaeo()
+        .arg("merge")
+        .args(&shards)
+        .arg("-o")
+        .arg(whole.path().join("merged.csv"))
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("schema version"));
+}
+
+#[test]
+fn merged_inputs_share_their_columns_and_kinds() {
+    let inputs = TempDir::new().unwrap();
+    let input = |name: &str, text: &str| {
+        let path = inputs.path().join(name);
+        fs::write(&path, text).unwrap();
+        path
+    };
+    let a = input("a.csv", "source_file,name,cyclomatic\na.c,f,1\n");
+    let b = input("b.csv", "source_file,name,cognitive\nb.c,g,2\n");
+    let text = input("text.csv", "source_file,name,cyclomatic\nc.c,h,high\n");
+    let output = inputs.path().join("merged.csv");
+    let merge = |files: &[&Path], args: &[&str]| {
+        let mut command = archaeo();
+        command
+            .arg("merge")
+            .args(files)
+            .arg("-o")
+            .arg(&output)
+            .args(args);
+        command
+    };
+
+    merge(&[&a, &b], &[])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(
+            "lacks columns [cyclomatic] and adds [cognitive]",
+        ));
+    let (header, rows) = merged(&[&a, &b], &["--union"]);
+    assert_eq!(header, ["source_file", "name", "cyclomatic", "cognitive"]);
+    assert_eq!(rows, [["a.c", "f", "1", ""], ["b.c", "g", "", "2"]]);
+
+    merge(&[&a, &text], &[])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("cyclomatic holds Text values"));
+}
+
+#[test]
+fn merged_functions_are_told_apart_by_their_id_or_key() {
+    let inputs = TempDir::new().unwrap();
+    let input = |name: &str, text: &str| {
+        let path = inputs.path().join(name);
+        fs::write(&path, text).unwrap();
+        path
+    };
+    let first = input(
+        "first.csv",
+        "function_id,source_file,name,start_line\n1,a.c,f,1\n2,a.c,g,5\n,a.c,h,9\n",
+    );
+    // The id wins over the key columns, and rows without one fall back to them
+    let second = input(
+        "second.csv",
+        "function_id,source_file,name,start_line\n1,a.c,renamed,1\n3,a.c,g,5\n,a.c,h,9\n,a.c,h,12\n",
+    );
+    let names = |args: &[&str]| -> Vec<String> {
+        let (_, rows) = merged(&[&first, &second], args);
+        rows.into_iter().map(|row| row[2].clone()).collect()
+    };
+    assert_eq!(names(&[]), ["f", "g", "h", "g", "h"]);
+    assert_eq!(names(&["--key", "source_file,name"]), ["f", "g", "h", "g"]);
+}
+
+#[test]
+fn suppressed_functions_are_skipped_by_the_rules() {
+    let repo = TempDir::new().unwrap();
+    let branchy = |name: &str| {
+        format!(
+            "int {}(int a) {{\n  if (a > 0) {{\n    return 1;\n  }}\n  return 0;\n}}\n",
+            name
+        )
+    };
+    let suppressed = format!(
+        "int inside(int a) {{\n  // archaeo:ignore\n  if (a > 0) {{\n    return 1;\n  }}\n  return 0;\n}}\n\n/* archaeo:ignore-next-function */\n{}",
+        branchy("next")
+    );
+    fs::write(repo.path().join("a.c"), &suppressed).unwrap();
+    let output = TempDir::new().unwrap();
+    let report = output.path().join("junit.xml");
+    let check = |args: &[&str], code: i32| -> String {
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(repo.path())
+            .arg("-o")
+            .arg(output.path())
+            .args(["--fail-on", "cyclomatic>1", "--report"])
+            .arg(&report)
+            .args(args)
+            .assert()
+            .code(code);
+        fs::read_to_string(&report).unwrap()
+    };
+
+    let junit = check(&[], 0);
+    assert!(junit.contains(r#"<testsuites name="archaeo" tests="2" failures="0" skipped="2">"#));
+    for name in ["inside:1", "next:10"] {
+        assert!(
+            junit.contains(&format!(
+                "name=\"{}\" file=\"{}\" line=\"{}\">\n      <skipped ",
+                name,
+                repo.path().join("a.c").display(),
+                &name[name.find(':').unwrap() + 1..]
+            )),
+            "{}",
+            junit
+        );
+    }
+    let junit = check(&["--no-suppressions"], 3);
+    assert!(
+        junit.contains(r#"tests="2" failures="2" skipped="0">"#),
+        "{}",
+        junit
+    );
+
+    // A plain marker between two functions is in neither of them
+    fs::write(
+        repo.path().join("a.c"),
+        format!("{}\n// archaeo:ignore\n\n{}", suppressed, branchy("loose")),
+    )
+    .unwrap();
+    let junit = check(&[], 3);
+    assert!(
+        junit.contains(r#"tests="3" failures="1" skipped="2">"#),
+        "{}",
+        junit
+    );
+    assert!(junit.contains(r#"name="loose:19""#), "{}", junit);
+    assert!(junit.contains("message=\"cyclomatic is 2, breaking cyclomatic&gt;1\""));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn rates_rank_the_functions_by_how_fast_they_grow() {
+    let corpus = TempDir::new().unwrap();
+    let db = corpus.path().join("metrics.sqlite");
+    let branches = |count: usize| -> String {
+        (0..count)
+            .map(|at| format!("  if (a == {}) {{ return {}; }}\n", at, at))
+            .collect()
+    };
+    for (tag, growing, shrinking) in [("v1", 0, 4), ("v2", 1, 3), ("v3", 3, 2)] {
+        fs::write(
+            corpus.path().join("lib.c"),
+            format!(
+                "int growing(int a) {{\n{}  return a;\n}}\n\nint shrinking(int a) {{\n{}  return a;\n}}\n",
+                branches(growing),
+                branches(shrinking)
+            ),
+        )
+        .unwrap();
+        archaeo()
+            .arg("record")
+            .arg("--db")
+            .arg(&db)
+            .args(["--tag", tag, "-p"])
+            .arg(corpus.path().join("lib.c"))
+            .assert()
+            .success();
+    }
+
+    let output = corpus.path().join("rates.csv");
+    archaeo()
+        .arg("query")
+        .arg("--db")
+        .arg(&db)
+        .args(["--metric", "cyclomatic", "--rates", "--fastest", "1", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+    let (header, rows) = read_csv(&output);
+    assert_eq!(
+        header,
+        [
+            "source_file",
+            "name",
+            "runs",
+            "first_tag",
+            "last_tag",
+            "cyclomatic",
+            "cyclomatic_velocity",
+            "cyclomatic_acceleration",
+            "growing_fastest"
+        ]
+    );
+    let rates: Vec<&[String]> = rows.iter().map(|row| &row[1..]).collect();
+    assert_eq!(
+        rates,
+        [
+            ["growing", "3", "v1", "v3", "4.0", "1.5", "1.0", "true"],
+            ["shrinking", "3", "v1", "v3", "3.0", "-1.0", "0.0", "false"]
+        ]
+    );
+}
+
+#[test]
+fn completions_and_man_pages_cover_the_commands() {
+    archaeo()
+        .args(["completions", "zsh"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::starts_with("#compdef archaeo")
+                .and(predicate::str::contains("--fail-on")),
+        );
+
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("man")
+        .arg("-o")
+        .arg(output.path())
+        .assert()
+        .success();
+    let page = fs::read_to_string(output.path().join("archaeo-source.1")).unwrap();
+    assert!(page.contains("fail\\-on"));
+}
+
+#[test]
+fn version_info_lists_the_grammars() {
+    archaeo().arg("--version-info").assert().success().stdout(
+        predicate::str::contains("rust-code-analysis")
+            .and(predicate::str::contains("tree-sitter-mozcpp")),
+    );
+    archaeo().args(["--version-info", "stats"]).assert().code(1);
+}
+
+#[test]
+fn path_styles_name_sources_and_lay_out_outputs() {
+    let output = source(
+        "",
+        &["--path-style", "relative-to-root", "--extensions", "c,cpp"],
+    );
+    let (_, rows) = read_csv(&output.path().join("cpp/geometry/shapes.csv"));
+    assert!(rows.iter().all(|row| row[1] == "cpp/geometry/shapes.cpp"));
+
+    let output = source("c", &["--path-style", "basename"]);
+    let (_, rows) = read_csv(&output.path().join("main.csv"));
+    assert!(rows.iter().all(|row| row[1] == "main.c"));
+
+    let checkout = fixture("");
+    let output = source(
+        "",
+        &[
+            "--strip-prefix",
+            checkout.to_str().unwrap(),
+            "--extensions",
+            "c",
+        ],
+    );
+    let (_, rows) = read_csv(&output.path().join("c/main.csv"));
+    assert!(rows.iter().all(|row| row[1] == "c/main.c"));
+}
+
+#[test]
+fn odd_and_colliding_names_get_outputs_of_their_own() {
+    let corpus = TempDir::new().unwrap();
+    let main = fs::read(fixture("c/main.c")).unwrap();
+    let long = format!("{}.c", "ü".repeat(120));
+    for name in ["a/util.c", "b/util.c", "a/con.c", "a/日本:語.c", &long] {
+        let path = corpus.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, &main).unwrap();
+    }
+
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .args(["--extensions", "c"])
+        .assert()
+        .success();
+    let outputs: Vec<String> = fs::read_dir(output.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".csv"))
+        .collect();
+    assert_eq!(outputs.len(), 5);
+    assert!(outputs.contains(&"%63on.csv".to_string()));
+    assert!(outputs.contains(&"日本%3A語.csv".to_string()));
+    assert_eq!(
+        outputs
+            .iter()
+            .filter(|name| name.starts_with("util-"))
+            .count(),
+        2
+    );
+    assert!(outputs.iter().all(|name| name.len() <= 255));
+}
+
+#[test]
+fn stats_count_the_lines_of_every_language() {
+    let corpus = corpus(&[
+        ("a.c", "// comment\nint f(int a) {\n\n    return a;\n}\n"),
+        ("b.rs", "fn g() {\n    // note\n    let x = 1;\n}\n"),
+        ("c.cpp", "int h() { return 1; }\n"),
+    ]);
+    let stats = |fmt: &str| {
+        let output = archaeo()
+            .arg("stats")
+            .arg("--path")
+            .arg(corpus.path())
+            .args(["--extensions", "c,rs,cpp", "--fmt", fmt])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(
+        stats("csv"),
+        "language,files,sloc,cloc,blank\nc/c++,2,6.0,1.0,1.0\nrust,1,4.0,1.0,0.0\n"
+    );
+    let json = read_json_str(&stats("json"));
+    assert_eq!(json[1]["language"], "rust");
+    assert_eq!(json[1]["files"], 1);
+    let table = stats("table");
+    assert!(table.contains("SUM                          3          10         2         1"));
+
+    // Only the walked extensions are counted
+    let output = archaeo()
+        .arg("stats")
+        .arg("--path")
+        .arg(corpus.path())
+        .args(["--fmt", "json"])
+        .output()
+        .unwrap();
+    let json = read_json_str(&String::from_utf8(output.stdout).unwrap());
+    assert_eq!(json.as_array().unwrap().len(), 1);
+    assert_eq!(json[0]["files"], 2);
+}
+
+#[test]
+fn symbols_are_listed_with_their_enclosing_scope() {
+    let corpus = corpus(&[(
+        "shapes.cpp",
+        "namespace geo {\nclass Shape {\npublic:\n    double area() const { return 0; }\n};\n}\nstruct P { int x; };\n",
+    )]);
+    let index = corpus.path().join("symbols.csv");
+    archaeo()
+        .current_dir(corpus.path())
+        .args(["symbols", "--path", "shapes.cpp", "-o"])
+        .arg(&index)
+        .assert()
+        .success();
+
+    let (header, rows) = read_csv(&index);
+    assert_eq!(
+        header,
+        [
+            "name",
+            "source_file",
+            "start_line",
+            "end_line",
+            "kind",
+            "parent_name",
+            "signature"
+        ]
+    );
+    let rows: Vec<Vec<&str>> = rows
+        .iter()
+        .map(|row| row.iter().map(String::as_str).collect())
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            vec!["geo", "shapes.cpp", "1", "6", "namespace", "", ""],
+            vec!["Shape", "shapes.cpp", "2", "5", "class", "geo", ""],
+            vec![
+                "area",
+                "shapes.cpp",
+                "4",
+                "4",
+                "function",
+                "Shape",
+                "double area() const"
+            ],
+            vec!["P", "shapes.cpp", "7", "7", "struct", "", ""],
+        ]
+    );
+}
+
+#[test]
+fn call_graphs_count_the_calls_between_functions_across_files() {
+    let corpus = corpus(&[
+        ("leaf.c", "int leaf(int a) { return a; }\n"),
+        (
+            "g.c",
+            "int mid(int a) { return leaf(a) + leaf(1); }\nint top(void) { return mid(2) + printf(\"x\"); }\n",
+        ),
+    ]);
+    let edges = corpus.path().join("edges.csv");
+    archaeo()
+        .current_dir(corpus.path())
+        .args(["callgraph", "--path", ".", "--extensions", "c", "-o"])
+        .arg(&edges)
+        .assert()
+        .success();
+
+    let (header, rows) = read_csv(&edges);
+    assert_eq!(
+        header,
+        [
+            "caller",
+            "caller_file",
+            "caller_start_line",
+            "callee",
+            "callee_file",
+            "calls"
+        ]
+    );
+    // Callees defined nowhere in the corpus are left without a file
+    assert_eq!(
+        rows,
+        [
+            ["mid", "./g.c", "1", "leaf", "./leaf.c", "2"],
+            ["top", "./g.c", "2", "mid", "./g.c", "1"],
+            ["top", "./g.c", "2", "printf", "", "1"],
+        ]
+    );
+
+    let output = archaeo()
+        .current_dir(corpus.path())
+        .args(["callgraph", "--path", "g.c", "--fmt", "json"])
+        .output()
+        .unwrap();
+    let json = read_json_str(&String::from_utf8(output.stdout).unwrap());
+    assert_eq!(json[0]["callee"], "leaf");
+    assert_eq!(json[0]["callee_file"], Value::Null);
+    assert_eq!(json[1]["callee_file"], "g.c");
+}
+
+#[test]
+fn fan_in_and_fan_out_count_distinct_callers_and_callees() {
+    let corpus = corpus(&[
+        ("leaf.c", "int leaf(int a) { return a; }\n"),
+        (
+            "g.c",
+            "int mid(int a) { return leaf(a) + leaf(1); }\nint top(void) { return mid(2) + leaf(3) + printf(\"x\"); }\n",
+        ),
+    ]);
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .current_dir(corpus.path())
+        .args(["source", "--path", ".", "--extensions", "c", "-o"])
+        .arg(output.path())
+        .assert()
+        .success();
+
+    let coupling = |file: &str| {
+        let (header, rows) = read_csv(&output.path().join(file));
+        let column = |name: &str| header.iter().position(|h| h == name).unwrap();
+        let (name, fan_in, fan_out) = (column("name"), column("fan_in"), column("fan_out"));
+        rows.iter()
+            .map(|row| (row[name].clone(), row[fan_in].clone(), row[fan_out].clone()))
+            .collect::<Vec<_>>()
+    };
+    // Calls to the same function count once, to functions outside of the corpus too
+    assert_eq!(
+        coupling("g.csv"),
+        [
+            ("mid".to_string(), "1.0".to_string(), "1.0".to_string()),
+            ("top".to_string(), "0.0".to_string(), "3.0".to_string()),
+        ]
+    );
+    assert_eq!(
+        coupling("leaf.csv"),
+        [("leaf".to_string(), "2.0".to_string(), "0.0".to_string())]
+    );
+}
+
+#[test]
+fn plugins_add_their_columns_to_every_function() {
+    let corpus = corpus(&[(
+        "a.c",
+        "int f(int a) {\n  // TODO fix\n  if (a) {\n    while (a) { a--; } // FIXME\n  }\n  return a;\n}\nint k() { return 0; }\n",
+    )]);
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path().join("a.c"))
+        .arg("-o")
+        .arg(output.path())
+        .args(["--plugin", "todo_comments,max_nesting"])
+        .assert()
+        .success();
+
+    let (header, rows) = read_csv(&output.path().join("a.csv"));
+    let column = |name: &str| header.iter().position(|h| h == name).unwrap();
+    let values: Vec<[&str; 3]> = rows
+        .iter()
+        .map(|row| {
+            [
+                row[column("name")].as_str(),
+                row[column("todo_comments")].as_str(),
+                row[column("max_nesting")].as_str(),
+            ]
+        })
+        .collect();
+    assert_eq!(values, [["f", "2.0", "3.0"], ["k", "0.0", "1.0"]]);
+
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .args(["--plugin", "nope"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "nope (available: todo_comments, max_nesting)",
+        ));
+}
+
+/// A module declaring two columns, computing one of them as 7 and dropping the functions
+/// named `k`, as told by the first letter of the name in the JSON it is given
+#[cfg(feature = "wasm")]
+const WASM_PLUGIN: &str = r#"(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "[\"lines\",\"extra\"]")
+  (data (i32.const 64) "{\"lines\":7}")
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "columns") (result i64) (i64.const 17))
+  (func (export "compute") (param i32 i32) (result i64) (i64.const 274877906955))
+  (func (export "filter") (param $ptr i32) (param $len i32) (result i32)
+    (i32.ne (i32.load8_u offset=28 (local.get $ptr)) (i32.const 107))))
+"#;
+
+#[cfg(feature = "wasm")]
+#[test]
+fn wasm_plugins_compute_columns_and_filter_functions() {
+    let corpus = corpus(&[
+        ("a.c", "int f(int a) { return a; }\nint k() { return 0; }\n"),
+        ("plugin.wat", WASM_PLUGIN),
+        ("broken.wat", "(module (memory (export \"memory\") 1))"),
+    ]);
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path().join("a.c"))
+        .arg("-o")
+        .arg(output.path())
+        .arg("--plugin")
+        .arg(corpus.path().join("plugin.wat"))
+        .assert()
+        .success();
+
+    let (header, rows) = read_csv(&output.path().join("a.csv"));
+    let column = |name: &str| header.iter().position(|h| h == name).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0][column("name")], "f");
+    assert_eq!(rows[0][column("lines")], "7.0");
+    // Declared columns the module leaves out are 0
+    assert_eq!(rows[0][column("extra")], "0.0");
+
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path().join("a.c"))
+        .arg("-o")
+        .arg(output.path())
+        .arg("--plugin")
+        .arg(corpus.path().join("broken.wat"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("broken.wat"));
+}
+
+#[cfg(not(feature = "wasm"))]
+#[test]
+fn wasm_plugins_need_the_wasm_feature() {
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(fixture("c"))
+        .arg("-o")
+        .arg(TempDir::new().unwrap().path())
+        .args(["--plugin", "metrics.wasm"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "archaeo was built without the `wasm` feature",
+        ));
+}
+
+#[test]
+fn json_logs_go_to_stderr_one_object_per_line() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("archaeo"))
+        .args(["--log-format", "json", "stats", "--fmt", "json", "--path"])
+        .arg(fixture("c"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    // Results on stdout stay parseable however much is logged
+    let stats = read_json_str(&String::from_utf8(output.stdout).unwrap());
+    assert_eq!(stats[0]["language"], "c/c++");
+
+    let logs: Vec<Value> = String::from_utf8(output.stderr)
+        .unwrap()
+        .lines()
+        .map(read_json_str)
+        .collect();
+    assert!(!logs.is_empty());
+    assert!(logs.iter().all(|log| log["level"] == "INFO"));
+    assert!(logs
+        .iter()
+        .any(|log| log["fields"]["message"] == "Summarising 3 files..."));
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("archaeo"))
+        .args([
+            "--log-format",
+            "json",
+            "--log-level",
+            "warn",
+            "stats",
+            "--path",
+        ])
+        .arg(fixture("c"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+}
+
+/// Accepts the first request on a free port, answering 200, and hands back its request
+/// line and body
+#[cfg(feature = "telemetry")]
+fn collector() -> (String, std::thread::JoinHandle<(String, Vec<u8>)>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (request_line.trim().to_string(), body)
+    });
+    (address, handle)
+}
+
+#[cfg(feature = "telemetry")]
+#[test]
+fn progress_metrics_are_flushed_to_the_otlp_endpoint() {
+    let (address, collected) = collector();
+    archaeo()
+        .arg("--otlp-endpoint")
+        .arg(format!("http://{}/v1/metrics", address))
+        .arg("source")
+        .arg("--path")
+        .arg(fixture("c"))
+        .arg("-o")
+        .arg(TempDir::new().unwrap().path())
+        .assert()
+        .success();
+
+    let (request_line, body) = collected.join().unwrap();
+    assert_eq!(request_line, "POST /v1/metrics HTTP/1.1");
+    // The protobuf export carries the names of the instruments as they are. Nothing
+    // failed, so there is nothing to export of the failures
+    let body = String::from_utf8_lossy(&body);
+    assert!(!body.contains("archaeo.files.failed"));
+    for name in ["archaeo.files.processed", "archaeo.queue.depth"] {
+        assert!(body.contains(name), "{} missing from the export", name);
+    }
+}
+
+#[cfg(not(feature = "telemetry"))]
+#[test]
+fn otlp_endpoints_need_the_telemetry_feature() {
+    Command::new(assert_cmd::cargo::cargo_bin!("archaeo"))
+        .args([
+            "--log-level",
+            "warn",
+            "--otlp-endpoint",
+            "http://127.0.0.1:9/v1/metrics",
+        ])
+        .args(["stats", "--path"])
+        .arg(fixture("c"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "archaeo was built without the `telemetry` feature",
+        ));
+}
+
+#[test]
+fn batches_share_an_output_between_every_n_files() {
+    let corpus = corpus(&[
+        ("a.c", "int a(void) { return 0; }\n"),
+        ("b.c", "int b(void) { return 0; }\n"),
+        ("sub/c.c", "int c(void) { return 0; }\n"),
+        ("sub/d.c", "int d(void) { return 0; }\n"),
+        ("sub/e.c", "int e(void) { return 0; }\n"),
+    ]);
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .args(["--batch", "2"])
+        .assert()
+        .success();
+
+    let batches = read_json(&output.path().join("batches.json"));
+    let batches = batches.as_object().unwrap();
+    assert_eq!(
+        batches.keys().collect::<Vec<_>>(),
+        ["batch-00000.csv", "batch-00001.csv", "batch-00002.csv"]
+    );
+    let mut files = Vec::new();
+    for (batch, sources) in batches {
+        let sources: Vec<&str> = sources
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|source| source.as_str().unwrap())
+            .collect();
+        // Every batch holds the rows of the files listed for it
+        let (header, rows) = read_csv(&output.path().join(batch));
+        let source_file = header.iter().position(|h| h == "source_file").unwrap();
+        let mut written: Vec<&str> = rows.iter().map(|row| row[source_file].as_str()).collect();
+        written.dedup();
+        assert_eq!(written, sources);
+        files.extend(sources.iter().map(|source| {
+            Path::new(source)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string()
+        }));
+    }
+    files.sort();
+    assert_eq!(files, ["a.c", "b.c", "c.c", "d.c", "e.c"]);
+    assert!(!output.path().join("a.csv").exists());
+}
+
+#[test]
+fn dry_runs_plan_the_language_and_output_of_every_file() {
+    let corpus = corpus(&[
+        ("a.c", "int f() { return 0; }\n"),
+        ("b.rs", "fn g() {}\n"),
+        ("c.txt", "hello\n"),
+    ]);
+    let plan = |args: &[&str]| {
+        let output = archaeo()
+            .current_dir(corpus.path())
+            .args(["source", "--path", ".", "-o", "out", "--dry-run"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // Files no language is known for are planned without an output
+    assert_eq!(
+        plan(&["--extensions", "c,rs,txt", "--fmt", "json"]),
+        "source_file\tlanguage\toutput_file\n\
+         ./a.c\tc/c++\tout/a.json\n\
+         ./b.rs\trust\tout/b.json\n\
+         ./c.txt\tunknown\t\n"
+    );
+    assert_eq!(
+        plan(&["--extensions", "c,rs", "--batch", "1"]),
+        "source_file\tlanguage\toutput_file\n\
+         ./a.c\tc/c++\tout/batch-00000.csv\n\
+         ./b.rs\trust\tout/batch-00001.csv\n"
+    );
+    assert!(!corpus.path().join("out").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_are_only_followed_within_the_tree_and_down_to_the_max_depth() {
+    use std::os::unix::fs::symlink;
+
+    let outside = corpus(&[("c.c", "int h() { return 0; }\n")]);
+    let corpus = corpus(&[
+        ("a.c", "int f() { return 0; }\n"),
+        ("deep/er/b.c", "int g() { return 0; }\n"),
+    ]);
+    symlink("a.c", corpus.path().join("alias.c")).unwrap();
+    symlink(outside.path(), corpus.path().join("escape")).unwrap();
+    symlink("..", corpus.path().join("deep/loop")).unwrap();
+    let walked = |args: &[&str]| {
+        let output = archaeo()
+            .current_dir(corpus.path())
+            .args(["source", "--path", ".", "-o", "out", "--dry-run"])
+            .args(["--extensions", "c"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let mut files: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect();
+        files.sort();
+        files
+    };
+
+    assert_eq!(walked(&[]), ["./a.c", "./deep/er/b.c"]);
+    // The loop back onto the tree and the link out of it are both left out
+    assert_eq!(
+        walked(&["--follow-symlinks"]),
+        ["./a.c", "./alias.c", "./deep/er/b.c"]
+    );
+    assert_eq!(
+        walked(&["--follow-symlinks", "--max-depth", "1"]),
+        ["./a.c", "./alias.c"]
+    );
+    assert_eq!(walked(&["--max-depth", "2"]), ["./a.c"]);
+    assert_eq!(walked(&["--one-file-system"]), ["./a.c", "./deep/er/b.c"]);
+}
+
+#[test]
+fn resumed_runs_skip_the_files_already_completed() {
+    let output = source("c", &[]);
+    let log = output.path().join(".archaeo-completed");
+    let completed = |log: &Path| {
+        let mut files: Vec<String> = fs::read_to_string(log)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                Path::new(line)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        files.sort();
+        files
+    };
+    assert_eq!(completed(&log), ["main.c", "stack.h", "stack_impl.c"]);
+
+    // Outputs of completed files are left alone, those of the rest are written
+    fs::remove_file(output.path().join("main.csv")).unwrap();
+    fs::remove_file(output.path().join("stack.csv")).unwrap();
+    let kept: String = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.ends_with("stack.h"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    fs::write(&log, kept).unwrap();
+    let resume = || {
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(fixture("c"))
+            .arg("-o")
+            .arg(output.path())
+            .arg("--resume")
+            .assert()
+            .success();
+    };
+    resume();
+    assert!(!output.path().join("main.csv").exists());
+    assert!(output.path().join("stack.csv").exists());
+    assert_eq!(completed(&log), ["main.c", "stack.h", "stack_impl.c"]);
+
+    // A run that doesn't resume starts the log afresh and redoes everything
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(fixture("c"))
+        .arg("-o")
+        .arg(output.path())
+        .assert()
+        .success();
+    assert!(output.path().join("main.csv").exists());
+    assert_eq!(completed(&log), ["main.c", "stack.h", "stack_impl.c"]);
+    assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 3);
+}
+
+#[test]
+fn generated_files_are_skipped_with_their_reason() {
+    let corpus = corpus(&[
+        ("a.c", "int f() { return 0; }\n"),
+        (
+            "parser.c",
+            "/* Code generated by bison. */\nint yyparse() { return 0; }\n",
+        ),
+    ]);
+    let run = |args: &[&str]| {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .arg("--manifest")
+            .args(args)
+            .assert()
+            .success();
+        (read_json(&output.path().join("manifest.json")), output)
+    };
+
+    let (manifest, output) = run(&[]);
+    assert!(output.path().join("parser.csv").exists());
+    assert_eq!(manifest["skipped"], serde_json::json!([]));
+
+    let (manifest, output) = run(&["--skip-generated"]);
+    assert!(output.path().join("a.csv").exists());
+    assert!(!output.path().join("parser.csv").exists());
+    assert_eq!(
+        manifest["skipped"],
+        serde_json::json!([{
+            "source_file": corpus.path().join("parser.c"),
+            "reason": "generated (code generated marker)",
+        }])
+    );
+
+    // Files above the size limit are skipped whatever their contents
+    let (manifest, _) = run(&["--skip-generated", "--max-file-size", "0"]);
+    assert_eq!(manifest["skipped"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn manifests_record_the_inputs_and_outputs_of_a_run() {
+    let corpus = corpus(&[("a.c", "int f() { return 0; }\n"), ("b.c", "int g;\n")]);
+    let run = |args: &[&str]| {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .arg("--manifest")
+            .args(args)
+            .assert()
+            .success();
+        (read_json(&output.path().join("manifest.json")), output)
+    };
+
+    let (manifest, output) = run(&[]);
+    assert_eq!(manifest["inputs"]["files"], 2);
+    assert_eq!(manifest["inputs"]["bytes"], 22 + 7);
+    assert_eq!(manifest["options"]["manifest"], true);
+    assert_eq!(manifest["resumed"], false);
+    assert_eq!(manifest["interrupted"], false);
+    // Files without functions write nothing
+    assert_eq!(
+        manifest["outputs"],
+        serde_json::json!([output.path().join("a.csv")])
+    );
+
+    // Inputs are hashed by their paths and contents alone
+    let (again, _) = run(&["--extended"]);
+    assert_eq!(again["inputs"]["sha256"], manifest["inputs"]["sha256"]);
+    fs::write(corpus.path().join("b.c"), "int h;\n").unwrap();
+    let (changed, _) = run(&[]);
+    assert_ne!(changed["inputs"]["sha256"], manifest["inputs"]["sha256"]);
+    assert_eq!(changed["inputs"]["bytes"], manifest["inputs"]["bytes"]);
+
+    // Resumed runs only list what they wrote themselves
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .args(["--manifest", "--resume"])
+        .assert()
+        .success();
+    let resumed = read_json(&output.path().join("manifest.json"));
+    assert_eq!(resumed["resumed"], true);
+    assert_eq!(resumed["outputs"], serde_json::json!([]));
+}
+
+#[test]
+fn files_parsed_within_the_timeout_are_extracted_as_usual() {
+    let corpus = corpus(&[("a.c", "int f(int a) { return a; }\n")]);
+    let run = |args: &[&str]| {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .arg("source")
+            .arg("--path")
+            .arg(corpus.path())
+            .arg("-o")
+            .arg(output.path())
+            .arg("--manifest")
+            .args(args)
+            .assert()
+            .success();
+        output
+    };
+
+    let plain = run(&[]);
+    let timed = run(&["--timeout-per-file", "60"]);
+    assert_eq!(
+        read_csv(&timed.path().join("a.csv")),
+        read_csv(&plain.path().join("a.csv"))
+    );
+    let manifest = read_json(&timed.path().join("manifest.json"));
+    assert_eq!(manifest["quarantined"], serde_json::json!([]));
+    assert!(!timed.path().join("quarantine").exists());
+}
+
+#[test]
+fn normalization_spans_every_file_of_the_corpus() {
+    let corpus = corpus(&[
+        ("a.c", "int f(int a) { if (a) return 1; return 0; }\n"),
+        (
+            "b.c",
+            "int g(int a) { if (a) { if (a > 1) return 2; return 1; } return 0; }\nint h() { return 0; }\n",
+        ),
+    ]);
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .arg("source")
+        .arg("--path")
+        .arg(corpus.path())
+        .arg("-o")
+        .arg(output.path())
+        .args(["--normalize", "minmax"])
+        .assert()
+        .success();
+
+    let normalized = |file: &str| {
+        let (header, rows) = read_csv(&output.path().join(file));
+        assert!(!header.contains(&"start_line_minmax".to_string()));
+        let column = |name: &str| header.iter().position(|h| h == name).unwrap();
+        rows.iter()
+            .map(|row| {
+                (
+                    row[column("name")].clone(),
+                    row[column("cyclomatic_minmax")].clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(normalized("a.csv"), [("f".to_string(), "0.5".to_string())]);
+    assert_eq!(
+        normalized("b.csv"),
+        [
+            ("g".to_string(), "1.0".to_string()),
+            ("h".to_string(), "0.0".to_string())
+        ]
+    );
+    // Files held back for the whole corpus still complete once written
+    assert_eq!(
+        fs::read_to_string(output.path().join(".archaeo-completed"))
+            .unwrap()
+            .lines()
+            .count(),
+        2
+    );
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn hugging_face_exports_shard_every_split() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    let output = TempDir::new().unwrap();
+    let dataset = output.path().join("metrics");
+    archaeo()
+        .arg("hf-export")
+        .arg("--path")
+        .arg(fixture("c"))
+        .arg("-o")
+        .arg(&dataset)
+        .args(["--extensions", "c", "--split", "1,1", "--shard-size", "2"])
+        .assert()
+        .success();
+
+    let infos = read_json(&dataset.join("dataset_infos.json"));
+    let infos = &infos["default"];
+    assert_eq!(infos["dataset_name"], "metrics");
+    assert_eq!(infos["features"]["cyclomatic"]["dtype"], "float64");
+    assert_eq!(infos["features"]["start_line"]["dtype"], "int64");
+    assert_eq!(infos["features"]["name"]["dtype"], "string");
+    // The split is told by the name of the shards rather than a column
+    assert!(infos["features"].get("split").is_none());
+
+    let mut total = 0;
+    for split in ["train", "test"] {
+        let examples = infos["splits"][split]["num_examples"].as_u64().unwrap() as usize;
+        let shards = examples.div_ceil(2);
+        let mut rows = 0;
+        for index in 0..shards {
+            let shard = dataset
+                .join("data")
+                .join(format!("{}-{:05}-of-{:05}.parquet", split, index, shards));
+            let reader = SerializedFileReader::new(fs::File::open(&shard).unwrap()).unwrap();
+            rows += reader.metadata().file_metadata().num_rows() as usize;
+        }
+        assert_eq!(rows, examples);
+        total += examples;
+    }
+    assert_eq!(total, 5);
+    assert_eq!(fs::read_dir(dataset.join("data")).unwrap().count(), 3);
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn hugging_face_shards_hold_at_least_one_row() {
+    archaeo()
+        .arg("hf-export")
+        .arg("--path")
+        .arg(fixture("c"))
+        .arg("-o")
+        .arg(TempDir::new().unwrap().path())
+        .args(["--shard-size", "0"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("--shard-size must be at least 1"));
+}
+
+#[test]
+fn ghidra_exports_locate_the_binary_and_address_of_every_function() {
+    let export = corpus(&[
+        (
+            "export/libfoo.so.c",
+            "/* Decompiled by Ghidra */\nint __cdecl FUN_00401000(int param_1)\n{\n  return param_1 + 1;\n}\n\nint main(void)\n{\n  return FUN_00401000(2);\n}\n",
+        ),
+        (
+            "export/libbar/parse@0x402000.c",
+            "int parse(char *param_1)\n{\n  return *param_1;\n}\n",
+        ),
+    ]);
+    let metrics = export.path().join("metrics.csv");
+    archaeo()
+        .current_dir(export.path())
+        .args(["ghidra", "--path", "export", "-o"])
+        .arg(&metrics)
+        .assert()
+        .success();
+
+    let (header, rows) = read_csv(&metrics);
+    assert_eq!(
+        header[..5],
+        ["name", "binary", "address", "source_file", "start_line"]
+    );
+    let located: Vec<&[String]> = rows.iter().map(|row| &row[..5]).collect();
+    // One file per binary, or a directory of per-function files named after it
+    assert_eq!(
+        located,
+        [
+            [
+                "FUN_00401000",
+                "libfoo.so",
+                "0x00401000",
+                "export/libfoo.so.c",
+                "2"
+            ],
+            ["main", "libfoo.so", "", "export/libfoo.so.c", "7"],
+            [
+                "parse",
+                "libbar",
+                "0x402000",
+                "export/libbar/parse@0x402000.c",
+                "1"
+            ],
+        ]
+    );
+}
+
+#[test]
+fn pairs_match_functions_of_two_runs_by_their_name_or_file() {
+    let corpus = corpus(&[
+        (
+            "l/a.c",
+            "int f(int a) { return a; }\nint g() { return 0; }\n",
+        ),
+        ("l/b.c", "int g() { return 1; }\n"),
+        (
+            "r/a.c",
+            "int f(int a) { if (a) return 1; return a; }\nint g() { return 0; }\n",
+        ),
+        ("r/b.c", "int g() { return 1; }\n"),
+    ]);
+    for (run, output) in [("l", "lo"), ("r", "ro")] {
+        archaeo()
+            .current_dir(corpus.path())
+            .args(["source", "--path", run, "-o", output])
+            .assert()
+            .success();
+    }
+    let pairs = |by: &str| {
+        let paired = corpus.path().join(format!("{}.csv", by));
+        archaeo()
+            .current_dir(corpus.path())
+            .args(["pair", "-l", "lo", "-r", "ro", "--by", by, "-o"])
+            .arg(&paired)
+            .assert()
+            .success();
+        let (header, rows) = read_csv(&paired);
+        let column = |name: &str| header.iter().position(|h| h == name).unwrap();
+        rows.iter()
+            .map(|row| {
+                [
+                    row[column("name")].clone(),
+                    row[column("left_source_file")].clone(),
+                    row[column("cyclomatic_delta")].clone(),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // `g` is in both files, so only its file tells its rows apart
+    assert_eq!(pairs("name"), [["f", "l/a.c", "1.0"]]);
+    assert_eq!(
+        pairs("file"),
+        [
+            ["f", "l/a.c", "1.0"],
+            ["g", "l/a.c", "0.0"],
+            ["g", "l/b.c", "0.0"]
+        ]
+    );
+}
+
+#[test]
+fn languages_are_forced_or_mapped_by_extension() {
+    let corpus = corpus(&[
+        (
+            "t.inl",
+            "template <typename T> T twice(T a) { return a + a; }\n",
+        ),
+        ("m.txt", "fn main() { let x = 1; }\n"),
+    ]);
+    let languages = |path: &str, args: &[&str]| {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .current_dir(corpus.path())
+            .args(["source", "--path", path, "-o"])
+            .arg(output.path())
+            .args(args)
+            .assert()
+            .success();
+        let file = Path::new(path).with_extension("csv");
+        let (header, rows) = read_csv(&output.path().join(file.file_name().unwrap()));
+        let column = |name: &str| header.iter().position(|h| h == name).unwrap();
+        rows.iter()
+            .map(|row| {
+                [
+                    row[column("name")].clone(),
+                    row[column("language")].clone(),
+                    row[column("language_resolution")].clone(),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Mapped extensions are matched however they are written
+    assert_eq!(
+        languages("t.inl", &["--map-ext", ".INL=cpp"]),
+        [["twice", "c/c++", "mapped"]]
+    );
+    assert_eq!(
+        languages("m.txt", &["--force-language", "rust"]),
+        [["main", "rust", "forced"]]
+    );
+
+    for (flag, value, error) in [
+        ("--force-language", "cobol", "unknown language cobol"),
+        (
+            "--map-ext",
+            "inl",
+            "--map-ext expects extension=language, got inl",
+        ),
+    ] {
+        archaeo()
+            .current_dir(corpus.path())
+            .args(["source", "--path", "m.txt", "-o", "out", flag, value])
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains(error));
+    }
+}
+
+#[test]
+fn metrics_a_language_lacks_are_masked_to_null() {
+    let corpus = corpus(&[(
+        "A.java",
+        "class A {\n    int f(int x) {\n        if (x > 0) { return 1; }\n        return 0;\n    }\n}\n",
+    )]);
+    let rows = |args: &[&str]| {
+        let output = TempDir::new().unwrap();
+        archaeo()
+            .current_dir(corpus.path())
+            .args(["source", "--path", "A.java", "-o"])
+            .arg(output.path())
+            .args(args)
+            .assert()
+            .success();
+        read_csv(&output.path().join("A.csv"))
+    };
+
+    let (header, masked) = rows(&["--mask-unsupported"]);
+    let column = |name: &str| header.iter().position(|h| h == name).unwrap();
+    assert!(!masked.is_empty());
+    for row in &masked {
+        // Java only has its arguments and functions counted
+        assert_eq!(row[column("metrics_supported")], "65");
+        assert_ne!(row[column("fn_args")], "");
+        assert_ne!(row[column("nom_functions")], "");
+        for masked in ["cyclomatic", "cognitive", "halstead_volume", "loc_sloc", "mi_sei"] {
+            assert_eq!(row[column(masked)], "", "{}", masked);
+        }
+    }
+
+    let (header, _) = rows(&[]);
+    assert!(!header.iter().any(|h| h == "metrics_supported"));
+}
+
+#[test]
+fn headers_are_written_with_their_translation_unit() {
+    let corpus = corpus(&[
+        (
+            "foo.h",
+            "static inline int twice(int a) { return a + a; }\n",
+        ),
+        (
+            "foo.c",
+            "#include \"foo.h\"\nint f() { return twice(1); }\n",
+        ),
+    ]);
+    let output = TempDir::new().unwrap();
+    archaeo()
+        .current_dir(corpus.path())
+        .args(["source", "--path", ".", "--translation-units", "-o"])
+        .arg(output.path())
+        .assert()
+        .success();
+
+    assert!(!output.path().join("foo.h.csv").exists());
+    let (header, rows) = read_csv(&output.path().join("foo.csv"));
+    let column = |name: &str| header.iter().position(|h| h == name).unwrap();
+    let units: Vec<[&str; 3]> = rows
+        .iter()
+        .map(|row| {
+            [
+                row[column("name")].as_str(),
+                row[column("source_file")].as_str(),
+                row[column("translation_unit")].as_str(),
+            ]
+        })
+        .collect();
+    assert_eq!(
+        units,
+        [["f", "./foo.c", "./foo.c"], ["twice", "./foo.h", "./foo.c"]]
+    );
+}
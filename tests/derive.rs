@@ -0,0 +1,8 @@
+//! Derives that can't do what a struct asks of them fail to compile rather than leaving
+//! fields alone
+
+#[test]
+fn misused_derives_fail_to_compile() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/derive/*.rs");
+}
@@ -0,0 +1,15 @@
+use archaeo_macros::ReplaceInfNan;
+
+#[derive(ReplaceInfNan)]
+struct Inner {
+    density: f64,
+}
+
+#[derive(ReplaceInfNan)]
+struct Outer {
+    #[replace_inf_nan(nested)]
+    #[replace_with(raw)]
+    inner: Inner,
+}
+
+fn main() {}
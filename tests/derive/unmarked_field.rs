@@ -0,0 +1,9 @@
+use archaeo_macros::ReplaceInfNan;
+
+#[derive(ReplaceInfNan)]
+struct Metrics {
+    cyclomatic: f64,
+    name: String,
+}
+
+fn main() {}
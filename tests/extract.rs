@@ -0,0 +1,189 @@
+//! The extraction core embedded through `archaeo::extract`, as the Python bindings and the
+//! C ABI call it
+
+use archaeo::extract::{extract_dir, extract_file, extract_path, extract_source, ExtractOptions};
+use assert_cmd::Command;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+#[test]
+fn extracted_rows_are_the_rows_source_writes() {
+    let output = TempDir::new().unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("archaeo"))
+        .args(["--quiet", "source", "--fmt", "json", "--path"])
+        .arg(fixture("c/main.c"))
+        .arg("-o")
+        .arg(output.path())
+        .assert()
+        .success();
+    let written: Value =
+        serde_json::from_str(&fs::read_to_string(output.path().join("main.json")).unwrap())
+            .unwrap();
+
+    let rows = extract_file(&fixture("c/main.c"), &ExtractOptions::default()).unwrap();
+    assert_eq!(rows.len(), written.as_array().unwrap().len());
+    for (row, written) in rows.iter().zip(written.as_array().unwrap()) {
+        for (column, value) in row {
+            let same = match (value.as_f64(), written[column].as_f64()) {
+                // Floats only survive the round trip through JSON up to the last digit
+                (Some(a), Some(b)) => (a - b).abs() <= 1e-9 * a.abs().max(1.0),
+                _ => *value == written[column],
+            };
+            assert!(same, "{}: {} != {}", column, value, written[column]);
+        }
+    }
+    let columns: Vec<&String> = rows[0].keys().collect();
+    assert_eq!(columns[..3], ["name", "source_file", "start_line"]);
+}
+
+#[test]
+fn annotated_rows_match_the_rows_source_writes() {
+    let corpus = TempDir::new().unwrap();
+    fs::create_dir(corpus.path().join("engine")).unwrap();
+    fs::write(corpus.path().join("engine/Cargo.toml"), "").unwrap();
+    fs::write(
+        corpus.path().join("engine/parse.c"),
+        "int parse(int a) {\n    // archaeo:ignore\n    return a;\n}\nint keep(void) { return 0; }\n",
+    )
+    .unwrap();
+    let advisories = corpus.path().join("advisories.json");
+    fs::write(&advisories, r#"{"engine/parse.c#keep": ["CVE-2021-0001"]}"#).unwrap();
+
+    let output = TempDir::new().unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("archaeo"))
+        .args(["--quiet", "source", "--fmt", "json", "--extensions", "c"])
+        .args(["--drop-suppressed", "--with-source", "--path"])
+        .arg(corpus.path())
+        .arg("--advisories")
+        .arg(&advisories)
+        .arg("-o")
+        .arg(output.path())
+        .assert()
+        .success();
+    let written: Vec<Value> =
+        serde_json::from_str(&fs::read_to_string(output.path().join("parse.json")).unwrap())
+            .unwrap();
+
+    let options = ExtractOptions {
+        extensions: vec!["c".to_string()],
+        drop_suppressed: true,
+        with_source: true,
+        advisories: Some(advisories),
+        ..ExtractOptions::default()
+    };
+    let rows = extract_dir(corpus.path(), &options).unwrap();
+    // The suppressed function is left out of both
+    let names: Vec<&Value> = rows.iter().map(|row| &row["name"]).collect();
+    assert_eq!(names, ["keep"]);
+    assert_eq!(rows.len(), written.len());
+    let row = &rows[0];
+    let columns = |row: &serde_json::Map<String, Value>| row.keys().cloned().collect::<Vec<_>>();
+    assert_eq!(columns(row), columns(written[0].as_object().unwrap()));
+    for column in ["project", "advisories", "advisory_count", "source"] {
+        assert_eq!(row[column], written[0][column], "{}", column);
+    }
+    assert_eq!(row["project"], "engine");
+    assert_eq!(row["advisory_count"], 1);
+}
+
+#[test]
+fn directories_resolve_coupling_across_their_files() {
+    let options = ExtractOptions {
+        extensions: vec!["c".to_string()],
+        ..ExtractOptions::default()
+    };
+    let rows = extract_dir(&fixture("c"), &options).unwrap();
+    let row = |name: &str| {
+        rows.iter()
+            .find(|row| row["name"] == name)
+            .unwrap_or_else(|| panic!("no row for {}", name))
+    };
+    // Files are walked by `extensions`, so the header is left out
+    assert!(rows
+        .iter()
+        .all(|row| row["source_file"].as_str().unwrap().ends_with(".c")));
+    assert_eq!(row("main")["fan_in"], 0.0);
+    assert_eq!(row("sum_positive")["fan_in"], 1.0);
+
+    // Calls into other files aren't resolved for a single file
+    let alone = extract_file(&fixture("c/main.c"), &options).unwrap();
+    assert_eq!(alone, extract_path(&fixture("c/main.c"), &options).unwrap());
+    assert_eq!(extract_path(&fixture("c"), &options).unwrap(), rows);
+}
+
+#[test]
+fn sources_in_memory_are_extracted_as_the_file_they_stand_for() {
+    let rows = extract_source(
+        Path::new("unsaved.c"),
+        b"int f(int a) { if (a) return 1; return 0; }\n",
+        &ExtractOptions {
+            extended: true,
+            ..ExtractOptions::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["name"], "f");
+    assert_eq!(rows[0]["source_file"], "unsaved.c");
+    assert_eq!(rows[0]["cyclomatic"], 2.0);
+}
+
+#[test]
+fn paths_of_the_wrong_kind_are_refused() {
+    let options = ExtractOptions::default();
+    assert!(extract_file(&fixture("c"), &options)
+        .unwrap_err()
+        .to_string()
+        .contains("is not a file"));
+    assert!(extract_dir(&fixture("c/main.c"), &options)
+        .unwrap_err()
+        .to_string()
+        .contains("is not a directory"));
+}
+
+#[test]
+fn options_missing_from_json_take_their_defaults() {
+    let options: ExtractOptions =
+        serde_json::from_str(r#"{"extended": true, "extensions": ["rs"]}"#).unwrap();
+    assert!(options.extended);
+    assert_eq!(options.extensions, ["rs"]);
+    assert_eq!(options.split_by, "file");
+    assert_eq!(options.join_on, ["source_file", "name"]);
+    assert_eq!(options.max_file_size, 5);
+}
+
+#[test]
+fn densities_divide_complexity_by_size_and_are_zero_for_empty_functions() {
+    let rows = extract_source(
+        Path::new("a.c"),
+        b"int f(int a) {\n  if (a) {\n    return 1;\n  }\n  return 0;\n}\nvoid g() {}\n",
+        &ExtractOptions::default(),
+    )
+    .unwrap();
+    let metric = |row: usize, column: &str| rows[row][column].as_f64().unwrap();
+    assert_eq!(
+        metric(0, "cyclomatic_per_sloc"),
+        metric(0, "cyclomatic") / metric(0, "loc_sloc")
+    );
+    assert_eq!(
+        metric(0, "cognitive_per_sloc"),
+        metric(0, "cognitive") / metric(0, "loc_sloc")
+    );
+    assert_eq!(
+        metric(0, "halstead_volume_per_lloc"),
+        metric(0, "halstead_volume") / metric(0, "loc_lloc")
+    );
+
+    // Nothing to divide by without logical lines
+    assert_eq!(rows[1]["name"], "g");
+    assert_eq!(metric(1, "loc_lloc"), 0.0);
+    assert_eq!(metric(1, "halstead_volume_per_lloc"), 0.0);
+}
@@ -0,0 +1,26 @@
+#include <stdio.h>
+#include "stack.h"
+
+/* Sums the values pushed, skipping the negative ones */
+int sum_positive(const int *values, int count) {
+    stack s;
+    int total = 0;
+    int value;
+
+    stack_init(&s);
+    for (int i = 0; i < count; i++) {
+        if (values[i] > 0) {
+            stack_push(&s, values[i]);
+        }
+    }
+    while (stack_pop(&s, &value) == 0) {
+        total += value;
+    }
+    return total;
+}
+
+int main(void) {
+    int values[] = {3, -1, 4, -1, 5};
+    printf("%d\n", sum_positive(values, 5));
+    return 0;
+}
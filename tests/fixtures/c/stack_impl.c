@@ -0,0 +1,21 @@
+#include "stack.h"
+
+void stack_init(stack *s) {
+    s->top = 0;
+}
+
+int stack_push(stack *s, int value) {
+    if (s->top >= 64) {
+        return -1;
+    }
+    s->items[s->top++] = value;
+    return 0;
+}
+
+int stack_pop(stack *s, int *value) {
+    if (s->top == 0) {
+        return -1;
+    }
+    *value = s->items[--s->top];
+    return 0;
+}
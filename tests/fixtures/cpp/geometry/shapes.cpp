@@ -0,0 +1,41 @@
+#include <cmath>
+#include <vector>
+
+namespace geometry {
+
+class Shape {
+public:
+    virtual ~Shape() {}
+    virtual double area() const = 0;
+};
+
+class Circle : public Shape {
+public:
+    explicit Circle(double radius) : radius_(radius) {}
+    double area() const override { return M_PI * radius_ * radius_; }
+
+private:
+    double radius_;
+};
+
+class Rectangle : public Shape {
+public:
+    Rectangle(double width, double height) : width_(width), height_(height) {}
+    double area() const override { return width_ * height_; }
+
+private:
+    double width_;
+    double height_;
+};
+
+double total_area(const std::vector<Shape *> &shapes) {
+    double total = 0.0;
+    for (const Shape *shape : shapes) {
+        if (shape != nullptr) {
+            total += shape->area();
+        }
+    }
+    return total;
+}
+
+} // namespace geometry
@@ -0,0 +1,31 @@
+/// Counts the words of every line, skipping blank ones
+pub fn word_counts(text: &str) -> Vec<usize> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split_whitespace().count())
+        .collect()
+}
+
+pub fn classify(value: i64) -> &'static str {
+    match value {
+        i64::MIN..=-1 => "negative",
+        0 => "zero",
+        1..=9 => "small",
+        _ => "large",
+    }
+}
+
+pub struct Counter {
+    count: u64,
+}
+
+impl Counter {
+    pub fn new() -> Self {
+        Counter { count: 0 }
+    }
+
+    pub fn bump(&mut self, by: u64) -> u64 {
+        self.count = self.count.saturating_add(by);
+        self.count
+    }
+}
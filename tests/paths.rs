@@ -0,0 +1,185 @@
+//! Naming of sources and outputs across platforms, however odd their names. Windows paths only parse into drive
+//! letters and UNC shares on Windows, so those cases only run there
+
+use archaeo::paths::{encode_name, has_extension, slashed, OutputNames, PathNaming, PathStyle};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::Path;
+
+fn encoded(name: &str) -> String {
+    encode_name(OsStr::new(name))
+}
+
+#[test]
+fn reserved_names_are_encoded() {
+    assert_eq!(encoded("con"), "%63on");
+    assert_eq!(encoded("NUL.tar"), "%4EUL.tar");
+    assert_eq!(encoded("Com1"), "%43om1");
+    assert_eq!(encoded("lpt9.h"), "%6Cpt9.h");
+    // Only the exact device names are reserved
+    assert_eq!(encoded("console"), "console");
+    assert_eq!(encoded("com10"), "com10");
+}
+
+#[test]
+fn forbidden_characters_are_encoded() {
+    assert_eq!(encoded("a<b>c:d"), "a%3Cb%3Ec%3Ad");
+    assert_eq!(encoded("what?*|\"x\""), "what%3F%2A%7C%22x%22");
+    assert_eq!(encoded("back\\slash"), "back%5Cslash");
+    assert_eq!(encoded("tab\tname"), "tab%09name");
+    assert_eq!(encoded("trailing. "), "trailing%2E%20");
+    assert_eq!(encoded("100%"), "100%25");
+    assert_eq!(encoded("plain_name.c"), "plain_name.c");
+}
+
+#[test]
+fn encoded_names_are_distinct() {
+    // `%` itself is encoded, so an encoded name can't pass for the one it encodes
+    let names = ["con", "%63on", "a:b", "a%3Ab", "x.", "x%2E", "x"];
+    let encoded: HashSet<String> = names.iter().map(|name| encoded(name)).collect();
+    assert_eq!(encoded.len(), names.len());
+}
+
+#[test]
+fn unicode_names_are_kept() {
+    assert_eq!(encoded("日本語.c"), "日本語.c");
+    assert_eq!(encoded("naïve café"), "naïve café");
+    assert_eq!(encoded("emoji🦀"), "emoji🦀");
+}
+
+#[cfg(unix)]
+#[test]
+fn bytes_that_are_not_utf8_are_encoded() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = OsStr::from_bytes(b"bad\xff\xfe.c");
+    assert_eq!(encode_name(name), "bad%FF%FE.c");
+    let source = Path::new(OsStr::from_bytes(b"src/\xffname.c"));
+    let outputs = OutputNames::new([source], |_, stem| Path::new("out").join(stem));
+    assert_eq!(outputs.get(source).unwrap(), Path::new("out/%FFname"));
+}
+
+#[test]
+fn long_names_are_shortened_with_a_hash() {
+    let long = "x".repeat(1000);
+    let shortened = encoded(&long);
+    assert!(shortened.len() <= 200);
+    assert!(shortened.starts_with("xxxx"));
+    assert_ne!(shortened, encoded(&"x".repeat(999)));
+
+    // Cut on a character boundary
+    let wide = "語".repeat(300);
+    let shortened = encoded(&wide);
+    assert!(shortened.len() <= 200);
+    assert!(shortened.contains('~'));
+}
+
+#[test]
+fn colliding_outputs_are_told_apart() {
+    let sources = [
+        Path::new("a/util.c"),
+        Path::new("a/util.h"),
+        Path::new("b/util.c"),
+        Path::new("b/Util.c"),
+        Path::new("a/main.c"),
+        Path::new("a/.hidden"),
+        Path::new("a/Makefile"),
+    ];
+    let outputs = OutputNames::new(sources, |_, stem| {
+        Path::new("out").join(format!("{}.csv", stem))
+    });
+    let names: HashSet<&Path> = sources
+        .iter()
+        .map(|source| outputs.get(source).unwrap())
+        .collect();
+    assert_eq!(names.len(), sources.len());
+    assert_eq!(
+        outputs.get(Path::new("a/main.c")).unwrap(),
+        Path::new("out/main.csv")
+    );
+    assert_eq!(
+        outputs.get(Path::new("a/.hidden")).unwrap(),
+        Path::new("out/.hidden.csv")
+    );
+    assert_eq!(
+        outputs.get(Path::new("a/Makefile")).unwrap(),
+        Path::new("out/Makefile.csv")
+    );
+    assert!(outputs
+        .get(Path::new("b/Util.c"))
+        .unwrap()
+        .to_string_lossy()
+        .starts_with("out/Util-"));
+
+    // The same sources get the same names on every run
+    let again = OutputNames::new(sources, |_, stem| {
+        Path::new("out").join(format!("{}.csv", stem))
+    });
+    assert!(sources
+        .iter()
+        .all(|source| outputs.get(source) == again.get(source)));
+}
+
+#[test]
+fn extensions_match_ignoring_case() {
+    assert!(has_extension(Path::new("src/MAIN.C"), &["c"]));
+    assert!(has_extension(Path::new("shapes.Cpp"), &[".cpp"]));
+    assert!(has_extension(Path::new("a.b.hpp"), &["h", "HPP"]));
+    assert!(!has_extension(Path::new("Makefile"), &["c"]));
+    assert!(!has_extension(Path::new(".c"), &["c"]));
+    assert!(!has_extension(Path::new("main.cc"), &["c"]));
+}
+
+#[test]
+fn slashed_paths_use_forward_slashes() {
+    assert_eq!(slashed(Path::new("src/lib/a.c")), "src/lib/a.c");
+    assert_eq!(slashed(Path::new("/src/a.c")), "/src/a.c");
+    assert_eq!(slashed(Path::new("./a.c")), "./a.c");
+}
+
+#[test]
+fn mirrored_outputs_stay_below_the_output_path() {
+    let naming = PathNaming::new(PathStyle::Given, Path::new("corpus"), None);
+    assert!(!naming.mirrors());
+
+    let naming = PathNaming::new(PathStyle::Given, Path::new("corpus"), Some(Path::new("..")));
+    assert_eq!(
+        naming.output_dir(Path::new("../x/../con/a.c")),
+        Path::new("x/%63on")
+    );
+
+    let naming = PathNaming::new(PathStyle::RelativeToRoot, Path::new("corpus"), None);
+    assert_eq!(naming.display(Path::new("corpus/src/a.c")), "src/a.c");
+    assert_eq!(
+        naming.output_dir(Path::new("corpus/src/a.c")),
+        Path::new("src")
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn drive_letters_and_unc_shares() {
+    assert_eq!(slashed(Path::new(r"C:\src\a.c")), "C:/src/a.c");
+    assert_eq!(slashed(Path::new(r"\\?\C:\src\a.c")), "C:/src/a.c");
+    assert_eq!(
+        slashed(Path::new(r"\\server\share\a.c")),
+        "//server/share/a.c"
+    );
+    assert_eq!(
+        slashed(Path::new(r"\\?\UNC\server\share\a.c")),
+        "//server/share/a.c"
+    );
+
+    let naming = PathNaming::new(PathStyle::Given, Path::new(r"C:\"), Some(Path::new("x")));
+    assert_eq!(
+        naming.output_dir(Path::new(r"C:\src\aux\a.c")),
+        Path::new(r"C\src\%61ux")
+    );
+    assert_eq!(
+        naming.output_dir(Path::new(r"\\server\share\src\a.c")),
+        Path::new(r"server\share\src")
+    );
+
+    let naming = PathNaming::new(PathStyle::RelativeToRoot, Path::new(r"c:\corpus"), None);
+    assert_eq!(naming.display(Path::new(r"C:\corpus\src\a.c")), "src/a.c");
+}
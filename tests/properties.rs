@@ -0,0 +1,140 @@
+//! Property tests feeding generated and mutated source text through decoding, language
+//! guessing, extraction and flattening, which must neither panic nor let a non-finite
+//! metric through
+
+use archaeo::extract::{extract_source, ExtractOptions, MetricsRow};
+use archaeo::language::SourceReader;
+use archaeo::schema::{ColumnKind, Schema};
+use proptest::prelude::*;
+use std::path::Path;
+
+const FIXTURES: [(&str, &str); 4] = [
+    ("main.c", include_str!("fixtures/c/main.c")),
+    ("stack_impl.c", include_str!("fixtures/c/stack_impl.c")),
+    (
+        "shapes.cpp",
+        include_str!("fixtures/cpp/geometry/shapes.cpp"),
+    ),
+    ("lib.rs", include_str!("fixtures/rust/src/lib.rs")),
+];
+
+/// Every numeric column the schema doesn't allow to be null holds a number. Non-finite
+/// metrics that got through would have been serialized as null
+fn assert_finite(rows: &[MetricsRow], extended: bool) {
+    let schema = Schema::current(extended);
+    for row in rows {
+        for column in &schema.types {
+            if column.nullable || !matches!(column.kind, ColumnKind::Float | ColumnKind::Integer) {
+                continue;
+            }
+            assert!(
+                row[&column.name].is_number(),
+                "{} of {} is {}",
+                column.name,
+                row["name"],
+                row[&column.name]
+            );
+        }
+    }
+}
+
+/// A C statement nested up to a few levels deep
+fn statement() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        "v_[a-z]{1,6}".prop_map(|name| format!("{} = {} + 1;", name, name)),
+        Just("return 0;".to_string()),
+        Just("x /= 0.0;".to_string()),
+        Just("call(y, z);".to_string()),
+    ];
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        let block = prop::collection::vec(inner, 0..4).prop_map(|body| body.join("\n"));
+        prop_oneof![
+            block
+                .clone()
+                .prop_map(|body| format!("if (a > b) {{\n{}\n}}", body)),
+            block
+                .clone()
+                .prop_map(|body| format!("for (i = 0; i < n; i++) {{\n{}\n}}", body)),
+            block
+                .clone()
+                .prop_map(|body| format!("while (a && b || c) {{\n{}\n}}", body)),
+            block.prop_map(|body| format!("switch (a) {{\ncase 1:\n{}\nbreak;\n}}", body)),
+        ]
+    })
+}
+
+/// A C file of uniquely named functions, along with their names
+fn c_file() -> impl Strategy<Value = (String, Vec<String>)> {
+    prop::collection::vec(prop::collection::vec(statement(), 0..5), 1..6).prop_map(|bodies| {
+        let names: Vec<String> = (0..bodies.len()).map(|i| format!("f{}", i)).collect();
+        let source = names
+            .iter()
+            .zip(bodies)
+            .map(|(name, body)| format!("int {}(int a, int b) {{\n{}\n}}\n", name, body.join("\n")))
+            .collect();
+        (source, names)
+    })
+}
+
+/// One of the fixtures with some of its bytes replaced, inserted or removed
+fn mutated_fixture() -> impl Strategy<Value = (&'static str, Vec<u8>)> {
+    let edits = prop::collection::vec((any::<prop::sample::Index>(), 0..3u8, any::<u8>()), 0..24);
+    (prop::sample::select(&FIXTURES[..]), edits).prop_map(|((name, source), edits)| {
+        let mut bytes = source.as_bytes().to_vec();
+        for (index, kind, byte) in edits {
+            let at = index.index(bytes.len() + 1);
+            match kind {
+                0 if at < bytes.len() => bytes[at] = byte,
+                1 => bytes.insert(at, byte),
+                _ if at < bytes.len() => {
+                    bytes.remove(at);
+                }
+                _ => {}
+            }
+        }
+        (name, bytes)
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(96))]
+
+    #[test]
+    fn decoding_ends_in_one_newline(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        let reader = SourceReader::default();
+        if let Ok((_, source, _)) = reader.decode_bytes(Path::new("random.c"), bytes) {
+            prop_assert!(std::str::from_utf8(&source).is_ok());
+            prop_assert!(source.ends_with(b"\n"));
+            prop_assert!(!source.ends_with(b"\n\n"));
+        }
+    }
+
+    #[test]
+    fn guessing_random_bytes_does_not_panic(
+        bytes in prop::collection::vec(any::<u8>(), 0..512),
+        extension in prop::sample::select(&["", ".c", ".py", ".js", ".txt"][..]),
+    ) {
+        let path = format!("random{}", extension);
+        let _ = SourceReader::default().decode_bytes(Path::new(&path), bytes);
+    }
+
+    #[test]
+    fn generated_functions_all_get_a_row((source, names) in c_file(), extended: bool) {
+        let options = ExtractOptions { extended, ..Default::default() };
+        let rows = extract_source(Path::new("generated.c"), source.as_bytes(), &options).unwrap();
+        let extracted: Vec<&str> = rows.iter().filter_map(|row| row["name"].as_str()).collect();
+        prop_assert_eq!(extracted, names.iter().map(String::as_str).collect::<Vec<_>>());
+        for row in &rows {
+            prop_assert!(row["cyclomatic"].as_f64().unwrap() >= 1.0);
+        }
+        assert_finite(&rows, extended);
+    }
+
+    #[test]
+    fn mutated_sources_keep_metrics_finite((name, bytes) in mutated_fixture(), extended: bool) {
+        let options = ExtractOptions { extended, ..Default::default() };
+        if let Ok(rows) = extract_source(Path::new(name), &bytes, &options) {
+            assert_finite(&rows, extended);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Snapshots of the flattened outputs of the fixture corpora, catching changes to column
+//! order and number formatting. Review changes with `cargo insta review`
+
+use assert_cmd::Command;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Runs `source` over a fixture from the crate root, so `source_file` stays relative, and
+/// returns the output written for `output_file`
+fn output_of(fixture: &str, args: &[&str], output_file: &str) -> String {
+    let output = TempDir::new().unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("archaeo"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--quiet", "source", "--path"])
+        .arg(Path::new("tests/fixtures").join(fixture))
+        .arg("-o")
+        .arg(output.path())
+        .args(args)
+        .assert()
+        .success();
+    fs::read_to_string(output.path().join(output_file)).unwrap()
+}
+
+#[test]
+fn c_csv() {
+    insta::assert_snapshot!(output_of("c", &[], "main.csv"));
+}
+
+#[test]
+fn c_header_csv() {
+    insta::assert_snapshot!(output_of("c", &[], "stack.csv"));
+}
+
+#[test]
+fn c_extended_csv() {
+    insta::assert_snapshot!(output_of("c", &["--extended"], "stack_impl-extended.csv"));
+}
+
+#[test]
+fn cpp_json() {
+    insta::assert_snapshot!(output_of("cpp", &["--fmt", "json"], "shapes.json"));
+}
+
+#[test]
+fn rust_csv() {
+    insta::assert_snapshot!(output_of("rust", &["--extensions", "rs"], "lib.csv"));
+}
+
+#[test]
+fn rust_masked_csv() {
+    insta::assert_snapshot!(output_of(
+        "rust",
+        &[
+            "--extensions",
+            "rs",
+            "--mask-unsupported",
+            "--non-finite",
+            "null"
+        ],
+        "lib.csv"
+    ));
+}